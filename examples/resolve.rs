@@ -0,0 +1,72 @@
+//! Loads a config and resolves questions against it without running the server. Names the config
+//! points outside of itself are answered by a fake upstream, rather than by real resolvers.
+
+// lints that clash with the style used throughout this crate
+#![allow(clippy::needless_return)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process;
+
+use tacodns::config;
+use tacodns::server::{self, Response, Upstream};
+use tacodns::server::protocol::{class, Question, record_type, Resource};
+
+const CONFIG: &str = r"
+zones:
+  example.com:
+    A: 10.0.0.1
+    MX: mail.example.com.
+  mail.example.com:
+    A: 10.0.0.2
+  www.example.com:
+    CNAME: example.net.
+";
+
+/// Says every name outside the config is at 192.0.2.1.
+struct FakeUpstream;
+
+impl Upstream for FakeUpstream {
+	fn resolvers(&self) -> &[SocketAddr] {
+		return &[];
+	}
+	
+	fn lookup(&self, question: Question, _servers: &[SocketAddr]) -> Response {
+		if question.qtype != record_type::A {
+			return Response::Ok(vec![], vec![], vec![]);
+		}
+		return Response::Ok(vec![Resource {
+			rname: question.qname,
+			rtype: record_type::A,
+			rclass: class::IN,
+			ttl: 300,
+			rdata: Ipv4Addr::new(192, 0, 2, 1).octets().to_vec(),
+		}], vec![], vec![]);
+	}
+}
+
+fn main() {
+	let config = match config::parse(CONFIG) {
+		Ok(config) => config,
+		Err(error) => {
+			eprintln!("{}", error);
+			process::exit(1);
+		}
+	};
+	
+	for &(name, qtype) in &[("example.com", record_type::A), ("www.example.com", record_type::A), ("example.com", record_type::MX), ("missing.example.com", record_type::A)] {
+		let question = Question {
+			qname: name.split('.').map(String::from).collect(),
+			qtype,
+			qclass: class::IN,
+		};
+		let answer = server::resolve(&question, &config, &FakeUpstream);
+		println!("{} (type {}): rcode {}", name, qtype, answer.rcode);
+		for record in answer.answer.iter().chain(&answer.authority).chain(&answer.additional) {
+			if record.rtype == record_type::A && record.rdata.len() == 4 {
+				println!("  {}. {} A {}", record.rname.join("."), record.ttl, Ipv4Addr::new(record.rdata[0], record.rdata[1], record.rdata[2], record.rdata[3]));
+			} else {
+				println!("  {}. {} type {} {:?}", record.rname.join("."), record.ttl, record.rtype, record.rdata);
+			}
+		}
+	}
+}
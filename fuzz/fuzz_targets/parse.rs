@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use tacodns::server::protocol;
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(message) = protocol::parse(data) {
+		let reparsed = protocol::parse(&protocol::serialize(&message, u16::MAX)).expect("failed to parse a serialized message");
+		// names are expanded when parsed, which can push a message past 64 KiB and get it truncated
+		let counts = |message: &protocol::Message| (message.question.len(), message.answer.len(), message.authority.len(), message.additional.len());
+		if counts(&reparsed) == counts(&message) {
+			assert_eq!(reparsed, message);
+		}
+	}
+});
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub struct NotACidrError;
+
+impl fmt::Display for NotACidrError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "expected an address or a prefix like 10.0.0.0/8")
+	}
+}
+
+/// An IPv4 or IPv6 prefix such as `10.0.0.0/24` or `2001:db8::/32`. A plain address is a prefix
+/// covering just that address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct Cidr {
+	pub address: IpAddr,
+	pub prefix: u8,
+}
+
+impl Cidr {
+	pub fn contains(&self, address: IpAddr) -> bool {
+		// clients on a dual-stack socket show up as IPv4-mapped IPv6 addresses
+		let address = match address {
+			IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+			address => address,
+		};
+		let (network, address, bits) = match (self.address, address) {
+			(IpAddr::V4(network), IpAddr::V4(address)) => (u32::from(network) as u128, u32::from(address) as u128, 32),
+			(IpAddr::V6(network), IpAddr::V6(address)) => (u128::from(network), u128::from(address), 128),
+			_ => return false,
+		};
+		if self.prefix == 0 { return true; }
+		let shift = bits - self.prefix as u32;
+		return network >> shift == address >> shift;
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = NotACidrError;
+	
+	fn from_str(value: &str) -> Result<Cidr, NotACidrError> {
+		let mut parts = value.splitn(2, '/');
+		let address: IpAddr = parts.next().unwrap().parse().map_err(|_| NotACidrError)?;
+		let max = if address.is_ipv4() { 32 } else { 128 };
+		let prefix = match parts.next() {
+			Some(prefix) => prefix.parse().map_err(|_| NotACidrError)?,
+			None => max,
+		};
+		if prefix > max { return Err(NotACidrError); }
+		Ok(Cidr { address, prefix })
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::cidr::{Cidr, NotACidrError};
+	
+	fn contains(cidr: &str, address: &str) -> bool {
+		cidr.parse::<Cidr>().unwrap().contains(address.parse().unwrap())
+	}
+	
+	#[test]
+	fn parse() {
+		assert_eq!("10.0.0.0/24".parse(), Ok(Cidr { address: "10.0.0.0".parse().unwrap(), prefix: 24 }));
+		assert_eq!("10.0.0.1".parse(), Ok(Cidr { address: "10.0.0.1".parse().unwrap(), prefix: 32 }));
+		assert_eq!("2001:db8::/32".parse(), Ok(Cidr { address: "2001:db8::".parse().unwrap(), prefix: 32 }));
+		assert_eq!("::1".parse(), Ok(Cidr { address: "::1".parse().unwrap(), prefix: 128 }));
+		assert_eq!("10.0.0.0/33".parse::<Cidr>(), Err(NotACidrError));
+		assert_eq!("10.0.0/24".parse::<Cidr>(), Err(NotACidrError));
+		assert_eq!("10.0.0.0/".parse::<Cidr>(), Err(NotACidrError));
+	}
+	
+	#[test]
+	fn ipv4() {
+		assert!(contains("10.0.0.0/24", "10.0.0.0"));
+		assert!(contains("10.0.0.0/24", "10.0.0.255"));
+		assert!(!contains("10.0.0.0/24", "10.0.1.0"));
+		assert!(contains("10.0.0.7", "10.0.0.7"));
+		assert!(!contains("10.0.0.7", "10.0.0.8"));
+		assert!(contains("0.0.0.0/0", "192.0.2.1"));
+		assert!(!contains("0.0.0.0/0", "::1"));
+		assert!(contains("10.0.0.0/24", "::ffff:10.0.0.1"));
+	}
+	
+	#[test]
+	fn ipv6() {
+		assert!(contains("2001:db8::/32", "2001:db8:1234::1"));
+		assert!(!contains("2001:db8::/32", "2001:db9::1"));
+		assert!(contains("2001:db8::/127", "2001:db8::1"));
+		assert!(!contains("2001:db8::/127", "2001:db8::2"));
+		assert!(contains("::/0", "::1"));
+		assert!(!contains("::/0", "127.0.0.1"));
+		assert!(!contains("::/96", "::ffff:10.0.0.1"));
+	}
+}
@@ -0,0 +1,126 @@
+//! Finding zones that are given more than once, either under the same key, which YAML doesn't
+//! allow and the loader quietly keeps only the last of, or under keys that match the same names.
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use crate::config::{Label, ZoneMatcher};
+
+/// A mapping or sequence being read.
+struct Frame {
+	mapping: bool,
+	/// Whether the next node in a mapping is a key, rather than the value of `key`.
+	at_key: bool,
+	key: Option<String>,
+	/// Whether this is the top level `zones` mapping.
+	zones: bool,
+}
+
+#[derive(Default)]
+struct Scanner {
+	stack: Vec<Frame>,
+	zone_keys: Vec<String>,
+	repeated: Vec<String>,
+}
+
+impl Scanner {
+	fn node_done(&mut self) {
+		if let Some(frame) = self.stack.last_mut() {
+			if frame.mapping { frame.at_key = !frame.at_key; }
+		}
+	}
+	
+	fn start(&mut self, mapping: bool) {
+		let zones = match &self.stack[..] {
+			[document] => !document.at_key && document.key.as_deref() == Some("zones"),
+			_ => false,
+		};
+		self.stack.push(Frame { mapping, at_key: true, key: None, zones });
+	}
+}
+
+impl MarkedEventReceiver for Scanner {
+	fn on_event(&mut self, event: Event, _: Marker) {
+		match event {
+			Event::MappingStart(_) => self.start(true),
+			Event::SequenceStart(_) => self.start(false),
+			Event::MappingEnd | Event::SequenceEnd => {
+				self.stack.pop();
+				self.node_done();
+			}
+			Event::Scalar(value, _, _, _) => {
+				if let Some(frame) = self.stack.last_mut().filter(|frame| frame.mapping && frame.at_key) {
+					if frame.zones {
+						if self.zone_keys.contains(&value) && !self.repeated.contains(&value) {
+							self.repeated.push(value.clone());
+						}
+						self.zone_keys.push(value.clone());
+					}
+					frame.key = Some(value);
+				}
+				self.node_done();
+			}
+			Event::Alias(_) => self.node_done(),
+			Event::DocumentStart => self.zone_keys.clear(),
+			_ => {}
+		}
+	}
+}
+
+/// The keys under `zones` that are given more than once in a YAML document, or in any of them if
+/// there are several. The same key in two documents is two zones, which are merged.
+pub fn repeated_zone_keys(yaml_data: &str) -> Vec<String> {
+	let mut scanner = Scanner::default();
+	// errors are reported when the document is loaded
+	let _ = Parser::new(yaml_data.chars()).load(&mut scanner, false);
+	return scanner.repeated;
+}
+
+/// What a zone's matchers match, the same for any two that match the same names, however their
+/// names were written (e.g. `Example.COM,www.example.com` and `www.example.com,example.com`). Plain
+/// labels are already lowercased as they're parsed, while patterns are compared as written.
+pub fn canonical_matchers(matchers: &[ZoneMatcher]) -> Vec<String> {
+	let mut canonical: Vec<String> = matchers.iter()
+		.map(|matcher| matcher.iter().map(Label::to_string).collect::<Vec<String>>().join("."))
+		.collect();
+	canonical.sort();
+	canonical.dedup();
+	return canonical;
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::duplicates::{canonical_matchers, repeated_zone_keys};
+	use crate::config::parse_zone_name;
+	
+	#[test]
+	fn test_repeated_zone_keys() {
+		assert_eq!(repeated_zone_keys(r"
+zones:
+  example.com:
+    A: 10.0.0.1
+  example.org:
+    # keys within a zone aren't zones
+    TXT: a
+    example.com: b
+  example.com:
+    A: 10.0.0.2
+  'example.org': {A: [10.0.0.3]}
+  example.net: {}
+other:
+  example.net: {}"), vec!["example.com".to_string(), "example.org".to_string()]);
+		assert!(repeated_zone_keys("zones: {example.com: {}, example.org: {}}").is_empty());
+		assert!(repeated_zone_keys("zones: {example.com: {}}\n---\nzones: {example.com: {}}").is_empty());
+		assert!(repeated_zone_keys("zones: [").is_empty());
+	}
+	
+	#[test]
+	fn test_canonical_matchers() {
+		let canonical = |name: &str| canonical_matchers(&parse_zone_name(name).unwrap().0);
+		assert_eq!(canonical("Example.COM,www.example.com"), canonical("www.example.com,example.com"));
+		assert_eq!(canonical("example.com,example.com"), canonical("example.com"));
+		assert_eq!(canonical("/^w+$/.Example.com"), canonical("/^w+$/.example.com"));
+		assert_ne!(canonical("/^W+$/.example.com"), canonical("/^w+$/.example.com"));
+		assert_ne!(canonical("*.example.com"), canonical("**.example.com"));
+	}
+}
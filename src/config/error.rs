@@ -0,0 +1,110 @@
+use std::fmt;
+
+use yaml_rust::{ScanError, Yaml};
+
+use crate::config::MAX_TTL;
+
+/// Where in the config something went wrong, for the diagnostic.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Location {
+	Document,
+	/// A top level key, e.g. `ttl`.
+	Key(String),
+	Zone(String),
+	/// A key within a zone, e.g. a record type like `A` or a setting like `forward`.
+	Record(String, String),
+}
+
+impl fmt::Display for Location {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Location::Document => write!(f, "the document"),
+			Location::Key(key) => write!(f, "'{}'", key),
+			Location::Zone(zone) => write!(f, "zone '{}'", zone),
+			Location::Record(zone, key) if key.to_uppercase() == *key => write!(f, "zone '{}', record '{}'", zone, key),
+			Location::Record(zone, key) => write!(f, "zone '{}', '{}'", zone, key),
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ConfigError {
+	/// The YAML itself is malformed. The error has the line and column it's at.
+	Yaml(ScanError),
+	/// The config has no YAML documents, or a file under `include` more than one.
+	DocumentCount(usize),
+	/// A document after the first of a config has a setting, which only the first can have.
+	LaterDocument { number: usize, key: String },
+	NotAMapping(Location),
+	WrongType { at: Location, expected: &'static str, found: String },
+	MissingField { at: Location, field: &'static str },
+	InvalidValue { at: Location, value: String, expected: &'static str },
+	InvalidTtl { at: Location, value: String },
+	UnknownRecordType { zone: String, record: String },
+	/// A key that isn't a setting, at the top level or in a zone, e.g. a misspelling.
+	UnknownKey { at: Location, key: String },
+	UnknownFlag { at: Location, flag: String, allowed: Vec<&'static str> },
+	InvalidZoneName(String),
+	NestedZone { zone: String, key: String },
+	/// A zone is in the view of a network that isn't under `networks`.
+	UnknownNetwork { zone: String, network: String },
+	/// The DNSSEC key file couldn't be read, or isn't an Ed25519 private key.
+	KeyFile { at: Location, path: String, reason: String },
+	/// A value refers to an environment variable that isn't set.
+	UnsetVariable { at: Location, variable: String },
+	/// A file under `include` couldn't be read, or isn't a valid config of zones.
+	Include { path: String, reason: String },
+	/// A zone is given more than once, either under the same key or one matching the same names,
+	/// with `strict` set.
+	DuplicateZone(String),
+	/// A TTL is longer than `MAX_TTL`, with `strict` set.
+	TtlTooLong { at: Location, ttl: u64 },
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Yaml(error) => write!(f, "invalid YAML: {}", error),
+			ConfigError::DocumentCount(0) => write!(f, "expected a YAML document, found none"),
+			ConfigError::DocumentCount(count) => write!(f, "expected one YAML document, found {}", count),
+			ConfigError::LaterDocument { number, key } => write!(f, "document {}: has {}, but only the first document can have settings", number, key),
+			ConfigError::NotAMapping(at) => write!(f, "{} is not a mapping", at),
+			ConfigError::WrongType { at, expected, found } => write!(f, "{}: expected {}, found {}", at, expected, found),
+			ConfigError::MissingField { at, field } => write!(f, "{}: missing '{}' field", at, field),
+			ConfigError::InvalidValue { at, value, expected } => write!(f, "{}: '{}' is not {}", at, value, expected),
+			ConfigError::InvalidTtl { at, value } => write!(f, "{}: '{}' is not a valid TTL", at, value),
+			ConfigError::UnknownRecordType { zone, record } => write!(f, "zone '{}': unknown record type '{}'", zone, record),
+			ConfigError::UnknownKey { at, key } => write!(f, "{}: unknown key '{}'", at, key),
+			ConfigError::UnknownFlag { at, flag, allowed } if allowed.is_empty() => write!(f, "{}: unknown flag '{}', none are allowed", at, flag),
+			ConfigError::UnknownFlag { at, flag, allowed } => write!(f, "{}: unknown flag '{}', expected one of {}", at, flag, allowed.join(", ")),
+			ConfigError::InvalidZoneName(zone) => write!(f, "'{}' is not a valid zone name", zone),
+			ConfigError::NestedZone { zone, key } => write!(f, "zone '{}': nested zones like '{}' aren't supported yet", zone, key),
+			ConfigError::UnknownNetwork { zone, network } => write!(f, "zone '{}': there's no network '{}' under 'networks'", zone, network),
+			ConfigError::KeyFile { at, path, reason } => write!(f, "{}: key file '{}' {}", at, path, reason),
+			ConfigError::UnsetVariable { at, variable } => write!(f, "{}: environment variable '{}' isn't set", at, variable),
+			ConfigError::Include { path, reason } => write!(f, "include '{}': {}", path, reason),
+			ConfigError::DuplicateZone(zone) => write!(f, "zone '{}' is given more than once", zone),
+			ConfigError::TtlTooLong { at, ttl } => write!(f, "{}: a TTL of {} seconds is longer than the maximum of {}", at, ttl, MAX_TTL.as_secs()),
+		}
+	}
+}
+
+impl From<ScanError> for ConfigError {
+	fn from(error: ScanError) -> Self {
+		ConfigError::Yaml(error)
+	}
+}
+
+/// Describes a value for `ConfigError::WrongType`.
+pub fn describe(yaml: &Yaml) -> String {
+	match yaml {
+		Yaml::String(string) => format!("'{}'", string),
+		Yaml::Integer(int) => int.to_string(),
+		Yaml::Real(real) => real.to_string(),
+		Yaml::Boolean(bool) => bool.to_string(),
+		Yaml::Array(_) => "a list".to_string(),
+		Yaml::Hash(_) => "a mapping".to_string(),
+		Yaml::Null => "nothing".to_string(),
+		_ => format!("{:?}", yaml),
+	}
+}
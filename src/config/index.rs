@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::{Label, Zone};
+use crate::config::matching::{could_match_descendant, is_within_zone, zone_domain};
+
+/// The zones of a config in tries of the labels of their matchers, read from the end of the name
+/// like delegation, so that finding the zones a name matches doesn't mean trying every one of them.
+/// Until it's built by `new`, every zone is tried.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ZoneIndex(Option<Arc<Tries>>);
+
+#[derive(Debug, PartialEq)]
+struct Tries {
+	matchers: Node,
+	/// The domains of the matchers, see `zone_domain`.
+	domains: Node,
+	/// Zones with a regular expression in one of their matchers, which are always tried.
+	unindexed: Vec<usize>,
+	/// How many zones the index was built from, which it must be built again for once they change.
+	zones: usize,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Node {
+	/// Zones with a matcher that ends here.
+	zones: Vec<usize>,
+	children: HashMap<String, Node>,
+	wildcard: Option<Box<Node>>,
+	sub_wildcard: Option<Box<Node>>,
+	all_wildcard: Option<Box<Node>>,
+}
+
+impl ZoneIndex {
+	pub fn new(zones: &[Zone]) -> ZoneIndex {
+		let mut tries = Tries { matchers: Node::default(), domains: Node::default(), unindexed: vec![], zones: zones.len() };
+		for (zone_index, zone) in zones.iter().enumerate() {
+			if zone.matchers.iter().flatten().any(|label| matches!(label, Label::Regex(..))) {
+				tries.unindexed.push(zone_index);
+				continue;
+			}
+			for matcher in &zone.matchers {
+				tries.matchers.insert(matcher, zone_index);
+				tries.domains.insert(zone_domain(zone, matcher), zone_index);
+			}
+		}
+		return ZoneIndex(Some(Arc::new(tries)));
+	}
+	
+	fn tries(&self, zones: &[Zone]) -> Option<&Tries> {
+		let tries = self.0.as_deref()?;
+		debug_assert_eq!(tries.zones, zones.len(), "the zone index wasn't built again after the zones changed");
+		return Some(tries);
+	}
+	
+	/// The indices of the zones that could match a lowercase name, in their order in the config.
+	/// Every zone matching the name is among them, but those with regular expressions still have to
+	/// be checked.
+	pub fn candidates(&self, zones: &[Zone], qname: &[String]) -> Vec<usize> {
+		let tries = match self.tries(zones) {
+			Some(tries) => tries,
+			None => return (0..zones.len()).collect(),
+		};
+		let mut candidates = tries.unindexed.clone();
+		tries.matchers.collect(qname, &mut candidates);
+		candidates.sort_unstable();
+		candidates.dedup();
+		return candidates;
+	}
+	
+	/// Whether a lowercase name is at or below the domain of any zone, see `zone_domain`.
+	pub fn is_within_zones(&self, zones: &[Zone], qname: &[String]) -> bool {
+		let tries = match self.tries(zones) {
+			Some(tries) => tries,
+			None => return zones.iter().any(|zone| is_within_zone(zone, qname)),
+		};
+		return (0..=qname.len()).any(|taken| tries.domains.matches(&qname[taken..]))
+			|| tries.unindexed.iter().any(|&zone_index| is_within_zone(&zones[zone_index], qname));
+	}
+	
+	/// Whether any zone could match some name below a lowercase name, see `could_match_descendant`.
+	pub fn could_match_descendant(&self, zones: &[Zone], qname: &[String]) -> bool {
+		let tries = match self.tries(zones) {
+			Some(tries) => tries,
+			None => return zones.iter().any(|zone| could_match_descendant(&zone.matchers, qname)),
+		};
+		return tries.matchers.could_match_below(qname)
+			|| tries.unindexed.iter().any(|&zone_index| could_match_descendant(&zones[zone_index].matchers, qname));
+	}
+}
+
+impl Node {
+	fn insert(&mut self, labels: &[Label], zone_index: usize) {
+		let mut node = self;
+		for label in labels.iter().rev() {
+			node = match label {
+				Label::Basic(label) => node.children.entry(label.clone()).or_default(),
+				Label::Wildcard => node.wildcard.get_or_insert_with(Default::default),
+				Label::SubWildcard => node.sub_wildcard.get_or_insert_with(Default::default),
+				Label::AllWildcard => node.all_wildcard.get_or_insert_with(Default::default),
+				Label::Regex(..) => unreachable!(),
+			};
+		}
+		if node.zones.last() != Some(&zone_index) { node.zones.push(zone_index); }
+	}
+	
+	/// Collects the zones at or below this node that match what's left of a name, which wildcards
+	/// take labels from the end of like the rest of the trie.
+	fn collect(&self, qname: &[String], zones: &mut Vec<usize>) {
+		if qname.is_empty() {
+			zones.extend(&self.zones);
+		}
+		if let Some((label, rest)) = qname.split_last() {
+			if let Some(child) = self.children.get(label) {
+				child.collect(rest, zones);
+			}
+			if let Some(wildcard) = &self.wildcard {
+				wildcard.collect(rest, zones);
+			}
+		}
+		if let Some(sub_wildcard) = &self.sub_wildcard {
+			for taken in 1..=qname.len() {
+				sub_wildcard.collect(&qname[..qname.len() - taken], zones);
+			}
+		}
+		if let Some(all_wildcard) = &self.all_wildcard {
+			for taken in 0..=qname.len() {
+				all_wildcard.collect(&qname[..qname.len() - taken], zones);
+			}
+		}
+	}
+	
+	/// Whether any zone at or below this node matches what's left of a name.
+	fn matches(&self, qname: &[String]) -> bool {
+		let mut zones = vec![];
+		self.collect(qname, &mut zones);
+		return !zones.is_empty();
+	}
+	
+	/// Whether a matcher at or below this node could match some name below what's left of a name,
+	/// which the labels the matcher has left once the name is used up can match.
+	fn could_match_below(&self, qname: &[String]) -> bool {
+		// a sub or all wildcard can take whatever's left of the name along with the labels below it
+		if self.sub_wildcard.is_some() || self.all_wildcard.is_some() { return true; }
+		return match qname.split_last() {
+			None => !self.children.is_empty() || self.wildcard.is_some(),
+			Some((label, rest)) => self.children.get(label).map(|child| child.could_match_below(rest)).unwrap_or(false)
+				|| self.wildcard.as_ref().map(|wildcard| wildcard.could_match_below(rest)).unwrap_or(false),
+		};
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config;
+	use crate::config::index::ZoneIndex;
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').filter(|label| !label.is_empty()).map(str::to_string).collect();
+	}
+	
+	#[test]
+	fn test_candidates() {
+		let config = config::parse(r"zones:
+  example.com:
+    A: 10.0.0.1
+  '*.example.com':
+    A: 10.0.0.2
+  '**.example.com':
+    A: 10.0.0.3
+  '***.example.org':
+    A: 10.0.0.4
+  /w+/.example.com:
+    A: 10.0.0.5
+  'foo.*.example.net,example.net':
+    A: 10.0.0.6").unwrap();
+		let candidates = |qname: &str| config.index.candidates(&config.zones, &name(qname));
+		
+		assert_eq!(candidates("example.com"), vec![0, 4]);
+		assert_eq!(candidates("www.example.com"), vec![1, 2, 4]);
+		assert_eq!(candidates("a.b.example.com"), vec![2, 4]);
+		assert_eq!(candidates("example.org"), vec![3, 4]);
+		assert_eq!(candidates("a.b.example.org"), vec![3, 4]);
+		assert_eq!(candidates("foo.bar.example.net"), vec![4, 5]);
+		assert_eq!(candidates("example.net"), vec![4, 5]);
+		assert_eq!(candidates("bar.example.net"), vec![4]);
+		assert_eq!(candidates(""), vec![4]);
+		
+		// until it's built, every zone is tried
+		assert_eq!(ZoneIndex::default().candidates(&config.zones, &name("example.com")), vec![0, 1, 2, 3, 4, 5]);
+	}
+	
+	#[test]
+	fn test_domains() {
+		let config = config::parse(r"zones:
+  www.example.com:
+    A: 10.0.0.1
+  example.net:
+    NS: ns1.example.net
+  '*.a.example.org':
+    A: 10.0.0.2
+  '_sip._tcp.example.info':
+    SRV: 10 5 5060 sip.example.info
+  'x.**.example.biz':
+    A: 10.0.0.3
+  /^api-\d+$/.example.dev:
+    A: 10.0.0.4").unwrap();
+		let unbuilt = ZoneIndex::default();
+		let names = ["", "com", "example.com", "www.example.com", "a.www.example.com", "net", "example.net", "a.example.net", "a.example.org", "x.a.example.org", "example.info", "_tcp.example.info", "_udp.example.info", "_sip._tcp.example.info", "example.biz", "a.b.example.biz", "example.dev", "api-1.example.dev"];
+		for qname in names.iter().map(|qname| name(qname)) {
+			// the same as trying every zone
+			assert_eq!(config.index.is_within_zones(&config.zones, &qname), unbuilt.is_within_zones(&config.zones, &qname), "{:?}", qname);
+			assert_eq!(config.index.could_match_descendant(&config.zones, &qname), unbuilt.could_match_descendant(&config.zones, &qname), "{:?}", qname);
+		}
+		assert!(config.index.is_within_zones(&config.zones, &name("other.example.com")));
+		assert!(!config.index.is_within_zones(&config.zones, &name("com")));
+		assert!(config.index.could_match_descendant(&config.zones, &name("_tcp.example.info")));
+		assert!(!config.index.could_match_descendant(&config.zones, &name("_udp.example.info")));
+	}
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a LOC record says its name is, in the units it's sent in:
+/// https://tools.ietf.org/html/rfc1876#section-2
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Position {
+	/// The size and precisions are in centimeters, as a digit and a power of ten in the high and
+	/// low nibble.
+	pub size: u8,
+	pub horizontal_precision: u8,
+	pub vertical_precision: u8,
+	/// Thousandths of an arc second north of the equator, offset by 2^31.
+	pub latitude: u32,
+	/// Thousandths of an arc second east of the prime meridian, offset by 2^31.
+	pub longitude: u32,
+	/// Centimeters above 100,000m below the WGS 84 reference spheroid.
+	pub altitude: u32,
+}
+
+const EQUATOR: i64 = 1 << 31;
+const ALTITUDE_OFFSET: i64 = 10_000_000;
+const THOUSANDTHS_PER_DEGREE: i64 = 60 * 60 * 1000;
+
+/// Parses the presentation format, e.g. `52 22 23.000 N 4 53 32.000 E -2.00m`, where the minutes
+/// and seconds of each angle, and the size and precisions after the altitude, can be left out.
+impl FromStr for Position {
+	type Err = ();
+	
+	fn from_str(value: &str) -> Result<Position, ()> {
+		let mut tokens = value.split_whitespace();
+		let latitude = parse_angle(&mut tokens, 90, "N", "S")?;
+		let longitude = parse_angle(&mut tokens, 180, "E", "W")?;
+		let altitude = parse_meters(tokens.next().ok_or(())?)?;
+		if altitude < -ALTITUDE_OFFSET || altitude > u32::MAX as i64 - ALTITUDE_OFFSET {
+			return Err(());
+		}
+		// the defaults are 1m, 10km, and 10m
+		let size = tokens.next().map(parse_precision).unwrap_or(Ok(0x12))?;
+		let horizontal_precision = tokens.next().map(parse_precision).unwrap_or(Ok(0x16))?;
+		let vertical_precision = tokens.next().map(parse_precision).unwrap_or(Ok(0x13))?;
+		if tokens.next().is_some() {
+			return Err(());
+		}
+		return Ok(Position {
+			size,
+			horizontal_precision,
+			vertical_precision,
+			latitude: (EQUATOR + latitude) as u32,
+			longitude: (EQUATOR + longitude) as u32,
+			altitude: (altitude + ALTITUDE_OFFSET) as u32,
+		});
+	}
+}
+
+impl fmt::Display for Position {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fn angle(f: &mut fmt::Formatter, value: u32, positive: &str, negative: &str) -> fmt::Result {
+			let value = value as i64 - EQUATOR;
+			let thousandths = value.abs();
+			return write!(f, "{} {} {}.{:03} {}",
+				thousandths / THOUSANDTHS_PER_DEGREE,
+				thousandths / 60_000 % 60,
+				thousandths / 1000 % 60,
+				thousandths % 1000,
+				if value < 0 { negative } else { positive });
+		}
+		fn meters(f: &mut fmt::Formatter, centimeters: i64) -> fmt::Result {
+			let sign = if centimeters < 0 { "-" } else { "" };
+			return write!(f, "{}{}.{:02}m", sign, centimeters.abs() / 100, centimeters.abs() % 100);
+		}
+		
+		angle(f, self.latitude, "N", "S")?;
+		write!(f, " ")?;
+		angle(f, self.longitude, "E", "W")?;
+		write!(f, " ")?;
+		meters(f, self.altitude as i64 - ALTITUDE_OFFSET)?;
+		for precision in &[self.size, self.horizontal_precision, self.vertical_precision] {
+			write!(f, " ")?;
+			meters(f, (precision >> 4) as i64 * 10i64.pow((precision & 0xf) as u32))?;
+		}
+		return Ok(());
+	}
+}
+
+/// Parses degrees, optionally followed by minutes and seconds, then the hemisphere, into signed
+/// thousandths of an arc second.
+fn parse_angle<'a>(tokens: &mut impl Iterator<Item=&'a str>, max_degrees: i64, positive: &str, negative: &str) -> Result<i64, ()> {
+	let mut parts = vec![];
+	let sign = loop {
+		let token = tokens.next().ok_or(())?;
+		if token.eq_ignore_ascii_case(positive) { break 1; }
+		if token.eq_ignore_ascii_case(negative) { break -1; }
+		parts.push(token);
+	};
+	let (degrees, minutes, seconds) = match parts[..] {
+		[degrees] => (degrees, "0", "0"),
+		[degrees, minutes] => (degrees, minutes, "0"),
+		[degrees, minutes, seconds] => (degrees, minutes, seconds),
+		_ => return Err(()),
+	};
+	let degrees = parse_scaled(degrees, 0).ok_or(())?;
+	let minutes = parse_scaled(minutes, 0).ok_or(())?;
+	let seconds = parse_scaled(seconds, 3).ok_or(())?;
+	if degrees < 0 || !(0..60).contains(&minutes) || !(0..60_000).contains(&seconds) {
+		return Err(());
+	}
+	let thousandths = degrees * THOUSANDTHS_PER_DEGREE + minutes * 60_000 + seconds;
+	if thousandths > max_degrees * THOUSANDTHS_PER_DEGREE {
+		return Err(());
+	}
+	return Ok(sign * thousandths);
+}
+
+/// Parses meters, with an optional `m` after them, into centimeters.
+fn parse_meters(value: &str) -> Result<i64, ()> {
+	return parse_scaled(value.strip_suffix('m').unwrap_or(value), 2).ok_or(());
+}
+
+/// Parses a size or precision, which is rounded down to a single significant digit to fit.
+fn parse_precision(value: &str) -> Result<u8, ()> {
+	let centimeters = parse_meters(value)?;
+	if !(0..=9_000_000_000).contains(&centimeters) {
+		return Err(());
+	}
+	let mut mantissa = centimeters;
+	let mut exponent = 0;
+	while mantissa >= 10 {
+		mantissa /= 10;
+		exponent += 1;
+	}
+	return Ok((mantissa << 4 | exponent) as u8);
+}
+
+/// Parses a decimal number with at most `places` digits after the point, scaled to a whole number
+/// of them (e.g. 2.5 with 2 places is 250).
+fn parse_scaled(value: &str, places: usize) -> Option<i64> {
+	let (negative, value) = match value.strip_prefix('-') {
+		Some(value) => (true, value),
+		None => (false, value),
+	};
+	let (whole, fraction) = match value.split_once('.') {
+		Some((whole, fraction)) => (whole, fraction),
+		None => (value, ""),
+	};
+	let digits = |digits: &str| digits.chars().all(|char| char.is_ascii_digit());
+	if whole.is_empty() || fraction.len() > places || !digits(whole) || !digits(fraction) {
+		return None;
+	}
+	let fraction = format!("{:0<width$}", fraction, width = places).parse().unwrap_or(0);
+	let value = whole.parse::<i64>().ok()?.checked_mul(10i64.pow(places as u32))?.checked_add(fraction)?;
+	return Some(if negative { -value } else { value });
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::loc::Position;
+	
+	#[test]
+	fn test_position() {
+		let position: Position = "52 22 23.000 N 4 53 32.000 E -2.00m".parse().unwrap();
+		assert_eq!(position, Position {
+			size: 0x12,
+			horizontal_precision: 0x16,
+			vertical_precision: 0x13,
+			latitude: (1 << 31) + 188_543_000,
+			longitude: (1 << 31) + 17_612_000,
+			altitude: 9_999_800,
+		});
+		assert_eq!(position.to_string(), "52 22 23.000 N 4 53 32.000 E -2.00m 1.00m 10000.00m 10.00m");
+		
+		// southern and western hemispheres, with the minutes and seconds left out
+		let position: Position = "33 52 S 151 12 45.5 E 58m 20m".parse().unwrap();
+		assert_eq!(position.latitude, (1 << 31) - 121_920_000);
+		assert_eq!(position.to_string(), "33 52 0.000 S 151 12 45.500 E 58.00m 20.00m 10000.00m 10.00m");
+		let position: Position = "42 21 S 71 W 0.5m 1.5m 250m 0m".parse().unwrap();
+		assert_eq!(position.longitude, (1 << 31) - 255_600_000);
+		assert_eq!(position.altitude, 10_000_050);
+		// precisions only keep their first digit
+		assert_eq!((position.size, position.horizontal_precision, position.vertical_precision), (0x12, 0x24, 0x00));
+		assert_eq!(position.to_string(), "42 21 0.000 S 71 0 0.000 W 0.50m 1.00m 200.00m 0.00m");
+		assert_eq!(position.to_string().parse::<Position>().unwrap(), position);
+		
+		for invalid in &[
+			"",
+			"52 22 23.000 N",
+			"52 22 23.000 E 4 53 32.000 N 0m",
+			"91 N 0 E 0m",
+			"90 0 0.001 N 0 E 0m",
+			"0 N 181 E 0m",
+			"52 60 N 0 E 0m",
+			"52 22 23.0001 N 0 E 0m",
+			"52 22 23 N 4 53 32 E -100000.01m",
+			"52 22 23 N 4 53 32 E 0m 1m 1m 1m 1m",
+			"52 22 23 N 4 53 32 E 0m 90000001m",
+			"52 22 23 N 4 53 32 E high",
+		] {
+			assert_eq!(invalid.parse::<Position>(), Err(()), "{}", invalid);
+		}
+	}
+}
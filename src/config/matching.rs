@@ -0,0 +1,113 @@
+//! Matching names against the labels of zones' matchers, which both the config (to check what a
+//! zone excludes) and the server (to find the zones answering a name) need.
+
+use crate::config::{Label, Zone, ZoneMatcher};
+
+/// Returns the name a zone matcher matches if it's made up only of plain labels.
+pub(crate) fn plain_name(matcher: &ZoneMatcher) -> Option<Vec<String>> {
+	return matcher.iter().map(|label| match label {
+		Label::Basic(label) => Some(label.clone()),
+		_ => None,
+	}).collect();
+}
+
+pub(crate) fn does_match(matchers: &[ZoneMatcher], qname: &[String]) -> bool {
+	return matchers.iter().any(|zone_matcher| does_match_labels(zone_matcher, qname));
+}
+
+/// Matches the labels of a zone matcher against a name, in any case, trying every way wildcards
+/// and eager regular expressions could split the name until one lines up.
+pub(crate) fn does_match_labels(labels: &[Label], qname: &[String]) -> bool {
+	let (label, labels) = match labels.split_first() {
+		Some(split) => split,
+		// out of labels, so we must also be out of names
+		None => return qname.is_empty(),
+	};
+	
+	return match label {
+		Label::Basic(string) => qname.first().map(|label| label.eq_ignore_ascii_case(string)).unwrap_or(false) && does_match_labels(labels, &qname[1..]),
+		Label::Regex(false, regex) => qname.first().map(|label| regex.is_match(label)).unwrap_or(false) && does_match_labels(labels, &qname[1..]),
+		// eager regular expressions consume as many labels as it takes to match them entirely
+		Label::Regex(true, regex) => (1..=qname.len())
+			.any(|taken| regex.is_match(&qname[..taken].join(".")) && does_match_labels(labels, &qname[taken..])),
+		// wildcards must match one label
+		Label::Wildcard => !qname.is_empty() && does_match_labels(labels, &qname[1..]),
+		// sub wildcards must match at least one label
+		Label::SubWildcard => (1..=qname.len()).any(|taken| does_match_labels(labels, &qname[taken..])),
+		// all wildcards can match any number of labels
+		Label::AllWildcard => (0..=qname.len()).any(|taken| does_match_labels(labels, &qname[taken..])),
+	};
+}
+
+/// Whether a zone matcher could match some name below a name, which makes the name an empty
+/// non-terminal when nothing matches it (e.g. `_tcp.example.com` for `_sip._tcp.example.com`).
+/// The labels before those matching the name can match whatever names below it are needed, and a
+/// sub or all wildcard can also match labels of the name itself.
+pub(crate) fn could_match_descendant(matchers: &[ZoneMatcher], qname: &[String]) -> bool {
+	return matchers.iter().any(|labels| (1..=labels.len()).any(|split| {
+		let rest = &labels[split..];
+		match labels[split - 1] {
+			Label::SubWildcard | Label::AllWildcard => (0..=qname.len()).any(|taken| does_match_labels(rest, &qname[taken..])),
+			_ => does_match_labels(rest, qname),
+		}
+	}));
+}
+
+/// The domain a zone's matcher is in, at or below which names are ours to answer for even when no
+/// zone has them. A name with SOA or NS records is the apex of its own domain, while any other is a
+/// name in its parent's, as is a wildcard or regular expression (e.g. `example.com` for
+/// `www.example.com` or `*.example.com`). A single label is only ever its own domain, rather than
+/// the whole tree.
+pub(crate) fn zone_domain<'a>(zone: &Zone, labels: &'a [Label]) -> &'a [Label] {
+	let apex = !zone.records.soa.is_empty() || !zone.records.ns.is_empty();
+	if labels.len() > 1 && !(apex && matches!(labels[0], Label::Basic(_))) {
+		return &labels[1..];
+	}
+	return labels;
+}
+
+/// Whether a name is at or below the domain of a zone, see `zone_domain`.
+pub(crate) fn is_within_zone(zone: &Zone, qname: &[String]) -> bool {
+	return zone.matchers.iter().any(|labels| {
+		let domain = zone_domain(zone, labels);
+		(0..=qname.len()).any(|taken| does_match_labels(domain, &qname[taken..]))
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::Label;
+	use crate::config::matching::could_match_descendant;
+	
+	#[test]
+	fn test_could_match_descendant() {
+		let name = |name: &str| name.split('.').map(|label| label.to_string()).collect::<Vec<String>>();
+		let matcher = |name: &str| name.split('.').map(|label| match label {
+			"*" => Label::Wildcard,
+			"**" => Label::SubWildcard,
+			"***" => Label::AllWildcard,
+			label => Label::Basic(label.to_string()),
+		}).collect::<Vec<Label>>();
+		
+		let srv = &[matcher("_sip._tcp.example.com")];
+		assert!(could_match_descendant(srv, &name("_tcp.example.com")));
+		assert!(could_match_descendant(srv, &name("_TCP.Example.com")));
+		assert!(could_match_descendant(srv, &name("example.com")));
+		assert!(!could_match_descendant(srv, &name("_sip._tcp.example.com")));
+		assert!(!could_match_descendant(srv, &name("_udp.example.com")));
+		assert!(!could_match_descendant(srv, &name("x._sip._tcp.example.com")));
+		
+		let wildcard = &[matcher("*.b.example.com")];
+		assert!(could_match_descendant(wildcard, &name("b.example.com")));
+		assert!(!could_match_descendant(wildcard, &name("a.b.example.com")));
+		assert!(!could_match_descendant(wildcard, &name("c.example.com")));
+		
+		// sub wildcards can match labels on both sides of the name
+		let sub_wildcard = &[matcher("x.**.example.com")];
+		assert!(could_match_descendant(sub_wildcard, &name("a.b.example.com")));
+		assert!(could_match_descendant(sub_wildcard, &name("example.com")));
+		assert!(!could_match_descendant(sub_wildcard, &name("a.example.net")));
+		assert!(could_match_descendant(&[matcher("www.**")], &name("example.com")));
+		assert!(could_match_descendant(&[matcher("***.example.com")], &name("example.com")));
+	}
+}
@@ -1,63 +1,202 @@
 extern crate yaml_rust;
 
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs::{self, read_to_string};
+use std::io;
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::slice;
+use std::str::FromStr;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use base64::Engine;
 use nom::branch::alt;
-use nom::bytes::complete::{escaped, tag, take, take_while_m_n};
+use nom::bytes::complete::{escaped, tag, take, take_while1};
 use nom::character::complete::none_of;
+use nom::combinator::opt;
+use nom::error::ErrorKind;
 use nom::IResult;
 use nom::multi::separated_list;
-use nom::sequence::delimited;
+use nom::sequence::{delimited, pair};
 use yaml_rust::{Yaml, yaml, YamlLoader};
 
+use std::convert::TryFrom;
+
+use crate::config::duplicates::{canonical_matchers, repeated_zone_keys};
+use crate::config::error::describe;
+use crate::config::matching::{does_match, plain_name};
 use crate::config::ttl::Parse;
-use crate::config::yaml_utils::ExpectStr;
+use crate::config::yaml_utils::{coerce_scalar, Expect};
 use crate::config::yaml_utils::OptionalIndex;
 use crate::regex::Regex;
 
 mod yaml_utils;
 mod ttl;
+mod cidr;
+mod index;
+mod error;
+mod loc;
+mod duplicates;
+mod zone_file;
+pub(crate) mod matching;
+
+pub use crate::config::cidr::Cidr;
+pub use crate::config::error::{ConfigError, Location};
+pub use crate::config::index::ZoneIndex;
+pub use crate::config::loc::Position;
+pub use crate::config::ttl::{MAX_TTL, parse_duration, ttl_secs};
+pub use crate::config::zone_file::dump_zones;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Label {
 	Basic(String),
-	Regex(bool, Regex),
+	/// A regular expression, along with whether it's eager, i.e. can take several labels.
+	Regex(bool, LabelRegex),
 	Wildcard /* * */,
 	SubWildcard /* ** */,
 	AllWildcard /* *** */,
 }
 
+impl fmt::Display for Label {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Label::Basic(label) => write!(f, "{}", label),
+			Label::Regex(_, regex) => write!(f, "/{}/{}", regex.pattern.replace('/', r"\/"), if regex.substring { "~" } else { "" }),
+			Label::Wildcard => write!(f, "*"),
+			Label::SubWildcard => write!(f, "**"),
+			Label::AllWildcard => write!(f, "***"),
+		}
+	}
+}
+
+/// The regular expression of a label, e.g. `/web-\d+/`, which has to match the whole of the label
+/// (or labels) rather than just part of it, unless it's written with a `~` after it (`/web/~`). It
+/// ignores case, so that names don't have to be lowercased to be matched against it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LabelRegex {
+	/// As written, for showing the label.
+	pub pattern: String,
+	pub substring: bool,
+	regex: Regex,
+}
+
+impl LabelRegex {
+	pub fn new(pattern: &str, substring: bool) -> Result<LabelRegex, regex::Error> {
+		let regex = match substring {
+			true => Regex::new(&format!("(?i){}", pattern))?,
+			false => Regex::new(&format!("(?i)^(?:{})$", pattern))?,
+		};
+		return Ok(LabelRegex { pattern: pattern.to_string(), substring, regex });
+	}
+	
+	pub fn is_match(&self, string: &str) -> bool {
+		return self.regex.is_match(string);
+	}
+}
+
 pub type ZoneMatcher = Vec<Label>;
 
+/// A health check deciding whether an A or AAAA record is answered.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Check {
+	/// Passes if a TCP connection can be made, e.g. `tcp://192.0.2.1:443`.
+	Tcp(SocketAddr),
+	/// Passes if a GET of the URL gets a successful status, e.g. `https://192.0.2.1/healthz`.
+	Http(String),
+}
+
+impl fmt::Display for Check {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Check::Tcp(address) => write!(f, "tcp://{}", address),
+			Check::Http(url) => write!(f, "{}", url),
+		}
+	}
+}
+
+/// Notes on a record for the people managing it, which can be given in the mapping form of any
+/// record. They're shown by the API and `--dump-zones`, and never answered.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Metadata {
+	pub comment: Option<String>,
+	pub labels: BTreeMap<String, String>,
+}
+
+/// On a single line, e.g. `primary LB [team=infra]`, for a comment in a zone file.
+impl fmt::Display for Metadata {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let comment = self.comment.as_deref().unwrap_or_default().split_whitespace().collect::<Vec<&str>>().join(" ");
+		let labels: Vec<String> = self.labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+		match (comment.is_empty(), labels.is_empty()) {
+			(_, true) => write!(f, "{}", comment),
+			(true, false) => write!(f, "[{}]", labels.join(", ")),
+			(false, false) => write!(f, "{} [{}]", comment, labels.join(", ")),
+		}
+	}
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct ARecord {
 	pub ttl: Duration,
 	pub ip4addr: Ipv4Addr,
+	/// How likely the record is to be answered first, relative to the others of its type, if any of
+	/// them have a weight.
+	pub weight: Option<u32>,
+	/// Leaves the record out of answers while it fails, unless every record of its type does.
+	pub check: Option<Check>,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct AaaaRecord {
 	pub ttl: Duration,
 	pub ip6addr: Ipv6Addr,
+	/// Like `ARecord::weight`.
+	pub weight: Option<u32>,
+	/// Like `ARecord::check`.
+	pub check: Option<Check>,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct NsRecord {
 	pub ttl: Duration,
 	pub name: String,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct CnameRecord {
 	pub ttl: Duration,
 	pub name: String,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct AnameRecord {
 	pub ttl: Duration,
 	pub name: String,
+	/// Override `Config::min_ttl` and `Config::max_ttl` for the flattened records.
+	pub min_ttl: Option<Duration>,
+	pub max_ttl: Option<Duration>,
+	pub metadata: Option<Metadata>,
+}
+
+/// Flattened like an ANAME at the apex of the zone it's in, and answered as a CNAME below it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AliasRecord {
+	pub ttl: Duration,
+	pub name: String,
+	pub metadata: Option<Metadata>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PtrRecord {
+	pub ttl: Duration,
+	pub name: String,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -65,12 +204,108 @@ pub struct MxRecord {
 	pub ttl: Duration,
 	pub priority: u16,
 	pub host: String,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TxtRecord {
 	pub ttl: Duration,
 	pub data: String,
+	pub metadata: Option<Metadata>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SrvRecord {
+	pub ttl: Duration,
+	pub priority: u16,
+	pub weight: u16,
+	pub port: u16,
+	pub target: String,
+	pub metadata: Option<Metadata>,
+}
+
+/// https://tools.ietf.org/html/rfc1035#section-3.3.2
+#[derive(Debug, PartialEq, Clone)]
+pub struct HinfoRecord {
+	pub ttl: Duration,
+	pub cpu: String,
+	pub os: String,
+	pub metadata: Option<Metadata>,
+}
+
+/// https://tools.ietf.org/html/rfc1876
+#[derive(Debug, PartialEq, Clone)]
+pub struct LocRecord {
+	pub ttl: Duration,
+	pub position: Position,
+	pub metadata: Option<Metadata>,
+}
+
+/// https://tools.ietf.org/html/rfc4255#section-3.1
+#[derive(Debug, PartialEq, Clone)]
+pub struct SshfpRecord {
+	pub ttl: Duration,
+	pub algorithm: u8,
+	pub fingerprint_type: u8,
+	pub fingerprint: Vec<u8>,
+	pub metadata: Option<Metadata>,
+}
+
+/// https://tools.ietf.org/html/rfc9460#section-2.2
+#[derive(Debug, PartialEq, Clone)]
+pub struct SvcbRecord {
+	pub ttl: Duration,
+	/// 0 for alias mode, in which the record only points at `target` and has no params.
+	pub priority: u16,
+	/// `.` in service mode for the name the record is at.
+	pub target: String,
+	pub params: SvcParams,
+	pub metadata: Option<Metadata>,
+}
+
+/// HTTPS records are SVCB records for HTTP origins, with the same fields:
+/// https://tools.ietf.org/html/rfc9460#section-9
+pub type HttpsRecord = SvcbRecord;
+
+/// The SvcParams of an SVCB or HTTPS record, of those that can be configured:
+/// https://tools.ietf.org/html/rfc9460#section-7
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SvcParams {
+	/// The names of the other params that a client must understand to use the record.
+	pub mandatory: Vec<String>,
+	pub alpn: Vec<String>,
+	pub no_default_alpn: bool,
+	pub port: Option<u16>,
+	pub ipv4hint: Vec<Ipv4Addr>,
+	pub ipv6hint: Vec<Ipv6Addr>,
+}
+
+/// https://tools.ietf.org/html/rfc3403#section-4.1
+#[derive(Debug, PartialEq, Clone)]
+pub struct NaptrRecord {
+	pub ttl: Duration,
+	pub order: u16,
+	pub preference: u16,
+	pub flags: String,
+	pub service: String,
+	pub regexp: String,
+	pub replacement: String,
+	pub metadata: Option<Metadata>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SoaRecord {
+	pub ttl: Duration,
+	pub mname: String,
+	pub rname: String,
+	/// Falls back to `Config::serial` when not set.
+	pub serial: Option<u32>,
+	pub refresh: Duration,
+	pub retry: Duration,
+	pub expire: Duration,
+	/// Falls back to `Config::nttl` when not set.
+	pub minimum: Option<Duration>,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -84,12 +319,44 @@ pub struct RnsRecord {
 	pub ttl: Duration,
 	pub host: RnsHost,
 	pub external: bool,
+	pub metadata: Option<Metadata>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TrppRecord {
 	pub ttl: Duration,
 	pub server: String,
+	pub metadata: Option<Metadata>,
+}
+
+/// Set by the `dnssec` key, at the top level or on a zone, to sign answers for DNSSEC-aware clients.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dnssec {
+	/// The TTL of the DNSKEY record.
+	pub ttl: Duration,
+	/// The 32 byte Ed25519 private key, from the `PrivateKey` field of a BIND `.private` file.
+	pub private_key: Vec<u8>,
+}
+
+/// A key in `tsig-keys`, which dynamic updates have to be signed with, as do queries of zones that
+/// require it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TsigKey {
+	/// Lowercase, without a trailing dot.
+	pub name: String,
+	/// The HMAC-SHA256 secret, given in base64.
+	pub secret: Vec<u8>,
+}
+
+/// Set by the `forward` key on a zone, to send every query for it to other DNS servers.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Forward {
+	pub servers: Vec<SocketAddr>,
+	/// Set by the `only` flag (e.g. `forward only:`) to answer with whatever the servers respond,
+	/// rather than falling back to the zone's records when they don't have any.
+	pub only: bool,
+	/// Cleared by the `noaa` flag, to leave the AA bit unset on forwarded answers.
+	pub authoritative: bool,
 }
 
 #[derive(Debug, Default, PartialEq, Clone)]
@@ -99,59 +366,688 @@ pub struct Records {
 	pub ns: Vec<NsRecord>,
 	pub cname: Vec<CnameRecord>,
 	pub aname: Vec<AnameRecord>,
+	pub alias: Vec<AliasRecord>,
 	pub mx: Vec<MxRecord>,
 	pub txt: Vec<TxtRecord>,
+	pub soa: Vec<SoaRecord>,
+	pub srv: Vec<SrvRecord>,
+	pub naptr: Vec<NaptrRecord>,
+	pub hinfo: Vec<HinfoRecord>,
+	pub loc: Vec<LocRecord>,
+	pub sshfp: Vec<SshfpRecord>,
+	pub svcb: Vec<SvcbRecord>,
+	pub https: Vec<HttpsRecord>,
+	pub ptr: Vec<PtrRecord>,
 	pub rns: Vec<RnsRecord>,
 	pub trpp: Vec<TrppRecord>,
+	pub forward: Option<Forward>,
+	/// Set by the `rotate` flag (e.g. `A rotate:`) to cycle which record is answered first.
+	pub rotate_a: bool,
+	pub rotate_aaaa: bool,
+	/// Set by `A: none` (or `AAAA: none`) to answer that the zone has no records of the type,
+	/// rather than going on to the zones after it, such as a wildcard above it, or to RNS.
+	pub no_a: bool,
+	pub no_aaaa: bool,
+	/// Set by the `inherit` flag on the zone (e.g. `example.com inherit:`) to answer A, AAAA, and TXT
+	/// queries for subdomains that no zone matches with this zone's records.
+	pub inherit: bool,
+	/// Clients allowed to transfer (AXFR) this zone, in addition to `Config::allow_transfer`.
+	pub allow_transfer: Vec<IpAddr>,
+	/// Overrides `Config::acl` for this zone: clients in `deny` are refused, and if `allow` isn't
+	/// empty, so is everyone not in it.
+	pub allow: Vec<Cidr>,
+	pub deny: Vec<Cidr>,
+	/// Overrides `Config::dnssec` for this zone, which must have a plain name to be signed as.
+	pub dnssec: Option<Dnssec>,
+	/// Secondaries sent a NOTIFY when this zone changes, in addition to `Config::notify`.
+	pub notify: Vec<SocketAddr>,
+	/// Set by a flag naming one of `Config::networks` on the zone (e.g. `example.com @internal:`) to
+	/// only answer clients in that network, who no longer get the zone by the same name without one.
+	pub view: Option<String>,
+	/// Overrides `Config::minimal_responses` for this zone.
+	pub minimal_responses: Option<bool>,
+	/// Overrides `Config::nttl` for this zone, as the minimum of an SOA that doesn't give one.
+	pub nodata_ttl: Option<Duration>,
+	/// Overrides `Config::resolve_external_cnames` for this zone.
+	pub resolve_external_cnames: Option<bool>,
+	/// The key in `Config::tsig_keys` that queries of this zone have to be signed with, or else
+	/// they're refused.
+	pub require_tsig: Option<String>,
+	/// The TTL of the zone's records that don't give one, from its `ttl` key or the TTL after its
+	/// name, if it gives one other than `Config::ttl`. Records added through the API take it.
+	pub ttl: Option<Duration>,
+	/// The TTLs given on record types (e.g. `A 5m:`), for the first key of each type, which records
+	/// added to the type through the API take, as they're written under that key.
+	pub type_ttls: Vec<(String, Duration)>,
+}
+
+impl Records {
+	/// Adds the records and settings of another zone matching the same names to this one, leaving
+	/// out records it already has. Settings this one has are kept.
+	fn merge(&mut self, other: Records) {
+		fn extend<T: PartialEq>(records: &mut Vec<T>, other: Vec<T>) {
+			for record in other {
+				if !records.contains(&record) { records.push(record); }
+			}
+		}
+		// taken apart, so that a new field can't be forgotten here
+		let Records {
+			a, aaaa, ns, cname, aname, alias, mx, txt, soa, srv, naptr, hinfo, loc, sshfp, svcb, https, ptr, rns, trpp,
+			forward, rotate_a, rotate_aaaa, no_a, no_aaaa, inherit, allow_transfer, allow, deny, dnssec, notify, view,
+			minimal_responses, nodata_ttl, resolve_external_cnames, require_tsig, ttl, type_ttls,
+		} = other;
+		extend(&mut self.a, a);
+		extend(&mut self.aaaa, aaaa);
+		extend(&mut self.ns, ns);
+		extend(&mut self.cname, cname);
+		extend(&mut self.aname, aname);
+		extend(&mut self.alias, alias);
+		extend(&mut self.mx, mx);
+		extend(&mut self.txt, txt);
+		// a zone has a single SOA
+		if self.soa.is_empty() { self.soa = soa; }
+		extend(&mut self.srv, srv);
+		extend(&mut self.naptr, naptr);
+		extend(&mut self.hinfo, hinfo);
+		extend(&mut self.loc, loc);
+		extend(&mut self.sshfp, sshfp);
+		extend(&mut self.svcb, svcb);
+		extend(&mut self.https, https);
+		extend(&mut self.ptr, ptr);
+		extend(&mut self.rns, rns);
+		extend(&mut self.trpp, trpp);
+		self.forward = self.forward.take().or(forward);
+		self.rotate_a |= rotate_a;
+		self.rotate_aaaa |= rotate_aaaa;
+		self.no_a |= no_a;
+		self.no_aaaa |= no_aaaa;
+		self.inherit |= inherit;
+		extend(&mut self.allow_transfer, allow_transfer);
+		extend(&mut self.allow, allow);
+		extend(&mut self.deny, deny);
+		self.dnssec = self.dnssec.take().or(dnssec);
+		extend(&mut self.notify, notify);
+		self.view = self.view.take().or(view);
+		self.minimal_responses = self.minimal_responses.or(minimal_responses);
+		self.nodata_ttl = self.nodata_ttl.or(nodata_ttl);
+		self.resolve_external_cnames = self.resolve_external_cnames.or(resolve_external_cnames);
+		self.require_tsig = self.require_tsig.take().or(require_tsig);
+		self.ttl = self.ttl.or(ttl);
+		for (rtype, ttl) in type_ttls {
+			if !self.type_ttls.iter().any(|(existing, _)| *existing == rtype) { self.type_ttls.push((rtype, ttl)); }
+		}
+	}
+	
+	/// The TTL records added to a type take when they don't give one, see `ttl` and `type_ttls`.
+	pub fn default_ttl(&self, rtype: &str, config_ttl: Duration) -> Duration {
+		return self.type_ttls.iter().find(|(key, _)| key == rtype).map(|(_, ttl)| *ttl)
+			.or(self.ttl)
+			.unwrap_or(config_ttl);
+	}
+	
+	/// The type given both as `none` and with addresses, if there is one, which can't both be
+	/// answered.
+	pub fn none_conflict(&self) -> Option<&'static str> {
+		if self.no_a && !self.a.is_empty() { return Some("A"); }
+		if self.no_aaaa && !self.aaaa.is_empty() { return Some("AAAA"); }
+		return None;
+	}
+	
+	/// Whether a zone delegates its names elsewhere, which is when it has NS records and no other
+	/// records.
+	pub fn is_delegation(&self) -> bool {
+		return !self.ns.is_empty() && *self == Records {
+			ns: self.ns.clone(),
+			inherit: self.inherit,
+			allow_transfer: self.allow_transfer.clone(),
+			allow: self.allow.clone(),
+			deny: self.deny.clone(),
+			minimal_responses: self.minimal_responses,
+			nodata_ttl: self.nodata_ttl,
+			resolve_external_cnames: self.resolve_external_cnames,
+			require_tsig: self.require_tsig.clone(),
+			ttl: self.ttl,
+			type_ttls: self.type_ttls.clone(),
+			view: self.view.clone(),
+			dnssec: self.dnssec.clone(),
+			notify: self.notify.clone(),
+			..Records::default()
+		};
+	}
+	
+	/// Every TTL given in the zone, along with the record type (or setting) it's for.
+	fn ttls(&self) -> Vec<(&'static str, Duration)> {
+		let mut ttls = vec![];
+		ttls.extend(self.a.iter().map(|a| ("A", a.ttl)));
+		ttls.extend(self.aaaa.iter().map(|aaaa| ("AAAA", aaaa.ttl)));
+		ttls.extend(self.ns.iter().map(|ns| ("NS", ns.ttl)));
+		ttls.extend(self.cname.iter().map(|cname| ("CNAME", cname.ttl)));
+		for aname in &self.aname {
+			ttls.extend(iter::once(aname.ttl).chain(aname.min_ttl).chain(aname.max_ttl).map(|ttl| ("ANAME", ttl)));
+		}
+		ttls.extend(self.alias.iter().map(|alias| ("ALIAS", alias.ttl)));
+		ttls.extend(self.mx.iter().map(|mx| ("MX", mx.ttl)));
+		ttls.extend(self.txt.iter().map(|txt| ("TXT", txt.ttl)));
+		for soa in &self.soa {
+			ttls.extend([soa.ttl, soa.refresh, soa.retry, soa.expire].iter().copied().chain(soa.minimum).map(|ttl| ("SOA", ttl)));
+		}
+		ttls.extend(self.srv.iter().map(|srv| ("SRV", srv.ttl)));
+		ttls.extend(self.naptr.iter().map(|naptr| ("NAPTR", naptr.ttl)));
+		ttls.extend(self.hinfo.iter().map(|hinfo| ("HINFO", hinfo.ttl)));
+		ttls.extend(self.loc.iter().map(|loc| ("LOC", loc.ttl)));
+		ttls.extend(self.sshfp.iter().map(|sshfp| ("SSHFP", sshfp.ttl)));
+		ttls.extend(self.svcb.iter().map(|svcb| ("SVCB", svcb.ttl)));
+		ttls.extend(self.https.iter().map(|https| ("HTTPS", https.ttl)));
+		ttls.extend(self.ptr.iter().map(|ptr| ("PTR", ptr.ttl)));
+		ttls.extend(self.rns.iter().map(|rns| ("RNS", rns.ttl)));
+		ttls.extend(self.trpp.iter().map(|trpp| ("TRPP", trpp.ttl)));
+		ttls.extend(self.dnssec.iter().map(|dnssec| ("dnssec", dnssec.ttl)));
+		ttls.extend(self.nodata_ttl.iter().map(|ttl| ("nodata-ttl", *ttl)));
+		return ttls;
+	}
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Zone {
 	pub matchers: Vec<ZoneMatcher>,
+	/// Names the zone doesn't answer for even though its matchers match them, written with a `!`
+	/// (e.g. `*.example.com,!internal.example.com`), which are left to the zones after it.
+	pub excludes: Vec<ZoneMatcher>,
 	pub records: Records,
 }
 
+impl Zone {
+	/// The zone's name as it's written in a config, along with what it excludes.
+	pub fn name(&self) -> String {
+		return iter::once(zone_name(&self.matchers))
+			.chain(self.excludes.iter().map(|exclude| format!("!{}", zone_name(slice::from_ref(exclude)))))
+			.collect::<Vec<String>>()
+			.join(",");
+	}
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Config {
 	pub ttl: Duration,
 	pub nttl: Duration,
 	pub serial: u32,
+	/// Range the TTLs of records looked up elsewhere (through ANAME, RNS, or the resolver) are
+	/// clamped into.
+	pub min_ttl: Option<Duration>,
+	pub max_ttl: Option<Duration>,
+	/// Clients allowed to transfer (AXFR) every zone.
+	pub allow_transfer: Vec<IpAddr>,
+	/// Clients allowed to query zones that look records up elsewhere (ANAME, RNS, and TRPP), or
+	/// everyone if empty.
+	pub acl: Vec<Cidr>,
+	/// Whether clients outside the ACL are still answered for zones that only have local records.
+	pub acl_local: bool,
+	/// Signs every zone with a plain name that's not within another such zone.
+	pub dnssec: Option<Dnssec>,
+	/// Keys allowed to make dynamic updates to every zone with a plain name. Updates are refused
+	/// when there are none.
+	pub tsig_keys: Vec<TsigKey>,
+	/// Secondaries sent a NOTIFY when any zone with a plain name changes.
+	pub notify: Vec<SocketAddr>,
+	/// Named lists of client networks, which zones can be limited to for split-horizon answers.
+	pub networks: HashMap<String, Vec<Cidr>>,
+	/// How often the health checks of A and AAAA records are run, and how long each may take.
+	pub check_interval: Duration,
+	pub check_timeout: Duration,
+	/// Leaves out of the authority and additional sections whatever the protocol doesn't require,
+	/// as BIND's option of the same name does.
+	pub minimal_responses: bool,
+	/// Fills the authority section of answers that have nothing in it with the NS records of the zone
+	/// that answered, if it has any.
+	pub fill_authority: bool,
+	/// Looks up the targets of CNAMEs (and ALIASes answered as CNAMEs) that are outside every zone
+	/// with the resolver, rather than answering with the CNAME alone for the client to follow.
+	pub resolve_external_cnames: bool,
+	/// Answers NXDOMAIN rather than REFUSED for names outside every zone's domain, for a server
+	/// that's meant to answer for whatever it's asked.
+	pub catch_all: bool,
+	/// Looks up the targets of ANAME and ALIAS records, and the names of forwarded zones, before
+	/// their cached answers expire, as `--prefetch` does.
+	pub prefetch: bool,
+	/// NS records answered for the zones without any of their own.
+	pub default_ns: Vec<NsRecord>,
+	/// The SOA answered for the zones without one of their own, rather than one made up from the
+	/// name asked about. Its owner is still the name asked about.
+	pub default_soa: Option<SoaRecord>,
+	/// Built from the zones by `compile`, which must be done again whenever they change.
+	pub index: ZoneIndex,
 	pub zones: Vec<Zone>,
 }
 
+impl Config {
+	/// Indexes the zones, which has to be done again whenever they change.
+	pub fn compile(&mut self) {
+		self.index = ZoneIndex::new(&self.zones);
+	}
+}
+
 const DEFAULT_TTL: Duration = Duration::from_secs(60 * 30);
 const DEFAULT_NTTL: Duration = Duration::from_secs(15);
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(86400);
+pub const DEFAULT_RETRY: Duration = Duration::from_secs(7200);
+pub const DEFAULT_EXPIRE: Duration = Duration::from_secs(3600000);
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The newest version of the config grammar. Configs can say which they're written for with
+/// `version`, so that a later one can change the grammar without misreading older configs.
+pub const CONFIG_VERSION: i64 = 1;
+
+/// The keys allowed at the top level of a config.
+const TOP_LEVEL_KEYS: &[&str] = &[
+	"version", "ttl", "nttl", "min-ttl", "max-ttl", "allow-transfer", "acl", "acl-local", "dnssec", "tsig-keys", "notify", "networks",
+	"check-interval", "check-timeout", "minimal-responses", "fill-authority", "resolve-external-cnames", "catch-all", "prefetch", "default-ns", "default-soa", "strict", "zones", "include", "defaults",
+];
+
+/// The keys in a zone that are settings rather than record types.
+const ZONE_KEYS: &[&str] = &[
+	"ttl", "forward", "allow-transfer", "allow", "deny", "dnssec", "notify", "minimal-responses", "nodata-ttl", "resolve-external-cnames", "require-tsig",
+];
+
+/// Reads the config at `path`, or those in it if it's a directory: each file ending in `.yml` or
+/// `.yaml`, in the order of their names, for `parse_many`.
+pub fn read(path: &str) -> io::Result<Vec<String>> {
+	if !fs::metadata(path)?.is_dir() {
+		return Ok(vec![read_to_string(path)?]);
+	}
+	let mut paths = vec![];
+	for entry in fs::read_dir(path)? {
+		let entry_path = entry?.path();
+		let is_yaml = entry_path.extension().map(|extension| extension == "yml" || extension == "yaml").unwrap_or(false);
+		if is_yaml && !entry_path.is_dir() {
+			paths.push(entry_path);
+		}
+	}
+	paths.sort();
+	return paths.iter()
+		.map(|path| read_to_string(path).map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error))))
+		.collect();
+}
 
-pub fn parse(yaml_data: &str) -> Config {
-	let docs = YamlLoader::load_from_str(yaml_data).unwrap();
-	if docs.len() == 0 { panic!("No documents."); }
-	if docs.len() > 1 { panic!("Expected only one document."); }
-	let yaml = docs[0].as_hash().expect("Expected document to be mapping.");
+pub fn parse(yaml_data: &str) -> Result<Config, ConfigError> {
+	return parse_many(&[yaml_data.to_string()]);
+}
+
+/// Parses a config given as several YAML documents, in as many strings, each of which can have
+/// several documents separated by `---`. The first document is the config, and the zones of the
+/// others are added to its own, merged as zones given more than once in the same document are.
+/// Only the first can have settings.
+pub fn parse_many(yaml_data: &[String]) -> Result<Config, ConfigError> {
+	let mut docs = vec![];
+	for yaml_data in yaml_data {
+		docs.extend(load_documents(yaml_data)?);
+	}
+	if docs.is_empty() { return Err(ConfigError::DocumentCount(0)); }
+	let later_docs = docs.split_off(1);
+	let yaml = docs[0].expect_hash(&Location::Document)?;
+	
+	// a misspelled key would otherwise just be ignored, though ones starting with x- are left for
+	// anchors to be defined under, as in Docker Compose files
+	for (key, _) in yaml {
+		let key = key.expect_str(&Location::Document)?;
+		if !TOP_LEVEL_KEYS.contains(&key) && !key.starts_with("x-") {
+			return Err(ConfigError::UnknownKey { at: Location::Document, key: key.to_string() });
+		}
+	}
+	if let Some(version) = yaml.optional_index("version") {
+		let at = Location::Key("version".to_string());
+		let version = version.expect_i64(&at)?;
+		if version < 1 || version > CONFIG_VERSION {
+			return Err(ConfigError::InvalidValue { at, value: version.to_string(), expected: "a supported config version (1)" });
+		}
+	}
 	
 	let ttl = match yaml.optional_index("ttl") {
-		Some(ttl_value) => Duration::from_yaml(ttl_value),
+		Some(ttl_value) => Duration::from_yaml(ttl_value, &Location::Key("ttl".to_string()))?,
 		None => DEFAULT_TTL,
 	};
 	
 	let nttl = match yaml.optional_index("nttl") {
-		Some(nttl_value) => Duration::from_yaml(nttl_value),
+		Some(nttl_value) => Duration::from_yaml(nttl_value, &Location::Key("nttl".to_string()))?,
 		None => DEFAULT_NTTL,
 	};
 	
-	let zones_data = yaml.optional_index("zones").expect("Expected zones field.");
-	let zones = parse_zones(zones_data, ttl);
+	let min_ttl = yaml.optional_index("min-ttl").map(|value| Duration::from_yaml(value, &Location::Key("min-ttl".to_string()))).transpose()?;
+	let max_ttl = yaml.optional_index("max-ttl").map(|value| Duration::from_yaml(value, &Location::Key("max-ttl".to_string()))).transpose()?;
+	
+	let allow_transfer = match yaml.optional_index("allow-transfer") {
+		Some(allow_transfer) => parse_allow_transfer(allow_transfer, &Location::Key("allow-transfer".to_string()))?,
+		None => vec![],
+	};
+	
+	let acl = match yaml.optional_index("acl") {
+		Some(acl) => parse_cidrs(acl, &Location::Key("acl".to_string()))?,
+		None => vec![],
+	};
+	
+	let acl_local = match yaml.optional_index("acl-local") {
+		Some(acl_local) => acl_local.expect_bool(&Location::Key("acl-local".to_string()))?,
+		None => true,
+	};
+	
+	let dnssec = yaml.optional_index("dnssec").map(|dnssec| parse_dnssec(dnssec, ttl, &Location::Key("dnssec".to_string()))).transpose()?;
+	
+	let tsig_keys = match yaml.optional_index("tsig-keys") {
+		Some(tsig_keys) => parse_tsig_keys(tsig_keys, &Location::Key("tsig-keys".to_string()))?,
+		None => vec![],
+	};
+	
+	let notify = match yaml.optional_index("notify") {
+		Some(notify) => parse_forward_servers(notify, &Location::Key("notify".to_string()))?,
+		None => vec![],
+	};
+	
+	let networks = match yaml.optional_index("networks") {
+		Some(networks) => parse_networks(networks, &Location::Key("networks".to_string()))?,
+		None => HashMap::new(),
+	};
+	
+	let check_interval = match yaml.optional_index("check-interval") {
+		Some(check_interval) => Duration::from_yaml(check_interval, &Location::Key("check-interval".to_string()))?,
+		None => DEFAULT_CHECK_INTERVAL,
+	};
+	let check_timeout = match yaml.optional_index("check-timeout") {
+		Some(check_timeout) => Duration::from_yaml(check_timeout, &Location::Key("check-timeout".to_string()))?,
+		None => DEFAULT_CHECK_TIMEOUT,
+	};
+	
+	let minimal_responses = match yaml.optional_index("minimal-responses") {
+		Some(minimal_responses) => minimal_responses.expect_bool(&Location::Key("minimal-responses".to_string()))?,
+		None => false,
+	};
+	
+	let fill_authority = match yaml.optional_index("fill-authority") {
+		Some(fill_authority) => fill_authority.expect_bool(&Location::Key("fill-authority".to_string()))?,
+		None => true,
+	};
+	
+	let resolve_external_cnames = match yaml.optional_index("resolve-external-cnames") {
+		Some(resolve) => resolve.expect_bool(&Location::Key("resolve-external-cnames".to_string()))?,
+		None => false,
+	};
+	
+	let catch_all = match yaml.optional_index("catch-all") {
+		Some(catch_all) => catch_all.expect_bool(&Location::Key("catch-all".to_string()))?,
+		None => false,
+	};
+	
+	let prefetch = match yaml.optional_index("prefetch") {
+		Some(prefetch) => prefetch.expect_bool(&Location::Key("prefetch".to_string()))?,
+		None => false,
+	};
+	
+	let strict = match yaml.optional_index("strict") {
+		Some(strict) => strict.expect_bool(&Location::Key("strict".to_string()))?,
+		None => false,
+	};
+	
+	// with zones in other documents, the first can be just the settings
+	let no_zones = Yaml::Hash(yaml::Hash::new());
+	let zones_data = match yaml.optional_index("zones") {
+		Some(zones_data) => zones_data,
+		None if !later_docs.is_empty() => &no_zones,
+		None => return Err(ConfigError::MissingField { at: Location::Document, field: "zones" }),
+	};
+	for yaml_data in yaml_data {
+		check_repeated_zones(yaml_data, strict)?;
+	}
+	let included;
+	let zones_data = match yaml.optional_index("include") {
+		Some(include) => {
+			included = include_zones(zones_data, include, strict)?;
+			&included
+		}
+		None => zones_data,
+	};
+	let defaults = yaml.optional_index("defaults").map(|defaults| parse_defaults(defaults, ttl)).transpose()?;
+	let (default_ns, default_soa) = parse_default_ns_soa(yaml, ttl)?;
+	let mut zones = parse_zones(zones_data, ttl, &networks, &tsig_keys)?;
+	if let Some(defaults) = &defaults {
+		apply_defaults(&mut zones, zones_data, defaults);
+	}
+	for (index, doc) in later_docs.iter().enumerate() {
+		let zones_data = match later_zones(doc, index + 2)? {
+			Some(zones_data) => zones_data,
+			None => continue,
+		};
+		let mut later = parse_zones(zones_data, ttl, &networks, &tsig_keys)?;
+		if let Some(defaults) = &defaults {
+			apply_defaults(&mut later, zones_data, defaults);
+		}
+		zones.append(&mut later);
+	}
+	let zones = merge_zones(zones, strict)?;
+	
+	let mut ttls = vec![(Location::Key("ttl".to_string()), ttl), (Location::Key("nttl".to_string()), nttl)];
+	ttls.extend(min_ttl.map(|min_ttl| (Location::Key("min-ttl".to_string()), min_ttl)));
+	ttls.extend(max_ttl.map(|max_ttl| (Location::Key("max-ttl".to_string()), max_ttl)));
+	ttls.extend(dnssec.as_ref().map(|dnssec| (Location::Key("dnssec".to_string()), dnssec.ttl)));
+	if let Some(soa) = &default_soa {
+		ttls.extend([soa.ttl, soa.refresh, soa.retry, soa.expire].iter().copied().chain(soa.minimum).map(|ttl| (Location::Key("default-soa".to_string()), ttl)));
+	}
+	for zone in &zones {
+		let name = zone.name();
+		ttls.extend(zone.records.ttls().into_iter().map(|(key, ttl)| (Location::Record(name.clone(), key.to_string()), ttl)));
+	}
+	check_ttls(ttls, strict)?;
+	let index = ZoneIndex::new(&zones);
 	
-	return Config {
+	return Ok(Config {
 		ttl,
 		nttl,
 		serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+		min_ttl,
+		max_ttl,
+		allow_transfer,
+		acl,
+		acl_local,
+		dnssec,
+		tsig_keys,
+		notify,
+		networks,
+		check_interval,
+		check_timeout,
+		minimal_responses,
+		fill_authority,
+		resolve_external_cnames,
+		catch_all,
+		prefetch,
+		default_ns,
+		default_soa,
+		index,
 		zones,
-	};
+	});
+}
+
+/// Loads the single YAML document of a file under `include`.
+fn load_document(yaml_data: &str) -> Result<Yaml, ConfigError> {
+	let mut docs = load_documents(yaml_data)?;
+	if docs.len() != 1 { return Err(ConfigError::DocumentCount(docs.len())); }
+	return Ok(docs.remove(0));
+}
+
+/// Loads the YAML documents of a config, with environment variables substituted into them.
+fn load_documents(yaml_data: &str) -> Result<Vec<Yaml>, ConfigError> {
+	let mut docs = YamlLoader::load_from_str(yaml_data)?;
+	for doc in &mut docs {
+		interpolate_document(doc)?;
+	}
+	return Ok(docs);
+}
+
+/// Substitutes environment variables into each value of a document, see `interpolate`.
+fn interpolate_document(doc: &mut Yaml) -> Result<(), ConfigError> {
+	if let Yaml::Hash(hash) = doc {
+		for (key, value) in hash.iter_mut() {
+			let key = key.as_str().unwrap_or_default();
+			match value {
+				Yaml::Hash(zones) if key == "zones" => for (zone, records) in zones.iter_mut() {
+					let zone = zone.as_str().unwrap_or_default();
+					match records {
+						Yaml::Hash(records) => for (record, value) in records.iter_mut() {
+							interpolate(value, &Location::Record(zone.to_string(), record.as_str().unwrap_or_default().to_string()))?;
+						},
+						records => interpolate(records, &Location::Zone(zone.to_string()))?,
+					}
+				},
+				value => interpolate(value, &Location::Key(key.to_string()))?,
+			}
+		}
+	}
+	return Ok(());
+}
+
+/// Substitutes every `${NAME}` in the strings of a value with the environment variable `NAME`, before
+/// they're parsed, so that e.g. `${PUBLIC_IP} 5m` still has a TTL. `$${` is a literal `${`.
+fn interpolate(value: &mut Yaml, at: &Location) -> Result<(), ConfigError> {
+	match value {
+		Yaml::String(string) => {
+			let mut interpolated = String::new();
+			let mut rest = string.as_str();
+			while let Some(start) = rest.find("${") {
+				let end = match rest[start..].find('}') {
+					Some(end) => start + end,
+					None => break,
+				};
+				if rest[..start].ends_with('$') {
+					interpolated.push_str(&rest[..start - 1]);
+					interpolated.push_str("${");
+					rest = &rest[start + 2..];
+					continue;
+				}
+				let variable = &rest[start + 2..end];
+				let substituted = env::var(variable).map_err(|_| ConfigError::UnsetVariable { at: at.clone(), variable: variable.to_string() })?;
+				interpolated.push_str(&rest[..start]);
+				interpolated.push_str(&substituted);
+				rest = &rest[end + 1..];
+			}
+			interpolated.push_str(rest);
+			*string = interpolated;
+		}
+		Yaml::Array(array) => for value in array {
+			interpolate(value, at)?;
+		},
+		Yaml::Hash(hash) => for (_, value) in hash.iter_mut() {
+			interpolate(value, at)?;
+		},
+		_ => {}
+	}
+	return Ok(());
+}
+
+/// Warns about the zones given more than once under the same key in a document, of which the YAML
+/// loader only keeps the last, or refuses them if `strict`.
+fn check_repeated_zones(yaml_data: &str, strict: bool) -> Result<(), ConfigError> {
+	for zone in repeated_zone_keys(yaml_data) {
+		if strict {
+			return Err(ConfigError::DuplicateZone(zone));
+		}
+		eprintln!("warning: zone '{}' is given more than once, and only the last is used", zone);
+	}
+	return Ok(());
+}
+
+/// Warns about TTLs longer than `MAX_TTL`, which are answered with `MAX_TTL` instead, or refuses
+/// them if `strict`.
+fn check_ttls(ttls: Vec<(Location, Duration)>, strict: bool) -> Result<(), ConfigError> {
+	for (at, ttl) in ttls.into_iter().filter(|(_, ttl)| *ttl > MAX_TTL) {
+		let error = ConfigError::TtlTooLong { at, ttl: ttl.as_secs() };
+		if strict {
+			return Err(error);
+		}
+		eprintln!("warning: {}, and is answered with the maximum", error);
+	}
+	return Ok(());
+}
+
+/// Merges zones that match the same names, e.g. `Example.COM` and `example.com`, into the first of
+/// them with a warning, or refuses them if `strict`. Zones in different views are kept apart.
+#[allow(clippy::type_complexity)]
+fn merge_zones(zones: Vec<Zone>, strict: bool) -> Result<Vec<Zone>, ConfigError> {
+	let mut merged: Vec<Zone> = vec![];
+	let mut indexes: HashMap<(Vec<String>, Vec<String>, Option<String>), usize> = HashMap::new();
+	for zone in zones {
+		let key = (canonical_matchers(&zone.matchers), canonical_matchers(&zone.excludes), zone.records.view.clone());
+		match indexes.get(&key) {
+			Some(&index) => {
+				let name = merged[index].name();
+				if strict {
+					return Err(ConfigError::DuplicateZone(name));
+				}
+				eprintln!("warning: zone '{}' is given more than once, and its records are merged", name);
+				merged[index].records.merge(zone.records);
+				if let Some(rtype) = merged[index].records.none_conflict() {
+					return Err(none_conflict_error(Location::Record(name, rtype.to_string())));
+				}
+			}
+			None => {
+				indexes.insert(key, merged.len());
+				merged.push(zone);
+			}
+		}
+	}
+	return Ok(merged);
+}
+
+/// Adds the zones of the files under `include` to those of the config, in order, with zones in later
+/// files replacing those with the same key. The files can only have zones.
+fn include_zones(zones: &Yaml, include: &Yaml, strict: bool) -> Result<Yaml, ConfigError> {
+	let mut merged = zones.expect_hash(&Location::Key("zones".to_string()))?.clone();
+	for path in arrayify(include.clone()) {
+		let path = path.expect_str(&Location::Key("include".to_string()))?;
+		let include_error = |reason: String| ConfigError::Include { path: path.to_string(), reason };
+		
+		let contents = read_to_string(path).map_err(|error| include_error(format!("can't be read: {}", error)))?;
+		let doc = load_document(&contents).map_err(|error| include_error(error.to_string()))?;
+		check_repeated_zones(&contents, strict).map_err(|error| include_error(error.to_string()))?;
+		let doc = doc.expect_hash(&Location::Document).map_err(|error| include_error(error.to_string()))?;
+		for (key, value) in doc {
+			if key.as_str() != Some("zones") {
+				return Err(include_error(format!("has {}, but only 'zones' can be included", describe(key))));
+			}
+			let zones = value.expect_hash(&Location::Key("zones".to_string())).map_err(|error| include_error(error.to_string()))?;
+			for (zone, records) in zones {
+				merged.insert(zone.clone(), records.clone());
+			}
+		}
+	}
+	return Ok(Yaml::Hash(merged));
+}
+
+/// The zones of a document after the first, which can't have settings, only zones and `x-` keys for
+/// anchors. `number` counts the documents from one, for the diagnostic.
+fn later_zones(doc: &Yaml, number: usize) -> Result<Option<&Yaml>, ConfigError> {
+	let doc = doc.expect_hash(&Location::Document)?;
+	for (key, _) in doc {
+		let is_anchors = key.as_str().map(|key| key.starts_with("x-")).unwrap_or(false);
+		if key.as_str() != Some("zones") && !is_anchors {
+			return Err(ConfigError::LaterDocument { number, key: describe(key) });
+		}
+	}
+	return Ok(doc.optional_index("zones"));
 }
 
-fn parse_basic(i: &[u8]) -> IResult<&[u8], Label> {
-	let (i, value) = take_while_m_n(1, 63, |x| (x >= 'a' as u8 && x <= 'z' as u8) || (x >= 'A' as u8 && x <= 'Z' as u8) || (x >= '0' as u8 && x <= '9' as u8) || x == '-' as u8 || x == '_' as u8)(i)?;
-	Ok((i, Label::Basic(String::from_utf8(value.to_vec()).unwrap().to_lowercase())))
+/// Parses a plain label, converting it to punycode if it's Unicode (e.g. `münchen` to
+/// `xn--mnchen-3ya`), as that's how it's queried.
+fn parse_basic(input: &[u8]) -> IResult<&[u8], Label> {
+	let (i, value) = take_while1(|x: u8| x.is_ascii_alphanumeric() || x == b'-' || x == b'_' || x >= 0x80)(input)?;
+	let label = match String::from_utf8(value.to_vec()) {
+		Ok(label) if label.is_ascii() => label.to_lowercase(),
+		Ok(label) => match idna::domain_to_ascii(&label) {
+			Ok(label) => label,
+			Err(_) => return Err(nom::Err::Failure((input, ErrorKind::Char))),
+		},
+		Err(_) => return Err(nom::Err::Failure((input, ErrorKind::Char))),
+	};
+	if label.len() > 63 {
+		return Err(nom::Err::Failure((input, ErrorKind::TooLarge)));
+	}
+	Ok((i, Label::Basic(label)))
 }
 
 fn parse_regex(i: &[u8]) -> IResult<&[u8], Label> {
@@ -160,9 +1056,11 @@ fn parse_regex(i: &[u8]) -> IResult<&[u8], Label> {
 		escaped(none_of("\\/"), '\\', take(1usize)),
 		tag("/"),
 	)(i)?;
+	let (i, substring) = opt(tag("~"))(i)?;
 	let pattern = String::from_utf8(value.to_vec()).unwrap()
 		.replace(r"\/", "/");
-	Ok((i, Label::Regex(pattern.contains(r"\."), Regex::new(&pattern).unwrap())))
+	let regex = LabelRegex::new(&pattern, substring.is_some()).map_err(|_| nom::Err::Failure((value, ErrorKind::Verify)))?;
+	Ok((i, Label::Regex(pattern.contains(r"\."), regex)))
 }
 
 fn parse_wildcard(i: &[u8]) -> IResult<&[u8], Label> {
@@ -188,29 +1086,177 @@ fn parse_zone_matcher(i: &[u8]) -> IResult<&[u8], ZoneMatcher> {
 	separated_list(tag("."), parse_label)(i)
 }
 
-fn parse_zone_matchers(i: &[u8]) -> IResult<&[u8], Vec<ZoneMatcher>> {
-	separated_list(tag(","), parse_zone_matcher)(i)
+/// Parses the matchers of a zone name, along with those after a `!` that it excludes.
+fn parse_zone_matchers(i: &[u8]) -> IResult<&[u8], (Vec<ZoneMatcher>, Vec<ZoneMatcher>)> {
+	let (i, matchers) = separated_list(tag(","), pair(opt(tag("!")), parse_zone_matcher))(i)?;
+	let (excludes, matchers): (Vec<_>, Vec<_>) = matchers.into_iter().partition(|(exclude, _)| exclude.is_some());
+	let labels = |matchers: Vec<(Option<&[u8]>, ZoneMatcher)>| matchers.into_iter().map(|(_, labels)| labels).collect();
+	Ok((i, (labels(matchers), labels(excludes))))
 }
 
-fn parse_zones(yaml: &Yaml, default_ttl: Duration) -> Vec<Zone> {
-	let yaml = yaml.as_hash().expect("Expected zones to be mapping.");
+/// Parses a zone name, e.g. `example.com,*.example.com,!internal.example.com`, into what it matches
+/// and what it excludes of that. A name matching both is excluded, wherever the `!` is in the name,
+/// and a name can't have only exclusions, as there'd be nothing to exclude them from.
+pub fn parse_zone_name(name: &str) -> Result<(Vec<ZoneMatcher>, Vec<ZoneMatcher>), ConfigError> {
+	return match parse_zone_matchers(name.as_ref()) {
+		Ok((&[], (matchers, excludes))) if !matchers.is_empty() || excludes.is_empty() => Ok((matchers, excludes)),
+		_ => Err(ConfigError::InvalidZoneName(name.to_string())),
+	};
+}
+
+/// Writes zone matchers back out as a zone name that parses to the same matchers.
+pub fn zone_name(matchers: &[ZoneMatcher]) -> String {
+	return matchers.iter()
+		.map(|matcher| matcher.iter().map(Label::to_string).collect::<Vec<String>>().join("."))
+		.collect::<Vec<String>>()
+		.join(",");
+}
+
+/// Drops the spaces after the commas between a zone key's matchers, as in `*.example.com,
+/// !internal.example.com`, which would otherwise start its flags. Commas inside a regular
+/// expression (e.g. `/a{1, 3}/`) are part of it and left alone.
+pub fn join_matchers(key: &str) -> String {
+	let mut joined = String::with_capacity(key.len());
+	let mut in_regex = false;
+	let mut escaped = false;
+	let mut after_comma = false;
+	for c in key.chars() {
+		if after_comma && c == ' ' {
+			continue;
+		}
+		after_comma = c == ',' && !in_regex;
+		if c == '/' && !escaped {
+			in_regex = !in_regex;
+		}
+		escaped = c == '\\' && !escaped;
+		joined.push(c);
+	}
+	return joined;
+}
+
+fn parse_zones(yaml: &Yaml, default_ttl: Duration, networks: &HashMap<String, Vec<Cidr>>, tsig_keys: &[TsigKey]) -> Result<Vec<Zone>, ConfigError> {
+	let yaml = yaml.expect_hash(&Location::Key("zones".to_string()))?;
 	
 	let mut zones = Vec::new();
 	
 	for (key, value) in yaml {
-		let (content, ttl, _) = parse_value_ttl(key.expect_str(), default_ttl);
-		let zone_matchers = parse_zone_matchers(content.as_ref()).unwrap().1;
+		let key = join_matchers(key.expect_str(&Location::Key("zones".to_string()))?);
+		let (content, ttl, flags) = parse_value_ttl(&key, default_ttl);
+		let (zone_matchers, excludes) = parse_zone_name(content)?;
+		for exclude in &excludes {
+			if let Some(name) = plain_name(exclude).filter(|name| !does_match(&zone_matchers, name)) {
+				eprintln!("warning: zone '{}' excludes '{}', which it doesn't match anyway", content, name.join("."));
+			}
+		}
 		
-		let value = value.as_hash().expect(format!("Expected zone value to be mapping: {:?}", value).as_str());
-		let records = parse_zone_content(value, ttl);
+		let at = Location::Zone(content.to_string());
+		let (views, flags): (Vec<&str>, Vec<&str>) = flags.into_iter().partition(|flag| flag.starts_with('@'));
+		check_flags(&flags, &["inherit"], &at)?;
+		let value = value.expect_hash(&at)?;
+		let mut records = parse_zone_content(content, value, ttl)?;
+		records.ttl = records.ttl.or(Some(ttl).filter(|ttl| *ttl != default_ttl));
+		records.inherit = flags.contains(&"inherit");
+		records.view = match views[..] {
+			[] => None,
+			[view] if networks.contains_key(&view[1..]) => Some(view[1..].to_string()),
+			[view] => return Err(ConfigError::UnknownNetwork { zone: content.to_string(), network: view[1..].to_string() }),
+			_ => return Err(ConfigError::InvalidValue { at, value: views.join(" "), expected: "a single view" }),
+		};
+		if let Some(key) = &records.require_tsig {
+			if !tsig_keys.iter().any(|tsig_key| tsig_key.name == *key) {
+				return Err(ConfigError::InvalidValue { at: Location::Record(content.to_string(), "require-tsig".to_string()), value: key.clone(), expected: "a key under 'tsig-keys'" });
+			}
+		}
+		if records.dnssec.is_some() && !zone_matchers.iter().any(|matcher| matcher.iter().all(|label| matches!(label, Label::Basic(_)))) {
+			return Err(ConfigError::InvalidValue { at: Location::Record(content.to_string(), "dnssec".to_string()), value: content.to_string(), expected: "a plain zone name to sign as" });
+		}
 		
 		zones.push(Zone {
 			matchers: zone_matchers,
+			excludes,
 			records,
 		});
 	}
 	
-	return zones;
+	return Ok(zones);
+}
+
+/// Parses the records under `defaults`, which are written like those of a zone, but can't include
+/// settings or a CNAME, as a CNAME can't have any other records beside it.
+fn parse_defaults(value: &Yaml, ttl: Duration) -> Result<Records, ConfigError> {
+	let at = Location::Key("defaults".to_string());
+	let mut records = Records::default();
+	for (key, value) in value.expect_hash(&at)? {
+		let key = key.expect_str(&at)?;
+		let (record_type, _, _) = parse_value_ttl(key, ttl);
+		if record_type.to_uppercase() != record_type || record_type == "CNAME" {
+			return Err(ConfigError::InvalidValue { at, value: key.to_string(), expected: "a record type other than CNAME" });
+		}
+		parse_records("defaults", key, value, ttl, &mut records)?;
+	}
+	return Ok(records);
+}
+
+/// Parses `default-ns` and `default-soa`, which are answered for zones without their own NS records
+/// or SOA, rather than being added to them like `defaults`. The SOA's `mname` can be left out for
+/// the first of the NS records.
+fn parse_default_ns_soa(yaml: &yaml::Hash, ttl: Duration) -> Result<(Vec<NsRecord>, Option<SoaRecord>), ConfigError> {
+	let mut records = Records::default();
+	if let Some(default_ns) = yaml.optional_index("default-ns") {
+		parse_records("default-ns", "NS", default_ns, ttl, &mut records)?;
+	}
+	if let Some(default_soa) = yaml.optional_index("default-soa") {
+		let mut default_soa = default_soa.clone();
+		if let (Yaml::Hash(hash), Some(ns)) = (&mut default_soa, records.ns.first()) {
+			hash.entry(Yaml::String("mname".to_string())).or_insert_with(|| Yaml::String(ns.name.clone()));
+		}
+		parse_records("default-soa", "SOA", &default_soa, ttl, &mut records)?;
+	}
+	return Ok((records.ns, records.soa.pop()));
+}
+
+/// Adds the records under `defaults` to every zone, after the zone's own records of each type. A
+/// zone can leave out a type of default records by giving that type with no value (e.g. `TXT:`).
+/// Zones with a CNAME get none, as nothing else can be beside it, and neither do delegations, as
+/// they'd no longer be one.
+fn apply_defaults(zones: &mut [Zone], zones_data: &Yaml, defaults: &Records) {
+	let zones_data = zones_data.as_hash().into_iter().flat_map(|zones_data| zones_data.values());
+	for (zone, zone_data) in zones.iter_mut().zip(zones_data) {
+		let records = &mut zone.records;
+		if !records.cname.is_empty() || records.is_delegation() { continue; }
+		let left_out: Vec<&str> = zone_data.as_hash().into_iter().flatten()
+			.filter(|(_, value)| value.is_null())
+			.filter_map(|(key, _)| key.as_str())
+			.map(|key| parse_value_ttl(key, DEFAULT_TTL).0)
+			.collect();
+		let add = |record_type: &str| !left_out.contains(&record_type);
+		// nor to a type the zone says it has none of
+		if add("A") && !records.no_a {
+			records.a.extend(defaults.a.iter().cloned());
+			records.rotate_a |= defaults.rotate_a;
+		}
+		if add("AAAA") && !records.no_aaaa {
+			records.aaaa.extend(defaults.aaaa.iter().cloned());
+			records.rotate_aaaa |= defaults.rotate_aaaa;
+		}
+		if add("NS") { records.ns.extend(defaults.ns.iter().cloned()); }
+		if add("ANAME") { records.aname.extend(defaults.aname.iter().cloned()); }
+		if add("ALIAS") { records.alias.extend(defaults.alias.iter().cloned()); }
+		if add("MX") { records.mx.extend(defaults.mx.iter().cloned()); }
+		if add("TXT") { records.txt.extend(defaults.txt.iter().cloned()); }
+		// a zone has a single SOA, so the default is only for zones without one
+		if add("SOA") && records.soa.is_empty() { records.soa.extend(defaults.soa.iter().cloned()); }
+		if add("SRV") { records.srv.extend(defaults.srv.iter().cloned()); }
+		if add("NAPTR") { records.naptr.extend(defaults.naptr.iter().cloned()); }
+		if add("HINFO") { records.hinfo.extend(defaults.hinfo.iter().cloned()); }
+		if add("LOC") { records.loc.extend(defaults.loc.iter().cloned()); }
+		if add("SSHFP") { records.sshfp.extend(defaults.sshfp.iter().cloned()); }
+		if add("SVCB") { records.svcb.extend(defaults.svcb.iter().cloned()); }
+		if add("HTTPS") { records.https.extend(defaults.https.iter().cloned()); }
+		if add("PTR") { records.ptr.extend(defaults.ptr.iter().cloned()); }
+		if add("RNS") { records.rns.extend(defaults.rns.iter().cloned()); }
+		if add("TRPP") { records.trpp.extend(defaults.trpp.iter().cloned()); }
+	}
 }
 
 fn arrayify(value: Yaml) -> yaml::Array {
@@ -221,198 +1267,864 @@ fn arrayify(value: Yaml) -> yaml::Array {
 	}
 }
 
-fn parse_value_ttl(value: &str, default_ttl: Duration) -> (&str, Duration, Vec<&str>) {
-	let parts: Vec<&str> = value.split(' ').collect();
-	
-	let body = parts[0];
-	
-	let duration = Duration::parse(parts.last().unwrap());
-	
-	let flags = parts[1..parts.len() - if duration.is_ok() { 1 } else { 0 }].to_vec();
-	
-	return (body, duration.unwrap_or(default_ttl), flags);
+/// Makes a `ConfigError::InvalidValue`, for use with `map_err`.
+fn invalid_value<E>(at: &Location, value: &str, expected: &'static str) -> impl FnOnce(E) -> ConfigError {
+	let error = ConfigError::InvalidValue { at: at.clone(), value: value.to_string(), expected };
+	return move |_| error;
 }
 
-fn parse_zone_content(zone: &yaml::Hash, ttl: Duration) -> Records {
-	let mut records = Records::default();
-	
-	for (key, value) in zone {
-		let (key_record_type, ttl, _) = parse_value_ttl(key.expect_str(), ttl);
-		
-		if key_record_type.to_uppercase().as_str() == key_record_type {
-			let entries = arrayify(value.clone());
-			match key_record_type {
-				"A" => {
-					for entry in entries {
-						let (value, ttl, _) = parse_value_ttl(&entry.expect_str(), ttl);
-						let ip4_addr: Ipv4Addr = value.parse().expect(format!("Value not valid IPv4 address: {:?}", value).as_str());
-						records.a.push(ARecord {
-							ttl,
-							ip4addr: ip4_addr,
-						});
-					}
-				}
-				"AAAA" => {
-					for entry in entries {
-						let (value, ttl, _) = parse_value_ttl(&entry.expect_str(), ttl);
-						let ip6_addr: Ipv6Addr = value.parse().expect(format!("Value not valid IPv6 address: {:?}", value).as_str());
-						records.aaaa.push(AaaaRecord {
+fn parse_allow_transfer(value: &Yaml, at: &Location) -> Result<Vec<IpAddr>, ConfigError> {
+	return arrayify(value.clone()).iter()
+		.map(|entry| {
+			let entry = entry.expect_str(at)?;
+			entry.parse().map_err(invalid_value(at, entry, "a valid IP address"))
+		})
+		.collect();
+}
+
+/// Parses addresses with an optional port, which defaults to 53. IPv6 addresses with a port are
+/// surrounded in square brackets.
+fn parse_forward_servers(value: &Yaml, at: &Location) -> Result<Vec<SocketAddr>, ConfigError> {
+	return arrayify(value.clone()).iter()
+		.map(|entry| {
+			let entry = entry.expect_str(at)?;
+			entry.parse()
+				.or_else(|_| entry.parse().map(|ip_addr| SocketAddr::new(ip_addr, 53)))
+				.map_err(invalid_value(at, entry, "a valid IP address or socket address"))
+		})
+		.collect();
+}
+
+/// Converts the Unicode labels of a name in a record to punycode, leaving the rest as they are,
+/// including a trailing dot.
+fn to_ascii_name(name: &str, at: &Location) -> Result<String, ConfigError> {
+	return Ok(name.split('.')
+		.map(|label| if label.is_ascii() {
+			Ok(label.to_string())
+		} else {
+			idna::domain_to_ascii(label).map_err(invalid_value(at, name, "a valid internationalized name"))
+		})
+		.collect::<Result<Vec<String>, ConfigError>>()?
+		.join("."));
+}
+
+/// The fields of an SVCB or HTTPS record, followed by the names of the params it can have.
+const SVCB_FIELDS: &[&str] = &["ttl", "priority", "target", "mandatory", "alpn", "no-default-alpn", "port", "ipv4hint", "ipv6hint"];
+
+/// Parses an SVCB or HTTPS record, e.g. `{ priority: 1, target: ., alpn: [h2, h3] }`. The target
+/// defaults to `.`, and the params are checked against the rules of RFC 9460 that are about the
+/// record alone.
+fn parse_svcb(hash: &yaml::Hash, ttl: Duration, at: &Location) -> Result<SvcbRecord, ConfigError> {
+	for key in hash.keys() {
+		let key = key.expect_str(at)?;
+		if !SVCB_FIELDS.contains(&key) && key != "comment" && key != "labels" {
+			return Err(ConfigError::InvalidValue { at: at.clone(), value: key.to_string(), expected: "one of ttl, priority, target, mandatory, alpn, no-default-alpn, port, ipv4hint, ipv6hint, comment, or labels" });
+		}
+	}
+	let strings = |field: &str| -> Result<Vec<String>, ConfigError> {
+		return hash.optional_index(field).map(|value| arrayify(value.clone())).unwrap_or_default().iter()
+			.map(|value| value.expect_str(at).map(str::to_string))
+			.collect();
+	};
+	
+	let params = SvcParams {
+		mandatory: strings("mandatory")?,
+		alpn: strings("alpn")?,
+		no_default_alpn: match hash.optional_index("no-default-alpn") {
+			Some(value) => value.expect_bool(at)?,
+			None => false,
+		},
+		port: optional_number(hash, "port", at)?,
+		ipv4hint: strings("ipv4hint")?.iter()
+			.map(|address| address.parse().map_err(invalid_value(at, address, "a valid IPv4 address")))
+			.collect::<Result<Vec<Ipv4Addr>, ConfigError>>()?,
+		ipv6hint: strings("ipv6hint")?.iter()
+			.map(|address| address.parse().map_err(invalid_value(at, address, "a valid IPv6 address")))
+			.collect::<Result<Vec<Ipv6Addr>, ConfigError>>()?,
+	};
+	let priority = optional_number(hash, "priority", at)?.ok_or(ConfigError::MissingField { at: at.clone(), field: "priority" })?;
+	
+	// https://tools.ietf.org/html/rfc9460#section-2.4.2
+	let given: Vec<&str> = SVCB_FIELDS[3..].iter().copied().filter(|field| hash.optional_index(field).is_some()).collect();
+	if priority == 0 {
+		if let Some(field) = given.first() {
+			return Err(ConfigError::InvalidValue { at: at.clone(), value: field.to_string(), expected: "allowed in alias mode, with a priority of 0" });
+		}
+	}
+	// https://tools.ietf.org/html/rfc9460#section-7.1.1
+	if let Some(id) = params.alpn.iter().find(|id| id.is_empty() || id.len() > 255) {
+		return Err(ConfigError::InvalidValue { at: at.clone(), value: id.clone(), expected: "an ALPN protocol ID from 1 to 255 bytes long" });
+	}
+	if params.no_default_alpn && params.alpn.is_empty() {
+		return Err(ConfigError::InvalidValue { at: at.clone(), value: "no-default-alpn".to_string(), expected: "allowed without alpn" });
+	}
+	// https://tools.ietf.org/html/rfc9460#section-8
+	if let Some(name) = params.mandatory.iter().find(|name| *name == "mandatory" || !given.contains(&name.as_str())) {
+		return Err(ConfigError::InvalidValue { at: at.clone(), value: name.clone(), expected: "another param given in the record" });
+	}
+	
+	let target = match hash.optional_index("target") {
+		Some(target) => target.expect_str(at)?,
+		None => ".",
+	};
+	return Ok(SvcbRecord {
+		ttl: optional_duration(hash, "ttl", at)?.unwrap_or(ttl),
+		priority,
+		target: to_ascii_name(target, at)?,
+		params,
+		metadata: None,
+	});
+}
+
+/// Parses hex digits, e.g. of an SSHFP fingerprint, into the bytes they're of.
+fn parse_hex(value: &str) -> Option<Vec<u8>> {
+	if value.is_empty() || !value.len().is_multiple_of(2) || !value.chars().all(|char| char.is_ascii_hexdigit()) {
+		return None;
+	}
+	return (0..value.len()).step_by(2).map(|index| u8::from_str_radix(&value[index..index + 2], 16).ok()).collect();
+}
+
+fn parse_cidrs(value: &Yaml, at: &Location) -> Result<Vec<Cidr>, ConfigError> {
+	return arrayify(value.clone()).iter()
+		.map(|entry| {
+			let entry = entry.expect_str(at)?;
+			entry.parse().map_err(invalid_value(at, entry, "a valid IP address or prefix"))
+		})
+		.collect();
+}
+
+fn parse_value_ttl(value: &str, default_ttl: Duration) -> (&str, Duration, Vec<&str>) {
+	let parts: Vec<&str> = value.split(' ').collect();
+	
+	let body = parts[0];
+	
+	// a value that's only a number, like `TXT: 12345`, is the value rather than its TTL
+	let duration = if parts.len() > 1 { Duration::parse(parts.last().unwrap()) } else { Err(ttl::NotATtlError) };
+	
+	let flags = parts[1..parts.len() - if duration.is_ok() { 1 } else { 0 }].to_vec();
+	
+	return (body, duration.unwrap_or(default_ttl), flags);
+}
+
+/// Errors if a flag isn't one of those allowed where it was given, as a typo would otherwise be
+/// silently ignored. Flags starting with a digit are taken to be a mistyped TTL.
+fn check_flags(flags: &[&str], allowed: &[&'static str], at: &Location) -> Result<(), ConfigError> {
+	return match flags.iter().find(|flag| !allowed.contains(flag)) {
+		Some(flag) if flag.starts_with(|c: char| c.is_ascii_digit()) => Err(ConfigError::InvalidTtl { at: at.clone(), value: flag.to_string() }),
+		Some(flag) => Err(ConfigError::UnknownFlag { at: at.clone(), flag: flag.to_string(), allowed: allowed.to_vec() }),
+		None => Ok(()),
+	};
+}
+
+/// `parse_value_ttl` for a record's value, checking its flags.
+fn parse_entry<'a>(value: &'a str, default_ttl: Duration, allowed_flags: &[&'static str], at: &Location) -> Result<(&'a str, Duration, Vec<&'a str>), ConfigError> {
+	let (value, ttl, flags) = parse_value_ttl(value, default_ttl);
+	check_flags(&flags, allowed_flags, at)?;
+	return Ok((value, ttl, flags));
+}
+
+/// Parses an entry that's either a string, or a mapping with the value under `field` along with an
+/// optional `ttl`, and the `comment` and `labels` every mapping can have. Flags are only taken from
+/// a string.
+fn parse_named_entry<'a>(entry: &'a Yaml, field: &'static str, default_ttl: Duration, allowed_flags: &[&'static str], at: &Location) -> Result<(String, Duration, Vec<&'a str>), ConfigError> {
+	return match entry {
+		Yaml::Hash(hash) => Ok((coerce_scalar(required(hash, field, at)?, at)?, optional_duration(hash, "ttl", at)?.unwrap_or(default_ttl), vec![])),
+		entry => parse_entry(entry.expect_str(at)?, default_ttl, allowed_flags, at).map(|(value, ttl, flags)| (value.to_string(), ttl, flags)),
+	};
+}
+
+/// The `comment` and `labels` of an entry given as a mapping, if it has either. Labels are a
+/// mapping of strings, though numbers and booleans are taken as they're written.
+fn parse_metadata(entry: &Yaml, at: &Location) -> Result<Option<Metadata>, ConfigError> {
+	let hash = match entry {
+		Yaml::Hash(hash) => hash,
+		_ => return Ok(None),
+	};
+	let comment = hash.optional_index("comment").map(|comment| comment.expect_str(at).map(str::to_string)).transpose()?;
+	let mut labels = BTreeMap::new();
+	if let Some(value) = hash.optional_index("labels") {
+		for (key, value) in value.expect_hash(at)? {
+			let value = match value {
+				Yaml::String(string) => string.clone(),
+				Yaml::Integer(_) | Yaml::Real(_) | Yaml::Boolean(_) => describe(value),
+				_ => return Err(wrong_type(value, at, "a string")),
+			};
+			labels.insert(key.expect_str(at)?.to_string(), value);
+		}
+	}
+	if comment.is_none() && labels.is_empty() {
+		return Ok(None);
+	}
+	return Ok(Some(Metadata { comment, labels }));
+}
+
+/// Parses an A or AAAA entry, which is either an address with an optional TTL, or a mapping with
+/// the address as `ip` along with an optional `ttl`, `weight`, and health `check`.
+fn parse_address_entry<A: FromStr>(entry: &Yaml, default_ttl: Duration, allowed_flags: &[&'static str], at: &Location, expected: &'static str) -> Result<(A, Duration, Option<u32>, Option<Check>), ConfigError> {
+	let hash = match entry {
+		Yaml::Hash(hash) => hash,
+		entry => {
+			let (value, ttl, _) = parse_entry(entry.expect_str(at)?, default_ttl, allowed_flags, at)?;
+			return Ok((value.parse().map_err(invalid_value(at, value, expected))?, ttl, None, None));
+		}
+	};
+	let ip = coerce_scalar(required(hash, "ip", at)?, at)?;
+	let ip = ip.parse().map_err(invalid_value(at, &ip, expected))?;
+	let ttl = hash.optional_index("ttl").map(|ttl| Duration::from_yaml(ttl, at)).transpose()?.unwrap_or(default_ttl);
+	let weight = match hash.optional_index("weight") {
+		Some(weight) => {
+			let weight = weight.expect_i64(at)?;
+			Some(u32::try_from(weight).map_err(invalid_value(at, &weight.to_string(), "a valid weight"))?)
+		}
+		None => None,
+	};
+	let check = hash.optional_index("check").map(|check| parse_check(check.expect_str(at)?, at)).transpose()?;
+	return Ok((ip, ttl, weight, check));
+}
+
+fn parse_check(value: &str, at: &Location) -> Result<Check, ConfigError> {
+	let expected = "a valid check, like tcp://192.0.2.1:443 or https://192.0.2.1/healthz";
+	if let Some(address) = value.strip_prefix("tcp://") {
+		return address.parse().map(Check::Tcp).map_err(invalid_value(at, value, expected));
+	}
+	if value.starts_with("http://") || value.starts_with("https://") {
+		return Ok(Check::Http(value.to_string()));
+	}
+	return Err(ConfigError::InvalidValue { at: at.clone(), value: value.to_string(), expected });
+}
+
+/// Parses a TXT value, which is taken as it is apart from a TTL at the end. Quoting it (e.g.
+/// `"retry in 30m" 1h`) keeps whatever it ends with as part of the value.
+fn parse_txt_value(value: &str, default_ttl: Duration, at: &Location) -> Result<(String, Duration), ConfigError> {
+	if let Some(quoted) = value.strip_prefix('"') {
+		if let Some(end) = quoted.rfind('"') {
+			let ttl = match quoted[end + 1..].trim() {
+				"" => default_ttl,
+				ttl => Duration::parse(ttl).map_err(|_| ConfigError::InvalidTtl { at: at.clone(), value: ttl.to_string() })?,
+			};
+			return Ok((quoted[..end].to_string(), ttl));
+		}
+	}
+	return Ok(match value.rsplit_once(' ').map(|(data, ttl)| (data, Duration::parse(ttl))) {
+		Some((data, Ok(ttl))) => (data.to_string(), ttl),
+		_ => (value.to_string(), default_ttl),
+	});
+}
+
+fn parse_zone_content(zone_name: &str, zone: &yaml::Hash, ttl: Duration) -> Result<Records, ConfigError> {
+	let mut records = Records::default();
+	let at_key = |key: &str| Location::Record(zone_name.to_string(), key.to_string());
+	
+	// a ttl key in the zone overrides both the global TTL and the one given after the zone name,
+	// while TTLs given for a record type or a single record override it in turn
+	let ttl = match zone.optional_index("ttl") {
+		Some(ttl_value) => {
+			let ttl = Duration::from_yaml(ttl_value, &at_key("ttl"))?;
+			records.ttl = Some(ttl);
+			ttl
+		}
+		None => ttl,
+	};
+	
+	let mut seen_types = vec![];
+	for (key, value) in zone {
+		let key = key.expect_str(&Location::Zone(zone_name.to_string()))?;
+		let (key_name, _, key_flags) = parse_value_ttl(key, ttl);
+		let at = at_key(key_name);
+		match key_name {
+			"forward" => check_flags(&key_flags, &["only", "noaa"], &at)?,
+			key_name if ZONE_KEYS.contains(&key_name) => check_flags(&key_flags, &[], &at)?,
+			_ => {}
+		}
+		match key_name {
+			"ttl" => continue,
+			"forward" => {
+				records.forward = Some(Forward {
+					servers: parse_forward_servers(value, &at)?,
+					only: key_flags.contains(&"only"),
+					authoritative: !key_flags.contains(&"noaa"),
+				});
+				continue;
+			}
+			"allow-transfer" => {
+				records.allow_transfer = parse_allow_transfer(value, &at)?;
+				continue;
+			}
+			"allow" => {
+				records.allow = parse_cidrs(value, &at)?;
+				continue;
+			}
+			"deny" => {
+				records.deny = parse_cidrs(value, &at)?;
+				continue;
+			}
+			"dnssec" => {
+				records.dnssec = Some(parse_dnssec(value, ttl, &at)?);
+				continue;
+			}
+			"notify" => {
+				records.notify = parse_forward_servers(value, &at)?;
+				continue;
+			}
+			"minimal-responses" => {
+				records.minimal_responses = Some(value.expect_bool(&at)?);
+				continue;
+			}
+			"nodata-ttl" => {
+				records.nodata_ttl = Some(Duration::from_yaml(value, &at)?);
+				continue;
+			}
+			"resolve-external-cnames" => {
+				records.resolve_external_cnames = Some(value.expect_bool(&at)?);
+				continue;
+			}
+			"require-tsig" => {
+				records.require_tsig = Some(value.expect_str(&at)?.trim_end_matches('.').to_lowercase());
+				continue;
+			}
+			_ => {}
+		}
+		let (key_record_type, key_ttl, _) = parse_value_ttl(key, ttl);
+		if key_record_type.to_uppercase().as_str() != key_record_type {
+			// names of zones have dots in them, where settings never do
+			if key_record_type.contains('.') {
+				return Err(ConfigError::NestedZone { zone: zone_name.to_string(), key: key_record_type.to_string() });
+			}
+			return Err(ConfigError::UnknownKey { at: Location::Zone(zone_name.to_string()), key: key_record_type.to_string() });
+		}
+		// the API writes records of a type under its first key
+		if !seen_types.contains(&key_record_type) {
+			seen_types.push(key_record_type);
+			if key_ttl != ttl { records.type_ttls.push((key_record_type.to_string(), key_ttl)); }
+		}
+		parse_records(zone_name, key, value, ttl, &mut records)?;
+	}
+	
+	return Ok(records);
+}
+
+/// Parses the entries of a record type in a zone (e.g. `A 5m: [10.0.0.1, 10.0.0.2 1h]`), adding
+/// them to `records`. The TTL is used for entries that don't give one, unless the key does.
+pub fn parse_records(zone_name: &str, key: &str, value: &Yaml, ttl: Duration, records: &mut Records) -> Result<(), ConfigError> {
+	let (key_record_type, ttl, flags) = parse_value_ttl(key, ttl);
+	let at = Location::Record(zone_name.to_string(), key_record_type.to_string());
+	
+	// flags can be given on the record type (e.g. `A rotate:`), and on each value (e.g.
+	// `RNS: ns1.example.com external`)
+	let (key_flags, value_flags): (&[&str], &[&str]) = match key_record_type {
+		"A" | "AAAA" => (&["rotate"], &[]),
+		"RNS" => (&[], &["external"]),
+		_ => (&[], &[]),
+	};
+	check_flags(&flags, key_flags, &at)?;
+	// every branch below takes its compact form from a string, which numbers are made into first
+	let entries = arrayify(value.clone()).into_iter()
+		.map(|entry| match entry {
+			Yaml::String(_) | Yaml::Integer(_) | Yaml::Real(_) | Yaml::Boolean(_) => coerce_scalar(&entry, &at).map(Yaml::String),
+			entry => Ok(entry),
+		})
+		.collect::<Result<Vec<Yaml>, ConfigError>>()?;
+	// `none` on its own says there are none of the type, rather than giving an address
+	let none = entries.len() == 1 && entries[0].as_str() == Some("none");
+	match key_record_type {
+		"A" if none => records.no_a = true,
+		"AAAA" if none => records.no_aaaa = true,
+		"A" => {
+			records.rotate_a |= flags.contains(&"rotate");
+			for entry in entries {
+				let (ip4addr, ttl, weight, check) = parse_address_entry(&entry, ttl, value_flags, &at, "a valid IPv4 address")?;
+				records.a.push(ARecord {
+					ttl,
+					ip4addr,
+					weight,
+					check,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"AAAA" => {
+			records.rotate_aaaa |= flags.contains(&"rotate");
+			for entry in entries {
+				let (ip6addr, ttl, weight, check) = parse_address_entry(&entry, ttl, value_flags, &at, "a valid IPv6 address")?;
+				records.aaaa.push(AaaaRecord {
+					ttl,
+					ip6addr,
+					weight,
+					check,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"NS" => {
+			for entry in entries {
+				let (value, ttl, _) = parse_named_entry(&entry, "name", ttl, value_flags, &at)?;
+				records.ns.push(NsRecord {
+					ttl,
+					name: to_ascii_name(&value, &at)?,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"CNAME" => {
+			for entry in entries {
+				let (value, ttl, _) = parse_named_entry(&entry, "name", ttl, value_flags, &at)?;
+				records.cname.push(CnameRecord {
+					ttl,
+					name: to_ascii_name(&value, &at)?,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"ANAME" => {
+			for entry in entries {
+				match &entry {
+					Yaml::String(string) => {
+						let (value, ttl, _) = parse_entry(string, ttl, value_flags, &at)?;
+						records.aname.push(AnameRecord {
 							ttl,
-							ip6addr: ip6_addr,
+							name: to_ascii_name(value, &at)?,
+							min_ttl: None,
+							max_ttl: None,
+							metadata: None,
 						});
 					}
+					Yaml::Hash(hash) => {
+						let name = coerce_scalar(required(hash, "name", &at)?, &at)?;
+						records.aname.push(AnameRecord {
+							ttl: optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+							name: to_ascii_name(&name, &at)?,
+							min_ttl: optional_duration(hash, "min-ttl", &at)?,
+							max_ttl: optional_duration(hash, "max-ttl", &at)?,
+							metadata: parse_metadata(&entry, &at)?,
+						});
+					}
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
 				}
-				"NS" => {
-					for entry in entries {
-						let (value, ttl, _) = parse_value_ttl(&entry.expect_str(), ttl);
-						records.ns.push(NsRecord {
+			}
+		}
+		"ALIAS" => {
+			for entry in entries {
+				let (value, ttl, _) = parse_named_entry(&entry, "name", ttl, value_flags, &at)?;
+				records.alias.push(AliasRecord {
+					ttl,
+					name: to_ascii_name(&value, &at)?,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"MX" => {
+			for entry in entries {
+				match &entry {
+					Yaml::String(string) => {
+						let (value, ttl, _) = parse_entry(string, ttl, value_flags, &at)?;
+						records.mx.push(MxRecord {
 							ttl,
-							name: value.trim_matches('.').to_string(),
+							priority: 10,
+							host: to_ascii_name(value, &at)?,
+							metadata: None,
 						});
 					}
-				}
-				"CNAME" => {
-					for entry in entries {
-						let (value, ttl, _) = parse_value_ttl(&entry.expect_str(), ttl);
-						records.cname.push(CnameRecord {
+					Yaml::Hash(hash) => {
+						let ttl = optional_duration(hash, "ttl", &at)?.unwrap_or(ttl);
+						let priority = optional_number(hash, "priority", &at)?.unwrap_or(10);
+						let host = coerce_scalar(required(hash, "host", &at)?, &at)?;
+						records.mx.push(MxRecord {
 							ttl,
-							name: value.trim_matches('.').to_string(),
+							priority,
+							host: to_ascii_name(&host, &at)?,
+							metadata: parse_metadata(&entry, &at)?,
 						});
 					}
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
 				}
-				"ANAME" => {
-					for entry in entries {
-						let (value, ttl, _) = parse_value_ttl(&entry.expect_str(), ttl);
-						records.aname.push(AnameRecord {
+			}
+		}
+		"TXT" => {
+			for entry in entries {
+				match &entry {
+					Yaml::String(string) => {
+						let (data, ttl) = parse_txt_value(string, ttl, &at)?;
+						records.txt.push(TxtRecord {
 							ttl,
-							name: value.trim_matches('.').to_string(),
+							data,
+							metadata: None,
 						});
 					}
-				}
-				"MX" => {
-					for entry in entries {
-						match &entry {
-							Yaml::String(string) => {
-								let (value, ttl, _) = parse_value_ttl(&string, ttl);
-								records.mx.push(MxRecord {
-									ttl,
-									priority: 10,
-									host: value.trim_matches('.').to_string(),
-								});
-							}
-							Yaml::Hash(hash) => {
-								fn _ttl(hash: &yaml::Hash) -> Option<Duration> {
-									Some(Duration::from_yaml(hash.optional_index("ttl")?))
-								}
-								let ttl = _ttl(&hash).unwrap_or(ttl);
-								fn _priority(hash: &yaml::Hash) -> Option<i64> {
-									Some(hash.optional_index("priority")?.as_i64().expect("Expected priority to be of type integer."))
-								}
-								let priority = _priority(&hash).unwrap_or(10);
-								let host = hash.optional_index("host").expect("Expected host field.").as_str().expect("Expected host field to be a string.");
-								records.mx.push(MxRecord {
-									ttl,
-									priority: priority as u16,
-									host: host.trim_matches('.').to_string(),
-								});
-							}
-							_ => panic!("Expected String, Array, or Hash: {:?}", entry),
-						}
+					// taken as it is, without a TTL or quotes to take off
+					Yaml::Hash(hash) => {
+						records.txt.push(TxtRecord {
+							ttl: optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+							data: coerce_scalar(required(hash, "data", &at)?, &at)?,
+							metadata: parse_metadata(&entry, &at)?,
+						});
 					}
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
 				}
-				"TXT" => {
-					for entry in entries {
-						match &entry {
-							Yaml::String(string) => {
-								let (value, ttl, flags) = parse_value_ttl(&string, ttl);
-								let mut data = value.to_string();
-								// add the flags back to the data
-								// TODO: find a better way to do this, maybe quoting and validating flags?
-								for flag in flags {
-									data.push(' ');
-									data.push_str(flag);
-								}
-								records.txt.push(TxtRecord {
-									ttl,
-									data,
-								});
-							}
-							_ => panic!("Expected String or Array: {:?}", entry),
+			}
+		}
+		"SRV" => {
+			for entry in entries {
+				match &entry {
+					Yaml::String(string) => {
+						// compact form: priority weight port target
+						let (value, ttl, flags) = parse_value_ttl(string, ttl);
+						let mut fields = vec![value];
+						fields.extend(flags);
+						if fields.len() != 4 {
+							return Err(ConfigError::InvalidValue { at, value: string.clone(), expected: "\"priority weight port target\"" });
 						}
+						let number = |value: &str| value.parse().map_err(invalid_value(&at, value, "a number from 0 to 65535"));
+						records.srv.push(SrvRecord {
+							ttl,
+							priority: number(fields[0])?,
+							weight: number(fields[1])?,
+							port: number(fields[2])?,
+							target: to_ascii_name(fields[3], &at)?,
+							metadata: None,
+						});
+					}
+					Yaml::Hash(hash) => {
+						let target = coerce_scalar(required(hash, "target", &at)?, &at)?;
+						records.srv.push(SrvRecord {
+							ttl: optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+							priority: optional_number(hash, "priority", &at)?.unwrap_or(10),
+							weight: optional_number(hash, "weight", &at)?.unwrap_or(0),
+							port: optional_number(hash, "port", &at)?.ok_or(ConfigError::MissingField { at: at.clone(), field: "port" })?,
+							target: to_ascii_name(&target, &at)?,
+							metadata: parse_metadata(&entry, &at)?,
+						});
 					}
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
 				}
-				"RNS" => {
-					for entry in entries {
-						// note: ttl value is ignored
-						let (value, ttl, flags) = parse_value_ttl(&entry.expect_str(), ttl);
-						
-						// split off the port number from the host
-						let split: Vec<&str> = value.split(":").collect();
-						let (host, port): (&str, u16) = match split.len() {
-							1 => (split[0], 53),
-							2 => (split[0], split[1].parse().unwrap()),
-							_ => panic!("Unexpected socket addr number"),
+			}
+		}
+		"NAPTR" => {
+			for entry in entries {
+				// only as a mapping, as the regexp is full of characters the compact forms of
+				// other types would split or take a TTL from
+				match &entry {
+					Yaml::Hash(hash) => {
+						let string = |field: &'static str| -> Result<String, ConfigError> {
+							let value = match hash.optional_index(field) {
+								Some(value) => coerce_scalar(value, &at)?,
+								None => String::new(),
+							};
+							if value.len() > 255 {
+								return Err(ConfigError::InvalidValue { at: at.clone(), value, expected: "at most 255 bytes long" });
+							}
+							return Ok(value);
 						};
-						
-						// try to parse the host into an IP
-						let host = if let Ok(ip_addr) = host.parse() {
-							RnsHost::SocketAddr(SocketAddr::new(ip_addr, port))
-						} else {
-							// if that fails, assume it's a DNS name
-							RnsHost::HostPort(host.to_string(), port)
+						let replacement = match hash.optional_index("replacement") {
+							Some(replacement) => coerce_scalar(replacement, &at)?,
+							None => ".".to_string(),
 						};
-						
-						records.rns.push(RnsRecord {
-							ttl,
-							host,
-							external: flags.contains(&"external"),
+						records.naptr.push(NaptrRecord {
+							ttl: optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+							order: optional_number(hash, "order", &at)?.ok_or(ConfigError::MissingField { at: at.clone(), field: "order" })?,
+							preference: optional_number(hash, "preference", &at)?.ok_or(ConfigError::MissingField { at: at.clone(), field: "preference" })?,
+							flags: string("flags")?,
+							service: string("service")?,
+							regexp: string("regexp")?,
+							replacement: to_ascii_name(&replacement, &at)?,
+							metadata: parse_metadata(&entry, &at)?,
 						});
 					}
+					_ => return Err(wrong_type(&entry, &at, "a mapping")),
 				}
-				"TRPP" => {
-					for entry in entries {
-						// note: ttl value is ignored
-						let (value, ttl, _) = parse_value_ttl(&entry.expect_str(), ttl);
-						records.trpp.push(TrppRecord {
-							ttl,
-							server: value.to_string(),
+			}
+		}
+		"HINFO" => {
+			for entry in entries {
+				let (cpu, os, ttl) = match &entry {
+					Yaml::String(string) => {
+						// compact form: cpu os
+						let (value, ttl, flags) = parse_value_ttl(string, ttl);
+						match flags[..] {
+							[os] => (value.to_string(), os.to_string(), ttl),
+							_ => return Err(ConfigError::InvalidValue { at, value: string.clone(), expected: "\"cpu os\"" }),
+						}
+					}
+					Yaml::Hash(hash) => (
+						coerce_scalar(required(hash, "cpu", &at)?, &at)?,
+						coerce_scalar(required(hash, "os", &at)?, &at)?,
+						optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+					),
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
+				};
+				for value in &[&cpu, &os] {
+					if value.len() > 255 {
+						return Err(ConfigError::InvalidValue { at, value: value.to_string(), expected: "at most 255 bytes long" });
+					}
+				}
+				records.hinfo.push(HinfoRecord {
+					ttl,
+					cpu,
+					os,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"LOC" => {
+			for entry in entries {
+				// the whole string is the location, as its sizes would be taken for a TTL
+				let (position, ttl) = match &entry {
+					Yaml::String(string) => (string.clone(), ttl),
+					Yaml::Hash(hash) => (coerce_scalar(required(hash, "location", &at)?, &at)?, optional_duration(hash, "ttl", &at)?.unwrap_or(ttl)),
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
+				};
+				records.loc.push(LocRecord {
+					ttl,
+					position: position.parse().map_err(invalid_value(&at, &position, "a location like '52 22 23.000 N 4 53 32.000 E -2.00m'"))?,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"SSHFP" => {
+			for entry in entries {
+				let (algorithm, fingerprint_type, fingerprint, ttl) = match &entry {
+					Yaml::String(string) => {
+						// compact form: algorithm type fingerprint
+						let (value, ttl, flags) = parse_value_ttl(string, ttl);
+						let number = |value: &str| value.parse().map_err(invalid_value(&at, value, "a number from 0 to 255"));
+						match flags[..] {
+							[fingerprint_type, fingerprint] => (number(value)?, number(fingerprint_type)?, fingerprint.to_string(), ttl),
+							_ => return Err(ConfigError::InvalidValue { at, value: string.clone(), expected: "\"algorithm type fingerprint\"" }),
+						}
+					}
+					Yaml::Hash(hash) => (
+						optional_number(hash, "algorithm", &at)?.ok_or(ConfigError::MissingField { at: at.clone(), field: "algorithm" })?,
+						optional_number(hash, "type", &at)?.ok_or(ConfigError::MissingField { at: at.clone(), field: "type" })?,
+						coerce_scalar(required(hash, "fingerprint", &at)?, &at)?,
+						optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+					),
+					_ => return Err(wrong_type(&entry, &at, "a string, list, or mapping")),
+				};
+				records.sshfp.push(SshfpRecord {
+					ttl,
+					algorithm,
+					fingerprint_type,
+					fingerprint: parse_hex(&fingerprint).ok_or_else(|| ConfigError::InvalidValue { at: at.clone(), value: fingerprint.clone(), expected: "a fingerprint in hex" })?,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"SVCB" | "HTTPS" => {
+			for entry in entries {
+				// only as a mapping, as there are too many fields for a compact form
+				let mut record = match &entry {
+					Yaml::Hash(hash) => parse_svcb(hash, ttl, &at)?,
+					_ => return Err(wrong_type(&entry, &at, "a mapping")),
+				};
+				record.metadata = parse_metadata(&entry, &at)?;
+				if key_record_type == "SVCB" { records.svcb.push(record); } else { records.https.push(record); }
+			}
+		}
+		"PTR" => {
+			for entry in entries {
+				let (value, ttl, _) = parse_named_entry(&entry, "name", ttl, value_flags, &at)?;
+				records.ptr.push(PtrRecord {
+					ttl,
+					name: to_ascii_name(&value, &at)?,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"SOA" => {
+			for entry in entries {
+				match &entry {
+					Yaml::Hash(hash) => {
+						records.soa.push(SoaRecord {
+							ttl: optional_duration(hash, "ttl", &at)?.unwrap_or(ttl),
+							mname: coerce_scalar(required(hash, "mname", &at)?, &at)?.trim_matches('.').to_string(),
+							rname: coerce_scalar(required(hash, "rname", &at)?, &at)?.trim_matches('.').to_string(),
+							serial: optional_number(hash, "serial", &at)?,
+							refresh: optional_duration(hash, "refresh", &at)?.unwrap_or(DEFAULT_REFRESH),
+							retry: optional_duration(hash, "retry", &at)?.unwrap_or(DEFAULT_RETRY),
+							expire: optional_duration(hash, "expire", &at)?.unwrap_or(DEFAULT_EXPIRE),
+							minimum: optional_duration(hash, "minimum", &at)?,
+							metadata: parse_metadata(&entry, &at)?,
 						});
 					}
+					_ => return Err(wrong_type(&entry, &at, "a mapping")),
 				}
-				_ => panic!("Unknown record type: {:?}", key),
 			}
-		} else {
-			unimplemented!("Nested zones not implemented yet.");
 		}
+		"RNS" => {
+			for entry in entries {
+				// note: ttl value is ignored
+				let (value, ttl, flags) = parse_named_entry(&entry, "host", ttl, value_flags, &at)?;
+				let external = match &entry {
+					Yaml::Hash(hash) => hash.optional_index("external").map(|external| external.expect_bool(&at)).transpose()?.unwrap_or(false),
+					_ => flags.contains(&"external"),
+				};
+				
+				// split off the port number from the host
+				let split: Vec<&str> = value.split(":").collect();
+				let (host, port): (&str, u16) = match split.len() {
+					1 => (split[0], 53),
+					2 => (split[0], split[1].parse().map_err(invalid_value(&at, &value, "a host with a valid port"))?),
+					_ => return Err(ConfigError::InvalidValue { at, value: value.to_string(), expected: "a host with an optional port" }),
+				};
+				
+				// try to parse the host into an IP
+				let host = if let Ok(ip_addr) = host.parse() {
+					RnsHost::SocketAddr(SocketAddr::new(ip_addr, port))
+				} else {
+					// if that fails, assume it's a DNS name
+					RnsHost::HostPort(host.to_string(), port)
+				};
+				
+				records.rns.push(RnsRecord {
+					ttl,
+					host,
+					external,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		"TRPP" => {
+			for entry in entries {
+				// note: ttl value is ignored
+				let (value, ttl, _) = parse_named_entry(&entry, "server", ttl, value_flags, &at)?;
+				records.trpp.push(TrppRecord {
+					ttl,
+					server: value,
+					metadata: parse_metadata(&entry, &at)?,
+				});
+			}
+		}
+		_ => return Err(ConfigError::UnknownRecordType { zone: zone_name.to_string(), record: key_record_type.to_string() }),
 	}
+	if records.none_conflict().is_some() {
+		return Err(none_conflict_error(at));
+	}
+	return Ok(());
+}
+
+/// The error for a zone with both `none` and addresses of a type, as `none` would hide them.
+fn none_conflict_error(at: Location) -> ConfigError {
+	return ConfigError::InvalidValue { at, value: "none".to_string(), expected: "allowed along with addresses of the same type" };
+}
+
+/// Parses `{algorithm: ed25519, key-file: Kexample.com.+015+12345.private}`. The key file is in the
+/// format `dnssec-keygen -a ED25519` writes.
+fn parse_dnssec(value: &Yaml, ttl: Duration, at: &Location) -> Result<Dnssec, ConfigError> {
+	let hash = value.expect_hash(at)?;
+	if let Some(algorithm) = hash.optional_index("algorithm") {
+		let algorithm = algorithm.expect_str(at)?;
+		if !algorithm.eq_ignore_ascii_case("ed25519") {
+			return Err(ConfigError::InvalidValue { at: at.clone(), value: algorithm.to_string(), expected: "a supported algorithm, which is only ed25519" });
+		}
+	}
+	let path = required(hash, "key-file", at)?.expect_str(at)?;
+	let key_file_error = |reason: &str| ConfigError::KeyFile { at: at.clone(), path: path.to_string(), reason: reason.to_string() };
+	
+	let contents = read_to_string(path).map_err(|error| key_file_error(&format!("can't be read: {}", error)))?;
+	let field = |name: &str| contents.lines()
+		.find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+		.map(str::trim);
+	// e.g. `Algorithm: 15 (ED25519)`
+	if field("Algorithm").and_then(|algorithm| algorithm.split_whitespace().next()) != Some("15") {
+		return Err(key_file_error("isn't an Ed25519 (algorithm 15) key"));
+	}
+	let private_key = field("PrivateKey")
+		.and_then(|private_key| base64::engine::general_purpose::STANDARD.decode(private_key).ok())
+		.filter(|private_key| private_key.len() == 32)
+		.ok_or_else(|| key_file_error("doesn't have a valid PrivateKey"))?;
 	
-	return records;
+	return Ok(Dnssec {
+		ttl: optional_duration(hash, "ttl", at)?.unwrap_or(ttl),
+		private_key,
+	});
+}
+
+fn parse_networks(value: &Yaml, at: &Location) -> Result<HashMap<String, Vec<Cidr>>, ConfigError> {
+	let mut networks = HashMap::new();
+	for (name, cidrs) in value.expect_hash(at)? {
+		let name = name.expect_str(at)?;
+		networks.insert(name.to_string(), parse_cidrs(cidrs, &Location::Key(format!("networks: {}", name)))?);
+	}
+	return Ok(networks);
+}
+
+fn parse_tsig_keys(value: &Yaml, at: &Location) -> Result<Vec<TsigKey>, ConfigError> {
+	let mut keys = vec![];
+	for (name, key) in value.expect_hash(at)? {
+		let name = name.expect_str(at)?;
+		let at = Location::Key(format!("tsig-keys: {}", name));
+		let hash = key.expect_hash(&at)?;
+		if let Some(algorithm) = hash.optional_index("algorithm") {
+			let algorithm = algorithm.expect_str(&at)?;
+			if !algorithm.eq_ignore_ascii_case("hmac-sha256") {
+				return Err(ConfigError::InvalidValue { at, value: algorithm.to_string(), expected: "a supported algorithm, which is only hmac-sha256" });
+			}
+		}
+		// the secret itself is left out of the error, as it may end up in logs
+		let secret = base64::engine::general_purpose::STANDARD.decode(required(hash, "secret", &at)?.expect_str(&at)?)
+			.map_err(invalid_value(&at, "secret", "base64"))?;
+		keys.push(TsigKey {
+			name: name.trim_end_matches('.').to_lowercase(),
+			secret,
+		});
+	}
+	return Ok(keys);
+}
+
+fn wrong_type(yaml: &Yaml, at: &Location, expected: &'static str) -> ConfigError {
+	return ConfigError::WrongType { at: at.clone(), expected, found: describe(yaml) };
+}
+
+fn required<'a>(hash: &'a yaml::Hash, field: &'static str, at: &Location) -> Result<&'a Yaml, ConfigError> {
+	return hash.optional_index(field).ok_or_else(|| ConfigError::MissingField { at: at.clone(), field });
+}
+
+fn optional_duration(hash: &yaml::Hash, field: &str, at: &Location) -> Result<Option<Duration>, ConfigError> {
+	return hash.optional_index(field).map(|value| Duration::from_yaml(value, at)).transpose();
+}
+
+fn optional_number<T: TryFrom<i64>>(hash: &yaml::Hash, field: &str, at: &Location) -> Result<Option<T>, ConfigError> {
+	return hash.optional_index(field)
+		.map(|value| {
+			let number = value.expect_i64(at)?;
+			T::try_from(number).map_err(invalid_value(at, &number.to_string(), "a number in range"))
+		})
+		.transpose();
 }
 
 trait FromTime<T> {
-	fn from_yaml(yaml: &Yaml) -> T;
+	fn from_yaml(yaml: &Yaml, at: &Location) -> Result<T, ConfigError>;
 }
 
 impl FromTime<Duration> for Duration {
-	fn from_yaml(yaml: &Yaml) -> Duration {
+	fn from_yaml(yaml: &Yaml, at: &Location) -> Result<Duration, ConfigError> {
 		match yaml {
+			Yaml::Integer(int) if *int >= 0 => {
+				return Ok(Duration::from_secs(*int as u64));
+			}
 			Yaml::Integer(int) => {
-				return Duration::from_secs(*int as u64);
+				return Err(ConfigError::InvalidTtl { at: at.clone(), value: int.to_string() });
 			}
 			Yaml::String(string) => {
-				return Duration::parse(string).unwrap();
+				return Duration::parse(string).map_err(|_| ConfigError::InvalidTtl { at: at.clone(), value: string.clone() });
 			}
-			_ => panic!("Cannot create Duration from non Integer or String type: {:?}", yaml)
+			_ => return Err(wrong_type(yaml, at, "a TTL")),
 		}
 	}
 }
 
 #[cfg(test)]
 mod test {
+	use std::collections::{BTreeMap, HashMap};
+	use std::{env, fs, process};
 	use std::time::{Duration, SystemTime, UNIX_EPOCH};
 	
-	use crate::config::{AaaaRecord, ARecord, Config, DEFAULT_NTTL, DEFAULT_TTL, Label, parse, parse_allwildcard, parse_basic, parse_regex, parse_subwildcard, parse_value_ttl, parse_wildcard, parse_zone_matcher, parse_zone_matchers, Records, TxtRecord, Zone};
-	use crate::regex::Regex;
+	use crate::config::{AaaaRecord, AnameRecord, ARecord, Check, Config, ConfigError, DEFAULT_EXPIRE, DEFAULT_NTTL, DEFAULT_RETRY, DEFAULT_TTL, Dnssec, HinfoRecord, join_matchers, Label, LabelRegex, Metadata, MxRecord, NaptrRecord, NsRecord, parse, parse_allwildcard, parse_many, parse_basic, parse_regex, parse_subwildcard, parse_value_ttl, parse_wildcard, parse_zone_matcher, parse_zone_matchers, parse_zone_name, Location, PtrRecord, read, Records, SoaRecord, SrvRecord, SshfpRecord, SvcbRecord, SvcParams, TsigKey, TxtRecord, Zone, zone_name, ZoneIndex};
+	
+	/// A config as parsing it gives it, with its zones indexed.
+	fn compiled(mut config: Config) -> Config {
+		config.compile();
+		return config;
+	}
 	
 	#[test]
 	fn test_parse_basic() {
@@ -423,12 +2135,34 @@ mod test {
 	
 	#[test]
 	fn test_parse_regex() {
-		assert_eq!(parse_regex("/test/".as_ref()).unwrap().1, Label::Regex(false, Regex::new(r"test").unwrap()));
-		assert_eq!(parse_regex(r"/te\dst/".as_ref()).unwrap().1, Label::Regex(false, Regex::new(r"te\dst").unwrap()));
-		assert_eq!(parse_regex(r"/te\/st/".as_ref()).unwrap().1, Label::Regex(false, Regex::new(r"te/st").unwrap()));
+		assert_eq!(parse_regex("/test/".as_ref()).unwrap().1, Label::Regex(false, LabelRegex::new(r"test", false).unwrap()));
+		assert_eq!(parse_regex(r"/te\dst/".as_ref()).unwrap().1, Label::Regex(false, LabelRegex::new(r"te\dst", false).unwrap()));
+		assert_eq!(parse_regex(r"/te\/st/".as_ref()).unwrap().1, Label::Regex(false, LabelRegex::new(r"te/st", false).unwrap()));
+		
+		assert_eq!(parse_regex(r"/abc\.xyz/".as_ref()).unwrap().1, Label::Regex(true, LabelRegex::new(r"abc\.xyz", false).unwrap()));
+		assert_eq!(parse_regex(r"/abc.xyz/".as_ref()).unwrap().1, Label::Regex(false, LabelRegex::new(r"abc.xyz", false).unwrap()));
 		
-		assert_eq!(parse_regex(r"/abc\.xyz/".as_ref()).unwrap().1, Label::Regex(true, Regex::new(r"abc\.xyz").unwrap()));
-		assert_eq!(parse_regex(r"/abc.xyz/".as_ref()).unwrap().1, Label::Regex(false, Regex::new(r"abc.xyz").unwrap()));
+		assert_eq!(parse_regex("/test/~".as_ref()).unwrap().1, Label::Regex(false, LabelRegex::new(r"test", true).unwrap()));
+		assert_eq!(parse_regex("/test/~".as_ref()).unwrap().1.to_string(), "/test/~");
+		assert_eq!(parse_regex(r"/te\/st/".as_ref()).unwrap().1.to_string(), r"/te\/st/");
+	}
+	
+	#[test]
+	fn test_label_regex() {
+		let anchored = LabelRegex::new(r"web", false).unwrap();
+		assert!(anchored.is_match("web"));
+		assert!(anchored.is_match("WEB"));
+		assert!(!anchored.is_match("somewebsite"));
+		// alternatives are all anchored, not just the first and last
+		let alternatives = LabelRegex::new(r"www|api", false).unwrap();
+		assert!(alternatives.is_match("api"));
+		assert!(!alternatives.is_match("wwwx"));
+		assert!(!alternatives.is_match("xapi"));
+		
+		let substring = LabelRegex::new(r"web", true).unwrap();
+		assert!(substring.is_match("somewebsite"));
+		assert!(substring.is_match("SomeWebSite"));
+		assert!(!substring.is_match("site"));
 	}
 	
 	#[test]
@@ -457,13 +2191,44 @@ mod test {
 		assert_eq!(parse_zone_matcher("abc".as_ref()).unwrap().1, vec![Label::Basic("abc".to_string())]);
 		assert_eq!(parse_zone_matcher("abc.xyz".as_ref()).unwrap().1, vec![Label::Basic("abc".to_string()), Label::Basic("xyz".to_string())]);
 		assert_eq!(parse_zone_matcher("*.xyz".as_ref()).unwrap().1, vec![Label::Wildcard, Label::Basic("xyz".to_string())]);
-		assert_eq!(parse_zone_matcher("/abc/./xyz/".as_ref()).unwrap().1, vec![Label::Regex(false, Regex::new(r"abc").unwrap()), Label::Regex(false, Regex::new(r"xyz").unwrap())]);
+		assert_eq!(parse_zone_matcher("_sip._tcp.example".as_ref()).unwrap().1, vec![Label::Basic("_sip".to_string()), Label::Basic("_tcp".to_string()), Label::Basic("example".to_string())]);
+		assert_eq!(parse_zone_matcher("/abc/./xyz/".as_ref()).unwrap().1, vec![Label::Regex(false, LabelRegex::new(r"abc", false).unwrap()), Label::Regex(false, LabelRegex::new(r"xyz", false).unwrap())]);
 	}
 	
 	#[test]
 	fn test_parse_zone_matchers() {
-		assert_eq!(parse_zone_matchers("abc,123".as_ref()).unwrap().1, vec![vec![Label::Basic("abc".to_string())], vec![Label::Basic("123".to_string())]]);
-		assert_eq!(parse_zone_matchers("abc,/123/".as_ref()).unwrap().1, vec![vec![Label::Basic("abc".to_string())], vec![Label::Regex(false, Regex::new(r"123").unwrap())]]);
+		assert_eq!(parse_zone_matchers("abc,123".as_ref()).unwrap().1, (vec![vec![Label::Basic("abc".to_string())], vec![Label::Basic("123".to_string())]], vec![]));
+		assert_eq!(parse_zone_matchers("abc,/123/".as_ref()).unwrap().1, (vec![vec![Label::Basic("abc".to_string())], vec![Label::Regex(false, LabelRegex::new(r"123", false).unwrap())]], vec![]));
+		// exclusions come apart from the rest, wherever they are
+		assert_eq!(parse_zone_matchers("!abc,*,!*.abc".as_ref()).unwrap().1, (vec![vec![Label::Wildcard]], vec![vec![Label::Basic("abc".to_string())], vec![Label::Wildcard, Label::Basic("abc".to_string())]]));
+		assert_eq!(parse_zone_name("!abc"), Err(ConfigError::InvalidZoneName("!abc".to_string())));
+	}
+	
+	#[test]
+	fn test_join_matchers() {
+		assert_eq!(join_matchers("*.example.com, !internal.example.com 5m"), "*.example.com,!internal.example.com 5m");
+		// a comma in a regular expression is its own
+		assert_eq!(join_matchers("/a{1, 3}/.example.com,  example.org"), "/a{1, 3}/.example.com,example.org");
+		assert_eq!(join_matchers(r"/a\/, b/, c"), r"/a\/, b/,c");
+	}
+	
+	#[test]
+	fn test_zone_excludes() {
+		let config = parse(r"zones:
+  '*.example.com, !internal.example.com 5m':
+    A: 10.0.0.1
+  '*.example.com':
+    A: 10.0.0.2
+  '*.example.org,!example.net':
+    A: 10.0.0.3").unwrap();
+		// excluding something makes a zone of its own, rather than one merged with the wildcard
+		assert_eq!(config.zones.len(), 3);
+		assert_eq!(config.zones[0].name(), "*.example.com,!internal.example.com");
+		assert_eq!(config.zones[0].excludes, vec![vec![Label::Basic("internal".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]]);
+		assert_eq!(config.zones[0].records.a[0].ttl, Duration::from_secs(5 * 60));
+		assert_eq!(config.zones[1].excludes, Vec::<Vec<Label>>::new());
+		// an exclusion that matches nothing else is only warned about
+		assert_eq!(config.zones[2].name(), "*.example.org,!example.net");
 	}
 	
 	#[test]
@@ -471,89 +2236,1404 @@ mod test {
 		assert_eq!(parse_value_ttl("test", Duration::from_secs(30)), ("test", Duration::from_secs(30), vec![]));
 		assert_eq!(parse_value_ttl("test 1", Duration::from_secs(30)), ("test", Duration::from_secs(1), vec![]));
 		assert_eq!(parse_value_ttl("test 1m", Duration::from_secs(30)), ("test", Duration::from_secs(60), vec![]));
+		assert_eq!(parse_value_ttl("test 1h30m", Duration::from_secs(30)), ("test", Duration::from_secs(90 * 60), vec![]));
+		assert_eq!(parse_value_ttl("test rotate 1d12h", Duration::from_secs(30)), ("test", Duration::from_secs(36 * 60 * 60), vec!["rotate"]));
+		// units out of order aren't a TTL, so they're left as a flag
+		assert_eq!(parse_value_ttl("test 30m1h", Duration::from_secs(30)), ("test", Duration::from_secs(30), vec!["30m1h"]));
+		assert_eq!(parse_value_ttl("12345", Duration::from_secs(30)), ("12345", Duration::from_secs(30), vec![]));
+	}
+	
+	#[test]
+	fn test_compound_ttls() {
+		let config = parse(r"ttl: 1h30m
+check-timeout: 1s500ms
+zones:
+  example.com 1d12h:
+    A: 10.0.0.1
+  www.example.com:
+    A 2m30s: [10.0.0.2, 10.0.0.3 1m30s]
+    TXT: hello 1w2d").unwrap();
+		assert_eq!(config.ttl, Duration::from_secs(90 * 60));
+		assert_eq!(config.check_timeout, Duration::from_millis(1500));
+		assert_eq!(config.zones[0].records.a[0].ttl, Duration::from_secs(36 * 60 * 60));
+		assert_eq!(config.zones[1].records.a.iter().map(|a| a.ttl).collect::<Vec<Duration>>(), vec![Duration::from_secs(150), Duration::from_secs(90)]);
+		assert_eq!(config.zones[1].records.txt[0].ttl, Duration::from_secs(9 * 24 * 60 * 60));
+		
+		assert_eq!(parse(r"zones:
+  example.com:
+    A: 10.0.0.1 30m1h"), Err(ConfigError::InvalidTtl {
+			at: Location::Record("example.com".to_string(), "A".to_string()),
+			value: "30m1h".to_string(),
+		}));
+	}
+	
+	#[test]
+	fn test_unquoted_scalars() {
+		let config = parse(r"zones:
+  example.com:
+    TXT: 12345
+  www.example.com:
+    TXT 300: [1.50, 42]").unwrap();
+		assert_eq!(config.zones[0].records.txt[0].data, "12345");
+		assert_eq!(config.zones[1].records.txt.iter().map(|txt| (txt.data.as_str(), txt.ttl)).collect::<Vec<(&str, Duration)>>(),
+			vec![("1.50", Duration::from_secs(300)), ("42", Duration::from_secs(300))]);
+		
+		// and in the fields of the mapping forms
+		let config = parse(r"zones:
+  example.com:
+    TXT: {data: 12345}
+    HINFO: {cpu: 386, os: 6.22}
+    SSHFP: {algorithm: 4, type: 2, fingerprint: 123456}
+  www.example.com:
+    CNAME: {name: 1234}").unwrap();
+		assert_eq!(config.zones[0].records.txt[0].data, "12345");
+		assert_eq!((config.zones[0].records.hinfo[0].cpu.as_str(), config.zones[0].records.hinfo[0].os.as_str()), ("386", "6.22"));
+		assert_eq!(config.zones[0].records.sshfp[0].fingerprint, vec![0x12, 0x34, 0x56]);
+		assert_eq!(config.zones[1].records.cname[0].name, "1234");
+		assert_eq!(parse(r"zones:
+  example.com:
+    TXT: {data: false}").unwrap_err().to_string(), "zone 'example.com', record 'TXT': expected a string, quoted if it's true or false, found false");
+		
+		assert_eq!(parse(r"zones:
+  example.com:
+    A: 10").unwrap_err().to_string(), "zone 'example.com', record 'A': '10' is not a valid IPv4 address");
+		assert_eq!(parse(r"zones:
+  example.com:
+    TXT: true"), Err(ConfigError::WrongType {
+			at: Location::Record("example.com".to_string(), "TXT".to_string()),
+			expected: "a string, quoted if it's true or false",
+			found: "true".to_string(),
+		}));
+	}
+	
+	#[test]
+	fn test_is_delegation() {
+		let config = parse(r"networks:
+  internal: [10.0.0.0/8]
+zones:
+  sub.example.com:
+    NS: ns1.example.net
+  viewed.example.com @internal:
+    NS: ns1.example.net
+    notify: [192.0.2.1]
+  hosts.example.com:
+    NS: ns1.example.net
+    A: 10.0.0.1").unwrap();
+		let delegations: Vec<bool> = config.zones.iter().map(|zone| zone.records.is_delegation()).collect();
+		assert_eq!(delegations, vec![true, true, false]);
 	}
 	
 	#[test]
 	fn test_a() {
 		assert_eq!(parse(r"zones:
   example.com:
-    A: 127.0.0.1"), Config {
+    A: 127.0.0.1").unwrap(), compiled(Config {
 			ttl: DEFAULT_TTL,
 			nttl: DEFAULT_NTTL,
 			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
 					a: vec![ARecord {
 						ttl: DEFAULT_TTL,
 						ip4addr: "127.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
 					}],
 					aaaa: vec![],
 					ns: vec![],
 					cname: vec![],
 					aname: vec![],
+					alias: vec![],
 					mx: vec![],
 					txt: vec![],
 					rns: vec![],
 					trpp: vec![],
+					forward: None,
+					..Default::default()
 				},
 			}],
-		});
+		}));
 	}
 	
 	#[test]
 	fn test_aaaa() {
 		assert_eq!(parse(r"zones:
   example.com:
-    AAAA: ::1"), Config {
+    AAAA: ::1").unwrap(), compiled(Config {
 			ttl: DEFAULT_TTL,
 			nttl: DEFAULT_NTTL,
 			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
 					a: vec![],
 					aaaa: vec![AaaaRecord {
 						ttl: DEFAULT_TTL,
 						ip6addr: "::1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
 					}],
 					ns: vec![],
 					cname: vec![],
 					aname: vec![],
+					alias: vec![],
 					mx: vec![],
 					txt: vec![],
 					rns: vec![],
 					trpp: vec![],
+					forward: None,
+					..Default::default()
 				},
 			}],
-		});
+		}));
 	}
 	
 	#[test]
 	fn test_txt() {
 		assert_eq!(parse(r"zones:
   example.com:
-    TXT: hello world"), Config {
+    TXT: hello world").unwrap(), compiled(Config {
 			ttl: DEFAULT_TTL,
 			nttl: DEFAULT_NTTL,
 			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
 					a: vec![],
 					aaaa: vec![],
 					ns: vec![],
 					cname: vec![],
 					aname: vec![],
+					alias: vec![],
 					mx: vec![],
 					txt: vec![TxtRecord {
 						ttl: DEFAULT_TTL,
 						data: "hello world".to_string(),
+						metadata: None,
 					}],
 					rns: vec![],
 					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_soa() {
+		assert_eq!(parse(r"zones:
+  example.com:
+    SOA:
+      mname: ns1.example.com.
+      rname: hostmaster.example.com.
+      serial: 2020010101
+      refresh: 1d
+      minimum: 5m").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					soa: vec![SoaRecord {
+						ttl: DEFAULT_TTL,
+						mname: "ns1.example.com".to_string(),
+						rname: "hostmaster.example.com".to_string(),
+						serial: Some(2020010101),
+						refresh: Duration::from_secs(60 * 60 * 24),
+						retry: DEFAULT_RETRY,
+						expire: DEFAULT_EXPIRE,
+						minimum: Some(Duration::from_secs(5 * 60)),
+						metadata: None,
+					}],
+					..Default::default()
 				},
 			}],
+		}));
+	}
+	
+	#[test]
+	fn test_srv() {
+		assert_eq!(parse(r"zones:
+  _sip._tcp.example.com:
+    SRV:
+      - 10 20 5060 sip.example.com. 5m
+      - priority: 5
+        port: 25565
+        target: mc.example.com").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("_sip".to_string()), Label::Basic("_tcp".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					srv: vec![SrvRecord {
+						ttl: Duration::from_secs(5 * 60),
+						priority: 10,
+						weight: 20,
+						port: 5060,
+						target: "sip.example.com.".to_string(),
+						metadata: None,
+					}, SrvRecord {
+						ttl: DEFAULT_TTL,
+						priority: 5,
+						weight: 0,
+						port: 25565,
+						target: "mc.example.com".to_string(),
+						metadata: None,
+					}],
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_naptr() {
+		let config = parse(r"zones:
+  4.3.2.1.5.5.5.0.0.8.1.e164.arpa:
+    NAPTR:
+      - order: 100
+        preference: 10
+        flags: u
+        service: E2U+sip
+        regexp: '!^\+1800555(.*)$!sip:\1@example.com!'
+      - order: 102
+        preference: 10
+        flags: s
+        service: SIP+D2U
+        replacement: _sip._udp.example.com.
+        ttl: 5m").unwrap();
+		assert_eq!(config.zones[0].records.naptr, vec![NaptrRecord {
+			ttl: DEFAULT_TTL,
+			order: 100,
+			preference: 10,
+			flags: "u".to_string(),
+			service: "E2U+sip".to_string(),
+			regexp: r"!^\+1800555(.*)$!sip:\1@example.com!".to_string(),
+			replacement: ".".to_string(),
+			metadata: None,
+		}, NaptrRecord {
+			ttl: Duration::from_secs(5 * 60),
+			order: 102,
+			preference: 10,
+			flags: "s".to_string(),
+			service: "SIP+D2U".to_string(),
+			regexp: "".to_string(),
+			replacement: "_sip._udp.example.com.".to_string(),
+			metadata: None,
+		}]);
+		
+		assert_eq!(parse(r"zones:
+  example.com:
+    NAPTR: 100 10 u E2U+sip !^.*$!sip:info@example.com! ."), Err(ConfigError::WrongType {
+			at: Location::Record("example.com".to_string(), "NAPTR".to_string()),
+			expected: "a mapping",
+			found: "'100 10 u E2U+sip !^.*$!sip:info@example.com! .'".to_string(),
+		}));
+	}
+	
+	#[test]
+	fn test_hinfo_loc_sshfp() {
+		let config = parse(r"zones:
+  host.example.com:
+    HINFO:
+      - INTEL-386 LINUX 5m
+      - {cpu: ARM Cortex-A72, os: Debian 12}
+    LOC:
+      - 52 22 23.000 N 4 53 32.000 E -2.00m
+      - {location: 33 52 S 151 12 45.5 E 58m 20m, ttl: 1h}
+    SSHFP:
+      - 4 2 123456789abcdef67890123456789abcdef67890123456789abcdef123456789
+      - {algorithm: 1, type: 1, fingerprint: DC0FFEE0, ttl: 5m}").unwrap();
+		let records = &config.zones[0].records;
+		assert_eq!(records.hinfo, vec![HinfoRecord {
+			ttl: Duration::from_secs(5 * 60),
+			cpu: "INTEL-386".to_string(),
+			os: "LINUX".to_string(),
+			metadata: None,
+		}, HinfoRecord {
+			ttl: DEFAULT_TTL,
+			cpu: "ARM Cortex-A72".to_string(),
+			os: "Debian 12".to_string(),
+			metadata: None,
+		}]);
+		assert_eq!(records.loc.iter().map(|loc| (loc.ttl, loc.position.to_string())).collect::<Vec<(Duration, String)>>(), vec![
+			(DEFAULT_TTL, "52 22 23.000 N 4 53 32.000 E -2.00m 1.00m 10000.00m 10.00m".to_string()),
+			(Duration::from_secs(60 * 60), "33 52 0.000 S 151 12 45.500 E 58.00m 20.00m 10000.00m 10.00m".to_string()),
+		]);
+		assert_eq!(records.sshfp[0].algorithm, 4);
+		assert_eq!(records.sshfp[0].fingerprint_type, 2);
+		assert_eq!(records.sshfp[0].fingerprint.len(), 32);
+		assert_eq!(records.sshfp[0].fingerprint[..2], [0x12, 0x34]);
+		assert_eq!(records.sshfp[1], SshfpRecord {
+			ttl: Duration::from_secs(5 * 60),
+			algorithm: 1,
+			fingerprint_type: 1,
+			fingerprint: vec![0xdc, 0x0f, 0xfe, 0xe0],
+			metadata: None,
+		});
+		
+		let error = |records: &str| parse(&format!("zones:\n  example.com:\n    {}", records)).unwrap_err().to_string();
+		assert_eq!(error("HINFO: INTEL-386"), "zone 'example.com', record 'HINFO': 'INTEL-386' is not \"cpu os\"");
+		assert_eq!(error("LOC: 52 22 23.000 N"), "zone 'example.com', record 'LOC': '52 22 23.000 N' is not a location like '52 22 23.000 N 4 53 32.000 E -2.00m'");
+		assert_eq!(error("SSHFP: 1 1 dc0ffee"), "zone 'example.com', record 'SSHFP': 'dc0ffee' is not a fingerprint in hex");
+		assert_eq!(error("SSHFP: 256 1 dc0ffee0"), "zone 'example.com', record 'SSHFP': '256' is not a number from 0 to 255");
+	}
+	
+	#[test]
+	fn test_svcb_https() {
+		let config = parse(r"zones:
+  example.com:
+    HTTPS:
+      - {priority: 0, target: cdn.example.net.}
+      - {priority: 1, alpn: [h2, h3], port: 8443, ipv4hint: 192.0.2.1, ipv6hint: ['2001:db8::1'], mandatory: [alpn], ttl: 5m}
+    SVCB 1h:
+      - {priority: 2, target: svc, alpn: h2, no-default-alpn: true}").unwrap();
+		let records = &config.zones[0].records;
+		assert_eq!(records.https, vec![SvcbRecord {
+			ttl: DEFAULT_TTL,
+			priority: 0,
+			target: "cdn.example.net.".to_string(),
+			params: SvcParams::default(),
+			metadata: None,
+		}, SvcbRecord {
+			ttl: Duration::from_secs(5 * 60),
+			priority: 1,
+			target: ".".to_string(),
+			params: SvcParams {
+				mandatory: vec!["alpn".to_string()],
+				alpn: vec!["h2".to_string(), "h3".to_string()],
+				no_default_alpn: false,
+				port: Some(8443),
+				ipv4hint: vec!["192.0.2.1".parse().unwrap()],
+				ipv6hint: vec!["2001:db8::1".parse().unwrap()],
+			},
+			metadata: None,
+		}]);
+		assert_eq!(records.svcb, vec![SvcbRecord {
+			ttl: Duration::from_secs(60 * 60),
+			priority: 2,
+			target: "svc".to_string(),
+			params: SvcParams {
+				alpn: vec!["h2".to_string()],
+				no_default_alpn: true,
+				..SvcParams::default()
+			},
+			metadata: None,
+		}]);
+		
+		let error = |records: &str| parse(&format!("zones:\n  example.com:\n    {}", records)).unwrap_err().to_string();
+		assert_eq!(error("HTTPS: {target: .}"), "zone 'example.com', record 'HTTPS': missing 'priority' field");
+		assert_eq!(error("HTTPS: 1 . alpn=h2"), "zone 'example.com', record 'HTTPS': expected a mapping, found '1 . alpn=h2'");
+		assert_eq!(error("HTTPS: {priority: 1, ech: abc}"), "zone 'example.com', record 'HTTPS': 'ech' is not one of ttl, priority, target, mandatory, alpn, no-default-alpn, port, ipv4hint, ipv6hint, comment, or labels");
+		assert_eq!(error("HTTPS: {priority: 0, target: cdn.example.net., alpn: h2}"), "zone 'example.com', record 'HTTPS': 'alpn' is not allowed in alias mode, with a priority of 0");
+		assert_eq!(error("SVCB: {priority: 1, no-default-alpn: true}"), "zone 'example.com', record 'SVCB': 'no-default-alpn' is not allowed without alpn");
+		assert_eq!(error("SVCB: {priority: 1, alpn: ''}"), "zone 'example.com', record 'SVCB': '' is not an ALPN protocol ID from 1 to 255 bytes long");
+		assert_eq!(error("SVCB: {priority: 1, alpn: h2, mandatory: port}"), "zone 'example.com', record 'SVCB': 'port' is not another param given in the record");
+		assert_eq!(error("SVCB: {priority: 1, ipv4hint: '2001:db8::1'}"), "zone 'example.com', record 'SVCB': '2001:db8::1' is not a valid IPv4 address");
+	}
+	
+	#[test]
+	fn test_ptr() {
+		assert_eq!(parse(r"zones:
+  1.1.168.192.in-addr.arpa:
+    PTR: router.example.com.
+  '*.*.10.in-addr.arpa':
+    PTR: [host.example.com, other.example.com 5m]").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("1".to_string()), Label::Basic("1".to_string()), Label::Basic("168".to_string()), Label::Basic("192".to_string()), Label::Basic("in-addr".to_string()), Label::Basic("arpa".to_string())]],
+				excludes: vec![],
+				records: Records {
+					ptr: vec![PtrRecord {
+						ttl: DEFAULT_TTL,
+						name: "router.example.com.".to_string(),
+						metadata: None,
+					}],
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Wildcard, Label::Wildcard, Label::Basic("10".to_string()), Label::Basic("in-addr".to_string()), Label::Basic("arpa".to_string())]],
+				excludes: vec![],
+				records: Records {
+					ptr: vec![PtrRecord {
+						ttl: DEFAULT_TTL,
+						name: "host.example.com".to_string(),
+						metadata: None,
+					}, PtrRecord {
+						ttl: Duration::from_secs(5 * 60),
+						name: "other.example.com".to_string(),
+						metadata: None,
+					}],
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_zone_ttl() {
+		assert_eq!(parse(r"ttl: 1h
+zones:
+  global.example.com:
+    A: 10.0.0.1
+  zone.example.com:
+    ttl: 5m
+    A: 10.0.0.2
+  suffix.example.com 10m:
+    ttl: 5m
+    A: 10.0.0.3
+  record.example.com:
+    ttl: 5m
+    A 2m: [10.0.0.4, 10.0.0.5 30s]").unwrap(), compiled(Config {
+			ttl: Duration::from_secs(60 * 60),
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("global".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(60 * 60),
+						ip4addr: "10.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("zone".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(5 * 60),
+						ip4addr: "10.0.0.2".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					ttl: Some(Duration::from_secs(5 * 60)),
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("suffix".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(5 * 60),
+						ip4addr: "10.0.0.3".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					ttl: Some(Duration::from_secs(5 * 60)),
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("record".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(2 * 60),
+						ip4addr: "10.0.0.4".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}, ARecord {
+						ttl: Duration::from_secs(30),
+						ip4addr: "10.0.0.5".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					ttl: Some(Duration::from_secs(5 * 60)),
+					type_ttls: vec![("A".to_string(), Duration::from_secs(2 * 60))],
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_rotate() {
+		assert_eq!(parse(r"zones:
+  example.com:
+    A rotate 5m: [10.0.0.1, 10.0.0.2]
+    AAAA: ::1").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(5 * 60),
+						ip4addr: "10.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}, ARecord {
+						ttl: Duration::from_secs(5 * 60),
+						ip4addr: "10.0.0.2".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![AaaaRecord {
+						ttl: DEFAULT_TTL,
+						ip6addr: "::1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					rotate_a: true,
+					type_ttls: vec![("A".to_string(), Duration::from_secs(5 * 60))],
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_inherit() {
+		assert_eq!(parse(r"zones:
+  example.com inherit 5m:
+    A: 10.0.0.1").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(5 * 60),
+						ip4addr: "10.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					inherit: true,
+					ttl: Some(Duration::from_secs(5 * 60)),
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_allow_transfer() {
+		assert_eq!(parse(r"allow-transfer: 10.0.0.2
+zones:
+  example.com:
+    allow-transfer: [10.0.0.3, '::1']
+    A: 10.0.0.1").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec!["10.0.0.2".parse().unwrap()],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: DEFAULT_TTL,
+						ip4addr: "10.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					allow_transfer: vec!["10.0.0.3".parse().unwrap(), "::1".parse().unwrap()],
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_acl() {
+		assert_eq!(parse(r"acl: [192.0.2.0/24, '2001:db8::/32']
+acl-local: false
+zones:
+  example.com:
+    allow: 198.51.100.0/24
+    deny: [198.51.100.66]
+    A: 10.0.0.1").unwrap(), compiled(Config {
+			ttl: DEFAULT_TTL,
+			nttl: DEFAULT_NTTL,
+			serial: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec!["192.0.2.0/24".parse().unwrap(), "2001:db8::/32".parse().unwrap()],
+			acl_local: false,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: DEFAULT_TTL,
+						ip4addr: "10.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					allow: vec!["198.51.100.0/24".parse().unwrap()],
+					deny: vec!["198.51.100.66".parse().unwrap()],
+					..Default::default()
+				},
+			}],
+		}));
+	}
+	
+	#[test]
+	fn test_names() {
+		// names are kept as written, so relative ones can be resolved at answer time
+		assert_eq!(parse(r"zones:
+  example.com:
+    NS: ns1.example.net.
+    MX: mail").unwrap().zones[0].records, Records {
+			ns: vec![NsRecord {
+				ttl: DEFAULT_TTL,
+				name: "ns1.example.net.".to_string(),
+				metadata: None,
+			}],
+			mx: vec![MxRecord {
+				ttl: DEFAULT_TTL,
+				priority: 10,
+				host: "mail".to_string(),
+				metadata: None,
+			}],
+			..Default::default()
+		});
+	}
+	
+	#[test]
+	fn test_aname_ttl_range() {
+		let config = parse(r"min-ttl: 1m
+max-ttl: 1d
+zones:
+  example.com:
+    ANAME:
+      - example.net. 5m
+      - name: example.org.
+        min-ttl: 30
+        max-ttl: 1h").unwrap();
+		assert_eq!(config.min_ttl, Some(Duration::from_secs(60)));
+		assert_eq!(config.max_ttl, Some(Duration::from_secs(86400)));
+		assert_eq!(config.zones[0].records.aname, vec![AnameRecord {
+			ttl: Duration::from_secs(5 * 60),
+			name: "example.net.".to_string(),
+			min_ttl: None,
+			max_ttl: None,
+			metadata: None,
+		}, AnameRecord {
+			ttl: DEFAULT_TTL,
+			name: "example.org.".to_string(),
+			min_ttl: Some(Duration::from_secs(30)),
+			max_ttl: Some(Duration::from_secs(60 * 60)),
+			metadata: None,
+		}]);
+	}
+	
+	#[test]
+	fn test_invalid_zone_name() {
+		// rather than silently matching the zone against just the first label
+		let name = format!("{}.example.com", "a".repeat(64));
+		assert_eq!(parse(&format!("zones:\n  {}:\n    A: 10.0.0.1", name)), Err(ConfigError::InvalidZoneName(name)));
+	}
+	
+	#[test]
+	fn test_unknown_flag() {
+		assert_eq!(parse(r"zones:
+  example.com:
+    RNS: ns1.example.net externel"), Err(ConfigError::UnknownFlag {
+			at: Location::Record("example.com".to_string(), "RNS".to_string()),
+			flag: "externel".to_string(),
+			allowed: vec!["external"],
+		}));
+	}
+	
+	#[test]
+	fn test_flag_on_wrong_type() {
+		assert_eq!(parse(r"zones:
+  example.com:
+    MX rotate: mail.example.com."), Err(ConfigError::UnknownFlag {
+			at: Location::Record("example.com".to_string(), "MX".to_string()),
+			flag: "rotate".to_string(),
+			allowed: vec![],
+		}));
+	}
+	
+	#[test]
+	fn test_invalid_ip() {
+		let error = parse(r"zones:
+  example.com:
+    A: 127.0.0.256").unwrap_err();
+		assert_eq!(error, ConfigError::InvalidValue {
+			at: Location::Record("example.com".to_string(), "A".to_string()),
+			value: "127.0.0.256".to_string(),
+			expected: "a valid IPv4 address",
 		});
+		assert_eq!(error.to_string(), "zone 'example.com', record 'A': '127.0.0.256' is not a valid IPv4 address");
+	}
+	
+	#[test]
+	fn test_invalid_ttl() {
+		assert_eq!(parse(r"ttl: 5x
+zones:
+  example.com:
+    A: 10.0.0.1"), Err(ConfigError::InvalidTtl { at: Location::Key("ttl".to_string()), value: "5x".to_string() }));
+		assert_eq!(parse(r"zones:
+  example.com:
+    A: 10.0.0.1 5x"), Err(ConfigError::InvalidTtl {
+			at: Location::Record("example.com".to_string(), "A".to_string()),
+			value: "5x".to_string(),
+		}));
+		// negative integers aren't TTLs, rather than wrapping around to huge ones
+		assert_eq!(parse(r"ttl: -1
+zones: {}"), Err(ConfigError::InvalidTtl { at: Location::Key("ttl".to_string()), value: "-1".to_string() }));
+		assert_eq!(parse(r"zones:
+  example.com:
+    A:
+      - ip: 10.0.0.1
+        ttl: -300"), Err(ConfigError::InvalidTtl {
+			at: Location::Record("example.com".to_string(), "A".to_string()),
+			value: "-300".to_string(),
+		}));
+	}
+	
+	#[test]
+	fn test_long_ttls() {
+		let zones = r"
+zones:
+  example.com:
+    A: 10.0.0.1 100000w
+    TXT: '99999999999999999999999w'";
+		// kept as they are, to be cut down as they're answered
+		let config = parse(zones).unwrap();
+		assert_eq!(config.zones[0].records.a[0].ttl, Duration::from_secs(100000 * 7 * 24 * 60 * 60));
+		// while strict refuses them
+		let error = parse(&format!("strict: true{}", zones)).unwrap_err();
+		assert_eq!(error, ConfigError::TtlTooLong {
+			at: Location::Record("example.com".to_string(), "A".to_string()),
+			ttl: 100000 * 7 * 24 * 60 * 60,
+		});
+		assert_eq!(error.to_string(), "zone 'example.com', record 'A': a TTL of 60480000000 seconds is longer than the maximum of 2147483647");
+		assert_eq!(parse("strict: true\nttl: 2147483648\nzones: {}").unwrap_err().to_string(), "'ttl': a TTL of 2147483648 seconds is longer than the maximum of 2147483647");
+		assert!(parse("strict: true\nttl: 2147483647\nzones: {}").is_ok());
+		// as are the timers of an SOA
+		assert_eq!(parse("strict: true\nzones:\n  example.com:\n    SOA: {mname: ns1.example.com., rname: hostmaster.example.com., refresh: 100000w}").unwrap_err().to_string(), "zone 'example.com', record 'SOA': a TTL of 60480000000 seconds is longer than the maximum of 2147483647");
+		assert_eq!(parse("strict: true\ndefault-soa: {mname: ns1.example.com., rname: hostmaster.example.com., expire: 100000w}\nzones: {}").unwrap_err().to_string(), "'default-soa': a TTL of 60480000000 seconds is longer than the maximum of 2147483647");
+	}
+	
+	#[test]
+	fn test_unknown_record_type() {
+		assert_eq!(parse(r"zones:
+  example.com:
+    AA: 10.0.0.1"), Err(ConfigError::UnknownRecordType { zone: "example.com".to_string(), record: "AA".to_string() }));
+	}
+	
+	#[test]
+	fn test_unknown_keys() {
+		assert_eq!(parse("zonez:\n  example.com: {A: 10.0.0.1}").unwrap_err().to_string(), "the document: unknown key 'zonez'");
+		assert_eq!(parse("ttl: 5m\nmax_ttl: 1d\nzones: {}").unwrap_err().to_string(), "the document: unknown key 'max_ttl'");
+		assert!(parse("x-ns: &ns {NS: ns1.example.com}\nzones: {example.com: *ns}").is_ok());
+		assert_eq!(parse(r"zones:
+  example.com:
+    tttl: 5m
+    A: 10.0.0.1"), Err(ConfigError::UnknownKey { at: Location::Zone("example.com".to_string()), key: "tttl".to_string() }));
+		// a flag doesn't make a misspelling any less of one
+		assert_eq!(parse("zones: {example.com: {Forward only: 10.0.0.2}}").unwrap_err().to_string(), "zone 'example.com': unknown key 'Forward'");
+		assert_eq!(parse("zones: {example.com: {www.example.com: {A: 10.0.0.1}}}").unwrap_err().to_string(), "zone 'example.com': nested zones like 'www.example.com' aren't supported yet");
+	}
+	
+	#[test]
+	fn test_version() {
+		assert!(parse("version: 1\nzones: {}").is_ok());
+		assert_eq!(parse("version: 2\nzones: {}").unwrap_err().to_string(), "'version': '2' is not a supported config version (1)");
+		assert_eq!(parse("version: one\nzones: {}").unwrap_err().to_string(), "'version': expected an integer, found 'one'");
+	}
+	
+	#[test]
+	fn test_zone_not_a_mapping() {
+		assert_eq!(parse(r"zones:
+  example.com: 10.0.0.1"), Err(ConfigError::NotAMapping(Location::Zone("example.com".to_string()))));
+	}
+	
+	#[test]
+	fn test_invalid_yaml() {
+		let error = parse("zones:
+  example.com:
+    A: [10.0.0.1
+").unwrap_err();
+		assert!(matches!(error, ConfigError::Yaml(_)));
+		assert!(error.to_string().contains("line 4"), "{}", error);
+	}
+	
+	#[test]
+	fn test_dnssec() {
+		let path = std::env::temp_dir().join(format!("tacodns-test-{}.private", std::process::id()));
+		std::fs::write(&path, "Private-key-format: v1.3
+Algorithm: 15 (ED25519)
+PrivateKey: AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=
+").unwrap();
+		let config = parse(&format!(r"dnssec:
+  key-file: {path}
+zones:
+  example.com:
+    A: 10.0.0.1
+  example.net:
+    dnssec:
+      algorithm: ED25519
+      key-file: {path}
+      ttl: 1h", path = path.display())).unwrap();
+		let dnssec = Dnssec {
+			ttl: DEFAULT_TTL,
+			private_key: (0..32).collect(),
+		};
+		assert_eq!(config.dnssec, Some(dnssec.clone()));
+		assert_eq!(config.zones[0].records.dnssec, None);
+		assert_eq!(config.zones[1].records.dnssec, Some(Dnssec { ttl: Duration::from_secs(60 * 60), ..dnssec }));
+		
+		let error = |dnssec: &str| parse(&format!(r"zones:
+  '*.example.com':
+    dnssec: {}", dnssec)).unwrap_err().to_string();
+		assert_eq!(error("{algorithm: rsasha256, key-file: x}"), "zone '*.example.com', 'dnssec': 'rsasha256' is not a supported algorithm, which is only ed25519");
+		assert!(error("{key-file: /nonexistent}").starts_with("zone '*.example.com', 'dnssec': key file '/nonexistent' can't be read: "));
+		assert_eq!(error(&format!("{{key-file: {}}}", path.display())), "zone '*.example.com', 'dnssec': '*.example.com' is not a plain zone name to sign as");
+		
+		std::fs::write(&path, "Algorithm: 8 (RSASHA256)\nPrivateKey: AAAA\n").unwrap();
+		assert_eq!(error(&format!("{{key-file: {}}}", path.display())), format!("zone '*.example.com', 'dnssec': key file '{}' isn't an Ed25519 (algorithm 15) key", path.display()));
+		std::fs::write(&path, "Algorithm: 15 (ED25519)\nPrivateKey: AAAA\n").unwrap();
+		assert_eq!(error(&format!("{{key-file: {}}}", path.display())), format!("zone '*.example.com', 'dnssec': key file '{}' doesn't have a valid PrivateKey", path.display()));
+		std::fs::remove_file(&path).unwrap();
+	}
+	
+	#[test]
+	fn test_tsig_keys() {
+		let config = parse(r"tsig-keys:
+  Certbot.:
+    secret: AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=
+  external-dns:
+    algorithm: HMAC-SHA256
+    secret: AAAA
+zones: {}").unwrap();
+		assert_eq!(config.tsig_keys, vec![
+			TsigKey { name: "certbot".to_string(), secret: (0..32).collect() },
+			TsigKey { name: "external-dns".to_string(), secret: vec![0, 0, 0] },
+		]);
+		
+		let error = |key: &str| parse(&format!("tsig-keys: {{certbot: {}}}\nzones: {{}}", key)).unwrap_err().to_string();
+		assert_eq!(error("{algorithm: hmac-md5, secret: AAAA}"), "'tsig-keys: certbot': 'hmac-md5' is not a supported algorithm, which is only hmac-sha256");
+		assert_eq!(error("{secret: not base64}"), "'tsig-keys: certbot': 'secret' is not base64");
+		assert_eq!(error("{algorithm: hmac-sha256}"), "'tsig-keys: certbot': missing 'secret' field");
+		
+		let require_tsig = |key: &str| parse(&format!("tsig-keys: {{certbot: {{secret: AAAA}}}}\nzones: {{internal.example.com: {{require-tsig: {}, A: 10.0.0.1}}}}", key));
+		assert_eq!(require_tsig("Certbot.").unwrap().zones[0].records.require_tsig, Some("certbot".to_string()));
+		assert_eq!(require_tsig("other").unwrap_err().to_string(), "zone 'internal.example.com', 'require-tsig': 'other' is not a key under 'tsig-keys'");
+	}
+	
+	#[test]
+	fn test_checks() {
+		let config = parse(r"check-interval: 30s
+check-timeout: 1s
+zones:
+  example.com:
+    A:
+      - { ip: 10.0.0.1, check: 'tcp://10.0.0.1:443', weight: 10, ttl: 1m }
+      - 10.0.0.2 5m
+    AAAA:
+      - { ip: '::1', check: 'https://[::1]/healthz' }").unwrap();
+		assert_eq!(config.check_interval, Duration::from_secs(30));
+		assert_eq!(config.check_timeout, Duration::from_secs(1));
+		assert_eq!(config.zones[0].records.a, vec![
+			ARecord { ttl: Duration::from_secs(60), ip4addr: "10.0.0.1".parse().unwrap(), weight: Some(10), check: Some(Check::Tcp("10.0.0.1:443".parse().unwrap())), metadata: None },
+			ARecord { ttl: Duration::from_secs(5 * 60), ip4addr: "10.0.0.2".parse().unwrap(), weight: None, check: None, metadata: None },
+		]);
+		assert_eq!(config.zones[0].records.aaaa[0].check, Some(Check::Http("https://[::1]/healthz".to_string())));
+		
+		let error = |entry: &str| parse(&format!("zones: {{example.com: {{A: [{}]}}}}", entry)).unwrap_err().to_string();
+		assert_eq!(error("{check: 'tcp://10.0.0.1:443'}"), "zone 'example.com', record 'A': missing 'ip' field");
+		assert_eq!(error("{ip: 10.0.0.1, check: 'udp://10.0.0.1:53'}"), "zone 'example.com', record 'A': 'udp://10.0.0.1:53' is not a valid check, like tcp://192.0.2.1:443 or https://192.0.2.1/healthz");
+		assert_eq!(error("{ip: 10.0.0.1, weight: -1}"), "zone 'example.com', record 'A': '-1' is not a valid weight");
+	}
+	
+	#[test]
+	fn test_metadata() {
+		let config = parse(r"zones:
+  example.com:
+    A:
+      - { ip: 10.0.0.1, comment: primary LB, labels: { team: infra, tier: 1 } }
+      - 10.0.0.2
+    NS: { name: ns1.example.com, ttl: 5m, comment: run by the registrar }
+    TXT: { data: 'v=spf1 -all 5m', labels: { ticket: OPS-12 } }
+    RNS: { host: 10.0.0.53, external: true }").unwrap();
+		let records = &config.zones[0].records;
+		let metadata = records.a[0].metadata.as_ref().unwrap();
+		assert_eq!(metadata.comment.as_deref(), Some("primary LB"));
+		assert_eq!(metadata.labels.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect::<Vec<(&str, &str)>>(), vec![("team", "infra"), ("tier", "1")]);
+		assert_eq!(metadata.to_string(), "primary LB [team=infra, tier=1]");
+		// the string forms never have any
+		assert_eq!(records.a[1].metadata, None);
+		assert_eq!(records.ns[0], NsRecord { ttl: Duration::from_secs(300), name: "ns1.example.com".to_string(), metadata: Some(Metadata { comment: Some("run by the registrar".to_string()), labels: BTreeMap::new() }) });
+		// the data of a mapping is taken as it is, TTL and all
+		assert_eq!((records.txt[0].data.as_str(), records.txt[0].ttl), ("v=spf1 -all 5m", DEFAULT_TTL));
+		assert_eq!(records.txt[0].metadata.as_ref().unwrap().to_string(), "[ticket=OPS-12]");
+		assert!(records.rns[0].external);
+		assert_eq!(records.rns[0].metadata, None);
+		
+		let error = |entry: &str| parse(&format!("zones: {{example.com: {{{}}}}}", entry)).unwrap_err().to_string();
+		assert_eq!(error("A: {ip: 10.0.0.1, comment: [a, b]}"), "zone 'example.com', record 'A': expected a string, found a list");
+		assert_eq!(error("MX: {host: mail, labels: {team: [a]}}"), "zone 'example.com', record 'MX': expected a string, found a list");
+		assert_eq!(error("CNAME: {target: example.net.}"), "zone 'example.com', record 'CNAME': missing 'name' field");
+	}
+	
+	#[test]
+	fn test_networks() {
+		let config = parse(r"networks:
+  internal: [10.0.0.0/8, 'fd00::/8']
+zones:
+  example.com @internal 5m:
+    A: 10.0.0.5
+  example.com:
+    A: 203.0.113.5").unwrap();
+		assert_eq!(config.networks["internal"], vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]);
+		assert_eq!(config.zones[0].records.view, Some("internal".to_string()));
+		assert_eq!(config.zones[0].records.a[0].ttl, Duration::from_secs(5 * 60));
+		assert_eq!(config.zones[1].records.view, None);
+		
+		let error = |zones: &str| parse(&format!("networks: {{internal: 10.0.0.0/8}}\nzones: {}", zones)).unwrap_err().to_string();
+		assert_eq!(error("{example.com @external: {}}"), "zone 'example.com': there's no network 'external' under 'networks'");
+		assert_eq!(error("{example.com @internal @internal: {}}"), "zone 'example.com': '@internal @internal' is not a single view");
+		assert_eq!(parse("networks: {internal: nope}\nzones: {}").unwrap_err().to_string(), "'networks: internal': 'nope' is not a valid IP address or prefix");
+	}
+	
+	#[test]
+	fn test_duplicate_zones() {
+		let config = parse(r"networks: {internal: 10.0.0.0/8}
+zones:
+  Example.COM,www.example.com:
+    A: [10.0.0.1, 10.0.0.2]
+  www.example.com,example.com:
+    A: [10.0.0.2, 10.0.0.3]
+    TXT: hello
+  example.com @internal:
+    A: 10.0.0.4
+  /^w+$/.example.com: {}
+  /^w+$/.Example.com: {}").unwrap();
+		assert_eq!(config.zones.len(), 3);
+		assert_eq!(config.zones[0].records.a.iter().map(|a| a.ip4addr.to_string()).collect::<Vec<String>>(), vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+		assert_eq!(config.zones[0].records.txt.len(), 1);
+		// views are answered apart, so the same names in another one aren't a duplicate
+		assert_eq!(config.zones[1].records.view, Some("internal".to_string()));
+		
+		let strict = |zones: &str| parse(&format!("strict: true\nzones:{}", zones)).unwrap_err().to_string();
+		assert_eq!(strict("\n  example.com: {}\n  Example.com: {}"), "zone 'example.com' is given more than once");
+		assert_eq!(strict("\n  /^w+$/.example.com: {}\n  /^w+$/.Example.com: {}"), "zone '/^w+$/.example.com' is given more than once");
+		// the loader only keeps the last of these, so they can't be merged, only refused
+		assert_eq!(strict("\n  example.com: {A: 10.0.0.1}\n  example.com: {A: 10.0.0.2}"), "zone 'example.com' is given more than once");
+		assert_eq!(parse("zones:\n  example.com: {A: 10.0.0.1}\n  example.com: {A: 10.0.0.2}").unwrap().zones[0].records.a.len(), 1);
+	}
+	
+	#[test]
+	fn test_none_with_addresses() {
+		let error = |zones: &str| parse(&format!("zones:{}", zones)).unwrap_err().to_string();
+		assert_eq!(error("\n  example.com: {A: none, A 5m: 10.0.0.1}"), "zone 'example.com', record 'A': 'none' is not allowed along with addresses of the same type");
+		assert_eq!(error("\n  example.com: {AAAA 5m: '::1', AAAA: none}"), "zone 'example.com', record 'AAAA': 'none' is not allowed along with addresses of the same type");
+		// nor once zones matching the same names are merged
+		assert_eq!(error("\n  example.com: {A: none}\n  Example.com: {A: 10.0.0.1}"), "zone 'example.com', record 'A': 'none' is not allowed along with addresses of the same type");
+		// the other type is fine
+		assert!(parse("zones:\n  example.com: {A: none}\n  Example.com: {AAAA: '::1'}").is_ok());
+	}
+	
+	#[test]
+	fn test_interpolation() {
+		env::set_var("TACODNS_TEST_PUBLIC_IP", "203.0.113.5");
+		env::remove_var("TACODNS_TEST_UNSET");
+		let config = parse(r"ttl: 10m
+zones:
+  example.com:
+    A: ${TACODNS_TEST_PUBLIC_IP} 5m
+    TXT: ip=${TACODNS_TEST_PUBLIC_IP}, literally $${TACODNS_TEST_PUBLIC_IP}").unwrap();
+		assert_eq!(config.zones[0].records.a, vec![ARecord {
+			ttl: Duration::from_secs(5 * 60),
+			ip4addr: "203.0.113.5".parse().unwrap(),
+			weight: None,
+			check: None,
+			metadata: None,
+		}]);
+		assert_eq!(config.zones[0].records.txt[0].data, "ip=203.0.113.5, literally ${TACODNS_TEST_PUBLIC_IP}");
+		
+		assert_eq!(parse("zones: {example.com: {A: '${TACODNS_TEST_UNSET}'}}").unwrap_err(), ConfigError::UnsetVariable {
+			at: Location::Record("example.com".to_string(), "A".to_string()),
+			variable: "TACODNS_TEST_UNSET".to_string(),
+		});
+		assert_eq!(parse("ttl: ${TACODNS_TEST_UNSET}\nzones: {}").unwrap_err().to_string(), "'ttl': environment variable 'TACODNS_TEST_UNSET' isn't set");
+	}
+	
+	#[test]
+	fn test_include() {
+		let path = |name: &str| env::temp_dir().join(format!("tacodns-test-{}-{}.yml", process::id(), name));
+		fs::write(path("first"), "zones:\n  example.org: {A: 10.0.0.2}\n  example.net: {A: 10.0.0.3}").unwrap();
+		fs::write(path("second"), "zones:\n  example.net: {A: 10.0.0.4}").unwrap();
+		fs::write(path("invalid"), "ttl: 5m\nzones: {}").unwrap();
+		let include = |names: &[&str]| parse(&format!("include: [{}]\nzones:\n  example.com: {{A: 10.0.0.1}}", names.iter().map(|name| format!("'{}'", path(name).display())).collect::<Vec<String>>().join(", ")));
+		
+		let config = include(&["first", "second"]).unwrap();
+		let zones: Vec<(String, Vec<String>)> = config.zones.iter()
+			.map(|zone| (zone.matchers[0].iter().map(Label::to_string).collect::<Vec<String>>().join("."), zone.records.a.iter().map(|a| a.ip4addr.to_string()).collect()))
+			.collect();
+		// the later file wins
+		assert_eq!(zones, vec![
+			("example.com".to_string(), vec!["10.0.0.1".to_string()]),
+			("example.org".to_string(), vec!["10.0.0.2".to_string()]),
+			("example.net".to_string(), vec!["10.0.0.4".to_string()]),
+		]);
+		
+		assert_eq!(include(&["invalid"]).unwrap_err().to_string(), format!("include '{}': has 'ttl', but only 'zones' can be included", path("invalid").display()));
+		assert!(include(&["missing"]).unwrap_err().to_string().starts_with(&format!("include '{}': can't be read: ", path("missing").display())));
+		for name in &["first", "second", "invalid"] {
+			fs::remove_file(path(name)).unwrap();
+		}
+	}
+	
+	#[test]
+	fn test_parse_many() {
+		let addresses = |config: &Config| config.zones.iter()
+			.map(|zone| (zone_name(&zone.matchers), zone.records.a.iter().map(|a| a.ip4addr.to_string()).collect()))
+			.collect::<Vec<(String, Vec<String>)>>();
+		
+		let directory = env::temp_dir().join(format!("tacodns-test-{}-configs", process::id()));
+		fs::create_dir_all(&directory).unwrap();
+		fs::write(directory.join("00-settings.yml"), "ttl: 5m\ndefaults:\n  TXT: shared").unwrap();
+		fs::write(directory.join("20-customer.yaml"), "zones:\n  example.org: {A: 10.0.0.2}\n  example.com: {A: 10.0.0.3}").unwrap();
+		fs::write(directory.join("10-customer.yml"), "zones:\n  example.com: {A: 10.0.0.1}").unwrap();
+		// only YAML files are read
+		fs::write(directory.join("README"), "not a config").unwrap();
+		let config = parse_many(&read(directory.to_str().unwrap()).unwrap()).unwrap();
+		fs::remove_dir_all(&directory).unwrap();
+		assert_eq!(config.ttl, Duration::from_secs(300));
+		// in the order of the files, with zones given again merged into the first
+		assert_eq!(addresses(&config), vec![
+			("example.com".to_string(), vec!["10.0.0.1".to_string(), "10.0.0.3".to_string()]),
+			("example.org".to_string(), vec!["10.0.0.2".to_string()]),
+		]);
+		assert_eq!(config.zones[1].records.txt.len(), 1);
+		
+		let config = parse("zones:\n  example.com: {A: 10.0.0.1}\n---\nx-address: &address 10.0.0.2\nzones:\n  example.org: {A: *address}").unwrap();
+		assert_eq!(addresses(&config), vec![
+			("example.com".to_string(), vec!["10.0.0.1".to_string()]),
+			("example.org".to_string(), vec!["10.0.0.2".to_string()]),
+		]);
+		// which is refused when strict
+		assert_eq!(parse("strict: true\nzones:\n  example.com: {A: 10.0.0.1}\n---\nzones:\n  Example.COM: {A: 10.0.0.2}"), Err(ConfigError::DuplicateZone("example.com".to_string())));
+		
+		// only the first document has settings
+		assert_eq!(parse("zones: {}\n---\nttl: 5m\nzones: {}").unwrap_err().to_string(), "document 2: has 'ttl', but only the first document can have settings");
+		assert_eq!(parse_many(&["zones: {}".to_string(), "zones: {}\n---\nnttl: 5m".to_string()]).unwrap_err().to_string(), "document 3: has 'nttl', but only the first document can have settings");
+		// and without others, it has to have zones
+		assert_eq!(parse("ttl: 5m"), Err(ConfigError::MissingField { at: Location::Document, field: "zones" }));
+		assert_eq!(parse_many(&[]), Err(ConfigError::DocumentCount(0)));
+	}
+	
+	#[test]
+	fn test_defaults() {
+		let config = parse(r"defaults:
+  NS: [ns1.example.net., ns2.example.net.]
+  TXT: verification=abc
+zones:
+  example.com:
+    A: 10.0.0.1
+  example.org:
+    NS: ns.example.org.
+    A: 10.0.0.2
+    TXT: v=spf1 -all
+  example.net:
+    A: 10.0.0.3
+    TXT:
+  www.example.com:
+    CNAME: example.com.
+  sub.example.com:
+    NS: ns.example.org.").unwrap();
+		let ns = |zone: usize| config.zones[zone].records.ns.iter().map(|ns| ns.name.as_str()).collect::<Vec<&str>>();
+		let txt = |zone: usize| config.zones[zone].records.txt.iter().map(|txt| txt.data.as_str()).collect::<Vec<&str>>();
+		
+		assert_eq!(ns(0), vec!["ns1.example.net.", "ns2.example.net."]);
+		assert_eq!(txt(0), vec!["verification=abc"]);
+		// the zone's own records come first
+		assert_eq!(ns(1), vec!["ns.example.org.", "ns1.example.net.", "ns2.example.net."]);
+		assert_eq!(txt(1), vec!["v=spf1 -all", "verification=abc"]);
+		// an empty type leaves the defaults of that type out
+		assert_eq!(ns(2), vec!["ns1.example.net.", "ns2.example.net."]);
+		assert!(txt(2).is_empty());
+		// nothing goes beside a CNAME, and delegations stay delegations
+		assert!(ns(3).is_empty() && txt(3).is_empty());
+		assert_eq!(ns(4), vec!["ns.example.org."]);
+		assert!(txt(4).is_empty());
+		
+		assert_eq!(parse("defaults: {CNAME: example.com.}\nzones: {}").unwrap_err().to_string(), "'defaults': 'CNAME' is not a record type other than CNAME");
+		assert_eq!(parse("defaults: {forward: 10.0.0.53}\nzones: {}").unwrap_err().to_string(), "'defaults': 'forward' is not a record type other than CNAME");
+	}
+	
+	#[test]
+	fn test_notify() {
+		let config = parse(r"notify: 10.0.0.5
+zones:
+  example.com:
+    notify: [10.0.0.6, '[::1]:5353']").unwrap();
+		assert_eq!(config.notify, vec!["10.0.0.5:53".parse().unwrap()]);
+		assert_eq!(config.zones[0].records.notify, vec!["10.0.0.6:53".parse().unwrap(), "[::1]:5353".parse().unwrap()]);
+		assert_eq!(parse("zones: {example.com: {notify only: 10.0.0.6}}").unwrap_err().to_string(), "zone 'example.com', 'notify': unknown flag 'only', none are allowed");
+	}
+	
+	#[test]
+	fn test_txt_quoting() {
+		let config = parse(r#"zones:
+  example.com:
+    TXT:
+      - retry in 30m
+      - '"retry in 30m"'
+      - '"retry in 30m" 1h'
+      - '"say "hi""'
+      - hello world"#).unwrap();
+		assert_eq!(config.zones[0].records.txt, vec![TxtRecord {
+			ttl: Duration::from_secs(30 * 60),
+			data: "retry in".to_string(),
+			metadata: None,
+		}, TxtRecord {
+			ttl: DEFAULT_TTL,
+			data: "retry in 30m".to_string(),
+			metadata: None,
+		}, TxtRecord {
+			ttl: Duration::from_secs(60 * 60),
+			data: "retry in 30m".to_string(),
+			metadata: None,
+		}, TxtRecord {
+			ttl: DEFAULT_TTL,
+			data: "say \"hi\"".to_string(),
+			metadata: None,
+		}, TxtRecord {
+			ttl: DEFAULT_TTL,
+			data: "hello world".to_string(),
+			metadata: None,
+		}]);
 	}
 }
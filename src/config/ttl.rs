@@ -2,6 +2,16 @@ use std::time::Duration;
 
 use crate::regex::Regex;
 
+/// The longest TTL a record can have, as TTLs are unsigned but were signed once, so those with the
+/// top bit set are taken to be 0. https://tools.ietf.org/html/rfc2181#section-8
+pub const MAX_TTL: Duration = Duration::from_secs(i32::MAX as u64);
+
+/// A TTL in seconds as it's sent, cut down to `MAX_TTL` if it's longer. A TTL of 0 is sent as it
+/// is, for the record to be used once and not cached, which is why nothing here caches one either.
+pub fn ttl_secs(ttl: Duration) -> u32 {
+	return ttl.min(MAX_TTL).as_secs() as u32;
+}
+
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
 pub struct NotATtlError;
 
@@ -9,39 +19,46 @@ pub trait Parse<T, E> {
 	fn parse(str: &str) -> Result<T, E>;
 }
 
+/// The units a duration can be written in, largest first, with how many seconds each is. A duration
+/// like `1h30m` has them in this order, each at most once, as `30m1h` is more likely a mistake.
+const UNITS: &[(&str, u64)] = &[("w", 60 * 60 * 24 * 7), ("d", 60 * 60 * 24), ("h", 60 * 60), ("m", 60), ("s", 1)];
+
 impl Parse<Duration, NotATtlError> for Duration {
+	/// Parses a duration like `90`, `5m`, `1h30m`, or `1500ms`. Records are answered with their TTL
+	/// in whole seconds, so milliseconds are mostly for the timeouts of health checks.
 	fn parse(time: &str) -> Result<Duration, NotATtlError> {
-		let regex = Regex::new(r"^(\d+)([smhdw])?$").unwrap();
+		// a plain number is in seconds
+		let regex = Regex::new(r"^(?:(\d+)w)?(?:(\d+)d)?(?:(\d+)h)?(?:(\d+)m)?(?:(\d+)s)?(?:(\d+)ms)?$|^(\d+)$").unwrap();
 		let captures = match regex.captures(time) {
-			Some(value) => value,
-			None => return Err(NotATtlError {}),
-		};
-		
-		let number: u64 = captures.get(1).unwrap().as_str().parse().unwrap();
-		let units = match captures.get(2) {
-			None => "s",
-			Some(value) => value.as_str(),
-		};
-		
-		let secs = match units {
-			"s" => number,
-			"m" => number * 60,
-			"h" => number * 60 * 60,
-			"d" => number * 60 * 60 * 24,
-			"w" => number * 60 * 60 * 24 * 7,
-			_ => panic!("unknown unit: {}", units),
+			Some(captures) if !time.is_empty() => captures,
+			_ => return Err(NotATtlError {}),
 		};
+		// too long for any TTL, which is caught along with those that are only a little too long
+		let number = |index: usize| captures.get(index).map(|number| number.as_str().parse::<u64>().unwrap_or(u64::MAX));
 		
-		Ok(Duration::from_secs(secs))
+		let mut secs = number(7).unwrap_or(0);
+		for (index, (_, unit_secs)) in UNITS.iter().enumerate() {
+			if let Some(number) = number(index + 1) {
+				secs = secs.saturating_add(number.saturating_mul(*unit_secs));
+			}
+		}
+		let millis = number(6).unwrap_or(0);
+		secs = secs.saturating_add(millis / 1000);
+		Ok(Duration::new(secs, (millis % 1000) as u32 * 1_000_000))
 	}
 }
 
+/// Parses a duration as it's written in the config, for the options that take one.
+pub fn parse_duration(time: &str) -> Result<Duration, String> {
+	return Duration::parse(time).map_err(|_| format!("'{}' isn't a duration, like 100ms or 1s", time));
+}
+
 #[cfg(test)]
 mod test {
 	mod parse_ttl {
 		use std::time::Duration;
 		
-		use crate::config::ttl::Parse;
+		use crate::config::ttl::{NotATtlError, Parse};
 		
 		fn parse_ttl(time: &str) -> Duration {
 			Duration::parse(time).unwrap()
@@ -94,5 +111,71 @@ mod test {
 			assert_eq!(parse_ttl("5w"), Duration::from_secs(5 * 60 * 60 * 24 * 7));
 			assert_eq!(parse_ttl("5000w"), Duration::from_secs(5000 * 60 * 60 * 24 * 7));
 		}
+		
+		#[test]
+		fn milliseconds() {
+			assert_eq!(parse_ttl("0ms"), Duration::from_millis(0));
+			assert_eq!(parse_ttl("500ms"), Duration::from_millis(500));
+			assert_eq!(parse_ttl("1500ms"), Duration::from_millis(1500));
+			// and not minutes and seconds
+			assert_eq!(parse_ttl("5ms"), Duration::from_millis(5));
+		}
+		
+		#[test]
+		fn compound() {
+			assert_eq!(parse_ttl("1h30m"), Duration::from_secs(90 * 60));
+			assert_eq!(parse_ttl("1d12h"), Duration::from_secs(36 * 60 * 60));
+			assert_eq!(parse_ttl("1m30s"), parse_ttl("90s"));
+			assert_eq!(parse_ttl("1m30s"), parse_ttl("90"));
+			assert_eq!(parse_ttl("2w1d1h1m1s"), Duration::from_secs(15 * 60 * 60 * 24 + 60 * 60 + 60 + 1));
+			assert_eq!(parse_ttl("1s500ms"), Duration::from_millis(1500));
+			// units can be left out, but needn't be
+			assert_eq!(parse_ttl("1h0m"), Duration::from_secs(60 * 60));
+			assert_eq!(parse_ttl("1d30m"), Duration::from_secs(60 * 60 * 24 + 30 * 60));
+		}
+		
+		#[test]
+		fn not_ttls() {
+			// the largest unit comes first, and each is only given once
+			assert_eq!(Duration::parse("30m1h"), Err(NotATtlError));
+			assert_eq!(Duration::parse("1h1h"), Err(NotATtlError));
+			assert_eq!(Duration::parse("500ms1s"), Err(NotATtlError));
+			// and each has a number
+			assert_eq!(Duration::parse("1h30"), Err(NotATtlError));
+			assert_eq!(Duration::parse("h"), Err(NotATtlError));
+			assert_eq!(Duration::parse(""), Err(NotATtlError));
+			assert_eq!(Duration::parse("5x"), Err(NotATtlError));
+			assert_eq!(Duration::parse("rotate"), Err(NotATtlError));
+			assert_eq!(Duration::parse("-5m"), Err(NotATtlError));
+		}
+		
+		#[test]
+		fn overflow() {
+			assert_eq!(parse_ttl("99999999999999999999w"), Duration::from_secs(u64::MAX));
+			assert_eq!(parse_ttl("9999999999999999999w"), Duration::from_secs(u64::MAX));
+			assert_eq!(parse_ttl("9999999999999999999w1s"), Duration::from_secs(u64::MAX));
+			assert_eq!(parse_ttl("99999999999999999999ms"), Duration::from_millis(u64::MAX));
+		}
+	}
+	
+	mod ttl_secs {
+		use std::time::Duration;
+		
+		use crate::config::ttl::{MAX_TTL, Parse, ttl_secs};
+		
+		#[test]
+		fn zero() {
+			// sent as it is, rather than made up to something that would be cached
+			assert_eq!(ttl_secs(Duration::from_secs(0)), 0);
+		}
+		
+		#[test]
+		fn overflow() {
+			assert_eq!(ttl_secs(Duration::from_secs(300)), 300);
+			assert_eq!(ttl_secs(MAX_TTL), 2147483647);
+			assert_eq!(ttl_secs(MAX_TTL + Duration::from_secs(1)), 2147483647);
+			assert_eq!(ttl_secs(Duration::parse("100000w").unwrap()), 2147483647);
+			assert_eq!(ttl_secs(Duration::from_secs(u64::MAX)), 2147483647);
+		}
 	}
 }
@@ -1,13 +1,46 @@
 use super::yaml_rust::Yaml;
 use super::yaml_rust::yaml::Hash;
 
-pub trait ExpectStr {
-	fn expect_str(&self) -> &str;
+use crate::config::error::{ConfigError, describe, Location};
+
+pub trait Expect {
+	fn expect_str(&self, at: &Location) -> Result<&str, ConfigError>;
+	fn expect_i64(&self, at: &Location) -> Result<i64, ConfigError>;
+	fn expect_bool(&self, at: &Location) -> Result<bool, ConfigError>;
+	fn expect_hash(&self, at: &Location) -> Result<&Hash, ConfigError>;
+}
+
+fn wrong_type(yaml: &Yaml, at: &Location, expected: &'static str) -> ConfigError {
+	ConfigError::WrongType { at: at.clone(), expected, found: describe(yaml) }
+}
+
+impl Expect for Yaml {
+	fn expect_str(&self, at: &Location) -> Result<&str, ConfigError> {
+		self.as_str().ok_or_else(|| wrong_type(self, at, "a string"))
+	}
+	
+	fn expect_i64(&self, at: &Location) -> Result<i64, ConfigError> {
+		self.as_i64().ok_or_else(|| wrong_type(self, at, "an integer"))
+	}
+	
+	fn expect_bool(&self, at: &Location) -> Result<bool, ConfigError> {
+		self.as_bool().ok_or_else(|| wrong_type(self, at, "true or false"))
+	}
+	
+	fn expect_hash(&self, at: &Location) -> Result<&Hash, ConfigError> {
+		self.as_hash().ok_or_else(|| ConfigError::NotAMapping(at.clone()))
+	}
 }
 
-impl ExpectStr for Yaml {
-	fn expect_str(&self) -> &str {
-		self.as_str().expect(&format!("Expected string: {:?}", self))
+/// A scalar as a string, for values YAML may have taken for something else, like `TXT: 12345` or
+/// `A: 10` written without quotes. Numbers are written as YAML read them, so `012` comes back as
+/// `12`, and booleans are refused rather than turned into text nobody meant.
+pub fn coerce_scalar(yaml: &Yaml, at: &Location) -> Result<String, ConfigError> {
+	match yaml {
+		Yaml::String(string) | Yaml::Real(string) => Ok(string.clone()),
+		Yaml::Integer(integer) => Ok(integer.to_string()),
+		Yaml::Boolean(_) => Err(wrong_type(yaml, at, "a string, quoted if it's true or false")),
+		_ => Err(wrong_type(yaml, at, "a string")),
 	}
 }
 
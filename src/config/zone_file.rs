@@ -0,0 +1,326 @@
+//! Writing the zones out in the master file format of https://tools.ietf.org/html/rfc1035#section-5,
+//! for auditing them or moving them to another server.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::config::{Config, Label, Metadata, Records, SvcbRecord, ttl_secs, Zone, ZoneMatcher};
+
+/// Writes every zone that a master file can hold, each under its own `$ORIGIN`, followed by a
+/// comment listing the zones that it can't: those with regular expressions, wildcards other than a
+/// single `*` as the first label, or a wildcard with exclusions. Records that are looked up as
+/// they're queried (ANAME, ALIAS, RNS, TRPP, and forwarding) are left as comments in their zone.
+pub fn dump_zones(config: &Config) -> String {
+	let mut output = String::new();
+	let mut skipped = vec![];
+	for zone in &config.zones {
+		for matcher in &zone.matchers {
+			match origin(matcher) {
+				Some((owner, origin)) if owner == "@" || (!has_relative_names(&zone.records) && zone.excludes.is_empty()) => {
+					write_zone(&mut output, zone, owner, &origin, config);
+				}
+				_ => skipped.push(matcher.iter().map(Label::to_string).collect::<Vec<String>>().join(".")),
+			}
+		}
+	}
+	if !skipped.is_empty() {
+		if !output.is_empty() {
+			output.push('\n');
+		}
+		// names relative to a wildcard are relative to whatever it matched, which a master file can't say
+		output.push_str("; not exportable, as they match names a master file can't express:\n");
+		for name in skipped {
+			let _ = writeln!(output, ";   {}", name);
+		}
+	}
+	return output;
+}
+
+/// The owner name of a zone's records, and the origin they're under, if it can be written in a
+/// master file: `@` for a plain name, and `*` for a wildcard over the plain name after it.
+fn origin(matcher: &ZoneMatcher) -> Option<(&'static str, Vec<String>)> {
+	let (owner, labels) = match matcher.split_first() {
+		Some((Label::Wildcard, rest)) => ("*", rest),
+		_ => ("@", &matcher[..]),
+	};
+	let origin = labels.iter().map(|label| match label {
+		Label::Basic(label) => Some(label.clone()),
+		_ => None,
+	}).collect::<Option<Vec<String>>>()?;
+	return Some((owner, origin));
+}
+
+/// Whether any of the records name another relative to the one queried.
+fn has_relative_names(records: &Records) -> bool {
+	let names = records.ns.iter().map(|ns| &ns.name)
+		.chain(records.cname.iter().map(|cname| &cname.name))
+		.chain(records.mx.iter().map(|mx| &mx.host))
+		.chain(records.srv.iter().map(|srv| &srv.target))
+		.chain(records.naptr.iter().map(|naptr| &naptr.replacement))
+		.chain(records.svcb.iter().chain(&records.https).map(|svcb| &svcb.target))
+		.chain(records.ptr.iter().map(|ptr| &ptr.name));
+	return names.into_iter().any(|name| !name.ends_with('.'));
+}
+
+fn write_zone(output: &mut String, zone: &Zone, owner: &str, origin: &[String], config: &Config) {
+	let records = &zone.records;
+	let absolute = |name: &str| absolute_name(name, origin);
+	let mut lines: Vec<(Duration, &str, String, &Option<Metadata>)> = vec![];
+	for soa in &records.soa {
+		lines.push((soa.ttl, "SOA", format!("{} {} {} {} {} {} {}",
+			absolute(&format!("{}.", soa.mname.trim_end_matches('.'))),
+			absolute(&format!("{}.", soa.rname.trim_end_matches('.'))),
+			soa.serial.unwrap_or(config.serial),
+			soa.refresh.as_secs(),
+			soa.retry.as_secs(),
+			soa.expire.as_secs(),
+			ttl_secs(soa.minimum.or(records.nodata_ttl).unwrap_or(config.nttl))), &soa.metadata));
+	}
+	lines.extend(records.ns.iter().map(|ns| (ns.ttl, "NS", absolute(&ns.name), &ns.metadata)));
+	lines.extend(records.a.iter().map(|a| (a.ttl, "A", a.ip4addr.to_string(), &a.metadata)));
+	lines.extend(records.aaaa.iter().map(|aaaa| (aaaa.ttl, "AAAA", aaaa.ip6addr.to_string(), &aaaa.metadata)));
+	lines.extend(records.cname.iter().map(|cname| (cname.ttl, "CNAME", absolute(&cname.name), &cname.metadata)));
+	lines.extend(records.mx.iter().map(|mx| (mx.ttl, "MX", format!("{} {}", mx.priority, absolute(&mx.host)), &mx.metadata)));
+	lines.extend(records.txt.iter().map(|txt| (txt.ttl, "TXT", character_strings(txt.data.as_bytes()), &txt.metadata)));
+	lines.extend(records.srv.iter().map(|srv| (srv.ttl, "SRV", format!("{} {} {} {}", srv.priority, srv.weight, srv.port, absolute(&srv.target)), &srv.metadata)));
+	lines.extend(records.naptr.iter().map(|naptr| (naptr.ttl, "NAPTR", format!("{} {} {} {} {} {}",
+		naptr.order,
+		naptr.preference,
+		character_string(naptr.flags.as_bytes()),
+		character_string(naptr.service.as_bytes()),
+		character_string(naptr.regexp.as_bytes()),
+		absolute(&naptr.replacement)), &naptr.metadata)));
+	lines.extend(records.hinfo.iter().map(|hinfo| (hinfo.ttl, "HINFO", format!("{} {}", character_string(hinfo.cpu.as_bytes()), character_string(hinfo.os.as_bytes())), &hinfo.metadata)));
+	lines.extend(records.loc.iter().map(|loc| (loc.ttl, "LOC", loc.position.to_string(), &loc.metadata)));
+	lines.extend(records.sshfp.iter().map(|sshfp| (sshfp.ttl, "SSHFP", format!("{} {} {}",
+		sshfp.algorithm,
+		sshfp.fingerprint_type,
+		sshfp.fingerprint.iter().map(|byte| format!("{:02X}", byte)).collect::<String>()), &sshfp.metadata)));
+	lines.extend(records.svcb.iter().map(|svcb| (svcb.ttl, "SVCB", svcb_rdata(svcb, origin), &svcb.metadata)));
+	lines.extend(records.https.iter().map(|https| (https.ttl, "HTTPS", svcb_rdata(https, origin), &https.metadata)));
+	lines.extend(records.ptr.iter().map(|ptr| (ptr.ttl, "PTR", absolute(&ptr.name), &ptr.metadata)));
+	
+	if !output.is_empty() {
+		output.push('\n');
+	}
+	let origin: Vec<String> = origin.iter().map(|label| escape_label(label)).collect();
+	let _ = writeln!(output, "$ORIGIN {}.", origin.join("."));
+	if let Some(view) = &records.view {
+		let _ = writeln!(output, "; only answered to the {} network", view);
+	}
+	for (ttl, rtype, rdata, metadata) in lines {
+		let _ = writeln!(output, "{}\t{}\tIN\t{}\t{}{}", owner, ttl_secs(ttl), rtype, rdata, comment(metadata));
+	}
+	
+	// looked up as they're queried, so there's nothing to write but what they point at
+	for aname in &records.aname {
+		let _ = writeln!(output, "; {}\t{}\tIN\tANAME\t{}{}", owner, ttl_secs(aname.ttl), aname.name, comment(&aname.metadata));
+	}
+	for alias in &records.alias {
+		let _ = writeln!(output, "; {}\t{}\tIN\tALIAS\t{}{}", owner, ttl_secs(alias.ttl), alias.name, comment(&alias.metadata));
+	}
+	if !records.rns.is_empty() {
+		let _ = writeln!(output, "; {} has RNS records, asking other servers for its records", owner);
+	}
+	if !records.trpp.is_empty() {
+		let _ = writeln!(output, "; {} has TRPP records, asking an HTTP server for its records", owner);
+	}
+	if let Some(forward) = &records.forward {
+		let servers: Vec<String> = forward.servers.iter().map(|server| server.to_string()).collect();
+		let _ = writeln!(output, "; {} is forwarded to {}", owner, servers.join(", "));
+	}
+}
+
+/// A record's comment and labels as a comment at the end of its line, if it has any.
+fn comment(metadata: &Option<Metadata>) -> String {
+	return match metadata {
+		Some(metadata) => format!("\t; {}", metadata),
+		None => String::new(),
+	};
+}
+
+/// Makes a name from the config absolute, with a trailing dot, where a relative one is under
+/// `origin`.
+fn absolute_name(name: &str, origin: &[String]) -> String {
+	let labels: Vec<String> = match name.strip_suffix('.') {
+		Some(absolute) => absolute.split('.').filter(|label| !label.is_empty()).map(escape_label).collect(),
+		None => name.split('.').map(escape_label).chain(origin.iter().map(|label| escape_label(label))).collect(),
+	};
+	return format!("{}.", labels.join("."));
+}
+
+/// The rdata of an SVCB or HTTPS record in presentation format, with its params in the order of
+/// their keys: https://tools.ietf.org/html/rfc9460#section-2.1
+fn svcb_rdata(svcb: &SvcbRecord, origin: &[String]) -> String {
+	let params = &svcb.params;
+	let mut rdata = format!("{} {}", svcb.priority, absolute_name(&svcb.target, origin));
+	if !params.mandatory.is_empty() {
+		let _ = write!(rdata, " mandatory={}", params.mandatory.join(","));
+	}
+	if !params.alpn.is_empty() {
+		// commas separate the IDs, so those in one are escaped, as are the escapes themselves
+		let alpn: Vec<String> = params.alpn.iter().map(|id| id.replace('\\', r"\\").replace(',', r"\,")).collect();
+		let _ = write!(rdata, " alpn={}", character_string(alpn.join(",").as_bytes()));
+	}
+	if params.no_default_alpn {
+		rdata.push_str(" no-default-alpn");
+	}
+	if let Some(port) = params.port {
+		let _ = write!(rdata, " port={}", port);
+	}
+	if !params.ipv4hint.is_empty() {
+		let _ = write!(rdata, " ipv4hint={}", params.ipv4hint.iter().map(|address| address.to_string()).collect::<Vec<String>>().join(","));
+	}
+	if !params.ipv6hint.is_empty() {
+		let _ = write!(rdata, " ipv6hint={}", params.ipv6hint.iter().map(|address| address.to_string()).collect::<Vec<String>>().join(","));
+	}
+	return rdata;
+}
+
+/// Escapes what would otherwise end a label or mean something else in a master file, along with
+/// anything that isn't printable ASCII.
+fn escape_label(label: &str) -> String {
+	let mut escaped = String::new();
+	for byte in label.bytes() {
+		match byte {
+			b'.' | b'"' | b';' | b'(' | b')' | b'\\' | b'@' | b'$' => {
+				escaped.push('\\');
+				escaped.push(byte as char);
+			}
+			0x21..=0x7e => escaped.push(byte as char),
+			_ => {
+				let _ = write!(escaped, "\\{:03}", byte);
+			}
+		}
+	}
+	return escaped;
+}
+
+/// A value as one or more quoted character-strings, split every 255 bytes as `serialize_txt`
+/// splits it on the wire.
+fn character_strings(data: &[u8]) -> String {
+	if data.is_empty() {
+		return character_string(data);
+	}
+	return data.chunks(255).map(character_string).collect::<Vec<String>>().join(" ");
+}
+
+/// A quoted character-string, with quotes and backslashes escaped, as well as anything that isn't
+/// printable ASCII. Values longer than 255 bytes are given to `character_strings` instead.
+fn character_string(data: &[u8]) -> String {
+	let mut quoted = String::from("\"");
+	for &byte in data {
+		match byte {
+			b'"' | b'\\' => {
+				quoted.push('\\');
+				quoted.push(byte as char);
+			}
+			0x20..=0x7e => quoted.push(byte as char),
+			_ => {
+				let _ = write!(quoted, "\\{:03}", byte);
+			}
+		}
+	}
+	quoted.push('"');
+	return quoted;
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::parse;
+	use crate::config::zone_file::{character_strings, dump_zones};
+	
+	#[test]
+	fn test_dump_zones() {
+		let mut config = parse(r#"ttl: 1h
+zones:
+  example.com:
+    SOA:
+      mname: ns1.example.com
+      rname: hostmaster.example.com.
+      refresh: 3h
+      retry: 1h
+      expire: 1w
+      minimum: 5m
+    NS: [ns1, ns2.example.net.]
+    A: {ip: 10.0.0.1, comment: primary LB, labels: {team: infra}}
+    AAAA: ::1
+    MX: [mail, {priority: 20, host: backup.example.net., comment: "fallback,\n  rarely used"}]
+    TXT: ['v=spf1 -all', '"retry in 30m; now" 5m']
+    HTTPS: {priority: 1, target: ., alpn: [h2, h3], port: 8443, ipv4hint: 10.0.0.1}
+  _sip._tcp.example.com:
+    SRV: 10 5 5060 sip.example.com.
+  www.example.com,web.example.com:
+    CNAME: example.com.
+  '*.example.com':
+    A: 10.0.0.2 30s
+    MX: mail.example.com.
+  host.example.com:
+    HINFO: {cpu: Intel x86, os: Linux}
+    LOC: 52 22 23.000 N 4 53 32.000 E -2.00m
+    SSHFP: 4 2 0123456789abcdef
+    ANAME: {name: lb.example.net., labels: {owner: web}}
+  4.3.2.1.e164.arpa:
+    NAPTR: {order: 100, preference: 10, flags: u, service: E2U+sip, regexp: '!^.*$!sip:info@example.com!'}
+  1.0.0.10.in-addr.arpa:
+    PTR: example.com.
+  '**.example.org':
+    A: 10.0.0.3
+  /^w+$/.example.com:
+    A: 10.0.0.4
+  '*.example.net':
+    CNAME: target"#).unwrap();
+		config.serial = 2020010100;
+		assert_eq!(dump_zones(&config), r#"$ORIGIN example.com.
+@	3600	IN	SOA	ns1.example.com. hostmaster.example.com. 2020010100 10800 3600 604800 300
+@	3600	IN	NS	ns1.example.com.
+@	3600	IN	NS	ns2.example.net.
+@	3600	IN	A	10.0.0.1	; primary LB [team=infra]
+@	3600	IN	AAAA	::1
+@	3600	IN	MX	10 mail.example.com.
+@	3600	IN	MX	20 backup.example.net.	; fallback, rarely used
+@	3600	IN	TXT	"v=spf1 -all"
+@	300	IN	TXT	"retry in 30m; now"
+@	3600	IN	HTTPS	1 . alpn="h2,h3" port=8443 ipv4hint=10.0.0.1
+
+$ORIGIN _sip._tcp.example.com.
+@	3600	IN	SRV	10 5 5060 sip.example.com.
+
+$ORIGIN www.example.com.
+@	3600	IN	CNAME	example.com.
+
+$ORIGIN web.example.com.
+@	3600	IN	CNAME	example.com.
+
+$ORIGIN example.com.
+*	30	IN	A	10.0.0.2
+*	3600	IN	MX	10 mail.example.com.
+
+$ORIGIN host.example.com.
+@	3600	IN	HINFO	"Intel x86" "Linux"
+@	3600	IN	LOC	52 22 23.000 N 4 53 32.000 E -2.00m 1.00m 10000.00m 10.00m
+@	3600	IN	SSHFP	4 2 0123456789ABCDEF
+; @	3600	IN	ANAME	lb.example.net.	; [owner=web]
+
+$ORIGIN 4.3.2.1.e164.arpa.
+@	3600	IN	NAPTR	100 10 "u" "E2U+sip" "!^.*$!sip:info@example.com!" .
+
+$ORIGIN 1.0.0.10.in-addr.arpa.
+@	3600	IN	PTR	example.com.
+
+; not exportable, as they match names a master file can't express:
+;   **.example.org
+;   /^w+$/.example.com
+;   *.example.net
+"#);
+	}
+	
+	#[test]
+	fn test_character_strings() {
+		assert_eq!(character_strings(b""), r#""""#);
+		assert_eq!(character_strings(b"a \"quoted\" \\ value"), r#""a \"quoted\" \\ value""#);
+		assert_eq!(character_strings("caf\u{e9};\t".as_bytes()), r#""caf\195\169;\009""#);
+		let long = "a".repeat(300);
+		assert_eq!(character_strings(long.as_bytes()), format!("\"{}\" \"{}\"", "a".repeat(255), "a".repeat(45)));
+	}
+}
@@ -0,0 +1,15 @@
+//! An authoritative DNS server configured with YAML, along with its resolution logic for those
+//! that want to answer from a config without running the server, through `config::parse` and
+//! `server::resolve`.
+
+// lints that clash with the style used throughout this crate
+#![allow(clippy::needless_return, clippy::len_zero)]
+
+extern crate clap;
+#[macro_use]
+extern crate lazy_static; // would put this in options.rs, but #[macro_use] can only be done in crate root
+
+pub mod options;
+pub mod config;
+pub mod server;
+mod regex;
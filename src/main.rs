@@ -1,25 +1,59 @@
-extern crate clap;
-#[macro_use]
-extern crate lazy_static; // would put this in options.rs, but #[macro_use] can only be done in crate root
+use std::{env, process};
 
-use std::{env, fs::read_to_string};
-
-mod options;
-mod config;
-mod server;
-mod regex;
+use tacodns::{config, options, server};
 
 fn main() {
 	let opts = options::parse();
 	if opts.verbose { println!("{:?}", opts); }
 	
-	let config_data = if let Some(config_env) = &opts.config_env {
-		env::var(config_env).expect(format!("Missing {:?} environment variable.", opts.config_env).as_str())
+	let (source, config_data) = if let Some(config_env) = &opts.config_env {
+		(format!("${}", config_env), env::var(config_env).map(|config_data| vec![config_data]).map_err(|error| error.to_string()))
 	} else {
-		read_to_string(&opts.config).unwrap()
+		(opts.config.clone(), config::read(&opts.config).map_err(|error| error.to_string()))
+	};
+	let config = match config_data.and_then(|config_data| config::parse_many(&config_data).map_err(|error| error.to_string())) {
+		Ok(config) => config,
+		Err(error) => {
+			eprintln!("{}: {}", source, error);
+			process::exit(1);
+		}
 	};
-	let config = config::parse(config_data.as_str());
 	if opts.verbose { println!("{:?}", config) }
+	if opts.check {
+		println!("{}: ok", source);
+		return;
+	}
+	if opts.dump_zones {
+		print!("{}", config::dump_zones(&config));
+		return;
+	}
+	if !opts.no_startup_checks {
+		if let Err(error) = server::check_upstreams(&opts, &config) {
+			eprintln!("{}", error);
+			process::exit(1);
+		}
+	}
 	
-	server::serve(opts, config);
+	let verbose = opts.verbose;
+	let server = match server::serve(opts, config) {
+		Ok(server) => server,
+		Err(error) => {
+			eprintln!("Failed to start server: {}", error);
+			process::exit(1);
+		}
+	};
+	if verbose {
+		for address in &server.addresses { println!("listening on {}", address); }
+		if let Some(tls_address) = server.tls_address { println!("listening for TLS on {}", tls_address); }
+		if let Some(health_address) = server.health_address { println!("serving health probes on {}", health_address); }
+		if let Some(api_address) = server.api_address { println!("serving the API on {}", api_address); }
+		if let Some(unix_path) = &server.unix_path { println!("listening on {}", unix_path.display()); }
+	}
+	let stats = server.stats();
+	if let Err(error) = server::on_usr1(move || print!("{}", stats.table())) {
+		eprintln!("warning: statistics won't be printed on SIGUSR1: {}", error);
+	}
+	let shutdown = server.shutdown_handle();
+	ctrlc::set_handler(move || shutdown.shutdown()).expect("Failed to set signal handler.");
+	server.join();
 }
@@ -1,28 +1,131 @@
 use std::fs::read_to_string;
-use std::net::{IpAddr, SocketAddr};
+use std::io;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::time::Duration;
 
 use resolv_conf::Config;
 
 use crate::clap::Clap;
+use crate::config::{Cidr, parse_duration};
 
 /// A powerful, developer-friendly, authoritative DNS server.
 #[derive(Clap)]
 #[clap(version = "0.1", author = "Chris Smith")]
 #[derive(Debug, Clone)]
 pub struct Options {
-	/// The address to listen on.
-	#[clap(short = "l", long = "listen", default_value = "0.0.0.0")]
-	pub listen_address: IpAddr,
+	/// An address to listen on for both UDP and TCP, with a port (e.g. `127.0.0.1:53` or
+	/// `[2001:db8::1]:53`) or without one to use `--port`. Can be repeated. An unspecified address,
+	/// either `0.0.0.0` or `::`, listens on both, unless `--ipv4-only` or `--ipv6-only` is given.
+	/// The TLS and health ports are on the IP of the first one. When started by systemd socket
+	/// activation, the sockets it passes are listened on instead.
+	#[clap(short = "l", long = "listen", number_of_values = 1, default_value = "0.0.0.0")]
+	pub listen: Vec<ListenAddress>,
 	
-	/// The port to listen on. This is for both UDP and TCP.
+	/// Only listen on IPv4 for an unspecified `--listen` address.
+	#[clap(long = "ipv4-only", conflicts_with = "ipv6-only")]
+	pub ipv4_only: bool,
+	
+	/// Only listen on IPv6 for an unspecified `--listen` address.
+	#[clap(long = "ipv6-only")]
+	pub ipv6_only: bool,
+	
+	/// The port to listen on, for `--listen` addresses without one.
 	#[clap(short = "p", long = "port", default_value = "53")]
 	pub listen_port: u16,
 	
+	/// Path of a Unix domain socket to also listen on, for local tooling. Messages are framed as they
+	/// are over TCP. The socket file is removed on shutdown.
+	#[clap(long = "listen-unix")]
+	pub listen_unix: Option<String>,
+	
+	/// Permissions to give the `--listen-unix` socket, in octal e.g. 0660. Defaults to the umask.
+	#[clap(long = "unix-mode", parse(try_from_str = parse_mode))]
+	pub unix_mode: Option<u32>,
+	
+	/// User to switch to once every socket is bound, so that port 53 can be bound as root without
+	/// answering queries as root. Their primary group is switched to as well, unless `--group` is
+	/// given.
+	#[clap(long = "user")]
+	pub user: Option<String>,
+	
+	/// Group to switch to once every socket is bound.
+	#[clap(long = "group")]
+	pub group: Option<String>,
+	
+	/// The port to listen on for DNS over TLS, usually 853. Requires `--tls-cert` and `--tls-key`.
+	#[clap(long = "tls-port")]
+	pub tls_port: Option<u16>,
+	
+	/// Path to the PEM encoded certificate chain to use for DNS over TLS.
+	#[clap(long = "tls-cert")]
+	pub tls_cert: Option<String>,
+	
+	/// Path to the PEM encoded private key to use for DNS over TLS.
+	#[clap(long = "tls-key")]
+	pub tls_key: Option<String>,
+	
+	/// Port to serve HTTP liveness and readiness probes on, at `/healthz` and `/readyz`.
+	#[clap(long = "health-port")]
+	pub health_port: Option<u16>,
+	
+	/// Name to look up, as if queried for its A records, to check readiness. `/readyz` fails when
+	/// it doesn't resolve. Without it, readiness only checks the worker threads are answering.
+	#[clap(long = "health-probe")]
+	pub health_probe: Option<String>,
+	
+	/// Port to serve the HTTP API for looking at and changing records on. Requests must have an
+	/// `Authorization: Bearer` header with the token in the `--api-token-env` environment variable.
+	#[clap(long = "api-port")]
+	pub api_port: Option<u16>,
+	
+	/// The IP to serve the HTTP API on. It can change every record, so it's only reachable from this
+	/// machine unless another address is given.
+	#[clap(long = "api-address", default_value = "127.0.0.1")]
+	pub api_address: IpAddr,
+	
+	/// Name of the environment variable holding the API token, which must be set to use
+	/// `--api-port`.
+	#[clap(long = "api-token-env", default_value = "TACODNS_API_TOKEN")]
+	pub api_token_env: String,
+	
+	/// Answered to `version.bind` TXT queries in the CHAOS class. Defaults to the version of
+	/// tacodns.
+	#[clap(long = "version-string")]
+	pub version_string: Option<String>,
+	
+	/// Answered to `hostname.bind` TXT queries in the CHAOS class. Defaults to the host name.
+	#[clap(long = "hostname-string")]
+	pub hostname_string: Option<String>,
+	
 	/// Enable verbose mode.
 	#[clap(long = "verbose")]
 	pub verbose: bool,
 	
-	/// Path to your YAML configuration file.
+	/// Print how queries for names matching this glob (e.g. `*.example.com`) are answered: why each
+	/// zone did or didn't match, which records were used, and what was looked up elsewhere. Can be
+	/// repeated. A query can also ask for its own trace with EDNS option 65001.
+	#[clap(long = "trace-name", number_of_values = 1)]
+	pub trace_name: Vec<String>,
+	
+	/// Print a line for every query answered, with the client address, name, type and response
+	/// code.
+	#[clap(long = "query-log")]
+	pub query_log: bool,
+	
+	/// Format of the query log, either text or json.
+	#[clap(long = "log-format", default_value = "text", possible_values = &["text", "json"])]
+	pub log_format: String,
+	
+	/// Print a line for every query that takes longer than this (e.g. `100ms`) to answer, with how
+	/// long went on parsing it, matching it against the zones, waiting on lookups elsewhere, and
+	/// serializing the response. It's in the format of `--log-format`.
+	#[clap(long = "slow-query-threshold", parse(try_from_str = parse_duration))]
+	pub slow_query_threshold: Option<Duration>,
+	
+	/// Path to your YAML configuration file, or a directory of them, which are read in the order of
+	/// their names, with the zones of each added to those of the first.
 	#[clap(short = "c", long = "config", default_value = "/etc/tacodns.yml")]
 	pub config: String,
 	
@@ -31,23 +134,279 @@ pub struct Options {
 	#[clap(long = "config-env")]
 	pub config_env: Option<String>,
 	
-	/// Number of worker threads. In addition to the number listed here, there are two more threads:
-	/// one blocking waiting for UDP packets and the other blocking waiting for TCP connections.
+	/// Check the configuration for errors and exit, without starting the server.
+	#[clap(long = "check")]
+	pub check: bool,
+	
+	/// Print every zone in the configuration as a standard (RFC 1035) zone file and exit, without
+	/// starting the server.
+	#[clap(long = "dump-zones")]
+	pub dump_zones: bool,
+	
+	/// Don't check at startup that the resolvers and the servers of RNS records can be reached.
+	#[clap(long = "no-startup-checks")]
+	pub no_startup_checks: bool,
+	
+	/// Exit if a resolver or RNS server can't be reached at startup, rather than warning about it.
+	#[clap(long = "strict-startup", conflicts_with = "no-startup-checks")]
+	pub strict_startup: bool,
+	
+	/// Number of worker threads. In addition to the number listed here, there are `--udp-threads`
+	/// blocking waiting for UDP packets and one more blocking waiting for TCP connections. Each open
+	/// TCP connection also gets its own thread.
 	#[clap(long = "threads", default_value = "4")]
 	pub threads: usize,
 	
+	/// Number of threads receiving UDP packets and handing them to the worker threads. Defaults to
+	/// the number of worker threads.
+	#[clap(long = "udp-threads")]
+	pub udp_threads: Option<usize>,
+	
+	/// Seconds an idle TCP connection is kept open waiting for further queries.
+	#[clap(long = "tcp-timeout", default_value = "10")]
+	pub tcp_timeout: u64,
+	
+	/// Seconds a TCP connection has to send each part of a query: its length, then the rest. The
+	/// first query has to start arriving within this too.
+	#[clap(long = "tcp-read-timeout", default_value = "5")]
+	pub tcp_read_timeout: u64,
+	
+	/// Seconds a TCP connection is kept open at most, however busy it is.
+	#[clap(long = "tcp-lifetime", default_value = "120")]
+	pub tcp_lifetime: u64,
+	
+	/// Most TCP connections, including DNS over TLS, open at once. Any more are closed as soon as
+	/// they're accepted.
+	#[clap(long = "max-tcp-conns", default_value = "128")]
+	pub max_tcp_conns: usize,
+	
+	/// Most UDP requests from one IP address waiting for or being answered by the worker threads at
+	/// once. Any more are dropped, so that a client flooding slow queries can't take every worker
+	/// from the others. Only fair with more `--threads` than this.
+	#[clap(long = "max-client-jobs", default_value = "8")]
+	pub max_client_jobs: usize,
+	
+	/// Largest UDP payload we advertise to EDNS clients. The default avoids IP fragmentation on most
+	/// networks: https://dnsflagday.net/2020/
+	#[clap(long = "max-udp-payload", default_value = "1232")]
+	pub max_udp_payload: u16,
+	
+	/// UDP requests per second to answer from each client (or IPv6 /56), with the rest dropped or
+	/// slipped. Unlimited if not given.
+	#[clap(long = "rrl-rate")]
+	pub rrl_rate: Option<u32>,
+	
+	/// UDP requests a client can make at once before `--rrl-rate` applies.
+	#[clap(long = "rrl-burst", default_value = "20")]
+	pub rrl_burst: u32,
+	
+	/// Answer every nth rate limited UDP request with an empty truncated response, so legitimate
+	/// clients retry over TCP, and drop the rest. 0 drops them all.
+	#[clap(long = "rrl-slip", default_value = "2")]
+	pub rrl_slip: u32,
+	
 	/// Server and port to use to lookup records that aren't hosted here. Surround IPv6 addresses in
-	/// square brackets.
-	#[clap(long = "resolver", default_value = read_from_resolv_conf())]
-	pub resolver: SocketAddr,
+	/// square brackets. Give it more than once to fail over to the next server when one doesn't
+	/// respond. Defaults to the nameservers in /etc/resolv.conf, or 1.1.1.1 when there are none.
+	#[clap(long = "resolver", number_of_values = 1)]
+	pub resolver: Vec<SocketAddr>,
+	
+	/// An address or prefix (e.g. `10.0.0.0/8`) of clients that may ask for recursion, which looks
+	/// names outside every zone up with `--resolver` for them. Can be repeated. Once given, the RD
+	/// bit is honored for every client: a query without it is answered without asking anything
+	/// elsewhere, so an ANAME has no addresses and a forwarded zone is SERVFAIL, and one with it
+	/// from anyone else is refused for names outside every zone.
+	#[clap(long = "allow-recursion", number_of_values = 1)]
+	pub allow_recursion: Vec<Cidr>,
+	
+	/// Milliseconds to wait on the resolver, or RNS servers, before answering with SERVFAIL.
+	#[clap(long = "resolver-timeout", default_value = "2000")]
+	pub resolver_timeout: u64,
+	
+	/// Seconds to cache negative answers from the resolver, or RNS servers, when they don't include
+	/// an SOA record to take the negative TTL from.
+	#[clap(long = "resolver-nttl", default_value = "60")]
+	pub resolver_nttl: u64,
+	
+	/// Most responses from the resolver, or RNS servers, to keep cached at once.
+	#[clap(long = "resolver-cache-size", default_value = "10000")]
+	pub resolver_cache_size: usize,
+	
+	/// File to keep the resolver cache in while the server isn't running. It's loaded at startup,
+	/// and saved every five minutes and at shutdown.
+	#[clap(long = "cache-file")]
+	pub cache_file: Option<String>,
+	
+	/// Look up the targets of ANAME and ALIAS records, and the names of forwarded zones, at startup
+	/// and again before their cached answers expire, as `prefetch: true` in the config does.
+	#[clap(long = "prefetch")]
+	pub prefetch: bool,
+	
+	/// Most responses to keep serialized for answering the same query again, when they come from
+	/// zones whose answers don't change between queries. 0 turns this off.
+	#[clap(long = "answer-cache-size", default_value = "10000")]
+	pub answer_cache_size: usize,
+	
+	/// Seconds the worker pool may go without finishing a request while others wait for it, before
+	/// the process aborts so that it can be restarted. 0 turns this off.
+	#[clap(long = "stall-timeout", default_value = "60")]
+	pub stall_timeout: u64,
+}
+
+/// A `--listen` address, which takes the port from `--port` when it doesn't have one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListenAddress {
+	Ip(IpAddr),
+	Socket(SocketAddr),
 }
 
-fn read_from_resolv_conf() -> &'static str {
-	let config = Config::parse(read_to_string("/etc/resolv.conf").unwrap()).unwrap();
-	let nameserver = config.nameservers.get(0).unwrap();
-	return Box::leak(format!("{}:53", nameserver).into_boxed_str());
+impl FromStr for ListenAddress {
+	type Err = AddrParseError;
+	
+	fn from_str(address: &str) -> Result<ListenAddress, AddrParseError> {
+		return match address.parse() {
+			Ok(ip) => Ok(ListenAddress::Ip(ip)),
+			Err(_) => address.parse().map(ListenAddress::Socket),
+		};
+	}
+}
+
+impl Options {
+	/// Every address to listen on for UDP and TCP, where an unspecified one is both `0.0.0.0` and
+	/// `::` unless one of them was ruled out.
+	pub fn listen_addresses(&self) -> Vec<SocketAddr> {
+		let mut addresses = vec![];
+		for address in &self.listen {
+			let address = match address {
+				ListenAddress::Ip(ip) => SocketAddr::new(*ip, self.listen_port),
+				ListenAddress::Socket(address) => *address,
+			};
+			let expanded = if address.ip().is_unspecified() {
+				let mut expanded = vec![];
+				if !self.ipv6_only { expanded.push(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), address.port())); }
+				if !self.ipv4_only { expanded.push(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), address.port())); }
+				expanded
+			} else {
+				vec![address]
+			};
+			for address in expanded {
+				// port 0 is a different port each time it's listened on
+				if address.port() == 0 || !addresses.contains(&address) {
+					addresses.push(address);
+				}
+			}
+		}
+		return addresses;
+	}
+	
+	/// The IP to listen on for everything other than UDP and TCP.
+	pub fn listen_ip(&self) -> IpAddr {
+		return self.listen_addresses().first().map(SocketAddr::ip).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+	}
+}
+
+fn parse_mode(mode: &str) -> Result<u32, ParseIntError> {
+	return u32::from_str_radix(mode, 8);
+}
+
+/// The resolver used when none is given and none can be found in /etc/resolv.conf, e.g. in
+/// containers without one.
+const FALLBACK_RESOLVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53);
+
+/// The nameservers in the contents of /etc/resolv.conf, or why there aren't any.
+fn read_from_resolv_conf(contents: io::Result<String>) -> Result<Vec<SocketAddr>, String> {
+	let contents = contents.map_err(|error| format!("can't read /etc/resolv.conf: {}", error))?;
+	let config = Config::parse(contents).map_err(|error| format!("can't parse /etc/resolv.conf: {}", error))?;
+	let nameservers: Vec<SocketAddr> = config.nameservers.iter()
+		.map(|nameserver| SocketAddr::new(nameserver.into(), 53))
+		.collect();
+	if nameservers.is_empty() {
+		return Err("there are no nameservers in /etc/resolv.conf".to_string());
+	}
+	return Ok(nameservers);
+}
+
+#[cfg(target_os = "linux")]
+fn hostname() -> Option<String> {
+	use std::ffi::CStr;
+	use std::os::raw::c_char;
+	
+	let mut buf = [0 as c_char; 256];
+	if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+		return None;
+	}
+	// the name isn't terminated if it was truncated
+	buf[buf.len() - 1] = 0;
+	return Some(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned());
+}
+
+#[cfg(not(target_os = "linux"))]
+fn hostname() -> Option<String> {
+	return None;
 }
 
 pub fn parse() -> Options {
-	Options::parse()
+	let mut options = Options::parse();
+	if options.version_string.is_none() {
+		options.version_string = Some(format!("tacodns {}", env!("CARGO_PKG_VERSION")));
+	}
+	if options.hostname_string.is_none() {
+		options.hostname_string = hostname();
+	}
+	if options.resolver.is_empty() {
+		options.resolver = match read_from_resolv_conf(read_to_string("/etc/resolv.conf")) {
+			Ok(nameservers) => nameservers,
+			Err(error) => {
+				eprintln!("warning: {}, so {} is used to lookup records that aren't hosted here", error, FALLBACK_RESOLVER);
+				vec![FALLBACK_RESOLVER]
+			}
+		};
+	}
+	return options;
+}
+
+#[cfg(test)]
+mod test {
+	use std::io;
+	use std::net::IpAddr;
+	
+	use crate::clap::Clap;
+	use crate::options::{ListenAddress, Options, read_from_resolv_conf};
+	
+	#[test]
+	fn test_listen_addresses() {
+		let options = Options::try_parse_from(["tacodns", "--port", "5353"]).unwrap();
+		assert_eq!(options.listen_addresses(), vec!["0.0.0.0:5353".parse().unwrap(), "[::]:5353".parse().unwrap()]);
+		let options = Options::try_parse_from(["tacodns", "--port", "5353", "--ipv4-only"]).unwrap();
+		assert_eq!(options.listen_addresses(), vec!["0.0.0.0:5353".parse().unwrap()]);
+		let options = Options::try_parse_from(["tacodns", "--listen", "0.0.0.0", "--listen", "[::]:53", "--ipv6-only"]).unwrap();
+		assert_eq!(options.listen_addresses(), vec!["[::]:53".parse().unwrap()]);
+		assert_eq!(options.listen_ip(), "::".parse::<IpAddr>().unwrap());
+		// both unspecified addresses on the same port are only listened on once
+		let options = Options::try_parse_from(["tacodns", "--listen", "::", "--listen", "0.0.0.0"]).unwrap();
+		assert_eq!(options.listen_addresses(), vec!["0.0.0.0:53".parse().unwrap(), "[::]:53".parse().unwrap()]);
+		assert!(Options::try_parse_from(["tacodns", "--ipv4-only", "--ipv6-only"]).is_err());
+		
+		let options = Options::try_parse_from(["tacodns", "-l", "127.0.0.1:53", "--listen", "[2001:db8::1]:53", "--listen", "::1"]).unwrap();
+		assert_eq!(options.listen, vec![
+			ListenAddress::Socket("127.0.0.1:53".parse().unwrap()),
+			ListenAddress::Socket("[2001:db8::1]:53".parse().unwrap()),
+			ListenAddress::Ip("::1".parse().unwrap()),
+		]);
+		assert_eq!(options.listen_addresses(), vec!["127.0.0.1:53".parse().unwrap(), "[2001:db8::1]:53".parse().unwrap(), "[::1]:53".parse().unwrap()]);
+		assert_eq!(options.listen_ip(), "127.0.0.1".parse::<IpAddr>().unwrap());
+		
+		assert!(Options::try_parse_from(["tacodns", "--listen", "localhost"]).is_err());
+	}
+	
+	#[test]
+	fn test_read_from_resolv_conf() {
+		let contents = "# generated\nsearch example.com\nnameserver 10.0.0.53\nnameserver 2001:db8::53\n".to_string();
+		assert_eq!(read_from_resolv_conf(Ok(contents)), Ok(vec!["10.0.0.53:53".parse().unwrap(), "[2001:db8::53]:53".parse().unwrap()]));
+		
+		assert_eq!(read_from_resolv_conf(Ok("search example.com\n".to_string())), Err("there are no nameservers in /etc/resolv.conf".to_string()));
+		assert_eq!(read_from_resolv_conf(Ok(String::new())), Err("there are no nameservers in /etc/resolv.conf".to_string()));
+		let missing = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+		assert_eq!(read_from_resolv_conf(Err(missing)), Err("can't read /etc/resolv.conf: No such file or directory".to_string()));
+	}
 }
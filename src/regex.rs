@@ -8,7 +8,7 @@ pub struct Regex(regex::Regex);
 
 impl Regex {
 	pub fn new(regex: &str) -> Result<Regex, regex::Error> {
-		regex::Regex::new(regex).map(|regex| Regex(regex))
+		regex::Regex::new(regex).map(Regex)
 	}
 }
 
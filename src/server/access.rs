@@ -0,0 +1,65 @@
+//! Who the request being answered is from, so that the zones only some clients may read are kept
+//! from the others however they're reached: through the question, but also through a CNAME, ANAME
+//! or ALIAS, the glue for a name, or the transfer of a zone above them. Those are zones with
+//! `require-tsig`, which only clients holding the key may read, and zones whose ACL leaves clients
+//! out.
+//!
+//! As with traces, a request is answered start to finish on one worker thread, so the client is
+//! kept in a thread local rather than passed down to every zone that's visited.
+
+use std::cell::RefCell;
+use std::net::IpAddr;
+
+use crate::config::{Config, Zone};
+
+struct Client {
+	address: IpAddr,
+	/// The name of the key the request was signed with, if it was.
+	key: Option<String>,
+	/// Whether a zone that only some clients may read was visited, which the answer may then leave
+	/// out for this client.
+	restricted: bool,
+}
+
+thread_local! {
+	static CLIENT: RefCell<Option<Client>> = const { RefCell::new(None) };
+}
+
+/// Starts answering a request from `address` on this thread, signed with `key` if it was.
+pub fn start(address: IpAddr, key: Option<&str>) {
+	CLIENT.with(|client| *client.borrow_mut() = Some(Client { address, key: key.map(str::to_string), restricted: false }));
+}
+
+/// Whether the client being answered may read a zone. Everything may be read when no request is
+/// being answered, as when the config is resolved by `resolve`.
+pub fn allows(config: &Config, zone: &Zone) -> bool {
+	return CLIENT.with(|client| match client.borrow_mut().as_mut() {
+		Some(client) => {
+			let tsig = zone.records.require_tsig.as_ref().map(|key| client.key.as_ref() == Some(key));
+			let acl = acl_allows(config, zone, client.address);
+			client.restricted |= tsig.is_some() || acl.is_some();
+			tsig.unwrap_or(true) && acl.unwrap_or(true)
+		}
+		None => true,
+	});
+}
+
+/// Whether a zone's ACL allows a client, or `None` if it allows everyone. It applies to zones that
+/// look records up elsewhere, so that clients left out of it can't have us do so on their behalf,
+/// and to the rest as well without `acl-local`.
+fn acl_allows(config: &Config, zone: &Zone, client: IpAddr) -> Option<bool> {
+	let records = &zone.records;
+	let upstream = !records.aname.is_empty() || !records.alias.is_empty() || !records.rns.is_empty() || !records.trpp.is_empty() || records.forward.is_some();
+	if !upstream && config.acl_local { return None; }
+	if records.deny.is_empty() && records.allow.is_empty() && config.acl.is_empty() { return None; }
+	
+	if records.deny.iter().any(|cidr| cidr.contains(client)) { return Some(false); }
+	if !records.allow.is_empty() { return Some(records.allow.iter().any(|cidr| cidr.contains(client))); }
+	return Some(config.acl.is_empty() || config.acl.iter().any(|cidr| cidr.contains(client)));
+}
+
+/// Whether a zone that only some clients may read was visited while answering the request, so that
+/// the answer may differ from one client to the next.
+pub fn visited_restricted() -> bool {
+	return CLIENT.with(|client| client.borrow().as_ref().map(|client| client.restricted).unwrap_or(false));
+}
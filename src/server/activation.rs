@@ -0,0 +1,161 @@
+//! Systemd socket activation (https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html),
+//! where systemd binds the sockets, to port 53 say, and passes them in, so that the server never has
+//! to run as root. They're listened on in place of the `--listen` addresses.
+
+#[cfg(target_os = "linux")]
+pub use linux::listen_fds;
+#[cfg(not(target_os = "linux"))]
+pub use other::listen_fds;
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+	use std::io;
+	use std::net::{TcpListener, UdpSocket};
+	
+	/// Systemd is only on Linux, so nothing is ever passed in.
+	pub fn listen_fds() -> io::Result<Option<Vec<(UdpSocket, TcpListener)>>> {
+		Ok(None)
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::env;
+	use std::io;
+	use std::mem;
+	use std::net::{TcpListener, UdpSocket};
+	use std::os::unix::io::{FromRawFd, RawFd};
+	use std::process;
+	
+	use libc::{c_int, c_void};
+	
+	use crate::server::pktinfo;
+	
+	/// The first descriptor passed, after stdin, stdout, and stderr.
+	const LISTEN_FDS_START: RawFd = 3;
+	
+	/// The UDP sockets and TCP listeners passed in by systemd, paired by the address they're bound
+	/// to, or `None` if nothing was passed. The variables passing them are removed, so that they
+	/// aren't passed on to anything started from here.
+	pub fn listen_fds() -> io::Result<Option<Vec<(UdpSocket, TcpListener)>>> {
+		let listen_pid = env::var("LISTEN_PID").ok();
+		let listen_fds = env::var("LISTEN_FDS").ok();
+		env::remove_var("LISTEN_PID");
+		env::remove_var("LISTEN_FDS");
+		env::remove_var("LISTEN_FDNAMES");
+		adopt(listen_pid.as_deref(), listen_fds.as_deref(), process::id(), LISTEN_FDS_START)
+	}
+	
+	fn invalid(message: String) -> io::Error {
+		io::Error::new(io::ErrorKind::InvalidInput, message)
+	}
+	
+	/// Takes ownership of the `count` descriptors from `first_fd` that `LISTEN_FDS` says were passed,
+	/// unless `LISTEN_PID` says they were meant for another process.
+	fn adopt(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32, first_fd: RawFd) -> io::Result<Option<Vec<(UdpSocket, TcpListener)>>> {
+		let count = match listen_fds {
+			Some(count) => count.parse::<RawFd>().map_err(|_| invalid(format!("LISTEN_FDS is '{}', not a number", count)))?,
+			None => return Ok(None),
+		};
+		if listen_pid.map(|listen_pid| listen_pid.parse() != Ok(pid)).unwrap_or(false) || count <= 0 {
+			return Ok(None);
+		}
+		
+		let mut udp_sockets = vec![];
+		let mut tcp_listeners = vec![];
+		for fd in first_fd..first_fd + count {
+			// set by sd_listen_fds, so that the descriptors aren't leaked into child processes
+			if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+				return Err(io::Error::last_os_error());
+			}
+			match socket_type(fd)? {
+				libc::SOCK_DGRAM => udp_sockets.push(unsafe { UdpSocket::from_raw_fd(fd) }),
+				libc::SOCK_STREAM => tcp_listeners.push(unsafe { TcpListener::from_raw_fd(fd) }),
+				_ => return Err(invalid(format!("passed descriptor {} is neither a datagram nor a stream socket", fd))),
+			}
+		}
+		
+		let mut listeners = vec![];
+		for udp_socket in udp_sockets {
+			let address = udp_socket.local_addr()?;
+			let tcp_listener = match tcp_listeners.iter().position(|tcp_listener| tcp_listener.local_addr().ok() == Some(address)) {
+				Some(index) => tcp_listeners.remove(index),
+				None => return Err(invalid(format!("passed a UDP socket on {} without a TCP one", address))),
+			};
+			if address.ip().is_unspecified() {
+				pktinfo::enable(&udp_socket)?;
+			}
+			listeners.push((udp_socket, tcp_listener));
+		}
+		if let Some(tcp_listener) = tcp_listeners.first() {
+			return Err(invalid(format!("passed a TCP socket on {} without a UDP one", tcp_listener.local_addr()?)));
+		}
+		Ok(Some(listeners))
+	}
+	
+	fn socket_type(fd: RawFd) -> io::Result<c_int> {
+		let mut kind: c_int = 0;
+		let mut len = mem::size_of::<c_int>() as libc::socklen_t;
+		let result = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, &mut kind as *mut c_int as *mut c_void, &mut len) };
+		if result != 0 {
+			let error = io::Error::last_os_error();
+			return Err(io::Error::new(error.kind(), format!("passed descriptor {} isn't a socket: {}", fd, error)));
+		}
+		Ok(kind)
+	}
+	
+	#[cfg(test)]
+	mod test {
+		use std::net::{SocketAddr, TcpListener, UdpSocket};
+		use std::os::unix::io::{AsRawFd, RawFd};
+		
+		use crate::server::activation::linux::adopt;
+		
+		/// Copies the sockets to consecutive descriptors from `first_fd`, as systemd passes them,
+		/// high enough not to clash with anything else a test has open.
+		fn pass(first_fd: RawFd, sockets: &[&dyn AsRawFd]) {
+			for (index, socket) in sockets.iter().enumerate() {
+				assert_eq!(unsafe { libc::dup2(socket.as_raw_fd(), first_fd + index as RawFd) }, first_fd + index as RawFd);
+			}
+		}
+		
+		#[test]
+		fn test_adopt() {
+			let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+			let address = udp.local_addr().unwrap();
+			let tcp = TcpListener::bind(address).unwrap();
+			// in whichever order they're passed
+			pass(900, &[&tcp, &udp]);
+			let pid = std::process::id();
+			let listeners = adopt(Some(&pid.to_string()), Some("2"), pid, 900).unwrap().unwrap();
+			assert_eq!(listeners.len(), 1);
+			assert_eq!(listeners[0].0.local_addr().unwrap(), address);
+			assert_eq!(listeners[0].1.local_addr().unwrap(), address);
+			
+			// the adopted sockets are the ones that were passed
+			let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+			client.send_to(b"ping", address).unwrap();
+			let mut buf = [0; 4];
+			assert_eq!(listeners[0].0.recv_from(&mut buf).unwrap(), (4, client.local_addr().unwrap()));
+		}
+		
+		#[test]
+		fn test_not_passed() {
+			assert!(adopt(None, None, 1, 910).unwrap().is_none());
+			// meant for the process that started this one
+			assert!(adopt(Some("2"), Some("2"), 1, 910).unwrap().is_none());
+			assert!(adopt(None, Some("0"), 1, 910).unwrap().is_none());
+			assert_eq!(adopt(None, Some("two"), 1, 910).unwrap_err().to_string(), "LISTEN_FDS is 'two', not a number");
+		}
+		
+		#[test]
+		fn test_unpaired() {
+			let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+			let other: SocketAddr = "127.0.0.1:0".parse().unwrap();
+			let tcp = TcpListener::bind(other).unwrap();
+			pass(920, &[&udp, &tcp]);
+			let error = adopt(None, Some("2"), 1, 920).unwrap_err().to_string();
+			assert_eq!(error, format!("passed a UDP socket on {} without a TCP one", udp.local_addr().unwrap()));
+		}
+	}
+}
@@ -0,0 +1,202 @@
+//! Serialized responses to queries answered entirely from zones whose answers don't change between
+//! queries, so that answering the same query again skips looking it up and serializing it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+
+use crate::server::{protocol, Transport};
+use crate::server::protocol::{Edns, Message, opcode};
+use crate::server::trace::TRACE_OPTION;
+
+/// How many parts the cache is split into, each behind its own lock, so that workers answering
+/// different queries don't wait on each other.
+const SHARDS: usize = 16;
+
+/// Everything in a query that changes the response to it, apart from its ID, its RD bit, and the
+/// case of the name asked about, which are patched in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct Key {
+	/// In lowercase.
+	qname: Vec<String>,
+	qtype: u16,
+	qclass: u16,
+	/// Whether the query came with EDNS, which the response then does too, and its DO bit.
+	edns: Option<bool>,
+	/// The size the response is limited to, which is only limited over UDP.
+	max_size: u16,
+	udp: bool,
+}
+
+impl Key {
+	/// The key of a parsed request, or `None` if it's never answered from the cache: anything but a
+	/// single question, a signed request, whose response is only for it, one asking for a trace,
+	/// which is always answered afresh, or an EDNS version we don't support.
+	pub(super) fn new(request: &Message, max_size: u16, transport: Transport) -> Option<Key> {
+		if request.header.qr || request.header.opcode != opcode::QUERY || request.tsig.is_some() { return None; }
+		if let Some(edns) = &request.edns {
+			if edns.version() != 0 || edns.options.iter().any(|option| option.code == TRACE_OPTION) { return None; }
+		}
+		let question = match request.question.as_slice() {
+			[question] => question,
+			_ => return None,
+		};
+		return Some(Key {
+			qname: question.qname.iter().filter(|label| !label.is_empty()).map(|label| label.to_ascii_lowercase()).collect(),
+			qtype: question.qtype,
+			qclass: question.qclass,
+			edns: request.edns.as_ref().map(Edns::dnssec_ok),
+			max_size,
+			udp: transport == Transport::Udp,
+		});
+	}
+}
+
+#[derive(Debug)]
+struct Entry {
+	response: Vec<u8>,
+	/// Kept for the query log, and to serialize again for a name asked in a different case.
+	message: Arc<Message>,
+	/// The zone the query is counted towards in the statistics, if any.
+	zone: Option<Arc<str>>,
+	used: u64,
+}
+
+/// A cached response, with the message it was serialized from and the zone it was answered from.
+type Cached = (Vec<u8>, Arc<Message>, Option<Arc<str>>);
+
+/// The answers from one config, shared by every thread answering from it, and replaced with an
+/// empty cache whenever it changes, so that none from an older config, which were still being
+/// worked out when it did, are mixed in.
+#[derive(Debug, Clone)]
+pub struct AnswerCache(Arc<Shards>);
+
+#[derive(Debug)]
+struct Shards {
+	hasher: RandomState,
+	shards: Vec<Mutex<Answers>>,
+}
+
+/// The least recently used answers are dropped first.
+#[derive(Debug, Default)]
+struct Answers {
+	entries: HashMap<Key, Entry>,
+	/// The key of every entry by when it was last used.
+	recency: BTreeMap<u64, Key>,
+	clock: u64,
+}
+
+impl Answers {
+	fn get(&mut self, key: &Key) -> Option<Cached> {
+		let entry = self.entries.get_mut(key)?;
+		let key = self.recency.remove(&entry.used).unwrap();
+		self.clock += 1;
+		entry.used = self.clock;
+		self.recency.insert(entry.used, key);
+		return Some((entry.response.clone(), entry.message.clone(), entry.zone.clone()));
+	}
+	
+	fn insert(&mut self, key: Key, response: Vec<u8>, message: Message, zone: Option<Arc<str>>, max_entries: usize) {
+		if max_entries == 0 { return; }
+		if let Some(entry) = self.entries.remove(&key) {
+			self.recency.remove(&entry.used);
+		}
+		while self.entries.len() >= max_entries {
+			let (_, oldest) = self.recency.pop_first().unwrap();
+			self.entries.remove(&oldest);
+		}
+		self.clock += 1;
+		self.recency.insert(self.clock, key.clone());
+		self.entries.insert(key, Entry { response, message: Arc::new(message), zone, used: self.clock });
+	}
+}
+
+impl Default for AnswerCache {
+	fn default() -> AnswerCache {
+		return AnswerCache(Arc::new(Shards {
+			hasher: RandomState::new(),
+			shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+		}));
+	}
+}
+
+impl AnswerCache {
+	fn shard(&self, key: &Key) -> &Mutex<Answers> {
+		return &self.0.shards[self.0.hasher.hash_one(key) as usize % SHARDS];
+	}
+	
+	/// The cached response to a parsed request with the given key, with the request's ID, RD bit and
+	/// name, along with the message it was serialized from and the zone it was answered from.
+	pub(super) fn get(&self, key: &Key, request: &Message) -> Option<Cached> {
+		let (mut response, message, zone) = self.shard(key).lock().unwrap_or_else(|error| error.into_inner()).get(key)?;
+		let qname = &request.question[0].qname;
+		let cached_qname = &message.question[0].qname;
+		if qname != cached_qname {
+			// the records owned by the name asked about are named as it was asked too
+			let mut renamed = Message::clone(&message);
+			renamed.question[0].qname = qname.clone();
+			for record in renamed.answer.iter_mut().chain(&mut renamed.authority).chain(&mut renamed.additional) {
+				if record.rname == *cached_qname {
+					record.rname = qname.clone();
+				}
+			}
+			response = protocol::serialize(&renamed, key.max_size);
+		}
+		response[..2].copy_from_slice(&request.header.id.to_be_bytes());
+		response[2] = (response[2] & !1) | request.header.rd as u8;
+		return Some((response, message, zone));
+	}
+	
+	/// Caches the raw response to a request with the given key, keeping at most about `max_entries`.
+	pub(super) fn insert(&self, key: Key, response: Vec<u8>, message: Message, zone: Option<String>, max_entries: usize) {
+		let max_entries = max_entries.div_ceil(SHARDS);
+		self.shard(&key).lock().unwrap_or_else(|error| error.into_inner()).insert(key, response, message, zone.map(Arc::from), max_entries);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::server::answers::{Answers, Key};
+	use crate::server::protocol::{self, class, Edns, EdnsOption, Message, Question, record_type, Resource};
+	use crate::server::trace::TRACE_OPTION;
+	use crate::server::Transport;
+	
+	#[test]
+	fn test_least_recently_used() {
+		let mut answers = Answers::default();
+		let key = |name: &str| Key { qname: vec![name.to_string()], qtype: 1, qclass: 1, edns: None, max_size: 512, udp: true };
+		let message = || Message::default();
+		answers.insert(key("a"), vec![1], message(), None, 2);
+		answers.insert(key("b"), vec![2], message(), None, 2);
+		assert_eq!(answers.get(&key("a")).unwrap().0, vec![1]);
+		// b is dropped, as a was used since
+		answers.insert(key("c"), vec![3], message(), None, 2);
+		assert!(answers.get(&key("b")).is_none());
+		assert_eq!(answers.get(&key("a")).unwrap().0, vec![1]);
+		assert_eq!(answers.get(&key("c")).unwrap().0, vec![3]);
+	}
+	
+	#[test]
+	fn test_key() {
+		let request = |qname: &str| protocol::make_message_from_question(vec![Question {
+			qname: qname.split('.').map(str::to_string).collect(),
+			qtype: record_type::A,
+			qclass: class::IN,
+		}]);
+		let key = |request: &Message| Key::new(request, 512, Transport::Udp);
+		
+		// the case of the name doesn't matter
+		assert!(key(&request("example.com")).is_some());
+		assert_eq!(key(&request("example.com")), key(&request("Example.COM")));
+		assert_ne!(key(&request("example.com")), Key::new(&request("example.com"), u16::MAX, Transport::Tcp));
+		
+		// while signed requests, and those asking for a trace, are never answered from the cache
+		let mut signed = request("example.com");
+		signed.tsig = Some(Resource { rname: vec!["key".to_string()], rtype: record_type::TSIG, rclass: class::ANY, ttl: 0, rdata: vec![] });
+		assert_eq!(key(&signed), None);
+		let mut traced = request("example.com");
+		traced.edns = Some(Edns { udp_payload_size: 512, extended_rcode_and_flags: 0, options: vec![EdnsOption { code: TRACE_OPTION, data: vec![] }] });
+		assert_eq!(key(&traced), None);
+	}
+}
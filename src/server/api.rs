@@ -0,0 +1,710 @@
+//! An HTTP API for looking at and changing the records being served, without editing the config and
+//! restarting. Every request needs an `Authorization: Bearer` header with the API token.
+//!
+//!   - `GET /zones` lists every zone along with its records.
+//!   - `GET /zones/{zone}/records` lists the records of a zone.
+//!   - `PUT /zones/{zone}/records/{type}` adds records to a zone, adding the zone after the others
+//!     if there isn't one by that name. The body is what the type would be given in the config, as
+//!     JSON, e.g. `["10.0.0.1", "10.0.0.2 5m"]`.
+//!   - `DELETE /zones/{zone}/records/{type}` removes every record of a type from a zone, and
+//!     `DELETE /zones/{zone}/records/{type}/{index}` the one at that index of the list.
+//!   - `GET /stats` returns the query counts, as at `/stats` on the health port, and `DELETE /stats`
+//!     returns them and starts counting again from zero.
+//!
+//! Zones are named as in the config (e.g. `example.com,*.example.com`), percent-encoded. Changes
+//! are also written to the config file with `?persist=true`. Every change bumps the serial and sends
+//! a NOTIFY to the secondaries of the zone, if it has a plain name.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+use yaml_rust::yaml;
+
+use crate::config::{self, Check, Config, Metadata, Records, RnsHost, SvcbRecord, Zone, ZoneMatcher};
+use crate::config::matching::plain_name;
+use crate::options::Options;
+use crate::server::notify;
+use crate::server::shared::SharedConfig;
+
+/// Longest request line or header line read before giving up on the request.
+const MAX_LINE_LENGTH: u64 = 1024;
+
+/// Most header lines read before giving up on the request.
+const MAX_HEADERS: usize = 64;
+
+/// Longest a client has to send the whole request, as requests are answered one at a time, and a
+/// client trickling it in would otherwise hold up the rest however short the timeout of each read.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest request body read, which is plenty for a list of records.
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+struct Request {
+	method: String,
+	path: String,
+	query: String,
+	authorization: Option<String>,
+	body: Vec<u8>,
+}
+
+/// An unsuccessful response: its status, and the message to give in the body.
+#[derive(Debug)]
+struct Error(&'static str, String);
+
+fn bad_request<S: ToString>(message: S) -> Error {
+	return Error("400 Bad Request", message.to_string());
+}
+
+fn not_found<S: ToString>(message: S) -> Error {
+	return Error("404 Not Found", message.to_string());
+}
+
+fn server_error<S: ToString>(message: S) -> Error {
+	return Error("500 Internal Server Error", message.to_string());
+}
+
+/// Answers a single HTTP request on the stream, then closes it. `config_path` is the file changes
+/// are persisted to, if the config was read from one.
+pub fn handle_connection<S: Read + Write>(mut stream: S, options: &Options, config: &SharedConfig, token: &str, config_path: Option<&str>) -> io::Result<()> {
+	let response = read_request(&mut stream, Instant::now() + REQUEST_TIMEOUT)?.and_then(|request| handle_request(request, options, config, token, config_path));
+	let (status, body) = match response {
+		Ok(body) => ("200 OK", body),
+		Err(Error(status, message)) => (status, json!({ "error": message })),
+	};
+	let body = format!("{}\n", body);
+	write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)?;
+	return stream.flush();
+}
+
+/// Reads from a stream until a deadline, after which reads fail.
+struct Deadline<S> {
+	stream: S,
+	deadline: Instant,
+}
+
+impl<S: Read> Read for Deadline<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if Instant::now() >= self.deadline {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "the request took too long to send"));
+		}
+		return self.stream.read(buf);
+	}
+}
+
+fn read_request<S: Read>(stream: &mut S, deadline: Instant) -> io::Result<Result<Request, Error>> {
+	let mut reader = BufReader::new(Deadline { stream, deadline });
+	let mut request_line = String::new();
+	(&mut reader).take(MAX_LINE_LENGTH).read_line(&mut request_line)?;
+	
+	let mut content_length = 0;
+	let mut authorization = None;
+	for headers in 0.. {
+		let mut line = String::new();
+		if (&mut reader).take(MAX_LINE_LENGTH).read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+			break;
+		}
+		if headers == MAX_HEADERS {
+			return Ok(Err(Error("431 Request Header Fields Too Large", format!("there can be at most {} header lines", MAX_HEADERS))));
+		}
+		if let Some((name, value)) = line.split_once(':') {
+			match name.trim().to_lowercase().as_str() {
+				"content-length" => content_length = value.trim().parse().unwrap_or(usize::MAX),
+				"authorization" => authorization = Some(value.trim().to_string()),
+				_ => {}
+			}
+		}
+	}
+	if content_length > MAX_BODY_LENGTH {
+		return Ok(Err(Error("413 Payload Too Large", format!("the body can be at most {} bytes", MAX_BODY_LENGTH))));
+	}
+	let mut body = vec![0; content_length];
+	reader.read_exact(&mut body)?;
+	
+	let mut parts = request_line.split_whitespace();
+	let (method, target) = match (parts.next(), parts.next()) {
+		(Some(method), Some(target)) => (method, target),
+		_ => return Ok(Err(bad_request("bad request"))),
+	};
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	return Ok(Ok(Request {
+		method: method.to_string(),
+		path: path.to_string(),
+		query: query.to_string(),
+		authorization,
+		body,
+	}));
+}
+
+fn handle_request(request: Request, options: &Options, config: &SharedConfig, token: &str, config_path: Option<&str>) -> Result<Value, Error> {
+	let authorized = request.authorization.as_deref()
+		.and_then(|authorization| authorization.strip_prefix("Bearer "))
+		.map(|given| is_same_token(given.as_bytes(), token.as_bytes()))
+		.unwrap_or(false);
+	if !authorized {
+		return Err(Error("401 Unauthorized", "missing or wrong bearer token".to_string()));
+	}
+	
+	let segments: Vec<String> = request.path.trim_start_matches('/').split('/')
+		.map(percent_decode)
+		.collect::<Option<_>>()
+		.ok_or_else(|| bad_request("invalid percent-encoding in the path"))?;
+	let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+	let config_path = if request.query.split('&').any(|parameter| parameter == "persist=true") {
+		Some(config_path.ok_or_else(|| bad_request("the config isn't from a single file, so changes can't be persisted"))?)
+	} else {
+		None
+	};
+	
+	return match (request.method.as_str(), &segments[..]) {
+		("GET", ["zones"]) => {
+			let config = config.get();
+			Ok(json!({
+				"ttl": config.ttl.as_secs(),
+				"nttl": config.nttl.as_secs(),
+				"serial": config.serial,
+				"zones": config.zones.iter().map(|zone| json!({
+					"name": zone.name(),
+					"records": records_json(&zone.records),
+				})).collect::<Vec<Value>>(),
+			}))
+		}
+		("GET", ["zones", name, "records"]) => {
+			let (matchers, excludes) = config::parse_zone_name(name).map_err(bad_request)?;
+			let config = config.get();
+			let zone = config.zones.iter().find(|zone| zone.matchers == matchers && zone.excludes == excludes).ok_or_else(|| not_found(format!("no zone '{}'", name)))?;
+			Ok(records_json(&zone.records))
+		}
+		("PUT", ["zones", name, "records", rtype]) => {
+			let (matchers, excludes) = config::parse_zone_name(name).map_err(bad_request)?;
+			check_record_type(rtype)?;
+			let value: Value = serde_json::from_slice(&request.body).map_err(|error| bad_request(format!("invalid JSON: {}", error)))?;
+			let value = to_yaml(&value);
+			
+			// nothing changes if any of the records are invalid, as they're added to a copy
+			let (json, config) = config.change(|config| {
+				let index = config.zones.iter().position(|zone| zone.matchers == matchers && zone.excludes == excludes);
+				let mut records = index.map(|index| config.zones[index].records.clone()).unwrap_or_default();
+				// records are written under the type's key in the zone, so they take its TTL
+				let ttl = records.default_ttl(rtype, config.ttl);
+				// `none` would hide the addresses of its type, so the two are refused together
+				config::parse_records(name, rtype, &value, ttl, &mut records).map_err(|error| match records.none_conflict() {
+					Some(_) => Error("409 Conflict", error.to_string()),
+					None => bad_request(error),
+				})?;
+				if let Some(config_path) = config_path {
+					persist(config_path, &matchers, &excludes, name, |zone| {
+						let key = type_key(zone, rtype).unwrap_or_else(|| Yaml::String(rtype.to_string()));
+						let mut entries = zone.remove(&key).map(arrayify).unwrap_or_default();
+						entries.append(&mut arrayify(value));
+						zone.insert(key, Yaml::Array(entries));
+					})?;
+				}
+				
+				let json = records_json(&records);
+				match index {
+					Some(index) => config.zones[index].records = records,
+					None => config.zones.push(Zone { matchers: matchers.clone(), excludes, records }),
+				}
+				Ok(json)
+			})?;
+			changed(options, &config, &matchers);
+			Ok(json)
+		}
+		("DELETE", ["zones", name, "records", rtype]) | ("DELETE", ["zones", name, "records", rtype, _]) => {
+			let (matchers, excludes) = config::parse_zone_name(name).map_err(bad_request)?;
+			check_record_type(rtype)?;
+			let entry = match segments.get(4) {
+				Some(entry) => Some(entry.parse::<usize>().map_err(|_| bad_request(format!("'{}' isn't an index", entry)))?),
+				None => None,
+			};
+			
+			let (json, config) = config.change(|config| {
+				let zone = config.zones.iter_mut().find(|zone| zone.matchers == matchers && zone.excludes == excludes).ok_or_else(|| not_found(format!("no zone '{}'", name)))?;
+				if !remove_records(&mut zone.records, rtype, entry)? {
+					return Err(not_found(format!("no {} record to remove", rtype)));
+				}
+				if let Some(config_path) = config_path {
+					persist(config_path, &matchers, &excludes, name, |zone| {
+						let key = match type_key(zone, rtype) {
+							Some(key) => key,
+							None => return,
+						};
+						let mut entries = zone.remove(&key).map(arrayify).unwrap_or_default();
+						match entry {
+							Some(entry) if entry < entries.len() => { entries.remove(entry); }
+							_ => entries.clear(),
+						}
+						if !entries.is_empty() {
+							zone.insert(key, Yaml::Array(entries));
+						}
+					})?;
+				}
+				Ok(records_json(&zone.records))
+			})?;
+			changed(options, &config, &matchers);
+			Ok(json)
+		}
+		("GET", ["stats"]) => Ok(config.stats().json()),
+		("DELETE", ["stats"]) => {
+			let stats = config.stats();
+			let json = stats.json();
+			stats.reset();
+			Ok(json)
+		}
+		_ => Err(not_found("not found")),
+	};
+}
+
+/// Notifies the secondaries of the zone with the matchers after a change to it.
+fn changed(options: &Options, config: &Config, matchers: &[ZoneMatcher]) {
+	for name in matchers.iter().filter_map(plain_name) {
+		notify::notify(options, config, &name);
+	}
+}
+
+/// Only plain record types are taken in paths, so that the TTL of every record is in the body.
+fn check_record_type(rtype: &str) -> Result<(), Error> {
+	if rtype.is_empty() || !rtype.chars().all(|char| char.is_ascii_uppercase()) {
+		return Err(bad_request(format!("'{}' isn't a record type", rtype)));
+	}
+	return Ok(());
+}
+
+/// Removes the record at an index of a type's list, or all of them if there's no index. Returns
+/// whether anything was removed.
+fn remove_records(records: &mut Records, rtype: &str, index: Option<usize>) -> Result<bool, Error> {
+	fn remove<T>(entries: &mut Vec<T>, index: Option<usize>) -> bool {
+		return match index {
+			Some(index) if index < entries.len() => {
+				entries.remove(index);
+				true
+			}
+			Some(_) => false,
+			None => entries.drain(..).count() > 0,
+		};
+	}
+	return Ok(match rtype {
+		"A" => remove(&mut records.a, index),
+		"AAAA" => remove(&mut records.aaaa, index),
+		"NS" => remove(&mut records.ns, index),
+		"CNAME" => remove(&mut records.cname, index),
+		"ANAME" => remove(&mut records.aname, index),
+		"ALIAS" => remove(&mut records.alias, index),
+		"MX" => remove(&mut records.mx, index),
+		"TXT" => remove(&mut records.txt, index),
+		"SOA" => remove(&mut records.soa, index),
+		"SRV" => remove(&mut records.srv, index),
+		"NAPTR" => remove(&mut records.naptr, index),
+		"HINFO" => remove(&mut records.hinfo, index),
+		"LOC" => remove(&mut records.loc, index),
+		"SSHFP" => remove(&mut records.sshfp, index),
+		"SVCB" => remove(&mut records.svcb, index),
+		"HTTPS" => remove(&mut records.https, index),
+		"PTR" => remove(&mut records.ptr, index),
+		"RNS" => remove(&mut records.rns, index),
+		"TRPP" => remove(&mut records.trpp, index),
+		_ => return Err(bad_request(format!("unknown record type '{}'", rtype))),
+	});
+}
+
+/// The records of a zone, by type, with TTLs in seconds. Types without records are left out, as
+/// are the comments and labels of records without them.
+fn records_json(records: &Records) -> Value {
+	let mut json = Map::new();
+	let mut add = |rtype: &str, entries: Vec<(Value, &Option<Metadata>)>| {
+		if entries.is_empty() {
+			return;
+		}
+		let entries = entries.into_iter().map(|(mut entry, metadata)| {
+			if let Some(metadata) = metadata {
+				if let Some(comment) = &metadata.comment {
+					entry["comment"] = json!(comment);
+				}
+				if !metadata.labels.is_empty() {
+					entry["labels"] = json!(metadata.labels);
+				}
+			}
+			entry
+		}).collect();
+		json.insert(rtype.to_string(), Value::Array(entries));
+	};
+	// weights and checks are only given for the records that have them
+	let address = |ttl: Duration, address: String, weight: Option<u32>, check: &Option<Check>| {
+		let mut json = json!({ "ttl": ttl.as_secs(), "address": address });
+		if let Some(weight) = weight {
+			json["weight"] = json!(weight);
+		}
+		if let Some(check) = check {
+			json["check"] = json!(check.to_string());
+		}
+		json
+	};
+	add("A", records.a.iter().map(|a| (address(a.ttl, a.ip4addr.to_string(), a.weight, &a.check), &a.metadata)).collect());
+	add("AAAA", records.aaaa.iter().map(|aaaa| (address(aaaa.ttl, aaaa.ip6addr.to_string(), aaaa.weight, &aaaa.check), &aaaa.metadata)).collect());
+	add("NS", records.ns.iter().map(|ns| (json!({ "ttl": ns.ttl.as_secs(), "name": ns.name }), &ns.metadata)).collect());
+	add("CNAME", records.cname.iter().map(|cname| (json!({ "ttl": cname.ttl.as_secs(), "name": cname.name }), &cname.metadata)).collect());
+	add("ANAME", records.aname.iter().map(|aname| (json!({
+		"ttl": aname.ttl.as_secs(),
+		"name": aname.name,
+		"min-ttl": aname.min_ttl.map(|ttl| ttl.as_secs()),
+		"max-ttl": aname.max_ttl.map(|ttl| ttl.as_secs()),
+	}), &aname.metadata)).collect());
+	add("ALIAS", records.alias.iter().map(|alias| (json!({ "ttl": alias.ttl.as_secs(), "name": alias.name }), &alias.metadata)).collect());
+	add("MX", records.mx.iter().map(|mx| (json!({ "ttl": mx.ttl.as_secs(), "priority": mx.priority, "host": mx.host }), &mx.metadata)).collect());
+	add("TXT", records.txt.iter().map(|txt| (json!({ "ttl": txt.ttl.as_secs(), "data": txt.data }), &txt.metadata)).collect());
+	add("SOA", records.soa.iter().map(|soa| (json!({
+		"ttl": soa.ttl.as_secs(),
+		"mname": soa.mname,
+		"rname": soa.rname,
+		"serial": soa.serial,
+		"refresh": soa.refresh.as_secs(),
+		"retry": soa.retry.as_secs(),
+		"expire": soa.expire.as_secs(),
+		"minimum": soa.minimum.map(|minimum| minimum.as_secs()),
+	}), &soa.metadata)).collect());
+	add("SRV", records.srv.iter().map(|srv| (json!({
+		"ttl": srv.ttl.as_secs(),
+		"priority": srv.priority,
+		"weight": srv.weight,
+		"port": srv.port,
+		"target": srv.target,
+	}), &srv.metadata)).collect());
+	add("NAPTR", records.naptr.iter().map(|naptr| (json!({
+		"ttl": naptr.ttl.as_secs(),
+		"order": naptr.order,
+		"preference": naptr.preference,
+		"flags": naptr.flags,
+		"service": naptr.service,
+		"regexp": naptr.regexp,
+		"replacement": naptr.replacement,
+	}), &naptr.metadata)).collect());
+	add("HINFO", records.hinfo.iter().map(|hinfo| (json!({ "ttl": hinfo.ttl.as_secs(), "cpu": hinfo.cpu, "os": hinfo.os }), &hinfo.metadata)).collect());
+	add("LOC", records.loc.iter().map(|loc| (json!({ "ttl": loc.ttl.as_secs(), "location": loc.position.to_string() }), &loc.metadata)).collect());
+	add("SSHFP", records.sshfp.iter().map(|sshfp| (json!({
+		"ttl": sshfp.ttl.as_secs(),
+		"algorithm": sshfp.algorithm,
+		"type": sshfp.fingerprint_type,
+		"fingerprint": sshfp.fingerprint.iter().map(|byte| format!("{:02x}", byte)).collect::<String>(),
+	}), &sshfp.metadata)).collect());
+	// as with addresses, only the params a record has are given
+	let svcb = |svcb: &SvcbRecord| {
+		let params = &svcb.params;
+		let mut json = json!({ "ttl": svcb.ttl.as_secs(), "priority": svcb.priority, "target": svcb.target });
+		if !params.mandatory.is_empty() {
+			json["mandatory"] = json!(params.mandatory);
+		}
+		if !params.alpn.is_empty() {
+			json["alpn"] = json!(params.alpn);
+		}
+		if params.no_default_alpn {
+			json["no-default-alpn"] = json!(true);
+		}
+		if let Some(port) = params.port {
+			json["port"] = json!(port);
+		}
+		if !params.ipv4hint.is_empty() {
+			json["ipv4hint"] = json!(params.ipv4hint.iter().map(|address| address.to_string()).collect::<Vec<String>>());
+		}
+		if !params.ipv6hint.is_empty() {
+			json["ipv6hint"] = json!(params.ipv6hint.iter().map(|address| address.to_string()).collect::<Vec<String>>());
+		}
+		json
+	};
+	add("SVCB", records.svcb.iter().map(|record| (svcb(record), &record.metadata)).collect());
+	add("HTTPS", records.https.iter().map(|record| (svcb(record), &record.metadata)).collect());
+	add("PTR", records.ptr.iter().map(|ptr| (json!({ "ttl": ptr.ttl.as_secs(), "name": ptr.name }), &ptr.metadata)).collect());
+	add("RNS", records.rns.iter().map(|rns| (json!({
+		"ttl": rns.ttl.as_secs(),
+		"host": match &rns.host {
+			RnsHost::SocketAddr(address) => address.to_string(),
+			RnsHost::HostPort(host, port) => format!("{}:{}", host, port),
+		},
+		"external": rns.external,
+	}), &rns.metadata)).collect());
+	add("TRPP", records.trpp.iter().map(|trpp| (json!({ "ttl": trpp.ttl.as_secs(), "server": trpp.server }), &trpp.metadata)).collect());
+	return Value::Object(json);
+}
+
+/// Converts a request body to what the config parser takes.
+fn to_yaml(value: &Value) -> Yaml {
+	return match value {
+		Value::Null => Yaml::Null,
+		Value::Bool(bool) => Yaml::Boolean(*bool),
+		Value::Number(number) => match number.as_i64() {
+			Some(int) => Yaml::Integer(int),
+			None => Yaml::Real(number.to_string()),
+		},
+		Value::String(string) => Yaml::String(string.clone()),
+		Value::Array(array) => Yaml::Array(array.iter().map(to_yaml).collect()),
+		Value::Object(object) => Yaml::Hash(object.iter().map(|(key, value)| (Yaml::String(key.clone()), to_yaml(value))).collect()),
+	};
+}
+
+fn arrayify(value: Yaml) -> yaml::Array {
+	return match value {
+		Yaml::Array(array) => array,
+		value => vec![value],
+	};
+}
+
+/// The key of a record type in a zone's mapping, which may also have a TTL or flags after the type.
+fn type_key(zone: &yaml::Hash, rtype: &str) -> Option<Yaml> {
+	return zone.keys()
+		.find(|key| key.as_str().and_then(|key| key.split_whitespace().next()) == Some(rtype))
+		.cloned();
+}
+
+/// Makes a change to the mapping of a zone in the config file, adding the zone if it isn't there.
+/// The file is parsed and written back out rather than the config being serialized, so that
+/// everything else in it stays the same, apart from comments and formatting.
+fn persist<F: FnOnce(&mut yaml::Hash)>(path: &str, matchers: &[ZoneMatcher], excludes: &[ZoneMatcher], name: &str, change: F) -> Result<(), Error> {
+	let contents = fs::read_to_string(path).map_err(|error| server_error(format!("failed to read the config: {}", error)))?;
+	let mut docs = YamlLoader::load_from_str(&contents).map_err(|error| server_error(format!("failed to parse the config: {}", error)))?;
+	if docs.len() > 1 {
+		return Err(bad_request("the config has more than one document, so changes can't be persisted"));
+	}
+	let zones = match docs.get_mut(0) {
+		Some(Yaml::Hash(document)) => document.get_mut(&Yaml::String("zones".to_string())),
+		_ => None,
+	};
+	let zones = match zones {
+		Some(Yaml::Hash(zones)) => zones,
+		_ => return Err(server_error("the config has no zones")),
+	};
+	
+	// the zone's key may also have a TTL or flags after its name, and spaces after its commas
+	let key = zones.keys()
+		.find(|key| key.as_str()
+			.map(config::join_matchers)
+			.and_then(|key| key.split_whitespace().next().and_then(|key| config::parse_zone_name(key).ok()))
+			.map(|(key_matchers, key_excludes)| key_matchers == matchers && key_excludes == excludes)
+			.unwrap_or(false))
+		.cloned()
+		.unwrap_or_else(|| Yaml::String(name.to_string()));
+	match zones.entry(key).or_insert_with(|| Yaml::Hash(yaml::Hash::new())) {
+		Yaml::Hash(zone) => change(zone),
+		_ => return Err(server_error(format!("zone '{}' in the config isn't a mapping", name))),
+	}
+	
+	let mut output = String::new();
+	YamlEmitter::new(&mut output).dump(&docs[0]).map_err(|error| server_error(format!("failed to write the config: {:?}", error)))?;
+	output.push('\n');
+	// written alongside and moved into place, so that a failure part way doesn't lose the config
+	let temporary = format!("{}.tmp", path);
+	fs::write(&temporary, output)
+		.and_then(|_| fs::rename(&temporary, path))
+		.map_err(|error| server_error(format!("failed to write the config: {}", error)))?;
+	return Ok(());
+}
+
+/// Compares in time that only depends on the lengths, so the token can't be guessed a byte at a time.
+fn is_same_token(given: &[u8], token: &[u8]) -> bool {
+	return given.len() == token.len() && given.iter().zip(token).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0;
+}
+
+fn percent_decode(string: &str) -> Option<String> {
+	let mut bytes = vec![];
+	let mut rest = string.as_bytes();
+	while let Some((&byte, after)) = rest.split_first() {
+		if byte == b'%' {
+			let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+			bytes.push(u8::from_str_radix(hex, 16).ok()?);
+			rest = &after[2..];
+		} else {
+			bytes.push(byte);
+			rest = after;
+		}
+	}
+	return String::from_utf8(bytes).ok();
+}
+
+#[cfg(test)]
+mod test {
+	use std::env;
+	use std::fs;
+	use std::io::{self, Cursor, Read, Write};
+	use std::time::Instant;
+	
+	use crate::config;
+	use crate::server::api::{handle_connection, MAX_HEADERS, percent_decode, read_request};
+	use crate::server::shared::SharedConfig;
+	use crate::server::test::test_options;
+	
+	/// Reads from the request and writes to a buffer, like a TCP stream would.
+	struct Stream {
+		request: Cursor<Vec<u8>>,
+		response: Vec<u8>,
+	}
+	
+	impl Read for Stream {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			return self.request.read(buf);
+		}
+	}
+	
+	impl Write for Stream {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			return self.response.write(buf);
+		}
+		
+		fn flush(&mut self) -> io::Result<()> {
+			return Ok(());
+		}
+	}
+	
+	const CONFIG: &str = "
+zones:
+  example.com:
+    A: [10.0.0.1, 10.0.0.2 5m]
+    MX: mail.example.com
+";
+	
+	fn request(config: &SharedConfig, method: &str, path: &str, body: &str, config_path: Option<&str>) -> String {
+		let request = format!("{} {} HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body);
+		let mut stream = Stream { request: Cursor::new(request.into_bytes()), response: vec![] };
+		handle_connection(&mut stream, &test_options(), config, "secret", config_path).unwrap();
+		return String::from_utf8(stream.response).unwrap();
+	}
+	
+	#[test]
+	fn test_authorization() {
+		let config = SharedConfig::new(config::parse(CONFIG).unwrap());
+		let mut stream = Stream { request: Cursor::new(b"GET /zones HTTP/1.1\r\n\r\n".to_vec()), response: vec![] };
+		handle_connection(&mut stream, &test_options(), &config, "secret", None).unwrap();
+		assert!(String::from_utf8(stream.response).unwrap().starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+		
+		let mut stream = Stream { request: Cursor::new(b"GET /zones HTTP/1.1\r\nAuthorization: Bearer secreT\r\n\r\n".to_vec()), response: vec![] };
+		handle_connection(&mut stream, &test_options(), &config, "secret", None).unwrap();
+		assert!(String::from_utf8(stream.response).unwrap().starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+	}
+	
+	#[test]
+	fn test_request_limits() {
+		let config = SharedConfig::new(config::parse(CONFIG).unwrap());
+		let headers = "X-Padding: 1\r\n".repeat(MAX_HEADERS + 1);
+		let request = format!("GET /zones HTTP/1.1\r\nAuthorization: Bearer secret\r\n{}\r\n", headers);
+		let mut stream = Stream { request: Cursor::new(request.into_bytes()), response: vec![] };
+		handle_connection(&mut stream, &test_options(), &config, "secret", None).unwrap();
+		assert!(String::from_utf8(stream.response).unwrap().starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+		
+		// the whole request has to be in by the deadline, not just each read
+		let mut stream = Cursor::new(b"GET /zones HTTP/1.1\r\n\r\n".to_vec());
+		assert_eq!(read_request(&mut stream, Instant::now()).err().map(|error| error.kind()), Some(io::ErrorKind::TimedOut));
+	}
+	
+	#[test]
+	fn test_get() {
+		let mut config = config::parse(CONFIG).unwrap();
+		config.serial = 1;
+		let config = SharedConfig::new(config);
+		let response = request(&config, "GET", "/zones", "", None);
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(response.ends_with("\r\n\r\n{\"nttl\":15,\"serial\":1,\"ttl\":1800,\"zones\":[{\"name\":\"example.com\",\"records\":{\"A\":[{\"address\":\"10.0.0.1\",\"ttl\":1800},{\"address\":\"10.0.0.2\",\"ttl\":300}],\"MX\":[{\"host\":\"mail.example.com\",\"priority\":10,\"ttl\":1800}]}}]}\n"));
+		
+		let response = request(&config, "GET", "/zones/example.com/records", "", None);
+		assert!(response.ends_with("\r\n\r\n{\"A\":[{\"address\":\"10.0.0.1\",\"ttl\":1800},{\"address\":\"10.0.0.2\",\"ttl\":300}],\"MX\":[{\"host\":\"mail.example.com\",\"priority\":10,\"ttl\":1800}]}\n"));
+		assert!(request(&config, "GET", "/zones/example.org/records", "", None).starts_with("HTTP/1.1 404 Not Found\r\n"));
+		assert!(request(&config, "GET", "/records", "", None).starts_with("HTTP/1.1 404 Not Found\r\n"));
+	}
+	
+	#[test]
+	fn test_put() {
+		let config = SharedConfig::new(config::parse(CONFIG).unwrap());
+		let serial = config.get().serial;
+		let response = request(&config, "PUT", "/zones/example.com/records/A", r#"["10.0.0.3 1m"]"#, None);
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert_eq!(config.get().zones[0].records.a.len(), 3);
+		assert_eq!(config.get().serial, serial.wrapping_add(1));
+		
+		// a zone that isn't there yet is added
+		let response = request(&config, "PUT", "/zones/%2A.example.org/records/TXT", r#""hello""#, None);
+		assert!(response.ends_with("\r\n\r\n{\"TXT\":[{\"data\":\"hello\",\"ttl\":1800}]}\n"));
+		
+		// comments and labels come back as they were given
+		let response = request(&config, "PUT", "/zones/%2A.example.org/records/NS", r#"{"name": "ns1.example.org", "comment": "primary", "labels": {"team": "infra"}}"#, None);
+		assert!(response.contains("\"NS\":[{\"comment\":\"primary\",\"labels\":{\"team\":\"infra\"},\"name\":\"ns1.example.org\",\"ttl\":1800}]"), "{}", response);
+		assert_eq!(config.get().zones[1].matchers, config::parse_zone_name("*.example.org").unwrap().0);
+		
+		// nothing changes when a record is invalid
+		let before = config.get();
+		assert!(request(&config, "PUT", "/zones/example.com/records/A", r#"["10.0.0.4", "nope"]"#, None).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert!(request(&config, "PUT", "/zones/example.com/records/A", "[", None).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert!(request(&config, "PUT", "/zones/example.com/records/A%205m", r#""10.0.0.4""#, None).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert!(request(&config, "PUT", "/zones/example.com/records/FOO", r#""bar""#, None).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert!(request(&config, "PUT", "/zones/example.com/records/A?persist=true", r#""10.0.0.4""#, None).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert_eq!(config.get(), before);
+		
+		// nor when `none` would hide addresses of the same type
+		assert!(request(&config, "PUT", "/zones/example.com/records/A", r#""none""#, None).starts_with("HTTP/1.1 409 Conflict\r\n"));
+		assert!(request(&config, "PUT", "/zones/example.com/records/AAAA", r#""none""#, None).starts_with("HTTP/1.1 200 OK\r\n"));
+		let before = config.get();
+		assert!(request(&config, "PUT", "/zones/example.com/records/AAAA", r#""::1""#, None).starts_with("HTTP/1.1 409 Conflict\r\n"));
+		assert_eq!(config.get(), before);
+	}
+	
+	#[test]
+	fn test_delete() {
+		let config = SharedConfig::new(config::parse(CONFIG).unwrap());
+		let response = request(&config, "DELETE", "/zones/example.com/records/A/0", "", None);
+		assert!(response.ends_with("\r\n\r\n{\"A\":[{\"address\":\"10.0.0.2\",\"ttl\":300}],\"MX\":[{\"host\":\"mail.example.com\",\"priority\":10,\"ttl\":1800}]}\n"));
+		assert!(request(&config, "DELETE", "/zones/example.com/records/A/1", "", None).starts_with("HTTP/1.1 404 Not Found\r\n"));
+		assert!(request(&config, "DELETE", "/zones/example.com/records/MX", "", None).starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(request(&config, "DELETE", "/zones/example.com/records/MX", "", None).starts_with("HTTP/1.1 404 Not Found\r\n"));
+		assert!(request(&config, "DELETE", "/zones/example.com/records/A/x", "", None).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert_eq!(config.get().zones[0].records.a.len(), 1);
+		assert!(config.get().zones[0].records.mx.is_empty());
+	}
+	
+	#[test]
+	fn test_stats() {
+		let config = SharedConfig::new(config::parse(CONFIG).unwrap());
+		config.stats().record(Some("example.com"), "example.com".to_string(), 0, true, 0);
+		assert!(request(&config, "GET", "/stats", "", None).contains("\"top_names\":[{\"name\":\"example.com\",\"queries\":1}]"));
+		assert!(request(&config, "DELETE", "/stats", "", None).contains("\"top_names\":[{\"name\":\"example.com\",\"queries\":1}]"));
+		assert!(request(&config, "GET", "/stats", "", None).contains("\"top_names\":[]"));
+		assert_eq!(config.stats().total().queries, 0);
+	}
+	
+	#[test]
+	fn test_persist() {
+		let path = env::temp_dir().join(format!("tacodns-api-test-{}.yml", std::process::id()));
+		let contents = format!("{}  example.info:\n    ttl: 1m\n    A 5m: 10.0.0.3\n    TXT: hi\n", CONFIG);
+		fs::write(&path, &contents).unwrap();
+		let path = path.to_str().unwrap();
+		let config = SharedConfig::new(config::parse(&contents).unwrap());
+		
+		let persist = |method: &str, path_and_query: &str, body: &str| {
+			assert!(request(&config, method, path_and_query, body, Some(path)).starts_with("HTTP/1.1 200 OK\r\n"));
+		};
+		persist("PUT", "/zones/example.com/records/A?persist=true", r#"["10.0.0.3 1m"]"#);
+		persist("DELETE", "/zones/example.com/records/A/0?persist=true", "");
+		persist("DELETE", "/zones/example.com/records/MX?persist=true", "");
+		persist("PUT", "/zones/example.org/records/TXT?persist=true", r#""hello""#);
+		// records take the TTL of the zone, or of the key they're written under
+		persist("PUT", "/zones/example.info/records/A?persist=true", r#""10.0.0.4""#);
+		persist("PUT", "/zones/example.info/records/TXT?persist=true", r#""hello""#);
+		persist("PUT", "/zones/example.info/records/MX?persist=true", r#""mail.example.info""#);
+		// only changes with the parameter are persisted
+		persist("PUT", "/zones/example.net/records/TXT", r#""hello""#);
+		
+		let persisted = config::parse(&fs::read_to_string(path).unwrap()).unwrap();
+		fs::remove_file(path).unwrap();
+		let config = config.get();
+		assert_eq!(persisted.zones, config.zones[..3]);
+		let info = &config.zones[1].records;
+		assert_eq!(info.a.iter().map(|a| a.ttl.as_secs()).collect::<Vec<u64>>(), vec![300, 300]);
+		assert_eq!((info.txt[0].ttl.as_secs(), info.txt[1].ttl.as_secs(), info.mx[0].ttl.as_secs()), (60, 60, 60));
+	}
+	
+	#[test]
+	fn test_percent_decode() {
+		assert_eq!(percent_decode("example.com"), Some("example.com".to_string()));
+		assert_eq!(percent_decode("%2a.example.com%2C%2A%2Aexample.com"), Some("*.example.com,**example.com".to_string()));
+		assert_eq!(percent_decode("%2"), None);
+		assert_eq!(percent_decode("%zz"), None);
+		assert_eq!(percent_decode("%ff"), None);
+	}
+}
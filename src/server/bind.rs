@@ -0,0 +1,139 @@
+//! Binding UDP sockets and TCP listeners so that an IPv6 one only ever takes IPv6, whatever the
+//! system default for `IPV6_V6ONLY` is, so that it doesn't clash with an IPv4 one on the same port.
+
+#[cfg(target_os = "linux")]
+pub use linux::{bind_tcp, bind_udp, is_unsupported};
+#[cfg(not(target_os = "linux"))]
+pub use other::{bind_tcp, bind_udp, is_unsupported};
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+	use std::io;
+	use std::net::{SocketAddr, TcpListener, UdpSocket};
+	
+	pub fn bind_udp(address: SocketAddr) -> io::Result<UdpSocket> {
+		UdpSocket::bind(address)
+	}
+	
+	pub fn bind_tcp(address: SocketAddr) -> io::Result<TcpListener> {
+		TcpListener::bind(address)
+	}
+	
+	pub fn is_unsupported(_error: &io::Error) -> bool {
+		false
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::io;
+	use std::mem;
+	use std::net::{SocketAddr, TcpListener, UdpSocket};
+	use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+	
+	use libc::{c_int, c_void};
+	
+	use crate::server::pktinfo::to_sockaddr;
+	
+	/// Like `UdpSocket::bind`, with `IPV6_V6ONLY` set on IPv6 sockets.
+	pub fn bind_udp(address: SocketAddr) -> io::Result<UdpSocket> {
+		let socket = bind(address, libc::SOCK_DGRAM)?;
+		Ok(UdpSocket::from(socket))
+	}
+	
+	/// Like `TcpListener::bind`, with `IPV6_V6ONLY` set on IPv6 listeners.
+	pub fn bind_tcp(address: SocketAddr) -> io::Result<TcpListener> {
+		let socket = socket(address, libc::SOCK_STREAM)?;
+		// as std does, so that a restarted server can listen again while old connections linger
+		set_option(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR)?;
+		let socket = bind_socket(socket, address)?;
+		if unsafe { libc::listen(socket.as_raw_fd(), 128) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(TcpListener::from(socket))
+	}
+	
+	/// Whether binding failed as the host doesn't have the address family at all, e.g. IPv6 when
+	/// it's disabled in the kernel.
+	pub fn is_unsupported(error: &io::Error) -> bool {
+		error.raw_os_error() == Some(libc::EAFNOSUPPORT)
+	}
+	
+	fn bind(address: SocketAddr, kind: c_int) -> io::Result<OwnedFd> {
+		let socket = socket(address, kind)?;
+		bind_socket(socket, address)
+	}
+	
+	fn socket(address: SocketAddr, kind: c_int) -> io::Result<OwnedFd> {
+		let family = if address.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+		let fd = unsafe { libc::socket(family, kind | libc::SOCK_CLOEXEC, 0) };
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+		if address.is_ipv6() {
+			set_option(&socket, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY)?;
+		}
+		Ok(socket)
+	}
+	
+	fn bind_socket(socket: OwnedFd, address: SocketAddr) -> io::Result<OwnedFd> {
+		let (name, name_len) = to_sockaddr(address);
+		let result = unsafe { libc::bind(socket.as_raw_fd(), &name as *const libc::sockaddr_storage as *const libc::sockaddr, name_len) };
+		if result != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(socket)
+	}
+	
+	fn set_option(socket: &OwnedFd, level: c_int, name: c_int) -> io::Result<()> {
+		let on: c_int = 1;
+		let result = unsafe {
+			libc::setsockopt(socket.as_raw_fd(), level, name, &on as *const c_int as *const c_void, mem::size_of::<c_int>() as libc::socklen_t)
+		};
+		if result != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+	
+	#[cfg(test)]
+	mod test {
+		use std::io::{Read, Write};
+		use std::net::{SocketAddr, TcpStream, UdpSocket};
+		use std::time::Duration;
+		
+		use crate::server::bind::{bind_tcp, bind_udp};
+		
+		#[test]
+		fn test_dual_stack() {
+			let udp_v4 = bind_udp("0.0.0.0:0".parse().unwrap()).unwrap();
+			let port = udp_v4.local_addr().unwrap().port();
+			let tcp_v4 = bind_tcp(SocketAddr::new("0.0.0.0".parse().unwrap(), port)).unwrap();
+			// not every sandbox has IPv6
+			let udp_v6 = match bind_udp(SocketAddr::new("::".parse().unwrap(), port)) {
+				Ok(socket) => socket,
+				Err(_) => return,
+			};
+			// only taking IPv6 is what lets both be bound to the same port
+			let tcp_v6 = bind_tcp(SocketAddr::new("::".parse().unwrap(), port)).unwrap();
+			
+			for (server, listener, client) in &[(&udp_v4, &tcp_v4, "127.0.0.1"), (&udp_v6, &tcp_v6, "::1")] {
+				let address = SocketAddr::new(client.parse().unwrap(), port);
+				let client = UdpSocket::bind(SocketAddr::new(address.ip(), 0)).unwrap();
+				client.send_to(b"ping", address).unwrap();
+				server.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+				let mut buf = [0; 16];
+				let (size, src) = server.recv_from(&mut buf).unwrap();
+				assert_eq!(&buf[..size], b"ping");
+				assert_eq!(src, client.local_addr().unwrap());
+				
+				let mut stream = TcpStream::connect(address).unwrap();
+				stream.write_all(b"ping").unwrap();
+				let (mut accepted, _) = listener.accept().unwrap();
+				accepted.read_exact(&mut buf[..4]).unwrap();
+				assert_eq!(&buf[..4], b"ping");
+			}
+		}
+	}
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::server::Response;
+
+// kept by the wall clock, rather than `Instant`, so that entries can be saved and outlive a restart
+struct Entry {
+	response: Response,
+	cache_time: SystemTime,
+	expiration: SystemTime,
+}
+
+/// An entry as it's saved to a cache file, with its times in seconds since the Unix epoch.
+#[derive(Serialize, Deserialize)]
+struct SavedEntry<K> {
+	key: K,
+	response: Response,
+	cached: u64,
+	expires: u64,
+}
+
+/// Responses from other DNS servers, kept until their TTL runs out.
+pub struct Cache<K> {
+	entries: HashMap<K, Entry>,
+}
+
+impl<K: Eq + Hash + Clone> Cache<K> {
+	pub fn new() -> Cache<K> {
+		return Cache {
+			entries: HashMap::new(),
+		};
+	}
+	
+	/// Returns the cached response, with its TTLs counted down by the time it's spent in the cache.
+	pub fn get(&mut self, key: &K, now: SystemTime) -> Option<Response> {
+		let entry = self.entries.get(key)?;
+		if entry.expiration <= now {
+			self.entries.remove(key);
+			return None;
+		}
+		
+		let mut response = entry.response.clone();
+		if let Response::Ok(answer, authority, additional) = &mut response {
+			// the clock may have been set back since
+			let elapsed = now.duration_since(entry.cache_time).unwrap_or_default().as_secs().min(u32::MAX as u64) as u32;
+			for record in answer.iter_mut().chain(authority.iter_mut()).chain(additional.iter_mut()) {
+				record.ttl = record.ttl.saturating_sub(elapsed);
+			}
+		}
+		return Some(response);
+	}
+	
+	/// When the response to `key` was cached, and when it expires, if it's cached.
+	pub fn lifetime(&self, key: &K) -> Option<(SystemTime, SystemTime)> {
+		return self.entries.get(key).map(|entry| (entry.cache_time, entry.expiration));
+	}
+	
+	/// Caches a response for `ttl` seconds, unless that's 0. When there are already `max_entries`,
+	/// expired entries are dropped, and if that wasn't enough, a random one.
+	pub fn insert(&mut self, key: K, response: Response, ttl: u32, now: SystemTime, max_entries: usize) {
+		if ttl == 0 || max_entries == 0 {
+			self.entries.remove(&key);
+			return;
+		}
+		
+		self.make_room(&key, now, max_entries);
+		self.entries.insert(key, Entry {
+			response,
+			cache_time: now,
+			expiration: now + Duration::from_secs(ttl as u64),
+		});
+	}
+	
+	/// Makes room for `key` when there are already `max_entries`, dropping expired entries, and if
+	/// that wasn't enough, a random one.
+	fn make_room(&mut self, key: &K, now: SystemTime, max_entries: usize) {
+		if self.entries.len() >= max_entries && !self.entries.contains_key(key) {
+			self.entries.retain(|_, entry| entry.expiration > now);
+			if self.entries.len() >= max_entries {
+				// the iteration order of a HashMap is as good as random
+				let victim = self.entries.keys().next().cloned().unwrap();
+				self.entries.remove(&victim);
+			}
+		}
+	}
+	
+	#[cfg(test)]
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+}
+
+impl<K: Eq + Hash + Clone + Serialize + DeserializeOwned> Cache<K> {
+	/// The entries that haven't expired by `now`, as JSON for `load`.
+	pub fn save(&self, now: SystemTime) -> Vec<u8> {
+		let entries: Vec<SavedEntry<&K>> = self.entries.iter()
+			.filter(|(_, entry)| entry.expiration > now)
+			.map(|(key, entry)| SavedEntry {
+				key,
+				response: entry.response.clone(),
+				cached: unix_seconds(entry.cache_time),
+				expires: unix_seconds(entry.expiration),
+			})
+			.collect();
+		return serde_json::to_vec(&entries).unwrap_or_default();
+	}
+	
+	/// Adds the entries from `save` that haven't expired by `now`, keeping those already cached,
+	/// and returns how many were added. Nothing is added if any of it can't be read.
+	pub fn load(&mut self, data: &[u8], now: SystemTime, max_entries: usize) -> serde_json::Result<usize> {
+		let saved: Vec<SavedEntry<K>> = serde_json::from_slice(data)?;
+		let mut added = 0;
+		for entry in saved {
+			// a time too far out to represent can only be from a corrupted file
+			let (cache_time, expiration) = match (UNIX_EPOCH.checked_add(Duration::from_secs(entry.cached)), UNIX_EPOCH.checked_add(Duration::from_secs(entry.expires))) {
+				(Some(cache_time), Some(expiration)) => (cache_time, expiration),
+				_ => continue,
+			};
+			if expiration <= now || max_entries == 0 || self.entries.contains_key(&entry.key) {
+				continue;
+			}
+			self.make_room(&entry.key, now, max_entries);
+			self.entries.insert(entry.key, Entry { response: entry.response, cache_time, expiration });
+			added += 1;
+		}
+		return Ok(added);
+	}
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+	return time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::{Duration, SystemTime};
+	
+	use crate::server::cache::Cache;
+	use crate::server::protocol::{record_type, Resource};
+	use crate::server::Response;
+	
+	fn response(ttl: u32) -> Response {
+		Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl,
+			rdata: vec![10, 0, 0, 1],
+		}], vec![], vec![])
+	}
+	
+	#[test]
+	fn test_countdown() {
+		let mut cache = Cache::new();
+		let now = SystemTime::now();
+		cache.insert("a", response(100), 100, now, 10);
+		assert_eq!(cache.get(&"a", now), Some(response(100)));
+		assert_eq!(cache.get(&"a", now + Duration::from_millis(1500)), Some(response(99)));
+		assert_eq!(cache.get(&"a", now + Duration::from_secs(99)), Some(response(1)));
+		// expires right at the boundary
+		assert_eq!(cache.get(&"a", now + Duration::from_secs(100)), None);
+		assert_eq!(cache.len(), 0);
+	}
+	
+	#[test]
+	fn test_longer_record_ttls() {
+		// records can outlive the entry, such as when the entry's TTL came from a negative answer,
+		// but never count down past 0
+		let mut cache = Cache::new();
+		let now = SystemTime::now();
+		cache.insert("a", response(3), 10, now, 10);
+		assert_eq!(cache.get(&"a", now + Duration::from_secs(9)), Some(response(0)));
+	}
+	
+	#[test]
+	fn test_zero_ttl() {
+		let mut cache = Cache::new();
+		let now = SystemTime::now();
+		cache.insert("a", response(0), 0, now, 10);
+		assert_eq!(cache.get(&"a", now), None);
+		assert_eq!(cache.len(), 0);
+	}
+	
+	#[test]
+	fn test_max_entries() {
+		let mut cache = Cache::new();
+		let now = SystemTime::now();
+		for key in 0..100 {
+			cache.insert(key, response(100), 100, now, 3);
+			assert!(cache.len() <= 3);
+		}
+		assert_eq!(cache.get(&99, now), Some(response(100)));
+		
+		// expired entries go first
+		let mut cache = Cache::new();
+		cache.insert(0, response(10), 10, now, 3);
+		cache.insert(1, response(100), 100, now, 3);
+		cache.insert(2, response(100), 100, now, 3);
+		let later = now + Duration::from_secs(10);
+		cache.insert(3, response(100), 100, later, 3);
+		assert_eq!(cache.len(), 3);
+		for key in 1..4 {
+			assert!(cache.get(&key, later).is_some());
+		}
+		
+		// and replacing an entry doesn't evict anything
+		cache.insert(3, response(50), 50, later, 3);
+		assert_eq!(cache.len(), 3);
+	}
+	
+	#[test]
+	fn test_save_load() {
+		let mut cache = Cache::new();
+		let now = SystemTime::now();
+		cache.insert("short".to_string(), response(10), 10, now, 10);
+		cache.insert("long".to_string(), response(100), 100, now, 10);
+		cache.insert("gone".to_string(), response(1), 1, now - Duration::from_secs(5), 10);
+		let saved = cache.save(now);
+		
+		// only what's still valid comes back, counted down by the time since it was cached
+		let mut loaded = Cache::new();
+		assert_eq!(loaded.load(&saved, now + Duration::from_secs(20), 10).unwrap(), 1);
+		assert_eq!(loaded.get(&"long".to_string(), now + Duration::from_secs(20)), Some(response(80)));
+		assert_eq!(loaded.get(&"short".to_string(), now + Duration::from_secs(20)), None);
+		
+		// entries already cached are newer than saved ones
+		let mut loaded = Cache::new();
+		loaded.insert("long".to_string(), response(5), 5, now, 10);
+		assert_eq!(loaded.load(&saved, now, 10).unwrap(), 1);
+		assert_eq!(loaded.get(&"long".to_string(), now), Some(response(5)));
+		
+		// nothing at all comes from a file that's corrupted
+		let mut loaded: Cache<String> = Cache::new();
+		assert!(loaded.load(&saved[..saved.len() - 1], now, 10).is_err());
+		assert!(loaded.load(b"[{\"key\": \"a\"}]", now, 10).is_err());
+		assert_eq!(loaded.load(format!("[{{\"key\": \"a\", \"response\": \"NameError\", \"cached\": 0, \"expires\": {}}}]", u64::MAX).as_bytes(), now, 10).unwrap(), 0);
+		assert_eq!(loaded.len(), 0);
+	}
+}
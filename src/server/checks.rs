@@ -0,0 +1,206 @@
+//! Health checks for A and AAAA records with a `check`, which are run in the background so that only
+//! addresses that are up get answered, along with weighting which address is answered first.
+//!
+//! Each server keeps the results of its own checks. As with traces, a request is answered start to
+//! finish on one worker thread, so the results it's answered with are kept in a thread local rather
+//! than passed down to every lookup of a zone's records.
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::net::TcpStream;
+use std::sync::{Arc, mpsc, RwLock};
+use std::time::Duration;
+
+use threadpool::ThreadPool;
+
+use crate::config::{Check, Config};
+use crate::options::Options;
+use crate::server::Shutdown;
+use crate::server::shared::SharedConfig;
+
+/// How many checks are run at the same time.
+const WORKERS: usize = 8;
+
+/// Whether each check passed the last time it was run, which can be cloned and sent off to other
+/// threads. Checks that haven't been run yet pass.
+#[derive(Debug, Clone, Default)]
+pub struct Health(Arc<RwLock<HashMap<Check, bool>>>);
+
+impl Health {
+	pub fn is_healthy(&self, check: &Check) -> bool {
+		return self.0.read().unwrap_or_else(|error| error.into_inner()).get(check).copied().unwrap_or(true);
+	}
+	
+	/// Records the result of a check, returning whether it changed.
+	pub fn set_healthy(&self, check: &Check, healthy: bool) -> bool {
+		let previous = self.0.write().unwrap_or_else(|error| error.into_inner()).insert(check.clone(), healthy);
+		return previous.unwrap_or(true) != healthy;
+	}
+	
+	/// Forgets the results of checks that are no longer in the config.
+	fn retain(&self, checks: &HashSet<Check>) {
+		self.0.write().unwrap_or_else(|error| error.into_inner()).retain(|check, _| checks.contains(check));
+	}
+}
+
+thread_local! {
+	static ANSWERING: RefCell<Health> = RefCell::default();
+}
+
+/// Starts a request on this thread, which is answered with the results in `health`.
+pub fn start(health: &Health) {
+	ANSWERING.with(|answering| *answering.borrow_mut() = health.clone());
+}
+
+fn is_healthy(check: &Check) -> bool {
+	return ANSWERING.with(|answering| answering.borrow().is_healthy(check));
+}
+
+/// Picks the records of a type to answer with, in order. Records whose checks fail are left out,
+/// unless all of them do, as addresses that may be down beat no answer at all. If any of them have
+/// a weight, they're ordered by picking each next one at random, weighted by it, with a weight of 1
+/// for those without.
+pub fn select<T>(records: &[T], check: impl Fn(&T) -> Option<&Check>, weight: impl Fn(&T) -> Option<u32>) -> Vec<&T> {
+	let mut selected: Vec<&T> = records.iter().filter(|record| check(record).map(is_healthy).unwrap_or(true)).collect();
+	if selected.is_empty() {
+		selected = records.iter().collect();
+	}
+	if selected.iter().all(|record| weight(record).is_none()) {
+		return selected;
+	}
+	return weighted_order(selected, |record| weight(record).unwrap_or(1), || RandomState::new().build_hasher().finish());
+}
+
+/// Orders records by repeatedly taking one of those left with a chance proportional to its weight,
+/// where `random` gives the numbers to pick with.
+fn weighted_order<T>(mut records: Vec<T>, weight: impl Fn(&T) -> u32, mut random: impl FnMut() -> u64) -> Vec<T> {
+	let mut ordered = Vec::with_capacity(records.len());
+	while !records.is_empty() {
+		let total: u64 = records.iter().map(|record| weight(record) as u64).sum();
+		// once only records with a weight of 0 are left, they keep their order
+		let mut pick = if total == 0 { 0 } else { random() % total };
+		let index = records.iter().position(|record| {
+			let weight = weight(record) as u64;
+			if pick < weight { return true; }
+			pick -= weight;
+			return false;
+		}).unwrap_or(0);
+		ordered.push(records.remove(index));
+	}
+	return ordered;
+}
+
+/// The checks of every A and AAAA record in the config.
+fn checks(config: &Config) -> HashSet<Check> {
+	return config.zones.iter()
+		.flat_map(|zone| zone.records.a.iter().filter_map(|a| a.check.clone())
+			.chain(zone.records.aaaa.iter().filter_map(|aaaa| aaaa.check.clone())))
+		.collect();
+}
+
+/// Runs the checks in the config every `check_interval` until the server is shut down, keeping
+/// their results in the server's `Health`. The config is read again each time, so checks added
+/// through the API are picked up, and those removed are forgotten.
+pub fn run_periodically(options: &Options, config: &SharedConfig, shutdown: &Shutdown) {
+	let checker = Checker::new();
+	while !shutdown.is_shutdown() {
+		let (checks, interval, timeout) = {
+			let config = config.get();
+			(checks(&config), config.check_interval, config.check_timeout)
+		};
+		config.health().retain(&checks);
+		checker.run(options, config.health(), checks, timeout);
+		shutdown.wait(interval);
+	}
+}
+
+/// Runs checks on a fixed set of threads, sharing one HTTP client between them.
+pub struct Checker {
+	pool: ThreadPool,
+	client: reqwest::blocking::Client,
+}
+
+impl Checker {
+	pub fn new() -> Checker {
+		return Checker {
+			pool: ThreadPool::with_name("health check".to_string(), WORKERS),
+			client: reqwest::blocking::Client::new(),
+		};
+	}
+	
+	/// Runs checks, up to `WORKERS` at the same time, and records their results in `health` once all
+	/// of them have finished.
+	pub fn run(&self, options: &Options, health: &Health, checks: HashSet<Check>, timeout: Duration) {
+		let (sender, receiver) = mpsc::channel();
+		for check in checks {
+			let sender = sender.clone();
+			let client = self.client.clone();
+			self.pool.execute(move || {
+				let healthy = passes(&check, &client, timeout);
+				let _ = sender.send((check, healthy));
+			});
+		}
+		// a check that panicked drops its sender without sending, and is left as it was
+		drop(sender);
+		for (check, healthy) in receiver {
+			if health.set_healthy(&check, healthy) && options.verbose {
+				println!("health check {} is now {}", check, if healthy { "passing" } else { "failing" });
+			}
+		}
+	}
+}
+
+fn passes(check: &Check, client: &reqwest::blocking::Client, timeout: Duration) -> bool {
+	return match check {
+		Check::Tcp(address) => TcpStream::connect_timeout(address, timeout).is_ok(),
+		Check::Http(url) => client.get(url).timeout(timeout).send()
+			.map(|response| response.status().is_success())
+			.unwrap_or(false),
+	};
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::HashSet;
+	
+	use crate::config::Check;
+	use crate::server::checks::{Health, is_healthy, select, start, weighted_order};
+	
+	#[test]
+	fn test_select() {
+		let up = Check::Tcp("192.0.2.1:443".parse().unwrap());
+		let down = Check::Tcp("192.0.2.2:443".parse().unwrap());
+		let health = Health::default();
+		assert!(!health.set_healthy(&up, true));
+		assert!(health.set_healthy(&down, false));
+		start(&health);
+		let unchecked = Check::Tcp("192.0.2.3:443".parse().unwrap());
+		assert!(is_healthy(&unchecked));
+		
+		let records = vec![(1, Some(&down)), (2, Some(&up)), (3, None), (4, Some(&unchecked))];
+		let select = |records: &[(u8, Option<&Check>)]| select(records, |record| record.1, |_| None).into_iter().map(|record| record.0).collect::<Vec<u8>>();
+		assert_eq!(select(&records), vec![2, 3, 4]);
+		// everything is answered when everything is down
+		assert_eq!(select(&records[..1]), vec![1]);
+		assert!(select(&[]).is_empty());
+		
+		// the results of another server's checks don't matter, and nor do those of checks forgotten
+		start(&Health::default());
+		assert_eq!(select(&records), vec![1, 2, 3, 4]);
+		health.retain(&vec![up.clone()].into_iter().collect::<HashSet<Check>>());
+		assert!(health.is_healthy(&down));
+	}
+	
+	#[test]
+	fn test_weighted_order() {
+		let weight = |record: &(u8, u32)| record.1;
+		let records = vec![(1, 10), (2, 1), (3, 0)];
+		// 0 to 9 picks the first record, 10 the second
+		assert_eq!(weighted_order(records.clone(), weight, || 0), vec![(1, 10), (2, 1), (3, 0)]);
+		assert_eq!(weighted_order(records.clone(), weight, || 9), vec![(1, 10), (2, 1), (3, 0)]);
+		assert_eq!(weighted_order(records.clone(), weight, || 10), vec![(2, 1), (1, 10), (3, 0)]);
+		assert_eq!(weighted_order(records, weight, || 22), vec![(1, 10), (2, 1), (3, 0)]);
+	}
+}
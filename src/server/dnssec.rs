@@ -0,0 +1,274 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+use crate::server::protocol::{self, record_type, Resource};
+
+/// https://tools.ietf.org/html/rfc8080#section-3
+pub const ED25519: u8 = 15;
+
+/// The zone key and secure entry point flags, as the one key both signs the zone and is what the
+/// DS record in the parent zone points to.
+const DNSKEY_FLAGS: u16 = 257;
+
+/// https://tools.ietf.org/html/rfc4034#section-2.1.2
+const DNSKEY_PROTOCOL: u8 = 3;
+
+/// An Ed25519 key to sign records with.
+pub struct SigningKey {
+	key_pair: Ed25519KeyPair,
+	tag: u16,
+}
+
+impl SigningKey {
+	/// Makes the key from the 32 byte private key, or `None` if it isn't 32 bytes.
+	pub fn from_seed(seed: &[u8]) -> Option<SigningKey> {
+		let key_pair = Ed25519KeyPair::from_seed_unchecked(seed).ok()?;
+		let tag = key_tag(&dnskey_rdata(key_pair.public_key().as_ref()));
+		return Some(SigningKey { key_pair, tag });
+	}
+	
+	pub fn dnskey_rdata(&self) -> Vec<u8> {
+		return dnskey_rdata(self.key_pair.public_key().as_ref());
+	}
+	
+	/// Makes the RRSIG record of an RRset, which must all have the same name, type, and class. The
+	/// signature is over the lowest TTL of the set, which is what the records should be answered
+	/// with.
+	///
+	/// https://tools.ietf.org/html/rfc4034#section-3.1.8.1
+	pub fn sign(&self, rrset: &[Resource], signer: &[String], inception: u32, expiration: u32) -> Resource {
+		let owner = &rrset[0];
+		let ttl = rrset.iter().map(|record| record.ttl).min().unwrap();
+		// a wildcard's asterisk isn't counted, so that validators know to expand it
+		let labels = owner.rname.iter().filter(|label| !label.is_empty()).count()
+			- if owner.rname.first().map(String::as_str) == Some("*") { 1 } else { 0 };
+		
+		let mut rdata = vec![];
+		rdata.write_u16::<BigEndian>(owner.rtype).unwrap();
+		rdata.push(ED25519);
+		rdata.push(labels as u8);
+		rdata.write_u32::<BigEndian>(ttl).unwrap();
+		rdata.write_u32::<BigEndian>(expiration).unwrap();
+		rdata.write_u32::<BigEndian>(inception).unwrap();
+		rdata.write_u16::<BigEndian>(self.tag).unwrap();
+		rdata.append(&mut canonical_name(signer));
+		
+		let signature = self.key_pair.sign(&signed_data(&rdata, rrset, ttl));
+		rdata.extend_from_slice(signature.as_ref());
+		return Resource {
+			rname: owner.rname.clone(),
+			rtype: record_type::RRSIG,
+			rclass: owner.rclass,
+			ttl,
+			rdata,
+		};
+	}
+}
+
+/// https://tools.ietf.org/html/rfc4034#section-2.1
+pub fn dnskey_rdata(public_key: &[u8]) -> Vec<u8> {
+	let mut rdata = vec![];
+	rdata.write_u16::<BigEndian>(DNSKEY_FLAGS).unwrap();
+	rdata.push(DNSKEY_PROTOCOL);
+	rdata.push(ED25519);
+	rdata.extend_from_slice(public_key);
+	return rdata;
+}
+
+/// https://tools.ietf.org/html/rfc4034#appendix-B
+pub fn key_tag(dnskey_rdata: &[u8]) -> u16 {
+	let mut sum: u32 = 0;
+	for (index, byte) in dnskey_rdata.iter().enumerate() {
+		sum += if index & 1 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+	}
+	sum += (sum >> 16) & 0xffff;
+	return sum as u16;
+}
+
+/// The rdata of an NSEC record, saying that `next` is the next name in the zone and which types
+/// the owner has.
+///
+/// https://tools.ietf.org/html/rfc4034#section-4.1
+pub fn nsec_rdata(next: &[String], types: &[u16]) -> Vec<u8> {
+	let mut rdata = canonical_name(next);
+	let mut types = types.to_vec();
+	types.sort();
+	types.dedup();
+	// a window of up to 32 bytes for each block of 256 types that has any
+	let mut index = 0;
+	while index < types.len() {
+		let window = types[index] >> 8;
+		let mut bitmap = vec![];
+		while index < types.len() && types[index] >> 8 == window {
+			let bit = (types[index] & 0xff) as usize;
+			if bitmap.len() <= bit / 8 { bitmap.resize(bit / 8 + 1, 0); }
+			bitmap[bit / 8] |= 0x80 >> (bit % 8);
+			index += 1;
+		}
+		rdata.push(window as u8);
+		rdata.push(bitmap.len() as u8);
+		rdata.append(&mut bitmap);
+	}
+	return rdata;
+}
+
+/// https://tools.ietf.org/html/rfc4034#section-6.2
+fn canonical_name(name: &[String]) -> Vec<u8> {
+	return protocol::serialize_name(name.iter().map(String::as_str)).to_ascii_lowercase();
+}
+
+/// Lowercases the names in the rdata of the types that have them. Names in rdata are never
+/// compressed here, and as label lengths are below 64 they're left alone by lowercasing.
+///
+/// https://tools.ietf.org/html/rfc4034#section-6.2
+pub fn canonical_rdata(rtype: u16, rdata: &[u8]) -> Vec<u8> {
+	fn lowercase_name(rdata: &mut [u8], mut offset: usize) -> usize {
+		while offset < rdata.len() && rdata[offset] != 0 {
+			let end = (offset + 1 + rdata[offset] as usize).min(rdata.len());
+			rdata[offset + 1..end].make_ascii_lowercase();
+			offset = end;
+		}
+		return offset + 1;
+	}
+	fn skip_strings(rdata: &[u8], mut offset: usize, count: usize) -> usize {
+		for _ in 0..count {
+			offset += 1 + rdata.get(offset).copied().unwrap_or(0) as usize;
+		}
+		return offset;
+	}
+	
+	let mut rdata = rdata.to_vec();
+	match rtype {
+		record_type::NS | record_type::CNAME | record_type::PTR => { lowercase_name(&mut rdata, 0); }
+		record_type::MX => { lowercase_name(&mut rdata, 2); }
+		record_type::SRV => { lowercase_name(&mut rdata, 6); }
+		record_type::SOA => {
+			let offset = lowercase_name(&mut rdata, 0);
+			lowercase_name(&mut rdata, offset);
+		}
+		record_type::NAPTR => {
+			let offset = skip_strings(&rdata, 4, 3);
+			lowercase_name(&mut rdata, offset);
+		}
+		_ => {}
+	}
+	return rdata;
+}
+
+/// What an RRSIG signs: its own rdata up to the signature, followed by the records in canonical
+/// form and order, with the TTL they're signed with.
+///
+/// https://tools.ietf.org/html/rfc4034#section-3.1.8.1
+pub fn signed_data(rrsig_rdata: &[u8], rrset: &[Resource], ttl: u32) -> Vec<u8> {
+	let mut rdatas: Vec<Vec<u8>> = rrset.iter().map(|record| canonical_rdata(record.rtype, &record.rdata)).collect();
+	rdatas.sort();
+	rdatas.dedup();
+	
+	let owner = canonical_name(&rrset[0].rname);
+	let mut data = rrsig_rdata.to_vec();
+	for rdata in rdatas {
+		data.extend_from_slice(&owner);
+		data.write_u16::<BigEndian>(rrset[0].rtype).unwrap();
+		data.write_u16::<BigEndian>(rrset[0].rclass).unwrap();
+		data.write_u32::<BigEndian>(ttl).unwrap();
+		data.write_u16::<BigEndian>(rdata.len() as u16).unwrap();
+		data.extend_from_slice(&rdata);
+	}
+	return data;
+}
+
+#[cfg(test)]
+mod test {
+	use ring::signature::{ED25519, UnparsedPublicKey};
+	
+	use crate::server::dnssec::{canonical_rdata, key_tag, nsec_rdata, signed_data, SigningKey};
+	use crate::server::protocol::{self, record_type, Resource};
+	
+	fn hex(string: &str) -> Vec<u8> {
+		return (0..string.len()).step_by(2).map(|index| u8::from_str_radix(&string[index..index + 2], 16).unwrap()).collect();
+	}
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').map(str::to_string).collect();
+	}
+	
+	fn test_key() -> SigningKey {
+		let seed: Vec<u8> = (0..32).collect();
+		return SigningKey::from_seed(&seed).unwrap();
+	}
+	
+	#[test]
+	fn test_dnskey() {
+		let key = test_key();
+		assert_eq!(key.dnskey_rdata(), hex("0101030f03a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b8"));
+		assert_eq!(key.tag, 34259);
+		assert_eq!(key_tag(&key.dnskey_rdata()), 34259);
+		assert!(SigningKey::from_seed(&[0; 31]).is_none());
+	}
+	
+	#[test]
+	fn test_sign() {
+		let key = test_key();
+		let record = |ttl: u32, address: [u8; 4]| Resource {
+			rname: name("Example.COM"),
+			rtype: record_type::A,
+			rclass: 1,
+			ttl,
+			rdata: address.to_vec(),
+		};
+		// out of order, with differing TTLs and a duplicate, all of which are canonicalized away
+		let rrset = vec![record(600, [10, 0, 0, 2]), record(300, [10, 0, 0, 1]), record(300, [10, 0, 0, 2])];
+		let rrsig = key.sign(&rrset, &name("example.com"), 1700000000, 1700604800);
+		assert_eq!(rrsig.rname, name("Example.COM"));
+		assert_eq!(rrsig.rtype, record_type::RRSIG);
+		assert_eq!(rrsig.ttl, 300);
+		
+		let data = hex("00010f020000012c655d2b806553f10085d3076578616d706c6503636f6d00076578616d706c6503636f6d00000100010000012c00040a000001076578616d706c6503636f6d00000100010000012c00040a000002");
+		let signature = hex("02fa3b5618bbc6926ca2de77db8fba75df7b54d1a7d3fe09af597ddc85e92665b7b3228723b1b7a812cfec491183e50eb102ef59bf2d4e1b9afbf8a0956dd50b");
+		assert_eq!(signed_data(&rrsig.rdata[..rrsig.rdata.len() - 64], &rrset, 300), data);
+		assert_eq!(rrsig.rdata, [&data[..31], &signature[..]].concat());
+		
+		let public_key = UnparsedPublicKey::new(&ED25519, key.dnskey_rdata()[4..].to_vec());
+		assert!(public_key.verify(&data, &signature).is_ok());
+	}
+	
+	#[test]
+	fn test_sign_wildcard() {
+		let rrset = vec![Resource {
+			rname: name("*.example.com"),
+			rtype: record_type::TXT,
+			rclass: 1,
+			ttl: 60,
+			rdata: protocol::serialize_txt("hi"),
+		}];
+		let rrsig = test_key().sign(&rrset, &name("example.com"), 0, 1);
+		assert_eq!(rrsig.rdata[3], 2);
+	}
+	
+	#[test]
+	fn test_canonical_rdata() {
+		assert_eq!(canonical_rdata(record_type::MX, &protocol::serialize_mx("Mail.Example.com", 10)), protocol::serialize_mx("mail.example.com", 10));
+		assert_eq!(
+			canonical_rdata(record_type::SOA, &protocol::serialize_soa("NS1.example.com", "Admin.example.com", 0x41424344, 1, 2, 3, 4)),
+			protocol::serialize_soa("ns1.example.com", "admin.example.com", 0x41424344, 1, 2, 3, 4),
+		);
+		assert_eq!(
+			canonical_rdata(record_type::NAPTR, &protocol::serialize_naptr(1, 2, "U", "E2U+SIP", "!^.*$!sip:A@B!", "SIP.example.com")),
+			protocol::serialize_naptr(1, 2, "U", "E2U+SIP", "!^.*$!sip:A@B!", "sip.example.com"),
+		);
+		assert_eq!(canonical_rdata(record_type::TXT, b"\x02Hi"), b"\x02Hi");
+	}
+	
+	#[test]
+	fn test_nsec() {
+		// https://tools.ietf.org/html/rfc4034#section-4.3
+		assert_eq!(
+			nsec_rdata(&name("host.example.com"), &[record_type::A, record_type::MX, record_type::RRSIG, record_type::NSEC, 1234]),
+			[&protocol::serialize_name(vec!["host", "example", "com"])[..], &hex("0006400100000003041b000000000000000000000000000000000000000000000000000020")[..]].concat(),
+		);
+		assert_eq!(
+			nsec_rdata(&name("\u{0}.example.com"), &[record_type::RRSIG, record_type::NSEC, record_type::NXNAME]),
+			[&protocol::serialize_name(vec!["\u{0}", "example", "com"])[..], &hex("00110000000000030000000000000000000080")[..]].concat(),
+		);
+	}
+}
@@ -0,0 +1,109 @@
+//! Extended DNS Errors (https://tools.ietf.org/html/rfc8914), an EDNS option saying why a query
+//! failed, since an rcode like SERVFAIL alone doesn't say whether a resolver timed out or the config
+//! has a loop. It's only sent to clients that sent EDNS themselves.
+//!
+//! As with traces, the error is kept in a thread local by whatever runs into it, however deep, for
+//! the response to pick up once the request has been answered.
+
+use std::cell::RefCell;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::server::protocol::EdnsOption;
+
+/// https://tools.ietf.org/html/rfc8914#section-2
+pub const OPTION_CODE: u16 = 15;
+
+/// Info codes, from https://tools.ietf.org/html/rfc8914#section-4
+pub mod info_code {
+	pub const OTHER: u16 = 0;
+	pub const PROHIBITED: u16 = 18;
+	pub const NOT_AUTHORITATIVE: u16 = 20;
+	pub const NO_REACHABLE_AUTHORITY: u16 = 22;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedError {
+	pub info_code: u16,
+	/// Said for whoever's debugging, rather than for the client to act on. May be empty.
+	pub extra_text: String,
+}
+
+impl ExtendedError {
+	pub fn to_option(&self) -> EdnsOption {
+		let mut data = vec![];
+		data.write_u16::<BigEndian>(self.info_code).unwrap();
+		data.extend_from_slice(self.extra_text.as_bytes());
+		return EdnsOption { code: OPTION_CODE, data };
+	}
+}
+
+thread_local! {
+	static ERROR: RefCell<Option<ExtendedError>> = const { RefCell::new(None) };
+}
+
+/// Starts a request on this thread, throwing away the error of one before it, which may not have
+/// finished if it panicked or was answered without a response, as with `resolve`.
+pub fn start() {
+	ERROR.with(|error| *error.borrow_mut() = None);
+}
+
+/// Sets the error of the request being answered, unless it already has one. The first is kept, as
+/// it's the first question that fails whose rcode is answered with.
+pub fn set(info_code: u16, extra_text: &str) {
+	ERROR.with(|error| {
+		let mut error = error.borrow_mut();
+		if error.is_none() {
+			*error = Some(ExtendedError { info_code, extra_text: extra_text.to_string() });
+		}
+	});
+}
+
+/// The error of the request being answered, if something set one.
+pub fn finish() -> Option<ExtendedError> {
+	return ERROR.with(|error| error.borrow_mut().take());
+}
+
+#[cfg(test)]
+mod test {
+	use byteorder::{BigEndian, ReadBytesExt};
+	
+	use crate::server::ede::{ExtendedError, finish, info_code, OPTION_CODE, set, start};
+	use crate::server::protocol::{Edns, EdnsOption, make_message_from_question, parse, Question, serialize};
+	
+	/// The error in an option, or `None` if it isn't an Extended DNS Error.
+	fn from_option(option: &EdnsOption) -> Option<ExtendedError> {
+		if option.code != OPTION_CODE || option.data.len() < 2 { return None; }
+		return Some(ExtendedError {
+			info_code: (&option.data[..2]).read_u16::<BigEndian>().unwrap(),
+			extra_text: String::from_utf8_lossy(&option.data[2..]).into_owned(),
+		});
+	}
+	
+	#[test]
+	fn test_set() {
+		start();
+		assert_eq!(finish(), None);
+		set(info_code::NO_REACHABLE_AUTHORITY, "");
+		set(info_code::OTHER, "later");
+		assert_eq!(finish(), Some(ExtendedError { info_code: info_code::NO_REACHABLE_AUTHORITY, extra_text: String::new() }));
+		assert_eq!(finish(), None);
+	}
+	
+	#[test]
+	fn test_round_trip() {
+		let error = ExtendedError { info_code: info_code::OTHER, extra_text: "CNAME chain too long".to_string() };
+		let mut message = make_message_from_question(vec![Question { qname: vec!["example".to_string(), "com".to_string()], qtype: 1, qclass: 1 }]);
+		message.edns = Some(Edns { udp_payload_size: 1232, extended_rcode_and_flags: 0, options: vec![error.to_option()] });
+		let parsed = parse(&serialize(&message, 512)).unwrap();
+		let options = parsed.edns.unwrap().options;
+		assert_eq!(options.len(), 1);
+		assert_eq!(options[0].code, OPTION_CODE);
+		assert_eq!(&options[0].data[..2], &[0, 0]);
+		assert_eq!(from_option(&options[0]), Some(error));
+		
+		assert_eq!(from_option(&EdnsOption { code: OPTION_CODE, data: vec![0, 22] }), Some(ExtendedError { info_code: 22, extra_text: String::new() }));
+		assert_eq!(from_option(&EdnsOption { code: OPTION_CODE, data: vec![0] }), None);
+		assert_eq!(from_option(&EdnsOption { code: 10, data: vec![0, 22] }), None);
+	}
+}
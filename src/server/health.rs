@@ -0,0 +1,102 @@
+//! A minimal HTTP server for liveness and readiness probes, e.g. from Kubernetes. It only knows three
+//! paths: `/healthz`, which answers as long as the server is running, `/readyz`, which also
+//! requires the server to be able to answer queries, and `/stats`, which has the query statistics
+//! as JSON.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::server::stats::Stats;
+
+/// Longest request line or header line read before giving up on the request.
+const MAX_LINE_LENGTH: u64 = 1024;
+
+/// Answers a single HTTP request on the stream, then closes it. `is_ready` is only called for
+/// `/readyz`.
+pub fn handle_connection<S: Read + Write, F: Fn() -> bool>(mut stream: S, is_ready: F, stats: &Stats) -> io::Result<()> {
+	let path = read_request_path(&mut stream)?;
+	let (status, content_type, body) = match path.as_deref() {
+		Some("/healthz") => ("200 OK", "text/plain", "ok\n".to_string()),
+		Some("/readyz") => if is_ready() {
+			("200 OK", "text/plain", "ready\n".to_string())
+		} else {
+			("503 Service Unavailable", "text/plain", "not ready\n".to_string())
+		},
+		Some("/stats") => ("200 OK", "application/json", format!("{}\n", stats.json())),
+		Some(_) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+		None => ("400 Bad Request", "text/plain", "bad request\n".to_string()),
+	};
+	write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)?;
+	return stream.flush();
+}
+
+/// Reads the request line and headers, returning the path if it's a GET request.
+fn read_request_path<S: Read>(stream: &mut S) -> io::Result<Option<String>> {
+	let mut reader = BufReader::new(stream);
+	let mut request_line = String::new();
+	(&mut reader).take(MAX_LINE_LENGTH).read_line(&mut request_line)?;
+	
+	// the headers don't matter, but are read so the client isn't reset for closing with them unread
+	loop {
+		let mut line = String::new();
+		if (&mut reader).take(MAX_LINE_LENGTH).read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+			break;
+		}
+	}
+	
+	let mut parts = request_line.split_whitespace();
+	return Ok(match (parts.next(), parts.next()) {
+		(Some("GET"), Some(path)) => {
+			// the query string isn't used
+			Some(path.split('?').next().unwrap().to_string())
+		}
+		_ => None,
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::{self, Cursor, Read, Write};
+	
+	use crate::server::health::handle_connection;
+	use crate::server::stats::Stats;
+	
+	/// Reads from the request and writes to a buffer, like a TCP stream would.
+	struct Stream {
+		request: Cursor<Vec<u8>>,
+		response: Vec<u8>,
+	}
+	
+	impl Read for Stream {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			return self.request.read(buf);
+		}
+	}
+	
+	impl Write for Stream {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			return self.response.write(buf);
+		}
+		
+		fn flush(&mut self) -> io::Result<()> {
+			return Ok(());
+		}
+	}
+	
+	fn request(request: &str, ready: bool) -> String {
+		let mut stream = Stream { request: Cursor::new(request.as_bytes().to_vec()), response: vec![] };
+		handle_connection(&mut stream, || ready, &Stats::default()).unwrap();
+		return String::from_utf8(stream.response).unwrap();
+	}
+	
+	#[test]
+	fn test_paths() {
+		assert!(request("GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n", false).starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(request("GET /readyz HTTP/1.1\r\n\r\n", true).starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(request("GET /readyz?verbose HTTP/1.1\r\n\r\n", false).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+		assert!(request("GET / HTTP/1.1\r\n\r\n", true).starts_with("HTTP/1.1 404 Not Found\r\n"));
+		assert!(request("POST /healthz HTTP/1.1\r\n\r\n", true).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert!(request("", true).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert!(request("GET /healthz HTTP/1.1\r\n\r\n", true).ends_with("\r\n\r\nok\n"));
+		assert!(request("GET /stats HTTP/1.1\r\n\r\n", true).contains("Content-Type: application/json\r\n"));
+	}
+}
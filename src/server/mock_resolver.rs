@@ -0,0 +1,170 @@
+//! A DNS server for tests to stand in for resolvers, RNS servers, and forwarders, answering the
+//! questions it's given canned replies for and recording every one it's asked.
+
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::server::{read_tcp_message, write_tcp_message};
+use crate::server::protocol::{self, Message, Question, Resource};
+
+/// How a question is replied to.
+#[derive(Debug, Clone)]
+pub enum Reply {
+	/// With the sections and rcode of this message. One too big for UDP is truncated there, and
+	/// answered in full over TCP.
+	Message(Message),
+	/// With nothing but this rcode.
+	Rcode(u16),
+	/// Not at all, so that the client times out.
+	Timeout,
+	/// As `Message`, but only after this long, for a resolver that's slow to respond. Nothing else
+	/// is answered in the meantime.
+	Slow(Duration, Message),
+}
+
+#[derive(Default)]
+struct State {
+	replies: HashMap<Question, Reply>,
+	/// How questions without a reply of their own are replied to, or refused if it's `None`.
+	otherwise: Option<Reply>,
+	received: Vec<Question>,
+	/// Connections of queries that time out, kept open until the resolver is dropped.
+	held: Vec<TcpStream>,
+}
+
+/// Listens over UDP and TCP on the same ephemeral loopback port. Questions without a reply are
+/// refused, unless `reply_otherwise` says otherwise. Names are compared in lowercase, as the server asks resolvers in lowercase anyway.
+pub struct MockResolver {
+	pub address: SocketAddr,
+	state: Arc<Mutex<State>>,
+	stop: Arc<AtomicBool>,
+	threads: Vec<JoinHandle<()>>,
+}
+
+impl MockResolver {
+	pub fn new() -> MockResolver {
+		let (udp, tcp, address) = loop {
+			let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+			let address = udp.local_addr().unwrap();
+			if let Ok(tcp) = TcpListener::bind(address) {
+				break (udp, tcp, address);
+			}
+		};
+		let state = Arc::new(Mutex::new(State::default()));
+		let stop = Arc::new(AtomicBool::new(false));
+		
+		let udp_thread = {
+			let state = state.clone();
+			let stop = stop.clone();
+			thread::spawn(move || {
+				let mut buf = vec![0; 512];
+				while let Ok((size, src)) = udp.recv_from(&mut buf) {
+					if stop.load(Ordering::SeqCst) { break; }
+					if let Some(response) = respond(&state, &buf[..size], 512) {
+						let _ = udp.send_to(&response, src);
+					}
+				}
+			})
+		};
+		let tcp_thread = {
+			let state = state.clone();
+			let stop = stop.clone();
+			thread::spawn(move || {
+				for stream in tcp.incoming() {
+					if stop.load(Ordering::SeqCst) { break; }
+					let mut stream = match stream {
+						Ok(stream) => stream,
+						Err(_) => continue,
+					};
+					let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+					while let Ok(request) = read_tcp_message(&mut stream) {
+						match respond(&state, &request, u16::MAX) {
+							Some(response) => if write_tcp_message(&mut stream, &response).is_err() { break; },
+							None => {
+								state.lock().unwrap().held.push(stream);
+								break;
+							}
+						}
+					}
+				}
+			})
+		};
+		
+		return MockResolver { address, state, stop, threads: vec![udp_thread, tcp_thread] };
+	}
+	
+	/// Replies to `question` with `reply` from now on.
+	pub fn reply(&self, question: Question, reply: Reply) {
+		self.state.lock().unwrap().replies.insert(lowercase(question), reply);
+	}
+	
+	/// Replies to every question without a reply of its own with `reply` from now on.
+	pub fn reply_otherwise(&self, reply: Reply) {
+		self.state.lock().unwrap().otherwise = Some(reply);
+	}
+	
+	/// Replies to `question` with `answer` as the answer section.
+	pub fn answer(&self, question: Question, answer: Vec<Resource>) {
+		self.reply(question, Reply::Message(Message { answer, ..Message::default() }));
+	}
+	
+	/// Every question asked so far, over either transport, in the order they came in.
+	pub fn received(&self) -> Vec<Question> {
+		return self.state.lock().unwrap().received.clone();
+	}
+}
+
+impl Drop for MockResolver {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::SeqCst);
+		// wakes both threads up to see they're done
+		if let Ok(socket) = UdpSocket::bind("127.0.0.1:0") {
+			let _ = socket.send_to(&[], self.address);
+		}
+		let _ = TcpStream::connect(self.address);
+		for thread in self.threads.drain(..) {
+			let _ = thread.join();
+		}
+		for stream in self.state.lock().unwrap().held.drain(..) {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+fn lowercase(question: Question) -> Question {
+	return Question {
+		qname: question.qname.iter().map(|label| label.to_lowercase()).collect(),
+		..question
+	};
+}
+
+/// The response to a request, or `None` if it's to time out.
+fn respond(state: &Mutex<State>, request: &[u8], max_size: u16) -> Option<Vec<u8>> {
+	let mut message = protocol::parse(request).ok()?;
+	let question = message.question.first().cloned()?;
+	let reply = {
+		let mut state = state.lock().unwrap();
+		state.received.push(question.clone());
+		state.replies.get(&lowercase(question)).cloned().or_else(|| state.otherwise.clone())
+	};
+	message.header.qr = true;
+	if let Some(Reply::Slow(delay, _)) = &reply {
+		thread::sleep(*delay);
+	}
+	match reply {
+		Some(Reply::Message(reply)) | Some(Reply::Slow(_, reply)) => {
+			message.header.rcode = reply.header.rcode;
+			message.answer = reply.answer;
+			message.authority = reply.authority;
+			message.additional = reply.additional;
+		}
+		Some(Reply::Rcode(rcode)) => message.header.rcode = rcode,
+		Some(Reply::Timeout) => return None,
+		None => message.header.rcode = 5,
+	}
+	return Some(protocol::serialize(&message, max_size));
+}
@@ -1,1247 +1,7489 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Write};
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::fs::{self, Permissions};
+use std::io::{self, Read, Write};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
-use std::sync::{Arc, Mutex};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use reqwest::Url;
-use threadpool::ThreadPool;
+use serde::{Deserialize, Serialize};
 
 use protocol::Resource;
 
-use crate::config::{Config, Label, RnsHost, ZoneMatcher};
-use crate::config::Label::{AllWildcard, SubWildcard, Wildcard};
+use crate::config::{Config, DEFAULT_EXPIRE, DEFAULT_REFRESH, DEFAULT_RETRY, Dnssec, Label, NsRecord, RnsHost, SvcParams, ttl_secs, Zone, zone_name, ZoneIndex, ZoneMatcher};
+use crate::config::matching::{does_match, does_match_labels, plain_name};
 use crate::options::Options;
-use crate::server::protocol::{Question, record_type};
+use crate::server::cache::Cache;
+use crate::server::dnssec::SigningKey;
+use crate::server::protocol::{class, Edns, Header, Message, opcode, ParseError, Question, record_type, svc_param};
+use crate::server::query_log::{QueryLog, type_name};
+use crate::server::rrl::{RateLimiter, Verdict};
+use crate::server::shared::{Answering, SharedConfig};
+use crate::server::stats::Stats;
+use crate::server::timing::{Phase, SlowQuery};
+use crate::server::upstream::{Counted, NoRecursion};
+use crate::server::workers::{describe_request, Workers};
 
-mod protocol;
+mod access;
+pub mod answers;
+mod activation;
+mod api;
+mod bind;
+mod cache;
+mod checks;
+mod dnssec;
+mod ede;
+mod health;
+#[cfg(test)]
+mod mock_resolver;
+mod notify;
+mod pktinfo;
+mod prefetch;
+mod privileges;
+pub mod protocol;
+mod query_log;
+mod rrl;
+mod shared;
+mod signal;
+mod startup;
+pub mod stats;
+mod tls;
+mod trace;
+mod timing;
+mod tsig;
+mod update;
+mod upstream;
+mod views;
+mod workers;
 
-pub fn serve(options: Options, config: Config) {
-	let udp_socket = UdpSocket::bind((options.listen_address, options.listen_port)).unwrap();
-	let tcp_socket = TcpListener::bind((options.listen_address, options.listen_port)).unwrap();
-	
-	assert!(options.threads >= 1, "Thread count must be >=1");
-	let pool = Arc::new(Mutex::new(ThreadPool::with_name("worker".to_string(), options.threads)));
-	
-	let udp = {
-		let pool = pool.clone();
-		let options = options.clone();
-		let config = config.clone();
-		thread::Builder::new().name("UDP server".to_string()).spawn(move || {
-			loop {
-				let mut buf = vec![0; 512];
-				let (_size, src) = udp_socket.recv_from(&mut buf).unwrap();
-				if options.verbose { println!("handling UDP request"); }
-				
-				let options = options.clone();
-				let config = config.clone();
-				let socket = udp_socket.try_clone().unwrap();
-				let instant = Instant::now();
-				pool.lock().unwrap().execute(move || {
-					let message = handle_request(buf, &options, &config, false);
-					
-					socket.send_to(&message, src).unwrap();
-					if options.verbose { println!("response took: {:?}", instant.elapsed()); }
-				});
-			}
-		}).expect("failed to spawn thread")
-	};
-	
-	let tcp = thread::Builder::new().name("UDP server".to_string()).spawn(move || {
-		loop {
-			let (mut stream, _src) = tcp_socket.accept().unwrap();
-			if options.verbose { println!("handling TCP request"); }
-			
-			let message_size = stream.read_u16::<BigEndian>().unwrap();
-			let mut buf: Vec<u8> = vec![0; message_size as usize];
-			stream.read(buf.as_mut_slice()).unwrap();
-			
-			let options = options.clone();
-			let config = config.clone();
-			let instant = Instant::now();
-			pool.lock().unwrap().execute(move || {
-				let message = handle_request(buf, &options, &config, true);
-				
-				stream.write_u16::<BigEndian>(message.len() as u16).unwrap();
-				stream.write(message.as_slice()).unwrap();
-				if options.verbose { println!("response took: {:?}", instant.elapsed()); }
-			});
-		}
-	}).expect("failed to spawn thread");
-	
-	udp.join().unwrap();
-	tcp.join().unwrap();
+pub use signal::on_usr1;
+pub use startup::check_upstreams;
+pub use upstream::Upstream;
+
+/// A running server, see `serve`.
+pub struct Server {
+	/// The addresses the server is listening on, which include the actual port if port 0 was given.
+	pub addresses: Vec<SocketAddr>,
+	/// The address the DNS over TLS listener is on, if enabled.
+	pub tls_address: Option<SocketAddr>,
+	/// The address the health and readiness probes are served on, if enabled.
+	pub health_address: Option<SocketAddr>,
+	/// The address the HTTP API is served on, if enabled.
+	pub api_address: Option<SocketAddr>,
+	/// The path of the Unix domain socket the server is listening on, if enabled.
+	pub unix_path: Option<PathBuf>,
+	shutdown: Shutdown,
+	threads: Vec<thread::JoinHandle<()>>,
+	pool: Workers,
+	cache_file: Option<PathBuf>,
+	stats: Stats,
 }
 
-fn handle_request(buf: Vec<u8>, options: &Options, config: &Config, tcp: bool) -> Vec<u8> {
-	let mut message = protocol::parse(&buf);
-	if options.verbose { println!("request: {:?}", message); }
-	if message.header.qr {
-		// this is actually a response...possibly a DDoS attempt?
-		// drop it
-		panic!("Cannot process a response as a request.");
+impl Server {
+	pub fn shutdown_handle(&self) -> Shutdown {
+		return self.shutdown.clone();
 	}
 	
-	assert_eq!(message.question.len(), 1);
-	
-	let question = &message.question[0];
-	let (answer, mut authority, mut additional) = handle_dns(question, &options, &config);
-	
-	
-	if answer.is_empty() && authority.is_empty() {
-		authority.push(make_soa(&question, &config));
+	/// The query statistics, which can also be sent off to other threads.
+	pub fn stats(&self) -> Stats {
+		return self.stats.clone();
 	}
 	
-	// always fill the authority section with something
-	// be it an actual NS record from above (thus delegating this domain elsewhere)
-	// or a re-search, which would generally be used to point back to this server
-	if authority.is_empty() && question.qtype != record_type::NS {
-		let mut ns_question = question.clone();
-		ns_question.qtype = record_type::NS;
-		let (mut _answer, _, mut _additional) = handle_dns(&ns_question, options, config);
-		authority.append(&mut _answer);
-		additional.append(&mut _additional);
+	/// Waits until the server is shut down and the requests already received have been answered,
+	/// then saves the resolver cache to `--cache-file`. Open TCP connections aren't waited for.
+	pub fn join(self) {
+		for thread in self.threads {
+			thread.join().unwrap();
+		}
+		self.pool.join();
+		if let Some(cache_file) = &self.cache_file {
+			save_resolver_cache(cache_file);
+		}
 	}
-	
-	message.header.qr = true;
-	message.header.aa = true;
-	message.header.ra = false;
-	
-	message.header.rcode = 0;
-	message.answer = answer;
-	message.authority = authority;
-	message.additional = additional;
-	
-	if options.verbose { println!("response: {:?}", message); }
-	return protocol::serialize(&message, tcp);
 }
 
-#[derive(Debug, PartialEq, Clone)]
-enum Response {
-	Ok(Vec<Resource>, Vec<Resource>, Vec<Resource>),
-	#[allow(dead_code)]
-	FormatError,
-	ServerFailure,
-	NameError,
-	NotImplemented,
-	#[allow(dead_code)]
-	Refused,
+/// Stops a running server. It can be cloned and sent off to other threads, e.g. a signal handler.
+#[derive(Clone)]
+pub struct Shutdown {
+	flag: Arc<AtomicBool>,
+	/// Notified once the flag is set, to wake the threads waiting between rounds of work.
+	wake: Arc<(Mutex<()>, Condvar)>,
+	addresses: Vec<SocketAddr>,
+	/// Number of threads receiving UDP packets on each address, each of which needs waking.
+	udp_threads: usize,
+	tls_address: Option<SocketAddr>,
+	health_address: Option<SocketAddr>,
+	api_address: Option<SocketAddr>,
+	unix_path: Option<PathBuf>,
 }
 
-/// Performs a DNS query against another DNS server.
-fn resolver_lookup(question: Question, server: SocketAddr) -> Response {
-	struct CacheEntry {
-		response: (Vec<Resource>, Vec<Resource>, Vec<Resource>),
-		cache_time: Instant,
-		expiration: Instant,
-	}
-	lazy_static! {
-		static ref CACHE: Mutex<HashMap<Question, CacheEntry>> = Mutex::new(HashMap::new());
-	}
-	
-	{
-		let cache: &mut HashMap<Question, CacheEntry> = &mut *CACHE.lock().unwrap();
-		let cached = cache.get(&question);
-		if let Some(entry) = cached {
-			if entry.expiration > Instant::now() {
-				let mut response = entry.response.clone();
-				for record in response.0.iter_mut().chain(response.1.iter_mut()).chain(response.2.iter_mut()) {
-					record.ttl -= entry.cache_time.elapsed().as_secs() as u32;
-				}
-				return Response::Ok(response.0, response.1, response.2);
-			} else {
-				cache.remove(&question);
-			}
+impl Shutdown {
+	pub fn shutdown(&self) {
+		if self.flag.swap(true, Ordering::SeqCst) {
+			return;
 		}
-	}
-	
-	match TcpStream::connect(server) {
-		Err(_) => return Response::ServerFailure,
-		Ok(mut stream) => {
-			let request = protocol::serialize(&protocol::make_message_from_question(vec![question.clone()]), true);
-			stream.write_u16::<BigEndian>(request.len() as u16).unwrap();
-			stream.write(request.as_slice()).unwrap();
-			
-			let message_size = stream.read_u16::<BigEndian>().unwrap();
-			let mut buffer: Vec<u8> = vec![0; message_size as usize];
-			stream.read(buffer.as_mut_slice()).unwrap();
-			
-			let message = protocol::parse(buffer.as_slice());
-			
-			match message.header.rcode {
-				1 => return Response::FormatError,
-				2 => return Response::ServerFailure,
-				3 => return Response::NameError,
-				4 => return Response::NotImplemented,
-				5 => return Response::Refused,
-				_ => {}
-			}
-			
-			{
-				let mut least_expiration = u32::max_value();
-				for record in message.answer.iter().chain(message.authority.iter()).chain(message.additional.iter()) {
-					if record.ttl < least_expiration {
-						least_expiration = record.ttl;
-					}
-				}
-				
-				let cache: &mut HashMap<Question, CacheEntry> = &mut *CACHE.lock().unwrap();
-				cache.insert(question, CacheEntry {
-					response: (message.answer.clone(), message.authority.clone(), message.additional.clone()),
-					cache_time: Instant::now(),
-					expiration: Instant::now() + Duration::from_secs(least_expiration as u64),
-				});
-			}
-			
-			return Response::Ok(message.answer, message.authority, message.additional);
+		{
+			let (lock, condvar) = &*self.wake;
+			let _lock = lock.lock().unwrap_or_else(|error| error.into_inner());
+			condvar.notify_all();
 		}
-	}
-}
-
-fn does_match(matchers: &[ZoneMatcher], qname: &[String]) -> bool {
-	'matcher: for zone_matcher in matchers {
-		// if our matcher ends in a wildcard, assume prefix mode (e.g. _acme-challenge.**)
-		// note: this will soon be replaced with depth-first search with backtracking
-		let rev = match zone_matcher.last().unwrap() {
-			Wildcard | SubWildcard | AllWildcard => false,
-			_ => true,
-		};
-		
-		let qname = qname.iter().map(|label| label.to_lowercase());
-		let qname: Box<dyn Iterator<Item=String>> = if rev {
-			Box::new(qname.rev())
-		} else {
-			Box::new(qname)
-		};
-		let mut qname = qname.peekable();
-		
-		let labels: Box<dyn Iterator<Item=&Label>> = if rev {
-			Box::new(zone_matcher.iter().rev())
-		} else {
-			Box::new(zone_matcher.iter())
-		};
 		
-		'label: for label in labels {
-			match label {
-				Label::Basic(string) => {
-					// if this label doesn't match exactly
-					if let Some(value) = qname.next() {
-						if &value != string {
-							// try another matcher
-							continue 'matcher;
-						}
-					} else {
-						continue 'matcher;
-					}
-				}
-				Label::Regex(eager, regex) => {
-					if *eager {
-						// currently unimplemented
-						// do we want to consume everything possible or just what's needed to match?
-						// originally, I was just going to consume labels until it matched (and that's what the below code does)
-						// but that doesn't allow patterns such as /([a-z]+\.)*[a-z]+/
-						// this is because it matches the first [a-z]+ and then dies
-						unimplemented!();
-						
-						/*
-						// eager mode we keep consuming labels until we match
-						let mut growing_name = String::new();
-						loop {
-							match qname.next() {
-								Some(label) => {
-									if growing_name.len() > 0 {
-										growing_name.insert(0, '.');
-									}
-									growing_name.insert_str(0, label);
-									
-									if regex.is_match(&growing_name) {
-										// we've matched; next label
-										continue 'label;
-									}
-								}
-								None => {
-									// we're out of labels with no match
-									continue 'matcher;
-								}
-							}
-						}*/
-					} else {
-						// if this regex doesn't match
-						if let Some(label) = qname.next() {
-							if !regex.is_match(&label) {
-								// try another matcher
-								continue 'matcher;
-							}
-						} else {
-							continue 'matcher;
-						}
-					}
-				}
-				Label::Wildcard => {
-					// wildcards must match one label
-					// take one off
-					if qname.next().is_none() {
-						// if no more labels; try another matcher
-						continue 'matcher;
-					}
-				}
-				Label::SubWildcard => {
-					// sub wildcards must match at least one label
-					if qname.next().is_none() {
-						continue 'matcher;
-					}
-					
-					// and consume all of them
-					while qname.peek().is_some() {
-						qname.next();
-					}
-				}
-				Label::AllWildcard => {
-					// all wildcards can match any number of additional labels
-					
-					// drain everything
-					while qname.peek().is_some() {
-						qname.next();
-					}
-					
-					// and match successfully
-					break 'label;
+		// the listeners are blocked waiting for something to arrive, so give them something
+		fn reachable(mut address: SocketAddr) -> SocketAddr {
+			if address.ip().is_unspecified() {
+				address.set_ip(if address.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() });
+			}
+			return address;
+		}
+		for address in &self.addresses {
+			let address = reachable(*address);
+			let local_address: SocketAddr = if address.is_ipv4() {
+				(Ipv4Addr::UNSPECIFIED, 0).into()
+			} else {
+				(Ipv6Addr::UNSPECIFIED, 0).into()
+			};
+			if let Ok(socket) = UdpSocket::bind(local_address) {
+				for _ in 0..self.udp_threads {
+					let _ = socket.send_to(&[], address);
 				}
 			}
+			let _ = TcpStream::connect(address);
 		}
-		
-		// out of labels
-		
-		// ensure we're also out of names
-		if qname.peek().is_none() {
-			// great! everything lines up
-			return true;
+		for address in self.tls_address.iter().chain(self.health_address.iter()).chain(self.api_address.iter()) {
+			let _ = TcpStream::connect(reachable(*address));
 		}
+		if let Some(unix_path) = &self.unix_path {
+			let _ = UnixStream::connect(unix_path);
+		}
+	}
+	
+	fn is_shutdown(&self) -> bool {
+		return self.flag.load(Ordering::SeqCst);
 	}
 	
-	// ran out of matchers; no match
-	return false;
+	/// Waits for `timeout`, or until the server is shut down if it's sooner, returning whether it is.
+	fn wait(&self, timeout: Duration) -> bool {
+		let (lock, condvar) = &*self.wake;
+		let lock = lock.lock().unwrap_or_else(|error| error.into_inner());
+		drop(condvar.wait_timeout_while(lock, timeout, |_| !self.is_shutdown()).unwrap_or_else(|error| error.into_inner()));
+		return self.is_shutdown();
+	}
 }
 
-fn make_soa(question: &Question, config: &Config) -> Resource {
-	// https://tools.ietf.org/html/rfc1035#section-3.3.13
-	let mut mname = vec!["ns1".to_string()];
-	mname.append(&mut question.qname.clone());
-	let mut rname = vec!["hostmaster".to_string()];
-	rname.append(&mut question.qname.clone());
-	let serial: u32 = config.serial;
-	let refresh: u32 = 86400;
-	let retry: u32 = 7200;
-	let expire: u32 = 3600000;
-	let ttl = config.nttl.as_secs() as u32;
-	
-	let mut cursor = Cursor::new(Vec::new());
-	cursor.write_all(&protocol::serialize_name(mname.iter().map(|label| label.as_str()))).unwrap();
-	cursor.write_all(&protocol::serialize_name(rname.iter().map(|label| label.as_str()))).unwrap();
-	cursor.write_u32::<BigEndian>(serial).unwrap();
-	cursor.write_u32::<BigEndian>(refresh).unwrap();
-	cursor.write_u32::<BigEndian>(retry).unwrap();
-	cursor.write_u32::<BigEndian>(expire).unwrap();
-	cursor.write_u32::<BigEndian>(ttl).unwrap();
-	
-	Resource {
-		rname: question.qname.clone(),
-		rtype: 6,
-		rclass: question.qclass,
-		ttl,
-		rdata: cursor.into_inner(),
+/// Binds a UDP socket and a TCP listener on the same address.
+fn bind_listener(address: SocketAddr) -> io::Result<(UdpSocket, TcpListener)> {
+	let udp_socket = bind::bind_udp(address)?;
+	if address.ip().is_unspecified() {
+		// replies have to come from the address the client sent to, which could be any of ours
+		pktinfo::enable(&udp_socket)?;
 	}
+	// bind TCP to the same port, which matters if port 0 was given
+	let tcp_socket = bind::bind_tcp(udp_socket.local_addr()?)?;
+	return Ok((udp_socket, tcp_socket));
 }
 
-fn handle_dns(question: &Question, options: &Options, config: &Config) -> (Vec<Resource>, Vec<Resource>, Vec<Resource>) {
-	let mut answer: Vec<Resource> = Vec::new();
-	let mut authority: Vec<Resource> = Vec::new();
-	let mut additional: Vec<Resource> = Vec::new();
+/// Starts listening for requests over UDP and TCP, returning once the sockets are bound. Every
+/// socket is bound before any is served on, so a failure to bind is returned here rather than
+/// after the health probe has reported the server as healthy.
+pub fn serve(options: Options, config: Config) -> io::Result<Server> {
+	let passed = activation::listen_fds().map_err(|error| io::Error::new(error.kind(), format!("can't use the sockets systemd passed: {}", error)))?;
+	let (mut listeners, addresses) = match passed {
+		Some(passed) => {
+			if options.verbose { println!("listening on {} addresses passed by systemd", passed.len()); }
+			(passed, vec![])
+		}
+		None => (vec![], options.listen_addresses()),
+	};
+	for address in addresses {
+		let listener = match bind_listener(address) {
+			Ok(listener) => listener,
+			// an unspecified address still listens on IPv4 on a host without IPv6
+			Err(error) if address.is_ipv6() && address.ip().is_unspecified() && !options.ipv6_only && bind::is_unsupported(&error) => {
+				if options.verbose { println!("not listening on {}: {}", address, error); }
+				continue;
+			}
+			Err(error) => return Err(io::Error::new(error.kind(), format!("can't listen on {}: {}", address, error))),
+		};
+		listeners.push(listener);
+	}
+	let addresses = listeners.iter().map(|(udp_socket, _)| udp_socket.local_addr()).collect::<io::Result<Vec<SocketAddr>>>()?;
+	let listen_ip = options.listen_ip();
+	let tls = match (options.tls_port, &options.tls_cert, &options.tls_key) {
+		(None, _, _) => None,
+		(Some(port), Some(cert), Some(key)) => {
+			let tls_config = tls::load_config(cert, key)?;
+			let tls_socket = TcpListener::bind((listen_ip, port))?;
+			Some((tls_socket.local_addr()?, tls_socket, tls_config))
+		}
+		_ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "--tls-port requires --tls-cert and --tls-key")),
+	};
+	let health_socket = match options.health_port {
+		Some(port) => Some(TcpListener::bind((listen_ip, port))?),
+		None => None,
+	};
+	let api = match options.api_port {
+		Some(port) => match env::var(&options.api_token_env) {
+			Ok(token) if !token.is_empty() => Some((TcpListener::bind((options.api_address, port))?, token)),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("--api-port requires a token in ${}", options.api_token_env))),
+		},
+		None => None,
+	};
+	let unix_socket = match &options.listen_unix {
+		Some(path) => Some(bind_unix(Path::new(path), options.unix_mode)?),
+		None => None,
+	};
+	privileges::drop_privileges(options.user.as_deref(), options.group.as_deref(), &privileges::Libc)
+		.map_err(|error| io::Error::new(error.kind(), format!("can't drop privileges: {}", error)))?;
 	
-	let qname: String = question.qname.join(".");
+	if options.threads == 0 {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "--threads must be at least 1"));
+	}
+	let cache_file = options.cache_file.as_ref().map(PathBuf::from);
+	if let Some(cache_file) = &cache_file {
+		load_resolver_cache(cache_file, &options);
+	}
+	let pool = Workers::new(options.threads);
+	let udp_threads = options.udp_threads.unwrap_or(options.threads).max(1);
+	let shutdown = Shutdown {
+		flag: Arc::new(AtomicBool::new(false)),
+		wake: Arc::default(),
+		addresses: addresses.clone(),
+		udp_threads,
+		tls_address: tls.as_ref().map(|(tls_address, _, _)| *tls_address),
+		health_address: match &health_socket {
+			Some(health_socket) => Some(health_socket.local_addr()?),
+			None => None,
+		},
+		api_address: match &api {
+			Some((api_socket, _)) => Some(api_socket.local_addr()?),
+			None => None,
+		},
+		unix_path: options.listen_unix.as_ref().map(PathBuf::from),
+	};
 	
-	for zone in &config.zones {
-		if does_match(&zone.matchers, &question.qname) {
-			match question.qtype {
-				// CNAME
-				_ if !zone.records.cname.is_empty() => {
-					for cname in &zone.records.cname {
-						// add the CNAME to our result
-						answer.push(Resource {
-							rname: question.qname.clone(),
-							rtype: record_type::CNAME,
-							rclass: question.qclass,
-							ttl: cname.ttl.as_secs() as u32,
-							rdata: protocol::serialize_name(cname.name.split('.')),
-						});
-						
-						// follow the CNAME and lookup records there
-						// (Note that this might trigger a stack overflow. We aren't handling this
-						// right now and shouldn't be considered a security issue. It's the fault of
-						// the configurer for not configuring it right.)
-						let question = Question {
-							qname: cname.name.split(".").map(|label| label.to_string()).collect(),
-							qtype: question.qtype,
-							qclass: 1,
-						};
-						let (mut cname_answer, _, _) = handle_dns(&question, options, config);
-						if cname_answer.len() > 0 {
-							answer.append(&mut cname_answer);
-						} else {
-							if let Response::Ok(mut cname_answer, _, _) = resolver_lookup(question, options.resolver) {
-								answer.append(&mut cname_answer);
+	// shared with the API and updates, which swap in changed copies
+	let config = SharedConfig::new(config);
+	
+	let limiter = options.rrl_rate.map(|rate| Arc::new(Mutex::new(RateLimiter::new(rate, options.rrl_burst, options.rrl_slip))));
+	// shared by TCP and TLS, which both hold a thread and a socket for each connection
+	let tcp_connections = Arc::new(AtomicUsize::new(0));
+	// shared by every UDP socket, so that a client can't get around the limit with several
+	let client_jobs = Arc::new(Mutex::new(HashMap::new()));
+	let mut threads = vec![];
+	
+	// every address gets its own receiving threads, which all hand requests to the same pool
+	for (udp_socket, tcp_socket) in listeners {
+		// the socket is shared by the receiving threads, and by the workers to reply from
+		let udp_socket = Arc::new(udp_socket);
+		for _ in 0..udp_threads {
+			let udp_socket = udp_socket.clone();
+			let limiter = limiter.clone();
+			let client_jobs = client_jobs.clone();
+			let pool = pool.clone();
+			let options = options.clone();
+			let config = config.clone();
+			let shutdown = shutdown.clone();
+			threads.push(thread::Builder::new().name("UDP server".to_string()).spawn(move || {
+				loop {
+					let mut buf = vec![0; MAX_UDP_RECEIVE.max(options.max_udp_payload) as usize];
+					let received = pktinfo::recv_from(&udp_socket, &mut buf);
+					if shutdown.is_shutdown() {
+						break;
+					}
+					let (size, src, local) = match received {
+						Ok(received) => received,
+						Err(error) => {
+							if options.verbose { println!("failed to receive UDP packet: {:?}", error); }
+							continue;
+						}
+					};
+					buf.truncate(size);
+					
+					let verdict = limiter.as_ref().map(|limiter| limiter.lock().unwrap().check(src.ip(), Instant::now())).unwrap_or(Verdict::Allow);
+					if verdict != Verdict::Allow {
+						if options.verbose { println!("rate limited UDP request from {}: {:?}", src, verdict); }
+						if let Some(message) = make_slip_response(&buf).filter(|_| verdict == Verdict::Slip) {
+							if let Err(error) = pktinfo::send_to(&udp_socket, &message, src, local) {
+								if options.verbose { println!("failed to send UDP response: {:?}", error); }
 							}
 						}
+						continue;
 					}
-				}
-				
-				// ANAME
-				record_type::A | record_type::AAAA if !zone.records.aname.is_empty() => {
-					for aname in &zone.records.aname {
-						// follow the ANAME and lookup records there
-						// (Note that this might trigger a stack overflow. We aren't handling this
-						// right now and shouldn't be considered a security issue. It's the fault of
-						// the configurer for not configuring it right.)
-						let question = Question {
-							qname: aname.name.split(".").map(|label| label.to_string()).collect(),
-							qtype: question.qtype,
-							qclass: 1,
-						};
-						let mut response = handle_dns(&question, options, config);
-						if response.0.len() == 0 {
-							if let Response::Ok(answer, authority, additional) = resolver_lookup(question, options.resolver) {
-								response = (answer, authority, additional);
-							}
+					let slot = match ClientSlot::take(&client_jobs, src.ip(), options.max_client_jobs) {
+						Some(slot) => slot,
+						None => {
+							if options.verbose { println!("dropping UDP request from {}, as it has {} waiting already", src, options.max_client_jobs); }
+							continue;
 						}
-						let aname_answer = response.0;
-						if aname_answer.len() > 0 {
-							let qname_split: Vec<String> = qname.split(".").map(|label| label.to_string()).collect();
-							for mut resource in aname_answer {
-								resource.rname = qname_split.clone();
-								answer.push(resource);
+					};
+					if options.verbose { println!("handling UDP request"); }
+					
+					let options = options.clone();
+					let config = config.clone();
+					let socket = udp_socket.clone();
+					let instant = Instant::now();
+					let request = buf.clone();
+					pool.execute(move || describe_request(&request, Transport::Udp, src), move || {
+						let _slot = slot;
+						for message in respond(&buf, &options, &config, Transport::Udp, src) {
+							if let Err(error) = pktinfo::send_to(&socket, &message, src, local) {
+								if options.verbose { println!("failed to send UDP response: {:?}", error); }
 							}
 						}
-					}
-				}
-				
-				// A
-				record_type::A => {
-					for a in &zone.records.a {
-						answer.push(Resource {
-							rname: question.qname.clone(),
-							rtype: question.qtype,
-							rclass: question.qclass,
-							ttl: a.ttl.as_secs() as u32,
-							rdata: a.ip4addr.octets().to_vec(),
-						});
-					}
+						if options.verbose { println!("response to {} took: {:?}", src, instant.elapsed()); }
+					});
 				}
-				
-				// AAAA
-				record_type::AAAA => {
-					for aaaa in &zone.records.aaaa {
-						answer.push(Resource {
-							rname: question.qname.clone(),
-							rtype: question.qtype,
-							rclass: question.qclass,
-							ttl: aaaa.ttl.as_secs() as u32,
-							rdata: aaaa.ip6addr.octets().to_vec(),
-						});
+			})?);
+		}
+		
+		
+		let tcp = {
+			let options = options.clone();
+			let config = config.clone();
+			let tcp_connections = tcp_connections.clone();
+			let shutdown = shutdown.clone();
+			thread::Builder::new().name("TCP server".to_string()).spawn(move || {
+				loop {
+					let accepted = tcp_socket.accept();
+					if shutdown.is_shutdown() {
+						break;
 					}
-				}
-				
-				// NS
-				record_type::NS => {
-					for ns in &zone.records.ns {
-						// add the NS to the response
-						answer.push(Resource {
-							rname: question.qname.clone(),
-							rtype: question.qtype,
-							rclass: question.qclass,
-							ttl: ns.ttl.as_secs() as u32,
-							rdata: protocol::serialize_name(ns.name.split('.')),
-						});
-						
-						// lookup A and AAAA records for this to go in the additional section
-						let string_labels: Vec<String> = ns.name.split('.').map(|label| label.to_string()).collect();
-						
-						// lookup A
-						let (mut answer, _, _) = handle_dns(&Question {
-							qname: string_labels.clone(),
-							qtype: record_type::A,
-							qclass: 1,
-						}, options, config);
-						if answer.len() > 0 {
-							additional.append(&mut answer);
+					let (stream, src) = match accepted {
+						Ok(connection) => connection,
+						Err(error) => {
+							if options.verbose { println!("failed to accept TCP connection: {:?}", error); }
+							continue;
 						}
-						
-						// lookup AAAA
-						let (mut answer, _, _) = handle_dns(&Question {
-							qname: string_labels,
-							qtype: record_type::AAAA,
-							qclass: 1,
-						}, options, config);
-						if answer.len() > 0 {
-							additional.append(&mut answer);
+					};
+					// closed right away, so the client can go elsewhere rather than waiting
+					let slot = match ConnectionSlot::take(&tcp_connections, options.max_tcp_conns) {
+						Some(slot) => slot,
+						None => {
+							if options.verbose { println!("closing TCP connection from {}, as {} are already open", src, options.max_tcp_conns); }
+							continue;
 						}
+					};
+					if options.verbose { println!("handling TCP connection"); }
+					
+					// connections are handled on their own thread as they may sit idle for a while, which
+					// would otherwise starve the pool
+					let spawned = {
+						let options = options.clone();
+						let config = config.clone();
+						thread::Builder::new().name("TCP connection".to_string()).spawn(move || {
+							let _slot = slot;
+							handle_tcp_connection(stream, &options, &config);
+						})
+					};
+					if let Err(error) = spawned {
+						if options.verbose { println!("failed to spawn TCP connection thread: {:?}", error); }
 					}
 				}
-				
-				// SOA
-				record_type::SOA => answer.push(make_soa(&question, &config)),
-				
-				// MX
-				record_type::MX => {
-					for mx in &zone.records.mx {
-						answer.push(Resource {
-							rname: question.qname.clone(),
-							rtype: question.qtype,
-							rclass: question.qclass,
-							ttl: mx.ttl.as_secs() as u32,
-							rdata: protocol::serialize_mx(&mx.host, mx.priority),
-						});
-					}
+			})?
+		};
+		
+		threads.push(tcp);
+	}
+	
+	if let Some((_, tls_socket, tls_config)) = tls {
+		let options = options.clone();
+		let config = config.clone();
+		let tcp_connections = tcp_connections.clone();
+		let shutdown = shutdown.clone();
+		threads.push(thread::Builder::new().name("TLS server".to_string()).spawn(move || {
+			loop {
+				let accepted = tls_socket.accept();
+				if shutdown.is_shutdown() {
+					break;
 				}
-				
-				// TXT
-				record_type::TXT => {
-					for txt in &zone.records.txt {
-						answer.push(Resource {
-							rname: question.qname.clone(),
-							rtype: question.qtype,
-							rclass: question.qclass,
-							ttl: txt.ttl.as_secs() as u32,
-							rdata: protocol::serialize_txt(&txt.data),
-						});
+				let (stream, src) = match accepted {
+					Ok(connection) => connection,
+					Err(error) => {
+						if options.verbose { println!("failed to accept TLS connection: {:?}", error); }
+						continue;
 					}
-				}
-				
-				// SRV
-				record_type::SRV => unimplemented!(),
+				};
+				let slot = match ConnectionSlot::take(&tcp_connections, options.max_tcp_conns) {
+					Some(slot) => slot,
+					None => {
+						if options.verbose { println!("closing TLS connection from {}, as {} are already open", src, options.max_tcp_conns); }
+						continue;
+					}
+				};
+				if options.verbose { println!("handling TLS connection"); }
 				
-				_ => {}
-			}
-			
-			if question.qtype != record_type::NS {
-				if answer.is_empty() && authority.is_empty() {
-					for trpp in &zone.records.trpp {
-						use serde::Deserialize;
-						#[derive(Debug, Deserialize)]
-						struct TrppMX {
-							pub host: String,
-							pub priority: Option<u16>,
-						}
-						#[derive(Debug, Deserialize)]
-						#[serde(untagged)]
-						enum TrppRec {
-							MX(TrppMX),
-							String(String),
-						}
-						
-						#[derive(Debug, Deserialize)]
-						struct TrppRecord {
-							pub ttl: Option<u32>,
-							pub rec: TrppRec,
-						}
-						let body: Vec<TrppRecord> = match reqwest::blocking::get(Url::parse_with_params(&trpp.server, &[("name", question.qname.join(".")), ("type", match question.qtype {
-							record_type::A => "A",
-							record_type::AAAA => "AAAA",
-							record_type::MX => "MX",
-							record_type::TXT => "TXT",
-							_ => continue,
-						}.to_string())]).unwrap()) {
-							Err(x) => {
-								if options.verbose { println!("failed to connect to TRPP server: {:?}", x); }
-								continue;
+				let spawned = {
+					let options = options.clone();
+					let config = config.clone();
+					let tls_config = tls_config.clone();
+					thread::Builder::new().name("TLS connection".to_string()).spawn(move || {
+						let _slot = slot;
+						let connection = match rustls::ServerConnection::new(tls_config) {
+							Ok(connection) => connection,
+							Err(error) => {
+								if options.verbose { println!("failed to start TLS connection: {:?}", error); }
+								return;
 							}
-							Ok(res) => match res.json() {
-								Err(x) => {
-									if options.verbose { println!("failed to parse TRPP response: {:?}", x); }
-									continue;
-								}
-								Ok(body) => body,
-							},
 						};
-						if options.verbose { println!("TRPP response: {:?}", body); }
-						for record in body {
-							let rdata = match question.qtype {
-								record_type::A => if let TrppRec::String(a) = record.rec {
-									match a.parse::<Ipv4Addr>() {
-										Err(_) => continue,
-										Ok(a) => a,
-									}.octets().to_vec()
-								} else { continue; },
-								record_type::AAAA => if let TrppRec::String(aaaa) = record.rec {
-									match aaaa.parse::<Ipv6Addr>() {
-										Err(_) => continue,
-										Ok(aaaa) => aaaa,
-									}.octets().to_vec()
-								} else { continue; },
-								record_type::MX => if let TrppRec::MX(mx) = record.rec { protocol::serialize_mx(&mx.host, mx.priority.unwrap_or(10)) } else { continue; },
-								record_type::TXT => if let TrppRec::String(txt) = record.rec { protocol::serialize_txt(&txt) } else { continue; },
-								x => panic!("unknown qtype when trying to serialize TRPP response: {:?}", x),
-							};
-							
-							answer.push(Resource {
-								rname: question.qname.clone(),
-								rtype: question.qtype,
-								rclass: question.qclass,
-								ttl: record.ttl.unwrap_or(trpp.ttl.as_secs() as u32),
-								rdata,
-							});
-						}
-					}
+						// the handshake happens on the first read, and failures close the connection
+						handle_stream(rustls::StreamOwned::new(connection, stream), &options, &config, Transport::Tls, src);
+					})
+				};
+				if let Err(error) = spawned {
+					if options.verbose { println!("failed to spawn TLS connection thread: {:?}", error); }
+				}
+			}
+		})?);
+	}
+	
+	if let Some(unix_socket) = unix_socket {
+		let options = options.clone();
+		let config = config.clone();
+		let shutdown = shutdown.clone();
+		threads.push(thread::Builder::new().name("Unix server".to_string()).spawn(move || {
+			loop {
+				let accepted = unix_socket.accept();
+				if shutdown.is_shutdown() {
+					break;
 				}
+				let stream = match accepted {
+					Ok((stream, _src)) => stream,
+					Err(error) => {
+						if options.verbose { println!("failed to accept Unix connection: {:?}", error); }
+						continue;
+					}
+				};
+				if options.verbose { println!("handling Unix connection"); }
 				
-				if answer.is_empty() && authority.is_empty() {
-					for rns in &zone.records.rns {
-						match rns.host.clone() {
-							RnsHost::SocketAddr(socket_addr) => {
-								if let Response::Ok(mut rns_answer, mut rns_authority, _) = resolver_lookup((*question).clone(), socket_addr) {
-									answer.append(&mut rns_answer);
-									authority.append(&mut rns_authority);
-								}
-							}
-							RnsHost::HostPort(host, port) => {
-								fn handle_response(ans: Vec<Resource>, port: u16) -> Option<SocketAddr> {
-									for record in ans {
-										let mut cursor = Cursor::new(record.rdata);
-										if record.rtype == record_type::A {
-											return Some(SocketAddr::new(IpAddr::from([cursor.read_u8().unwrap(), cursor.read_u8().unwrap(), cursor.read_u8().unwrap(), cursor.read_u8().unwrap()]), port));
-										}
-										if record.rtype == record_type::AAAA {
-											return Some(SocketAddr::new(IpAddr::from([cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap(), cursor.read_u16::<BigEndian>().unwrap()]), port));
-										}
-									}
-									return None;
-								}
-								
-								// lookup and attempt connection over IPv6
-								for qtype in &[record_type::AAAA, record_type::A] {
-									let ns_question = Question {
-										qname: host.split(".").map(|label| label.to_string()).collect(),
-										qtype: *qtype,
-										qclass: 1,
-									};
-									let mut addr = None;
-									if rns.external {
-										if let Response::Ok(ans, _, _) = resolver_lookup(ns_question, options.resolver) {
-											addr = handle_response(ans, port);
-										}
-									} else {
-										let (ans, _, _) = handle_dns(&ns_question, options, config);
-										if ans.len() > 0 {
-											addr = handle_response(ans, port);
-										} else {
-											if let Response::Ok(ans, _, _) = resolver_lookup(ns_question, options.resolver) {
-												addr = handle_response(ans, port);
-											}
-										}
-									}
-									
-									if let Some(addr) = addr {
-										if let Response::Ok(mut rns_answer, mut rns_authority, _) = resolver_lookup(question.clone(), addr) {
-											answer.append(&mut rns_answer);
-											authority.append(&mut rns_authority);
-											
-											if !answer.is_empty() || !authority.is_empty() {
-												// only query up until we get an answer
-												break;
-											}
-										}
-									}
-								}
-							}
-						}
+				let spawned = {
+					let options = options.clone();
+					let config = config.clone();
+					thread::Builder::new().name("Unix connection".to_string()).spawn(move || {
+						// the client is on this machine, and the socket's permissions decide who that can be
+						handle_stream(stream, &options, &config, Transport::Unix, (Ipv4Addr::LOCALHOST, 0).into());
+					})
+				};
+				if let Err(error) = spawned {
+					if options.verbose { println!("failed to spawn Unix connection thread: {:?}", error); }
+				}
+			}
+			if let Some(unix_path) = &shutdown.unix_path {
+				if let Err(error) = fs::remove_file(unix_path) {
+					if options.verbose { println!("failed to remove Unix socket: {:?}", error); }
+				}
+			}
+		})?);
+	}
+	
+	if let Some((api_socket, token)) = api {
+		let options = options.clone();
+		let config = config.clone();
+		let shutdown = shutdown.clone();
+		// changes are only persisted to a config file, not to the environment variable
+		// a directory of configs can't be written back to
+		let config_path = if options.config_env.is_none() && !Path::new(&options.config).is_dir() { Some(options.config.clone()) } else { None };
+		threads.push(thread::Builder::new().name("API server".to_string()).spawn(move || {
+			loop {
+				let accepted = api_socket.accept();
+				if shutdown.is_shutdown() {
+					break;
+				}
+				let stream = match accepted {
+					Ok((stream, _src)) => stream,
+					Err(error) => {
+						if options.verbose { println!("failed to accept API connection: {:?}", error); }
+						continue;
 					}
+				};
+				// answered one at a time, which also keeps changes to the config file in order
+				let handled = stream.set_read_timeout(Some(HEALTH_TIMEOUT))
+					.and_then(|_| api::handle_connection(stream, &options, &config, &token, config_path.as_deref()));
+				if let Err(error) = handled {
+					if options.verbose { println!("failed to answer API request: {:?}", error); }
 				}
 			}
-			
-			if !answer.is_empty() || !authority.is_empty() {
-				// we've got an answer; break the search
-				break;
+		})?);
+	}
+	
+	if let Some(path) = cache_file.clone() {
+		let shutdown = shutdown.clone();
+		// not joined, as it may be sleeping until it next saves, while `join` saves once more
+		thread::Builder::new().name("cache saver".to_string()).spawn(move || {
+			loop {
+				thread::sleep(CACHE_SAVE_INTERVAL);
+				if shutdown.is_shutdown() {
+					return;
+				}
+				save_resolver_cache(&path);
+			}
+		})?;
+	}
+	
+	if options.stall_timeout > 0 {
+		let pool = pool.clone();
+		let timeout = Duration::from_secs(options.stall_timeout);
+		let shutdown = shutdown.clone();
+		// not joined, as it may be sleeping until it next looks at the pool
+		thread::Builder::new().name("pool watchdog".to_string()).spawn(move || {
+			pool.watch(timeout, || shutdown.is_shutdown());
+		})?;
+	}
+	
+	{
+		let options = options.clone();
+		let config = config.clone();
+		let shutdown = shutdown.clone();
+		threads.push(thread::Builder::new().name("health checks".to_string()).spawn(move || {
+			checks::run_periodically(&options, &config, &shutdown);
+		})?);
+	}
+	
+	if options.prefetch || config.get().prefetch {
+		let options = options.clone();
+		let config = config.clone();
+		let shutdown = shutdown.clone();
+		threads.push(thread::Builder::new().name("prefetch".to_string()).spawn(move || {
+			prefetch::run_periodically(&options, &config, &shutdown);
+		})?);
+	}
+	
+	// started last, so that it only ever answers once the DNS listeners are up
+	if let Some(health_socket) = health_socket {
+		let config = config.clone();
+		let pool = pool.clone();
+		let shutdown = shutdown.clone();
+		threads.push(thread::Builder::new().name("health server".to_string()).spawn(move || {
+			loop {
+				let accepted = health_socket.accept();
+				if shutdown.is_shutdown() {
+					break;
+				}
+				let stream = match accepted {
+					Ok((stream, _src)) => stream,
+					Err(error) => {
+						if options.verbose { println!("failed to accept health connection: {:?}", error); }
+						continue;
+					}
+				};
+				// probes are infrequent, so they're answered one at a time
+				let handled = stream.set_read_timeout(Some(HEALTH_TIMEOUT))
+					.and_then(|_| health::handle_connection(stream, || is_ready(&pool, &options, &config), config.stats()));
+				if let Err(error) = handled {
+					if options.verbose { println!("failed to answer health probe: {:?}", error); }
+				}
+			}
+		})?);
+	}
+	
+	let stats = config.stats().clone();
+	return Ok(Server {
+		addresses,
+		tls_address: shutdown.tls_address,
+		health_address: shutdown.health_address,
+		api_address: shutdown.api_address,
+		unix_path: shutdown.unix_path.clone(),
+		shutdown,
+		threads,
+		pool,
+		cache_file,
+		stats,
+	});
+}
+
+/// Binds a Unix domain socket, replacing the socket file left behind by a server that didn't shut
+/// down cleanly. One that's still being listened on is left alone, and fails to bind.
+fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+	if let Ok(metadata) = fs::symlink_metadata(path) {
+		if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() {
+			fs::remove_file(path)?;
+		}
+	}
+	let socket = UnixListener::bind(path)?;
+	if let Some(mode) = mode {
+		fs::set_permissions(path, Permissions::from_mode(mode))?;
+	}
+	return Ok(socket);
+}
+
+/// Size of the buffer UDP requests are received into, the largest UDP payload EDNS clients
+/// commonly advertise. Requests are rarely anywhere near this, but ones that don't fit would be
+/// silently truncated.
+const MAX_UDP_RECEIVE: u16 = 4096;
+
+/// How long a health probe may take to send its request, or to get a readiness check done.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks the server can answer queries by looking `--health-probe` up on the worker pool, as any
+/// other request would be. A pool that's stuck, or a probe name that doesn't resolve, means the
+/// server isn't ready.
+fn is_ready(pool: &Workers, options: &Options, config: &SharedConfig) -> bool {
+	let question = options.health_probe.as_ref().map(|probe| Question {
+		qname: probe.split('.').filter(|label| !label.is_empty()).map(|label| label.to_lowercase()).collect(),
+		qtype: record_type::A,
+		qclass: class::IN,
+	});
+	let (sender, receiver) = mpsc::channel();
+	{
+		let options = options.clone();
+		let config = config.clone();
+		let probe = options.health_probe.clone().unwrap_or_default();
+		pool.execute(move || format!("the health probe for {}", probe), move || {
+			let response = question.map(|question| handle_dns(&question, &options, &config.served()));
+			let _ = sender.send(response);
+		});
+	}
+	return match receiver.recv_timeout(HEALTH_TIMEOUT) {
+		Ok(None) | Ok(Some(Response::Ok(_, _, _))) => true,
+		Ok(Some(response)) => {
+			if options.verbose { println!("health probe failed: {:?}", response); }
+			false
+		}
+		Err(_) => false,
+	};
+}
+
+/// Answers length-prefixed requests on a TCP connection in the order they arrive, until the client
+/// closes the connection or it has been idle for `--tcp-timeout`.
+///
+/// https://tools.ietf.org/html/rfc7766#section-6.2.1
+fn handle_tcp_connection(stream: TcpStream, options: &Options, config: &SharedConfig) {
+	let src = match stream.peer_addr() {
+		Ok(src) => src,
+		Err(error) => {
+			if options.verbose { println!("failed to get TCP peer address: {:?}", error); }
+			return;
+		}
+	};
+	handle_stream(stream, options, config, Transport::Tcp, src);
+}
+
+/// Holds one of the TCP connections allowed open at once, until it's dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+	/// Takes a slot if fewer than `max` connections are open.
+	fn take(open: &Arc<AtomicUsize>, max: usize) -> Option<ConnectionSlot> {
+		return open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| if count < max { Some(count + 1) } else { None })
+			.ok()
+			.map(|_| ConnectionSlot(open.clone()));
+	}
+}
+
+impl Drop for ConnectionSlot {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Holds one of the UDP jobs a client is allowed waiting or running at once, until it's dropped.
+/// The job owns it, so it's dropped even if the job panics.
+struct ClientSlot {
+	jobs: Arc<Mutex<HashMap<IpAddr, usize>>>,
+	client: IpAddr,
+}
+
+impl ClientSlot {
+	/// Takes a slot if `client` has fewer than `max` jobs.
+	fn take(jobs: &Arc<Mutex<HashMap<IpAddr, usize>>>, client: IpAddr, max: usize) -> Option<ClientSlot> {
+		let mut counts = jobs.lock().unwrap_or_else(|error| error.into_inner());
+		let count = counts.entry(client).or_insert(0);
+		if *count >= max {
+			return None;
+		}
+		*count += 1;
+		return Some(ClientSlot { jobs: jobs.clone(), client });
+	}
+}
+
+impl Drop for ClientSlot {
+	fn drop(&mut self) {
+		let mut counts = self.jobs.lock().unwrap_or_else(|error| error.into_inner());
+		// clients without jobs are forgotten, so the map only holds the busy ones
+		if let Some(count) = counts.get_mut(&self.client) {
+			*count -= 1;
+			if *count == 0 {
+				counts.remove(&self.client);
+			}
+		}
+	}
+}
+
+/// Streams whose reads can time out, so that clients can't hold connections open forever.
+trait ReadTimeout {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		return TcpStream::set_read_timeout(self, timeout);
+	}
+}
+
+impl ReadTimeout for UnixStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		return UnixStream::set_read_timeout(self, timeout);
+	}
+}
+
+impl ReadTimeout for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		return self.sock.set_read_timeout(timeout);
+	}
+}
+
+/// Answers length-prefixed requests on a stream, TCP, TLS, or Unix, until it's closed or fails.
+///
+/// Each part of a request has to arrive within `--tcp-read-timeout`, apart from the start of those
+/// after the first, which are waited for up to `--tcp-timeout`, and the connection is closed after
+/// `--tcp-lifetime` regardless.
+fn handle_stream<S: Read + Write + ReadTimeout>(mut stream: S, options: &Options, config: &SharedConfig, transport: Transport, src: SocketAddr) {
+	let read_timeout = Duration::from_secs(options.tcp_read_timeout);
+	let deadline = Instant::now() + Duration::from_secs(options.tcp_lifetime);
+	let mut wait_timeout = read_timeout;
+	loop {
+		let mut size = [0; 2];
+		let buf = read_exact_by(&mut stream, &mut size, wait_timeout, deadline).and_then(|_| {
+			let mut buf = vec![0; u16::from_be_bytes(size) as usize];
+			read_exact_by(&mut stream, &mut buf, read_timeout, deadline)?;
+			Ok(buf)
+		});
+		let buf = match buf {
+			Ok(buf) => buf,
+			Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return,
+			Err(error) => {
+				if options.verbose { println!("closing TCP connection: {:?}", error); }
+				return;
+			}
+		};
+		
+		let instant = Instant::now();
+		for message in respond(&buf, options, config, transport, src) {
+			if let Err(error) = write_tcp_message(&mut stream, &message) {
+				if options.verbose { println!("failed to send TCP response: {:?}", error); }
+				return;
+			}
+		}
+		if options.verbose { println!("response to {} took: {:?}", src, instant.elapsed()); }
+		wait_timeout = Duration::from_secs(options.tcp_timeout);
+	}
+}
+
+/// Fills the buffer from the stream, giving up when a read takes longer than `timeout` or it's past
+/// the deadline.
+fn read_exact_by<S: Read + ReadTimeout>(stream: &mut S, buf: &mut [u8], timeout: Duration, deadline: Instant) -> io::Result<()> {
+	let mut filled = 0;
+	while filled < buf.len() {
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		if remaining == Duration::from_secs(0) {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "connection has been open too long"));
+		}
+		stream.set_read_timeout(Some(timeout.min(remaining)))?;
+		match stream.read(&mut buf[filled..]) {
+			Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+			Ok(size) => filled += size,
+			Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+			Err(error) => return Err(error),
+		}
+	}
+	return Ok(());
+}
+
+/// Reads a message prefixed by its 2 byte length, as used over TCP.
+fn read_tcp_message<S: Read>(stream: &mut S) -> io::Result<Vec<u8>> {
+	let message_size = stream.read_u16::<BigEndian>()?;
+	let mut buf: Vec<u8> = vec![0; message_size as usize];
+	stream.read_exact(buf.as_mut_slice())?;
+	Ok(buf)
+}
+
+/// Writes a message prefixed by its 2 byte length, as used over TCP.
+fn write_tcp_message<S: Write>(stream: &mut S, message: &[u8]) -> io::Result<()> {
+	stream.write_u16::<BigEndian>(message.len() as u16)?;
+	stream.write_all(message)
+}
+
+/// The protocol a request came in over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transport {
+	Udp,
+	Tcp,
+	Tls,
+	Unix,
+}
+
+/// Answers a raw request, parsing it once for whichever of an update, a zone transfer, or a query
+/// it turns out to be. Returns the raw responses, of which only a zone transfer has more than one,
+/// and none if the request should be dropped. Their sizes and rcodes are counted in `stats`.
+fn respond(buf: &[u8], options: &Options, config: &SharedConfig, transport: Transport, src: SocketAddr) -> Vec<Vec<u8>> {
+	let responses = make_responses(buf, options, config, transport, src);
+	let stats = config.stats();
+	stats.record_request(buf.len());
+	for response in &responses {
+		stats.record_response(response.len(), (response[3] & 0x0f) as u16);
+	}
+	return responses;
+}
+
+/// The responses to a raw request, see `respond`.
+fn make_responses(buf: &[u8], options: &Options, config: &SharedConfig, transport: Transport, src: SocketAddr) -> Vec<Vec<u8>> {
+	timing::start(options.slow_query_threshold.is_some());
+	checks::start(config.health());
+	access::start(src.ip(), None);
+	let request = protocol::parse(buf);
+	timing::lap(Phase::Parse);
+	if let Ok(message) = &request {
+		if let Some(response) = update::handle_update(message, buf, options, config, transport, src) {
+			return vec![response];
+		}
+	}
+	let answering = config.answering();
+	if let Some(response) = request.as_ref().ok().and_then(|message| cached_response(message, options, &answering, transport, src)) {
+		return vec![response];
+	}
+	if let Some(responses) = request.as_ref().ok().and_then(|message| handle_transfer(buf, message, options, &answering, transport, src)) {
+		return responses;
+	}
+	return handle_request(buf, request, options, &answering, transport, src).into_iter().collect();
+}
+
+/// Handles a parsed request and returns the raw response, or `None` if the request should be
+/// dropped without a response. The response is kept in the answer cache if it can be.
+fn handle_request(buf: &[u8], request: Result<Message, ParseError>, options: &Options, answering: &Answering, transport: Transport, src: SocketAddr) -> Option<Vec<u8>> {
+	let instant = Instant::now();
+	let config = &*answering.config;
+	let key = request.as_ref().ok().and_then(|message| answers::Key::new(message, max_response_size(message, transport), transport));
+	let (mut response, max_size, signer) = make_response(buf, request, options, answering, transport, src)?;
+	response.header.ra = recursion_available(options, src.ip());
+	for section in [&mut response.answer, &mut response.authority, &mut response.additional] {
+		normalize_rrsets(section);
+	}
+	if let Some(signer) = &signer {
+		tsig::sign(&mut response, max_size, signer, SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+	}
+	let serialized = protocol::serialize(&response, max_size);
+	timing::lap(Phase::Serialize);
+	if let (Some(threshold), Some(phases)) = (options.slow_query_threshold, timing::finish()) {
+		if phases.total > threshold {
+			println!("{}", SlowQuery::new(&response, serialized.len(), phases).format(&options.log_format));
+		}
+	}
+	if options.query_log {
+		let entry = QueryLog::new(src, transport, &response, instant.elapsed());
+		println!("{}", entry.format(&options.log_format));
+	}
+	// a traced name is always answered afresh, so that it's always traced
+	let traced = response.question.iter().any(|question| is_traced_name(&question.qname, options));
+	if let Some(key) = key.filter(|_| options.answer_cache_size > 0 && !traced && is_cacheable(&response, config)) {
+		let zone = stats_zone(config, &response.question[0]);
+		answering.answers.insert(key, serialized.clone(), response, zone, options.answer_cache_size);
+	}
+	return Some(serialized);
+}
+
+/// Gives the records of each RRset in a section the lowest TTL among them, as they must all have
+/// the same one (https://tools.ietf.org/html/rfc2181#section-5.2), then drops records repeated in
+/// it, such as a CNAME target that's reached again while adding glue. Otherwise, records keep the
+/// order they were added in. RRSIGs form a set for each type they cover.
+fn normalize_rrsets(records: &mut Vec<Resource>) {
+	fn covered(record: &Resource) -> Option<&[u8]> {
+		return if record.rtype == record_type::RRSIG { record.rdata.get(..2) } else { None };
+	}
+	fn is_same_rrset(a: &Resource, b: &Resource) -> bool {
+		return a.rtype == b.rtype && a.rclass == b.rclass && covered(a) == covered(b) && is_same_name(&a.rname, &b.rname);
+	}
+	
+	let ttls: Vec<u32> = records.iter()
+		.map(|record| records.iter().filter(|other| is_same_rrset(record, other)).map(|other| other.ttl).min().unwrap())
+		.collect();
+	let mut normalized: Vec<Resource> = Vec::with_capacity(records.len());
+	for (mut record, ttl) in records.drain(..).zip(ttls) {
+		record.ttl = ttl;
+		if !normalized.iter().any(|other| is_same_rrset(&record, other) && record.rdata == other.rdata) {
+			normalized.push(record);
+		}
+	}
+	*records = normalized;
+}
+
+/// The response to a request that's been answered before from zones whose answers don't change
+/// between queries, see `answers`.
+fn cached_response(request: &Message, options: &Options, answering: &Answering, transport: Transport, src: SocketAddr) -> Option<Vec<u8>> {
+	if options.answer_cache_size == 0 { return None; }
+	let instant = Instant::now();
+	let key = answers::Key::new(request, max_response_size(request, transport), transport)?;
+	let (mut response, message, zone) = answering.answers.get(&key, request)?;
+	record_stats(&answering.stats, zone.as_deref(), &request.question[0], message.header.rcode, !message.answer.is_empty(), 0);
+	// the response was cached for whichever client asked first, who may differ in whether recursion
+	// is available to them
+	response[3] = (response[3] & 0x7f) | if recursion_available(options, src.ip()) { 0x80 } else { 0 };
+	if options.query_log {
+		let entry = QueryLog::new(src, transport, &message, instant.elapsed());
+		println!("{}", entry.format(&options.log_format));
+	}
+	return Some(response);
+}
+
+/// Whether a response would be the same for any client asking again until the config changes,
+/// which it is when every name in it, and the name asked for, is only in zones with nothing looked
+/// up elsewhere, picked by health or rotation, or answered differently depending on the client.
+fn is_cacheable(response: &Message, config: &Config) -> bool {
+	// records of a zone this client may not read may have been left out, which others could read
+	if access::visited_restricted() { return false; }
+	if config.zones.iter().any(|zone| zone.records.view.is_some()) { return false; }
+	if response.header.opcode != opcode::QUERY || (response.header.rcode != 0 && response.header.rcode != 3) { return false; }
+	if response.edns.as_ref().map(Edns::dnssec_ok).unwrap_or(false) { return false; }
+	let question = match response.question.as_slice() {
+		[question] if question.qclass == class::IN => question,
+		_ => return false,
+	};
+	// looked up for a client allowed to ask for recursion, which others would be refused
+	if response.header.ra && !is_within_zones(config, &question.qname) { return false; }
+	let is_static = |zone: &Zone| {
+		let records = &zone.records;
+		let upstream = !records.aname.is_empty() || !records.alias.is_empty() || !records.rns.is_empty() || !records.trpp.is_empty() || records.forward.is_some();
+		let picked = records.rotate_a || records.rotate_aaaa
+			|| records.a.iter().any(|a| a.weight.is_some() || a.check.is_some())
+			|| records.aaaa.iter().any(|aaaa| aaaa.weight.is_some() || aaaa.check.is_some());
+		let regex = zone.matchers.iter().flatten().any(|label| matches!(label, Label::Regex(..)));
+		let acl = !config.acl_local && (!config.acl.is_empty() || !records.allow.is_empty() || !records.deny.is_empty());
+		return !upstream && !picked && !regex && !acl;
+	};
+	if !matching_zones(config, &question.qname).into_iter().all(|(_, zone)| is_static(zone)) { return false; }
+	return response.answer.iter().chain(&response.authority).chain(&response.additional).all(|record| {
+		// a TTL of 0 is for a record not to be cached, by us any more than by anyone else
+		if record.ttl == 0 { return false; }
+		let zones = matching_zones(config, &record.rname);
+		return !zones.is_empty() && zones.into_iter().all(|(_, zone)| is_static(zone));
+	});
+}
+
+/// Makes the response to a raw request, along with the size it must be truncated to and what to sign
+/// it with if the request was signed, or `None` if the request should be dropped without a response.
+fn make_response(buf: &[u8], request: Result<Message, ParseError>, options: &Options, answering: &Answering, transport: Transport, src: SocketAddr) -> Option<(Message, u16, Option<tsig::Signer>)> {
+	let config = &*answering.client_view(src.ip());
+	let stats = &answering.stats;
+	let mut message = match request {
+		Ok(message) => message,
+		Err(ParseError::Truncated) => {
+			if options.verbose { println!("dropping request too short to contain a header"); }
+			return None;
+		}
+		Err(ParseError::Malformed(header, error)) => {
+			if options.verbose { println!("malformed request: {:?}", error); }
+			if header.qr { return None; }
+			return Some((make_error_response(header, Response::FormatError), if transport == Transport::Udp { 512 } else { u16::MAX }, None));
+		}
+	};
+	if options.verbose { println!("request: {:?}", message); }
+	if message.header.qr {
+		// this is actually a response...possibly a DDoS attempt?
+		// drop it
+		return None;
+	}
+	
+	let max_size = max_response_size(&message, transport);
+	let edns = message.edns.as_ref().map(|request| Edns {
+		udp_payload_size: options.max_udp_payload,
+		// https://tools.ietf.org/html/rfc3225#section-3
+		extended_rcode_and_flags: request.extended_rcode_and_flags & Edns::DNSSEC_OK,
+		options: vec![],
+	});
+	
+	// a signed request is verified before anything else, and the response signed with the same key
+	let signer = match &message.tsig {
+		None => None,
+		Some(_) => match tsig::verify(&message, buf, &config.tsig_keys, SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()) {
+			Ok(signer) => Some(signer),
+			Err((rcode, record, signer)) => {
+				if options.verbose { println!("signature of a request from {} failed verification with rcode {}", src, rcode); }
+				let mut response = make_error_response(message.header, Response::Refused);
+				response.header.rcode = rcode;
+				response.question = message.question;
+				response.edns = edns;
+				response.tsig = record;
+				return Some((response, max_size, signer.map(|signer| *signer)));
+			}
+		},
+	};
+	access::start(src.ip(), signer.as_ref().map(|signer| signer.key.name.as_str()));
+	
+	// we're never a secondary, so a NOTIFY from a primary is only acknowledged:
+	// https://tools.ietf.org/html/rfc1996#section-3.7
+	if message.header.opcode == opcode::NOTIFY {
+		let mut response = make_error_response(message.header, Response::Ok(vec![], vec![], vec![]));
+		response.header.aa = true;
+		response.question = message.question;
+		response.edns = edns;
+		return Some((response, max_size, signer));
+	}
+	
+	// dynamic updates are handled before we get here, by `update::handle_update`, and any other
+	// opcode (IQUERY, STATUS, and those never assigned) isn't one we answer
+	if message.header.opcode != opcode::QUERY {
+		if options.verbose { println!("opcode {} isn't implemented", message.header.opcode); }
+		let mut response = make_error_response(message.header, Response::NotImplemented);
+		response.question = message.question;
+		response.edns = edns;
+		return Some((response, max_size, signer));
+	}
+	
+	let invalid_name = message.question.iter_mut().any(|question| !normalize_name(&mut question.qname));
+	if message.question.is_empty() || invalid_name || message.edns.as_ref().map(|edns| edns.version() != 0).unwrap_or(false) {
+		let response = if message.question.is_empty() || invalid_name { Response::FormatError } else { Response::BadVersion };
+		let mut message = make_error_response(message.header, response);
+		message.edns = edns;
+		return Some((message, max_size, signer));
+	}
+	
+	// every zone is in the IN class, and CHAOS is only for asking about the server itself, so there's
+	// nothing in any other to match against
+	if message.question.iter().any(|question| question.qclass != class::IN && question.qclass != class::CH) {
+		if options.verbose { println!("refusing a question in an unsupported class to {}", src); }
+		let mut response = make_error_response(message.header, Response::Refused);
+		response.question = message.question;
+		response.edns = edns;
+		return Some((response, max_size, signer));
+	}
+	
+	// lets tests check that a request which panics doesn't stop later ones being answered
+	#[cfg(test)]
+	if message.question[0].qname == ["panic", "test"] {
+		panic!("asked to panic");
+	}
+	
+	// zone transfers are only served on streams, by `handle_transfer`
+	if message.question.iter().any(|question| question.qtype == record_type::AXFR) {
+		let response = if transport == Transport::Udp { Response::NotImplemented } else { Response::FormatError };
+		let mut message = make_error_response(message.header, response);
+		message.edns = edns;
+		return Some((message, max_size, signer));
+	}
+	
+	// practically every client sends a single question, but if there are more we answer all of
+	// them, in order, and report the rcode of the first one that didn't succeed
+	let mut rcode = 0;
+	let mut answer = vec![];
+	let mut authority = vec![];
+	let mut additional = vec![];
+	let mut authoritative = true;
+	let dnssec_ok = message.edns.as_ref().map(Edns::dnssec_ok).unwrap_or(false);
+	let trace_option = message.edns.as_ref().map(|edns| edns.options.iter().any(|option| option.code == trace::TRACE_OPTION)).unwrap_or(false);
+	let trace_name = message.question.iter().any(|question| is_traced_name(&question.qname, options));
+	trace::start(trace_option || trace_name);
+	ede::start();
+	// with `--allow-recursion`, the RD bit is honored: names outside every zone are looked up for
+	// the clients it allows, and nothing at all is looked up elsewhere for a query without it
+	let honor_rd = !options.allow_recursion.is_empty();
+	let no_recursion = NoRecursion(options);
+	let upstream = Counted::new(if honor_rd && !message.header.rd { &no_recursion } else { options });
+	for question in &message.question {
+		let (question_rcode, mut question_answer, question_authority, question_additional, question_authoritative) = if question.qname.is_empty() {
+			// the root isn't ours, and nothing else is going to be found there
+			if options.verbose { println!("refusing the root to {}", src); }
+			ede::set(ede::info_code::NOT_AUTHORITATIVE, "");
+			(Response::Refused.rcode(), vec![], vec![], vec![], true)
+		} else if may_ask(question, config) {
+			// zones are all in the IN class, so CHAOS queries are only about the server itself
+			if question.qclass == class::CH {
+				answer_chaos(question, options, config)
+			} else if honor_rd && message.header.rd && !is_within_zones(config, &question.qname) {
+				if recursion_available(options, src.ip()) {
+					answer_recursively(question, &upstream)
+				} else {
+					if options.verbose { println!("refusing recursion for {:?} to {}", question.qname, src); }
+					ede::set(ede::info_code::NOT_AUTHORITATIVE, "recursion isn't available");
+					(Response::Refused.rcode(), vec![], vec![], vec![], true)
+				}
+			} else {
+				answer_question(question, &upstream, config, dnssec_ok)
 			}
+		} else {
+			if options.verbose { println!("refusing {:?} to {}", question.qname, src); }
+			ede::set(ede::info_code::PROHIBITED, "");
+			(Response::Refused.rcode(), vec![], vec![], vec![], true)
+		};
+		record_stats(stats, stats_zone(config, question).as_deref(), question, question_rcode, !question_answer.is_empty(), upstream.take());
+		if rcode == 0 {
+			rcode = question_rcode;
+		}
+		authoritative &= question_authoritative;
+		answer.append(&mut question_answer);
+		for record in question_authority {
+			if !authority.contains(&record) { authority.push(record); }
+		}
+		for record in question_additional {
+			if !additional.contains(&record) { additional.push(record); }
+		}
+	}
+	timing::lap(Phase::Answer);
+	if let Some(lines) = trace::finish() {
+		if trace_name { println!("trace of a query from {}:\n{}", src, lines.join("\n")); }
+		if trace_option { additional.push(trace::make_record(&message.question[0].qname, &lines)); }
+	}
+	
+	message.header.qr = true;
+	message.header.aa = authoritative;
+	message.header.tc = false;
+	
+	message.header.rcode = rcode;
+	message.answer = answer;
+	message.authority = authority;
+	message.additional = additional;
+	message.edns = edns;
+	message.tsig = None;
+	if let (Some(edns), Some(error)) = (&mut message.edns, ede::finish()) {
+		edns.options.push(error.to_option());
+	}
+	
+	if options.verbose { println!("response: {:?}", message); }
+	return Some((message, max_size, signer));
+}
+
+/// Whether a name matches one of the `--trace-name` globs.
+fn is_traced_name(qname: &[String], options: &Options) -> bool {
+	return options.trace_name.iter().any(|glob| trace::matches_glob(glob, qname));
+}
+
+/// The most a response to a request can take up, which is only limited over UDP:
+/// https://tools.ietf.org/html/rfc6891#section-6.2.5
+fn max_response_size(request: &Message, transport: Transport) -> u16 {
+	if transport != Transport::Udp { return u16::MAX; }
+	return request.edns.as_ref().map(|edns| edns.udp_payload_size.max(512)).unwrap_or(512);
+}
+
+/// Drops the empty label some clients end a name with, as if it were written with a trailing dot,
+/// then returns whether every label is ASCII without dots, as internationalized names are queried
+/// in punycode and a dot within a label could otherwise be mistaken for two labels. Labels must
+/// also be 1 to 63 bytes long, which only names that weren't parsed from the wire could break.
+fn normalize_name(qname: &mut Vec<String>) -> bool {
+	if qname.last().map(String::is_empty).unwrap_or(false) {
+		qname.pop();
+	}
+	return qname.iter().all(|label| !label.is_empty() && label.len() <= 63 && label.is_ascii() && !label.contains('.'));
+}
+
+/// Checks the ACL and `require-tsig` of every zone matching the question, so that clients outside
+/// of the ACL can't have us look records up elsewhere on their behalf, and only clients holding the
+/// key can read the zone.
+fn may_ask(question: &Question, config: &Config) -> bool {
+	if question.qclass == class::CH { return true; }
+	return may_read(config, &question.qname);
+}
+
+/// Whether the client being answered may read every zone matching a name, see `access`.
+fn may_read(config: &Config, qname: &[String]) -> bool {
+	return matching_zones(config, qname).into_iter().all(|(_, zone)| access::allows(config, zone));
+}
+
+/// Whether we're authoritative for the answer to a question, which we are unless it's for a zone
+/// forwarded with the `noaa` flag.
+fn is_authoritative(question: &Question, config: &Config) -> bool {
+	return matching_zones(config, &question.qname).into_iter()
+		.find_map(|(_, zone)| zone.records.forward.as_ref())
+		.map(|forward| forward.authoritative)
+		.unwrap_or(true);
+}
+
+/// Whether to leave out of the authority and additional sections whatever the protocol doesn't
+/// require, which the first zone for the question that says either way decides.
+fn is_minimal(question: &Question, config: &Config) -> bool {
+	return matching_zones(config, &question.qname).into_iter()
+		.find_map(|(_, zone)| zone.records.minimal_responses)
+		.unwrap_or(config.minimal_responses);
+}
+
+/// Answers a single question, returning the rcode along with the answer, authority, and additional
+/// sections, and whether the answer is authoritative. With `dnssec_ok`, the answer and authority
+/// sections are signed where they're in a zone with a DNSSEC key.
+fn answer_question(question: &Question, upstream: &dyn Upstream, config: &Config, dnssec_ok: bool) -> (u16, Vec<Resource>, Vec<Resource>, Vec<Resource>, bool) {
+	let mut answering_zone = None;
+	let response = handle_dns_noting_zone(question, upstream, config, 0, &mut answering_zone);
+	let rcode = response.rcode();
+	let referral = matches!(response, Response::Referral(..));
+	let signed = dnssec_ok && question.qclass == class::IN && is_authoritative(question, config);
+	let authoritative = is_authoritative(question, config) && !referral;
+	let (answer, mut authority, mut additional) = match response {
+		Response::Ok(answer, authority, additional) => (answer, authority, additional),
+		Response::Referral(authority, additional) => (vec![], authority, additional),
+		_ => (vec![], vec![], vec![]),
+	};
+	
+	if answer.is_empty() && authority.is_empty() && (rcode == 0 || rcode == 3) {
+		// negative answers (NXDOMAIN or no data) carry the SOA so that resolvers can cache them
+		let mut soa_question = question.clone();
+		soa_question.qtype = record_type::SOA;
+		let soa = match handle_dns(&soa_question, upstream, config) {
+			Response::Ok(soa, _, _) => soa,
+			_ => vec![],
+		};
+		// a forwarder's SOA may be too short to have a minimum, in which case ours is used
+		let mut soa = soa.into_iter().find(|record| record.rtype == record_type::SOA && record.rdata.len() >= 20)
+			.unwrap_or_else(|| make_soa(question, config, config.nttl));
+		// https://tools.ietf.org/html/rfc2308#section-3
+		let minimum = (&soa.rdata[soa.rdata.len() - 4..]).read_u32::<BigEndian>().unwrap();
+		soa.ttl = soa.ttl.min(minimum);
+		authority.push(soa);
+	}
+	
+	// an answer with nothing else in the authority section gets the NS records of the zone it's
+	// from, which generally point back to this server, and their addresses where they're ours
+	let fill = config.fill_authority && rcode == 0 && authority.is_empty() && question.qtype != record_type::NS && !is_minimal(question, config);
+	if let Some(zone_index) = answering_zone.filter(|_| fill) {
+		let zone = &config.zones[zone_index];
+		let ns_question = Question { qtype: record_type::NS, ..question.clone() };
+		authority = zone_records(zone, zone_index, &ns_question, record_type::NS, config);
+		for ns in ns_records(zone, config) {
+			add_additional(&mut additional, glue_records(&rewrite_xname(&ns.name, &question.qname), config));
+		}
+	}
+	
+	if signed {
+		let (rcode, answer, authority) = add_dnssec_records(question, rcode, answer, authority, referral, config);
+		return (rcode, answer, authority, additional, authoritative);
+	}
+	return (rcode, answer, authority, additional, authoritative);
+}
+
+/// Looks a question about a name outside every zone up with the resolvers, for a client that's
+/// allowed to ask for recursion, in the same form as `answer_question`. The answer isn't ours, so
+/// it's never authoritative.
+fn answer_recursively(question: &Question, upstream: &dyn Upstream) -> (u16, Vec<Resource>, Vec<Resource>, Vec<Resource>, bool) {
+	let response = upstream.lookup(question.clone(), upstream.resolvers());
+	let rcode = response.rcode();
+	return match response {
+		Response::Ok(answer, authority, additional) => (rcode, answer, authority, additional, false),
+		_ => (rcode, vec![], vec![], vec![], false),
+	};
+}
+
+/// The zone a question is counted towards in the statistics, which is the first that matches it.
+fn stats_zone(config: &Config, question: &Question) -> Option<String> {
+	return match question.qclass {
+		class::IN => matching_zones(config, &question.qname).first().map(|(_, zone)| zone.name()),
+		_ => None,
+	};
+}
+
+/// Counts a question towards the statistics of `zone`, see `stats`.
+fn record_stats(stats: &Stats, zone: Option<&str>, question: &Question, rcode: u16, answered: bool, upstream_lookups: u64) {
+	stats.record(zone, question.qname.join(".").to_lowercase(), rcode, answered, upstream_lookups);
+}
+
+/// Whether a client may ask for recursion, see `--allow-recursion`.
+fn recursion_available(options: &Options, client: IpAddr) -> bool {
+	return options.allow_recursion.iter().any(|cidr| cidr.contains(client));
+}
+
+/// What a question resolves to, as it's answered to a client that doesn't ask for DNSSEC.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Answer {
+	/// https://tools.ietf.org/html/rfc1035#section-4.1.1
+	pub rcode: u16,
+	pub answer: Vec<Resource>,
+	pub authority: Vec<Resource>,
+	pub additional: Vec<Resource>,
+	/// Whether the answer is from the config, rather than a referral or a zone forwarded with the
+	/// `noaa` flag.
+	pub authoritative: bool,
+}
+
+/// Resolves a question against the config, looking up whatever it points outside of itself through
+/// `upstream`. It's answered as it would be to a client in no view that every ACL allows, so zones
+/// limited to a view aren't used. A name with a label that isn't valid on the wire is a format
+/// error, while the root and the CHAOS class, which the server only answers about itself, are
+/// refused.
+pub fn resolve(question: &Question, config: &Config, upstream: &dyn Upstream) -> Answer {
+	let mut question = question.clone();
+	let refused = if !normalize_name(&mut question.qname) {
+		Some(Response::FormatError)
+	} else if question.qname.is_empty() || question.qclass == class::CH {
+		Some(Response::Refused)
+	} else {
+		None
+	};
+	if let Some(response) = refused {
+		return Answer { rcode: response.rcode(), answer: vec![], authority: vec![], additional: vec![], authoritative: true };
+	}
+	
+	let config = if config.zones.iter().any(|zone| zone.records.view.is_some()) {
+		let zones = config.zones.iter().filter(|zone| zone.records.view.is_none()).cloned().collect();
+		// the index no longer lines up with the zones, so they're all tried
+		Cow::Owned(Config { zones, index: ZoneIndex::default(), ..config.clone() })
+	} else {
+		Cow::Borrowed(config)
+	};
+	let (rcode, answer, authority, additional, authoritative) = answer_question(&question, upstream, &config, false);
+	return Answer { rcode, answer, authority, additional, authoritative };
+}
+
+/// How long before now signatures are valid from, for validators with clocks that are behind.
+const SIGNATURE_INCEPTION: Duration = Duration::from_secs(60 * 60);
+/// How long after now signatures are valid for.
+const SIGNATURE_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Signs the answer and authority sections of a response, adding an NSEC record to negative
+/// answers and referrals, and returns them with the rcode to answer with.
+///
+/// Every NSEC is a white lie made up on the fly: its owner is the name asked for and its next name
+/// is the one right after it (`\000.name`), so it denies nothing but what was asked and can't be
+/// used to walk the zone. A name that doesn't exist gets no data with the NXNAME type instead of
+/// NXDOMAIN, which is known as compact denial of existence.
+///
+/// https://tools.ietf.org/html/rfc4470
+/// https://tools.ietf.org/html/rfc9824
+fn add_dnssec_records(question: &Question, rcode: u16, answer: Vec<Resource>, mut authority: Vec<Resource>, referral: bool, config: &Config) -> (u16, Vec<Resource>, Vec<Resource>) {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+	
+	if referral {
+		// the NS records belong to the delegated zone so stay unsigned, but that it has no DS
+		// record has to be proven for the delegation to be insecure rather than bogus
+		let cut = authority[0].rname.clone();
+		if signer(&cut, config).is_some() {
+			let nsec = make_nsec(&cut, vec![record_type::NS], ttl_secs(config.nttl));
+			authority.append(&mut sign_records(vec![nsec], config, now));
 		}
+		return (rcode, answer, authority);
+	}
+	
+	let mut rcode = rcode;
+	let negative = answer.is_empty() && (rcode == 0 || rcode == 3);
+	if let Some((apex, _)) = signer(&question.qname, config).filter(|_| negative) {
+		// https://tools.ietf.org/html/rfc4035#section-2.3
+		let mut ttl = ttl_secs(config.nttl);
+		for soa in authority.iter_mut().filter(|record| record.rtype == record_type::SOA) {
+			// validators look for it at the apex of the zone, rather than at the name asked for
+			soa.rname = apex.clone();
+			ttl = soa.ttl;
+		}
+		let types = if rcode == 3 {
+			vec![record_type::NXNAME]
+		} else {
+			present_types(&question.qname, config).into_iter().filter(|rtype| *rtype != question.qtype).collect()
+		};
+		authority.push(make_nsec(&question.qname, types, ttl));
+		rcode = 0;
+	}
+	
+	return (rcode, sign_records(answer, config, now), sign_records(authority, config, now));
+}
+
+/// Makes the NSEC record for a name with the given types, as described in `add_dnssec_records`.
+fn make_nsec(name: &[String], mut types: Vec<u16>, ttl: u32) -> Resource {
+	types.extend(&[record_type::RRSIG, record_type::NSEC]);
+	let mut next = vec!["\0".to_string()];
+	next.extend_from_slice(name);
+	return Resource {
+		rname: name.to_vec(),
+		rtype: record_type::NSEC,
+		rclass: class::IN,
+		ttl,
+		rdata: dnssec::nsec_rdata(&next, &types),
+	};
+}
+
+/// The types of the records configured for a name, for the NSEC record saying which it has.
+fn present_types(qname: &[String], config: &Config) -> Vec<u16> {
+	let mut types = vec![];
+	for (_, zone) in matching_zones(config, qname) {
+		let records = &zone.records;
+		let flattened = !records.aname.is_empty() || !records.alias.is_empty();
+		for (present, rtype) in &[
+			(!records.a.is_empty() || flattened, record_type::A),
+			(!records.aaaa.is_empty() || flattened, record_type::AAAA),
+			(!ns_records(zone, config).is_empty(), record_type::NS),
+			(!records.cname.is_empty(), record_type::CNAME),
+			(!records.soa.is_empty(), record_type::SOA),
+			(!records.ptr.is_empty(), record_type::PTR),
+			(!records.mx.is_empty(), record_type::MX),
+			(!records.txt.is_empty(), record_type::TXT),
+			(!records.srv.is_empty(), record_type::SRV),
+			(!records.naptr.is_empty(), record_type::NAPTR),
+			(!records.hinfo.is_empty(), record_type::HINFO),
+			(!records.loc.is_empty(), record_type::LOC),
+			(!records.sshfp.is_empty(), record_type::SSHFP),
+			(!records.svcb.is_empty(), record_type::SVCB),
+			(!records.https.is_empty(), record_type::HTTPS),
+		] {
+			if *present { types.push(*rtype); }
+		}
+	}
+	if signer(qname, config).map(|(apex, _)| apex.len() == qname.len()).unwrap_or(false) {
+		types.extend(&[record_type::SOA, record_type::DNSKEY]);
+	}
+	return types;
+}
+
+/// Groups records into RRsets, in the order they first appear, following each with its RRSIG if
+/// its name is within a signed zone. The records of a set are given the TTL it's signed with.
+fn sign_records(records: Vec<Resource>, config: &Config, now: Duration) -> Vec<Resource> {
+	let mut rrsets: Vec<Vec<Resource>> = vec![];
+	for record in records {
+		let rrset = rrsets.iter_mut().find(|rrset| rrset[0].rtype == record.rtype && rrset[0].rclass == record.rclass && is_same_name(&rrset[0].rname, &record.rname));
+		match rrset {
+			Some(rrset) => rrset.push(record),
+			None => rrsets.push(vec![record]),
+		}
+	}
+	
+	let inception = (now - SIGNATURE_INCEPTION).as_secs() as u32;
+	let expiration = (now + SIGNATURE_VALIDITY).as_secs() as u32;
+	let mut signed = vec![];
+	for mut rrset in rrsets {
+		if let Some((apex, dnssec)) = signer(&rrset[0].rname, config) {
+			// the key's length is checked when the config is parsed
+			let rrsig = SigningKey::from_seed(&dnssec.private_key).unwrap().sign(&rrset, &apex, inception, expiration);
+			for record in &mut rrset {
+				record.ttl = rrsig.ttl;
+			}
+			signed.append(&mut rrset);
+			signed.push(rrsig);
+		} else {
+			signed.append(&mut rrset);
+		}
+	}
+	return signed;
+}
+
+/// The apex and key of the signed zone a name is in, which is the highest zone with a plain name
+/// that the name is within and that has a key, or is covered by the global one.
+fn signer<'a>(name: &[String], config: &'a Config) -> Option<(Vec<String>, &'a Dnssec)> {
+	return config.zones.iter()
+		.filter(|zone| !zone.records.is_delegation())
+		.filter_map(|zone| Some((zone, zone.records.dnssec.as_ref().or(config.dnssec.as_ref())?)))
+		.flat_map(|(zone, dnssec)| zone.matchers.iter().filter_map(plain_name).map(move |apex| (apex, dnssec)))
+		.filter(|(apex, _)| apex.len() <= name.len() && is_same_name(apex, &name[name.len() - apex.len()..]))
+		.min_by_key(|(apex, _)| apex.len());
+}
+
+fn is_same_name(a: &[String], b: &[String]) -> bool {
+	return a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b));
+}
+
+/// Makes an empty response with the given error, echoing the request's header.
+fn make_error_response(mut header: Header, response: Response) -> Message {
+	header.qr = true;
+	header.aa = false;
+	header.tc = false;
+	header.ra = false;
+	header.z = 0;
+	header.rcode = response.rcode();
+	return Message { header, ..Default::default() };
+}
+
+/// Makes an empty truncated response to a raw request that's been rate limited, telling the client
+/// to retry over TCP, or `None` if it doesn't deserve one.
+fn make_slip_response(buf: &[u8]) -> Option<Vec<u8>> {
+	let request = protocol::parse(buf).ok()?;
+	if request.header.qr { return None; }
+	let mut message = make_error_response(request.header, Response::Ok(vec![], vec![], vec![]));
+	message.header.tc = true;
+	message.question = request.question;
+	return Some(protocol::serialize(&message, 512));
+}
+
+/// How many bytes of records to put in each message of a zone transfer.
+const TRANSFER_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// Streams a zone to a secondary server if the raw request is an AXFR, returning the raw messages
+/// to send, or `None` if it's some other request that should go to `handle_request`.
+///
+/// Only zones with a plain name can be transferred, as wildcards and regular expressions can't be
+/// enumerated. The transfer holds the records of that zone followed by those of every other plain
+/// zone below it that the client may read, between two copies of the SOA. A signed request has its
+/// transfer signed with the same key.
+///
+/// https://tools.ietf.org/html/rfc5936
+fn handle_transfer(buf: &[u8], request: &Message, options: &Options, answering: &Answering, transport: Transport, src: SocketAddr) -> Option<Vec<Vec<u8>>> {
+	let config = &*answering.client_view(src.ip());
+	let instant = Instant::now();
+	if request.header.qr || request.question.len() != 1 || request.question[0].qtype != record_type::AXFR {
+		return None;
+	}
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+	let signer = match &request.tsig {
+		None => None,
+		Some(_) => match tsig::verify(request, buf, &config.tsig_keys, now) {
+			Ok(signer) => Some(signer),
+			Err((rcode, record, signer)) => {
+				if options.verbose { println!("signature of a transfer request from {} failed verification with rcode {}", src, rcode); }
+				let mut response = make_error_response(request.header.clone(), Response::Refused);
+				response.header.rcode = rcode;
+				response.question = request.question.clone();
+				response.tsig = record;
+				if let Some(signer) = &signer {
+					tsig::sign(&mut response, u16::MAX, signer, now);
+				}
+				return Some(vec![protocol::serialize(&response, u16::MAX)]);
+			}
+		},
+	};
+	access::start(src.ip(), signer.as_ref().map(|signer| signer.key.name.as_str()));
+	let question = &request.question[0];
+	let qname: Vec<String> = question.qname.iter().map(|label| label.to_lowercase()).collect();
+	
+	let apex = config.zones.iter().enumerate()
+		.find(|(_, zone)| zone.matchers.iter().any(|matcher| plain_name(matcher).as_ref() == Some(&qname)));
+	let allowed = |zone: &Zone| (config.allow_transfer.contains(&src.ip()) || zone.records.allow_transfer.contains(&src.ip())) && access::allows(config, zone);
+	let mut messages = match apex {
+		Some((apex_index, apex)) if allowed(apex) => {
+			let soa = zone_records(apex, apex_index, question, record_type::SOA, config).into_iter().next()
+				.unwrap_or_else(|| make_soa(question, config, nttl(apex, config)));
+			
+			let mut records = vec![soa.clone()];
+			for (zone_index, zone) in config.zones.iter().enumerate().filter(|(_, zone)| access::allows(config, zone)) {
+				for name in zone.matchers.iter().filter_map(plain_name) {
+					if name.len() < qname.len() || name[name.len() - qname.len()..] != qname[..] { continue; }
+					let name_question = Question {
+						qname: name,
+						qtype: record_type::AXFR,
+						qclass: question.qclass,
+					};
+					for rtype in &[record_type::NS, record_type::A, record_type::AAAA, record_type::PTR, record_type::MX, record_type::TXT, record_type::SRV, record_type::NAPTR, record_type::HINFO, record_type::LOC, record_type::SSHFP, record_type::SVCB, record_type::HTTPS] {
+						records.append(&mut zone_records(zone, zone_index, &name_question, *rtype, config));
+					}
+					records.extend(zone.records.cname.iter().map(|cname| Resource {
+						rname: name_question.qname.clone(),
+						rtype: record_type::CNAME,
+						rclass: question.qclass,
+						ttl: ttl_secs(cname.ttl),
+						rdata: protocol::serialize_name(rewrite_xname(&cname.name, &name_question.qname).iter().map(String::as_str)),
+					}));
+				}
+			}
+			records.push(soa);
+			
+			// split the records across messages, sizing them as if there was no name compression
+			let mut messages = vec![];
+			let mut size = 0;
+			for record in records {
+				let record_size = record.rname.iter().map(|label| label.len() + 1).sum::<usize>() + 1 + 10 + record.rdata.len();
+				if messages.is_empty() || size + record_size > TRANSFER_MESSAGE_SIZE {
+					let mut message = make_error_response(request.header.clone(), Response::Ok(vec![], vec![], vec![]));
+					message.header.aa = true;
+					message.question = request.question.clone();
+					messages.push(message);
+					size = 0;
+				}
+				size += record_size;
+				messages.last_mut().unwrap().answer.push(record);
+			}
+			messages
+		}
+		_ => {
+			if options.verbose { println!("refusing transfer of {:?} to {}", question.qname, src); }
+			vec![make_error_response(request.header.clone(), Response::Refused)]
+		}
+	};
+	if let Some(signer) = &signer {
+		tsig::sign_transfer(&mut messages, signer, now);
+	}
+	
+	if options.query_log {
+		let answers = messages.iter().map(|message| message.answer.len()).sum();
+		let mut entry = QueryLog::new(src, transport, &messages[0], instant.elapsed());
+		entry.answers = answers;
+		println!("{}", entry.format(&options.log_format));
+	}
+	return Some(messages.iter().map(|message| protocol::serialize(message, u16::MAX)).collect());
+}
+
+/// What looking up a question came to, before it's made into a message.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Response {
+	Ok(Vec<Resource>, Vec<Resource>, Vec<Resource>),
+	/// The name is delegated elsewhere, by the NS records in the authority section, with any glue
+	/// for them in the additional section.
+	Referral(Vec<Resource>, Vec<Resource>),
+	FormatError,
+	ServerFailure,
+	NameError,
+	NotImplemented,
+	Refused,
+	BadVersion,
+}
+
+impl Response {
+	/// https://tools.ietf.org/html/rfc1035#section-4.1.1
+	pub fn rcode(&self) -> u16 {
+		match self {
+			Response::Ok(..) | Response::Referral(..) => 0,
+			Response::FormatError => 1,
+			Response::ServerFailure => 2,
+			Response::NameError => 3,
+			Response::NotImplemented => 4,
+			Response::Refused => 5,
+			// https://tools.ietf.org/html/rfc6891#section-9
+			Response::BadVersion => 16,
+		}
+	}
+}
+
+/// Responses to `resolver_lookup`, by the servers asked and the question in lowercase.
+type ResolverCache = Mutex<Cache<(Vec<SocketAddr>, Question)>>;
+
+lazy_static! {
+	static ref RESOLVER_CACHE: ResolverCache = Mutex::new(Cache::new());
+}
+
+/// How often the resolver cache is saved to `--cache-file`, apart from when the server shuts down.
+const CACHE_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Writes the resolver cache to `path`, through a temporary file so that a crash midway doesn't
+/// leave half a cache behind. Failing to is only warned about, as the server can do without it.
+fn save_resolver_cache(path: &Path) {
+	/// Held while saving, so that two saves don't write the temporary file at once.
+	static SAVING: Mutex<()> = Mutex::new(());
+	let _saving = SAVING.lock().unwrap_or_else(|error| error.into_inner());
+	// the cache is only held while it's serialized, so that lookups don't wait on the disk
+	let data = RESOLVER_CACHE.lock().unwrap().save(SystemTime::now());
+	let temporary = PathBuf::from(format!("{}.tmp", path.display()));
+	if let Err(error) = fs::write(&temporary, data).and_then(|_| fs::rename(&temporary, path)) {
+		eprintln!("warning: couldn't save the cache file {}: {}", path.display(), error);
+	}
+}
+
+/// Fills the resolver cache from `path`, unless it isn't there yet. A cache file that can't be read
+/// is only warned about, as the cache fills up again as names are looked up.
+fn load_resolver_cache(path: &Path, options: &Options) {
+	let data = match fs::read(path) {
+		Ok(data) => data,
+		Err(ref error) if error.kind() == io::ErrorKind::NotFound => return,
+		Err(error) => {
+			eprintln!("warning: couldn't read the cache file {}: {}", path.display(), error);
+			return;
+		}
+	};
+	match RESOLVER_CACHE.lock().unwrap().load(&data, SystemTime::now(), options.resolver_cache_size) {
+		Ok(count) => if options.verbose { println!("loaded {} cached lookups from {}", count, path.display()); },
+		Err(error) => eprintln!("warning: ignoring the cache file {}, which is corrupted: {}", path.display(), error),
+	}
+}
+
+/// Performs a DNS query against other DNS servers, failing over to the next one when a server
+/// doesn't respond.
+fn resolver_lookup(question: Question, servers: &[SocketAddr], options: &Options) -> Response {
+	return resolver_lookup_within(question, servers, options, Duration::from_millis(options.resolver_timeout));
+}
+
+/// `resolver_lookup`, giving each server `timeout` to respond rather than `--resolver-timeout`.
+fn resolver_lookup_within(question: Question, servers: &[SocketAddr], options: &Options, timeout: Duration) -> Response {
+	// clients may randomize the case of names (DNS 0x20), which shouldn't miss the cache, so the
+	// upstream is asked in lowercase and the answer is given back in the case that was asked for
+	let qname = question.qname.clone();
+	let key = (servers.to_vec(), Question {
+		qname: question.qname.iter().map(|label| label.to_lowercase()).collect(),
+		..question
+	});
+	if let Some(response) = RESOLVER_CACHE.lock().unwrap().get(&key, SystemTime::now()) {
+		if response == Response::ServerFailure { ede::set(ede::info_code::NO_REACHABLE_AUTHORITY, ""); }
+		return with_qname_case(response, &qname);
+	}
+	return with_qname_case(timing::upstream(|| resolver_fetch(key, options, timeout, &RESOLVER_CACHE, SystemTime::now())), &qname);
+}
+
+/// Asks the servers of a key of the resolver cache its question, whether or not it's cached, and
+/// caches the response in `cache` as of `now`.
+fn resolver_fetch(key: (Vec<SocketAddr>, Question), options: &Options, timeout: Duration, cache: &ResolverCache, now: SystemTime) -> Response {
+	// long enough to shed load off a resolver that's down, short enough to notice it's back
+	const SERVFAIL_TTL: u32 = 5;
+	
+	let (response, ttl) = match failover_exchange(&key.1, &key.0, options, timeout) {
+		Err(error) => {
+			ede::set(ede::info_code::NO_REACHABLE_AUTHORITY, &error.to_string());
+			(Response::ServerFailure, SERVFAIL_TTL)
+		}
+		// what a truncated response leaves out can't be told apart from what the name doesn't have,
+		// so it's neither cached nor answered with, even when it's the one that came over TCP
+		Ok(message) if message.header.tc => {
+			ede::set(ede::info_code::NO_REACHABLE_AUTHORITY, "the resolver's response was truncated");
+			return Response::ServerFailure;
+		}
+		Ok(message) => {
+			// https://tools.ietf.org/html/rfc2308#section-5
+			let negative_ttl = message.authority.iter()
+				.find(|record| record.rtype == record_type::SOA && record.rdata.len() >= 20)
+				.map(|soa| soa.ttl.min((&soa.rdata[soa.rdata.len() - 4..]).read_u32::<BigEndian>().unwrap()))
+				.unwrap_or(options.resolver_nttl as u32);
+			
+			match message.header.rcode {
+				1 => return Response::FormatError,
+				2 => {
+					ede::set(ede::info_code::NO_REACHABLE_AUTHORITY, "the resolver failed");
+					(Response::ServerFailure, SERVFAIL_TTL)
+				}
+				3 => (Response::NameError, negative_ttl),
+				4 => return Response::NotImplemented,
+				5 => return Response::Refused,
+				_ if message.answer.is_empty() => (Response::Ok(message.answer, message.authority, message.additional), negative_ttl),
+				_ => {
+					// the additional section is only a hint, so its TTLs don't hold back the answer
+					let least_expiration = message.answer.iter().chain(message.authority.iter())
+						.map(|record| record.ttl)
+						.min()
+						.unwrap_or(0);
+					(Response::Ok(message.answer, message.authority, message.additional), least_expiration)
+				}
+			}
+		}
+	};
+	
+	cache.lock().unwrap().insert(key, response.clone(), ttl, now, options.resolver_cache_size);
+	return response;
+}
+
+/// Gives records owned by the name asked for the case it was asked in.
+fn with_qname_case(response: Response, qname: &[String]) -> Response {
+	return match response {
+		Response::Ok(mut answer, mut authority, mut additional) => {
+			for record in answer.iter_mut().chain(authority.iter_mut()).chain(additional.iter_mut()) {
+				if record.rname.len() == qname.len() && record.rname.iter().zip(qname).all(|(a, b)| a.eq_ignore_ascii_case(b)) {
+					record.rname = qname.to_vec();
+				}
+			}
+			Response::Ok(answer, authority, additional)
+		}
+		response => response,
+	};
+}
+
+/// How long a server that didn't respond is passed over in favor of the next one.
+const BAD_SERVER_TIME: Duration = Duration::from_secs(30);
+
+/// Tries `resolver_exchange` against each server in order until one responds, skipping those that
+/// recently didn't, unless none have. Each server is given `timeout` to respond.
+fn failover_exchange(question: &Question, servers: &[SocketAddr], options: &Options, timeout: Duration) -> io::Result<Message> {
+	lazy_static! {
+		static ref BAD_UNTIL: Mutex<HashMap<SocketAddr, Instant>> = Mutex::new(HashMap::new());
+	}
+	
+	let now = Instant::now();
+	let (good, bad): (Vec<SocketAddr>, Vec<SocketAddr>) = {
+		let bad_until = BAD_UNTIL.lock().unwrap();
+		servers.iter().partition(|server| bad_until.get(server).map(|until| *until <= now).unwrap_or(true))
+	};
+	
+	let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no resolvers to query");
+	for server in if good.is_empty() { bad } else { good } {
+		match resolver_exchange(question, server, timeout) {
+			Ok(message) => {
+				BAD_UNTIL.lock().unwrap().remove(&server);
+				return Ok(message);
+			}
+			Err(error) => {
+				if options.verbose { println!("resolver lookup against {} failed: {:?}", server, error); }
+				BAD_UNTIL.lock().unwrap().insert(server, Instant::now() + BAD_SERVER_TIME);
+				last_error = error;
+			}
+		}
+	}
+	return Err(last_error);
+}
+
+/// Sends a query over UDP, retrying over TCP if the response was truncated. Each of the two
+/// attempts gives up after `timeout`.
+fn resolver_exchange(question: &Question, server: SocketAddr, timeout: Duration) -> io::Result<Message> {
+	fn timed_out() -> io::Error {
+		io::Error::new(io::ErrorKind::TimedOut, "resolver didn't respond in time")
+	}
+	
+	let mut request = protocol::make_message_from_question(vec![question.clone()]);
+	// randomly seeded, so responses are harder to spoof
+	request.header.id = RandomState::new().build_hasher().finish() as u16;
+	
+	let local_address: SocketAddr = if server.is_ipv4() {
+		(Ipv4Addr::UNSPECIFIED, 0).into()
+	} else {
+		(Ipv6Addr::UNSPECIFIED, 0).into()
+	};
+	let socket = UdpSocket::bind(local_address)?;
+	socket.connect(server)?;
+	socket.set_write_timeout(Some(timeout))?;
+	socket.send(&protocol::serialize(&request, 512))?;
+	
+	let deadline = Instant::now() + timeout;
+	let message = loop {
+		let now = Instant::now();
+		if now >= deadline {
+			return Err(timed_out());
+		}
+		socket.set_read_timeout(Some(deadline - now))?;
+		let mut buf = vec![0; 512];
+		let size = match socket.recv(&mut buf) {
+			Ok(size) => size,
+			Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return Err(timed_out()),
+			Err(error) => return Err(error),
+		};
+		buf.truncate(size);
+		// ignore anything that isn't a response to our query
+		match protocol::parse(&buf) {
+			Ok(message) if verify_response(&request, &message) => break message,
+			_ => continue,
+		}
+	};
+	if !message.header.tc {
+		return Ok(message);
+	}
+	
+	let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+	write_tcp_message(&mut stream, &protocol::serialize(&request, u16::MAX))?;
+	let message = protocol::parse(&read_tcp_message(&mut stream)?)
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed response from resolver"))?;
+	if !verify_response(&request, &message) {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "resolver responded to a different query"));
+	}
+	return Ok(message);
+}
+
+/// Whether `response` answers `request`: it has the same ID and asks the same questions, apart from
+/// the case of the names, which servers don't all keep.
+fn verify_response(request: &Message, response: &Message) -> bool {
+	return response.header.qr
+		&& response.header.id == request.header.id
+		&& response.question.len() == request.question.len()
+		&& response.question.iter().zip(&request.question).all(|(a, b)| {
+			a.qtype == b.qtype
+				&& a.qclass == b.qclass
+				&& a.qname.len() == b.qname.len()
+				&& a.qname.iter().zip(&b.qname).all(|(a, b)| a.eq_ignore_ascii_case(b))
+		});
+}
+
+/// The zones matching a name, in order, along with their indices, leaving out those that exclude
+/// it. Only those the config's index says could match are checked, or all of them when it's out of
+/// date.
+fn matching_zones<'a>(config: &'a Config, qname: &[String]) -> Vec<(usize, &'a Zone)> {
+	let lowercase: Vec<String> = qname.iter().map(|label| label.to_lowercase()).collect();
+	let matches = |zone: &Zone| does_match(&zone.matchers, qname) && !does_match(&zone.excludes, qname);
+	return config.index.candidates(&config.zones, &lowercase).into_iter()
+		.map(|zone_index| (zone_index, &config.zones[zone_index]))
+		.filter(|(_, zone)| matches(zone))
+		.collect();
+}
+
+/// Whether each of a zone's matchers matches a name, for tracing, as the matcher along with `None`
+/// if it does or why it doesn't. Of all the ways of lining the name up with a matcher, the reason
+/// is from the one that got furthest into it, which for a near miss is the label that's wrong.
+fn match_outcomes(matchers: &[ZoneMatcher], qname: &[String]) -> Vec<(String, Option<String>)> {
+	let qname: Vec<String> = qname.iter().map(|label| label.to_lowercase()).collect();
+	return matchers.iter().map(|labels| {
+		let mut furthest = (0, String::new());
+		let mismatch = if find_mismatch(labels, &qname, 0, &mut furthest) { None } else { Some(furthest.1) };
+		(zone_name(slice::from_ref(labels)), mismatch)
+	}).collect();
+}
+
+/// Matches as `does_match_labels` does, keeping in `furthest` why the attempt that got furthest
+/// into the matcher failed, along with how many of its labels it got past.
+fn find_mismatch(labels: &[Label], qname: &[String], matched: usize, furthest: &mut (usize, String)) -> bool {
+	fn miss(reason: String, matched: usize, furthest: &mut (usize, String)) -> bool {
+		if matched >= furthest.0 { *furthest = (matched, reason); }
+		return false;
+	}
+	
+	let (label, labels) = match labels.split_first() {
+		Some(split) => split,
+		None if qname.is_empty() => return true,
+		None => return miss(format!("nothing matches '{}'", qname.join(".")), matched, furthest),
+	};
+	if qname.is_empty() && !matches!(label, Label::AllWildcard) {
+		return miss(format!("the name ends before '{}'", label), matched, furthest);
+	}
+	
+	return match label {
+		Label::Basic(string) if &qname[0] == string => find_mismatch(labels, &qname[1..], matched + 1, furthest),
+		Label::Basic(string) => miss(format!("'{}' isn't '{}'", qname[0], string), matched, furthest),
+		Label::Regex(false, regex) if regex.is_match(&qname[0]) => find_mismatch(labels, &qname[1..], matched + 1, furthest),
+		Label::Regex(false, _) => miss(format!("'{}' doesn't match {}", qname[0], label), matched, furthest),
+		Label::Regex(true, regex) => {
+			let takes: Vec<usize> = (1..=qname.len()).filter(|taken| regex.is_match(&qname[..*taken].join("."))).collect();
+			if takes.is_empty() {
+				return miss(format!("'{}' doesn't match {}", qname.join("."), label), matched, furthest);
+			}
+			takes.into_iter().any(|taken| find_mismatch(labels, &qname[taken..], matched + 1, furthest))
+		}
+		Label::Wildcard => find_mismatch(labels, &qname[1..], matched + 1, furthest),
+		Label::SubWildcard => (1..=qname.len()).any(|taken| find_mismatch(labels, &qname[taken..], matched + 1, furthest)),
+		Label::AllWildcard => (0..=qname.len()).any(|taken| find_mismatch(labels, &qname[taken..], matched + 1, furthest)),
+	};
+}
+
+/// Whether a name is at or below the domain of any zone, see `zone_domain`.
+fn is_within_zones(config: &Config, qname: &[String]) -> bool {
+	let lowercase: Vec<String> = qname.iter().map(|label| label.to_lowercase()).collect();
+	return config.index.is_within_zones(&config.zones, &lowercase);
+}
+
+/// Returns how far to rotate the records of the given type in a zone, advancing every time so that
+/// each query (from any worker thread) gets the next record first.
+fn next_rotation(zone_index: usize, rtype: u16) -> usize {
+	lazy_static! {
+		static ref ROTATIONS: Mutex<HashMap<(usize, u16), usize>> = Mutex::new(HashMap::new());
+	}
+	let mut rotations = ROTATIONS.lock().unwrap();
+	let rotation = rotations.entry((zone_index, rtype)).or_insert(0);
+	let current = *rotation;
+	*rotation = rotation.wrapping_add(1);
+	return current;
+}
+
+/// Makes the answers for the records of a type configured directly in a zone, without following
+/// any names.
+fn zone_records(zone: &Zone, zone_index: usize, question: &Question, rtype: u16, config: &Config) -> Vec<Resource> {
+	let make = |ttl: &Duration, rdata: Vec<u8>| Resource {
+		rname: question.qname.clone(),
+		rtype,
+		rclass: question.qclass,
+		ttl: ttl_secs(*ttl),
+		rdata,
+	};
+	let records = &zone.records;
+	let mut answer: Vec<Resource> = match rtype {
+		record_type::A => checks::select(&records.a, |a| a.check.as_ref(), |a| a.weight).into_iter().map(|a| make(&a.ttl, a.ip4addr.octets().to_vec())).collect(),
+		record_type::AAAA => checks::select(&records.aaaa, |aaaa| aaaa.check.as_ref(), |aaaa| aaaa.weight).into_iter().map(|aaaa| make(&aaaa.ttl, aaaa.ip6addr.octets().to_vec())).collect(),
+		record_type::NS => ns_records(zone, config).iter().map(|ns| make(&ns.ttl, protocol::serialize_name(rewrite_xname(&ns.name, &question.qname).iter().map(String::as_str)))).collect(),
+		record_type::SOA => records.soa.iter().map(|soa| make(&soa.ttl, protocol::serialize_soa(&soa.mname, &soa.rname, soa.serial.unwrap_or(config.serial), ttl_secs(soa.refresh), ttl_secs(soa.retry), ttl_secs(soa.expire), ttl_secs(soa.minimum.unwrap_or(nttl(zone, config)))))).collect(),
+		record_type::PTR => records.ptr.iter().map(|ptr| make(&ptr.ttl, protocol::serialize_name(rewrite_xname(&ptr.name, &question.qname).iter().map(String::as_str)))).collect(),
+		record_type::MX => records.mx.iter().map(|mx| make(&mx.ttl, protocol::serialize_mx(&rewrite_xname(&mx.host, &question.qname).join("."), mx.priority))).collect(),
+		record_type::TXT => records.txt.iter().map(|txt| make(&txt.ttl, protocol::serialize_txt(&txt.data))).collect(),
+		record_type::SRV => records.srv.iter().map(|srv| make(&srv.ttl, protocol::serialize_srv(srv.priority, srv.weight, srv.port, &rewrite_xname(&srv.target, &question.qname).join(".")))).collect(),
+		record_type::NAPTR => records.naptr.iter().map(|naptr| make(&naptr.ttl, protocol::serialize_naptr(naptr.order, naptr.preference, &naptr.flags, &naptr.service, &naptr.regexp, &rewrite_xname(&naptr.replacement, &question.qname).join(".")))).collect(),
+		record_type::HINFO => records.hinfo.iter().map(|hinfo| make(&hinfo.ttl, protocol::serialize_hinfo(&hinfo.cpu, &hinfo.os))).collect(),
+		record_type::LOC => records.loc.iter().map(|loc| {
+			let position = &loc.position;
+			make(&loc.ttl, protocol::serialize_loc(position.size, position.horizontal_precision, position.vertical_precision, position.latitude, position.longitude, position.altitude))
+		}).collect(),
+		record_type::SSHFP => records.sshfp.iter().map(|sshfp| make(&sshfp.ttl, protocol::serialize_sshfp(sshfp.algorithm, sshfp.fingerprint_type, &sshfp.fingerprint))).collect(),
+		record_type::SVCB | record_type::HTTPS => if rtype == record_type::SVCB { &records.svcb } else { &records.https }.iter()
+			.map(|svcb| make(&svcb.ttl, protocol::serialize_svcb(svcb.priority, &rewrite_xname(&svcb.target, &question.qname).join("."), svc_params(&svcb.params))))
+			.collect(),
+		_ => vec![],
+	};
+	
+	let rotate = match rtype {
+		record_type::A => records.rotate_a,
+		record_type::AAAA => records.rotate_aaaa,
+		_ => false,
+	};
+	if rotate && !answer.is_empty() {
+		let len = answer.len();
+		answer.rotate_left(next_rotation(zone_index, rtype) % len);
+	}
+	
+	return answer;
+}
+
+/// Clamps the TTLs of records looked up elsewhere into the configured range. This happens as they're
+/// answered rather than in the cache, so that the cache keeps counting down the real TTL.
+fn clamp_ttls(records: &mut [Resource], min_ttl: Option<Duration>, max_ttl: Option<Duration>) {
+	for record in records {
+		if let Some(min_ttl) = min_ttl {
+			record.ttl = record.ttl.max(ttl_secs(min_ttl));
+		}
+		if let Some(max_ttl) = max_ttl {
+			record.ttl = record.ttl.min(ttl_secs(max_ttl));
+		}
+	}
+}
+
+/// Resolves a name given in the config into labels. Names ending in a dot are absolute, while others
+/// are relative to the name being queried, like in zone files, so `MX: mail` in `example.com`
+/// points at `mail.example.com`.
+fn rewrite_xname(xname: &str, qname: &[String]) -> Vec<String> {
+	let labels = |name: &str| name.split('.').filter(|label| !label.is_empty()).map(|label| label.to_string()).collect::<Vec<String>>();
+	return match xname.strip_suffix('.') {
+		Some(absolute) => labels(absolute),
+		// the query's case (which may be randomized) only belongs in the owner name, not in rdata
+		None => labels(xname).into_iter().chain(qname.iter().map(|label| label.to_lowercase())).collect(),
+	};
+}
+
+/// The NS records of a zone, or `default-ns` if it has none.
+fn ns_records<'a>(zone: &'a Zone, config: &'a Config) -> &'a [NsRecord] {
+	return if zone.records.ns.is_empty() { &config.default_ns } else { &zone.records.ns };
+}
+
+/// The minimum of a zone's SOA when it doesn't give one, which negative answers are cached for.
+fn nttl(zone: &Zone, config: &Config) -> Duration {
+	return zone.records.nodata_ttl.unwrap_or(config.nttl);
+}
+
+/// Synthesizes an SOA for zones that don't configure one, from `default-soa` if it's given, with
+/// `nttl` as its minimum unless that gives one.
+fn make_soa(question: &Question, config: &Config, nttl: Duration) -> Resource {
+	if let Some(soa) = &config.default_soa {
+		return Resource {
+			rname: question.qname.clone(),
+			rtype: record_type::SOA,
+			rclass: question.qclass,
+			ttl: ttl_secs(soa.ttl),
+			rdata: protocol::serialize_soa(&soa.mname, &soa.rname, soa.serial.unwrap_or(config.serial), ttl_secs(soa.refresh), ttl_secs(soa.retry), ttl_secs(soa.expire), ttl_secs(soa.minimum.unwrap_or(nttl))),
+		};
+	}
+	let qname: Vec<String> = question.qname.iter().map(|label| label.to_lowercase()).collect();
+	let mut mname = vec!["ns1".to_string()];
+	mname.append(&mut qname.clone());
+	let mut rname = vec!["hostmaster".to_string()];
+	rname.append(&mut qname.clone());
+	let ttl = ttl_secs(nttl);
+	
+	Resource {
+		rname: question.qname.clone(),
+		rtype: record_type::SOA,
+		rclass: question.qclass,
+		ttl,
+		rdata: protocol::serialize_soa(&mname.join("."), &rname.join("."), config.serial, DEFAULT_REFRESH.as_secs() as u32, DEFAULT_RETRY.as_secs() as u32, DEFAULT_EXPIRE.as_secs() as u32, ttl),
+	}
+}
+
+/// The params of an SVCB or HTTPS record as keys and their values in wire format:
+/// https://tools.ietf.org/html/rfc9460#section-7
+fn svc_params(params: &SvcParams) -> Vec<(u16, Vec<u8>)> {
+	let mut wire = vec![];
+	if !params.mandatory.is_empty() {
+		let mut keys: Vec<u16> = params.mandatory.iter().filter_map(|name| svc_param::key(name)).collect();
+		keys.sort_unstable();
+		wire.push((svc_param::MANDATORY, keys.iter().flat_map(|key| key.to_be_bytes()).collect()));
+	}
+	if !params.alpn.is_empty() {
+		let mut alpn = vec![];
+		for id in &params.alpn {
+			alpn.push(id.len() as u8);
+			alpn.extend_from_slice(id.as_bytes());
+		}
+		wire.push((svc_param::ALPN, alpn));
+	}
+	if params.no_default_alpn {
+		wire.push((svc_param::NO_DEFAULT_ALPN, vec![]));
+	}
+	if let Some(port) = params.port {
+		wire.push((svc_param::PORT, port.to_be_bytes().to_vec()));
+	}
+	if !params.ipv4hint.is_empty() {
+		wire.push((svc_param::IPV4HINT, params.ipv4hint.iter().flat_map(|address| address.octets()).collect()));
+	}
+	if !params.ipv6hint.is_empty() {
+		wire.push((svc_param::IPV6HINT, params.ipv6hint.iter().flat_map(|address| address.octets()).collect()));
+	}
+	return wire;
+}
+
+/// Answers with a CNAME to `name`, followed by the records found there, either here or, when
+/// `resolve_external` and it's outside every zone, from the resolver. Returns `None` if following
+/// it failed, most likely because of a loop.
+fn cname_records(question: &Question, name: &str, ttl: Duration, resolve_external: bool, upstream: &dyn Upstream, config: &Config, depth: usize) -> Option<Vec<Resource>> {
+	let target = rewrite_xname(name, &question.qname);
+	let mut answer = vec![Resource {
+		rname: question.qname.clone(),
+		rtype: record_type::CNAME,
+		rclass: question.qclass,
+		ttl: ttl_secs(ttl),
+		rdata: protocol::serialize_name(target.iter().map(String::as_str)),
+	}];
+	
+	trace::note(depth * 2 + 1, || format!("CNAME to {}", target.join(".")));
+	// ANY gets the CNAME alone, as that's everything there is at this name
+	if question.qtype == record_type::ANY { return Some(answer); }
+	
+	// follow the CNAME and lookup records there
+	let question = Question {
+		qname: target,
+		qtype: question.qtype,
+		qclass: 1,
+	};
+	match handle_dns_at_depth(&question, upstream, config, depth + 1) {
+		Response::Ok(mut cname_answer, _, _) if cname_answer.len() > 0 => {
+			answer.append(&mut cname_answer);
+		}
+		// most likely a loop, which the resolver can't help with
+		Response::ServerFailure => return None,
+		// otherwise the client follows it, as we'd be an open resolver for any name a CNAME points at
+		_ if resolve_external && !is_within_zones(config, &question.qname) => {
+			if let Response::Ok(mut cname_answer, _, _) = traced_lookup(upstream, question, upstream.resolvers(), depth) {
+				clamp_ttls(&mut cname_answer, config.min_ttl, config.max_ttl);
+				answer.append(&mut cname_answer);
+			}
+		}
+		_ => trace::note(depth * 2 + 1, || "left for the client to follow".to_string()),
+	}
+	return Some(answer);
+}
+
+/// Looks up the records at `name`, either here or from the resolver, and answers with them as if
+/// they were at the name queried. CNAMEs followed on the way are left out, as they keep their own
+/// names and there can't be one at the name queried along with the records. Returns `None` if
+/// following it failed, most likely because of a loop.
+fn aname_records(question: &Question, name: &str, min_ttl: Option<Duration>, max_ttl: Option<Duration>, upstream: &dyn Upstream, config: &Config, depth: usize) -> Option<Vec<Resource>> {
+	// follow the ANAME and lookup records there
+	let qname = question.qname.clone();
+	let question_type = question.qtype;
+	let question = Question {
+		qname: rewrite_xname(name, &question.qname),
+		qtype: question.qtype,
+		qclass: 1,
+	};
+	trace::note(depth * 2 + 1, || format!("flattening {}", question.qname.join(".")));
+	// rather than being looked up elsewhere, as it would be if it weren't ours
+	if !may_read(config, &question.qname) {
+		trace::note(depth * 2 + 1, || "left out, as the client may not read it".to_string());
+		return Some(vec![]);
+	}
+	let mut answer = match handle_dns_at_depth(&question, upstream, config, depth + 1) {
+		Response::Ok(answer, _, _) if answer.len() > 0 => answer,
+		Response::ServerFailure => return None,
+		_ => match traced_lookup(upstream, question, upstream.resolvers(), depth) {
+			Response::Ok(answer, _, _) => answer,
+			_ => vec![],
+		},
+	};
+	answer.retain(|resource| resource.rtype == question_type);
+	clamp_ttls(&mut answer, min_ttl, max_ttl);
+	for resource in &mut answer {
+		resource.rname = qname.clone();
+	}
+	return Some(answer);
+}
+
+/// Whether a name is the apex of a zone, rather than a name below it matched by a wildcard or
+/// regular expression: it has as many labels as one of the zone's matchers that matches it has
+/// plain labels.
+fn is_apex(matchers: &[ZoneMatcher], qname: &[String]) -> bool {
+	return matchers.iter()
+		.filter(|matcher| does_match_labels(matcher, qname))
+		.any(|matcher| matcher.iter().filter(|label| matches!(label, Label::Basic(_))).count() == qname.len());
+}
+
+/// The name the NS records of a delegation are at, which is the part of the query name matched by
+/// the plain labels at the end of the zone's name (e.g. `sub.example.com` for a query for
+/// `www.sub.example.com` matched by `**.sub.example.com`).
+fn delegation_point(matchers: &[ZoneMatcher], qname: &[String]) -> Vec<String> {
+	let labels = matchers.iter()
+		.find(|matcher| does_match_labels(matcher, qname))
+		.map(|matcher| matcher.iter().rev().take_while(|label| matches!(label, Label::Basic(_))).count())
+		.filter(|labels| *labels > 0)
+		.unwrap_or(qname.len());
+	return qname[qname.len() - labels..].to_vec();
+}
+
+/// Refers a question to the servers a zone delegates to, along with their addresses when they're
+/// in the zones configured here.
+///
+/// https://tools.ietf.org/html/rfc1034#section-4.3.2
+fn make_referral(zone: &Zone, zone_index: usize, cut: Vec<String>, question: &Question, config: &Config) -> Response {
+	let ns_question = Question {
+		qname: cut,
+		qtype: record_type::NS,
+		qclass: question.qclass,
+	};
+	let mut additional = vec![];
+	for ns in &zone.records.ns {
+		for record in glue_records(&rewrite_xname(&ns.name, &ns_question.qname), config) {
+			if !additional.contains(&record) { additional.push(record); }
+		}
+	}
+	return Response::Referral(zone_records(zone, zone_index, &ns_question, record_type::NS, config), additional);
+}
+
+/// The most records added to the additional section for the names in an answer, so that it doesn't
+/// crowd the answer out of a UDP response.
+const MAX_ADDITIONAL: usize = 16;
+
+/// Adds records to the additional section, skipping those already in it, until it's full.
+fn add_additional(additional: &mut Vec<Resource>, records: Vec<Resource>) {
+	for record in records {
+		if additional.len() >= MAX_ADDITIONAL { return; }
+		if !additional.contains(&record) { additional.push(record); }
+	}
+}
+
+/// Adds the additional section of a response from elsewhere to ours, leaving out its OPT record,
+/// which is about that exchange rather than the answer.
+fn add_upstream_additional(additional: &mut Vec<Resource>, mut records: Vec<Resource>, config: &Config) {
+	records.retain(|record| record.rtype != record_type::OPT);
+	clamp_ttls(&mut records, config.min_ttl, config.max_ttl);
+	add_additional(additional, records);
+}
+
+/// The A and AAAA records configured for a name, for the additional section. Unlike a lookup, these
+/// are never looked up elsewhere, and those of zones the client may not read are left out.
+fn glue_records(name: &[String], config: &Config) -> Vec<Resource> {
+	for (zone_index, zone) in matching_zones(config, name) {
+		if zone.records.is_delegation() || !access::allows(config, zone) { continue; }
+		let glue: Vec<Resource> = [record_type::A, record_type::AAAA].iter()
+			.flat_map(|rtype| zone_records(zone, zone_index, &Question {
+				qname: name.to_vec(),
+				qtype: *rtype,
+				qclass: class::IN,
+			}, *rtype, config))
+			.collect();
+		if !glue.is_empty() { return glue; }
+	}
+	return vec![];
+}
+
+/// Looks up the records for a question. Returns `Response::NameError` if no zone matches the name,
+/// `Response::Refused` if it's outside the domains of the zones as well, an empty `Response::Ok`
+/// if a zone matches but has no records of the requested type, and the upstream error if an RNS or
+/// forwarded lookup failed and nothing else answered.
+fn handle_dns(question: &Question, upstream: &dyn Upstream, config: &Config) -> Response {
+	return handle_dns_at_depth(question, upstream, config, 0);
+}
+
+/// Asks `servers` a question through `upstream`, adding what was asked and what came back to the
+/// trace.
+fn traced_lookup(upstream: &dyn Upstream, question: Question, servers: &[SocketAddr], depth: usize) -> Response {
+	trace::note(depth * 2 + 1, || format!("asking {:?} for {} {}", servers, question.qname.join("."), type_name(question.qtype)));
+	let response = upstream.lookup(question, servers);
+	trace::note(depth * 2 + 2, || match &response {
+		Response::Ok(answer, authority, _) => format!("{} answers, {} authority records", answer.len(), authority.len()),
+		response => format!("{:?}", response),
+	});
+	return response;
+}
+
+/// Answers a CHAOS question in the same form as `answer_question`.
+fn answer_chaos(question: &Question, options: &Options, config: &Config) -> (u16, Vec<Resource>, Vec<Resource>, Vec<Resource>, bool) {
+	let response = handle_chaos(question, options);
+	let rcode = response.rcode();
+	let answer = match response {
+		Response::Ok(answer, _, _) => answer,
+		_ => vec![],
+	};
+	return (rcode, answer, vec![], vec![], is_authoritative(question, config));
+}
+
+/// Identifies the server to monitoring tools through `version.bind` and `hostname.bind`, refusing
+/// any other CHAOS query.
+fn handle_chaos(question: &Question, options: &Options) -> Response {
+	let qname: Vec<String> = question.qname.iter().map(|label| label.to_lowercase()).collect();
+	let value = match qname.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+		["version", "bind"] => &options.version_string,
+		["hostname", "bind"] => &options.hostname_string,
+		_ => return Response::Refused,
+	};
+	let value = match value {
+		Some(value) => value,
+		None => return Response::Refused,
+	};
+	if question.qtype != record_type::TXT && question.qtype != record_type::ANY {
+		return Response::Refused;
+	}
+	return Response::Ok(vec![Resource {
+		rname: question.qname.clone(),
+		rtype: record_type::TXT,
+		rclass: class::CH,
+		ttl: 0,
+		rdata: protocol::serialize_txt(value),
+	}], vec![], vec![]);
+}
+
+/// How many CNAME, ANAME, NS, or RNS host lookups may be chained before giving up, so that a loop
+/// in the configuration results in SERVFAIL rather than a stack overflow.
+const MAX_RECURSION_DEPTH: usize = 16;
+
+fn handle_dns_at_depth(question: &Question, upstream: &dyn Upstream, config: &Config, depth: usize) -> Response {
+	return handle_dns_noting_zone(question, upstream, config, depth, &mut None);
+}
+
+/// `handle_dns_at_depth`, setting `answering_zone` to the index of the zone that answered, if one
+/// did with records of its own rather than by referring or failing.
+fn handle_dns_noting_zone(question: &Question, upstream: &dyn Upstream, config: &Config, depth: usize, answering_zone: &mut Option<usize>) -> Response {
+	if depth > MAX_RECURSION_DEPTH {
+		if upstream.verbose() { println!("giving up on {:?} after following {} names", question.qname, MAX_RECURSION_DEPTH); }
+		ede::set(ede::info_code::OTHER, "too many names followed, the config may have a loop");
+		return Response::ServerFailure;
+	}
+	
+	// every zone is in the trace, as the one that was meant to match is usually the one that didn't
+	if trace::is_tracing() {
+		trace::note(depth * 2, || format!("{} {}", question.qname.join("."), type_name(question.qtype)));
+		for zone in &config.zones {
+			for (matcher, mismatch) in match_outcomes(&zone.matchers, &question.qname) {
+				trace::note(depth * 2 + 1, || match mismatch {
+					None => format!("{} matches", matcher),
+					Some(reason) => format!("{} doesn't match: {}", matcher, reason),
+				});
+			}
+			for exclude in zone.excludes.iter().filter(|exclude| does_match(slice::from_ref(exclude), &question.qname)) {
+				trace::note(depth * 2 + 1, || format!("!{} excludes it", zone_name(slice::from_ref(exclude))));
+			}
+		}
+	}
+	
+	let mut answer: Vec<Resource> = Vec::new();
+	let mut authority: Vec<Resource> = Vec::new();
+	let mut additional: Vec<Resource> = Vec::new();
+	
+	// the question was only answered for a client that may read its zones, but the names followed
+	// from it may be in others
+	if !may_read(config, &question.qname) {
+		trace::note(depth * 2 + 1, || "refused, as the client may not read it".to_string());
+		return Response::Refused;
+	}
+	
+	let mut matched = false;
+	let mut failure = None;
+	for (zone_index, zone) in matching_zones(config, &question.qname) {
+		matched = true;
+		let step = |what: &str| trace::note(depth * 2 + 1, || format!("{}: {}", zone.name(), what));
+		
+		// a zone with nothing but NS records delegates its names to those servers, apart from
+		// answering for the NS records themselves at the point of delegation
+		if zone.records.is_delegation() {
+			let cut = delegation_point(&zone.matchers, &question.qname);
+			// the DS records of a delegation are the parent's, so we answer that there are none
+			if (question.qtype != record_type::NS && question.qtype != record_type::DS) || cut.len() != question.qname.len() {
+				step("delegated, so referred to its NS records");
+				return make_referral(zone, zone_index, cut, question, config);
+			}
+		}
+		
+		// unlike RNS, which is only asked when nothing else answers, forwarders are asked first
+		// and for every type
+		if let Some(forward) = &zone.records.forward {
+			step("forwarded");
+			match traced_lookup(upstream, (*question).clone(), &forward.servers, depth) {
+				Response::Ok(mut forward_answer, mut forward_authority, _) => {
+					clamp_ttls(&mut forward_answer, config.min_ttl, config.max_ttl);
+					answer.append(&mut forward_answer);
+					authority.append(&mut forward_authority);
+				}
+				response => failure = Some(response),
+			}
+			if forward.only || !answer.is_empty() || !authority.is_empty() {
+				break;
+			}
+		}
+		
+		// `A: none` answers that there are none, even where the zones after this one have some
+		if (question.qtype == record_type::A && zone.records.no_a) || (question.qtype == record_type::AAAA && zone.records.no_aaaa) {
+			step("none of this type");
+			break;
+		}
+		
+		match question.qtype {
+			// CNAME
+			_ if !zone.records.cname.is_empty() => {
+				step("CNAME");
+				let resolve_external = zone.records.resolve_external_cnames.unwrap_or(config.resolve_external_cnames);
+				for cname in &zone.records.cname {
+					match cname_records(question, &cname.name, cname.ttl, resolve_external, upstream, config, depth) {
+						Some(mut cname_answer) => answer.append(&mut cname_answer),
+						None => return Response::ServerFailure,
+					}
+				}
+			}
+			
+			// ALIAS below the apex, which is answered as a CNAME
+			_ if !zone.records.alias.is_empty() && !is_apex(&zone.matchers, &question.qname) => {
+				step("ALIAS below the apex, answered as a CNAME");
+				let resolve_external = zone.records.resolve_external_cnames.unwrap_or(config.resolve_external_cnames);
+				for alias in &zone.records.alias {
+					match cname_records(question, &alias.name, alias.ttl, resolve_external, upstream, config, depth) {
+						Some(mut alias_answer) => answer.append(&mut alias_answer),
+						None => return Response::ServerFailure,
+					}
+				}
+			}
+			
+			// ANAME
+			record_type::A | record_type::AAAA if !zone.records.aname.is_empty() => {
+				step("ANAME");
+				for aname in &zone.records.aname {
+					match aname_records(question, &aname.name, aname.min_ttl.or(config.min_ttl), aname.max_ttl.or(config.max_ttl), upstream, config, depth) {
+						Some(mut aname_answer) => answer.append(&mut aname_answer),
+						None => return Response::ServerFailure,
+					}
+				}
+			}
+			
+			// ALIAS at the apex, which is flattened like an ANAME
+			record_type::A | record_type::AAAA if !zone.records.alias.is_empty() => {
+				step("ALIAS at the apex, flattened like an ANAME");
+				for alias in &zone.records.alias {
+					match aname_records(question, &alias.name, config.min_ttl, config.max_ttl, upstream, config, depth) {
+						Some(mut alias_answer) => answer.append(&mut alias_answer),
+						None => return Response::ServerFailure,
+					}
+				}
+			}
+			
+			// NS
+			record_type::NS => {
+				step("NS records, with their addresses");
+				answer.append(&mut zone_records(zone, zone_index, question, record_type::NS, config));
+				let minimal = zone.records.minimal_responses.unwrap_or(config.minimal_responses);
+				if !minimal {
+					for ns in ns_records(zone, config) {
+						// lookup A and AAAA records for this to go in the additional section
+						let string_labels = rewrite_xname(&ns.name, &question.qname);
+						
+						// lookup A
+						if let Response::Ok(answer, _, _) = handle_dns_at_depth(&Question {
+							qname: string_labels.clone(),
+							qtype: record_type::A,
+							qclass: 1,
+						}, upstream, config, depth + 1) {
+							add_additional(&mut additional, answer);
+						}
+						
+						// lookup AAAA
+						if let Response::Ok(answer, _, _) = handle_dns_at_depth(&Question {
+							qname: string_labels,
+							qtype: record_type::AAAA,
+							qclass: 1,
+						}, upstream, config, depth + 1) {
+							add_additional(&mut additional, answer);
+						}
+					}
+				}
+			}
+			
+			// MX and SRV, with the addresses of their targets in the additional section when
+			// they're configured here, saving mail servers and the like a lookup
+			record_type::MX | record_type::SRV => {
+				step("records, with their targets' addresses");
+				answer.append(&mut zone_records(zone, zone_index, question, question.qtype, config));
+				let targets: Vec<&String> = if question.qtype == record_type::MX {
+					zone.records.mx.iter().map(|mx| &mx.host).collect()
+				} else {
+					zone.records.srv.iter().map(|srv| &srv.target).collect()
+				};
+				let minimal = zone.records.minimal_responses.unwrap_or(config.minimal_responses);
+				if !minimal {
+					for target in targets {
+						let target = rewrite_xname(target, &question.qname);
+						// an SRV target of "." means there's no such service
+						if target.is_empty() { continue; }
+						add_additional(&mut additional, glue_records(&target, config));
+					}
+				}
+			}
+			
+			// SOA
+			record_type::SOA => {
+				step("SOA records, made up if there are none");
+				if zone.records.soa.is_empty() {
+					answer.push(make_soa(question, config, nttl(zone, config)));
+				}
+				answer.append(&mut zone_records(zone, zone_index, question, record_type::SOA, config));
+			}
+			
+			// ANY
+			// Rather than the minimal response allowed by RFC 8482, this answers with everything
+			// configured in the zone. Names aren't followed, and SOAs aren't synthesized.
+			record_type::ANY => {
+				step("every record configured");
+				for rtype in &[record_type::A, record_type::AAAA, record_type::NS, record_type::SOA, record_type::PTR, record_type::MX, record_type::TXT, record_type::SRV, record_type::NAPTR, record_type::HINFO, record_type::LOC, record_type::SSHFP, record_type::SVCB, record_type::HTTPS] {
+					answer.append(&mut zone_records(zone, zone_index, question, *rtype, config));
+				}
+			}
+			
+			record_type::A | record_type::AAAA | record_type::PTR | record_type::TXT | record_type::NAPTR | record_type::HINFO | record_type::LOC | record_type::SSHFP | record_type::SVCB | record_type::HTTPS => {
+				step("records");
+				answer.append(&mut zone_records(zone, zone_index, question, question.qtype, config));
+			}
+			
+			// DNSKEY, at the apex of a signed zone
+			record_type::DNSKEY => {
+				step("DNSKEY, if the zone is signed");
+				if let Some((apex, dnssec)) = signer(&question.qname, config) {
+					if apex.len() == question.qname.len() && answer.is_empty() {
+						answer.push(Resource {
+							rname: question.qname.clone(),
+							rtype: record_type::DNSKEY,
+							rclass: question.qclass,
+							ttl: ttl_secs(dnssec.ttl),
+							rdata: SigningKey::from_seed(&dnssec.private_key).unwrap().dnskey_rdata(),
+						});
+					}
+				}
+			}
+			
+			_ => step("nothing configured for this type"),
+		}
+		
+		if question.qtype != record_type::NS {
+			if answer.is_empty() && authority.is_empty() {
+				for trpp in &zone.records.trpp {
+					use serde::Deserialize;
+					#[derive(Debug, Deserialize)]
+					struct TrppMX {
+						pub host: String,
+						pub priority: Option<u16>,
+					}
+					#[derive(Debug, Deserialize)]
+					#[serde(untagged)]
+					enum TrppRec {
+						MX(TrppMX),
+						String(String),
+					}
+					
+					#[derive(Debug, Deserialize)]
+					struct TrppRecord {
+						pub ttl: Option<u32>,
+						pub rec: TrppRec,
+					}
+					trace::note(depth * 2 + 1, || format!("asking TRPP server {}", trpp.server));
+					let body: Vec<TrppRecord> = match reqwest::blocking::get(Url::parse_with_params(&trpp.server, &[("name", question.qname.join(".")), ("type", match question.qtype {
+						record_type::A => "A",
+						record_type::AAAA => "AAAA",
+						record_type::MX => "MX",
+						record_type::TXT => "TXT",
+						_ => continue,
+					}.to_string())]).unwrap()) {
+						Err(x) => {
+							if upstream.verbose() { println!("failed to connect to TRPP server: {:?}", x); }
+							continue;
+						}
+						Ok(res) => match res.json() {
+							Err(x) => {
+								if upstream.verbose() { println!("failed to parse TRPP response: {:?}", x); }
+								continue;
+							}
+							Ok(body) => body,
+						},
+					};
+					if upstream.verbose() { println!("TRPP response: {:?}", body); }
+					for record in body {
+						let rdata = match question.qtype {
+							record_type::A => if let TrppRec::String(a) = record.rec {
+								match a.parse::<Ipv4Addr>() {
+									Err(_) => continue,
+									Ok(a) => a,
+								}.octets().to_vec()
+							} else { continue; },
+							record_type::AAAA => if let TrppRec::String(aaaa) = record.rec {
+								match aaaa.parse::<Ipv6Addr>() {
+									Err(_) => continue,
+									Ok(aaaa) => aaaa,
+								}.octets().to_vec()
+							} else { continue; },
+							record_type::MX => if let TrppRec::MX(mx) = record.rec { protocol::serialize_mx(&mx.host, mx.priority.unwrap_or(10)) } else { continue; },
+							record_type::TXT => if let TrppRec::String(txt) = record.rec { protocol::serialize_txt(&txt) } else { continue; },
+							x => panic!("unknown qtype when trying to serialize TRPP response: {:?}", x),
+						};
+						
+						answer.push(Resource {
+							rname: question.qname.clone(),
+							rtype: question.qtype,
+							rclass: question.qclass,
+							ttl: record.ttl.unwrap_or(ttl_secs(trpp.ttl)),
+							rdata,
+						});
+					}
+				}
+			}
+			
+			if answer.is_empty() && authority.is_empty() {
+				for rns in &zone.records.rns {
+					match rns.host.clone() {
+						RnsHost::SocketAddr(socket_addr) => {
+							match traced_lookup(upstream, (*question).clone(), &[socket_addr], depth) {
+								Response::Ok(mut rns_answer, mut rns_authority, rns_additional) => {
+									clamp_ttls(&mut rns_answer, config.min_ttl, config.max_ttl);
+									answer.append(&mut rns_answer);
+									authority.append(&mut rns_authority);
+									add_upstream_additional(&mut additional, rns_additional, config);
+								}
+								response => failure = Some(response),
+							}
+						}
+						RnsHost::HostPort(host, port) => {
+							fn addresses(ans: Vec<Resource>, port: u16) -> Vec<SocketAddr> {
+								return ans.into_iter().filter_map(|record| match (record.rtype, record.rdata.len()) {
+									(record_type::A, 4) => Some(IpAddr::from(<[u8; 4]>::try_from(record.rdata).unwrap())),
+									(record_type::AAAA, 16) => Some(IpAddr::from(<[u8; 16]>::try_from(record.rdata).unwrap())),
+									_ => None,
+								}).map(|ip| SocketAddr::new(ip, port)).collect();
+							}
+							
+							// every IPv6 address, then every IPv4 one, each tried in turn until one
+							// responds, so that a host that's unreachable over IPv6 is still asked
+							let mut servers = vec![];
+							for qtype in &[record_type::AAAA, record_type::A] {
+								let ns_question = Question {
+									qname: host.split(".").map(|label| label.to_string()).collect(),
+									qtype: *qtype,
+									qclass: 1,
+								};
+								if rns.external {
+									if let Response::Ok(ans, _, _) = traced_lookup(upstream, ns_question, upstream.resolvers(), depth) {
+										servers.append(&mut addresses(ans, port));
+									}
+								} else {
+									match handle_dns_at_depth(&ns_question, upstream, config, depth + 1) {
+										Response::Ok(ans, _, _) if ans.len() > 0 => {
+											servers.append(&mut addresses(ans, port));
+										}
+										_ => {
+											if let Response::Ok(ans, _, _) = traced_lookup(upstream, ns_question, upstream.resolvers(), depth) {
+												servers.append(&mut addresses(ans, port));
+											}
+										}
+									}
+								}
+							}
+							
+							if !servers.is_empty() {
+								match traced_lookup(upstream, question.clone(), &servers, depth) {
+									Response::Ok(mut rns_answer, mut rns_authority, rns_additional) => {
+										clamp_ttls(&mut rns_answer, config.min_ttl, config.max_ttl);
+										answer.append(&mut rns_answer);
+										authority.append(&mut rns_authority);
+										add_upstream_additional(&mut additional, rns_additional, config);
+									}
+									response => failure = Some(response),
+								}
+							}
+						}
+					}
+				}
+			}
+		}
+		
+		if !answer.is_empty() || !authority.is_empty() {
+			// we've got an answer; break the search
+			*answering_zone = Some(zone_index);
+			break;
+		}
+	}
+	
+	if !matched {
+		// fall back to the closest ancestor zone that lets its records be inherited
+		for start in 1..question.qname.len() {
+			if let Some((zone_index, zone)) = matching_zones(config, &question.qname[start..]).into_iter().find(|(_, zone)| zone.records.inherit) {
+				// the zone's records reach names it doesn't match, which the client may be kept from
+				// just as well
+				if !access::allows(config, zone) {
+					trace::note(depth * 2 + 1, || format!("{}: refused, as the client may not read it", zone.name()));
+					return Response::Refused;
+				}
+				trace::note(depth * 2 + 1, || format!("{}: inherited", zone.name()));
+				matched = true;
+				*answering_zone = Some(zone_index);
+				if let record_type::A | record_type::AAAA | record_type::TXT = question.qtype {
+					// the records keep the name that was asked for
+					answer.append(&mut zone_records(zone, zone_index, question, question.qtype, config));
+				}
+				break;
+			}
+		}
+	}
+	
+	if answer.is_empty() && authority.is_empty() {
+		if let Some(failure) = failure {
+			trace::note(depth * 2 + 1, || format!("failed with {:?}", failure));
+			return failure;
+		}
+		// we can't say that names outside the domains we answer for don't exist, as we aren't
+		// authoritative for them, unless we're meant to answer for everything
+		if !matched && !config.catch_all && !is_within_zones(config, &question.qname) {
+			trace::note(depth * 2 + 1, || "refused, as it's outside the domain of every zone".to_string());
+			if depth == 0 { ede::set(ede::info_code::NOT_AUTHORITATIVE, ""); }
+			return Response::Refused;
+		}
+		// names that only exist because there are names below them still exist, which resolvers
+		// minimizing the names they ask about rely on: https://tools.ietf.org/html/rfc8020
+		let lowercase: Vec<String> = question.qname.iter().map(|label| label.to_lowercase()).collect();
+		if !matched && !config.index.could_match_descendant(&config.zones, &lowercase) {
+			trace::note(depth * 2 + 1, || "NXDOMAIN, as no zone matches it or a name below it".to_string());
+			return Response::NameError;
+		}
+	}
+	
+	trace::note(depth * 2 + 1, || format!("{} answers, {} authority records", answer.len(), authority.len()));
+	return Response::Ok(answer, authority, additional);
+}
+
+#[cfg(test)]
+mod test {
+	use std::{env, process};
+	use std::cell::RefCell;
+	use std::collections::HashMap;
+	use std::convert::TryFrom;
+	use std::fs;
+	use std::io::{self, Read, Write};
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+	use std::os::unix::fs::PermissionsExt;
+	use std::os::unix::net::{UnixListener, UnixStream};
+	use std::sync::{Arc, Mutex};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::thread;
+	use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+	
+	use ring::signature::{ED25519, UnparsedPublicKey};
+	
+	use crate::config::{AaaaRecord, AnameRecord, ARecord, CnameRecord, Config, DEFAULT_EXPIRE, DEFAULT_REFRESH, DEFAULT_RETRY, Dnssec, Label, LabelRegex, MxRecord, NsRecord, PtrRecord, Records, RnsHost, RnsRecord, SoaRecord, SrvRecord, TxtRecord, Zone, ZoneIndex};
+	use crate::options::{ListenAddress, Options};
+	use crate::server::answers::AnswerCache;
+	use crate::server::stats::{Stats, ZoneCounts};
+	use crate::server::timing::{self, Phase, SlowQuery};
+	use crate::server::{cached_response, ClientSlot, does_match, handle_dns, serve, Transport, handle_tcp_connection, load_resolver_cache, make_slip_response, make_soa, match_outcomes, matching_zones, normalize_name, normalize_rrsets, read_tcp_message, resolve, resolver_lookup, respond, Response, save_resolver_cache, Upstream, verify_response, write_tcp_message};
+	use crate::server::shared::{Answering, SharedConfig};
+	use crate::server::views::ClientViews;
+	use crate::server::cache::Cache;
+	use crate::server::checks::{self, Checker, Health};
+	use crate::server::dnssec::{self, SigningKey};
+	use crate::server::ede;
+	use crate::server::mock_resolver::{MockResolver, Reply};
+	use crate::server::protocol::{self, class, Edns, EdnsOption, Message, opcode, Question, record_type, Resource};
+	use crate::server::trace::TRACE_OPTION;
+	use crate::server::tsig;
+	
+	#[test]
+	fn test_does_match() {
+		let com = &["com".to_string()];
+		let example_com = &["example".to_string(), "com".to_string()];
+		let www_example_com = &["www".to_string(), "example".to_string(), "com".to_string()];
+		let lcom = Label::Basic("com".to_string());
+		let lexample = Label::Basic("example".to_string());
+		let lwww = Label::Basic("www".to_string());
+		
+		assert!(does_match(&[vec![Label::Basic("example".to_string())]], &["ExAmplE".to_string()]));
+		
+		assert!(does_match(&[vec![lcom.clone()]], com));
+		assert!(!does_match(&[vec![lcom.clone()]], example_com));
+		assert!(does_match(&[vec![lexample.clone(), lcom.clone()]], example_com));
+		assert!(!does_match(&[vec![lexample.clone(), lcom.clone()]], com));
+		assert!(does_match(&[vec![lwww.clone(), lexample.clone(), lcom.clone()]], www_example_com));
+		
+		assert!(!does_match(&[vec![Label::Wildcard, lcom.clone()]], com));
+		assert!(does_match(&[vec![Label::Wildcard, lcom.clone()]], example_com));
+		assert!(!does_match(&[vec![Label::Wildcard, lcom.clone()]], www_example_com));
+		assert!(does_match(&[vec![Label::Wildcard, Label::Wildcard, lcom.clone()]], www_example_com));
+		
+		assert!(!does_match(&[vec![lwww.clone(), Label::Wildcard, lcom.clone()]], com));
+		assert!(!does_match(&[vec![lwww.clone(), Label::Wildcard, lcom.clone()]], example_com));
+		assert!(does_match(&[vec![lwww.clone(), Label::Wildcard, lcom.clone()]], www_example_com));
+		
+		assert!(!does_match(&[vec![Label::SubWildcard, lcom.clone()]], com));
+		assert!(does_match(&[vec![Label::SubWildcard, lcom.clone()]], example_com));
+		assert!(does_match(&[vec![Label::SubWildcard, lcom.clone()]], www_example_com));
+		
+		assert!(!does_match(&[vec![Label::Basic("prefix".to_string()), Label::SubWildcard]], &["prefix".to_string()]));
+		assert!(does_match(&[vec![Label::Basic("prefix".to_string()), Label::SubWildcard]], &["prefix".to_string(), "example".to_string()]));
+		assert!(does_match(&[vec![Label::Basic("prefix".to_string()), Label::SubWildcard]], &["prefix".to_string(), "example".to_string(), "com".to_string()]));
+		
+		assert!(!does_match(&[vec![Label::AllWildcard, lcom.clone()]], &[]));
+		assert!(does_match(&[vec![Label::AllWildcard, lcom.clone()]], com));
+		assert!(does_match(&[vec![Label::AllWildcard, lcom.clone()]], example_com));
+		assert!(does_match(&[vec![Label::AllWildcard, lcom.clone()]], www_example_com));
+		
+		assert!(does_match(&[vec![Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], com));
+		assert!(!does_match(&[vec![Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::Regex(false, LabelRegex::new(r"c.m", false).unwrap())]], com));
+		assert!(does_match(&[vec![Label::Regex(false, LabelRegex::new(r"[a-z]{3}", false).unwrap())]], com));
+		
+		assert!(does_match(&[vec![Label::Regex(true, LabelRegex::new(r"[a-z]+\.[a-z]+", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::Regex(true, LabelRegex::new(r"([a-z]+\.)*[a-z]+", false).unwrap())]], com));
+		assert!(does_match(&[vec![Label::Regex(true, LabelRegex::new(r"([a-z]+\.)*[a-z]+", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::Regex(true, LabelRegex::new(r"([a-z]+\.)*[a-z]+", false).unwrap())]], www_example_com));
+		
+		// eager regular expressions must match all of the labels they take
+		let abc_xyz = || Label::Regex(true, LabelRegex::new(r"abc\.xyz", false).unwrap());
+		let name = |name: &str| name.split('.').map(|label| label.to_string()).collect::<Vec<String>>();
+		assert!(does_match(&[vec![abc_xyz()]], &name("abc.xyz")));
+		assert!(!does_match(&[vec![abc_xyz()]], &name("abc.xyz.com")));
+		assert!(!does_match(&[vec![abc_xyz()]], &name("0abc.xyz")));
+		assert!(does_match(&[vec![abc_xyz(), lcom.clone()]], &name("abc.xyz.com")));
+		assert!(!does_match(&[vec![abc_xyz(), lcom.clone()]], &name("abc.xyz.example.com")));
+		// and can be in the middle of a matcher
+		assert!(does_match(&[vec![lwww.clone(), abc_xyz(), lexample.clone(), lcom.clone()]], &name("www.abc.xyz.example.com")));
+		assert!(!does_match(&[vec![lwww.clone(), abc_xyz(), lexample.clone(), lcom.clone()]], &name("www.abc.example.com")));
+		let labels = || Label::Regex(true, LabelRegex::new(r"([a-z]+\.)*[a-z]+", false).unwrap());
+		assert!(does_match(&[vec![Label::Wildcard, labels(), lexample.clone(), lcom.clone()]], &name("a.b.c.example.com")));
+		assert!(does_match(&[vec![Label::Wildcard, labels(), lexample.clone(), lcom.clone()]], &name("a.b.example.com")));
+		assert!(!does_match(&[vec![Label::Wildcard, labels(), lexample.clone(), lcom.clone()]], &name("a.example.com")));
+		// backtracking lets later labels take what they need
+		assert!(does_match(&[vec![labels(), lwww.clone(), lcom.clone()]], &name("a.www.b.www.com")));
+		assert!(does_match(&[vec![Label::SubWildcard, lexample.clone(), Label::SubWildcard]], &name("a.example.b.example.c")));
+		assert!(!does_match(&[vec![lwww.clone(), Label::AllWildcard, lcom.clone()]], &name("example.com")));
+		
+		assert!(!does_match(&[vec![Label::Regex(false, LabelRegex::new(r"[a-z]+", false).unwrap()), lcom.clone()]], com));
+		assert!(does_match(&[vec![Label::Regex(false, LabelRegex::new(r"[a-z]+", false).unwrap()), lcom.clone()]], example_com));
+		
+		assert!(does_match(&[vec![lexample.clone(), Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::Regex(false, LabelRegex::new(r"example", false).unwrap()), lcom.clone()]], example_com));
+		assert!(does_match(&[vec![Label::Wildcard, Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::SubWildcard, Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::AllWildcard, Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], example_com));
+		assert!(does_match(&[vec![Label::AllWildcard, Label::Regex(false, LabelRegex::new(r"com", false).unwrap())]], com));
+	}
+	
+	#[test]
+	fn test_does_match_regex_anchored() {
+		let name = |name: &str| name.split('.').map(|label| label.to_string()).collect::<Vec<String>>();
+		let config = crate::config::parse(r"zones:
+  /web/.example.com:
+    A: 10.0.0.1
+  /api/~.example.com:
+    A: 10.0.0.2
+  /[a-z]+\.internal/:
+    A: 10.0.0.3").unwrap();
+		let matches = |qname: &str| config.zones.iter().enumerate()
+			.filter(|(_, zone)| does_match(&zone.matchers, &name(qname)))
+			.map(|(zone_index, _)| zone_index)
+			.collect::<Vec<usize>>();
+		// the whole label has to match, not just part of it
+		assert_eq!(matches("web.example.com"), vec![0]);
+		assert_eq!(matches("somewebsite.example.com"), Vec::<usize>::new());
+		assert_eq!(matches("web2.example.com"), Vec::<usize>::new());
+		// unless the regex is marked with ~
+		assert_eq!(matches("myapi-v2.example.com"), vec![1]);
+		assert_eq!(matches("api.example.com"), vec![1]);
+		// in any case, without lowercasing the name
+		assert_eq!(matches("WeB.Example.COM"), vec![0]);
+		assert_eq!(matches("Db.INTERNAL"), vec![2]);
+		assert_eq!(matches("db.internal.corp"), Vec::<usize>::new());
+	}
+	
+	/// Zones found through the index are the same as those found by trying every zone.
+	#[test]
+	fn test_matching_zones() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    A: 10.0.0.1
+  '*.example.com':
+    A: 10.0.0.2
+  '**.example.com':
+    A: 10.0.0.3
+  '***.example.org,example.net':
+    A: 10.0.0.4
+  /w+/.example.com:
+    A: 10.0.0.5
+  /[a-z]+\.b/.example.com:
+    A: 10.0.0.6
+  '*.**.example.org':
+    A: 10.0.0.7
+  '***':
+    A: 10.0.0.8
+  example.com:
+    TXT: second").unwrap();
+		let names = ["", "com", "example.com", "EXAMPLE.com", "www.example.com", "a.b.example.com", "example.org", "a.example.org", "a.b.c.example.org", "example.net", "www.example.net", "www.example.COM."];
+		for name in &names {
+			let qname: Vec<String> = name.split('.').filter(|label| !label.is_empty()).map(String::from).collect();
+			let indexed: Vec<usize> = matching_zones(&config, &qname).into_iter().map(|(zone_index, _)| zone_index).collect();
+			let linear: Vec<usize> = config.zones.iter().enumerate().filter(|(_, zone)| does_match(&zone.matchers, &qname)).map(|(zone_index, _)| zone_index).collect();
+			assert_eq!(indexed, linear, "{}", name);
+		}
+	}
+	
+	/// Run with `cargo test --release -- --ignored test_matching_zones_load` to compare looking zones
+	/// up through the index with trying every one of them.
+	#[test]
+	#[ignore]
+	fn test_matching_zones_load() {
+		const ZONES: usize = 10000;
+		let mut yaml = "zones:\n".to_string();
+		for zone in 0..ZONES {
+			yaml.push_str(&format!("  '*.zone{0}.example.com,zone{0}.example.com':\n    A: 10.0.0.1\n", zone));
+		}
+		let config = crate::config::parse(&yaml).unwrap();
+		let names: Vec<Vec<String>> = (0..1000).map(|zone| vec!["www".to_string(), format!("zone{}", zone * 7), "example".to_string(), "com".to_string()]).collect();
+		
+		let instant = Instant::now();
+		for name in &names {
+			assert_eq!(matching_zones(&config, name).len(), 1);
+		}
+		let indexed = instant.elapsed();
+		let instant = Instant::now();
+		for name in &names {
+			assert_eq!(config.zones.iter().filter(|zone| does_match(&zone.matchers, name)).count(), 1);
+		}
+		let linear = instant.elapsed();
+		println!("{} lookups in {} zones took {:?} indexed and {:?} trying every zone", names.len(), ZONES, indexed, linear);
+		assert!(indexed < linear);
+	}
+	
+	#[test]
+	fn test_match_outcomes() {
+		let config = crate::config::parse(r"zones:
+  www.example.com,/^api-\d+$/.example.com:
+    A: 10.0.0.1
+  '**.sub.example.com':
+    A: 10.0.0.2
+").unwrap();
+		let name = |name: &str| name.split('.').map(|label| label.to_string()).collect::<Vec<String>>();
+		let outcomes = |zone: usize, qname: &str| match_outcomes(&config.zones[zone].matchers, &name(qname));
+		
+		assert_eq!(outcomes(0, "API-1.example.com"), vec![
+			("www.example.com".to_string(), Some("'api-1' isn't 'www'".to_string())),
+			(r"/^api-\d+$/.example.com".to_string(), None),
+		]);
+		// the reason is the label where the matcher stopped matching
+		assert_eq!(outcomes(0, "www.exmple.com")[0].1, Some("'exmple' isn't 'example'".to_string()));
+		assert_eq!(outcomes(0, "api-x.example.com")[1].1, Some(r"'api-x' doesn't match /^api-\d+$/".to_string()));
+		assert_eq!(outcomes(0, "www.example.com.au")[0].1, Some("nothing matches 'au'".to_string()));
+		assert_eq!(outcomes(0, "example.com")[0].1, Some("'example' isn't 'www'".to_string()));
+		assert_eq!(outcomes(1, "sub.example.com")[0].1, Some("the name ends before 'sub'".to_string()));
+		assert_eq!(outcomes(1, "a.sub.example.org")[0].1, Some("'org' isn't 'com'".to_string()));
+		assert_eq!(outcomes(1, "a.b.sub.example.com")[0].1, None);
+	}
+	
+	/// A query with the trace option gets a trace back explaining why a zone didn't match it.
+	#[test]
+	fn test_trace_option() {
+		let config = crate::config::parse(r"zones:
+  /^api-\d+$/.example.com:
+    A: 10.0.0.1
+  example.com:
+    NS: ns1.example.com
+").unwrap();
+		let trace = |qname: &str, option: bool| {
+			let mut request = protocol::make_message_from_question(vec![Question { qname: name(qname), qtype: record_type::A, qclass: 1 }]);
+			request.edns = Some(Edns {
+				udp_payload_size: 1232,
+				extended_rcode_and_flags: 0,
+				options: if option { vec![EdnsOption { code: TRACE_OPTION, data: vec![] }] } else { vec![] },
+			});
+			let request = protocol::serialize(&request, u16::MAX);
+			let response = protocol::parse(&handle_request(request, &test_options(), &config, Transport::Tcp, test_src()).unwrap()).unwrap();
+			let record = response.additional.into_iter().find(|record| record.rclass == class::CH)?;
+			assert_eq!((record.rtype, record.ttl), (record_type::TXT, 0));
+			let mut lines = vec![];
+			let mut rdata = &record.rdata[..];
+			while let Some((&len, rest)) = rdata.split_first() {
+				lines.push(String::from_utf8(rest[..len as usize].to_vec()).unwrap());
+				rdata = &rest[len as usize..];
+			}
+			Some(lines)
+		};
+		
+		let lines = trace("api-x.example.com", true).unwrap();
+		assert_eq!(lines[0], "api-x.example.com A");
+		assert!(lines.contains(&r"  /^api-\d+$/.example.com doesn't match: 'api-x' doesn't match /^api-\d+$/".to_string()), "{:#?}", lines);
+		assert!(lines.contains(&"  example.com doesn't match: 'api-x' isn't 'example'".to_string()), "{:#?}", lines);
+		
+		let lines = trace("api-1.example.com", true).unwrap();
+		assert!(lines.contains(&r"  /^api-\d+$/.example.com matches".to_string()), "{:#?}", lines);
+		assert!(lines.contains(&r"  /^api-\d+$/.example.com: records".to_string()), "{:#?}", lines);
+		
+		assert_eq!(trace("api-1.example.com", false), None);
+	}
+	
+	fn test_src() -> SocketAddr {
+		"127.0.0.1:5353".parse().unwrap()
+	}
+	
+	/// Parses and answers a raw query, as `respond` does for one that isn't an update, a zone
+	/// transfer, or already cached.
+	pub fn handle_request(buf: Vec<u8>, options: &Options, config: &Config, transport: Transport, src: SocketAddr) -> Option<Vec<u8>> {
+		timing::start(options.slow_query_threshold.is_some());
+		let request = protocol::parse(&buf);
+		timing::lap(Phase::Parse);
+		let answering = Answering { config: Arc::new(config.clone()), answers: AnswerCache::default(), views: ClientViews::new(config), stats: Stats::default() };
+		checks::start(&Health::default());
+		return super::handle_request(&buf, request, options, &answering, transport, src);
+	}
+	
+	/// Answers a raw query from the answer cache, as `respond` does when it's there.
+	fn cached(buf: &[u8], options: &Options, config: &SharedConfig, transport: Transport, src: SocketAddr) -> Option<Vec<u8>> {
+		return cached_response(&protocol::parse(buf).unwrap(), options, &config.answering(), transport, src);
+	}
+	
+	pub fn test_options() -> Options {
+		Options {
+			listen: vec!["127.0.0.1".parse().unwrap()],
+			ipv4_only: false,
+			ipv6_only: false,
+			listen_port: 0,
+			verbose: false,
+			trace_name: vec![],
+			config: "".to_string(),
+			config_env: None,
+			check: false,
+			dump_zones: false,
+			no_startup_checks: false,
+			strict_startup: false,
+			listen_unix: None,
+			unix_mode: None,
+			user: None,
+			group: None,
+			threads: 0,
+			udp_threads: None,
+			tcp_timeout: 10,
+			tcp_read_timeout: 5,
+			tcp_lifetime: 120,
+			max_tcp_conns: 128,
+			max_client_jobs: 8,
+			tls_port: None,
+			tls_cert: None,
+			tls_key: None,
+			query_log: false,
+			rrl_rate: None,
+			rrl_burst: 20,
+			rrl_slip: 2,
+			log_format: "text".to_string(),
+			slow_query_threshold: None,
+			max_udp_payload: 1232,
+			resolver_timeout: 2000,
+			resolver_nttl: 60,
+			resolver_cache_size: 10000,
+			answer_cache_size: 0,
+			stall_timeout: 0,
+			cache_file: None,
+			prefetch: false,
+			resolver: vec!["127.0.0.53:53".parse().unwrap()],
+			allow_recursion: vec![],
+			health_port: None,
+			health_probe: None,
+			api_port: None,
+			api_address: Ipv4Addr::LOCALHOST.into(),
+			api_token_env: "TACODNS_API_TOKEN".to_string(),
+			version_string: Some("tacodns 1.2.3".to_string()),
+			hostname_string: Some("ns1".to_string()),
+		}
+	}
+	
+	#[test]
+	fn test_a() {
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "10.10.10.10".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![10, 10, 10, 10],
+		}], vec![], vec![]));
+		
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "10.10.10.10".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}, ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "11.11.11.11".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![10, 10, 10, 10],
+		}, Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![11, 11, 11, 11],
+		}], vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_case() {
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["ExAmple".to_string(), "cOm".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "10.10.10.10".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["ExAmple".to_string(), "cOm".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![10, 10, 10, 10],
+		}], vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_mixed_case() {
+		// answers A queries with the name asked, counting them
+		let (udp, _tcp, upstream) = fake_resolver();
+		let count = Arc::new(AtomicUsize::new(0));
+		let server = {
+			let count = count.clone();
+			thread::spawn(move || {
+				udp.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+				let mut buf = vec![0; 512];
+				while let Ok((size, src)) = udp.recv_from(&mut buf) {
+					count.fetch_add(1, Ordering::SeqCst);
+					let mut message = protocol::parse(&buf[..size]).unwrap();
+					message.header.qr = true;
+					message.answer = vec![Resource {
+						rname: message.question[0].qname.clone(),
+						rtype: record_type::A,
+						rclass: 1,
+						ttl: 300,
+						rdata: vec![10, 0, 0, 2],
+					}];
+					udp.send_to(&protocol::serialize(&message, 512), src).unwrap();
+				}
+			})
+		};
+		
+		let config = crate::config::parse(&format!(r"zones:
+  example.com:
+    A: 10.0.0.1
+    NS: ns1
+    MX: mail
+  ns1.example.com:
+    A: 10.0.0.53
+  upstream.example.com:
+    RNS: {}", upstream)).unwrap();
+		let exchange = |qname: &str, qtype: u16| {
+			let request = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+				qname: qname.split('.').map(|label| label.to_string()).collect(),
+				qtype,
+				qclass: 1,
+			}]), 512);
+			let response = handle_request(request.clone(), &test_options(), &config, Transport::Udp, test_src()).unwrap();
+			// the question section is echoed byte for byte
+			let question_len = qname.len() + 2 + 4;
+			assert_eq!(response[12..12 + question_len], request[12..12 + question_len]);
+			protocol::parse(&response).unwrap()
+		};
+		let name = |name: &str| name.split('.').map(|label| label.to_string()).collect::<Vec<String>>();
+		
+		let response = exchange("ExAmPlE.CoM", record_type::A);
+		assert_eq!(response.answer[0].rname, name("ExAmPlE.CoM"));
+		// names in rdata don't take on the query's case
+		assert_eq!(response.authority[0].rname, name("ExAmPlE.CoM"));
+		assert_eq!(response.authority[0].rdata, protocol::serialize_name(vec!["ns1", "example", "com"]));
+		assert_eq!(response.additional[0].rname, name("ns1.example.com"));
+		
+		let response = exchange("eXaMpLe.cOm", record_type::MX);
+		assert_eq!(response.answer[0].rname, name("eXaMpLe.cOm"));
+		assert_eq!(response.answer[0].rdata, protocol::serialize_mx("mail.example.com", 10));
+		
+		// cached regardless of case
+		assert_eq!(exchange("UpStReAm.example.com", record_type::A).answer[0].rname, name("UpStReAm.example.com"));
+		assert_eq!(exchange("uPsTrEaM.example.com", record_type::A).answer[0].rname, name("uPsTrEaM.example.com"));
+		assert_eq!(count.load(Ordering::SeqCst), 1);
+		
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_aaaa() {
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::AAAA,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![AaaaRecord {
+						ttl: Duration::from_secs(100),
+						ip6addr: "::1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::AAAA,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+		}], vec![], vec![]));
+		
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::AAAA,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![AaaaRecord {
+						ttl: Duration::from_secs(100),
+						ip6addr: "::2".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}, AaaaRecord {
+						ttl: Duration::from_secs(100),
+						ip6addr: "::3".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::AAAA,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2],
+		}, Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::AAAA,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
+		}], vec![], vec![]));
 	}
 	
-	return (answer, authority, additional);
-}
-
-#[cfg(test)]
-mod test {
-	use std::time::Duration;
+	#[test]
+	fn test_ns() {
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::NS,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![],
+					ns: vec![NsRecord {
+						ttl: Duration::from_secs(100),
+						name: "ns.example.com.".to_string(),
+						metadata: None,
+					}],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::NS,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![2, 110, 115, 7, 101, 120, 97, 109, 112, 108, 101, 3, 99, 111, 109, 0],
+		}], vec![], vec![]));
+		
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::NS,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![],
+					ns: vec![NsRecord {
+						ttl: Duration::from_secs(100),
+						name: "ns.example.com.".to_string(),
+						metadata: None,
+					}],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("ns".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "1.1.1.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::NS,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![2, 110, 115, 7, 101, 120, 97, 109, 112, 108, 101, 3, 99, 111, 109, 0],
+		}], vec![], vec![Resource {
+			rname: vec!["ns".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![1, 1, 1, 1],
+		}]));
+	}
+	
+	/// NS records are answered at the apex when asked for, and otherwise fill in the authority
+	/// section, with the addresses of the servers in the additional section either way.
+	#[test]
+	fn test_ns_sections() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    NS: ns1
+    A: 10.0.0.1
+  ns1.example.com:
+    A: 10.0.0.53").unwrap();
+		let query = |qtype: u16| {
+			let request = protocol::make_message_from_question(vec![Question {
+				qname: vec!["example".to_string(), "com".to_string()],
+				qtype,
+				qclass: class::IN,
+			}]);
+			protocol::parse(&handle_request(protocol::serialize(&request, u16::MAX), &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap()
+		};
+		let ns = protocol::serialize_name(vec!["ns1", "example", "com"]);
+		
+		let response = query(record_type::NS);
+		assert!(response.header.aa);
+		assert_eq!(response.answer.iter().map(|record| &record.rdata).collect::<Vec<&Vec<u8>>>(), vec![&ns]);
+		assert!(response.authority.is_empty());
+		assert_eq!(response.additional.iter().map(|record| &record.rdata).collect::<Vec<&Vec<u8>>>(), vec![&vec![10, 0, 0, 53]]);
+		
+		let response = query(record_type::A);
+		assert!(response.header.aa);
+		assert_eq!(response.answer.len(), 1);
+		assert_eq!(response.authority.iter().map(|record| &record.rdata).collect::<Vec<&Vec<u8>>>(), vec![&ns]);
+		assert_eq!(response.additional.iter().map(|record| &record.rdata).collect::<Vec<&Vec<u8>>>(), vec![&vec![10, 0, 0, 53]]);
+	}
 	
-	use crate::config::{AaaaRecord, ARecord, CnameRecord, Config, Label, MxRecord, NsRecord, Records, TxtRecord, Zone};
-	use crate::options::Options;
-	use crate::regex::Regex;
-	use crate::server::{does_match, handle_dns};
-	use crate::server::protocol::{Question, record_type, Resource};
+	#[test]
+	fn test_normalize_rrsets() {
+		let record = |rname: &str, rtype: u16, ttl: u32, rdata: &[u8]| Resource { rname: name(rname), rtype, rclass: class::IN, ttl, rdata: rdata.to_vec() };
+		let mut records = vec![
+			record("example.com", record_type::A, 300, &[10, 0, 0, 1]),
+			record("www.example.com", record_type::A, 60, &[10, 0, 0, 2]),
+			record("EXAMPLE.com", record_type::A, 120, &[10, 0, 0, 3]),
+			record("example.com", record_type::A, 30, &[10, 0, 0, 1]),
+			record("example.com", record_type::TXT, 600, b"\x02hi"),
+			// RRSIGs covering different types aren't a set
+			record("example.com", record_type::RRSIG, 300, &[0, 1]),
+			record("example.com", record_type::RRSIG, 600, &[0, 16]),
+		];
+		normalize_rrsets(&mut records);
+		assert_eq!(records, vec![
+			record("example.com", record_type::A, 30, &[10, 0, 0, 1]),
+			record("www.example.com", record_type::A, 60, &[10, 0, 0, 2]),
+			record("EXAMPLE.com", record_type::A, 30, &[10, 0, 0, 3]),
+			record("example.com", record_type::TXT, 600, b"\x02hi"),
+			record("example.com", record_type::RRSIG, 300, &[0, 1]),
+			record("example.com", record_type::RRSIG, 600, &[0, 16]),
+		]);
+	}
 	
 	#[test]
-	fn test_does_match() {
-		let com = &["com".to_string()];
-		let example_com = &["example".to_string(), "com".to_string()];
-		let www_example_com = &["www".to_string(), "example".to_string(), "com".to_string()];
-		let lcom = Label::Basic("com".to_string());
-		let lexample = Label::Basic("example".to_string());
-		let lwww = Label::Basic("www".to_string());
+	fn test_normalize_glue() {
+		// ns2 leads back to ns1, whose addresses were configured with different TTLs
+		let config = crate::config::parse(r"zones:
+  example.com:
+    NS:
+      - ns1
+      - ns2
+  ns1.example.com:
+    A 1m:
+      - 10.0.0.53
+      - 10.0.0.54 30
+  ns2.example.com:
+    CNAME: ns1").unwrap();
+		let response = query(Question { qname: name("example.com"), qtype: record_type::NS, qclass: class::IN }, &config);
+		assert_eq!(response.answer.len(), 2);
+		let additional: Vec<(String, u16, u32)> = response.additional.iter()
+			.map(|record| (record.rname.join("."), record.rtype, record.ttl))
+			.collect();
+		assert_eq!(additional, vec![
+			("ns1.example.com".to_string(), record_type::A, 30),
+			("ns1.example.com".to_string(), record_type::A, 30),
+			("ns2.example.com".to_string(), record_type::CNAME, 1800),
+		]);
+	}
+	
+	#[test]
+	fn test_metadata_not_answered() {
+		let plain = crate::config::parse(r"zones:
+  example.com:
+    A: 10.0.0.1
+    MX: mail
+    TXT: v=spf1 -all
+    NS: ns1").unwrap();
+		let annotated = crate::config::parse(r"zones:
+  example.com:
+    A: {ip: 10.0.0.1, comment: primary LB, labels: {team: infra}}
+    MX: {priority: 10, host: mail, comment: primary}
+    TXT: {data: v=spf1 -all, labels: {owner: mail}}
+    NS: {name: ns1, comment: ours}").unwrap();
+		for &qtype in &[record_type::A, record_type::MX, record_type::TXT, record_type::NS] {
+			let question = Question { qname: name("example.com"), qtype, qclass: class::IN };
+			assert_eq!(query(question.clone(), &annotated), query(question, &plain));
+		}
+	}
+	
+	#[test]
+	fn test_minimal_responses() {
+		let zones = r"
+zones:
+  ns1.sub.example.com:
+    A: 10.0.1.53
+  sub.example.com,**.sub.example.com:
+    NS: ns1.sub.example.com.
+  example.com:
+    NS: ns1
+    A: 10.0.0.1
+    MX: mail
+  ns1.example.com:
+    A: 10.0.0.53
+  mail.example.com:
+    A: 10.0.0.25
+  example.org:
+    minimal-responses: false
+    NS: ns1.example.com.
+    A: 10.0.0.2";
+		let full = crate::config::parse(zones).unwrap();
+		let minimal = crate::config::parse(&format!("minimal-responses: true{}", zones)).unwrap();
+		// the sizes of the answer, authority, and additional sections
+		let sections = |config: &Config, name: &str, qtype: u16| {
+			let request = protocol::make_message_from_question(vec![Question {
+				qname: name.split('.').map(|label| label.to_string()).collect(),
+				qtype,
+				qclass: class::IN,
+			}]);
+			let response = protocol::parse(&handle_request(protocol::serialize(&request, u16::MAX), &test_options(), config, Transport::Udp, test_src()).unwrap()).unwrap();
+			(response.answer.len(), response.authority.len(), response.additional.len())
+		};
 		
-		assert!(does_match(&[vec![Label::Basic("example".to_string())]], &["ExAmplE".to_string()]));
+		assert_eq!(sections(&full, "example.com", record_type::A), (1, 1, 1));
+		assert_eq!(sections(&minimal, "example.com", record_type::A), (1, 0, 0));
+		assert_eq!(sections(&full, "example.com", record_type::MX), (1, 1, 2));
+		assert_eq!(sections(&minimal, "example.com", record_type::MX), (1, 0, 0));
+		assert_eq!(sections(&full, "example.com", record_type::NS), (1, 0, 1));
+		assert_eq!(sections(&minimal, "example.com", record_type::NS), (1, 0, 0));
+		// negative answers keep their SOA
+		assert_eq!(sections(&full, "example.com", record_type::TXT), (0, 1, 0));
+		assert_eq!(sections(&minimal, "example.com", record_type::TXT), (0, 1, 0));
+		// and referrals their NS records and glue
+		assert_eq!(sections(&full, "www.sub.example.com", record_type::A), (0, 1, 1));
+		assert_eq!(sections(&minimal, "www.sub.example.com", record_type::A), (0, 1, 1));
+		// a zone can say otherwise
+		assert_eq!(sections(&minimal, "example.org", record_type::A), (1, 1, 1));
+		let minimal_zone = crate::config::parse(&zones.replace("minimal-responses: false", "minimal-responses: true")).unwrap();
+		assert_eq!(sections(&minimal_zone, "example.org", record_type::A), (1, 0, 0));
+		assert_eq!(sections(&minimal_zone, "example.com", record_type::A), (1, 1, 1));
+	}
+	
+	#[test]
+	fn test_fill_authority() {
+		let zones = r"
+zones:
+  /api-\d+/.example.com:
+    A: 10.0.0.1
+  /web-\d+/.example.com:
+    NS: ns1.example.com.
+    A: 10.0.0.2
+  '*.example.com':
+    NS: ns1.example.net.
+    A: 10.0.0.3
+  ns1.example.com:
+    A: 10.0.0.53";
+		let config = crate::config::parse(zones).unwrap();
+		let authority = |config: &Config, name: &str| {
+			let response = query(Question { qname: name.split('.').map(str::to_string).collect(), qtype: record_type::A, qclass: class::IN }, config);
+			let glue = response.additional.iter().map(|record| record.rname.join(".")).collect::<Vec<String>>();
+			(response.header.rcode, response.authority.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>(), glue)
+		};
+		let ns = |name: &str| protocol::serialize_name(name.split('.'));
 		
-		assert!(does_match(&[vec![lcom.clone()]], com));
-		assert!(!does_match(&[vec![lcom.clone()]], example_com));
-		assert!(does_match(&[vec![lexample.clone(), lcom.clone()]], example_com));
-		assert!(!does_match(&[vec![lexample.clone(), lcom.clone()]], com));
-		assert!(does_match(&[vec![lwww.clone(), lexample.clone(), lcom.clone()]], www_example_com));
+		// the zone that answered has no NS records, and those of the zone after it aren't about it
+		assert_eq!(authority(&config, "api-1.example.com"), (0, vec![], vec![]));
+		// one that has its own gets them, with their addresses
+		assert_eq!(authority(&config, "web-1.example.com"), (0, vec![ns("ns1.example.com")], vec!["ns1.example.com".to_string()]));
+		assert_eq!(authority(&config, "www.example.com"), (0, vec![ns("ns1.example.net")], vec![]));
+		// errors have nothing to fill it with
+		assert_eq!(authority(&config, "example.org"), (5, vec![], vec![]));
 		
-		assert!(!does_match(&[vec![Label::Wildcard, lcom.clone()]], com));
-		assert!(does_match(&[vec![Label::Wildcard, lcom.clone()]], example_com));
-		assert!(!does_match(&[vec![Label::Wildcard, lcom.clone()]], www_example_com));
-		assert!(does_match(&[vec![Label::Wildcard, Label::Wildcard, lcom.clone()]], www_example_com));
+		let unfilled = crate::config::parse(&format!("fill-authority: false{}", zones)).unwrap();
+		assert_eq!(authority(&unfilled, "web-1.example.com"), (0, vec![], vec![]));
+		assert_eq!(authority(&unfilled, "www.example.com"), (0, vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_default_ns_soa() {
+		let config = crate::config::parse(r"
+default-ns: [ns1.example.net., ns2.example.net.]
+default-soa:
+  rname: hostmaster.example.net
+  minimum: 5m
+zones:
+  example.com:
+    A: 10.0.0.1
+  '*.example.com':
+    A: 10.0.0.2
+  example.org:
+    NS: ns.example.org.
+    SOA: {mname: ns.example.org, rname: admin.example.org, minimum: 5m}").unwrap();
+		let ask = |name: &str, qtype: u16| query(Question { qname: name.split('.').map(str::to_string).collect(), qtype, qclass: class::IN }, &config);
+		let rdata = |records: Vec<Resource>| records.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>();
+		let ns = |name: &str| protocol::serialize_name(name.split('.'));
+		let soa = |mname: &str, rname: &str| protocol::serialize_soa(mname, rname, config.serial, DEFAULT_REFRESH.as_secs() as u32, DEFAULT_RETRY.as_secs() as u32, DEFAULT_EXPIRE.as_secs() as u32, 300);
 		
-		assert!(!does_match(&[vec![lwww.clone(), Label::Wildcard, lcom.clone()]], com));
-		assert!(!does_match(&[vec![lwww.clone(), Label::Wildcard, lcom.clone()]], example_com));
-		assert!(does_match(&[vec![lwww.clone(), Label::Wildcard, lcom.clone()]], www_example_com));
+		// a zone without NS records answers with the defaults, and fills the authority section with them
+		assert_eq!(rdata(ask("example.com", record_type::NS).answer), vec![ns("ns1.example.net"), ns("ns2.example.net")]);
+		assert_eq!(rdata(ask("www.example.com", record_type::A).authority), vec![ns("ns1.example.net"), ns("ns2.example.net")]);
+		// while one with its own keeps them
+		assert_eq!(rdata(ask("example.org", record_type::NS).answer), vec![ns("ns.example.org")]);
 		
-		assert!(!does_match(&[vec![Label::SubWildcard, lcom.clone()]], com));
-		assert!(does_match(&[vec![Label::SubWildcard, lcom.clone()]], example_com));
-		assert!(does_match(&[vec![Label::SubWildcard, lcom.clone()]], www_example_com));
+		// negative answers carry the default SOA, owned by the name asked about, with the first NS as its mname
+		let response = ask("www.example.com", record_type::TXT);
+		assert_eq!(response.authority[0].rname, vec!["www", "example", "com"]);
+		assert_eq!(rdata(response.authority), vec![soa("ns1.example.net", "hostmaster.example.net")]);
+		assert_eq!(rdata(ask("example.com", record_type::SOA).answer), vec![soa("ns1.example.net", "hostmaster.example.net")]);
+		assert_eq!(rdata(ask("example.org", record_type::TXT).authority), vec![soa("ns.example.org", "admin.example.org")]);
 		
-		assert!(!does_match(&[vec![Label::Basic("prefix".to_string()), Label::SubWildcard]], &["prefix".to_string()]));
-		assert!(does_match(&[vec![Label::Basic("prefix".to_string()), Label::SubWildcard]], &["prefix".to_string(), "example".to_string()]));
-		assert!(does_match(&[vec![Label::Basic("prefix".to_string()), Label::SubWildcard]], &["prefix".to_string(), "example".to_string(), "com".to_string()]));
+		assert_eq!(crate::config::parse("default-soa: {rname: hostmaster.example.net}\nzones: {}").unwrap_err().to_string(), "zone 'default-soa', record 'SOA': missing 'mname' field");
+	}
+	
+	#[test]
+	fn test_answer_cache() {
+		let options = Options { answer_cache_size: 10, ..test_options() };
+		let config = SharedConfig::new(crate::config::parse(r"zones:
+  cached.test:
+    A: 10.0.0.1
+  rotated.test:
+    A rotate: [10.0.0.2, 10.0.0.3]
+  uncached.test:
+    A: 10.0.0.5 0").unwrap());
+		let request = |qname: &str, id: u16| {
+			let mut request = protocol::make_message_from_question(vec![Question {
+				qname: qname.split('.').map(str::to_string).collect(),
+				qtype: record_type::A,
+				qclass: class::IN,
+			}]);
+			request.header.id = id;
+			return protocol::serialize(&request, u16::MAX);
+		};
+		let send = |qname: &str, id: u16| respond(&request(qname, id), &options, &config, Transport::Udp, test_src()).remove(0);
 		
-		assert!(!does_match(&[vec![Label::AllWildcard, lcom.clone()]], &[]));
-		assert!(does_match(&[vec![Label::AllWildcard, lcom.clone()]], com));
-		assert!(does_match(&[vec![Label::AllWildcard, lcom.clone()]], example_com));
-		assert!(does_match(&[vec![Label::AllWildcard, lcom.clone()]], www_example_com));
+		assert_eq!(cached(&request("cached.test", 1), &options, &config, Transport::Udp, test_src()), None);
+		let first = send("cached.test", 1);
+		let second = cached(&request("cached.test", 2), &options, &config, Transport::Udp, test_src()).unwrap();
+		assert_eq!(&second[..2], &[0, 2]);
+		assert_eq!(first[2..], second[2..]);
+		// the size limit of UDP doesn't apply to other transports
+		assert_eq!(cached(&request("cached.test", 2), &options, &config, Transport::Tcp, test_src()), None);
+		// a name asked in another case is answered the same, named as it was asked
+		let response = protocol::parse(&cached(&request("CACHED.test", 3), &options, &config, Transport::Udp, test_src()).unwrap()).unwrap();
+		assert_eq!(response.question[0].qname, name("CACHED.test"));
+		assert_eq!(response.answer[0].rname, name("CACHED.test"));
+		assert_eq!(response.answer[0].rdata, vec![10, 0, 0, 1]);
 		
-		assert!(does_match(&[vec![Label::Regex(false, Regex::new(r"com").unwrap())]], com));
-		assert!(!does_match(&[vec![Label::Regex(false, Regex::new(r"com").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::Regex(false, Regex::new(r"c.m").unwrap())]], com));
-		assert!(does_match(&[vec![Label::Regex(false, Regex::new(r"[a-z]{3}").unwrap())]], com));
+		send("rotated.test", 1);
+		assert_eq!(cached(&request("rotated.test", 2), &options, &config, Transport::Udp, test_src()), None);
 		
-		/*
-		eager not yet implemented
-		assert!(does_match(&[vec![Label::Regex(true, Regex::new(r"[a-z]+\.[a-z]+").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::Regex(true, Regex::new(r"([a-z]+\.)*[a-z]+").unwrap())]], com));
-		assert!(does_match(&[vec![Label::Regex(true, Regex::new(r"([a-z]+\.)*[a-z]+").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::Regex(true, Regex::new(r"([a-z]+\.)*[a-z]+").unwrap())]], www_example_com));
-		*/
+		// nor are answers with a TTL of 0
+		let response = protocol::parse(&send("uncached.test", 1)).unwrap();
+		assert_eq!(response.answer[0].ttl, 0);
+		assert_eq!(cached(&request("uncached.test", 2), &options, &config, Transport::Udp, test_src()), None);
 		
-		assert!(!does_match(&[vec![Label::Regex(false, Regex::new(r"[a-z]+").unwrap()), lcom.clone()]], com));
-		assert!(does_match(&[vec![Label::Regex(false, Regex::new(r"[a-z]+").unwrap()), lcom.clone()]], example_com));
+		// a change to the config drops every answer
+		config.change(|config| {
+			config.zones[0].records.a[0].ip4addr = "10.0.0.4".parse().unwrap();
+			Ok::<_, ()>(())
+		}).unwrap();
+		assert_eq!(cached(&request("cached.test", 3), &options, &config, Transport::Udp, test_src()), None);
+		send("cached.test", 3);
+		let response = protocol::parse(&cached(&request("cached.test", 4), &options, &config, Transport::Udp, test_src()).unwrap()).unwrap();
+		assert_eq!(response.header.id, 4);
+		assert_eq!(response.answer[0].rdata, vec![10, 0, 0, 4]);
+	}
+	
+	#[test]
+	fn test_stats() {
+		let resolver = MockResolver::new();
+		let target = Question { qname: name("target.stats.test"), qtype: record_type::A, qclass: 1 };
+		resolver.answer(target.clone(), vec![Resource { rname: target.qname.clone(), rtype: record_type::A, rclass: 1, ttl: 300, rdata: vec![192, 0, 2, 1] }]);
+		let options = Options { answer_cache_size: 10, resolver: vec![resolver.address], ..test_options() };
+		let config = SharedConfig::new(crate::config::parse(r"zones:
+  stats.test:
+    A: 10.0.0.1
+  www.stats.test:
+    TXT: hello
+  flat.stats.test:
+    ANAME: target.stats.test.").unwrap());
+		let request = |qname: &str| protocol::serialize(&protocol::make_message_from_question(vec![Question {
+			qname: name(qname),
+			qtype: record_type::A,
+			qclass: class::IN,
+		}]), 512);
+		// answered from the answer cache, where it can be, as the server would
+		let send = |qname: &str, times: usize| for _ in 0..times {
+			respond(&request(qname), &options, &config, Transport::Udp, test_src());
+		};
+		send("stats.test", 200);
+		send("WWW.stats.test", 100);
+		send("missing.stats.test", 50);
+		send("flat.stats.test", 20);
+		send("example.org", 5);
+		for index in 0..30 { send(&format!("{}.missing.stats.test", index), 1); }
 		
-		assert!(does_match(&[vec![lexample.clone(), Label::Regex(false, Regex::new(r"com").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::Regex(false, Regex::new(r"example").unwrap()), lcom.clone()]], example_com));
-		assert!(does_match(&[vec![Label::Wildcard, Label::Regex(false, Regex::new(r"com").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::SubWildcard, Label::Regex(false, Regex::new(r"com").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::AllWildcard, Label::Regex(false, Regex::new(r"com").unwrap())]], example_com));
-		assert!(does_match(&[vec![Label::AllWildcard, Label::Regex(false, Regex::new(r"com").unwrap())]], com));
+		let stats = config.stats();
+		assert_eq!(stats.zone("stats.test"), Some(ZoneCounts { queries: 200, answers: 200, nxdomain: 0, no_data: 0, upstream_lookups: 0 }));
+		assert_eq!(stats.zone("www.stats.test"), Some(ZoneCounts { queries: 100, answers: 0, nxdomain: 0, no_data: 100, upstream_lookups: 0 }));
+		assert_eq!(stats.zone("flat.stats.test"), Some(ZoneCounts { queries: 20, answers: 20, nxdomain: 0, no_data: 0, upstream_lookups: 20 }));
+		// names that no zone matches are only in the total
+		assert_eq!(stats.total(), ZoneCounts { queries: 405, answers: 220, nxdomain: 80, no_data: 100, upstream_lookups: 20 });
+		assert_eq!(stats.top_names()[..4], [
+			("stats.test".to_string(), 200),
+			("www.stats.test".to_string(), 100),
+			("missing.stats.test".to_string(), 50),
+			("flat.stats.test".to_string(), 20),
+		]);
+		// every request is counted by its size and the rcode of its response
+		assert_eq!(stats.sizes().iter().fold((0, 0), |(requests, responses), size| (requests + size.1, responses + size.2)), (405, 405));
+		assert_eq!(stats.rcodes(), vec![("NOERROR".to_string(), 320), ("NXDOMAIN".to_string(), 80), ("REFUSED".to_string(), 5)]);
 	}
 	
-	fn test_options() -> Options {
-		Options {
-			listen_address: "127.0.0.1".parse().unwrap(),
-			listen_port: 0,
-			verbose: false,
-			config: "".to_string(),
-			config_env: None,
-			threads: 0,
-			resolver: "127.0.0.53:53".parse().unwrap(),
-		}
+	#[test]
+	fn test_cname() {
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "127.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("www".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![CnameRecord {
+						ttl: Duration::from_secs(100),
+						name: "example.com.".to_string(),
+						metadata: None,
+					}],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::CNAME,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0],
+		}, Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![127, 0, 0, 1],
+		}], vec![], vec![]));
+		
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["www2".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![ARecord {
+						ttl: Duration::from_secs(100),
+						ip4addr: "127.0.0.1".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
+					}],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("www".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![CnameRecord {
+						ttl: Duration::from_secs(100),
+						name: "example.com.".to_string(),
+						metadata: None,
+					}],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("www2".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![CnameRecord {
+						ttl: Duration::from_secs(100),
+						name: "www.example.com.".to_string(),
+						metadata: None,
+					}],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["www2".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::CNAME,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0],
+		}, Resource {
+			rname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::CNAME,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0],
+		}, Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![127, 0, 0, 1],
+		}], vec![], vec![]));
 	}
 	
 	#[test]
-	fn test_a() {
+	fn test_mx() {
 		assert_eq!(handle_dns(&Question {
 			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::A,
+			qtype: record_type::MX,
 			qclass: 1,
 		}, &test_options(), &Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
-					a: vec![ARecord {
-						ttl: Duration::from_secs(100),
-						ip4addr: "10.10.10.10".parse().unwrap(),
-					}],
+					a: vec![],
 					aaaa: vec![],
 					ns: vec![],
 					cname: vec![],
 					aname: vec![],
-					mx: vec![],
+					alias: vec![],
+					mx: vec![MxRecord {
+						ttl: Duration::from_secs(100),
+						priority: 10,
+						host: "mail.example.com.".to_string(),
+						metadata: None,
+					}],
 					txt: vec![],
 					rns: vec![],
 					trpp: vec![],
+					forward: None,
+					..Default::default()
 				},
 			}],
-		}), (vec![Resource {
+		}), Response::Ok(vec![Resource {
 			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::A,
+			rtype: record_type::MX,
 			rclass: 1,
 			ttl: 100,
-			rdata: vec![10, 10, 10, 10],
+			rdata: vec![0, 10, 4, b'm', b'a', b'i', b'l', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0],
 		}], vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_mx_additional() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    MX:
+      - mail
+      - mail.example.org.
+    SRV: 10 20 5060 sip
+  mail.example.com:
+    A: 10.0.0.1
+    AAAA: '2001:db8::1'
+  sip.example.com:
+    A: 10.0.0.2").unwrap();
+		let additional = |qtype: u16| match handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype,
+			qclass: class::IN,
+		}, &test_options(), &config) {
+			Response::Ok(answer, _, additional) => {
+				assert_eq!(answer.len(), if qtype == record_type::MX { 2 } else { 1 });
+				additional.into_iter().map(|record| (record.rname.join("."), record.rtype)).collect::<Vec<(String, u16)>>()
+			}
+			response => panic!("unexpected response {:?}", response),
+		};
 		
+		// mail.example.org isn't configured here, so it adds nothing
+		assert_eq!(additional(record_type::MX), vec![
+			("mail.example.com".to_string(), record_type::A),
+			("mail.example.com".to_string(), record_type::AAAA),
+		]);
+		assert_eq!(additional(record_type::SRV), vec![("sip.example.com".to_string(), record_type::A)]);
+	}
+	
+	#[test]
+	fn test_txt() {
 		assert_eq!(handle_dns(&Question {
 			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::A,
+			qtype: record_type::TXT,
 			qclass: 1,
 		}, &test_options(), &Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
-					a: vec![ARecord {
-						ttl: Duration::from_secs(100),
-						ip4addr: "10.10.10.10".parse().unwrap(),
-					}, ARecord {
+					a: vec![],
+					aaaa: vec![],
+					ns: vec![],
+					cname: vec![],
+					aname: vec![],
+					alias: vec![],
+					mx: vec![],
+					txt: vec![TxtRecord {
 						ttl: Duration::from_secs(100),
-						ip4addr: "11.11.11.11".parse().unwrap(),
+						data: "data content".to_string(),
+						metadata: None,
 					}],
+					rns: vec![],
+					trpp: vec![],
+					forward: None,
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::TXT,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![12, b'd', b'a', b't', b'a', b' ', b'c', b'o', b'n', b't', b'e', b'n', b't'],
+		}], vec![], vec![]));
+		
+		let long = "x".repeat(300);
+		let mut long_rdata = vec![255];
+		long_rdata.append(&mut vec![b'x'; 255]);
+		long_rdata.push(45);
+		long_rdata.append(&mut vec![b'x'; 45]);
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["_acme-challenge".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::TXT,
+			qclass: 1,
+		}, &test_options(), &Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("_acme-challenge".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					a: vec![],
 					aaaa: vec![],
 					ns: vec![],
 					cname: vec![],
 					aname: vec![],
+					alias: vec![],
 					mx: vec![],
-					txt: vec![],
+					txt: vec![TxtRecord {
+						ttl: Duration::from_secs(100),
+						data: "v=spf1 -all".to_string(),
+						metadata: None,
+					}, TxtRecord {
+						ttl: Duration::from_secs(5),
+						data: long,
+						metadata: None,
+					}, TxtRecord {
+						ttl: Duration::from_secs(5),
+						data: "".to_string(),
+						metadata: None,
+					}],
 					rns: vec![],
 					trpp: vec![],
+					forward: None,
+					..Default::default()
 				},
 			}],
-		}), (vec![Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::A,
+		}), Response::Ok(vec![Resource {
+			rname: vec!["_acme-challenge".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::TXT,
 			rclass: 1,
 			ttl: 100,
-			rdata: vec![10, 10, 10, 10],
+			rdata: vec![11, b'v', b'=', b's', b'p', b'f', b'1', b' ', b'-', b'a', b'l', b'l'],
 		}, Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::A,
+			rname: vec!["_acme-challenge".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::TXT,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![11, 11, 11, 11],
+			ttl: 5,
+			rdata: long_rdata,
+		}, Resource {
+			rname: vec!["_acme-challenge".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::TXT,
+			rclass: 1,
+			ttl: 5,
+			rdata: vec![0],
 		}], vec![], vec![]));
 	}
 	
 	#[test]
-	fn test_case() {
+	fn test_srv() {
 		assert_eq!(handle_dns(&Question {
-			qname: vec!["ExAmple".to_string(), "cOm".to_string()],
-			qtype: record_type::A,
+			qname: vec!["_sip".to_string(), "_tcp".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::SRV,
 			qclass: 1,
 		}, &test_options(), &Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![Zone {
+				matchers: vec![vec![Label::Basic("_sip".to_string()), Label::Basic("_tcp".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					srv: vec![SrvRecord {
+						ttl: Duration::from_secs(100),
+						priority: 10,
+						weight: 20,
+						port: 5060,
+						target: "sip.example.com.".to_string(),
+						metadata: None,
+					}],
+					..Default::default()
+				},
+			}],
+		}), Response::Ok(vec![Resource {
+			rname: vec!["_sip".to_string(), "_tcp".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::SRV,
+			rclass: 1,
+			ttl: 100,
+			rdata: vec![0, 10, 0, 20, 0x13, 0xc4, 3, b's', b'i', b'p', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0],
+		}], vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_delegation() {
+		let config = crate::config::parse(r"zones:
+  ns1.sub.example.com:
+    A: 10.0.0.1
+  sub.example.com,**.sub.example.com:
+    NS: [ns1.sub.example.com., ns2.example.net.]
+  example.com:
+    A: 10.0.0.2").unwrap();
+		let query = |name: &str, qtype: u16| {
+			let request = protocol::make_message_from_question(vec![Question {
+				qname: name.split('.').map(String::from).collect(),
+				qtype,
+				qclass: 1,
+			}]);
+			let request = protocol::serialize(&request, u16::MAX);
+			protocol::parse(&handle_request(request, &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap()
+		};
+		let name = |name: &str| name.split('.').map(String::from).collect::<Vec<String>>();
+		let ns = |target: &str| Resource {
+			rname: name("sub.example.com"),
+			rtype: record_type::NS,
+			rclass: 1,
+			ttl: 1800,
+			rdata: protocol::serialize_name(target.split('.')),
+		};
+		let glue = Resource {
+			rname: name("ns1.sub.example.com"),
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 1800,
+			rdata: vec![10, 0, 0, 1],
+		};
+		
+		// names in the delegated zone are referred to its nameservers, with glue for the one we have
+		for qname in &["sub.example.com", "www.sub.example.com"] {
+			let response = query(qname, record_type::A);
+			assert_eq!(response.header.rcode, 0);
+			assert!(!response.header.aa);
+			assert!(response.answer.is_empty());
+			assert_eq!(response.authority, vec![ns("ns1.sub.example.com"), ns("ns2.example.net")]);
+			assert_eq!(response.additional, vec![glue.clone()]);
+		}
+		
+		// the NS records at the delegation point are answered as before
+		let response = query("sub.example.com", record_type::NS);
+		assert!(response.header.aa);
+		assert_eq!(response.answer, vec![ns("ns1.sub.example.com"), ns("ns2.example.net")]);
+		assert_eq!(response.additional, vec![glue.clone()]);
+		
+		// and names outside it aren't affected
+		let response = query("ns1.sub.example.com", record_type::A);
+		assert!(response.header.aa);
+		assert_eq!(response.answer, vec![glue]);
+		let response = query("example.com", record_type::A);
+		assert!(response.header.aa);
+		assert_eq!(response.answer[0].rdata, vec![10, 0, 0, 2]);
+	}
+	
+	#[test]
+	fn test_dnssec() {
+		let mut config = crate::config::parse(r"zones:
+  example.com:
+    A: 10.0.0.1
+  www.example.com:
+    A: [10.0.0.2, 10.0.0.3]
+  sub.example.com,**.sub.example.com:
+    NS: ns1.example.net.").unwrap();
+		config.dnssec = Some(Dnssec {
+			ttl: Duration::from_secs(3600),
+			private_key: (0..32).collect(),
+		});
+		// so that names outside the zones are denied too
+		config.catch_all = true;
+		let key = SigningKey::from_seed(&(0..32).collect::<Vec<u8>>()).unwrap();
+		let query = |name: &str, qtype: u16, dnssec_ok: bool| {
+			let mut request = protocol::make_message_from_question(vec![Question {
+				qname: name.split('.').map(String::from).collect(),
+				qtype,
+				qclass: 1,
+			}]);
+			request.edns = Some(Edns {
+				udp_payload_size: 4096,
+				extended_rcode_and_flags: if dnssec_ok { Edns::DNSSEC_OK } else { 0 },
+				options: vec![],
+			});
+			let request = protocol::serialize(&request, u16::MAX);
+			protocol::parse(&handle_request(request, &test_options(), &config, Transport::Tcp, test_src()).unwrap()).unwrap()
+		};
+		let name = |name: &str| name.split('.').map(String::from).collect::<Vec<String>>();
+		let types = |records: &[Resource]| records.iter().map(|record| record.rtype).collect::<Vec<u16>>();
+		let verify = |rrset: &[Resource], rrsig: &Resource| {
+			assert_eq!(rrsig.rtype, record_type::RRSIG);
+			assert!(rrset.iter().all(|record| record.ttl == rrsig.ttl));
+			let signed = &rrsig.rdata[..rrsig.rdata.len() - 64];
+			assert!(signed.ends_with(&protocol::serialize_name(vec!["example", "com"])));
+			let public_key = UnparsedPublicKey::new(&ED25519, key.dnskey_rdata()[4..].to_vec());
+			assert!(public_key.verify(&dnssec::signed_data(signed, rrset, rrsig.ttl), &rrsig.rdata[signed.len()..]).is_ok());
+		};
+		let nsec = |owner: &str, types: &[u16]| {
+			let mut next = name(owner);
+			next.insert(0, "\0".to_string());
+			Resource {
+				rname: name(owner),
+				rtype: record_type::NSEC,
+				rclass: 1,
+				ttl: 15,
+				rdata: dnssec::nsec_rdata(&next, types),
+			}
+		};
+		
+		// only clients that ask for them get signatures
+		let response = query("www.example.com", record_type::A, false);
+		assert_eq!(types(&response.answer), vec![record_type::A, record_type::A]);
+		let response = query("www.example.com", record_type::A, true);
+		assert_eq!(response.edns.unwrap().extended_rcode_and_flags, Edns::DNSSEC_OK);
+		assert_eq!(types(&response.answer), vec![record_type::A, record_type::A, record_type::RRSIG]);
+		verify(&response.answer[..2], &response.answer[2]);
+		
+		let response = query("example.com", record_type::DNSKEY, true);
+		assert_eq!(response.answer[0].rdata, key.dnskey_rdata());
+		assert_eq!(response.answer[0].ttl, 3600);
+		verify(&response.answer[..1], &response.answer[1]);
+		assert!(query("www.example.com", record_type::DNSKEY, true).answer.is_empty());
+		
+		// no data is proven by an NSEC with the types that are there
+		let response = query("www.example.com", record_type::MX, true);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(types(&response.authority), vec![record_type::SOA, record_type::RRSIG, record_type::NSEC, record_type::RRSIG]);
+		assert_eq!(response.authority[0].rname, name("example.com"));
+		assert_eq!(response.authority[2], nsec("www.example.com", &[record_type::A, record_type::RRSIG, record_type::NSEC]));
+		verify(&response.authority[..1], &response.authority[1]);
+		verify(&response.authority[2..3], &response.authority[3]);
+		
+		// as is a name that doesn't exist, which is answered as no data
+		let response = query("nothere.example.com", record_type::A, true);
+		assert_eq!(response.header.rcode, 0);
+		assert!(response.header.aa);
+		assert_eq!(response.authority[2], nsec("nothere.example.com", &[record_type::NXNAME, record_type::RRSIG, record_type::NSEC]));
+		verify(&response.authority[2..3], &response.authority[3]);
+		assert_eq!(query("nothere.example.com", record_type::A, false).header.rcode, 3);
+		
+		// zones without a key aren't signed
+		let response = query("example.org", record_type::A, true);
+		assert_eq!(response.header.rcode, 3);
+		assert_eq!(types(&response.authority), vec![record_type::SOA]);
+		
+		// delegations are proven to be insecure
+		let response = query("www.sub.example.com", record_type::A, true);
+		assert!(!response.header.aa);
+		assert_eq!(types(&response.authority), vec![record_type::NS, record_type::NSEC, record_type::RRSIG]);
+		assert_eq!(response.authority[1], nsec("sub.example.com", &[record_type::NS, record_type::RRSIG, record_type::NSEC]));
+		verify(&response.authority[1..2], &response.authority[2]);
+		let response = query("sub.example.com", record_type::DS, true);
+		assert!(response.header.aa);
+		assert!(response.answer.is_empty());
+		assert_eq!(response.authority[2], nsec("sub.example.com", &[record_type::NS, record_type::RRSIG, record_type::NSEC]));
+	}
+	
+	#[test]
+	fn test_chaos() {
+		let chaos = |name: &str, qtype: u16| {
+			let mut request = protocol::make_message_from_question(vec![Question {
+				qname: name.split('.').map(String::from).collect(),
+				qtype,
+				qclass: class::CH,
+			}]);
+			request.header.id = 1;
+			let request = protocol::serialize(&request, u16::MAX);
+			protocol::parse(&handle_request(request, &test_options(), &soa_config(), Transport::Udp, test_src()).unwrap()).unwrap()
+		};
+		
+		let response = chaos("VERSION.bind", record_type::TXT);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer, vec![Resource {
+			rname: vec!["VERSION".to_string(), "bind".to_string()],
+			rtype: record_type::TXT,
+			rclass: class::CH,
+			ttl: 0,
+			rdata: protocol::serialize_txt("tacodns 1.2.3"),
+		}]);
+		assert!(response.authority.is_empty());
+		
+		let response = chaos("hostname.bind", record_type::TXT);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer[0].rdata, protocol::serialize_txt("ns1"));
+		
+		assert_eq!(chaos("authors.bind", record_type::TXT).header.rcode, 5);
+		assert_eq!(chaos("version.bind", record_type::A).header.rcode, 5);
+		// the zones aren't in the CHAOS class, so aren't leaked through it
+		let response = chaos("example.com", record_type::SOA);
+		assert_eq!(response.header.rcode, 5);
+		assert!(response.answer.is_empty());
+		
+		// while IN queries for the same names are answered from the zones as before, which don't
+		// have them
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["version".to_string(), "bind".to_string()],
+			qtype: record_type::TXT,
+			qclass: class::IN,
+		}, &test_options(), &soa_config()), Response::Refused);
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::SOA,
+			qclass: class::IN,
+		}, &test_options(), &soa_config()).rcode(), 0);
+	}
+	
+	/// Answers A questions with 192.0.2.1, SOA questions as a broken forwarder might, and others
+	/// with nothing, recording what it was asked.
+	struct FakeUpstream {
+		asked: RefCell<Vec<(Vec<String>, Vec<SocketAddr>)>>,
+	}
+	
+	impl Upstream for FakeUpstream {
+		fn resolvers(&self) -> &[SocketAddr] {
+			return &[];
+		}
+		
+		fn lookup(&self, question: Question, servers: &[SocketAddr]) -> Response {
+			self.asked.borrow_mut().push((question.qname.clone(), servers.to_vec()));
+			if question.qtype == record_type::SOA {
+				// too short to have a minimum
+				return Response::Ok(vec![Resource { rname: question.qname, rtype: record_type::SOA, rclass: class::IN, ttl: 60, rdata: vec![0] }], vec![], vec![]);
+			}
+			if question.qtype != record_type::A {
+				return Response::Ok(vec![], vec![], vec![]);
+			}
+			return Response::Ok(vec![Resource {
+				rname: question.qname,
+				rtype: record_type::A,
+				rclass: class::IN,
+				ttl: 300,
+				rdata: vec![192, 0, 2, 1],
+			}], vec![], vec![]);
+		}
+	}
+	
+	#[test]
+	fn test_resolve() {
+		let config = crate::config::parse(r"networks: {internal: 10.0.0.0/8}
+resolve-external-cnames: true
+zones:
+  example.com:
+    A: 10.0.0.1
+  www.example.com:
+    CNAME: example.net.
+  internal.example.com @internal:
+    A: 10.0.0.2
+  forwarded.example.com:
+    forward: 10.0.0.53").unwrap();
+		let upstream = FakeUpstream { asked: RefCell::new(vec![]) };
+		let question = |name: &str, qtype: u16| Question {
+			qname: name.split('.').map(String::from).collect(),
+			qtype,
+			qclass: class::IN,
+		};
+		
+		let answer = resolve(&question("WWW.example.com.", record_type::A), &config, &upstream);
+		assert_eq!(answer.rcode, 0);
+		assert!(answer.authoritative);
+		assert_eq!(answer.answer.iter().map(|record| record.rtype).collect::<Vec<u16>>(), vec![record_type::CNAME, record_type::A]);
+		assert_eq!(answer.answer[1].rdata, vec![192, 0, 2, 1]);
+		assert_eq!(upstream.asked.borrow()[0], (vec!["example".to_string(), "net".to_string()], vec![]));
+		
+		// forwarded zones are asked of their own servers, and a bad SOA from them is replaced
+		upstream.asked.borrow_mut().clear();
+		let answer = resolve(&question("forwarded.example.com", record_type::TXT), &config, &upstream);
+		assert_eq!(answer.rcode, 0);
+		assert_eq!(upstream.asked.borrow()[0].1, vec!["10.0.0.53:53".parse().unwrap()]);
+		assert_eq!(answer.authority.len(), 1);
+		assert!(answer.authority[0].rtype == record_type::SOA && answer.authority[0].rdata.len() >= 20);
+		
+		assert_eq!(resolve(&question("internal.example.com", record_type::A), &config, &upstream).rcode, 3);
+		assert_eq!(resolve(&question("", record_type::A), &config, &upstream).rcode, 5);
+		assert_eq!(resolve(&Question { qclass: class::CH, ..question("version.bind", record_type::TXT) }, &config, &upstream).rcode, 5);
+		assert_eq!(resolve(&question("a..example.com", record_type::A), &config, &upstream).rcode, 1);
+		assert_eq!(resolve(&Question { qname: vec!["a.b".to_string()], ..question("", record_type::A) }, &config, &upstream).rcode, 1);
+	}
+	
+	#[test]
+	fn test_naptr() {
+		let qname: Vec<String> = "4.3.2.1.5.5.5.0.0.8.1.e164.arpa".split('.').map(String::from).collect();
+		let config = crate::config::parse(r"zones:
+  4.3.2.1.5.5.5.0.0.8.1.e164.arpa:
+    NAPTR:
+      order: 100
+      preference: 10
+      flags: u
+      service: E2U+sip
+      regexp: '!^.*$!sip:info@example.com!'
+      ttl: 100").unwrap();
+		let mut rdata = vec![0, 100, 0, 10, 1, b'u', 7];
+		rdata.extend_from_slice(b"E2U+sip");
+		rdata.push(27);
+		rdata.extend_from_slice(b"!^.*$!sip:info@example.com!");
+		// the replacement defaults to the root
+		rdata.push(0);
+		assert_eq!(handle_dns(&Question {
+			qname: qname.clone(),
+			qtype: record_type::NAPTR,
+			qclass: 1,
+		}, &test_options(), &config), Response::Ok(vec![Resource {
+			rname: qname,
+			rtype: record_type::NAPTR,
+			rclass: 1,
+			ttl: 100,
+			rdata,
+		}], vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_hinfo_loc_sshfp() {
+		let config = crate::config::parse(r"zones:
+  host.example.com:
+    HINFO: INTEL-386 LINUX
+    LOC: 52 22 23.000 N 4 53 32.000 E -2.00m
+    SSHFP: 1 1 dc0ffee0").unwrap();
+		let rdata = |qtype: u16| match handle_dns(&Question {
+			qname: vec!["host".to_string(), "example".to_string(), "com".to_string()],
+			qtype,
+			qclass: 1,
+		}, &test_options(), &config) {
+			Response::Ok(answer, _, _) => answer.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>(),
+			response => panic!("{:?}", response),
+		};
+		
+		let mut hinfo = vec![9];
+		hinfo.extend_from_slice(b"INTEL-386");
+		hinfo.push(5);
+		hinfo.extend_from_slice(b"LINUX");
+		assert_eq!(rdata(record_type::HINFO), vec![hinfo]);
+		// https://tools.ietf.org/html/rfc1876#section-2
+		assert_eq!(rdata(record_type::LOC), vec![vec![
+			0, 0x12, 0x16, 0x13,
+			0x8b, 0x3c, 0xf0, 0x18,
+			0x81, 0x0c, 0xbc, 0xe0,
+			0x00, 0x98, 0x95, 0xb8,
+		]]);
+		assert_eq!(rdata(record_type::SSHFP), vec![vec![1, 1, 0xdc, 0x0f, 0xfe, 0xe0]]);
+		assert_eq!(rdata(record_type::ANY).len(), 3);
+	}
+	
+	#[test]
+	fn test_svcb_https() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    HTTPS:
+      - { priority: 0, target: cdn.example.net. }
+      - { priority: 1, target: ., ipv6hint: '2001:db8::1', port: 8443, alpn: [h2, h3], ipv4hint: [192.0.2.1, 192.0.2.2], mandatory: port }
+  _dns.example.com:
+    SVCB: { priority: 1, target: dns, alpn: dot, no-default-alpn: true }").unwrap();
+		let rdata = |qname: &str, qtype: u16| match handle_dns(&Question { qname: name(qname), qtype, qclass: 1 }, &test_options(), &config) {
+			Response::Ok(answer, _, _) => answer.into_iter().map(|record| (record.rtype, record.rdata)).collect::<Vec<(u16, Vec<u8>)>>(),
+			response => panic!("{:?}", response),
+		};
+		
+		// alias mode is only a target
+		let mut alias = vec![0, 0, 3];
+		alias.extend_from_slice(b"cdn");
+		alias.push(7);
+		alias.extend_from_slice(b"example");
+		alias.push(3);
+		alias.extend_from_slice(b"net");
+		alias.push(0);
+		// service mode has its params in order of their keys, whatever order they're given in
+		let service = vec![
+			0, 1,
+			0,
+			0, 0, 0, 2, 0, 3,
+			0, 1, 0, 6, 2, b'h', b'2', 2, b'h', b'3',
+			0, 3, 0, 2, 0x20, 0xfb,
+			0, 4, 0, 8, 192, 0, 2, 1, 192, 0, 2, 2,
+			0, 6, 0, 16, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+		];
+		assert_eq!(rdata("example.com", record_type::HTTPS), vec![(record_type::HTTPS, alias), (record_type::HTTPS, service)]);
+		assert_eq!(rdata("example.com", record_type::SVCB), vec![]);
+		
+		// a relative target is relative to the name queried
+		let mut svcb = vec![0, 1, 3];
+		svcb.extend_from_slice(b"dns");
+		svcb.push(4);
+		svcb.extend_from_slice(b"_dns");
+		svcb.push(7);
+		svcb.extend_from_slice(b"example");
+		svcb.push(3);
+		svcb.extend_from_slice(b"com");
+		svcb.extend_from_slice(&[0, 0, 1, 0, 4, 3, b'd', b'o', b't', 0, 2, 0, 0]);
+		assert_eq!(rdata("_dns.example.com", record_type::SVCB), vec![(record_type::SVCB, svcb)]);
+	}
+	
+	fn soa_config() -> Config {
+		Config {
+			ttl: Duration::from_secs(1800),
+			nttl: Duration::from_secs(15),
+			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
 					a: vec![ARecord {
 						ttl: Duration::from_secs(100),
 						ip4addr: "10.10.10.10".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
 					}],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
+					soa: vec![SoaRecord {
+						ttl: Duration::from_secs(3600),
+						mname: "ns.example.com".to_string(),
+						rname: "admin.example.com".to_string(),
+						serial: Some(0x01020304),
+						refresh: Duration::from_secs(0x0a),
+						retry: Duration::from_secs(0x0b),
+						expire: Duration::from_secs(0x0c),
+						minimum: Some(Duration::from_secs(0x0d)),
+						metadata: None,
+					}],
+					..Default::default()
 				},
 			}],
-		}), (vec![Resource {
-			rname: vec!["ExAmple".to_string(), "cOm".to_string()],
-			rtype: record_type::A,
+		}
+	}
+	
+	#[test]
+	fn test_soa() {
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::SOA,
+			qclass: 1,
+		}, &test_options(), &soa_config()), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::SOA,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![10, 10, 10, 10],
+			ttl: 3600,
+			rdata: vec![
+				2, b'n', b's', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+				5, b'a', b'd', b'm', b'i', b'n', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+				1, 2, 3, 4,
+				0, 0, 0, 0x0a,
+				0, 0, 0, 0x0b,
+				0, 0, 0, 0x0c,
+				0, 0, 0, 0x0d,
+			],
 		}], vec![], vec![]));
 	}
 	
+	/// Runs a question through the full request path and parses the result.
+	fn query(question: Question, config: &Config) -> protocol::Message {
+		let request = protocol::serialize(&protocol::make_message_from_question(vec![question]), 512);
+		protocol::parse(&handle_request(request, &test_options(), config, Transport::Udp, test_src()).unwrap()).unwrap()
+	}
+	
+	#[test]
+	fn test_soa_negative() {
+		let response = query(Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::TXT,
+			qclass: 1,
+		}, &soa_config());
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer, vec![]);
+		assert_eq!(response.authority.len(), 1);
+		assert_eq!(response.authority[0].rtype, record_type::SOA);
+		// the negative TTL is capped by the SOA minimum
+		assert_eq!(response.authority[0].ttl, 0x0d);
+	}
+	
+	#[test]
+	fn test_empty_non_terminal() {
+		let config = crate::config::parse(r"zones:
+  _sip._tcp.example.com:
+    SRV: 10 0 5060 sip.example.com").unwrap();
+		let question = |name: &str| Question {
+			qname: name.split('.').map(String::from).collect(),
+			qtype: record_type::NS,
+			qclass: 1,
+		};
+		
+		// names above configured ones exist, with no records
+		assert_eq!(handle_dns(&question("_tcp.example.com"), &test_options(), &config), Response::Ok(vec![], vec![], vec![]));
+		let response = query(question("_tcp.example.com"), &config);
+		assert_eq!(response.header.rcode, 0);
+		assert!(response.answer.is_empty());
+		assert_eq!(response.authority[0].rtype, record_type::SOA);
+		
+		// while their siblings don't
+		assert_eq!(handle_dns(&question("_udp._tcp.example.com"), &test_options(), &config), Response::NameError);
+		assert_eq!(query(question("_udp._tcp.example.com"), &config).header.rcode, 3);
+	}
+	
+	#[test]
+	fn test_excluded_names() {
+		let question = |name: &str| Question {
+			qname: name.split('.').map(String::from).collect(),
+			qtype: record_type::A,
+			qclass: 1,
+		};
+		let addresses = |response: protocol::Message| response.answer.into_iter().map(|answer| answer.rdata).collect::<Vec<Vec<u8>>>();
+		
+		// an empty zone before the wildcard doesn't hide the name, as it falls through to the wildcard
+		let shadowed = crate::config::parse(r"zones:
+  internal.example.com: {}
+  '*.example.com':
+    A: 10.0.0.1").unwrap();
+		assert_eq!(addresses(query(question("internal.example.com"), &shadowed)), vec![vec![10, 0, 0, 1]]);
+		
+		// while excluding it leaves no such name
+		let excluded = crate::config::parse(r"zones:
+  '*.example.com, !internal.example.com':
+    A: 10.0.0.1").unwrap();
+		assert_eq!(addresses(query(question("www.example.com"), &excluded)), vec![vec![10, 0, 0, 1]]);
+		assert_eq!(handle_dns(&question("Internal.Example.com"), &test_options(), &excluded), Response::NameError);
+		assert_eq!(query(question("internal.example.com"), &excluded).header.rcode, 3);
+		
+		// an excluded name falls through to the zones after, wherever the exclusion is in the name
+		let config = crate::config::parse(r"zones:
+  '!*.staging.example.com,**.example.com':
+    A: 10.0.0.1
+  '*.staging.example.com':
+    A: 10.0.0.2").unwrap();
+		assert_eq!(addresses(query(question("api.staging.example.com"), &config)), vec![vec![10, 0, 0, 2]]);
+		assert_eq!(addresses(query(question("staging.example.com"), &config)), vec![vec![10, 0, 0, 1]]);
+		assert_eq!(addresses(query(question("v1.api.staging.example.com"), &config)), vec![vec![10, 0, 0, 1]]);
+	}
+	
+	#[test]
+	fn test_explicit_none() {
+		let config = crate::config::parse(r"nttl: 5m
+zones:
+  v6.example.com:
+    A: none
+    AAAA: 2001:db8::1
+    nodata-ttl: 30s
+  v4.example.com:
+    AAAA: none
+  '*.example.com':
+    A: 10.0.0.1
+    AAAA: 2001:db8::2").unwrap();
+		let question = |name: &str, qtype: u16| Question { qname: name.split('.').map(String::from).collect(), qtype, qclass: 1 };
+		let minimum = |record: &Resource| u32::from_be_bytes(<[u8; 4]>::try_from(&record.rdata[record.rdata.len() - 4..]).unwrap());
+		
+		// none rather than the wildcard's, with the zone's negative TTL as the SOA's minimum
+		let response = query(question("v6.example.com", record_type::A), &config);
+		assert_eq!((response.header.rcode, response.header.aa, response.answer.len()), (0, true, 0));
+		assert_eq!(response.authority.len(), 1);
+		assert_eq!((response.authority[0].rtype, response.authority[0].ttl, minimum(&response.authority[0])), (record_type::SOA, 30, 30));
+		assert_eq!(query(question("v6.example.com", record_type::AAAA), &config).answer[0].rdata, "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec());
+		
+		// a zone without a negative TTL of its own has the global one, and can say none of AAAA too
+		let response = query(question("v4.example.com", record_type::AAAA), &config);
+		assert_eq!((response.header.rcode, response.answer.len()), (0, 0));
+		assert_eq!(minimum(&response.authority[0]), 300);
+		// while a type without `none` still falls through to the wildcard
+		assert_eq!(query(question("v4.example.com", record_type::A), &config).answer[0].rdata, vec![10, 0, 0, 1]);
+	}
+	
 	#[test]
-	fn test_aaaa() {
-		assert_eq!(handle_dns(&Question {
-			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::AAAA,
-			qclass: 1,
-		}, &test_options(), &Config {
+	fn test_names_outside_zones() {
+		let zones = r"
+zones:
+  www.example.com:
+    A: 10.0.0.1
+  '*.example.net':
+    A: 10.0.0.2
+  example.org:
+    NS: ns1.example.org.
+    A: 10.0.0.3";
+		let config = crate::config::parse(zones).unwrap();
+		let rcode = |name: &str, config: &Config| {
+			let response = query(Question {
+				qname: name.split('.').map(String::from).collect(),
+				qtype: record_type::A,
+				qclass: 1,
+			}, config);
+			// only names in our domains get a SOA for resolvers to cache the answer with
+			assert_eq!(response.authority.iter().any(|record| record.rtype == record_type::SOA), response.header.rcode != 5);
+			response.header.rcode
+		};
+		
+		// the parent of a name is in its domain, whether or not anything's configured there
+		assert_eq!(rcode("example.com", &config), 0);
+		assert_eq!(rcode("nope.example.com", &config), 3);
+		// as it is of a wildcard
+		assert_eq!(rcode("example.net", &config), 0);
+		assert_eq!(rcode("a.b.example.net", &config), 3);
+		// while a name with NS or SOA records is the top of its own
+		assert_eq!(rcode("nope.example.org", &config), 3);
+		
+		// anything above isn't ours, even when there are names below it
+		assert_eq!(rcode("com", &config), 5);
+		assert_eq!(rcode("nope.com", &config), 5);
+		assert_eq!(rcode("org", &config), 5);
+		assert_eq!(rcode("example.edu", &config), 5);
+		
+		// unless we're a catch-all
+		let config = crate::config::parse(&format!("catch-all: true{}", zones)).unwrap();
+		assert_eq!(rcode("com", &config), 0);
+		assert_eq!(rcode("example.edu", &config), 3);
+	}
+	
+	#[test]
+	fn test_external_cnames() {
+		let zones = r"
+zones:
+  www.example.com:
+    CNAME: example.com.
+  example.com:
+    A: 10.0.0.1
+  external.example.com:
+    CNAME: example.net.
+  resolved.example.com:
+    resolve-external-cnames: true
+    CNAME: example.net.
+  '*.alias.example.com':
+    ALIAS: example.net.";
+		let answer = |name: &str, config: &Config| {
+			let upstream = FakeUpstream { asked: RefCell::new(vec![]) };
+			let answer = resolve(&Question {
+				qname: name.split('.').map(String::from).collect(),
+				qtype: record_type::A,
+				qclass: class::IN,
+			}, config, &upstream);
+			let asked = !upstream.asked.borrow().is_empty();
+			(answer.answer.iter().map(|record| record.rtype).collect::<Vec<u16>>(), asked)
+		};
+		let config = crate::config::parse(zones).unwrap();
+		
+		// targets in our zones are followed either way
+		assert_eq!(answer("www.example.com", &config), (vec![record_type::CNAME, record_type::A], false));
+		// while ones outside them are left to the client
+		assert_eq!(answer("external.example.com", &config), (vec![record_type::CNAME], false));
+		assert_eq!(answer("a.alias.example.com", &config), (vec![record_type::CNAME], false));
+		// unless the zone asks for them to be resolved
+				assert_eq!(answer("resolved.example.com", &config), (vec![record_type::CNAME, record_type::A], true));
+		
+		// or every zone does
+		let config = crate::config::parse(&format!("resolve-external-cnames: true{}", zones)).unwrap();
+		assert_eq!(answer("www.example.com", &config), (vec![record_type::CNAME, record_type::A], false));
+		assert_eq!(answer("external.example.com", &config), (vec![record_type::CNAME, record_type::A], true));
+		assert_eq!(answer("a.alias.example.com", &config), (vec![record_type::CNAME, record_type::A], true));
+		let config = crate::config::parse(&format!("resolve-external-cnames: true{}", zones.replace("CNAME: example.net.", "resolve-external-cnames: false\n    CNAME: example.net."))).unwrap();
+		assert_eq!(answer("external.example.com", &config), (vec![record_type::CNAME], false));
+	}
+	
+	#[test]
+	fn test_rcode() {
+		let config = Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
-					a: vec![],
-					aaaa: vec![AaaaRecord {
+					a: vec![ARecord {
 						ttl: Duration::from_secs(100),
-						ip6addr: "::1".parse().unwrap(),
+						ip4addr: "10.10.10.10".parse().unwrap(),
+						weight: None,
+						check: None,
+						metadata: None,
 					}],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
+					..Default::default()
+				},
+			}, Zone {
+				matchers: vec![vec![Label::Basic("broken".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
+				records: Records {
+					rns: vec![RnsRecord {
+						ttl: Duration::from_secs(100),
+						// nothing listens here, so the lookup fails
+						host: RnsHost::SocketAddr("127.0.0.1:1".parse().unwrap()),
+						external: false,
+						metadata: None,
+					}],
+					..Default::default()
 				},
 			}],
-		}), (vec![Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::AAAA,
-			rclass: 1,
-			ttl: 100,
-			rdata: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
-		}], vec![], vec![]));
+		};
 		
-		assert_eq!(handle_dns(&Question {
+		// name exists
+		let response = query(Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &config);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer.len(), 1);
+		
+		// name exists, but has no records of this type
+		let response = query(Question {
 			qname: vec!["example".to_string(), "com".to_string()],
 			qtype: record_type::AAAA,
 			qclass: 1,
-		}, &test_options(), &Config {
+		}, &config);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer.len(), 0);
+		assert_eq!(response.authority[0].rtype, record_type::SOA);
+		
+		// no such name
+		assert_eq!(handle_dns(&Question {
+			qname: vec!["nope".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &test_options(), &config), Response::NameError);
+		let response = query(Question {
+			qname: vec!["nope".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &config);
+		assert_eq!(response.header.rcode, 3);
+		assert_eq!(response.answer.len(), 0);
+		assert_eq!(response.authority[0].rtype, record_type::SOA);
+		
+		// upstream failure
+		let response = query(Question {
+			qname: vec!["broken".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &config);
+		assert_eq!(response.header.rcode, 2);
+		assert_eq!(response.answer.len(), 0);
+	}
+	
+	#[test]
+	fn test_malformed_request() {
+		let mut request = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]), 512);
+		request[0] = 0x12;
+		request[1] = 0x34;
+		
+		// not even a header; drop it
+		assert_eq!(handle_request(request[..5].to_vec(), &test_options(), &soa_config(), Transport::Udp, test_src()), None);
+		
+		// header is fine, but the question isn't
+		let response = protocol::parse(&handle_request(request[..15].to_vec(), &test_options(), &soa_config(), Transport::Udp, test_src()).unwrap()).unwrap();
+		assert_eq!(response.header.id, 0x1234);
+		assert!(response.header.qr);
+		assert_eq!(response.header.rcode, 1);
+		assert!(response.question.is_empty());
+		
+		// no questions at all
+		let response = protocol::parse(&handle_request(request[..12].iter().cloned().enumerate().map(|(i, x)| if i == 5 { 0 } else { x }).collect(), &test_options(), &soa_config(), Transport::Udp, test_src()).unwrap()).unwrap();
+		assert_eq!(response.header.id, 0x1234);
+		assert_eq!(response.header.rcode, 1);
+		
+		// responses are dropped
+		let mut response = request.clone();
+		response[2] |= 0x80;
+		assert_eq!(handle_request(response, &test_options(), &soa_config(), Transport::Udp, test_src()), None);
+	}
+	
+	#[test]
+	fn test_normalize_name() {
+		let normalize = |labels: &[&str]| {
+			let mut qname: Vec<String> = labels.iter().map(|label| label.to_string()).collect();
+			let valid = normalize_name(&mut qname);
+			return if valid { Some(qname) } else { None };
+		};
+		let name = |labels: &[&str]| Some(labels.iter().map(|label| label.to_string()).collect::<Vec<String>>());
+		assert_eq!(normalize(&["example", "com"]), name(&["example", "com"]));
+		// a trailing dot
+		assert_eq!(normalize(&["example", "com", ""]), name(&["example", "com"]));
+		assert_eq!(normalize(&[""]), name(&[]));
+		assert_eq!(normalize(&[]), name(&[]));
+		// only the one
+		assert_eq!(normalize(&["example", "com", "", ""]), None);
+		assert_eq!(normalize(&["example", "", "com"]), None);
+		assert_eq!(normalize(&[&"a".repeat(63), "com"]), name(&[&"a".repeat(63), "com"]));
+		assert_eq!(normalize(&[&"a".repeat(64), "com"]), None);
+		
+		// the root is refused rather than looked up, even by a zone that matches anything
+		let config = crate::config::parse("zones:\n  '***':\n    A: 10.0.0.1").unwrap();
+		let query = |qname: Vec<String>| {
+			let request = protocol::serialize(&protocol::make_message_from_question(vec![Question { qname, qtype: record_type::NS, qclass: class::IN }]), 512);
+			return protocol::parse(&handle_request(request, &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap();
+		};
+		let response = query(vec![]);
+		assert_eq!(response.header.rcode, 5);
+		assert!(response.answer.is_empty());
+		assert_eq!(query(vec!["example".to_string()]).header.rcode, 0);
+	}
+	
+	#[test]
+	fn test_tcp_pipelining() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			handle_tcp_connection(stream, &test_options(), &SharedConfig::new(soa_config()));
+		});
+		
+		let request = |id: u16, qname: Vec<String>| {
+			let mut message = protocol::make_message_from_question(vec![Question {
+				qname,
+				qtype: record_type::SOA,
+				qclass: 1,
+			}]);
+			message.header.id = id;
+			protocol::serialize(&message, u16::MAX)
+		};
+		
+		let mut stream = TcpStream::connect(address).unwrap();
+		let mut requests = vec![];
+		write_tcp_message(&mut requests, &request(1, vec!["example".to_string(), "com".to_string()])).unwrap();
+		write_tcp_message(&mut requests, &request(2, vec!["nope".to_string(), "example".to_string(), "com".to_string()])).unwrap();
+		// split the requests across writes so the server sees partial messages
+		let (start, end) = requests.split_at(5);
+		stream.write_all(start).unwrap();
+		stream.flush().unwrap();
+		thread::sleep(Duration::from_millis(50));
+		stream.write_all(end).unwrap();
+		
+		let first = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+		assert_eq!(first.header.id, 1);
+		assert_eq!(first.header.rcode, 0);
+		assert_eq!(first.answer.len(), 1);
+		let second = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+		assert_eq!(second.header.id, 2);
+		assert_eq!(second.header.rcode, 3);
+		
+		drop(stream);
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_transfer() {
+		let zone = |name: &str, records: Records| Zone {
+			matchers: vec![name.split('.').map(|label| if label == "*" { Label::Wildcard } else { Label::Basic(label.to_string()) }).collect()],
+			excludes: vec![],
+			records,
+		};
+		let config = |allow_transfer: Vec<IpAddr>| Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
-			zones: vec![Zone {
-				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![],
-					aaaa: vec![AaaaRecord {
-						ttl: Duration::from_secs(100),
-						ip6addr: "::2".parse().unwrap(),
-					}, AaaaRecord {
-						ttl: Duration::from_secs(100),
-						ip6addr: "::3".parse().unwrap(),
-					}],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}],
-		}), (vec![Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::AAAA,
-			rclass: 1,
-			ttl: 100,
-			rdata: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2],
-		}, Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::AAAA,
-			rclass: 1,
-			ttl: 100,
-			rdata: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-		}], vec![], vec![]));
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer,
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![
+				zone("example.com", Records {
+					a: vec![ARecord { ttl: Duration::from_secs(1800), ip4addr: Ipv4Addr::new(10, 0, 0, 1), weight: None, check: None, metadata: None }],
+					// enough to need several messages
+					txt: (0..200).map(|i| TxtRecord { ttl: Duration::from_secs(1800), data: format!("{:0200}", i), metadata: None }).collect(),
+					..Default::default()
+				}),
+				zone("www.example.com", Records {
+					cname: vec![CnameRecord { ttl: Duration::from_secs(1800), name: "example.com.".to_string(), metadata: None }],
+					..Default::default()
+				}),
+				zone("*.example.com", Records {
+					a: vec![ARecord { ttl: Duration::from_secs(1800), ip4addr: Ipv4Addr::new(10, 0, 0, 2), weight: None, check: None, metadata: None }],
+					..Default::default()
+				}),
+				zone("example.net", Records {
+					a: vec![ARecord { ttl: Duration::from_secs(1800), ip4addr: Ipv4Addr::new(10, 0, 0, 3), weight: None, check: None, metadata: None }],
+					..Default::default()
+				}),
+			],
+		};
+		let transfer = |qname: &str, config: Config| {
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let address = listener.local_addr().unwrap();
+			let server = thread::spawn(move || {
+				let (stream, _) = listener.accept().unwrap();
+				handle_tcp_connection(stream, &test_options(), &SharedConfig::new(config));
+			});
+			
+			let mut message = protocol::make_message_from_question(vec![Question {
+				qname: qname.split('.').map(|label| label.to_string()).collect(),
+				qtype: record_type::AXFR,
+				qclass: 1,
+			}]);
+			message.header.id = 7;
+			let mut stream = TcpStream::connect(address).unwrap();
+			write_tcp_message(&mut stream, &protocol::serialize(&message, u16::MAX)).unwrap();
+			
+			// keep reading until the closing SOA
+			let mut messages = vec![];
+			let mut records: Vec<Resource> = vec![];
+			loop {
+				let message = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+				assert_eq!(message.header.id, 7);
+				let done = message.header.rcode != 0 || (records.len() + message.answer.len() > 1 && message.answer.last().map(|record| record.rtype) == Some(record_type::SOA));
+				records.extend(message.answer.iter().cloned());
+				messages.push(message);
+				if done { break; }
+			}
+			drop(stream);
+			server.join().unwrap();
+			(messages, records)
+		};
+		
+		let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+		let (messages, records) = transfer("example.com", config(vec![localhost]));
+		assert!(messages.len() > 1);
+		assert!(messages.iter().all(|message| message.header.aa && message.header.rcode == 0));
+		assert_eq!(records.len(), 1 + 1 + 200 + 1 + 1);
+		assert_eq!(records.first(), records.last());
+		assert_eq!(records[0].rtype, record_type::SOA);
+		assert_eq!(records[1].rtype, record_type::A);
+		assert_eq!(records[1].rdata, vec![10, 0, 0, 1]);
+		assert!(records[2..202].iter().all(|record| record.rtype == record_type::TXT));
+		assert_eq!(records[202].rname, vec!["www".to_string(), "example".to_string(), "com".to_string()]);
+		assert_eq!(records[202].rtype, record_type::CNAME);
+		
+		// the allow list can also be given for just the zone
+		let mut zone_config = config(vec![]);
+		zone_config.zones[0].records.allow_transfer = vec![localhost];
+		assert_eq!(transfer("example.com", zone_config).0[0].header.rcode, 0);
+		
+		// not allowed
+		assert_eq!(transfer("example.com", config(vec![])).0[0].header.rcode, 5);
+		let mut zone_config = config(vec![]);
+		zone_config.zones[3].records.allow_transfer = vec![localhost];
+		assert_eq!(transfer("example.com", zone_config).0[0].header.rcode, 5);
+		
+		// wildcards can't be enumerated
+		assert_eq!(transfer("foo.example.com", config(vec![localhost])).0[0].header.rcode, 5);
+		
+		// and zone transfers aren't possible over UDP
+		assert_eq!(query(Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::AXFR,
+			qclass: 1,
+		}, &config(vec![localhost])).header.rcode, 4);
 	}
 	
 	#[test]
-	fn test_ns() {
-		assert_eq!(handle_dns(&Question {
-			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::NS,
-			qclass: 1,
-		}, &test_options(), &Config {
+	fn test_acl() {
+		let config = |acl: &[&str], acl_local: bool, allow: &[&str], deny: &[&str]| Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
-			zones: vec![Zone {
-				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![NsRecord {
-						ttl: Duration::from_secs(100),
-						name: "ns.example.com".to_string(),
-					}],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: acl.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+			acl_local,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![
+				Zone {
+					matchers: vec![vec![Label::Basic("alias".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+					excludes: vec![],
+					records: Records {
+						aname: vec![AnameRecord { ttl: Duration::from_secs(1800), name: "example.net.".to_string(), min_ttl: None, max_ttl: None, metadata: None }],
+						alias: vec![],
+						allow: allow.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+						deny: deny.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+						..Default::default()
+					},
 				},
-			}],
-		}), (vec![Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::NS,
-			rclass: 1,
-			ttl: 100,
-			rdata: vec![2, 110, 115, 7, 101, 120, 97, 109, 112, 108, 101, 3, 99, 111, 109, 0],
-		}], vec![], vec![]));
+				Zone {
+					matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("net".to_string())]],
+					excludes: vec![],
+					records: Records {
+						a: vec![ARecord { ttl: Duration::from_secs(1800), ip4addr: Ipv4Addr::new(10, 0, 0, 1), weight: None, check: None, metadata: None }],
+						..Default::default()
+					},
+				},
+			],
+		};
+		let rcode = |qname: &str, config: &Config, src: &str| {
+			let request = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+				qname: qname.split('.').map(|label| label.to_string()).collect(),
+				qtype: record_type::A,
+				qclass: 1,
+			}]), 512);
+			let src = SocketAddr::new(src.parse().unwrap(), 5353);
+			protocol::parse(&handle_request(request, &test_options(), config, Transport::Udp, src).unwrap()).unwrap().header.rcode
+		};
 		
-		assert_eq!(handle_dns(&Question {
+		// an empty ACL allows everyone
+		let open = config(&[], true, &[], &[]);
+		assert_eq!(rcode("alias.example.com", &open, "198.51.100.1"), 0);
+		
+		let acl = config(&["192.0.2.0/24", "2001:db8::/32"], true, &[], &[]);
+		assert_eq!(rcode("alias.example.com", &acl, "192.0.2.1"), 0);
+		assert_eq!(rcode("alias.example.com", &acl, "192.0.2.255"), 0);
+		assert_eq!(rcode("alias.example.com", &acl, "198.51.100.1"), 5);
+		assert_eq!(rcode("alias.example.com", &acl, "2001:db8:1::1"), 0);
+		assert_eq!(rcode("alias.example.com", &acl, "2001:db9::1"), 5);
+		// local records are still served
+		assert_eq!(rcode("example.net", &acl, "198.51.100.1"), 0);
+		// unless configured otherwise
+		let strict = config(&["192.0.2.0/24"], false, &[], &[]);
+		assert_eq!(rcode("example.net", &strict, "198.51.100.1"), 5);
+		assert_eq!(rcode("example.net", &strict, "192.0.2.1"), 0);
+		
+		// zones can override the ACL
+		let allow = config(&["192.0.2.0/24"], true, &["198.51.100.0/24"], &[]);
+		assert_eq!(rcode("alias.example.com", &allow, "198.51.100.1"), 0);
+		assert_eq!(rcode("alias.example.com", &allow, "192.0.2.1"), 5);
+		let deny = config(&["192.0.2.0/24"], true, &[], &["192.0.2.1"]);
+		assert_eq!(rcode("alias.example.com", &deny, "192.0.2.1"), 5);
+		assert_eq!(rcode("alias.example.com", &deny, "192.0.2.2"), 0);
+	}
+	
+	#[test]
+	fn test_acl_inherited() {
+		let config = crate::config::parse(r"acl-local: false
+zones:
+  example.com inherit:
+    deny: [192.0.2.1]
+    A: 10.0.0.1").unwrap();
+		let respond = |qname: &str, src: &str| {
+			let request = protocol::serialize(&protocol::make_message_from_question(vec![Question { qname: name(qname), qtype: record_type::A, qclass: class::IN }]), 512);
+			let src = SocketAddr::new(src.parse().unwrap(), 5353);
+			let response = protocol::parse(&handle_request(request, &test_options(), &config, Transport::Udp, src).unwrap()).unwrap();
+			(response.header.rcode, response.answer.len())
+		};
+		
+		assert_eq!(respond("example.com", "192.0.2.1"), (5, 0));
+		// names answered by inheriting from the zone are denied just the same
+		assert_eq!(respond("www.example.com", "192.0.2.1"), (5, 0));
+		assert_eq!(respond("www.example.com", "192.0.2.2"), (0, 1));
+	}
+	
+	#[test]
+	fn test_failover() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		// nothing listens on a port that was just freed
+		let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let config = crate::config::parse(&format!(r"zones:
+  example.com:
+    A:
+      - {{ ip: 10.0.0.1, check: 'tcp://{}', weight: 10 }}
+      - {{ ip: 10.0.0.2, check: 'tcp://{}', weight: 1 }}
+  example.net:
+    A:
+      - {{ ip: 10.0.0.3, check: 'tcp://{}' }}", closed, listener.local_addr().unwrap(), closed)).unwrap();
+		let answer = |qname: &str| match handle_dns(&Question {
+			qname: qname.split('.').map(String::from).collect(),
+			qtype: record_type::A,
+			qclass: class::IN,
+		}, &test_options(), &config) {
+			Response::Ok(answer, _, _) => answer.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>(),
+			response => panic!("unexpected response {:?}", response),
+		};
+		
+		// every check passes until it's been run
+		let health = Health::default();
+		checks::start(&health);
+		assert_eq!(answer("example.com").len(), 2);
+		Checker::new().run(&test_options(), &health, vec![config.zones[0].records.a[0].check.clone().unwrap(), config.zones[0].records.a[1].check.clone().unwrap()].into_iter().collect(), Duration::from_secs(1));
+		assert_eq!(answer("example.com"), vec![vec![10, 0, 0, 2]]);
+		// when every check fails, every record is answered
+		assert_eq!(answer("example.net"), vec![vec![10, 0, 0, 3]]);
+	}
+	
+	#[test]
+	fn test_views() {
+		let config = crate::config::parse(r"networks:
+  internal: [10.0.0.0/8, 'fd00::/8']
+zones:
+  example.com @internal:
+    A: 10.0.0.5
+  example.com:
+    A: 203.0.113.5
+    MX: mail.example.com
+  intranet.example.com @internal:
+    A: 10.0.0.6").unwrap();
+		let answer = |qname: &str, qtype: u16, src: &str| {
+			let request = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+				qname: qname.split('.').map(|label| label.to_string()).collect(),
+				qtype,
+				qclass: class::IN,
+			}]), 512);
+			let src = SocketAddr::new(src.parse().unwrap(), 5353);
+			let response = protocol::parse(&handle_request(request, &test_options(), &config, Transport::Udp, src).unwrap()).unwrap();
+			(response.header.rcode, response.answer.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>())
+		};
+		
+		assert_eq!(answer("example.com", record_type::A, "10.1.2.3"), (0, vec![vec![10, 0, 0, 5]]));
+		assert_eq!(answer("example.com", record_type::A, "fd00::1"), (0, vec![vec![10, 0, 0, 5]]));
+		assert_eq!(answer("example.com", record_type::A, "198.51.100.1"), (0, vec![vec![203, 0, 113, 5]]));
+		// the zone the view replaces is gone entirely, not just the types it has
+		assert_eq!(answer("example.com", record_type::MX, "10.1.2.3").1, Vec::<Vec<u8>>::new());
+		assert_eq!(answer("example.com", record_type::MX, "198.51.100.1").1.len(), 1);
+		assert_eq!(answer("intranet.example.com", record_type::A, "10.1.2.3"), (0, vec![vec![10, 0, 0, 6]]));
+		assert_eq!(answer("intranet.example.com", record_type::A, "198.51.100.1"), (3, vec![]));
+	}
+	
+	#[test]
+	fn test_slip_response() {
+		let question = Question {
 			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::NS,
+			qtype: record_type::A,
 			qclass: 1,
-		}, &test_options(), &Config {
+		};
+		let mut request = protocol::make_message_from_question(vec![question.clone()]);
+		request.header.id = 42;
+		request.header.rd = true;
+		let response = protocol::parse(&make_slip_response(&protocol::serialize(&request, 512)).unwrap()).unwrap();
+		assert_eq!(response.header.id, 42);
+		assert!(response.header.qr);
+		assert!(response.header.tc);
+		assert!(response.header.rd);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.question, vec![question]);
+		assert!(response.answer.is_empty());
+		
+		// responses and garbage don't get one
+		request.header.qr = true;
+		assert_eq!(make_slip_response(&protocol::serialize(&request, 512)), None);
+		assert_eq!(make_slip_response(&[1, 2, 3]), None);
+	}
+	
+	#[test]
+	fn test_inbound_notify() {
+		let question = Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::SOA,
+			qclass: class::IN,
+		};
+		let mut request = protocol::make_message_from_question(vec![question.clone()]);
+		request.header.id = 42;
+		request.header.opcode = opcode::NOTIFY;
+		request.header.aa = true;
+		request.answer.push(make_soa(&question, &soa_config(), soa_config().nttl));
+		let response = protocol::parse(&handle_request(protocol::serialize(&request, 512), &test_options(), &soa_config(), Transport::Udp, test_src()).unwrap()).unwrap();
+		assert_eq!(response.header.id, 42);
+		assert_eq!(response.header.opcode, opcode::NOTIFY);
+		assert!(response.header.qr);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.question, vec![question]);
+		assert!(response.answer.is_empty());
+	}
+	
+	/// Opcodes other than QUERY and classes other than IN and CH are turned away before anything is
+	/// looked up, which the name that panics when it's answered would show.
+	#[test]
+	fn test_unsupported_opcode_and_class() {
+		let respond = |opcode: u8, qclass: u16| {
+			let mut request = protocol::make_message_from_question(vec![Question { qname: name("panic.test"), qtype: record_type::A, qclass }]);
+			request.header.id = 42;
+			request.header.opcode = opcode;
+			protocol::parse(&handle_request(protocol::serialize(&request, 512), &test_options(), &soa_config(), Transport::Udp, test_src()).unwrap()).unwrap()
+		};
+		for (opcode, qclass, rcode) in &[(opcode::STATUS, class::IN, 4), (opcode::IQUERY, class::IN, 4), (15, class::IN, 4), (opcode::QUERY, 4, 5), (opcode::QUERY, class::ANY, 5)] {
+			let response = respond(*opcode, *qclass);
+			assert_eq!((response.header.id, response.header.opcode, response.header.rcode), (42, *opcode, *rcode));
+			assert!(response.header.qr);
+			assert_eq!(response.question, vec![Question { qname: name("panic.test"), qtype: record_type::A, qclass: *qclass }]);
+			assert!(response.answer.is_empty() && response.authority.is_empty() && response.additional.is_empty());
+		}
+	}
+	
+	#[test]
+	fn test_require_tsig() {
+		let config = crate::config::parse(r"tsig-keys:
+  resolver:
+    secret: AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=
+  other:
+    secret: AAAA
+zones:
+  internal.example.com:
+    require-tsig: resolver
+    A: 10.0.0.1
+  inherited.example.com inherit:
+    require-tsig: resolver
+    A: 10.0.0.3
+  example.com:
+    A: 10.0.0.2").unwrap();
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let respond = |qname: &str, key: Option<(&str, &[u8])>| {
+			let mut request = protocol::make_message_from_question(vec![Question { qname: name(qname), qtype: record_type::A, qclass: class::IN }]);
+			request.header.id = 42;
+			let buf = match key {
+				Some((key_name, secret)) => tsig::test::signed_request(request, key_name, secret, now),
+				None => protocol::serialize(&request, 512),
+			};
+			let response = protocol::parse(&handle_request(buf, &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap();
+			let tsig = response.tsig.as_ref().map(|record| tsig::Tsig::parse(&record.rdata).unwrap());
+			(response.header.rcode, response.answer.len(), tsig.map(|tsig| (tsig.error, tsig.mac.len())))
+		};
+		let secret = config.tsig_keys[0].secret.clone();
+		let other = config.tsig_keys[1].secret.clone();
+		
+		assert_eq!(respond("internal.example.com", None), (5, 0, None));
+		assert_eq!(respond("internal.example.com", Some(("resolver", &secret))), (0, 1, Some((0, 32))));
+		// signed, but not with the key the zone requires
+		assert_eq!(respond("internal.example.com", Some(("other", &other))), (5, 0, Some((0, 32))));
+		assert_eq!(respond("internal.example.com", Some(("resolver", &other))), (9, 0, Some((tsig::error::BADSIG, 0))));
+		assert_eq!(respond("internal.example.com", Some(("unknown", &secret))), (9, 0, Some((tsig::error::BADKEY, 0))));
+		
+		// names answered by inheriting from the zone are kept from the client just the same
+		assert_eq!(respond("x.inherited.example.com", None), (5, 0, None));
+		assert_eq!(respond("x.inherited.example.com", Some(("resolver", &secret))), (0, 1, Some((0, 32))));
+		
+		// other zones can be queried either way, and a signed query gets a signed response
+		assert_eq!(respond("example.com", None), (0, 1, None));
+		assert_eq!(respond("example.com", Some(("resolver", &secret))), (0, 1, Some((0, 32))));
+	}
+	
+	#[test]
+	fn test_require_tsig_followed() {
+		let options = Options { answer_cache_size: 10, ..test_options() };
+		let config = SharedConfig::new(crate::config::parse(r"tsig-keys:
+  resolver:
+    secret: AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=
+allow-transfer: [127.0.0.1]
+zones:
+  internal.example.com:
+    require-tsig: resolver
+    A: 10.0.0.1
+  mail.internal.example.com:
+    require-tsig: resolver
+    A: 10.0.0.2
+  example.com:
+    NS: ns.internal.example.com.
+    MX: mail.internal.example.com.
+  ns.internal.example.com:
+    require-tsig: resolver
+    A: 10.0.0.3
+  www.example.com:
+    CNAME: internal.example.com.
+  flat.example.com:
+    ANAME: internal.example.com.").unwrap());
+		let secret = config.get().tsig_keys[0].secret.clone();
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let request = |qname: &str, qtype: u16, signed: bool| {
+			let request = protocol::make_message_from_question(vec![Question { qname: name(qname), qtype, qclass: class::IN }]);
+			return match signed {
+				true => tsig::test::signed_request(request, "resolver", &secret, now),
+				false => protocol::serialize(&request, 512),
+			};
+		};
+		let respond = |qname: &str, qtype: u16, signed: bool| {
+			let responses = respond(&request(qname, qtype, signed), &options, &config, Transport::Tcp, test_src());
+			return responses.iter().map(|response| protocol::parse(response).unwrap()).collect::<Vec<Message>>();
+		};
+		let records = |qname: &str, qtype: u16, signed: bool| {
+			let response = respond(qname, qtype, signed).remove(0);
+			return (response.answer.len(), response.additional.iter().filter(|record| record.rtype == record_type::A).count());
+		};
+		
+		// a CNAME is left for the client to follow, and an ANAME is flattened to nothing
+		assert_eq!(records("www.example.com", record_type::A, false), (1, 0));
+		assert_eq!(records("www.example.com", record_type::A, true), (2, 0));
+		assert_eq!(records("flat.example.com", record_type::A, false), (0, 0));
+		assert_eq!(records("flat.example.com", record_type::A, true), (1, 0));
+		// the addresses of MX and NS targets are left out of the additional section, as are those of
+		// the NS records filling the authority section
+		assert_eq!(records("example.com", record_type::MX, false), (1, 0));
+		assert_eq!(records("example.com", record_type::MX, true), (1, 2));
+		assert_eq!(records("example.com", record_type::NS, false), (1, 0));
+		assert_eq!(records("example.com", record_type::NS, true), (1, 1));
+		// and none of these are cached, as they'd be answered differently to a signed query
+		assert!(cached(&request("www.example.com", record_type::A, false), &options, &config, Transport::Tcp, test_src()).is_none());
+		assert!(cached(&request("example.com", record_type::MX, false), &options, &config, Transport::Tcp, test_src()).is_none());
+		
+		// a transfer of a zone above them leaves them out, unless it's signed, when it's signed too
+		let transferred = |signed: bool| {
+			let messages = respond("example.com", record_type::AXFR, signed);
+			assert!(messages.iter().all(|message| message.tsig.is_some() == signed));
+			return messages.iter().flat_map(|message| &message.answer).filter(|record| record.rtype == record_type::A).count();
+		};
+		assert_eq!(transferred(false), 0);
+		assert_eq!(transferred(true), 3);
+	}
+	
+	#[test]
+	fn test_edns() {
+		let config = Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
 				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				excludes: vec![],
 				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![NsRecord {
-						ttl: Duration::from_secs(100),
-						name: "ns.example.com".to_string(),
-					}],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}, Zone {
-				matchers: vec![vec![Label::Basic("ns".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![ARecord {
+					txt: (0..10).map(|i| TxtRecord {
 						ttl: Duration::from_secs(100),
-						ip4addr: "1.1.1.1".parse().unwrap(),
-					}],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
+						data: i.to_string().repeat(200),
+						metadata: None,
+					}).collect(),
+					..Default::default()
 				},
 			}],
-		}), (vec![Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::NS,
+		};
+		let request = |edns: Option<Edns>| {
+			let mut message = protocol::make_message_from_question(vec![Question {
+				qname: vec!["example".to_string(), "com".to_string()],
+				qtype: record_type::TXT,
+				qclass: 1,
+			}]);
+			message.edns = edns;
+			let request = protocol::serialize(&message, 512);
+			protocol::parse(&handle_request(request, &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap()
+		};
+		
+		// plain DNS is limited to 512 bytes over UDP
+		let response = request(None);
+		assert!(response.header.tc);
+		assert!(response.answer.len() < 10);
+		assert_eq!(response.edns, None);
+		
+		// the client's payload size is used for truncation, and ours is advertised back
+		let response = request(Some(Edns {
+			udp_payload_size: 4096,
+			extended_rcode_and_flags: Edns::DNSSEC_OK,
+			options: vec![],
+		}));
+		assert!(!response.header.tc);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer.len(), 10);
+		assert_eq!(response.edns, Some(Edns {
+			udp_payload_size: 1232,
+			extended_rcode_and_flags: Edns::DNSSEC_OK,
+			options: vec![],
+		}));
+		
+		// payload sizes below 512 are treated as 512
+		let response = request(Some(Edns {
+			udp_payload_size: 100,
+			extended_rcode_and_flags: 0,
+			options: vec![],
+		}));
+		assert!(response.header.tc);
+		// the records are one RRset, which isn't split
+		assert!(response.answer.is_empty());
+		
+		// we only speak version 0
+		let response = request(Some(Edns {
+			udp_payload_size: 4096,
+			extended_rcode_and_flags: 1 << 16,
+			options: vec![],
+		}));
+		assert_eq!(response.header.rcode, 16);
+		assert!(response.answer.is_empty());
+		assert_eq!(response.edns.unwrap().udp_payload_size, 1232);
+	}
+	
+	#[test]
+	fn test_extended_errors() {
+		let resolver = MockResolver::new();
+		resolver.reply(Question { qname: name("target.ede.test"), qtype: record_type::A, qclass: 1 }, Reply::Rcode(2));
+		let options = Options { resolver: vec![resolver.address], ..test_options() };
+		let config = crate::config::parse(r"zones:
+  a.ede.test: {CNAME: b.ede.test.}
+  b.ede.test: {CNAME: a.ede.test.}
+  flat.ede.test: {ANAME: target.ede.test.}").unwrap();
+		let request = |qname: &str, edns: bool| {
+			let mut message = protocol::make_message_from_question(vec![Question { qname: name(qname), qtype: record_type::A, qclass: 1 }]);
+			if edns { message.edns = Some(Edns { udp_payload_size: 1232, ..Default::default() }); }
+			let response = protocol::parse(&handle_request(protocol::serialize(&message, 512), &options, &config, Transport::Udp, test_src()).unwrap()).unwrap();
+			// the info code, then the text
+			let error = response.edns.map(|edns| edns.options.iter()
+				.find(|option| option.code == 15)
+				.map(|option| (u16::from_be_bytes([option.data[0], option.data[1]]), String::from_utf8(option.data[2..].to_vec()).unwrap())));
+			return (response.header.rcode, error);
+		};
+		
+		assert_eq!(request("a.ede.test", true), (2, Some(Some((0, "too many names followed, the config may have a loop".to_string())))));
+		assert_eq!(request("example.org", true), (5, Some(Some((20, String::new())))));
+		// a failed ANAME is answered without addresses, but still says why
+		assert_eq!(request("flat.ede.test", true), (0, Some(Some((22, "the resolver failed".to_string())))));
+		// nothing failed, the name only has names below it
+		assert_eq!(request("ede.test", true), (0, Some(None)));
+		// and only clients sending EDNS get it
+		assert_eq!(request("a.ede.test", false), (2, None));
+	}
+	
+	/// Binds UDP and TCP sockets on the same free port, to stand in for a resolver.
+	fn fake_resolver() -> (UdpSocket, TcpListener, SocketAddr) {
+		loop {
+			let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+			let address = udp.local_addr().unwrap();
+			if let Ok(tcp) = TcpListener::bind(address) {
+				return (udp, tcp, address);
+			}
+		}
+	}
+	
+	/// Answers the next UDP query with an empty, truncated response.
+	fn reply_truncated(udp: &UdpSocket) {
+		let mut buf = vec![0; 512];
+		let (size, src) = udp.recv_from(&mut buf).unwrap();
+		let mut message = protocol::parse(&buf[..size]).unwrap();
+		message.header.qr = true;
+		message.header.tc = true;
+		udp.send_to(&protocol::serialize(&message, 512), src).unwrap();
+	}
+	
+	fn resolver_question(name: &str) -> Question {
+		Question {
+			qname: vec![name.to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}
+	}
+	
+	#[test]
+	fn test_recursion_desired() {
+		let resolver = MockResolver::new();
+		let a = |name: &str, ip: [u8; 4]| Resource {
+			rname: name.split('.').map(|label| label.to_string()).collect(),
+			rtype: record_type::A,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![2, 110, 115, 7, 101, 120, 97, 109, 112, 108, 101, 3, 99, 111, 109, 0],
-		}], vec![], vec![Resource {
-			rname: vec!["ns".to_string(), "example".to_string(), "com".to_string()],
+			ttl: 300,
+			rdata: ip.to_vec(),
+		};
+		resolver.answer(Question { qname: name("elsewhere.test"), qtype: record_type::A, qclass: 1 }, vec![a("elsewhere.test", [192, 0, 2, 1])]);
+		resolver.answer(Question { qname: name("target.test"), qtype: record_type::A, qclass: 1 }, vec![a("target.test", [192, 0, 2, 2])]);
+		let config = crate::config::parse(r"zones:
+  example.com:
+    NS: ns1.example.com.
+    A: 10.0.0.1
+  aname.example.com:
+    ANAME: target.test.").unwrap();
+		let mut options = test_options();
+		options.resolver = vec![resolver.address];
+		options.allow_recursion = vec!["10.0.0.0/8".parse().unwrap()];
+		let allowed: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+		let denied: SocketAddr = "192.0.2.53:5353".parse().unwrap();
+		let ask = |qname: &str, rd: bool, src: SocketAddr, options: &Options| {
+			let mut request = protocol::make_message_from_question(vec![Question { qname: name(qname), qtype: record_type::A, qclass: 1 }]);
+			request.header.rd = rd;
+			let response = protocol::parse(&handle_request(protocol::serialize(&request, 512), options, &config, Transport::Udp, src).unwrap()).unwrap();
+			assert_eq!(response.header.rd, rd);
+			let answer: Vec<Vec<u8>> = response.answer.iter().map(|record| record.rdata.clone()).collect();
+			return (response.header.rcode, response.header.ra, response.header.aa, answer);
+		};
+		
+		// recursion desired and allowed: names outside every zone are looked up
+		assert_eq!(ask("elsewhere.test", true, allowed, &options), (0, true, false, vec![vec![192, 0, 2, 1]]));
+		assert_eq!(ask("example.com", true, allowed, &options), (0, true, true, vec![vec![10, 0, 0, 1]]));
+		assert_eq!(ask("aname.example.com", true, allowed, &options), (0, true, true, vec![vec![192, 0, 2, 2]]));
+		// desired but not allowed: refused, though our own zones are still answered in full
+		assert_eq!(ask("elsewhere.test", true, denied, &options), (5, false, true, vec![]));
+		assert_eq!(ask("example.com", true, denied, &options), (0, false, true, vec![vec![10, 0, 0, 1]]));
+		assert_eq!(ask("aname.example.com", true, denied, &options), (0, false, true, vec![vec![192, 0, 2, 2]]));
+		// not desired: nothing is looked up elsewhere for anyone, so an ANAME is left unflattened
+		for &src in &[allowed, denied] {
+			let ra = src == allowed;
+			assert_eq!(ask("elsewhere.test", false, src, &options), (5, ra, true, vec![]));
+			assert_eq!(ask("example.com", false, src, &options), (0, ra, true, vec![vec![10, 0, 0, 1]]));
+			assert_eq!(ask("aname.example.com", false, src, &options), (0, ra, true, vec![]));
+		}
+		// each looked up once, the rest from the cache
+		assert_eq!(resolver.received().len(), 2);
+		
+		// without --allow-recursion, RD changes nothing and recursion is never available
+		options.allow_recursion = vec![];
+		assert_eq!(ask("elsewhere.test", true, allowed, &options), (5, false, true, vec![]));
+		assert_eq!(ask("aname.example.com", false, allowed, &options), (0, false, true, vec![vec![192, 0, 2, 2]]));
+		
+		// a cached response says whether recursion is available to whoever it's sent to
+		options.allow_recursion = vec!["10.0.0.0/8".parse().unwrap()];
+		options.answer_cache_size = 10;
+		let config = SharedConfig::new(crate::config::parse("zones:\n  cached.example.com:\n    A: 10.0.0.1").unwrap());
+		let request = protocol::serialize(&protocol::make_message_from_question(vec![Question { qname: name("cached.example.com"), qtype: record_type::A, qclass: 1 }]), 512);
+		assert!(!protocol::parse(&respond(&request, &options, &config, Transport::Udp, denied)[0]).unwrap().header.ra);
+		assert!(protocol::parse(&cached(&request, &options, &config, Transport::Udp, allowed).unwrap()).unwrap().header.ra);
+		assert!(!protocol::parse(&cached(&request, &options, &config, Transport::Udp, denied).unwrap()).unwrap().header.ra);
+	}
+	
+	#[test]
+	fn test_resolver_timeout() {
+		let mut options = test_options();
+		options.resolver_timeout = 200;
+		
+		// nothing ever comes back over UDP
+		let resolver = MockResolver::new();
+		resolver.reply(resolver_question("udp-timeout"), Reply::Timeout);
+		let instant = Instant::now();
+		assert_eq!(resolver_lookup(resolver_question("udp-timeout"), &[resolver.address], &options), Response::ServerFailure);
+		assert!(instant.elapsed() >= Duration::from_millis(200));
+		assert!(instant.elapsed() < Duration::from_millis(1000));
+		
+		// truncated over UDP, then the TCP connection is accepted but never answered
+		let (udp, tcp, address) = fake_resolver();
+		let server = thread::spawn(move || {
+			reply_truncated(&udp);
+			let (stream, _) = tcp.accept().unwrap();
+			thread::sleep(Duration::from_millis(1000));
+			drop(stream);
+		});
+		let instant = Instant::now();
+		assert_eq!(resolver_lookup(resolver_question("tcp-timeout"), &[address], &options), Response::ServerFailure);
+		assert!(instant.elapsed() < Duration::from_millis(1000));
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_resolver_truncated() {
+		let (udp, tcp, address) = fake_resolver();
+		let server = thread::spawn(move || {
+			reply_truncated(&udp);
+			let (mut stream, _) = tcp.accept().unwrap();
+			let mut message = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+			message.header.qr = true;
+			message.answer.push(Resource {
+				rname: message.question[0].qname.clone(),
+				rtype: record_type::A,
+				rclass: 1,
+				ttl: 100,
+				rdata: vec![10, 10, 10, 10],
+			});
+			write_tcp_message(&mut stream, &protocol::serialize(&message, u16::MAX)).unwrap();
+		});
+		assert_eq!(resolver_lookup(resolver_question("truncated"), &[address], &test_options()), Response::Ok(vec![Resource {
+			rname: vec!["truncated".to_string(), "example".to_string(), "com".to_string()],
 			rtype: record_type::A,
 			rclass: 1,
 			ttl: 100,
-			rdata: vec![1, 1, 1, 1],
-		}]));
+			rdata: vec![10, 10, 10, 10],
+		}], vec![], vec![]));
+		server.join().unwrap();
 	}
 	
 	#[test]
-	fn test_cname() {
-		assert_eq!(handle_dns(&Question {
-			qname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
-			qtype: record_type::A,
-			qclass: 1,
-		}, &test_options(), &Config {
+	fn test_resolver_truncated_over_tcp() {
+		let (udp, tcp, address) = fake_resolver();
+		// truncated over both, every time it's asked, as nothing is cached
+		let server = thread::spawn(move || {
+			for _ in 0..2 {
+				reply_truncated(&udp);
+				let (mut stream, _) = tcp.accept().unwrap();
+				let mut message = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+				message.header.qr = true;
+				message.header.tc = true;
+				message.answer.push(Resource {
+					rname: message.question[0].qname.clone(),
+					rtype: record_type::A,
+					rclass: 1,
+					ttl: 100,
+					rdata: vec![10, 10, 10, 10],
+				});
+				write_tcp_message(&mut stream, &protocol::serialize(&message, u16::MAX)).unwrap();
+			}
+		});
+		ede::start();
+		assert_eq!(resolver_lookup(resolver_question("still-truncated"), &[address], &test_options()), Response::ServerFailure);
+		assert_eq!(ede::finish().map(|error| error.info_code), Some(ede::info_code::NO_REACHABLE_AUTHORITY));
+		assert_eq!(resolver_lookup(resolver_question("still-truncated"), &[address], &test_options()), Response::ServerFailure);
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_slow_query_phases() {
+		let resolver = MockResolver::new();
+		let target = Question { qname: name("slow.test"), qtype: record_type::A, qclass: 1 };
+		resolver.reply(target.clone(), Reply::Slow(Duration::from_millis(300), Message {
+			answer: vec![Resource { rname: target.qname.clone(), rtype: record_type::A, rclass: 1, ttl: 300, rdata: vec![192, 0, 2, 1] }],
+			..Message::default()
+		}));
+		let config = crate::config::parse(r"zones:
+  slow.example.com:
+    ANAME: slow.test.").unwrap();
+		let mut options = test_options();
+		options.resolver = vec![resolver.address];
+		let question = Question { qname: name("slow.example.com"), qtype: record_type::A, qclass: 1 };
+		
+		timing::start(true);
+		assert!(matches!(handle_dns(&question, &options, &config), Response::Ok(answer, _, _) if answer.len() == 1));
+		timing::lap(Phase::Answer);
+		let phases = timing::finish().unwrap();
+		// the wait on the resolver is nearly all of it, and isn't counted as matching
+		assert!(phases.upstream >= Duration::from_millis(300) && phases.upstream * 10 > phases.total * 9, "{:?}", phases);
+		assert!(phases.matching < Duration::from_millis(100), "{:?}", phases);
+		let line = SlowQuery::new(&protocol::make_message_from_question(vec![question]), 60, phases).format("text");
+		assert!(line.starts_with("slow query: slow.example.com. A rcode=0 size=60 "), "{}", line);
+		assert!(line.contains(&format!(" upstream={}us ", phases.upstream.as_micros())), "{}", line);
+		
+		// nothing is timed unless it's been started
+		timing::start(false);
+		timing::lap(Phase::Parse);
+		assert_eq!(timing::finish(), None);
+	}
+	
+	#[test]
+	fn test_verify_response() {
+		let mut request = protocol::make_message_from_question(vec![resolver_question("verify")]);
+		request.header.id = 1234;
+		let mut response = request.clone();
+		response.header.qr = true;
+		assert!(verify_response(&request, &response));
+		// only responses count
+		assert!(!verify_response(&request, &request));
+		
+		let verifies = |change: &dyn Fn(&mut Message)| {
+			let mut response = response.clone();
+			change(&mut response);
+			return verify_response(&request, &response);
+		};
+		assert!(!verifies(&|response| response.header.id = 4321));
+		assert!(!verifies(&|response| response.question[0].qtype = record_type::AAAA));
+		assert!(!verifies(&|response| response.question[0].qclass = class::CH));
+		assert!(!verifies(&|response| response.question[0].qname[0] = "other".to_string()));
+		assert!(!verifies(&|response| response.question.clear()));
+		// the case of the name may differ
+		assert!(verifies(&|response| response.question[0].qname[0] = "VeRiFy".to_string()));
+	}
+	
+	#[test]
+	fn test_ptr() {
+		let config = Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
 			zones: vec![Zone {
-				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				matchers: vec![vec![Label::Basic("1".to_string()), Label::Basic("1".to_string()), Label::Basic("168".to_string()), Label::Basic("192".to_string()), Label::Basic("in-addr".to_string()), Label::Basic("arpa".to_string())]],
+				excludes: vec![],
 				records: Records {
-					a: vec![ARecord {
+					ptr: vec![PtrRecord {
 						ttl: Duration::from_secs(100),
-						ip4addr: "127.0.0.1".parse().unwrap(),
+						name: "router.example.com.".to_string(),
+						metadata: None,
 					}],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
+					..Default::default()
 				},
 			}, Zone {
-				matchers: vec![vec![Label::Basic("www".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+				matchers: vec![vec![Label::Wildcard, Label::Wildcard, Label::Wildcard, Label::Basic("10".to_string()), Label::Basic("in-addr".to_string()), Label::Basic("arpa".to_string())]],
+				excludes: vec![],
 				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![CnameRecord {
+					ptr: vec![PtrRecord {
 						ttl: Duration::from_secs(100),
-						name: "example.com".to_string(),
+						name: "host.example.com.".to_string(),
+						metadata: None,
 					}],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
+					..Default::default()
 				},
 			}],
-		}), (vec![Resource {
-			rname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
-			rtype: record_type::CNAME,
+		};
+		let name = |labels: &[&str]| labels.iter().map(|label| label.to_string()).collect::<Vec<String>>();
+		
+		assert_eq!(handle_dns(&Question {
+			qname: name(&["1", "1", "168", "192", "in-addr", "arpa"]),
+			qtype: record_type::PTR,
+			qclass: 1,
+		}, &test_options(), &config), Response::Ok(vec![Resource {
+			rname: name(&["1", "1", "168", "192", "in-addr", "arpa"]),
+			rtype: record_type::PTR,
 			rclass: 1,
 			ttl: 100,
-			rdata: vec![7, 'e' as u8, 'x' as u8, 'a' as u8, 'm' as u8, 'p' as u8, 'l' as u8, 'e' as u8, 3, 'c' as u8, 'o' as u8, 'm' as u8, 0],
-		}, Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::A,
+			rdata: vec![
+				6, b'r', b'o', b'u', b't', b'e', b'r',
+				7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+				3, b'c', b'o', b'm',
+				0,
+			],
+		}], vec![], vec![]));
+		
+		// the rname keeps the casing of the question, even when matched by a wildcard
+		assert_eq!(handle_dns(&Question {
+			qname: name(&["4", "3", "2", "10", "IN-ADDR", "Arpa"]),
+			qtype: record_type::PTR,
+			qclass: 1,
+		}, &test_options(), &config), Response::Ok(vec![Resource {
+			rname: name(&["4", "3", "2", "10", "IN-ADDR", "Arpa"]),
+			rtype: record_type::PTR,
 			rclass: 1,
 			ttl: 100,
-			rdata: vec![127, 0, 0, 1],
+			rdata: vec![
+				4, b'h', b'o', b's', b't',
+				7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+				3, b'c', b'o', b'm',
+				0,
+			],
 		}], vec![], vec![]));
 		
+		// no other record types in reverse zones
 		assert_eq!(handle_dns(&Question {
-			qname: vec!["www2".to_string(), "example".to_string(), "com".to_string()],
+			qname: name(&["4", "3", "2", "10", "in-addr", "arpa"]),
 			qtype: record_type::A,
 			qclass: 1,
-		}, &test_options(), &Config {
+		}, &test_options(), &config), Response::Ok(vec![], vec![], vec![]));
+	}
+	
+	#[test]
+	fn test_multiple_questions() {
+		let mut config = soa_config();
+		config.catch_all = true;
+		let request = |names: &[&str]| {
+			let request = protocol::serialize(&protocol::make_message_from_question(names.iter().map(|name| Question {
+				qname: vec![name.to_string(), "com".to_string()],
+				qtype: record_type::A,
+				qclass: 1,
+			}).collect()), 512);
+			protocol::parse(&handle_request(request, &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap()
+		};
+		
+		// both questions are answered, though the same record is only given once
+		let response = request(&["example", "example"]);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.question.len(), 2);
+		assert_eq!(response.answer.len(), 1);
+		
+		// the first failure decides the rcode, but other questions are still answered
+		let response = request(&["example", "nope"]);
+		assert_eq!(response.header.rcode, 3);
+		assert_eq!(response.answer.len(), 1);
+		assert_eq!(response.authority.len(), 1);
+		assert_eq!(response.authority[0].rtype, record_type::SOA);
+	}
+	
+	/// Answers UDP queries with the given rcode and authority section until no query has arrived
+	/// for a while, counting the queries.
+	fn counting_resolver(rcode: u16, authority: Vec<Resource>) -> (SocketAddr, Arc<AtomicUsize>, thread::JoinHandle<()>) {
+		let (udp, _tcp, address) = fake_resolver();
+		let count = Arc::new(AtomicUsize::new(0));
+		let server = {
+			let count = count.clone();
+			thread::spawn(move || {
+				udp.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+				let mut buf = vec![0; 512];
+				while let Ok((size, src)) = udp.recv_from(&mut buf) {
+					count.fetch_add(1, Ordering::SeqCst);
+					let mut message = protocol::parse(&buf[..size]).unwrap();
+					message.header.qr = true;
+					message.header.rcode = rcode;
+					message.authority = authority.clone();
+					udp.send_to(&protocol::serialize(&message, 512), src).unwrap();
+				}
+			})
+		};
+		(address, count, server)
+	}
+	
+	#[test]
+	fn test_resolver_failover() {
+		// the first resolver never answers
+		let (_dead_udp, _dead_tcp, dead) = fake_resolver();
+		let (alive, count, server) = counting_resolver(0, vec![]);
+		let mut options = test_options();
+		options.resolver_timeout = 200;
+		let servers = [dead, alive];
+		
+		let start = Instant::now();
+		assert_eq!(resolver_lookup(resolver_question("failover"), &servers, &options), Response::Ok(vec![], vec![], vec![]));
+		assert!(start.elapsed() >= Duration::from_millis(200));
+		
+		// the dead resolver is skipped for a while
+		let start = Instant::now();
+		assert_eq!(resolver_lookup(resolver_question("failover2"), &servers, &options), Response::Ok(vec![], vec![], vec![]));
+		assert!(start.elapsed() < Duration::from_millis(200));
+		
+		server.join().unwrap();
+		assert_eq!(count.load(Ordering::SeqCst), 2);
+	}
+	
+	#[test]
+	fn test_rns_host_fallback() {
+		// only answers over IPv4, so the IPv6 address the host also has is refused
+		let (udp, _tcp, address) = fake_resolver();
+		let server = thread::spawn(move || {
+			udp.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+			let mut buf = vec![0; 512];
+			while let Ok((size, src)) = udp.recv_from(&mut buf) {
+				let mut message = protocol::parse(&buf[..size]).unwrap();
+				message.header.qr = true;
+				let question = message.question[0].clone();
+				assert_eq!(question.qtype, record_type::MX);
+				message.answer = vec![Resource {
+					rname: question.qname,
+					rtype: record_type::MX,
+					rclass: 1,
+					ttl: 300,
+					rdata: protocol::serialize_mx("mail.upstream.test", 10),
+				}];
+				message.additional = vec![Resource {
+					rname: vec!["mail".to_string(), "upstream".to_string(), "test".to_string()],
+					rtype: record_type::A,
+					rclass: 1,
+					ttl: 300,
+					rdata: vec![10, 0, 0, 25],
+				}];
+				message.edns = Some(Edns { udp_payload_size: 1232, extended_rcode_and_flags: 0, options: vec![] });
+				udp.send_to(&protocol::serialize(&message, 512), src).unwrap();
+			}
+		});
+		
+		let config = crate::config::parse(&format!(r"zones:
+  ns.rns-fallback.test:
+    AAAA: ::1
+    A: 127.0.0.1
+  rns-fallback.test:
+    RNS: ns.rns-fallback.test:{}", address.port())).unwrap();
+		let response = handle_dns(&Question {
+			qname: vec!["rns-fallback".to_string(), "test".to_string()],
+			qtype: record_type::MX,
+			qclass: 1,
+		}, &test_options(), &config);
+		match response {
+			Response::Ok(answer, authority, additional) => {
+				assert_eq!(answer.iter().map(|record| record.rtype).collect::<Vec<u16>>(), vec![record_type::MX]);
+				assert!(authority.is_empty());
+				assert_eq!(additional.iter().map(|record| (record.rtype, record.rdata.clone())).collect::<Vec<(u16, Vec<u8>)>>(), vec![(record_type::A, vec![10, 0, 0, 25])]);
+			}
+			response => panic!("{:?}", response),
+		}
+		server.join().unwrap();
+	}
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').map(String::from).collect();
+	}
+	
+	#[test]
+	fn test_aname_flattening() {
+		let resolver = MockResolver::new();
+		let target = |qtype: u16| Question { qname: name("target.aname-flattening.test"), qtype, qclass: 1 };
+		// the target is itself a CNAME, which is followed by the resolver
+		resolver.answer(target(record_type::A), vec![
+			Resource { rname: name("target.aname-flattening.test"), rtype: record_type::CNAME, rclass: 1, ttl: 300, rdata: protocol::serialize_name(["final", "aname-flattening", "test"].iter().copied()) },
+			Resource { rname: name("final.aname-flattening.test"), rtype: record_type::A, rclass: 1, ttl: 300, rdata: vec![192, 0, 2, 1] },
+		]);
+		resolver.reply(target(record_type::AAAA), Reply::Rcode(2));
+		let config = crate::config::parse("zones:\n  flat.example.com:\n    ANAME: target.aname-flattening.test.").unwrap();
+		let mut options = test_options();
+		options.resolver = vec![resolver.address];
+		let answer = |qtype: u16| match handle_dns(&Question { qname: name("flat.example.com"), qtype, qclass: 1 }, &options, &config) {
+			Response::Ok(answer, _, _) => answer,
+			response => panic!("unexpected response: {:?}", response),
+		};
+		
+		// only the address is kept, at the name asked for
+		assert_eq!(answer(record_type::A), vec![Resource { rname: name("flat.example.com"), rtype: record_type::A, rclass: 1, ttl: 300, rdata: vec![192, 0, 2, 1] }]);
+		// and nothing at all when the resolver fails
+		assert_eq!(answer(record_type::AAAA), vec![]);
+		assert_eq!(resolver.received(), vec![target(record_type::A), target(record_type::AAAA)]);
+	}
+	
+	#[test]
+	fn test_rns_host_lookup() {
+		let ns = |qtype: u16| Question { qname: name("ns.rns-host.test"), qtype, qclass: 1 };
+		let question = Question { qname: name("rns-host.test"), qtype: record_type::TXT, qclass: 1 };
+		let lookup = |external: &str| {
+			let resolver = MockResolver::new();
+			let rns = MockResolver::new();
+			resolver.answer(ns(record_type::A), vec![Resource { rname: name("ns.rns-host.test"), rtype: record_type::A, rclass: 1, ttl: 300, rdata: vec![127, 0, 0, 1] }]);
+			resolver.answer(ns(record_type::AAAA), vec![]);
+			rns.answer(question.clone(), vec![Resource { rname: name("rns-host.test"), rtype: record_type::TXT, rclass: 1, ttl: 300, rdata: protocol::serialize_txt("from rns") }]);
+			// nothing listens on the address configured here, so only the one from the resolver answers
+			let config = crate::config::parse(&format!(r"zones:
+  ns.rns-host.test:
+    A: {}
+  rns-host.test:
+    RNS: ns.rns-host.test:{}{}", if external.is_empty() { "127.0.0.1" } else { "127.0.0.2" }, rns.address.port(), external)).unwrap();
+			let mut options = test_options();
+			options.resolver = vec![resolver.address];
+			options.resolver_timeout = 200;
+			let rtypes = match handle_dns(&question, &options, &config) {
+				Response::Ok(answer, _, _) => answer.iter().map(|record| record.rtype).collect::<Vec<u16>>(),
+				response => panic!("unexpected response: {:?}", response),
+			};
+			(rtypes, resolver.received(), rns.received())
+		};
+		
+		// the host's addresses come from the zones here, and the resolver only for what they lack
+		assert_eq!(lookup(""), (vec![record_type::TXT], vec![ns(record_type::AAAA)], vec![question.clone()]));
+		// unless it's external, when the resolver is asked for all of them
+		assert_eq!(lookup(" external"), (vec![record_type::TXT], vec![ns(record_type::AAAA), ns(record_type::A)], vec![question.clone()]));
+	}
+	
+	#[test]
+	fn test_clamp_ttls() {
+		// answers A queries with a TTL depending on the name
+		let (udp, _tcp, address) = fake_resolver();
+		let server = thread::spawn(move || {
+			udp.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+			let mut buf = vec![0; 512];
+			while let Ok((size, src)) = udp.recv_from(&mut buf) {
+				let mut message = protocol::parse(&buf[..size]).unwrap();
+				message.header.qr = true;
+				let question = message.question[0].clone();
+				message.answer = vec![Resource {
+					ttl: if question.qname[0].starts_with("short") { 5 } else { 604800 },
+					rname: question.qname,
+					rtype: record_type::A,
+					rclass: 1,
+					rdata: vec![10, 0, 0, 1],
+				}];
+				udp.send_to(&protocol::serialize(&message, 512), src).unwrap();
+			}
+		});
+		
+		let config = crate::config::parse(&format!(r"min-ttl: 1m
+max-ttl: 1d
+zones:
+  short.example.com:
+    ANAME: short-clamp.upstream.test.
+  long.example.com:
+    ANAME: long-clamp.upstream.test.
+  custom.example.com:
+    ANAME:
+      - name: short-custom.upstream.test.
+        min-ttl: 30
+      - name: long-custom.upstream.test.
+        max-ttl: 1h
+  rns.example.com:
+    RNS: {}", address)).unwrap();
+		let mut options = test_options();
+		options.resolver = vec![address];
+		let ttls = |name: &str| match handle_dns(&Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype: record_type::A,
+			qclass: 1,
+		}, &options, &config) {
+			Response::Ok(answer, _, _) => answer.iter().map(|record| record.ttl).collect::<Vec<u32>>(),
+			response => panic!("unexpected response: {:?}", response),
+		};
+		
+		assert_eq!(ttls("short.example.com"), vec![60]);
+		assert_eq!(ttls("long.example.com"), vec![86400]);
+		assert_eq!(ttls("custom.example.com"), vec![30, 3600]);
+		assert_eq!(ttls("rns.example.com"), vec![86400]);
+		// cached answers are clamped again
+		assert_eq!(ttls("short.example.com"), vec![60]);
+		
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_forward() {
+		// answers TXT and MX queries, and NXDOMAIN for names starting with "missing"
+		let (udp, _tcp, upstream) = fake_resolver();
+		let count = Arc::new(AtomicUsize::new(0));
+		let server = {
+			let count = count.clone();
+			thread::spawn(move || {
+				udp.set_read_timeout(Some(Duration::from_millis(1000))).unwrap();
+				let mut buf = vec![0; 512];
+				while let Ok((size, src)) = udp.recv_from(&mut buf) {
+					count.fetch_add(1, Ordering::SeqCst);
+					let mut message = protocol::parse(&buf[..size]).unwrap();
+					message.header.qr = true;
+					let question = message.question[0].clone();
+					if question.qname[0].starts_with("missing") {
+						message.header.rcode = 3;
+					} else {
+						message.answer = vec![Resource {
+							rname: question.qname,
+							rtype: question.qtype,
+							rclass: 1,
+							ttl: 300,
+							rdata: match question.qtype {
+								record_type::MX => protocol::serialize_mx("mail.corp.internal", 10),
+								_ => protocol::serialize_txt("forwarded"),
+							},
+						}];
+					}
+					udp.send_to(&protocol::serialize(&message, 512), src).unwrap();
+				}
+			})
+		};
+		let (_dead_udp, _dead_tcp, dead) = fake_resolver();
+		
+		let config = crate::config::parse(&format!(r"zones:
+  corp.internal:
+    forward: {upstream}
+    TXT: local
+  '*.corp.internal':
+    forward only noaa: [{upstream}]
+    TXT: local
+  fallback.internal:
+    forward: {dead}
+    TXT: local
+  rns.internal:
+    RNS: {upstream}
+    TXT: local", upstream = upstream, dead = dead)).unwrap();
+		let mut options = test_options();
+		options.resolver_timeout = 200;
+		let answer = |name: &str, qtype: u16| match handle_dns(&Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype,
+			qclass: 1,
+		}, &options, &config) {
+			Response::Ok(answer, _, _) => answer.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>(),
+			response => panic!("unexpected response: {:?}", response),
+		};
+		
+		// every type goes to the forwarder, even when there are records here
+		assert_eq!(answer("corp.internal", record_type::TXT), vec![protocol::serialize_txt("forwarded")]);
+		assert_eq!(answer("corp.internal", record_type::MX), vec![protocol::serialize_mx("mail.corp.internal", 10)]);
+		assert_eq!(count.load(Ordering::SeqCst), 2);
+		
+		// without the only flag, the records here are used when the forwarder doesn't answer
+		assert_eq!(answer("fallback.internal", record_type::TXT), vec![protocol::serialize_txt("local")]);
+		// but with it, whatever the forwarder says goes
+		assert_eq!(handle_dns(&resolver_question("missing"), &options, &crate::config::parse(&format!(r"zones:
+  '*.example.com':
+    forward only: {}
+    A: 10.0.0.1", upstream)).unwrap()), Response::NameError);
+		
+		// RNS is only asked when nothing else answers
+		assert_eq!(answer("rns.internal", record_type::TXT), vec![protocol::serialize_txt("local")]);
+		assert_eq!(count.load(Ordering::SeqCst), 3);
+		
+		// the AA bit can be left unset for forwarded zones
+		let aa = |qname: &str| {
+			let request = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+				qname: qname.split('.').map(|label| label.to_string()).collect(),
+				qtype: record_type::TXT,
+				qclass: 1,
+			}]), 512);
+			protocol::parse(&handle_request(request, &options, &config, Transport::Udp, test_src()).unwrap()).unwrap().header.aa
+		};
+		assert!(aa("corp.internal"));
+		assert!(!aa("www.corp.internal"));
+		
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_resolver_cache_file() {
+		let (address, count, server) = counting_resolver(3, vec![]);
+		let record = |ttl: u32| Resource {
+			rname: vec!["cached".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl,
+			rdata: vec![10, 0, 0, 9],
+		};
+		// as saved by a server that was stopped ten minutes ago
+		let then = SystemTime::now() - Duration::from_secs(10 * 60);
+		let mut cache = Cache::new();
+		cache.insert((vec![address], resolver_question("cached")), Response::Ok(vec![record(3600)], vec![], vec![]), 3600, then, 10);
+		cache.insert((vec![address], resolver_question("expired")), Response::Ok(vec![record(300)], vec![], vec![]), 300, then, 10);
+		let path = env::temp_dir().join(format!("tacodns-test-{}-cache.json", process::id()));
+		fs::write(&path, cache.save(then)).unwrap();
+		load_resolver_cache(&path, &test_options());
+		
+		// what's still valid is answered without asking, counted down by the time it was saved for
+		assert_eq!(resolver_lookup(resolver_question("cached"), &[address], &test_options()), Response::Ok(vec![record(3000)], vec![], vec![]));
+		assert_eq!(count.load(Ordering::SeqCst), 0);
+		assert_eq!(resolver_lookup(resolver_question("expired"), &[address], &test_options()), Response::NameError);
+		assert_eq!(count.load(Ordering::SeqCst), 1);
+		
+		// and saved again along with what's been looked up since
+		save_resolver_cache(&path);
+		let mut saved: Cache<(Vec<SocketAddr>, Question)> = Cache::new();
+		saved.load(&fs::read(&path).unwrap(), SystemTime::now(), 10000).unwrap();
+		assert!(saved.get(&(vec![address], resolver_question("cached")), SystemTime::now()).is_some());
+		assert_eq!(saved.get(&(vec![address], resolver_question("expired")), SystemTime::now()), Some(Response::NameError));
+		
+		// a corrupted file is only warned about
+		fs::write(&path, b"[{\"key\": ").unwrap();
+		load_resolver_cache(&path, &test_options());
+		fs::remove_file(&path).unwrap();
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_resolver_negative_cache() {
+		let soa = Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::SOA,
+			rclass: 1,
+			ttl: 3600,
+			rdata: protocol::serialize_soa("ns.example.com", "admin.example.com", 1, 2, 3, 4, 300),
+		};
+		
+		// NXDOMAIN is served from the cache the second time
+		let (address, count, server) = counting_resolver(3, vec![soa.clone()]);
+		assert_eq!(resolver_lookup(resolver_question("nxdomain"), &[address], &test_options()), Response::NameError);
+		assert_eq!(resolver_lookup(resolver_question("nxdomain"), &[address], &test_options()), Response::NameError);
+		assert_eq!(count.load(Ordering::SeqCst), 1);
+		// but other names aren't
+		assert_eq!(resolver_lookup(resolver_question("nxdomain2"), &[address], &test_options()), Response::NameError);
+		assert_eq!(count.load(Ordering::SeqCst), 2);
+		server.join().unwrap();
+		
+		// so is an empty NOERROR, with the SOA intact
+		let (address, count, server) = counting_resolver(0, vec![soa.clone()]);
+		assert_eq!(resolver_lookup(resolver_question("nodata"), &[address], &test_options()), Response::Ok(vec![], vec![soa.clone()], vec![]));
+		assert_eq!(resolver_lookup(resolver_question("nodata"), &[address], &test_options()), Response::Ok(vec![], vec![soa], vec![]));
+		assert_eq!(count.load(Ordering::SeqCst), 1);
+		server.join().unwrap();
+		
+		// and SERVFAIL, briefly
+		let (address, count, server) = counting_resolver(2, vec![]);
+		assert_eq!(resolver_lookup(resolver_question("servfail"), &[address], &test_options()), Response::ServerFailure);
+		assert_eq!(resolver_lookup(resolver_question("servfail"), &[address], &test_options()), Response::ServerFailure);
+		assert_eq!(count.load(Ordering::SeqCst), 1);
+		server.join().unwrap();
+	}
+	
+	#[test]
+	fn test_loops() {
+		let zone = |name: &str, records: Records| Zone {
+			matchers: vec![vec![Label::Basic(name.to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
+			excludes: vec![],
+			records,
+		};
+		let config = Config {
 			ttl: Duration::from_secs(1800),
 			nttl: Duration::from_secs(15),
 			serial: 0,
-			zones: vec![Zone {
-				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![ARecord {
-						ttl: Duration::from_secs(100),
-						ip4addr: "127.0.0.1".parse().unwrap(),
-					}],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}, Zone {
-				matchers: vec![vec![Label::Basic("www".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![CnameRecord {
-						ttl: Duration::from_secs(100),
-						name: "example.com".to_string(),
-					}],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}, Zone {
-				matchers: vec![vec![Label::Basic("www2".to_string()), Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![CnameRecord {
-						ttl: Duration::from_secs(100),
-						name: "www.example.com".to_string(),
-					}],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}],
-		}), (vec![Resource {
-			rname: vec!["www2".to_string(), "example".to_string(), "com".to_string()],
-			rtype: record_type::CNAME,
+			min_ttl: None,
+			max_ttl: None,
+			allow_transfer: vec![],
+			acl: vec![],
+			acl_local: true,
+			dnssec: None,
+			tsig_keys: vec![],
+			notify: vec![],
+			networks: HashMap::new(),
+			check_interval: Duration::from_secs(10),
+			check_timeout: Duration::from_secs(2),
+			minimal_responses: false,
+			fill_authority: true,
+			resolve_external_cnames: false,
+			catch_all: false,
+			prefetch: false,
+			default_ns: vec![],
+			default_soa: None,
+			index: ZoneIndex::default(),
+			zones: vec![zone("a", Records {
+				cname: vec![CnameRecord {
+					ttl: Duration::from_secs(100),
+					name: "b.example.com.".to_string(),
+					metadata: None,
+				}],
+				..Default::default()
+			}), zone("b", Records {
+				cname: vec![CnameRecord {
+					ttl: Duration::from_secs(100),
+					name: "a.example.com.".to_string(),
+					metadata: None,
+				}],
+				..Default::default()
+			}), zone("www", Records {
+				aname: vec![AnameRecord {
+					ttl: Duration::from_secs(100),
+					name: "www.example.com.".to_string(),
+					min_ttl: None,
+					max_ttl: None,
+					metadata: None,
+				}],
+				..Default::default()
+			})],
+		};
+		let question = |name: &str| Question {
+			qname: vec![name.to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		};
+		
+		assert_eq!(handle_dns(&question("a"), &test_options(), &config), Response::ServerFailure);
+		assert_eq!(handle_dns(&question("www"), &test_options(), &config), Response::ServerFailure);
+		
+		let response = query(question("b"), &config);
+		assert_eq!(response.header.rcode, 2);
+		assert!(response.answer.is_empty());
+	}
+	
+	#[test]
+	fn test_zone_ttl() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    ttl: 5m
+    A: 10.10.10.10").unwrap();
+		let response = query(Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}, &config);
+		assert_eq!(response.answer.len(), 1);
+		assert_eq!(response.answer[0].ttl, 5 * 60);
+	}
+	
+	#[test]
+	fn test_ttl_limits() {
+		let config = crate::config::parse(r"nttl: 100000w
+zones:
+  example.com:
+    A: 10.10.10.10 100000w
+  zero.example.com:
+    CNAME: example.com. 0
+  soa.example.com:
+    SOA: {mname: ns1.example.com., rname: hostmaster.example.com., refresh: 100000w, retry: 100000w, expire: 100000w}").unwrap();
+		let ttls = |name: &str, qtype: u16| {
+			let response = query(Question { qname: name.split('.').map(String::from).collect(), qtype, qclass: 1 }, &config);
+			// the SOA of a negative answer, otherwise the answer
+			let records = if response.answer.is_empty() { &response.authority } else { &response.answer };
+			records.iter().map(|record| (record.rtype, record.ttl)).collect::<Vec<(u16, u32)>>()
+		};
+		// cut down to the most a TTL can be, rather than wrapping around
+		assert_eq!(ttls("example.com", record_type::A), vec![(record_type::A, 2147483647)]);
+		assert_eq!(ttls("example.com", record_type::MX), vec![(record_type::SOA, 2147483647)]);
+		// while 0 is sent as it is
+		assert_eq!(ttls("zero.example.com", record_type::A), vec![(record_type::CNAME, 0), (record_type::A, 2147483647)]);
+		// as are the timers of an SOA
+		let soa = query(Question { qname: vec!["soa".to_string(), "example".to_string(), "com".to_string()], qtype: record_type::SOA, qclass: 1 }, &config).answer.remove(0);
+		let timers = &soa.rdata[soa.rdata.len() - 16..soa.rdata.len() - 4];
+		assert_eq!(timers, [[0x7f, 0xff, 0xff, 0xff]; 3].concat());
+	}
+	
+	#[test]
+	fn test_rotate() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    A rotate: [10.0.0.1, 10.0.0.2, 10.0.0.3]
+    AAAA: ['::1', '::2']").unwrap();
+		let first = |qtype: u16| match handle_dns(&Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype,
+			qclass: 1,
+		}, &test_options(), &config) {
+			Response::Ok(answer, _, _) => {
+				assert_eq!(answer.len(), if qtype == record_type::A { 3 } else { 2 });
+				answer[0].rdata.clone()
+			}
+			response => panic!("unexpected response: {:?}", response),
+		};
+		
+		// every address gets a turn at being first
+		let mut firsts: Vec<Vec<u8>> = (0..3).map(|_| first(record_type::A)).collect();
+		firsts.sort();
+		assert_eq!(firsts, vec![vec![10, 0, 0, 1], vec![10, 0, 0, 2], vec![10, 0, 0, 3]]);
+		
+		// without the flag the order is kept
+		for _ in 0..3 {
+			assert_eq!(first(record_type::AAAA), "::1".parse::<std::net::Ipv6Addr>().unwrap().octets().to_vec());
+		}
+	}
+	
+	#[test]
+	fn test_any() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    A: 10.10.10.10
+    MX: mail.example.com. 5m
+    TXT: hello
+  www.example.com:
+    CNAME: example.com.").unwrap();
+		let any = |name: &[&str]| handle_dns(&Question {
+			qname: name.iter().map(|label| label.to_string()).collect(),
+			qtype: record_type::ANY,
+			qclass: 1,
+		}, &test_options(), &config);
+		
+		assert_eq!(any(&["example", "com"]), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::A,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![3, 'w' as u8, 'w' as u8, 'w' as u8, 7, 'e' as u8, 'x' as u8, 'a' as u8, 'm' as u8, 'p' as u8, 'l' as u8, 'e' as u8, 3, 'c' as u8, 'o' as u8, 'm' as u8, 0],
+			ttl: 1800,
+			rdata: vec![10, 10, 10, 10],
 		}, Resource {
-			rname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
-			rtype: record_type::CNAME,
+			rname: vec!["example".to_string(), "com".to_string()],
+			rtype: record_type::MX,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![7, 'e' as u8, 'x' as u8, 'a' as u8, 'm' as u8, 'p' as u8, 'l' as u8, 'e' as u8, 3, 'c' as u8, 'o' as u8, 'm' as u8, 0],
+			ttl: 300,
+			rdata: protocol::serialize_mx("mail.example.com", 10),
 		}, Resource {
 			rname: vec!["example".to_string(), "com".to_string()],
-			rtype: record_type::A,
+			rtype: record_type::TXT,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![127, 0, 0, 1],
+			ttl: 1800,
+			rdata: protocol::serialize_txt("hello"),
+		}], vec![], vec![]));
+		
+		// the CNAME isn't followed
+		assert_eq!(any(&["www", "example", "com"]), Response::Ok(vec![Resource {
+			rname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::CNAME,
+			rclass: 1,
+			ttl: 1800,
+			rdata: protocol::serialize_name(vec!["example", "com"]),
 		}], vec![], vec![]));
 	}
 	
 	#[test]
-	fn test_mx() {
-		assert_eq!(handle_dns(&Question {
-			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::MX,
+	fn test_relative_names() {
+		let config = crate::config::parse(r"zones:
+  example.com:
+    MX: [mail, mail.example.net.]
+    NS: ns1.example.net.
+  www.example.com:
+    CNAME: web
+  '*.example.org':
+    MX: mail").unwrap();
+		let answer = |name: &str, qtype: u16| match handle_dns(&Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype,
 			qclass: 1,
-		}, &test_options(), &Config {
-			ttl: Duration::from_secs(1800),
-			nttl: Duration::from_secs(15),
-			serial: 0,
-			zones: vec![Zone {
-				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![MxRecord {
-						ttl: Duration::from_secs(100),
-						priority: 10,
-						host: "mail.example.com".to_string(),
-					}],
-					txt: vec![],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}],
-		}), (vec![Resource {
+		}, &test_options(), &config) {
+			Response::Ok(answer, _, _) => answer.into_iter().map(|record| record.rdata).collect::<Vec<Vec<u8>>>(),
+			response => panic!("unexpected response: {:?}", response),
+		};
+		
+		assert_eq!(answer("example.com", record_type::MX), vec![
+			protocol::serialize_mx("mail.example.com", 10),
+			protocol::serialize_mx("mail.example.net", 10),
+		]);
+		assert_eq!(answer("example.com", record_type::NS), vec![protocol::serialize_name(vec!["ns1", "example", "net"])]);
+		assert_eq!(answer("www.example.com", record_type::CNAME), vec![protocol::serialize_name(vec!["web", "www", "example", "com"])]);
+		// relative to the name queried, not the zone
+		assert_eq!(answer("foo.example.org", record_type::MX), vec![protocol::serialize_mx("mail.foo.example.org", 10)]);
+	}
+	
+	/// Records answered for a wildcard are at the name queried, in the case it was queried in, apart
+	/// from the CNAMEs of a chain, which keep their own names.
+	#[test]
+	fn test_wildcard_owner() {
+		let config = crate::config::parse(r"zones:
+  '*.example.com':
+    MX: mail
+    TXT: hello
+    SRV: 10 20 5060 sip
+  '*.flat.example.com':
+    ANAME: www.example.org.
+  '*.chain.example.com':
+    CNAME: www.example.org.
+  www.example.org:
+    CNAME: target.example.org.
+  target.example.org:
+    A: 10.0.0.1").unwrap();
+		let answer = |name: &str, qtype: u16| match handle_dns(&Question {
+			qname: name.split('.').map(String::from).collect(),
+			qtype,
+			qclass: class::IN,
+		}, &test_options(), &config) {
+			Response::Ok(answer, _, _) => answer.into_iter().map(|record| (record.rname.join("."), record.rtype)).collect::<Vec<(String, u16)>>(),
+			response => panic!("unexpected response {:?}", response),
+		};
+		
+		for qtype in &[record_type::MX, record_type::TXT, record_type::SRV] {
+			assert_eq!(answer("Foo.example.COM", *qtype), vec![("Foo.example.COM".to_string(), *qtype)]);
+		}
+		// flattened, so the CNAME at the target isn't answered
+		assert_eq!(answer("Foo.flat.example.com", record_type::A), vec![("Foo.flat.example.com".to_string(), record_type::A)]);
+		assert_eq!(answer("Foo.chain.example.com", record_type::A), vec![
+			("Foo.chain.example.com".to_string(), record_type::CNAME),
+			("www.example.org".to_string(), record_type::CNAME),
+			("target.example.org".to_string(), record_type::A),
+		]);
+	}
+	
+	#[test]
+	fn test_alias() {
+		let config = crate::config::parse(r"zones:
+  example.com,**.example.com:
+    ALIAS: target.cdn.net.
+    MX: mail.example.com.
+  target.cdn.net:
+    A: 10.0.0.1").unwrap();
+		let lookup = |name: &str, qtype: u16| handle_dns(&Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype,
+			qclass: 1,
+		}, &test_options(), &config);
+		let target = Resource {
+			rname: vec!["target".to_string(), "cdn".to_string(), "net".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 1800,
+			rdata: vec![10, 0, 0, 1],
+		};
+		
+		// flattened at the apex
+		assert_eq!(lookup("example.com", record_type::A), Response::Ok(vec![Resource {
+			rname: vec!["example".to_string(), "com".to_string()],
+			..target.clone()
+		}], vec![], vec![]));
+		assert_eq!(lookup("example.com", record_type::MX), Response::Ok(vec![Resource {
 			rname: vec!["example".to_string(), "com".to_string()],
 			rtype: record_type::MX,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![0, 10, 4, 'm' as u8, 'a' as u8, 'i' as u8, 'l' as u8, 7, 'e' as u8, 'x' as u8, 'a' as u8, 'm' as u8, 'p' as u8, 'l' as u8, 'e' as u8, 3, 'c' as u8, 'o' as u8, 'm' as u8, 0],
+			ttl: 1800,
+			rdata: protocol::serialize_mx("mail.example.com", 10),
 		}], vec![], vec![]));
+		
+		// a CNAME below it
+		assert_eq!(lookup("deep.example.com", record_type::A), Response::Ok(vec![Resource {
+			rname: vec!["deep".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::CNAME,
+			rclass: 1,
+			ttl: 1800,
+			rdata: protocol::serialize_name(vec!["target", "cdn", "net"]),
+		}, target], vec![], vec![]));
 	}
 	
 	#[test]
-	fn test_txt() {
-		assert_eq!(handle_dns(&Question {
-			qname: vec!["example".to_string(), "com".to_string()],
-			qtype: record_type::TXT,
+	fn test_idn() {
+		let config = crate::config::parse(r"zones:
+  münchen.example:
+    A: 10.0.0.1
+    CNAME: straße.example.
+  Straße.example:
+    MX: mail.bücher.example.").unwrap();
+		assert_eq!(config.zones[0].matchers, vec![vec![Label::Basic("xn--mnchen-3ya".to_string()), Label::Basic("example".to_string())]]);
+		
+		let lookup = |name: &str, qtype: u16| handle_dns(&Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype,
 			qclass: 1,
-		}, &test_options(), &Config {
-			ttl: Duration::from_secs(1800),
-			nttl: Duration::from_secs(15),
-			serial: 0,
-			zones: vec![Zone {
-				matchers: vec![vec![Label::Basic("example".to_string()), Label::Basic("com".to_string())]],
-				records: Records {
-					a: vec![],
-					aaaa: vec![],
-					ns: vec![],
-					cname: vec![],
-					aname: vec![],
-					mx: vec![],
-					txt: vec![TxtRecord {
-						ttl: Duration::from_secs(100),
-						data: "data content".to_string(),
-					}],
-					rns: vec![],
-					trpp: vec![],
-				},
-			}],
-		}), (vec![Resource {
-			rname: vec!["example".to_string(), "com".to_string()],
+		}, &test_options(), &config);
+		assert_eq!(lookup("xn--mnchen-3ya.example", record_type::CNAME), Response::Ok(vec![Resource {
+			rname: vec!["xn--mnchen-3ya".to_string(), "example".to_string()],
+			rtype: record_type::CNAME,
+			rclass: 1,
+			ttl: 1800,
+			rdata: protocol::serialize_name(vec!["xn--strae-oqa", "example"]),
+		}], vec![], vec![]));
+		assert_eq!(lookup("XN--MNCHEN-3YA.example", record_type::CNAME).rcode(), 0);
+		// ß isn't mapped to ss, as it's a distinct letter since IDNA 2008
+		assert_eq!(lookup("xn--strae-oqa.example", record_type::MX), Response::Ok(vec![Resource {
+			rname: vec!["xn--strae-oqa".to_string(), "example".to_string()],
+			rtype: record_type::MX,
+			rclass: 1,
+			ttl: 1800,
+			rdata: protocol::serialize_mx("mail.xn--bcher-kva.example", 10),
+		}], vec![], vec![]));
+		
+		// names have to be queried in punycode
+		let rcode = |buf: Vec<u8>| protocol::parse(&handle_request(buf, &test_options(), &config, Transport::Udp, test_src()).unwrap()).unwrap().header.rcode;
+		let query = |qname: Vec<&str>| protocol::serialize(&protocol::make_message_from_question(vec![Question {
+			qname: qname.into_iter().map(|label| label.to_string()).collect(),
+			qtype: record_type::A,
+			qclass: 1,
+		}]), 512);
+		assert_eq!(rcode(query(vec!["xn--mnchen-3ya", "example"])), 0);
+		assert_eq!(rcode(query(vec!["münchen", "example"])), 1);
+		assert_eq!(rcode(query(vec!["xn--mnchen-3ya.example"])), 1);
+		
+		// a 64 byte label doesn't fit the length of a label
+		let mut buf = query(vec!["example"]);
+		buf.truncate(12);
+		buf.push(64);
+		buf.extend_from_slice(&[b'a'; 64]);
+		buf.extend_from_slice(&[0, 0, 1, 0, 1]);
+		assert_eq!(rcode(buf), 1);
+	}
+	
+	#[test]
+	fn test_inherit() {
+		let config = crate::config::parse(r"zones:
+  example.com inherit:
+    A: 10.0.0.1
+    TXT: hello
+    MX: mail.example.com.
+  www.example.com:
+    A: 10.0.0.2").unwrap();
+		let lookup = |name: &str, qtype: u16| handle_dns(&Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype,
+			qclass: 1,
+		}, &test_options(), &config);
+		
+		assert_eq!(lookup("foo.bar.example.com", record_type::A), Response::Ok(vec![Resource {
+			rname: vec!["foo".to_string(), "bar".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 1800,
+			rdata: vec![10, 0, 0, 1],
+		}], vec![], vec![]));
+		assert_eq!(lookup("foo.example.com", record_type::TXT), Response::Ok(vec![Resource {
+			rname: vec!["foo".to_string(), "example".to_string(), "com".to_string()],
 			rtype: record_type::TXT,
 			rclass: 1,
-			ttl: 100,
-			rdata: vec![12, 'd' as u8, 'a' as u8, 't' as u8, 'a' as u8, ' ' as u8, 'c' as u8, 'o' as u8, 'n' as u8, 't' as u8, 'e' as u8, 'n' as u8, 't' as u8],
+			ttl: 1800,
+			rdata: protocol::serialize_txt("hello"),
+		}], vec![], vec![]));
+		
+		// other types aren't inherited, but the name still exists
+		assert_eq!(lookup("foo.example.com", record_type::MX), Response::Ok(vec![], vec![], vec![]));
+		
+		// configured zones still win
+		assert_eq!(lookup("www.example.com", record_type::A), Response::Ok(vec![Resource {
+			rname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 1800,
+			rdata: vec![10, 0, 0, 2],
 		}], vec![], vec![]));
+		
+		assert_eq!(lookup("foo.example.org", record_type::A), Response::Refused);
+	}
+	
+	#[test]
+	fn test_serve() {
+		let mut options = test_options();
+		options.threads = 2;
+		let server = serve(options, soa_config()).unwrap();
+		assert_ne!(server.addresses[0].port(), 0);
+		
+		let mut message = protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.header.id = 42;
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		socket.send_to(&protocol::serialize(&message, 512), server.addresses[0]).unwrap();
+		let mut buf = vec![0; 512];
+		let size = socket.recv(&mut buf).unwrap();
+		let response = protocol::parse(&buf[..size]).unwrap();
+		assert_eq!(response.header.id, 42);
+		assert_eq!(response.answer[0].rdata, vec![10, 10, 10, 10]);
+		
+		let address = server.addresses[0];
+		server.shutdown_handle().shutdown();
+		server.join();
+		
+		// the sockets are closed, so the port can be used again
+		UdpSocket::bind(address).unwrap();
+		TcpListener::bind(address).unwrap();
+	}
+	
+	#[test]
+	fn test_serve_addresses() {
+		let options = Options {
+			listen: vec!["127.0.0.1:0".parse().unwrap(), "127.0.0.1".parse().unwrap()],
+			threads: 2,
+			..test_options()
+		};
+		let server = serve(options, soa_config()).unwrap();
+		assert_eq!(server.addresses.len(), 2);
+		assert_ne!(server.addresses[0], server.addresses[1]);
+		
+		let message = protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		for address in &server.addresses {
+			let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+			socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+			socket.send_to(&protocol::serialize(&message, 512), address).unwrap();
+			let mut buf = vec![0; 512];
+			let size = socket.recv(&mut buf).unwrap();
+			assert_eq!(protocol::parse(&buf[..size]).unwrap().answer[0].rdata, vec![10, 10, 10, 10]);
+			
+			let mut stream = TcpStream::connect(address).unwrap();
+			stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+			write_tcp_message(&mut stream, &protocol::serialize(&message, u16::MAX)).unwrap();
+			assert_eq!(protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap().answer[0].rdata, vec![10, 10, 10, 10]);
+		}
+		
+		// a port that's taken is reported with the address
+		let taken = Options { listen: vec![ListenAddress::Socket(server.addresses[1])], ..test_options() };
+		let error = serve(taken, soa_config()).err().unwrap();
+		assert!(error.to_string().starts_with(&format!("can't listen on {}: ", server.addresses[1])));
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	#[test]
+	fn test_serve_dual_stack() {
+		let options = Options { listen: vec!["0.0.0.0".parse().unwrap()], threads: 2, ..test_options() };
+		let server = serve(options, soa_config()).unwrap();
+		assert!(server.addresses[0].is_ipv4());
+		// not every sandbox has IPv6
+		if server.addresses.len() == 2 {
+			assert!(server.addresses[1].is_ipv6());
+		}
+		
+		let message = protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		for (address, loopback) in server.addresses.iter().zip(&["127.0.0.1", "::1"]) {
+			let address = SocketAddr::new(loopback.parse().unwrap(), address.port());
+			let socket = UdpSocket::bind(SocketAddr::new(address.ip(), 0)).unwrap();
+			socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+			socket.send_to(&protocol::serialize(&message, 512), address).unwrap();
+			let mut buf = vec![0; 512];
+			let (size, from) = socket.recv_from(&mut buf).unwrap();
+			assert_eq!(from, address);
+			assert_eq!(protocol::parse(&buf[..size]).unwrap().answer[0].rdata, vec![10, 10, 10, 10]);
+			
+			let mut stream = TcpStream::connect(address).unwrap();
+			stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+			write_tcp_message(&mut stream, &protocol::serialize(&message, u16::MAX)).unwrap();
+			assert_eq!(protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap().answer[0].rdata, vec![10, 10, 10, 10]);
+		}
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	#[test]
+	fn test_worker_panics() {
+		let options = Options { threads: 1, ..test_options() };
+		let server = serve(options, soa_config()).unwrap();
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let query = |name: &str| protocol::serialize(&protocol::make_message_from_question(vec![Question {
+			qname: name.split('.').map(|label| label.to_string()).collect(),
+			qtype: record_type::A,
+			qclass: 1,
+		}]), 512);
+		
+		// more than there are workers, each of which goes unanswered
+		for _ in 0..3 {
+			socket.send_to(&query("panic.test"), server.addresses[0]).unwrap();
+		}
+		socket.send_to(&query("example.com"), server.addresses[0]).unwrap();
+		let mut buf = vec![0; 512];
+		let size = socket.recv(&mut buf).unwrap();
+		assert_eq!(protocol::parse(&buf[..size]).unwrap().answer[0].rdata, vec![10, 10, 10, 10]);
+		assert_eq!(server.pool.panics(), 3);
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	#[test]
+	fn test_tcp_limits() {
+		let mut options = test_options();
+		options.threads = 1;
+		options.tcp_read_timeout = 1;
+		options.max_tcp_conns = 2;
+		let server = serve(options, soa_config()).unwrap();
+		
+		// sends nothing, not even the length
+		let mut idle = TcpStream::connect(server.addresses[0]).unwrap();
+		idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let instant = Instant::now();
+		
+		let mut stream = TcpStream::connect(server.addresses[0]).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let mut request = protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::SOA,
+			qclass: 1,
+		}]);
+		request.header.id = 42;
+		write_tcp_message(&mut stream, &protocol::serialize(&request, u16::MAX)).unwrap();
+		let response = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+		assert_eq!(response.header.id, 42);
+		assert_eq!(response.answer.len(), 1);
+		
+		// both connections are still open, so a third is closed straight away
+		let mut rejected = TcpStream::connect(server.addresses[0]).unwrap();
+		rejected.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		assert!(matches!(rejected.read(&mut [0; 2]), Ok(0) | Err(_)));
+		assert!(instant.elapsed() < Duration::from_secs(1));
+		
+		assert!(matches!(idle.read(&mut [0; 2]), Ok(0) | Err(_)));
+		assert!(instant.elapsed() >= Duration::from_secs(1));
+		assert!(instant.elapsed() < Duration::from_secs(3));
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	fn http_get(address: SocketAddr, path: &str) -> String {
+		let mut stream = TcpStream::connect(address).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+		write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		return response;
+	}
+	
+	#[test]
+	fn test_health() {
+		let mut options = test_options();
+		options.threads = 1;
+		options.health_port = Some(0);
+		options.health_probe = Some("Example.com.".to_string());
+		let server = serve(options, soa_config()).unwrap();
+		let health_address = server.health_address.unwrap();
+		assert_ne!(health_address.port(), 0);
+		
+		assert!(http_get(health_address, "/healthz").starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(http_get(health_address, "/readyz").starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(http_get(health_address, "/metrics").starts_with("HTTP/1.1 404 Not Found\r\n"));
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+		
+		// the health socket is closed too
+		TcpListener::bind(health_address).unwrap();
+	}
+	
+	#[test]
+	fn test_health_probe_fails() {
+		let mut options = test_options();
+		options.threads = 1;
+		options.health_port = Some(0);
+		options.health_probe = Some("foo.example.org".to_string());
+		let server = serve(options, soa_config()).unwrap();
+		let health_address = server.health_address.unwrap();
+		
+		// still alive, just not ready
+		assert!(http_get(health_address, "/healthz").starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(http_get(health_address, "/readyz").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	#[test]
+	fn test_api() {
+		env::set_var("TACODNS_TEST_API_TOKEN", "secret");
+		let mut options = test_options();
+		options.threads = 1;
+		options.api_port = Some(0);
+		options.api_token_env = "TACODNS_TEST_API_TOKEN".to_string();
+		let server = serve(options, soa_config()).unwrap();
+		let api_address = server.api_address.unwrap();
+		
+		let request = |token: &str| {
+			let mut stream = TcpStream::connect(api_address).unwrap();
+			stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+			let body = r#"["10.0.0.1"]"#;
+			write!(stream, "PUT /zones/www.example.com/records/A HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}", token, body.len(), body).unwrap();
+			let mut response = String::new();
+			stream.read_to_string(&mut response).unwrap();
+			response
+		};
+		assert!(request("wrong").starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+		assert!(request("secret").starts_with("HTTP/1.1 200 OK\r\n"));
+		
+		// the new zone is answered straight away
+		let message = protocol::make_message_from_question(vec![Question {
+			qname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		socket.send_to(&protocol::serialize(&message, 512), server.addresses[0]).unwrap();
+		let mut buf = vec![0; 512];
+		let size = socket.recv(&mut buf).unwrap();
+		let response = protocol::parse(&buf[..size]).unwrap();
+		assert_eq!(response.answer[0].rdata, vec![10, 0, 0, 1]);
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+		
+		// the API socket is closed too
+		TcpListener::bind(api_address).unwrap();
+	}
+	
+	#[test]
+	fn test_api_requires_token() {
+		let mut options = test_options();
+		options.api_port = Some(0);
+		options.api_token_env = "TACODNS_TEST_UNSET_API_TOKEN".to_string();
+		let error = serve(options, soa_config()).err().unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+	}
+	
+	/// Run with `cargo test --release -- --ignored test_udp_load` to check that UDP requests aren't
+	/// dropped under load.
+	#[test]
+	#[ignore]
+	fn test_udp_load() {
+		const QUERIES: u16 = 10000;
+		const IN_FLIGHT: u16 = 100;
+		let mut options = test_options();
+		options.threads = 4;
+		// all from the one client
+		options.max_client_jobs = IN_FLIGHT as usize;
+		let server = serve(options, soa_config()).unwrap();
+		let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+		client.connect(server.addresses[0]).unwrap();
+		client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		
+		let send = |id: u16| {
+			let mut request = protocol::make_message_from_question(vec![Question {
+				qname: vec!["example".to_string(), "com".to_string()],
+				qtype: record_type::SOA,
+				qclass: 1,
+			}]);
+			request.header.id = id;
+			client.send(&protocol::serialize(&request, 512)).unwrap();
+		};
+		// keep a window of requests in flight, so what's measured is the server rather than the
+		// socket buffers overflowing
+		for id in 0..IN_FLIGHT {
+			send(id);
+		}
+		let mut answered = vec![false; QUERIES as usize];
+		let instant = Instant::now();
+		let mut buf = [0; 512];
+		for received in 0..QUERIES {
+			let size = client.recv(&mut buf).expect("response dropped");
+			let response = protocol::parse(&buf[..size]).unwrap();
+			assert!(!answered[response.header.id as usize]);
+			answered[response.header.id as usize] = true;
+			if received + IN_FLIGHT < QUERIES {
+				send(received + IN_FLIGHT);
+			}
+		}
+		println!("{} queries answered in {:?}", QUERIES, instant.elapsed());
+		assert!(answered.iter().all(|answered| *answered));
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	#[test]
+	fn test_client_slot() {
+		let jobs = Arc::new(Mutex::new(HashMap::new()));
+		let client: IpAddr = "192.0.2.1".parse().unwrap();
+		let first = ClientSlot::take(&jobs, client, 2).unwrap();
+		let second = ClientSlot::take(&jobs, client, 2).unwrap();
+		assert!(ClientSlot::take(&jobs, client, 2).is_none());
+		// other clients have slots of their own
+		let other = ClientSlot::take(&jobs, "192.0.2.2".parse().unwrap(), 2).unwrap();
+		
+		drop(first);
+		let third = ClientSlot::take(&jobs, client, 2).unwrap();
+		// a job that panics still gives its slot back
+		assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+			let _slot = second;
+			panic!("test panic");
+		})).is_err());
+		drop((third, other));
+		assert!(jobs.lock().unwrap().is_empty());
+	}
+	
+	#[test]
+	fn test_client_fairness() {
+		// every query for the forwarded zone waits out the resolver timeout
+		let resolver = MockResolver::new();
+		resolver.reply_otherwise(Reply::Timeout);
+		let config = crate::config::parse(&format!(r"zones:
+  example.com:
+    A: 10.10.10.10
+  slow.test:
+    forward only: {}", resolver.address)).unwrap();
+		let mut options = test_options();
+		options.threads = 12;
+		options.resolver_timeout = 300;
+		let server = serve(options, config).unwrap();
+		
+		let query = |name: String, id: u16| {
+			let mut request = protocol::make_message_from_question(vec![Question {
+				qname: name.split('.').map(|label| label.to_string()).collect(),
+				qtype: record_type::A,
+				qclass: 1,
+			}]);
+			request.header.id = id;
+			return protocol::serialize(&request, 512);
+		};
+		// each name is different, so none are answered from the cache
+		let flooder = UdpSocket::bind("127.0.0.1:0").unwrap();
+		for id in 0..1000 {
+			flooder.send_to(&query(format!("{}.slow.test", id), id), server.addresses[0]).unwrap();
+		}
+		
+		// another loopback address, so a different client
+		let client = UdpSocket::bind("127.0.0.2:0").unwrap();
+		client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+		let mut buf = [0; 512];
+		for id in 0..10 {
+			// the flood can fill the socket's receive buffer, in which case the query is dropped
+			// before the server sees it, so it's sent again, as a client would
+			let (instant, size) = (0..5).find_map(|_| {
+				let instant = Instant::now();
+				client.send_to(&query("example.com".to_string(), id), server.addresses[0]).unwrap();
+				client.recv(&mut buf).ok().map(|size| (instant, size))
+			}).unwrap();
+			let response = protocol::parse(&buf[..size]).unwrap();
+			assert_eq!((response.header.id, response.answer[0].rdata.clone()), (id, vec![10, 10, 10, 10]));
+			// without the limit, this would wait behind hundreds of slow lookups
+			assert!(instant.elapsed() < Duration::from_millis(250), "took {:?}", instant.elapsed());
+		}
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+	}
+	
+	#[test]
+	fn test_unix() {
+		let path = env::temp_dir().join(format!("tacodns-test-{}.sock", process::id()));
+		let mut options = test_options();
+		options.threads = 1;
+		options.listen_unix = Some(path.to_str().unwrap().to_string());
+		options.unix_mode = Some(0o600);
+		let server = serve(options, soa_config()).unwrap();
+		assert_eq!(server.unix_path.as_ref(), Some(&path));
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+		
+		let mut stream = UnixStream::connect(&path).unwrap();
+		let mut request = protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::SOA,
+			qclass: 1,
+		}]);
+		request.header.id = 42;
+		write_tcp_message(&mut stream, &protocol::serialize(&request, u16::MAX)).unwrap();
+		let response = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+		assert_eq!(response.header.id, 42);
+		assert_eq!(response.answer.len(), 1);
+		drop(stream);
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+		assert!(!path.exists());
+	}
+	
+	#[test]
+	fn test_unix_stale_socket() {
+		let path = env::temp_dir().join(format!("tacodns-test-stale-{}.sock", process::id()));
+		drop(UnixListener::bind(&path).unwrap());
+		assert!(path.exists());
+		
+		let mut options = test_options();
+		options.threads = 1;
+		options.listen_unix = Some(path.to_str().unwrap().to_string());
+		let server = serve(options.clone(), soa_config()).unwrap();
+		// another server can't take over a socket that's in use
+		assert!(serve(options, soa_config()).is_err());
+		
+		server.shutdown_handle().shutdown();
+		server.join();
+		assert!(!path.exists());
+	}
+	
+	#[test]
+	fn test_health_bind_failure() {
+		let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut options = test_options();
+		options.threads = 1;
+		options.health_port = Some(taken.local_addr().unwrap().port());
+		assert!(serve(options, soa_config()).is_err());
+	}
+	
+	#[test]
+	fn test_tls() {
+		let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+		let cert_path = std::env::temp_dir().join(format!("tacodns-test-{}-cert.pem", std::process::id()));
+		let key_path = std::env::temp_dir().join(format!("tacodns-test-{}-key.pem", std::process::id()));
+		fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+		fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+		
+		let mut options = test_options();
+		options.threads = 1;
+		options.tls_port = Some(0);
+		options.tls_cert = Some(cert_path.to_str().unwrap().to_string());
+		options.tls_key = Some(key_path.to_str().unwrap().to_string());
+		let server = serve(options, soa_config()).unwrap();
+		fs::remove_file(&cert_path).unwrap();
+		fs::remove_file(&key_path).unwrap();
+		let tls_address = server.tls_address.unwrap();
+		
+		// a failed handshake just closes the connection
+		let mut stream = TcpStream::connect(tls_address).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		stream.write_all(&[0, 12, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]).unwrap();
+		let mut buf = vec![];
+		let _ = stream.read_to_end(&mut buf);
+		
+		let mut roots = rustls::RootCertStore::empty();
+		roots.add(&rustls::Certificate(cert.serialize_der().unwrap())).unwrap();
+		let client_config = rustls::ClientConfig::builder()
+			.with_safe_defaults()
+			.with_root_certificates(roots)
+			.with_no_client_auth();
+		let connection = rustls::ClientConnection::new(Arc::new(client_config), rustls::ServerName::try_from("localhost").unwrap()).unwrap();
+		let mut stream = rustls::StreamOwned::new(connection, TcpStream::connect(tls_address).unwrap());
+		
+		let mut message = protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.header.id = 853;
+		write_tcp_message(&mut stream, &protocol::serialize(&message, u16::MAX)).unwrap();
+		stream.flush().unwrap();
+		let response = protocol::parse(&read_tcp_message(&mut stream).unwrap()).unwrap();
+		assert_eq!(response.header.id, 853);
+		assert_eq!(response.answer[0].rdata, vec![10, 10, 10, 10]);
+		
+		server.shutdown_handle().shutdown();
+		server.join();
 	}
 }
@@ -0,0 +1,182 @@
+//! Outbound NOTIFY (https://tools.ietf.org/html/rfc1996), so that secondaries transfer a zone again
+//! as soon as it changes, rather than once its SOA says to refresh.
+//!
+//! Zones change at runtime through dynamic updates and the HTTP API, each of which bumps the serial
+//! of the config before notifying, so that secondaries see a newer SOA when they check.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::config::matching::plain_name;
+use crate::options::Options;
+use crate::server::{make_soa, nttl, zone_records};
+use crate::server::protocol::{self, class, Message, opcode, Question, record_type};
+
+/// How many times a NOTIFY is sent to a secondary that doesn't respond.
+const ATTEMPTS: u32 = 5;
+
+/// How long to wait for a secondary to respond before sending the NOTIFY again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Tells the secondaries of every zone with a plain name that `name` is in that it changed, in the
+/// background. The SOA sent along has the serial of `config`, which should already be bumped.
+pub fn notify(options: &Options, config: &Config, name: &[String]) {
+	let name: Vec<String> = name.iter().map(|label| label.to_lowercase()).collect();
+	for (zone_index, zone) in config.zones.iter().enumerate() {
+		for apex in zone.matchers.iter().filter_map(plain_name) {
+			if apex.len() > name.len() || name[name.len() - apex.len()..] != apex[..] { continue; }
+			let mut targets: Vec<SocketAddr> = config.notify.iter().chain(&zone.records.notify).cloned().collect();
+			targets.sort();
+			targets.dedup();
+			
+			let question = Question {
+				qname: apex,
+				qtype: record_type::SOA,
+				qclass: class::IN,
+			};
+			let soa = zone_records(zone, zone_index, &question, record_type::SOA, config).into_iter().next()
+				.unwrap_or_else(|| make_soa(&question, config, nttl(zone, config)));
+			for target in targets {
+				let mut request = protocol::make_message_from_question(vec![question.clone()]);
+				// randomly seeded, so responses are harder to spoof
+				request.header.id = RandomState::new().build_hasher().finish() as u16;
+				request.header.opcode = opcode::NOTIFY;
+				request.header.rd = false;
+				request.header.aa = true;
+				request.answer.push(soa.clone());
+				
+				let verbose = options.verbose;
+				let spawned = thread::Builder::new().name("NOTIFY".to_string()).spawn(move || {
+					match send(&request, target, RETRY_INTERVAL) {
+						Ok(()) => if verbose { println!("notified {} of a change to {:?}", target, request.question[0].qname); },
+						Err(error) => if verbose { println!("failed to notify {}: {:?}", target, error); },
+					}
+				});
+				if let Err(error) = spawned {
+					if options.verbose { println!("failed to spawn NOTIFY thread: {:?}", error); }
+				}
+			}
+		}
+	}
+}
+
+/// Sends a NOTIFY to a secondary until it responds, waiting `interval` between attempts.
+fn send(request: &Message, target: SocketAddr, interval: Duration) -> io::Result<()> {
+	let local_address: SocketAddr = if target.is_ipv4() {
+		(Ipv4Addr::UNSPECIFIED, 0).into()
+	} else {
+		(Ipv6Addr::UNSPECIFIED, 0).into()
+	};
+	let socket = UdpSocket::bind(local_address)?;
+	socket.connect(target)?;
+	let buf = protocol::serialize(request, 512);
+	
+	for _ in 0..ATTEMPTS {
+		socket.send(&buf)?;
+		let deadline = Instant::now() + interval;
+		loop {
+			let now = Instant::now();
+			if now >= deadline { break; }
+			socket.set_read_timeout(Some(deadline - now))?;
+			let mut response = vec![0; 512];
+			let size = match socket.recv(&mut response) {
+				Ok(size) => size,
+				Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut => break,
+				// the secondary isn't listening (yet), which is retried like no response
+				Err(ref error) if error.kind() == io::ErrorKind::ConnectionRefused => continue,
+				Err(error) => return Err(error),
+			};
+			response.truncate(size);
+			// ignore anything that isn't a response to our NOTIFY
+			match protocol::parse(&response) {
+				Ok(response) if response.header.qr && response.header.id == request.header.id && response.header.opcode == opcode::NOTIFY => {
+					return match response.header.rcode {
+						0 => Ok(()),
+						rcode => Err(io::Error::new(io::ErrorKind::InvalidData, format!("secondary responded with rcode {}", rcode))),
+					};
+				}
+				_ => continue,
+			}
+		}
+	}
+	return Err(io::Error::new(io::ErrorKind::TimedOut, "secondary didn't respond"));
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::UdpSocket;
+	use std::thread;
+	use std::time::Duration;
+	
+	use crate::config;
+	use crate::server::make_soa;
+	use crate::server::notify::{notify, send};
+	use crate::server::protocol::{self, class, Message, opcode, Question, record_type};
+	use crate::server::test::test_options;
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').map(str::to_string).collect();
+	}
+	
+	/// Receives a NOTIFY on the socket, responding to it if `respond` is set.
+	fn receive(secondary: &UdpSocket, respond: bool) -> Message {
+		let mut buf = vec![0; 512];
+		let (size, src) = secondary.recv_from(&mut buf).unwrap();
+		let request = protocol::parse(&buf[..size]).unwrap();
+		if respond {
+			let mut response = Message { header: request.header.clone(), question: request.question.clone(), ..Default::default() };
+			response.header.qr = true;
+			secondary.send_to(&protocol::serialize(&response, 512), src).unwrap();
+		}
+		return request;
+	}
+	
+	#[test]
+	fn test_notify() {
+		let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+		secondary.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let mut config = config::parse(&format!("
+zones:
+  example.com:
+    notify: {}
+    A: 10.0.0.1
+  example.org:
+    A: 10.0.0.2
+", secondary.local_addr().unwrap())).unwrap();
+		config.serial = 42;
+		
+		notify(&test_options(), &config, &name("WWW.example.com"));
+		let request = receive(&secondary, true);
+		assert_eq!(request.header.opcode, opcode::NOTIFY);
+		assert!(request.header.aa);
+		let question = Question { qname: name("example.com"), qtype: record_type::SOA, qclass: class::IN };
+		assert_eq!(request.question, vec![question.clone()]);
+		assert_eq!(request.answer, vec![make_soa(&question, &config, config.nttl)]);
+		
+		// zones without secondaries aren't notified
+		secondary.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+		notify(&test_options(), &config, &name("example.org"));
+		assert!(secondary.recv_from(&mut [0; 512]).is_err());
+	}
+	
+	#[test]
+	fn test_retry() {
+		let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+		secondary.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let target = secondary.local_addr().unwrap();
+		let mut request = protocol::make_message_from_question(vec![Question { qname: name("example.com"), qtype: record_type::SOA, qclass: class::IN }]);
+		request.header.id = 42;
+		request.header.opcode = opcode::NOTIFY;
+		let sender = thread::spawn(move || send(&request, target, Duration::from_millis(100)));
+		
+		let first = receive(&secondary, false);
+		let second = receive(&secondary, true);
+		assert_eq!(first, second);
+		assert!(sender.join().unwrap().is_ok());
+	}
+}
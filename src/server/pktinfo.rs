@@ -0,0 +1,280 @@
+//! Receiving and sending UDP packets along with the local address they were sent to, so that on a
+//! socket bound to an unspecified address (e.g. 0.0.0.0) on a host with several addresses, replies
+//! leave from the address the client expects them from.
+
+#[cfg(target_os = "linux")]
+pub use linux::{enable, recv_from, send_to};
+#[cfg(target_os = "linux")]
+pub(super) use linux::to_sockaddr;
+#[cfg(not(target_os = "linux"))]
+pub use other::{enable, recv_from, send_to};
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+	use std::io;
+	use std::net::{IpAddr, SocketAddr, UdpSocket};
+	
+	pub fn enable(_socket: &UdpSocket) -> io::Result<()> {
+		Ok(())
+	}
+	
+	pub fn recv_from(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+		let (size, src) = socket.recv_from(buf)?;
+		Ok((size, src, None))
+	}
+	
+	pub fn send_to(socket: &UdpSocket, buf: &[u8], dst: SocketAddr, _local: Option<IpAddr>) -> io::Result<usize> {
+		socket.send_to(buf, dst)
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::io;
+	use std::mem;
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+	use std::os::unix::io::AsRawFd;
+	use std::ptr;
+	
+	use libc::{c_int, c_void};
+	
+	/// Room for either kind of control message, aligned as `cmsghdr` requires.
+	type Control = [u64; 8];
+	
+	/// Asks the kernel to report the local address of every packet received on the socket.
+	pub fn enable(socket: &UdpSocket) -> io::Result<()> {
+		let (level, name) = match socket.local_addr()? {
+			SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+			SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+		};
+		let on: c_int = 1;
+		let result = unsafe {
+			libc::setsockopt(socket.as_raw_fd(), level, name, &on as *const c_int as *const c_void, mem::size_of::<c_int>() as libc::socklen_t)
+		};
+		if result != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+	
+	/// Like `UdpSocket::recv_from`, additionally returning the local address the packet was sent to
+	/// if `enable` was called on the socket.
+	pub fn recv_from(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+		let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+		let mut iov = libc::iovec {
+			iov_base: buf.as_mut_ptr() as *mut c_void,
+			iov_len: buf.len(),
+		};
+		let mut control: Control = [0; 8];
+		let mut message: libc::msghdr = unsafe { mem::zeroed() };
+		message.msg_name = &mut name as *mut libc::sockaddr_storage as *mut c_void;
+		message.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+		message.msg_iov = &mut iov;
+		message.msg_iovlen = 1;
+		message.msg_control = control.as_mut_ptr() as *mut c_void;
+		message.msg_controllen = mem::size_of::<Control>() as _;
+		
+		let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, 0) };
+		if size < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let src = from_sockaddr(&name)?;
+		
+		let mut local = None;
+		unsafe {
+			let mut cmsg = libc::CMSG_FIRSTHDR(&message);
+			while !cmsg.is_null() {
+				match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+					(libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+						let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+						local = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(info.ipi_spec_dst.s_addr))));
+					}
+					(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+						let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo);
+						local = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+					}
+					_ => {}
+				}
+				cmsg = libc::CMSG_NXTHDR(&message, cmsg);
+			}
+		}
+		Ok((size as usize, src, local))
+	}
+	
+	/// Like `UdpSocket::send_to`, sending from the given local address if there is one. A link-local
+	/// IPv6 destination is sent out of the interface its scope ID names, which is the one it was
+	/// received on.
+	pub fn send_to(socket: &UdpSocket, buf: &[u8], dst: SocketAddr, local: Option<IpAddr>) -> io::Result<usize> {
+		let local = match local {
+			Some(local) => local,
+			None => return socket.send_to(buf, dst),
+		};
+		
+		let (mut name, name_len) = to_sockaddr(dst);
+		let mut iov = libc::iovec {
+			iov_base: buf.as_ptr() as *mut c_void,
+			iov_len: buf.len(),
+		};
+		let mut control: Control = [0; 8];
+		let mut message: libc::msghdr = unsafe { mem::zeroed() };
+		message.msg_name = &mut name as *mut libc::sockaddr_storage as *mut c_void;
+		message.msg_namelen = name_len;
+		message.msg_iov = &mut iov;
+		message.msg_iovlen = 1;
+		message.msg_control = control.as_mut_ptr() as *mut c_void;
+		
+		unsafe {
+			match local {
+				IpAddr::V4(local) => {
+					message.msg_controllen = libc::CMSG_SPACE(mem::size_of::<libc::in_pktinfo>() as u32) as _;
+					let cmsg = libc::CMSG_FIRSTHDR(&message);
+					(*cmsg).cmsg_level = libc::IPPROTO_IP;
+					(*cmsg).cmsg_type = libc::IP_PKTINFO;
+					(*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::in_pktinfo>() as u32) as _;
+					ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, libc::in_pktinfo {
+						ipi_ifindex: 0,
+						ipi_spec_dst: libc::in_addr { s_addr: u32::from(local).to_be() },
+						ipi_addr: libc::in_addr { s_addr: 0 },
+					});
+				}
+				IpAddr::V6(local) => {
+					message.msg_controllen = libc::CMSG_SPACE(mem::size_of::<libc::in6_pktinfo>() as u32) as _;
+					let cmsg = libc::CMSG_FIRSTHDR(&message);
+					(*cmsg).cmsg_level = libc::IPPROTO_IPV6;
+					(*cmsg).cmsg_type = libc::IPV6_PKTINFO;
+					(*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::in6_pktinfo>() as u32) as _;
+					ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in6_pktinfo, libc::in6_pktinfo {
+						ipi6_addr: libc::in6_addr { s6_addr: local.octets() },
+						ipi6_ifindex: match dst {
+							SocketAddr::V6(dst) => dst.scope_id(),
+							SocketAddr::V4(_) => 0,
+						},
+					});
+				}
+			}
+		}
+		
+		let size = unsafe { libc::sendmsg(socket.as_raw_fd(), &message, 0) };
+		if size < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(size as usize)
+	}
+	
+	fn from_sockaddr(name: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+		match name.ss_family as c_int {
+			libc::AF_INET => {
+				let name = unsafe { &*(name as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+				Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(u32::from_be(name.sin_addr.s_addr)), u16::from_be(name.sin_port))))
+			}
+			libc::AF_INET6 => {
+				let name = unsafe { &*(name as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+				Ok(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(name.sin6_addr.s6_addr), u16::from_be(name.sin6_port), name.sin6_flowinfo, name.sin6_scope_id)))
+			}
+			family => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected address family {}", family))),
+		}
+	}
+	
+	pub fn to_sockaddr(address: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+		let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+		let len = match address {
+			SocketAddr::V4(address) => {
+				let name = unsafe { &mut *(&mut name as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+				name.sin_family = libc::AF_INET as libc::sa_family_t;
+				name.sin_port = address.port().to_be();
+				name.sin_addr = libc::in_addr { s_addr: u32::from(*address.ip()).to_be() };
+				mem::size_of::<libc::sockaddr_in>()
+			}
+			SocketAddr::V6(address) => {
+				let name = unsafe { &mut *(&mut name as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+				name.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+				name.sin6_port = address.port().to_be();
+				name.sin6_flowinfo = address.flowinfo();
+				name.sin6_addr = libc::in6_addr { s6_addr: address.ip().octets() };
+				name.sin6_scope_id = address.scope_id();
+				mem::size_of::<libc::sockaddr_in6>()
+			}
+		};
+		(name, len as libc::socklen_t)
+	}
+	
+	#[cfg(test)]
+	mod test {
+		use std::net::{IpAddr, SocketAddr, SocketAddrV6, UdpSocket};
+		use std::time::Duration;
+		
+		use crate::server::pktinfo::{enable, recv_from, send_to};
+		use crate::server::pktinfo::linux::{from_sockaddr, to_sockaddr};
+		
+		#[test]
+		fn test_local_address() {
+			let server = UdpSocket::bind("0.0.0.0:0").unwrap();
+			enable(&server).unwrap();
+			let port = server.local_addr().unwrap().port();
+			
+			// all of 127.0.0.0/8 is local on Linux, standing in for a host with several addresses
+			for local in &["127.0.0.1", "127.0.0.2"] {
+				let local: IpAddr = local.parse().unwrap();
+				let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+				client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+				client.send_to(b"ping", SocketAddr::new(local, port)).unwrap();
+				
+				let mut buf = [0; 16];
+				let (size, src, received_at) = recv_from(&server, &mut buf).unwrap();
+				assert_eq!(&buf[..size], b"ping");
+				assert_eq!(src, client.local_addr().unwrap());
+				assert_eq!(received_at, Some(local));
+				
+				send_to(&server, b"pong", src, received_at).unwrap();
+				let (size, reply_from) = client.recv_from(&mut buf).unwrap();
+				assert_eq!(&buf[..size], b"pong");
+				assert_eq!(reply_from, SocketAddr::new(local, port));
+			}
+		}
+		
+		#[test]
+		fn test_ipv6() {
+			// not every sandbox has IPv6
+			let server = match UdpSocket::bind("[::]:0") {
+				Ok(server) => server,
+				Err(_) => return,
+			};
+			let client = match UdpSocket::bind("[::1]:0") {
+				Ok(client) => client,
+				Err(_) => return,
+			};
+			enable(&server).unwrap();
+			let address: SocketAddr = format!("[::1]:{}", server.local_addr().unwrap().port()).parse().unwrap();
+			client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+			client.send_to(b"ping", address).unwrap();
+			
+			let mut buf = [0; 16];
+			let (size, src, received_at) = recv_from(&server, &mut buf).unwrap();
+			assert_eq!(&buf[..size], b"ping");
+			assert_eq!(received_at, Some(address.ip()));
+			send_to(&server, b"pong", src, received_at).unwrap();
+			let (_, reply_from) = client.recv_from(&mut buf).unwrap();
+			assert_eq!(reply_from, address);
+		}
+		
+		#[test]
+		fn test_scope_id() {
+			// a link-local address is only meaningful along with the interface it's on
+			let address = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 53, 0, 2));
+			assert_eq!(from_sockaddr(&to_sockaddr(address).0).unwrap(), address);
+		}
+		
+		#[test]
+		fn test_disabled() {
+			// without `enable` the local address is unknown, and replies are sent as usual
+			let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+			let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+			client.send_to(b"ping", server.local_addr().unwrap()).unwrap();
+			let mut buf = [0; 16];
+			let (_, src, received_at) = recv_from(&server, &mut buf).unwrap();
+			assert_eq!(received_at, None);
+			send_to(&server, b"pong", src, received_at).unwrap();
+			assert_eq!(client.recv_from(&mut buf).unwrap().1, server.local_addr().unwrap());
+		}
+	}
+}
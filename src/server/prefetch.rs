@@ -0,0 +1,201 @@
+//! Looking up the names the config points outside of itself before their cached answers run out,
+//! so that the queries flattening an ANAME don't wait on the resolver for them: the targets of ANAME
+//! and ALIAS records, and the names of forwarded zones. Turned on with `prefetch: true` or
+//! `--prefetch`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+use crate::config::matching::plain_name;
+use crate::options::Options;
+use crate::server::{matching_zones, resolver_fetch, RESOLVER_CACHE, ResolverCache, rewrite_xname, Shutdown};
+use crate::server::protocol::{Question, record_type};
+use crate::server::shared::SharedConfig;
+
+/// A question along with the servers it's asked of, as the resolver cache keeps it.
+type Target = (Vec<SocketAddr>, Question);
+
+/// How long a target whose answer couldn't be cached (e.g. as its TTL is 0) waits to be looked up
+/// again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The longest and shortest waits between rounds. The config is read again each round, so targets
+/// added through the API are picked up.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+const MIN_WAIT: Duration = Duration::from_millis(100);
+
+/// Looks up the targets in the config as they come due until the server is shut down, starting
+/// with all of them.
+pub fn run_periodically(options: &Options, config: &SharedConfig, shutdown: &Shutdown) {
+	let mut attempted = HashMap::new();
+	while !shutdown.is_shutdown() {
+		let targets = targets(&config.served(), options);
+		let wait = refresh_due(options, &RESOLVER_CACHE, targets, &mut attempted, SystemTime::now());
+		shutdown.wait(wait.max(MIN_WAIT));
+	}
+}
+
+/// Looks up the targets that are due in `cache` as of `now`, returning how long after it the next
+/// one is. `attempted` keeps when those that couldn't be cached were last looked up.
+fn refresh_due(options: &Options, cache: &ResolverCache, targets: HashSet<Target>, attempted: &mut HashMap<Target, SystemTime>, now: SystemTime) -> Duration {
+	attempted.retain(|target, _| targets.contains(target));
+	let mut next = now + MAX_WAIT;
+	for target in targets {
+		let lifetime = cache.lock().unwrap().lifetime(&target);
+		let mut due = match lifetime {
+			Some((cached, expires)) => refresh_time(&target, cached, expires),
+			None => attempted.get(&target).map(|at| *at + RETRY_INTERVAL).unwrap_or(now),
+		};
+		if due <= now {
+			if options.verbose { println!("prefetching {} {} from {:?}", target.1.qname.join("."), target.1.qtype, target.0); }
+			resolver_fetch(target.clone(), options, Duration::from_millis(options.resolver_timeout), cache, now);
+			let lifetime = cache.lock().unwrap().lifetime(&target);
+			due = match lifetime {
+				Some((cached, expires)) => refresh_time(&target, cached, expires),
+				None => {
+					attempted.insert(target, now);
+					now + RETRY_INTERVAL
+				}
+			};
+		}
+		next = next.min(due);
+	}
+	return next.duration_since(now).unwrap_or_default();
+}
+
+/// When a cached answer is looked up again: once a tenth of its TTL is left, and earlier by up to
+/// another twentieth of it, which differs from one target to the next so that targets cached at
+/// the same time aren't all looked up again at the same time.
+fn refresh_time(target: &Target, cached: SystemTime, expires: SystemTime) -> SystemTime {
+	let ttl = expires.duration_since(cached).unwrap_or_default();
+	let mut hasher = DefaultHasher::new();
+	target.hash(&mut hasher);
+	let jitter = (ttl / 20).mul_f64((hasher.finish() % 1000) as f64 / 1000.0);
+	return expires - ttl / 10 - jitter;
+}
+
+/// The A and AAAA questions asked elsewhere to answer the zones: for the targets of ANAME and
+/// ALIAS records outside every zone, of the resolvers, and for the plain names of forwarded zones,
+/// of their servers. Targets relative to their zone are only known for zones with plain names.
+fn targets(config: &Config, options: &Options) -> HashSet<Target> {
+	let mut targets = HashSet::new();
+	let mut add = |servers: &[SocketAddr], qname: Vec<String>| {
+		for &qtype in &[record_type::A, record_type::AAAA] {
+			targets.insert((servers.to_vec(), Question { qname: qname.clone(), qtype, qclass: 1 }));
+		}
+	};
+	for zone in &config.zones {
+		let names: Vec<Vec<String>> = zone.matchers.iter().filter_map(plain_name).collect();
+		let xnames = zone.records.aname.iter().map(|aname| &aname.name).chain(zone.records.alias.iter().map(|alias| &alias.name));
+		for xname in xnames {
+			let qnames = match xname.ends_with('.') {
+				true => vec![rewrite_xname(xname, &[])],
+				false => names.iter().map(|name| rewrite_xname(xname, name)).collect(),
+			};
+			for qname in qnames {
+				let qname: Vec<String> = qname.iter().map(|label| label.to_lowercase()).collect();
+				// targets in our own zones are answered from them
+				if matching_zones(config, &qname).is_empty() {
+					add(&options.resolver, qname);
+				}
+			}
+		}
+		if let Some(forward) = &zone.records.forward {
+			for name in &names {
+				add(&forward.servers, name.clone());
+			}
+		}
+	}
+	return targets;
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::HashMap;
+	use std::sync::Mutex;
+	use std::time::{Duration, SystemTime};
+	
+	use crate::config;
+	use crate::server::cache::Cache;
+	use crate::server::Response;
+	use crate::server::mock_resolver::MockResolver;
+	use crate::server::prefetch::{refresh_due, targets};
+	use crate::server::protocol::{Question, record_type, Resource};
+	use crate::server::test::test_options;
+	
+	#[test]
+	fn test_targets() {
+		let config = config::parse(r"zones:
+  example.com:
+    ANAME: target.example.net.
+  www.example.com:
+    ALIAS: www.example.net.
+  '*.example.com':
+    ANAME: internal.example.com.
+  internal.example.com:
+    A: 10.0.0.1
+  shop.example.com:
+    ANAME: cdn
+  forwarded.example.com:
+    forward: 192.0.2.53").unwrap();
+		let mut targets: Vec<(String, String, u16)> = targets(&config, &test_options()).into_iter()
+			.map(|(servers, question)| (format!("{:?}", servers), question.qname.join("."), question.qtype))
+			.collect();
+		targets.sort();
+		let target = |server: &str, name: &str, qtype: u16| (format!("[{}]", server), name.to_string(), qtype);
+		// the target in our own zones is left out, and the relative one is below its zone
+		assert_eq!(targets, vec![
+			target("127.0.0.53:53", "cdn.shop.example.com", record_type::A), target("127.0.0.53:53", "cdn.shop.example.com", record_type::AAAA),
+			target("127.0.0.53:53", "target.example.net", record_type::A), target("127.0.0.53:53", "target.example.net", record_type::AAAA),
+			target("127.0.0.53:53", "www.example.net", record_type::A), target("127.0.0.53:53", "www.example.net", record_type::AAAA),
+			target("192.0.2.53:53", "forwarded.example.com", record_type::A), target("192.0.2.53:53", "forwarded.example.com", record_type::AAAA),
+		]);
+	}
+	
+	#[test]
+	fn test_refresh_due() {
+		let resolver = MockResolver::new();
+		let question = Question {
+			qname: vec!["prefetch".to_string(), "example".to_string(), "net".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		};
+		resolver.answer(question.clone(), vec![Resource {
+			rname: question.qname.clone(),
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 20,
+			rdata: vec![192, 0, 2, 1],
+		}]);
+		let mut options = test_options();
+		options.resolver = vec![resolver.address];
+		let config = config::parse(r"zones:
+  prefetch.example.com:
+    ANAME: prefetch.example.net.").unwrap();
+		let cache = Mutex::new(Cache::new());
+		let lookups = || resolver.received().iter().filter(|received| **received == question).count();
+		let key = (options.resolver.clone(), question.clone());
+		let start = SystemTime::now();
+		
+		// everything is looked up to start with, and due again before it expires
+		let mut attempted = HashMap::new();
+		let wait = refresh_due(&options, &cache, targets(&config, &options), &mut attempted, start);
+		assert_eq!(lookups(), 1);
+		assert!(wait <= Duration::from_secs(18) && wait >= Duration::from_secs(17), "{:?}", wait);
+		
+		// and isn't looked up again until then
+		assert_eq!(refresh_due(&options, &cache, targets(&config, &options), &mut attempted, start + wait / 2), wait - wait / 2);
+		assert_eq!(lookups(), 1);
+		
+		// when it's cached afresh, so that it's still answered after the answer first cached would have expired
+		let refreshed = start + wait;
+		assert!(refresh_due(&options, &cache, targets(&config, &options), &mut attempted, refreshed) >= Duration::from_secs(17));
+		assert_eq!(lookups(), 2);
+		assert_eq!(cache.lock().unwrap().lifetime(&key), Some((refreshed, refreshed + Duration::from_secs(20))));
+		assert!(matches!(cache.lock().unwrap().get(&key, start + Duration::from_secs(25)), Some(Response::Ok(answer, _, _)) if answer.len() == 1));
+	}
+}
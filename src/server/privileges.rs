@@ -0,0 +1,247 @@
+//! Dropping root once every socket is bound, for `--user` and `--group`, so that the server can
+//! listen on port 53 without answering queries as root.
+
+use std::io;
+
+/// The system calls that dropping privileges makes, so that the order they're made in can be
+/// tested without being root.
+pub trait Syscalls {
+	/// The ID of a user and of their primary group.
+	fn user_ids(&self, name: &str) -> io::Result<(u32, u32)>;
+	fn group_id(&self, name: &str) -> io::Result<u32>;
+	/// Replaces the supplementary groups, which root usually has a few of, with just `gid`.
+	fn set_groups(&self, gid: u32) -> io::Result<()>;
+	fn set_gid(&self, gid: u32) -> io::Result<()>;
+	fn set_uid(&self, uid: u32) -> io::Result<()>;
+}
+
+/// Switches to `user` and `group`, or to the user's primary group if no group is given. The
+/// groups go first, as they can't be changed once the user isn't root, and any failure is
+/// returned rather than serving with some privileges left.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>, syscalls: &dyn Syscalls) -> io::Result<()> {
+	let (uid, user_gid) = match user {
+		Some(user) => {
+			let (uid, gid) = syscalls.user_ids(user)?;
+			(Some(uid), Some(gid))
+		}
+		None => (None, None),
+	};
+	let gid = match group {
+		Some(group) => Some(syscalls.group_id(group)?),
+		None => user_gid,
+	};
+	if let Some(gid) = gid {
+		syscalls.set_groups(gid)?;
+		syscalls.set_gid(gid)?;
+	}
+	if let Some(uid) = uid {
+		syscalls.set_uid(uid)?;
+	}
+	return Ok(());
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::Libc;
+#[cfg(not(target_os = "linux"))]
+pub use other::Libc;
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+	use std::io;
+	
+	use crate::server::privileges::Syscalls;
+	
+	pub struct Libc;
+	
+	fn unsupported() -> io::Error {
+		io::Error::new(io::ErrorKind::Other, "--user and --group are only supported on Linux")
+	}
+	
+	impl Syscalls for Libc {
+		fn user_ids(&self, _name: &str) -> io::Result<(u32, u32)> {
+			Err(unsupported())
+		}
+		
+		fn group_id(&self, _name: &str) -> io::Result<u32> {
+			Err(unsupported())
+		}
+		
+		fn set_groups(&self, _gid: u32) -> io::Result<()> {
+			Err(unsupported())
+		}
+		
+		fn set_gid(&self, _gid: u32) -> io::Result<()> {
+			Err(unsupported())
+		}
+		
+		fn set_uid(&self, _uid: u32) -> io::Result<()> {
+			Err(unsupported())
+		}
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::ffi::CString;
+	use std::io;
+	use std::mem;
+	use std::ptr;
+	
+	use libc::{c_char, c_int};
+	
+	use crate::server::privileges::Syscalls;
+	
+	/// The system calls themselves.
+	pub struct Libc;
+	
+	fn c_name(name: &str) -> io::Result<CString> {
+		CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' has a NUL in it", name)))
+	}
+	
+	fn not_found(kind: &str, name: &str) -> io::Error {
+		io::Error::new(io::ErrorKind::NotFound, format!("no {} named '{}'", kind, name))
+	}
+	
+	fn check(result: c_int) -> io::Result<()> {
+		if result != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+	
+	/// Calls `getpwnam_r` or `getgrnam_r`, growing the buffer for the strings until they fit.
+	fn lookup<T>(name: &str, get: impl Fn(*const c_char, *mut T, *mut c_char, usize, *mut *mut T) -> c_int) -> io::Result<Option<T>> {
+		let name = c_name(name)?;
+		let mut buf: Vec<c_char> = vec![0; 1024];
+		loop {
+			let mut entry: T = unsafe { mem::zeroed() };
+			let mut found: *mut T = ptr::null_mut();
+			match get(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) {
+				0 if found.is_null() => return Ok(None),
+				0 => return Ok(Some(entry)),
+				libc::ERANGE => buf.resize(buf.len() * 2, 0),
+				error => return Err(io::Error::from_raw_os_error(error)),
+			}
+		}
+	}
+	
+	impl Syscalls for Libc {
+		fn user_ids(&self, name: &str) -> io::Result<(u32, u32)> {
+			let passwd = lookup(name, |name, entry, buf, len, found| unsafe { libc::getpwnam_r(name, entry, buf, len, found) })?;
+			passwd.map(|passwd: libc::passwd| (passwd.pw_uid, passwd.pw_gid)).ok_or_else(|| not_found("user", name))
+		}
+		
+		fn group_id(&self, name: &str) -> io::Result<u32> {
+			let group = lookup(name, |name, entry, buf, len, found| unsafe { libc::getgrnam_r(name, entry, buf, len, found) })?;
+			group.map(|group: libc::group| group.gr_gid).ok_or_else(|| not_found("group", name))
+		}
+		
+		fn set_groups(&self, gid: u32) -> io::Result<()> {
+			check(unsafe { libc::setgroups(1, &gid) })
+		}
+		
+		fn set_gid(&self, gid: u32) -> io::Result<()> {
+			check(unsafe { libc::setgid(gid) })
+		}
+		
+		fn set_uid(&self, uid: u32) -> io::Result<()> {
+			check(unsafe { libc::setuid(uid) })
+		}
+	}
+	
+	#[cfg(test)]
+	mod test {
+		use crate::server::privileges::linux::Libc;
+		use crate::server::privileges::Syscalls;
+		
+		#[test]
+		fn test_lookup() {
+			assert_eq!(Libc.user_ids("root").unwrap(), (0, 0));
+			assert_eq!(Libc.group_id("root").unwrap(), 0);
+			assert_eq!(Libc.user_ids("no-such-user-here").unwrap_err().to_string(), "no user named 'no-such-user-here'");
+			assert_eq!(Libc.group_id("no-such-group-here").unwrap_err().to_string(), "no group named 'no-such-group-here'");
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::cell::RefCell;
+	use std::io;
+	
+	use crate::server::privileges::{drop_privileges, Syscalls};
+	
+	/// Records the calls made, failing the one named by `fail`.
+	#[derive(Default)]
+	struct Recorder {
+		calls: RefCell<Vec<String>>,
+		fail: Option<&'static str>,
+	}
+	
+	impl Recorder {
+		fn call(&self, call: String) -> io::Result<()> {
+			let failed = self.fail.map(|fail| call.starts_with(fail)).unwrap_or(false);
+			self.calls.borrow_mut().push(call);
+			if failed {
+				return Err(io::Error::new(io::ErrorKind::PermissionDenied, "not permitted"));
+			}
+			return Ok(());
+		}
+	}
+	
+	impl Syscalls for Recorder {
+		fn user_ids(&self, name: &str) -> io::Result<(u32, u32)> {
+			self.call(format!("user_ids {}", name))?;
+			return Ok((1000, 1000));
+		}
+		
+		fn group_id(&self, name: &str) -> io::Result<u32> {
+			self.call(format!("group_id {}", name))?;
+			return Ok(53);
+		}
+		
+		fn set_groups(&self, gid: u32) -> io::Result<()> {
+			return self.call(format!("set_groups {}", gid));
+		}
+		
+		fn set_gid(&self, gid: u32) -> io::Result<()> {
+			return self.call(format!("set_gid {}", gid));
+		}
+		
+		fn set_uid(&self, uid: u32) -> io::Result<()> {
+			return self.call(format!("set_uid {}", uid));
+		}
+	}
+	
+	#[test]
+	fn test_drop_privileges() {
+		let recorder = Recorder::default();
+		drop_privileges(Some("taco"), None, &recorder).unwrap();
+		// the user's own group, and the groups before the user
+		assert_eq!(*recorder.calls.borrow(), vec!["user_ids taco", "set_groups 1000", "set_gid 1000", "set_uid 1000"]);
+		
+		let recorder = Recorder::default();
+		drop_privileges(Some("taco"), Some("dns"), &recorder).unwrap();
+		assert_eq!(*recorder.calls.borrow(), vec!["user_ids taco", "group_id dns", "set_groups 53", "set_gid 53", "set_uid 1000"]);
+		
+		let recorder = Recorder::default();
+		drop_privileges(None, Some("dns"), &recorder).unwrap();
+		assert_eq!(*recorder.calls.borrow(), vec!["group_id dns", "set_groups 53", "set_gid 53"]);
+		
+		let recorder = Recorder::default();
+		drop_privileges(None, None, &recorder).unwrap();
+		assert!(recorder.calls.borrow().is_empty());
+	}
+	
+	#[test]
+	fn test_drop_privileges_failing() {
+		// the user isn't switched to with root's groups left
+		let recorder = Recorder { fail: Some("set_gid"), ..Recorder::default() };
+		assert_eq!(drop_privileges(Some("taco"), None, &recorder).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+		assert_eq!(*recorder.calls.borrow(), vec!["user_ids taco", "set_groups 1000", "set_gid 1000"]);
+		
+		let recorder = Recorder { fail: Some("user_ids"), ..Recorder::default() };
+		assert!(drop_privileges(Some("taco"), Some("dns"), &recorder).is_err());
+		assert_eq!(*recorder.calls.borrow(), vec!["user_ids taco"]);
+	}
+}
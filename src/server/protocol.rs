@@ -1,22 +1,98 @@
 extern crate byteorder;
 
-use std::io::{self, Cursor, Read, Seek, Write};
-use std::io::SeekFrom::Start;
+use std::io::{self, Cursor, Read, Write};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 
 pub mod record_type {
 	pub const A: u16 = 1;
 	pub const NS: u16 = 2;
 	pub const CNAME: u16 = 5;
 	pub const SOA: u16 = 6;
+	pub const MB: u16 = 7;
+	pub const MG: u16 = 8;
+	pub const MR: u16 = 9;
+	pub const PTR: u16 = 12;
+	pub const HINFO: u16 = 13;
+	pub const MINFO: u16 = 14;
 	pub const MX: u16 = 15;
 	pub const TXT: u16 = 16;
+	pub const RP: u16 = 17;
+	pub const AFSDB: u16 = 18;
+	pub const RT: u16 = 21;
+	pub const SIG: u16 = 24;
+	pub const PX: u16 = 26;
 	pub const AAAA: u16 = 28;
+	pub const LOC: u16 = 29;
+	pub const NXT: u16 = 30;
 	pub const SRV: u16 = 33;
+	pub const NAPTR: u16 = 35;
+	pub const OPT: u16 = 41;
+	pub const DS: u16 = 43;
+	pub const SSHFP: u16 = 44;
+	pub const RRSIG: u16 = 46;
+	pub const NSEC: u16 = 47;
+	pub const DNSKEY: u16 = 48;
+	/// https://tools.ietf.org/html/rfc9460#section-14.1
+	pub const SVCB: u16 = 64;
+	pub const HTTPS: u16 = 65;
+	/// Only ever in NSEC type bitmaps, to mark a name as nonexistent:
+	/// https://tools.ietf.org/html/rfc9824#section-2
+	pub const NXNAME: u16 = 128;
+	/// https://tools.ietf.org/html/rfc8945#section-4.2
+	pub const TSIG: u16 = 250;
+	pub const AXFR: u16 = 252;
+	pub const ANY: u16 = 255;
 }
 
-#[derive(Debug, Default)]
+pub mod class {
+	pub const IN: u16 = 1;
+	/// CHAOS, used these days to ask a server about itself: https://tools.ietf.org/html/rfc4892
+	pub const CH: u16 = 3;
+	/// Only in dynamic updates, to delete a record: https://tools.ietf.org/html/rfc2136#section-2.5.4
+	pub const NONE: u16 = 254;
+	pub const ANY: u16 = 255;
+}
+
+/// The keys of the SvcParams in SVCB and HTTPS records that can be configured:
+/// https://tools.ietf.org/html/rfc9460#section-14.3.2
+pub mod svc_param {
+	pub const MANDATORY: u16 = 0;
+	pub const ALPN: u16 = 1;
+	pub const NO_DEFAULT_ALPN: u16 = 2;
+	pub const PORT: u16 = 3;
+	pub const IPV4HINT: u16 = 4;
+	pub const IPV6HINT: u16 = 6;
+	
+	/// The key of a SvcParam by the name it's given in the presentation format, e.g. `alpn`.
+	pub fn key(name: &str) -> Option<u16> {
+		return match name {
+			"mandatory" => Some(MANDATORY),
+			"alpn" => Some(ALPN),
+			"no-default-alpn" => Some(NO_DEFAULT_ALPN),
+			"port" => Some(PORT),
+			"ipv4hint" => Some(IPV4HINT),
+			"ipv6hint" => Some(IPV6HINT),
+			_ => None,
+		};
+	}
+}
+
+pub mod opcode {
+	/// https://tools.ietf.org/html/rfc1035#section-4.1.1
+	pub const QUERY: u8 = 0;
+	/// Inverse queries, which are obsolete: https://tools.ietf.org/html/rfc3425
+	pub const IQUERY: u8 = 1;
+	/// A server status request, which was never specified.
+	pub const STATUS: u8 = 2;
+	/// https://tools.ietf.org/html/rfc1996#section-3.1
+	pub const NOTIFY: u8 = 4;
+	/// https://tools.ietf.org/html/rfc2136#section-1.3
+	pub const UPDATE: u8 = 5;
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Header {
 	// https://tools.ietf.org/html/rfc1035#page-26
 	pub id: u16,
@@ -29,18 +105,18 @@ pub struct Header {
 	pub ra: bool,
 	// 3 bits
 	pub z: u8,
-	// 4 bits
-	pub rcode: u8,
+	// 4 bits, extended to 12 bits by EDNS: https://tools.ietf.org/html/rfc6891#section-6.1.3
+	pub rcode: u16,
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Question {
 	pub qname: Vec<String>,
 	pub qtype: u16,
 	pub qclass: u16,
 }
 
-#[derive(Debug, Default, PartialEq, Clone)]
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Resource {
 	pub rname: Vec<String>,
 	pub rtype: u16,
@@ -49,20 +125,37 @@ pub struct Resource {
 	pub rdata: Vec<u8>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct EdnsOption {
-	code: u16,
-	data: Vec<u8>,
+	pub code: u16,
+	pub data: Vec<u8>,
 }
 
-#[derive(Debug, Default)]
+/// The contents of an OPT pseudo-record: https://tools.ietf.org/html/rfc6891#section-6.1.2
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Edns {
-	udp_payload_size: u16,
-	extended_rcode_and_flags: u32,
-	options: Vec<EdnsOption>,
+	pub udp_payload_size: u16,
+	// upper 8 bits of the extended rcode, 8 bit version, DO bit, 15 bits of zero
+	// The extended rcode is kept in `Header::rcode` instead, so those bits are zero when parsed and
+	// ignored when serialized.
+	pub extended_rcode_and_flags: u32,
+	pub options: Vec<EdnsOption>,
+}
+
+impl Edns {
+	pub const DNSSEC_OK: u32 = 1 << 15;
+	
+	pub fn version(&self) -> u8 {
+		return (self.extended_rcode_and_flags >> 16) as u8;
+	}
+	
+	/// Whether the client wants DNSSEC records: https://tools.ietf.org/html/rfc3225#section-3
+	pub fn dnssec_ok(&self) -> bool {
+		return self.extended_rcode_and_flags & Edns::DNSSEC_OK != 0;
+	}
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Message {
 	pub header: Header,
 	pub question: Vec<Question>,
@@ -70,144 +163,314 @@ pub struct Message {
 	pub authority: Vec<Resource>,
 	pub additional: Vec<Resource>,
 	pub edns: Option<Edns>,
+	/// The TSIG record signing the message, which comes after every other record:
+	/// https://tools.ietf.org/html/rfc8945#section-5.1
+	pub tsig: Option<Resource>,
 }
 
-pub fn parse(buf: &[u8]) -> Message {
-	let mut message: Message = Default::default();
-	let mut cursor = Cursor::new(buf.to_vec());
+#[derive(Debug)]
+pub enum ParseError {
+	/// The message is too short to even contain a header.
+	Truncated,
+	/// The header could be read, but the rest of the message is malformed.
+	Malformed(Header, io::Error),
+}
+
+/// How many compression pointers a name may follow. A name has at most 127 labels, and a pointer
+/// to each of them and one to its end is as many as one could usefully need.
+const MAX_POINTERS: usize = 128;
+
+fn invalid_data(error: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Reads a name at the cursor, following compression pointers, and leaves the cursor after it.
+fn parse_name(cursor: &mut Cursor<Vec<u8>>) -> io::Result<Vec<String>> {
+	let mut result = vec![];
+	// the length of the name once expanded, which is limited to 255 bytes
+	let mut length = 1;
 	
-	let mut header = &mut message.header;
-	header.id = cursor.read_u16::<BigEndian>().unwrap();
-	let flags = cursor.read_u16::<BigEndian>().unwrap();
-	header.qr = flags >> 15 == 1;
-	header.opcode = (flags >> 11 & 0b1111) as u8;
-	header.aa = (flags >> 10 & 1) == 1;
-	header.tc = (flags >> 9 & 1) == 1;
-	header.rd = (flags >> 8 & 1) == 1;
-	header.ra = (flags >> 7 & 1) == 1;
-	header.z = (flags >> 4 & 0b111) as u8;
-	header.rcode = (flags & 0b1111) as u8;
-	
-	let question_count = cursor.read_u16::<BigEndian>().unwrap();
-	//println!("question_count: {:.unwrap()}", question_count);
-	let answer_count = cursor.read_u16::<BigEndian>().unwrap();
-	//println!("answer_count: {:.unwrap()}", answer_count);
-	let authority_count = cursor.read_u16::<BigEndian>().unwrap();
-	//println!("authority_count: {:.unwrap()}", authority_count);
-	let additional_count = cursor.read_u16::<BigEndian>().unwrap();
-	//println!("additional_count: {:.unwrap()}", additional_count);
-	
-	fn parse_name(cursor: &mut Cursor<Vec<u8>>) -> Vec<String> {
-		let mut result = vec![];
-		
-		let mut label_cursor: &mut Cursor<Vec<u8>> = cursor;
-		let mut _label_cursor = Cursor::new(vec![]);
-		loop {
-			let label_size = label_cursor.read_u8().unwrap();
-			if label_size == 0 { break; }
-			
-			if label_size >> 6 == 3 {
-				// message compression: https://tools.ietf.org/html/rfc1035#section-4.1.4
-				let second_octet = label_cursor.read_u8().unwrap();
-				let offset = ((label_size as u16 & 0b00111111) << 8) | second_octet as u16;
-				_label_cursor = cursor.clone();
-				label_cursor = &mut _label_cursor;
-				label_cursor.seek(Start(offset as u64)).unwrap();
-				continue;
+	let buf = cursor.get_ref();
+	let mut position = cursor.position() as usize;
+	// where the name started, or where the last compression pointer pointed to
+	let mut segment_start = position;
+	// where to continue reading the message once the name is done, if we followed a pointer
+	let mut end = None;
+	// pointers only pointing backwards is enough to stop loops, but not long chains of them
+	// being followed again for every name that points into them
+	let mut pointers = 0;
+	loop {
+		let label_size = *buf.get(position).ok_or_else(|| invalid_data("name runs past the end of the message"))? as usize;
+		position += 1;
+		if label_size == 0 { break; }
+		
+		if label_size >> 6 == 3 {
+			// message compression: https://tools.ietf.org/html/rfc1035#section-4.1.4
+			let second_octet = *buf.get(position).ok_or_else(|| invalid_data("name runs past the end of the message"))? as usize;
+			position += 1;
+			let offset = ((label_size & 0b00111111) << 8) | second_octet;
+			// pointers may only point backwards, which guarantees that we don't loop forever
+			if offset >= segment_start {
+				return Err(invalid_data("compression pointer doesn't point backwards"));
 			}
-			
-			let mut label_buf = vec![0u8; label_size as usize];
-			label_cursor.read_exact(label_buf.as_mut()).unwrap();
-			let label = String::from_utf8(label_buf)
-				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)).unwrap();
-			result.push(label);
+			pointers += 1;
+			if pointers > MAX_POINTERS {
+				return Err(invalid_data("name follows too many compression pointers"));
+			}
+			if end.is_none() {
+				end = Some(position);
+			}
+			position = offset;
+			segment_start = offset;
+			continue;
+		}
+		if label_size >> 6 != 0 {
+			return Err(invalid_data("unknown label type"));
+		}
+		length += 1 + label_size;
+		if length > 255 {
+			return Err(invalid_data("name is longer than 255 bytes"));
 		}
 		
-		return result;
+		let label_buf = buf.get(position..position + label_size).ok_or_else(|| invalid_data("label runs past the end of the message"))?;
+		position += label_size;
+		let label = String::from_utf8(label_buf.to_vec())
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		result.push(label);
 	}
 	
-	// question
-	message.question = Vec::with_capacity(question_count as usize);
-	for _ in 0..question_count {
-		let mut question: Question = Default::default();
+	cursor.set_position(end.unwrap_or(position) as u64);
+	return Ok(result);
+}
+
+/// Where the last record of a message starts, which for a signed message is its TSIG record. Names
+/// are read as `parse` reads them, so that the record is found even when its owner is compressed.
+pub fn last_record_start(buf: &[u8]) -> Option<usize> {
+	let mut cursor = Cursor::new(buf.to_vec());
+	cursor.set_position(4);
+	let mut counts = [0; 4];
+	for count in &mut counts {
+		*count = cursor.read_u16::<BigEndian>().ok()? as usize;
+	}
+	for _ in 0..counts[0] {
+		parse_name(&mut cursor).ok()?;
+		cursor.set_position(cursor.position() + 4);
+	}
+	let records = counts[1] + counts[2] + counts[3];
+	for _ in 1..records {
+		parse_name(&mut cursor).ok()?;
+		cursor.set_position(cursor.position() + 8);
+		let rdata_len = cursor.read_u16::<BigEndian>().ok()?;
+		cursor.set_position(cursor.position() + rdata_len as u64);
+	}
+	return if records > 0 && (cursor.position() as usize) < buf.len() { Some(cursor.position() as usize) } else { None };
+}
+
+pub fn parse(buf: &[u8]) -> Result<Message, ParseError> {
+	let mut message: Message = Default::default();
+	let mut cursor = Cursor::new(buf.to_vec());
+	
+	fn parse_header(cursor: &mut Cursor<Vec<u8>>) -> io::Result<(Header, [u16; 4])> {
+		let mut header = Header { id: cursor.read_u16::<BigEndian>()?, ..Default::default() };
+		let flags = cursor.read_u16::<BigEndian>()?;
+		header.qr = flags >> 15 == 1;
+		header.opcode = (flags >> 11 & 0b1111) as u8;
+		header.aa = (flags >> 10 & 1) == 1;
+		header.tc = (flags >> 9 & 1) == 1;
+		header.rd = (flags >> 8 & 1) == 1;
+		header.ra = (flags >> 7 & 1) == 1;
+		header.z = (flags >> 4 & 0b111) as u8;
+		header.rcode = flags & 0b1111;
 		
-		question.qname = parse_name(&mut cursor);
-		question.qtype = cursor.read_u16::<BigEndian>().unwrap();
-		question.qclass = cursor.read_u16::<BigEndian>().unwrap();
+		let question_count = cursor.read_u16::<BigEndian>()?;
+		let answer_count = cursor.read_u16::<BigEndian>()?;
+		let authority_count = cursor.read_u16::<BigEndian>()?;
+		let additional_count = cursor.read_u16::<BigEndian>()?;
 		
-		message.question.push(question);
+		Ok((header, [question_count, answer_count, authority_count, additional_count]))
+	}
+	let (header, [question_count, answer_count, authority_count, additional_count]) = match parse_header(&mut cursor) {
+		Ok(header) => header,
+		Err(_) => return Err(ParseError::Truncated),
+	};
+	message.header = header;
+	
+	/// How many bytes are left to read, which bounds how many questions or records there can be,
+	/// whatever the header claims.
+	fn remaining(cursor: &Cursor<Vec<u8>>) -> usize {
+		return cursor.get_ref().len().saturating_sub(cursor.position() as usize);
+	}
+	
+	fn parse_questions(cursor: &mut Cursor<Vec<u8>>, count: u16) -> io::Result<Vec<Question>> {
+		// a question is at least a root name and its type and class
+		let mut questions = Vec::with_capacity((count as usize).min(remaining(cursor) / 5));
+		for _ in 0..count {
+			let question = Question {
+				qname: parse_name(cursor)?,
+				qtype: cursor.read_u16::<BigEndian>()?,
+				qclass: cursor.read_u16::<BigEndian>()?,
+			};
+			
+			questions.push(question);
+		}
+		return Ok(questions);
+	}
+	
+	fn read_bytes(cursor: &mut Cursor<Vec<u8>>, len: usize) -> io::Result<Vec<u8>> {
+		let mut bytes = vec![0; len];
+		cursor.read_exact(&mut bytes)?;
+		return Ok(bytes);
+	}
+	
+	fn expand_name(cursor: &mut Cursor<Vec<u8>>) -> io::Result<Vec<u8>> {
+		return Ok(serialize_name(parse_name(cursor)?.iter().map(|label| label.as_str())));
+	}
+	
+	/// A part of the rdata of a type that holds names.
+	enum RdataPart {
+		Bytes(usize),
+		Name,
+		/// A length-prefixed string, as in NAPTR.
+		String,
+		/// Whatever's left of the rdata, e.g. a signature.
+		Rest,
+	}
+	
+	/// What the rdata of the types whose names may be compressed is made of: those of RFC 1035,
+	/// which must be expanded, and those that RFC 3597 says should be as well, as older servers
+	/// compress them too. https://tools.ietf.org/html/rfc3597#section-4
+	fn rdata_parts(rtype: u16) -> Option<&'static [RdataPart]> {
+		use RdataPart::*;
+		return Some(match rtype {
+			record_type::NS | record_type::CNAME | record_type::MB | record_type::MG | record_type::MR | record_type::PTR => &[Name],
+			record_type::SOA => &[Name, Name, Bytes(20)],
+			record_type::MINFO | record_type::RP => &[Name, Name],
+			record_type::MX | record_type::AFSDB | record_type::RT => &[Bytes(2), Name],
+			record_type::PX => &[Bytes(2), Name, Name],
+			record_type::SRV => &[Bytes(6), Name],
+			record_type::NAPTR => &[Bytes(4), String, String, String, Name],
+			// the signer's name mustn't be compressed in an RRSIG, but is expanded in case it is
+			record_type::SIG | record_type::RRSIG => &[Bytes(18), Name, Rest],
+			record_type::NXT => &[Name, Rest],
+			_ => return None,
+		});
 	}
 	
 	// answer, authority, additional
 	fn read_resources(cursor: &mut Cursor<Vec<u8>>, count: u16) -> io::Result<Vec<Resource>> {
-		let mut resources = Vec::with_capacity(count as usize);
+		// a record is at least a root name, its type, class, TTL, and rdata length
+		let mut resources = Vec::with_capacity((count as usize).min(remaining(cursor) / 11));
 		for _ in 0..count {
-			let mut resource: Resource = Default::default();
-			
-			resource.rname = parse_name(cursor);
-			resource.rtype = cursor.read_u16::<BigEndian>().unwrap();
-			resource.rclass = cursor.read_u16::<BigEndian>().unwrap();
-			resource.ttl = cursor.read_u32::<BigEndian>().unwrap();
+			let mut resource = Resource {
+				rname: parse_name(cursor)?,
+				rtype: cursor.read_u16::<BigEndian>()?,
+				rclass: cursor.read_u16::<BigEndian>()?,
+				ttl: cursor.read_u32::<BigEndian>()?,
+				..Default::default()
+			};
 			
-			let rdata_len = cursor.read_u16::<BigEndian>().unwrap();
-			let rdata_buf = match resource.rtype {
-				record_type::CNAME | record_type::NS => {
-					serialize_name(parse_name(cursor).iter().map(|label| label.as_str()))
-				}
-				record_type::MX => {
-					let mut rdata_buf = vec![];
-					rdata_buf.push(cursor.read_u8().unwrap());
-					rdata_buf.push(cursor.read_u8().unwrap());
-					rdata_buf.append(&mut serialize_name(parse_name(cursor).iter().map(|label| label.as_str())));
-					rdata_buf
-				}
-				_ => {
-					let mut rdata_buf = vec![0; rdata_len as usize];
-					cursor.read_exact(rdata_buf.as_mut()).unwrap();
-					rdata_buf
+			let rdata_len = cursor.read_u16::<BigEndian>()?;
+			if rdata_len as usize > remaining(cursor) {
+				return Err(invalid_data("rdata runs past the end of the message"));
+			}
+			let rdata_end = cursor.position() + rdata_len as u64;
+			// names in these may be compressed, and are expanded as the pointers would be wrong
+			// anywhere but in this message. Any other type is kept as it is, which is only safe as
+			// names in types defined since mustn't be compressed
+			resource.rdata = match rdata_parts(resource.rtype) {
+				Some(parts) => {
+					let mut rdata = vec![];
+					for part in parts {
+						match part {
+							RdataPart::Bytes(len) => rdata.append(&mut read_bytes(cursor, *len)?),
+							RdataPart::Name => rdata.append(&mut expand_name(cursor)?),
+							RdataPart::String => {
+								let len = cursor.read_u8()?;
+								rdata.push(len);
+								rdata.append(&mut read_bytes(cursor, len as usize)?);
+							}
+							RdataPart::Rest => {
+								let len = rdata_end.saturating_sub(cursor.position()) as usize;
+								rdata.append(&mut read_bytes(cursor, len)?);
+							}
+						}
+					}
+					rdata
 				}
+				None => read_bytes(cursor, rdata_len as usize)?,
 			};
-			resource.rdata = rdata_buf;
+			if cursor.position() != rdata_end {
+				return Err(invalid_data("rdata length doesn't match its contents"));
+			}
 			
 			resources.push(resource);
 		}
 		return Ok(resources);
 	}
-	message.answer = read_resources(&mut cursor, answer_count).unwrap();
-	message.authority = read_resources(&mut cursor, authority_count).unwrap();
-	message.additional = read_resources(&mut cursor, additional_count).unwrap().into_iter().filter(|x| if x.rtype == 41 {
+	
+	fn parse_edns(resource: &Resource) -> io::Result<Edns> {
 		let mut options = vec![];
-		let len = x.rdata.len() as u64;
-		let mut cursor = Cursor::new(x.rdata.clone());
+		let len = resource.rdata.len() as u64;
+		let mut cursor = Cursor::new(resource.rdata.clone());
 		
 		while cursor.position() < len {
-			let code = cursor.read_u16::<BigEndian>().unwrap();
-			let length = cursor.read_u16::<BigEndian>().unwrap();
+			let code = cursor.read_u16::<BigEndian>()?;
+			let length = cursor.read_u16::<BigEndian>()?;
+			if length as u64 > len - cursor.position() {
+				return Err(invalid_data("EDNS option runs past the end of its record"));
+			}
 			let mut data = vec![0; length as usize];
-			cursor.read_exact(&mut data).unwrap();
+			cursor.read_exact(&mut data)?;
 			options.push(EdnsOption {
 				code,
 				data,
 			});
 		}
 		
-		message.edns = Some(Edns {
-			udp_payload_size: x.rclass,
-			extended_rcode_and_flags: x.ttl,
+		Ok(Edns {
+			udp_payload_size: resource.rclass,
+			extended_rcode_and_flags: resource.ttl,
 			options,
-		});
-		return false;
-	} else {
-		return true;
-	}).collect();
+		})
+	}
 	
-	return message;
+	fn parse_sections(message: &mut Message, cursor: &mut Cursor<Vec<u8>>, counts: [u16; 4]) -> io::Result<()> {
+		message.question = parse_questions(cursor, counts[0])?;
+		message.answer = read_resources(cursor, counts[1])?;
+		message.authority = read_resources(cursor, counts[2])?;
+		let additional = read_resources(cursor, counts[3])?;
+		let last = additional.len().saturating_sub(1);
+		for (index, resource) in additional.into_iter().enumerate() {
+			if resource.rtype == record_type::TSIG {
+				if index != last {
+					return Err(invalid_data("TSIG record isn't the last record"));
+				}
+				message.tsig = Some(resource);
+			} else if resource.rtype == record_type::OPT {
+				let mut edns = parse_edns(&resource)?;
+				message.header.rcode |= ((edns.extended_rcode_and_flags >> 24) as u16) << 4;
+				edns.extended_rcode_and_flags &= 0x00ffffff;
+				message.edns = Some(edns);
+			} else {
+				message.additional.push(resource);
+			}
+		}
+		Ok(())
+	}
+	
+	if let Err(error) = parse_sections(&mut message, &mut cursor, [question_count, answer_count, authority_count, additional_count]) {
+		return Err(ParseError::Malformed(message.header, error));
+	}
+	
+	return Ok(message);
 }
 
 /// Takes a list of labels (e.g. `["google", "com"]`) and converts it into a binary format useful for rdata
+///
+/// Empty labels are skipped, so that the root can be given as `"".split('.')`.
 pub fn serialize_name<'a, I: IntoIterator<Item=&'a str>>(name: I) -> Vec<u8> {
 	let mut bytes = vec![];
-	for label in name {
+	for label in name.into_iter().filter(|label| !label.is_empty()) {
 		bytes.push(label.len() as u8);
 		bytes.append(&mut label.as_bytes().to_vec());
 	}
@@ -223,9 +486,96 @@ pub fn serialize_mx(host: &str, priority: u16) -> Vec<u8> {
 	return rdata;
 }
 
+/// https://tools.ietf.org/html/rfc2782
+pub fn serialize_srv(priority: u16, weight: u16, port: u16, target: &str) -> Vec<u8> {
+	let mut rdata: Vec<u8> = vec![];
+	rdata.write_u16::<BigEndian>(priority).unwrap();
+	rdata.write_u16::<BigEndian>(weight).unwrap();
+	rdata.write_u16::<BigEndian>(port).unwrap();
+	rdata.append(&mut serialize_name(target.split(".")));
+	return rdata;
+}
+
+/// The strings are written as character-strings, so must be at most 255 bytes each.
+///
+/// https://tools.ietf.org/html/rfc3403#section-4.1
+pub fn serialize_naptr(order: u16, preference: u16, flags: &str, service: &str, regexp: &str, replacement: &str) -> Vec<u8> {
+	let mut rdata: Vec<u8> = vec![];
+	rdata.write_u16::<BigEndian>(order).unwrap();
+	rdata.write_u16::<BigEndian>(preference).unwrap();
+	for string in &[flags, service, regexp] {
+		rdata.push(string.len() as u8);
+		rdata.extend_from_slice(string.as_bytes());
+	}
+	rdata.append(&mut serialize_name(replacement.split(".")));
+	return rdata;
+}
+
+/// The strings are written as character-strings, so must be at most 255 bytes each.
+///
+/// https://tools.ietf.org/html/rfc1035#section-3.3.2
+pub fn serialize_hinfo(cpu: &str, os: &str) -> Vec<u8> {
+	let mut rdata: Vec<u8> = vec![];
+	for string in &[cpu, os] {
+		rdata.push(string.len() as u8);
+		rdata.extend_from_slice(string.as_bytes());
+	}
+	return rdata;
+}
+
+/// https://tools.ietf.org/html/rfc1876#section-2
+pub fn serialize_loc(size: u8, horizontal_precision: u8, vertical_precision: u8, latitude: u32, longitude: u32, altitude: u32) -> Vec<u8> {
+	// version 0 is the only one
+	let mut rdata: Vec<u8> = vec![0, size, horizontal_precision, vertical_precision];
+	for value in &[latitude, longitude, altitude] {
+		rdata.write_u32::<BigEndian>(*value).unwrap();
+	}
+	return rdata;
+}
+
+/// https://tools.ietf.org/html/rfc4255#section-3.1
+pub fn serialize_sshfp(algorithm: u8, fingerprint_type: u8, fingerprint: &[u8]) -> Vec<u8> {
+	let mut rdata: Vec<u8> = vec![algorithm, fingerprint_type];
+	rdata.extend_from_slice(fingerprint);
+	return rdata;
+}
+
+/// Serializes SVCB and HTTPS records, which have the same format. The params are keys and their
+/// values, already in their wire format, and are written in order of their keys as they must be.
+///
+/// https://tools.ietf.org/html/rfc9460#section-2.2
+pub fn serialize_svcb(priority: u16, target: &str, mut params: Vec<(u16, Vec<u8>)>) -> Vec<u8> {
+	let mut rdata: Vec<u8> = vec![];
+	rdata.write_u16::<BigEndian>(priority).unwrap();
+	rdata.append(&mut serialize_name(target.split(".")));
+	params.sort_by_key(|(key, _)| *key);
+	for (key, value) in params {
+		rdata.write_u16::<BigEndian>(key).unwrap();
+		rdata.write_u16::<BigEndian>(value.len() as u16).unwrap();
+		rdata.extend_from_slice(&value);
+	}
+	return rdata;
+}
+
+/// https://tools.ietf.org/html/rfc1035#section-3.3.13
+pub fn serialize_soa(mname: &str, rname: &str, serial: u32, refresh: u32, retry: u32, expire: u32, minimum: u32) -> Vec<u8> {
+	let mut rdata: Vec<u8> = vec![];
+	rdata.append(&mut serialize_name(mname.split(".")));
+	rdata.append(&mut serialize_name(rname.split(".")));
+	for value in &[serial, refresh, retry, expire, minimum] {
+		rdata.write_u32::<BigEndian>(*value).unwrap();
+	}
+	return rdata;
+}
+
+/// Splits a TXT value into 255 byte character-strings. An empty value still produces one (empty)
+/// character-string, as TXT rdata must contain at least one.
 pub fn serialize_txt(value: &str) -> Vec<u8> {
 	let mut data = value.bytes();
 	let mut rdata = vec![];
+	if data.len() == 0 {
+		rdata.push(0);
+	}
 	while data.len() > 0 {
 		let group_size = data.len().min(255);
 		rdata.push(group_size as u8);
@@ -236,10 +586,37 @@ pub fn serialize_txt(value: &str) -> Vec<u8> {
 	return rdata;
 }
 
-pub fn serialize(message: &Message, tcp: bool) -> Vec<u8> {
-	fn name_len(name: &Vec<String>) -> usize {
-		return name.iter().map(|name| 1 + name.len()).sum::<usize>() + 1;
+/// Serializes a message, truncating it to fit in `max_size` bytes. Pass `u16::MAX` for TCP, and
+/// the client's advertised payload size (or 512 without EDNS) for UDP.
+pub fn serialize(message: &Message, max_size: u16) -> Vec<u8> {
+	fn name_len(name: &[String]) -> usize {
+		// matches `serialize_name`, which skips empty labels
+		return name.iter().filter(|name| !name.is_empty()).map(|name| 1 + name.len()).sum::<usize>() + 1;
+	}
+	fn resource_len(resource: &Resource) -> usize {
+		return name_len(&resource.rname) + 10 + resource.rdata.len();
 	}
+	/// How many records from the start of a section fit in `available_size`, without leaving out
+	/// only some of an RRset, along with their size.
+	fn fit(available_size: usize, resources: &[Resource]) -> (usize, usize) {
+		let mut kept = 0;
+		let mut size = 0;
+		for resource in resources {
+			if size + resource_len(resource) > available_size { break; }
+			size += resource_len(resource);
+			kept += 1;
+		}
+		while let Some(split) = resources[..kept].iter().position(|record| resources[kept..].iter().any(|dropped| dropped.rname == record.rname && dropped.rtype == record.rtype)) {
+			kept = split;
+		}
+		return (kept, resources[..kept].iter().map(resource_len).sum());
+	}
+	/// Leaves out records until the message fits: additional ones first, then authority, then
+	/// answers. Only leaving out answers, or the NS records of a referral, truncates the message, as
+	/// the rest can be done without.
+	///
+	/// https://tools.ietf.org/html/rfc2181#section-9
+	#[allow(clippy::type_complexity)]
 	fn compute_truncation<'a>(available_size: usize, question: &'a [Question], answer: &'a [Resource], authority: &'a [Resource], additional: &'a [Resource]) ->
 	(usize, bool, &'a [Question], &'a [Resource], &'a [Resource], &'a [Resource]) {
 		let mut size = 12;
@@ -252,67 +629,65 @@ pub fn serialize(message: &Message, tcp: bool) -> Vec<u8> {
 			size += increase;
 		}
 		
-		for (index, a) in answer.iter().enumerate() {
-			let increase = name_len(&a.rname) + 10 + a.rdata.len();
-			if size + increase > available_size {
-				return (size, true, question, &answer[0..index], &authority[0..0], &additional[0..0]);
-			}
-			size += increase;
-		}
-		
-		for (index, a) in authority.iter().enumerate() {
-			let increase = name_len(&a.rname) + 10 + a.rdata.len();
-			if size + increase > available_size {
-				return (size, true, question, answer, &authority[0..index], &additional[0..0]);
-			}
-			size += increase;
+		let answer_len: usize = answer.iter().map(resource_len).sum();
+		let authority_len: usize = authority.iter().map(resource_len).sum();
+		if size + answer_len + authority_len <= available_size {
+			let (kept, additional_len) = fit(available_size - size - answer_len - authority_len, additional);
+			return (size + answer_len + authority_len + additional_len, false, question, answer, authority, &additional[..kept]);
 		}
-		
-		for (index, a) in additional.iter().enumerate() {
-			let increase = name_len(&a.rname) + 10 + a.rdata.len();
-			if size + increase > available_size {
-				return (size, true, question, answer, authority, &additional[0..index]);
-			}
-			size += increase;
+		if size + answer_len <= available_size {
+			let (kept, authority_len) = fit(available_size - size - answer_len, authority);
+			let referral = answer.is_empty() && authority.iter().any(|record| record.rtype == record_type::NS);
+			return (size + answer_len + authority_len, referral, question, answer, &authority[..kept], &additional[0..0]);
 		}
-		
-		return (size, false, question, answer, authority, additional);
+		// even the header may not fit if `max_size` is tiny
+		let (kept, answer_len) = fit(available_size.saturating_sub(size), answer);
+		return (size + answer_len, true, question, &answer[..kept], &authority[0..0], &additional[0..0]);
 	}
-	let available_size: u16 = if tcp { u16::max_value() } else { message.edns.as_ref().map(|edns| edns.udp_payload_size).unwrap_or(512) };
-	let mut additional;
+	
+	// the OPT record is kept even if the rest of the message gets truncated, so leave room for it
+	let opt: Vec<Resource> = message.edns.iter().map(|edns| {
+		let mut rdata = vec![];
+		for option in &edns.options {
+			rdata.write_u16::<BigEndian>(option.code).unwrap();
+			rdata.write_u16::<BigEndian>(option.data.len() as u16).unwrap();
+			rdata.extend_from_slice(&option.data);
+		}
+		Resource {
+			rname: vec![],
+			rtype: record_type::OPT,
+			rclass: edns.udp_payload_size,
+			ttl: (edns.extended_rcode_and_flags & 0x00ffffff) | (((message.header.rcode >> 4) as u32 & 0xff) << 24),
+			rdata,
+		}
+	}).collect();
+	let opt_len = opt.iter().map(|opt| name_len(&opt.rname) + 10 + opt.rdata.len()).sum::<usize>();
+	// as is the TSIG record, which signs whatever's left
+	let tsig_len = message.tsig.iter().map(resource_len).sum::<usize>();
+	let available_size = (max_size as usize).saturating_sub(opt_len + tsig_len);
 	let (buff_len, truncated, question, answer, authority, additional) =
-		compute_truncation(available_size as usize, &message.question, &message.answer, &message.authority, if message.edns.is_none() { &message.additional } else {
-			additional = Vec::with_capacity(message.additional.len() + 1);
-			additional.extend_from_slice(&message.additional);
-			additional.push(Resource {
-				rname: vec![],
-				rtype: 41,
-				rclass: u16::max_value(),
-				ttl: 0,
-				rdata: vec![]
-			});
-			&additional
-		});
-	assert!(buff_len <= u16::max_value() as usize);
+		compute_truncation(available_size, &message.question, &message.answer, &message.authority, &message.additional);
+	let buff_len = buff_len + opt_len + tsig_len;
+	assert!(buff_len <= u16::MAX as usize);
 	let mut cursor = Cursor::new(Vec::with_capacity(buff_len));
 	
 	let header = &message.header;
 	cursor.write_u16::<BigEndian>(header.id).unwrap();
 	let mut flags = 0u16;
-	flags |= header.rcode as u16;
-	flags |= (header.z << 4) as u16;
+	flags |= header.rcode & 0b1111;
+	flags |= ((header.z & 0b111) as u16) << 4;
 	flags |= if header.ra { 1 } else { 0 } << 7;
 	flags |= if header.rd { 1 } else { 0 } << 8;
-	flags |= if truncated { 1 } else { 0 } << 9;
+	flags |= if truncated || header.tc { 1 } else { 0 } << 9;
 	flags |= if header.aa { 1 } else { 0 } << 10;
-	flags |= (header.opcode as u16) << 11;
+	flags |= ((header.opcode & 0b1111) as u16) << 11;
 	flags |= if header.qr { 1 } else { 0 } << 15;
 	cursor.write_u16::<BigEndian>(flags).unwrap();
 	
 	cursor.write_u16::<BigEndian>(question.len() as u16).unwrap();
 	cursor.write_u16::<BigEndian>(answer.len() as u16).unwrap();
 	cursor.write_u16::<BigEndian>(authority.len() as u16).unwrap();
-	cursor.write_u16::<BigEndian>(additional.len() as u16).unwrap();
+	cursor.write_u16::<BigEndian>((additional.len() + opt.len() + message.tsig.iter().count()) as u16).unwrap();
 	
 	for question in question {
 		cursor.write_all(serialize_name(question.qname.iter().map(|label| label.as_str())).as_slice()).unwrap();
@@ -333,6 +708,8 @@ pub fn serialize(message: &Message, tcp: bool) -> Vec<u8> {
 	write_resources(&mut cursor, answer);
 	write_resources(&mut cursor, authority);
 	write_resources(&mut cursor, additional);
+	write_resources(&mut cursor, &opt);
+	write_resources(&mut cursor, message.tsig.as_slice());
 	
 	let buffer = cursor.into_inner();
 	assert_eq!(buffer.len(), buff_len);
@@ -345,3 +722,549 @@ pub fn make_message_from_question(question: Vec<Question>) -> Message {
 	message.question = question;
 	return message;
 }
+
+#[cfg(test)]
+mod test {
+	use crate::server::protocol::{Edns, EdnsOption, make_message_from_question, MAX_POINTERS, Message, parse, ParseError, Question, record_type, Resource, serialize, serialize_mx, serialize_name, serialize_naptr, serialize_soa, serialize_srv, serialize_txt};
+	
+	fn query() -> Vec<u8> {
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.header.id = 0x1234;
+		serialize(&message, 512)
+	}
+	
+	fn header(question_count: u8, answer_count: u8) -> Vec<u8> {
+		vec![0x12, 0x34, 0x01, 0x00, 0, question_count, 0, answer_count, 0, 0, 0, 0]
+	}
+	
+	#[test]
+	fn test_truncated_header() {
+		let query = query();
+		for len in 0..12 {
+			match parse(&query[..len]) {
+				Err(ParseError::Truncated) => {}
+				result => panic!("expected truncated error for length {}: {:?}", len, result),
+			}
+		}
+	}
+	
+	#[test]
+	fn test_truncated_body() {
+		let query = query();
+		for len in 12..query.len() {
+			match parse(&query[..len]) {
+				Err(ParseError::Malformed(header, _)) => assert_eq!(header.id, 0x1234),
+				result => panic!("expected malformed error for length {}: {:?}", len, result),
+			}
+		}
+		assert!(parse(&query).is_ok());
+	}
+	
+	#[test]
+	fn test_label_past_end() {
+		let mut buf = header(1, 0);
+		buf.extend_from_slice(&[63, b'a', b'b', 0, 0, 1, 0, 1]);
+		match parse(&buf) {
+			Err(ParseError::Malformed(header, _)) => assert_eq!(header.id, 0x1234),
+			result => panic!("expected malformed error: {:?}", result),
+		}
+	}
+	
+	#[test]
+	fn test_compression_loop() {
+		// pointer to itself
+		let mut buf = header(1, 0);
+		buf.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+		assert!(parse(&buf).is_err());
+		
+		// pointer past the end of the message
+		let mut buf = header(1, 0);
+		buf.extend_from_slice(&[0xc0, 0xff, 0, 1, 0, 1]);
+		assert!(parse(&buf).is_err());
+		
+		// two names pointing at each other, through a label
+		let mut buf = header(1, 1);
+		buf.extend_from_slice(&[1, b'a', 0xc0, 19, 0, 1, 0, 1]);
+		buf.extend_from_slice(&[1, b'b', 0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+		assert!(parse(&buf).is_err());
+	}
+	
+	#[test]
+	fn test_pointer_chain() {
+		// a name at the start of an opaque record's rdata, then a chain of pointers each pointing at
+		// the one before, and a record whose name points at the end of the chain
+		let chain = |pointers: usize| {
+			let mut buf = header(0, 2);
+			let rdata_len = 3 + 2 * (pointers - 1);
+			buf.extend_from_slice(&[0, 0, 99, 0, 1, 0, 0, 0, 60, (rdata_len >> 8) as u8, rdata_len as u8, 1, b'a', 0]);
+			let mut target = 23;
+			for _ in 1..pointers {
+				let offset = buf.len();
+				buf.extend_from_slice(&[0xc0 | (target >> 8) as u8, target as u8]);
+				target = offset;
+			}
+			buf.extend_from_slice(&[0xc0 | (target >> 8) as u8, target as u8, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+			parse(&buf)
+		};
+		assert_eq!(chain(MAX_POINTERS).unwrap().answer[1].rname, vec!["a".to_string()]);
+		assert!(chain(MAX_POINTERS + 1).is_err());
+	}
+	
+	#[test]
+	fn test_counts_past_end() {
+		// every count at its maximum, with nothing after the header
+		let buf = [0x12, 0x34, 0x81, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+		match parse(&buf) {
+			Err(ParseError::Malformed(header, _)) => assert_eq!(header.id, 0x1234),
+			result => panic!("expected malformed error: {:?}", result),
+		}
+		
+		// and with a single record after it
+		let mut buf = header(0, 0xff);
+		buf.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+		assert!(parse(&buf).is_err());
+	}
+	
+	#[test]
+	fn test_rdata_past_end() {
+		// an A record claiming far more rdata than there is
+		let mut buf = header(0, 1);
+		buf.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 60, 0xff, 0xff, 10, 0, 0, 1]);
+		assert!(parse(&buf).is_err());
+		
+		// as does one with a name in it
+		let mut buf = header(0, 1);
+		buf.extend_from_slice(&[0, 0, 5, 0, 1, 0, 0, 0, 60, 0xff, 0xff, 1, b'a', 0]);
+		assert!(parse(&buf).is_err());
+		
+		// and an EDNS option claiming more than its record has
+		let mut buf = header(0, 0);
+		buf[11] = 1;
+		buf.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 6, 0, 10, 0xff, 0xff, 1, 2]);
+		assert!(parse(&buf).is_err());
+		buf[25] = 0;
+		buf[26] = 2;
+		assert!(parse(&buf).is_ok());
+	}
+	
+	#[test]
+	fn test_compression() {
+		// second question points back at the first one's name
+		let mut buf = header(2, 0);
+		buf.extend_from_slice(&[7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1]);
+		buf.extend_from_slice(&[3, b'w', b'w', b'w', 0xc0, 12, 0, 28, 0, 1]);
+		let message = parse(&buf).unwrap();
+		assert_eq!(message.question[1], Question {
+			qname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::AAAA,
+			qclass: 1,
+		});
+	}
+	
+	#[test]
+	fn test_fuzz() {
+		// simple deterministic generator, good enough to throw garbage at the parser
+		let mut state: u32 = 0x2545_f491;
+		let mut next = || {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			state
+		};
+		
+		// whatever parses has to survive being serialized and parsed again
+		let check = |buf: &[u8]| {
+			if let Ok(message) = parse(buf) {
+				assert_eq!(parse(&serialize(&message, u16::MAX)).unwrap(), message);
+			}
+		};
+		
+		for _ in 0..10000 {
+			let len = (next() % 64) as usize;
+			let buf: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+			check(&buf);
+		}
+		
+		// mutations of a valid query are more likely to get past the header
+		let query = query();
+		for _ in 0..10000 {
+			let mut buf = query.clone();
+			for _ in 0..(next() % 4 + 1) {
+				let index = next() as usize % buf.len();
+				buf[index] = next() as u8;
+			}
+			check(&buf);
+		}
+	}
+	
+	#[test]
+	fn test_edns() {
+		let mut message = make_message_from_question(vec![]);
+		message.header.rcode = 16;
+		message.edns = Some(Edns {
+			udp_payload_size: 1232,
+			extended_rcode_and_flags: Edns::DNSSEC_OK,
+			options: vec![EdnsOption {
+				code: 10,
+				data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+			}],
+		});
+		let buf = serialize(&message, 512);
+		// the lower 4 bits of the rcode go in the header
+		assert_eq!(buf[3] & 0b1111, 0);
+		
+		let parsed = parse(&buf).unwrap();
+		assert_eq!(parsed.header.rcode, 16);
+		assert!(parsed.additional.is_empty());
+		assert_eq!(parsed.edns, Some(Edns {
+			udp_payload_size: 1232,
+			extended_rcode_and_flags: Edns::DNSSEC_OK,
+			options: vec![EdnsOption {
+				code: 10,
+				data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+			}],
+		}));
+		assert_eq!(parsed.edns.unwrap().version(), 0);
+	}
+	
+	#[test]
+	fn test_edns_truncation() {
+		// an additional section that fits in 512 bytes, but not along with the 11 byte OPT record
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.header.qr = true;
+		message.additional = (0..30).map(|i| Resource {
+			rname: vec![format!("ns{:02}", i), "example".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 60,
+			rdata: vec![10, 0, 0, i],
+		}).collect();
+		message.additional.truncate(15);
+		message.additional[14].rdata = vec![0; 5];
+		assert_eq!(serialize(&message, u16::MAX).len(), 510);
+		
+		message.edns = Some(Edns {
+			udp_payload_size: 1232,
+			..Default::default()
+		});
+		let buf = serialize(&message, 512);
+		assert!(buf.len() <= 512);
+		let parsed = parse(&buf).unwrap();
+		// additional records can be left out without truncating the message
+		assert!(!parsed.header.tc);
+		assert!(parsed.additional.len() < message.additional.len());
+		assert!(parsed.edns.is_some());
+		
+		// empty labels take no space
+		message.additional = vec![Resource {
+			rname: vec!["".to_string(), "example".to_string(), "".to_string(), "com".to_string()],
+			rtype: record_type::A,
+			rclass: 1,
+			ttl: 60,
+			rdata: vec![10, 0, 0, 1],
+		}];
+		let parsed = parse(&serialize(&message, 512)).unwrap();
+		assert_eq!(parsed.additional[0].rname, vec!["example".to_string(), "com".to_string()]);
+	}	
+	fn resource(name: &str, rtype: u16, rdata: Vec<u8>) -> Resource {
+		Resource {
+			rname: name.split('.').map(|label| label.to_string()).collect(),
+			rtype,
+			rclass: 1,
+			ttl: 300,
+			rdata,
+		}
+	}
+	
+	fn assert_round_trip(message: &Message, max_size: u16) {
+		assert_eq!(&parse(&serialize(message, max_size)).unwrap(), message);
+	}
+	
+	#[test]
+	fn test_round_trip_query() {
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["www".to_string(), "example".to_string(), "com".to_string()],
+			qtype: record_type::AAAA,
+			qclass: 1,
+		}]);
+		message.header.id = 0xbeef;
+		message.edns = Some(Edns {
+			udp_payload_size: 4096,
+			extended_rcode_and_flags: Edns::DNSSEC_OK,
+			options: vec![EdnsOption {
+				code: 10,
+				data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+			}],
+		});
+		assert_round_trip(&message, 512);
+	}
+	
+	#[test]
+	fn test_round_trip_response() {
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::ANY,
+			qclass: 1,
+		}]);
+		message.header.id = 7;
+		message.header.qr = true;
+		message.header.aa = true;
+		message.header.ra = true;
+		message.answer = vec![
+			resource("example.com", record_type::A, vec![10, 0, 0, 1]),
+			resource("example.com", record_type::AAAA, vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+			resource("www.example.com", record_type::CNAME, serialize_name(vec!["example", "com"])),
+			resource("example.com", record_type::MX, serialize_mx("mail.example.com", 10)),
+			resource("example.com", record_type::TXT, serialize_txt("hello")),
+			resource("_sip._tcp.example.com", record_type::SRV, serialize_srv(1, 2, 5060, "sip.example.com")),
+			resource("1.0.0.10.in-addr.arpa", record_type::PTR, serialize_name(vec!["example", "com"])),
+			resource("example.com", 65, vec![0, 1, 0]),
+		];
+		message.authority = vec![
+			resource("example.com", record_type::SOA, serialize_soa("ns1.example.com", "admin.example.com", 1, 2, 3, 4, 5)),
+			resource("example.com", record_type::NS, serialize_name(vec!["ns1", "example", "com"])),
+		];
+		message.additional = vec![resource("ns1.example.com", record_type::A, vec![10, 0, 0, 53])];
+		assert_round_trip(&message, 512);
+		
+		// with an extended rcode, which is split between the header and the OPT record
+		message.header.rcode = 16;
+		message.edns = Some(Edns {
+			udp_payload_size: 1232,
+			..Default::default()
+		});
+		assert_round_trip(&message, 512);
+	}
+	
+	#[test]
+	fn test_round_trip_512() {
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.header.qr = true;
+		message.answer = (0..15).map(|i| resource(&format!("ns{:02}.example.com", i), record_type::A, vec![10, 0, 0, i])).collect();
+		message.answer[14].rdata = vec![0; 7];
+		assert_eq!(serialize(&message, 512).len(), 512);
+		assert_round_trip(&message, 512);
+		
+		// one byte more and the last record doesn't fit
+		message.answer[14].rdata.push(0);
+		let parsed = parse(&serialize(&message, 512)).unwrap();
+		assert!(parsed.header.tc);
+		assert_eq!(parsed.answer[..], message.answer[..14]);
+	}
+	
+	#[test]
+	fn test_truncation() {
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.header.qr = true;
+		message.answer = vec![
+			resource("a.example.com", record_type::A, vec![10, 0, 0, 1]),
+			resource("b.example.com", record_type::A, vec![10, 0, 0, 2]),
+			resource("b.example.com", record_type::A, vec![10, 0, 0, 3]),
+		];
+		message.authority = vec![
+			resource("example.com", record_type::NS, serialize_name(vec!["ns1", "example", "com"])),
+			resource("example.com", record_type::NS, serialize_name(vec!["ns2", "example", "com"])),
+		];
+		message.additional = vec![
+			resource("ns1.example.com", record_type::A, vec![10, 0, 0, 53]),
+			resource("ns2.example.com", record_type::A, vec![10, 0, 0, 54]),
+			resource("ns2.example.com", record_type::A, vec![10, 0, 0, 55]),
+		];
+		let len = |message: &Message, answer: usize, authority: usize, additional: usize| {
+			let mut message = message.clone();
+			message.answer.truncate(answer);
+			message.authority.truncate(authority);
+			message.additional.truncate(additional);
+			serialize(&message, u16::MAX).len() as u16
+		};
+		let truncate = |message: &Message, max_size: u16| {
+			let buf = serialize(message, max_size);
+			assert!(buf.len() <= max_size as usize);
+			parse(&buf).unwrap()
+		};
+		
+		// additional records go first, without truncating the message, and the RRset that doesn't
+		// fit goes entirely
+		let parsed = truncate(&message, len(&message, 3, 2, 3) - 1);
+		assert!(!parsed.header.tc);
+		assert_eq!(parsed.answer, message.answer);
+		assert_eq!(parsed.authority, message.authority);
+		assert_eq!(parsed.additional, message.additional[..1]);
+		
+		// then authority records
+		let parsed = truncate(&message, len(&message, 3, 2, 0) - 1);
+		assert!(!parsed.header.tc);
+		assert_eq!(parsed.answer, message.answer);
+		assert!(parsed.authority.is_empty());
+		assert!(parsed.additional.is_empty());
+		
+		// unless they're a referral, which can't be done without
+		let mut referral = message.clone();
+		referral.answer.clear();
+		let parsed = truncate(&referral, len(&referral, 0, 2, 0) - 1);
+		assert!(parsed.header.tc);
+		assert!(parsed.authority.is_empty());
+		
+		// then answers, which truncates the message
+		let parsed = truncate(&message, len(&message, 3, 0, 0) - 1);
+		assert!(parsed.header.tc);
+		assert_eq!(parsed.answer, message.answer[..1]);
+		assert!(parsed.authority.is_empty());
+		
+		// the OPT record is always kept
+		message.edns = Some(Edns {
+			udp_payload_size: 1232,
+			..Default::default()
+		});
+		let parsed = truncate(&message, len(&message, 3, 0, 0) - 1);
+		assert!(parsed.header.tc);
+		assert_eq!(parsed.answer, message.answer[..1]);
+		assert!(parsed.edns.is_some());
+		
+		// and the header is kept whatever the size
+		assert_eq!(serialize(&make_message_from_question(vec![]), 5).len(), 12);
+	}
+	
+	#[test]
+	fn test_tsig() {
+		let mut message = make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::A,
+			qclass: 1,
+		}]);
+		message.answer = vec![
+			resource("example.com", record_type::A, vec![10, 0, 0, 1]),
+			resource("example.com", record_type::A, vec![10, 0, 0, 2]),
+		];
+		message.additional = vec![resource("ns1.example.com", record_type::A, vec![10, 0, 0, 53])];
+		message.edns = Some(Edns { udp_payload_size: 1232, ..Default::default() });
+		message.tsig = Some(Resource { rclass: 255, ttl: 0, ..resource("key", record_type::TSIG, vec![0; 20]) });
+		assert_round_trip(&message, u16::MAX);
+		
+		// it comes after the OPT record
+		let buf = serialize(&message, u16::MAX);
+		assert_eq!(&buf[buf.len() - 35..buf.len() - 25], &[3, b'k', b'e', b'y', 0, 0, 250, 0, 255, 0]);
+		
+		// and is kept when the rest is truncated, here with only room for the question, the OPT
+		// record, and itself
+		let parsed = parse(&serialize(&message, 12 + 17 + 11 + 35)).unwrap();
+		assert!(parsed.header.tc);
+		assert_eq!(parsed.answer.len(), 0);
+		assert_eq!(parsed.tsig, message.tsig);
+		
+		// anywhere but last, it's malformed
+		let mut buf = serialize(&Message { tsig: None, ..message.clone() }, u16::MAX);
+		let mut tsig_first = serialize(&Message { additional: vec![], edns: None, ..message.clone() }, u16::MAX);
+		tsig_first[11] = 2;
+		tsig_first.extend_from_slice(&buf.split_off(buf.len() - 11));
+		assert!(matches!(parse(&tsig_first), Err(ParseError::Malformed(..))));
+	}
+	
+	#[test]
+	fn test_rdata_decompression() {
+		let mut buf = header(1, 4);
+		buf[2] |= 0x80;
+		buf.extend_from_slice(&[7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 255, 0, 1]);
+		// MX, PTR, SRV, and SOA, with their names pointing back at the question
+		buf.extend_from_slice(&[0xc0, 12, 0, 15, 0, 1, 0, 0, 0, 60, 0, 9, 0, 10, 4, b'm', b'a', b'i', b'l', 0xc0, 12]);
+		buf.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+		buf.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 60, 0, 8, 0, 1, 0, 2, 0, 80, 0xc0, 12]);
+		buf.extend_from_slice(&[0xc0, 12, 0, 6, 0, 1, 0, 0, 0, 60, 0, 24, 0xc0, 12, 0xc0, 12]);
+		buf.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5]);
+		
+		let message = parse(&buf).unwrap();
+		let rdata: Vec<Vec<u8>> = message.answer.iter().map(|resource| resource.rdata.clone()).collect();
+		assert_eq!(rdata, vec![
+			serialize_mx("mail.example.com", 10),
+			serialize_name(vec!["example", "com"]),
+			serialize_srv(1, 2, 80, "example.com"),
+			serialize_soa("example.com", "example.com", 1, 2, 3, 4, 5),
+		]);
+		assert_round_trip(&message, u16::MAX);
+	}
+	
+	#[test]
+	fn test_rdata_decompression_rfc3597() {
+		let mut buf = header(1, 5);
+		buf[2] |= 0x80;
+		buf.extend_from_slice(&[7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 255, 0, 1]);
+		// NAPTR, RRSIG, RP, and AFSDB, with their names pointing back at the question
+		buf.extend_from_slice(&[0xc0, 12, 0, 35, 0, 1, 0, 0, 0, 60, 0, 17, 0, 10, 0, 20, 1, b'u', 7, b'E', b'2', b'U', b'+', b's', b'i', b'p', 0, 0xc0, 12]);
+		buf.extend_from_slice(&[0xc0, 12, 0, 46, 0, 1, 0, 0, 0, 60, 0, 24, 0, 1, 13, 2, 0, 0, 0, 60, 0, 0, 0, 2, 0, 0, 0, 1, 0x30, 0x39, 0xc0, 12, 1, 2, 3, 4]);
+		buf.extend_from_slice(&[0xc0, 12, 0, 17, 0, 1, 0, 0, 0, 60, 0, 4, 0xc0, 12, 0xc0, 12]);
+		buf.extend_from_slice(&[0xc0, 12, 0, 18, 0, 1, 0, 0, 0, 60, 0, 4, 0, 1, 0xc0, 12]);
+		// a type we don't know is opaque, even where it looks like a pointer
+		buf.extend_from_slice(&[0xc0, 12, 0xff, 0, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+		
+		let message = parse(&buf).unwrap();
+		let rdata: Vec<Vec<u8>> = message.answer.iter().map(|resource| resource.rdata.clone()).collect();
+		let name = serialize_name(vec!["example", "com"]);
+		assert_eq!(rdata, vec![
+			serialize_naptr(10, 20, "u", "E2U+sip", "", "example.com"),
+			[&[0, 1, 13, 2, 0, 0, 0, 60, 0, 0, 0, 2, 0, 0, 0, 1, 0x30, 0x39], &name[..], &[1, 2, 3, 4]].concat(),
+			[&name[..], &name[..]].concat(),
+			[&[0, 1], &name[..]].concat(),
+			vec![0xc0, 12],
+		]);
+		
+		// the expanded names stand on their own in a response to some other question
+		let response = Message {
+			question: vec![Question { qname: vec!["other".to_string(), "test".to_string()], qtype: record_type::ANY, qclass: 1 }],
+			answer: message.answer[..4].to_vec(),
+			..message.clone()
+		};
+		assert_eq!(parse(&serialize(&response, u16::MAX)).unwrap().answer, response.answer);
+	}
+	
+	#[test]
+	fn test_rdata_length_mismatch() {
+		// an MX record claiming one byte more than its contents
+		let mut buf = header(0, 1);
+		buf.extend_from_slice(&[0, 0, 15, 0, 1, 0, 0, 0, 60, 0, 6, 0, 10, 1, b'a', 0, 0]);
+		assert!(parse(&buf).is_err());
+		
+		// and one byte less
+		let mut buf = header(0, 1);
+		buf.extend_from_slice(&[0, 0, 15, 0, 1, 0, 0, 0, 60, 0, 4, 0, 10, 1, b'a', 0]);
+		assert!(parse(&buf).is_err());
+		
+		let mut buf = header(0, 1);
+		buf.extend_from_slice(&[0, 0, 15, 0, 1, 0, 0, 0, 60, 0, 5, 0, 10, 1, b'a', 0]);
+		assert!(parse(&buf).is_ok());
+	}
+	
+	#[test]
+	fn test_invalid_names() {
+		// label types other than plain labels and pointers are reserved
+		let mut buf = header(1, 0);
+		buf.extend_from_slice(&[0x40, b'a', 0, 0, 1, 0, 1]);
+		assert!(parse(&buf).is_err());
+		
+		// names are limited to 255 bytes
+		for (labels, valid) in &[(3, true), (4, false)] {
+			let mut buf = header(1, 0);
+			for _ in 0..*labels {
+				buf.push(63);
+				buf.extend_from_slice(&[b'a'; 63]);
+			}
+			buf.extend_from_slice(&[1, b'a', 0, 0, 1, 0, 1]);
+			assert_eq!(parse(&buf).is_ok(), *valid);
+		}
+	}
+}
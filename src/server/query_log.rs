@@ -0,0 +1,121 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::server::Transport;
+use crate::server::protocol::{Message, record_type};
+
+/// One line of the query log, describing a single answered request.
+#[derive(Debug, Serialize)]
+pub struct QueryLog {
+	/// Seconds since the Unix epoch, with millisecond precision.
+	pub timestamp: f64,
+	pub client: SocketAddr,
+	pub protocol: &'static str,
+	pub qname: String,
+	pub qtype: String,
+	pub rcode: u16,
+	pub answers: usize,
+	/// Time spent making the response, in microseconds.
+	pub elapsed: u128,
+}
+
+impl QueryLog {
+	pub fn new(client: SocketAddr, transport: Transport, response: &Message, elapsed: Duration) -> QueryLog {
+		let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+		let question = response.question.first();
+		return QueryLog {
+			timestamp: timestamp.as_millis() as f64 / 1000.0,
+			client,
+			protocol: match transport {
+				Transport::Udp => "udp",
+				Transport::Tcp => "tcp",
+				Transport::Tls => "tls",
+				Transport::Unix => "unix",
+			},
+			qname: question.map(|question| question.qname.join(".") + ".").unwrap_or_default(),
+			qtype: question.map(|question| type_name(question.qtype)).unwrap_or_default(),
+			rcode: response.header.rcode,
+			answers: response.answer.len(),
+			elapsed: elapsed.as_micros(),
+		};
+	}
+	
+	/// Formats the entry as either `json` or, for anything else, space separated text.
+	pub fn format(&self, format: &str) -> String {
+		if format == "json" {
+			return serde_json::to_string(self).unwrap();
+		}
+		return format!("{:.3} {} {} {} {} rcode={} answers={} elapsed={}us",
+			self.timestamp, self.client, self.protocol, self.qname, self.qtype, self.rcode, self.answers, self.elapsed);
+	}
+}
+
+pub fn type_name(qtype: u16) -> String {
+	return match qtype {
+		record_type::A => "A",
+		record_type::NS => "NS",
+		record_type::CNAME => "CNAME",
+		record_type::SOA => "SOA",
+		record_type::PTR => "PTR",
+		record_type::MX => "MX",
+		record_type::TXT => "TXT",
+		record_type::AAAA => "AAAA",
+		record_type::SRV => "SRV",
+		record_type::SVCB => "SVCB",
+		record_type::HTTPS => "HTTPS",
+		record_type::AXFR => "AXFR",
+		record_type::ANY => "ANY",
+		qtype => return format!("TYPE{}", qtype),
+	}.to_string();
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+	
+	use crate::server::Transport;
+	use crate::server::protocol::{Header, Message, Question, record_type};
+	use crate::server::query_log::QueryLog;
+	
+	fn entry(qtype: u16) -> QueryLog {
+		let response = Message {
+			header: Header { rcode: 3, ..Default::default() },
+			question: vec![Question {
+				qname: vec!["example".to_string(), "com".to_string()],
+				qtype,
+				qclass: 1,
+			}],
+			answer: vec![],
+			authority: vec![],
+			additional: vec![],
+			edns: None,
+			tsig: None,
+		};
+		let mut entry = QueryLog::new("127.0.0.1:5353".parse().unwrap(), Transport::Tls, &response, Duration::from_micros(42));
+		entry.timestamp = 1.5;
+		return entry;
+	}
+	
+	#[test]
+	fn test_text() {
+		assert_eq!(entry(record_type::AAAA).format("text"), "1.500 127.0.0.1:5353 tls example.com. AAAA rcode=3 answers=0 elapsed=42us");
+		assert_eq!(entry(257).format("text"), "1.500 127.0.0.1:5353 tls example.com. TYPE257 rcode=3 answers=0 elapsed=42us");
+	}
+	
+	#[test]
+	fn test_json() {
+		let json: serde_json::Value = serde_json::from_str(&entry(record_type::A).format("json")).unwrap();
+		assert_eq!(json, serde_json::json!({
+			"timestamp": 1.5,
+			"client": "127.0.0.1:5353",
+			"protocol": "tls",
+			"qname": "example.com.",
+			"qtype": "A",
+			"rcode": 3,
+			"answers": 0,
+			"elapsed": 42,
+		}));
+	}
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+/// How often buckets that have refilled are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What to do with a request from a client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+	Allow,
+	/// Send a truncated response, so that legitimate clients retry over TCP.
+	Slip,
+	Drop,
+}
+
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+	limited: u32,
+}
+
+/// Response rate limiting: a token bucket per client, refilling at `rate` requests per second up to
+/// `burst`. Every `slip`th limited request slips rather than being dropped, or none if it's 0.
+///
+/// https://kb.isc.org/docs/aa-00994
+pub struct RateLimiter {
+	rate: f64,
+	burst: f64,
+	slip: u32,
+	buckets: HashMap<IpAddr, Bucket>,
+	pruned: Instant,
+}
+
+impl RateLimiter {
+	pub fn new(rate: u32, burst: u32, slip: u32) -> RateLimiter {
+		return RateLimiter {
+			rate: rate as f64,
+			burst: burst.max(1) as f64,
+			slip,
+			buckets: HashMap::new(),
+			pruned: Instant::now(),
+		};
+	}
+	
+	pub fn check(&mut self, client: IpAddr, now: Instant) -> Verdict {
+		if now.saturating_duration_since(self.pruned) >= PRUNE_INTERVAL {
+			self.prune(now);
+		}
+		
+		// IPv6 clients usually have a whole /56 or more to themselves
+		let client = match client {
+			IpAddr::V6(client) => IpAddr::V6(Ipv6Addr::from(u128::from(client) & !((1 << 72) - 1))),
+			client => client,
+		};
+		let burst = self.burst;
+		let bucket = self.buckets.entry(client).or_insert(Bucket { tokens: burst, updated: now, limited: 0 });
+		bucket.tokens = (bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * self.rate).min(burst);
+		bucket.updated = now;
+		
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			return Verdict::Allow;
+		}
+		bucket.limited = bucket.limited.wrapping_add(1);
+		if self.slip != 0 && bucket.limited.is_multiple_of(self.slip) {
+			return Verdict::Slip;
+		}
+		return Verdict::Drop;
+	}
+	
+	/// Forgets the buckets that would be full by now, as they're no different from new ones.
+	fn prune(&mut self, now: Instant) {
+		let (rate, burst) = (self.rate, self.burst);
+		self.buckets.retain(|_, bucket| bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * rate < burst);
+		self.pruned = now;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::IpAddr;
+	use std::time::{Duration, Instant};
+	
+	use crate::server::rrl::{RateLimiter, Verdict};
+	
+	fn ip(ip: &str) -> IpAddr {
+		ip.parse().unwrap()
+	}
+	
+	#[test]
+	fn test_bucket() {
+		let mut limiter = RateLimiter::new(2, 3, 0);
+		let now = Instant::now();
+		for _ in 0..3 {
+			assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Allow);
+		}
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Drop);
+		// other clients have their own bucket
+		assert_eq!(limiter.check(ip("192.0.2.2"), now), Verdict::Allow);
+		
+		// refills at the rate
+		let now = now + Duration::from_millis(500);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Allow);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Drop);
+		
+		// but only up to the burst
+		let now = now + Duration::from_secs(60 * 60);
+		for _ in 0..3 {
+			assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Allow);
+		}
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Drop);
+	}
+	
+	#[test]
+	fn test_ipv6_prefix() {
+		let mut limiter = RateLimiter::new(1, 1, 0);
+		let now = Instant::now();
+		assert_eq!(limiter.check(ip("2001:db8:0:1::1"), now), Verdict::Allow);
+		assert_eq!(limiter.check(ip("2001:db8:0:1::2"), now), Verdict::Drop);
+		assert_eq!(limiter.check(ip("2001:db8:0:100::1"), now), Verdict::Allow);
+	}
+	
+	#[test]
+	fn test_slip() {
+		let mut limiter = RateLimiter::new(1, 1, 2);
+		let now = Instant::now();
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Allow);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Drop);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Slip);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Drop);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Slip);
+		
+		let mut limiter = RateLimiter::new(1, 1, 1);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Allow);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Slip);
+		assert_eq!(limiter.check(ip("192.0.2.1"), now), Verdict::Slip);
+	}
+	
+	#[test]
+	fn test_prune() {
+		let mut limiter = RateLimiter::new(1, 2, 0);
+		let now = Instant::now();
+		limiter.check(ip("192.0.2.1"), now);
+		limiter.check(ip("192.0.2.2"), now + Duration::from_secs(59));
+		limiter.check(ip("192.0.2.2"), now + Duration::from_secs(59));
+		assert_eq!(limiter.buckets.len(), 2);
+		
+		// the first has refilled by now, while the second hasn't
+		limiter.check(ip("192.0.2.3"), now + Duration::from_secs(60));
+		assert_eq!(limiter.buckets.len(), 2);
+		assert!(!limiter.buckets.contains_key(&ip("192.0.2.1")));
+	}
+}
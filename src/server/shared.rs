@@ -0,0 +1,177 @@
+//! The config being served, shared by every thread. A request takes the current config and lets go
+//! of the lock straight away, so that lookups never hold up a change, while a change is made to a
+//! copy that's swapped in whole once it's done, so that no request sees it half made.
+//!
+//! The records added by dynamic updates are kept alongside the config, and layered over it once
+//! for each change to either rather than for every request.
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::config::Config;
+use crate::server::answers::AnswerCache;
+use crate::server::checks::Health;
+use crate::server::stats::Stats;
+use crate::server::update::Updates;
+use crate::server::views::ClientViews;
+
+/// The config being served, which can be cloned and sent off to other threads.
+#[derive(Clone)]
+pub struct SharedConfig {
+	current: Arc<RwLock<Current>>,
+	/// Held while a change is made, so that each change is made on top of the one before it.
+	changing: Arc<Mutex<()>>,
+	/// Counts of the queries answered and the results of the health checks, which are kept as the
+	/// config changes.
+	stats: Stats,
+	health: Health,
+}
+
+/// What a request is answered from: the config as `SharedConfig::served` gives it, the answers
+/// cached from it and the configs its clients see, and the counts to add the request to.
+#[derive(Clone)]
+pub struct Answering {
+	pub config: Arc<Config>,
+	pub answers: AnswerCache,
+	pub views: ClientViews,
+	pub stats: Stats,
+}
+
+impl Answering {
+	/// The config as a client sees it, see `views`.
+	pub fn client_view(&self, client: IpAddr) -> Arc<Config> {
+		return self.views.get(&self.config, client);
+	}
+}
+
+#[derive(Clone)]
+struct Current {
+	/// As configured, which is what the API changes.
+	config: Arc<Config>,
+	updates: Arc<Updates>,
+	/// The config with the updates layered over it, which is what's answered from.
+	served: Arc<Config>,
+	/// The answers cached from `served` and the configs its clients see, which start out empty with
+	/// each change.
+	answers: AnswerCache,
+	views: ClientViews,
+}
+
+impl SharedConfig {
+	pub fn new(config: Config) -> SharedConfig {
+		let config = Arc::new(config);
+		return SharedConfig {
+			current: Arc::new(RwLock::new(Current { config: config.clone(), updates: Arc::default(), views: ClientViews::new(&config), served: config, answers: AnswerCache::default() })),
+			changing: Arc::new(Mutex::new(())),
+			stats: Stats::default(),
+			health: Health::default(),
+		};
+	}
+	
+	fn current(&self) -> Current {
+		return self.current.read().unwrap_or_else(|error| error.into_inner()).clone();
+	}
+	
+	/// The config as it is now, without the records added by updates, which stays the same for as
+	/// long as it's held.
+	pub fn get(&self) -> Arc<Config> {
+		return self.current().config;
+	}
+	
+	/// The config as it is now with the records added by updates, which is what to answer from.
+	pub fn served(&self) -> Arc<Config> {
+		return self.current().served;
+	}
+	
+	/// What to answer a request from now.
+	pub fn answering(&self) -> Answering {
+		let current = self.current();
+		return Answering { config: current.served, answers: current.answers, views: current.views, stats: self.stats.clone() };
+	}
+	
+	pub fn stats(&self) -> &Stats {
+		return &self.stats;
+	}
+	
+	pub fn health(&self) -> &Health {
+		return &self.health;
+	}
+	
+	/// Makes a change to a copy of the config, and if it succeeds, swaps it in as `swap` does.
+	/// Returns what the change gave along with the changed config.
+	pub fn change<T, E>(&self, change: impl FnOnce(&mut Config) -> Result<T, E>) -> Result<(T, Arc<Config>), E> {
+		let _changing = self.changing.lock().unwrap_or_else(|error| error.into_inner());
+		let current = self.current();
+		let mut config = Config::clone(&current.config);
+		let changed = change(&mut config)?;
+		return Ok((changed, self.swap(config, current.updates)));
+	}
+	
+	/// Makes a change to a copy of the records added by updates, given the config with and without
+	/// them, and swaps them in as `swap` does if they changed. Returns what the change gave, along
+	/// with the changed config if anything changed.
+	pub fn update<T>(&self, update: impl FnOnce(&Config, &Config, &mut Updates) -> T) -> (T, Option<Arc<Config>>) {
+		let _changing = self.changing.lock().unwrap_or_else(|error| error.into_inner());
+		let current = self.current();
+		let mut updates = Updates::clone(&current.updates);
+		let updated = update(&current.config, &current.served, &mut updates);
+		if updates == *current.updates {
+			return (updated, None);
+		}
+		// the records are changed in the updates rather than the config, which only gets a new serial
+		return (updated, Some(self.swap(Config::clone(&current.config), Arc::new(updates))));
+	}
+	
+	/// Bumps the serial of a changed config so that secondaries see the change, indexes its zones
+	/// again, drops the answers cached from before it, and swaps it in along with the updates layered
+	/// over it.
+	fn swap(&self, mut config: Config, updates: Arc<Updates>) -> Arc<Config> {
+		config.serial = config.serial.wrapping_add(1);
+		config.compile();
+		let config = Arc::new(config);
+		let served = if updates.is_empty() { config.clone() } else { Arc::new(updates.layer_over(Config::clone(&config))) };
+		*self.current.write().unwrap_or_else(|error| error.into_inner()) = Current { config: config.clone(), updates, views: ClientViews::new(&served), served, answers: AnswerCache::default() };
+		return config;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::Arc;
+	
+	use crate::config;
+	use crate::server::shared::SharedConfig;
+	
+	#[test]
+	fn test_change() {
+		let shared = SharedConfig::new(config::parse("zones:\n  example.com:\n    A: 10.0.0.1").unwrap());
+		let before = shared.get();
+		
+		// a failed change leaves the config as it was
+		assert_eq!(shared.change(|config| {
+			config.zones.clear();
+			Err::<(), _>("invalid")
+		}), Err("invalid"));
+		assert_eq!(shared.get(), before);
+		
+		// while one that succeeds is swapped in, leaving the config held from before it alone
+		let (count, after) = shared.change(|config| {
+			config.zones.clear();
+			Ok::<_, ()>(before.zones.len())
+		}).unwrap();
+		assert_eq!(count, 1);
+		assert_eq!(shared.get(), after);
+		assert!(after.zones.is_empty());
+		assert_eq!(after.serial, before.serial.wrapping_add(1));
+		assert_eq!(before.zones.len(), 1);
+	}
+	
+	#[test]
+	fn test_update() {
+		let shared = SharedConfig::new(config::parse("zones:\n  example.com:\n    A: 10.0.0.1").unwrap());
+		let served = shared.served();
+		// an update that changes nothing doesn't bump the serial, or layer anything again
+		assert_eq!(shared.update(|_, _, _| 5), (5, None));
+		assert!(Arc::ptr_eq(&shared.served(), &served));
+	}
+}
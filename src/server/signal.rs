@@ -0,0 +1,93 @@
+//! SIGUSR1, on which the statistics are printed. A signal handler can't do much safely, so it only
+//! writes a byte to a pipe, and a thread reading the other end does the rest.
+
+#[cfg(target_os = "linux")]
+pub use linux::on_usr1;
+#[cfg(not(target_os = "linux"))]
+pub use other::on_usr1;
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+	use std::io;
+	
+	/// The signal is only handled on Linux, elsewhere it keeps its default action.
+	pub fn on_usr1<F: Fn() + Send + 'static>(_handler: F) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::fs::File;
+	use std::io::{self, Read};
+	use std::mem;
+	use std::os::unix::io::FromRawFd;
+	use std::sync::atomic::{AtomicI32, Ordering};
+	use std::thread;
+	
+	use libc::{c_int, c_void};
+	
+	/// The end of the pipe the handler writes to, or -1 before it's set up.
+	static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+	
+	extern "C" fn handle(_signal: c_int) {
+		let fd = WRITE_FD.load(Ordering::Relaxed);
+		if fd < 0 { return; }
+		unsafe {
+			// the interrupted code may look at errno right after, so it's left as it was
+			let errno = *libc::__errno_location();
+			libc::write(fd, b"\0".as_ptr() as *const c_void, 1);
+			*libc::__errno_location() = errno;
+		}
+	}
+	
+	/// Calls `handler` on its own thread each time the process gets SIGUSR1. Signals arriving while
+	/// it runs are coalesced, at most one more call following. Only the first call sets it up.
+	pub fn on_usr1<F: Fn() + Send + 'static>(handler: F) -> io::Result<()> {
+		let mut fds: [c_int; 2] = [-1, -1];
+		if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		// a full pipe already has a call coming, so the handler doesn't have to wait for the thread
+		unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK); }
+		let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+		if WRITE_FD.compare_exchange(-1, fds[1], Ordering::SeqCst, Ordering::SeqCst).is_err() {
+			unsafe { libc::close(fds[1]); }
+			return Err(io::Error::new(io::ErrorKind::AlreadyExists, "SIGUSR1 is already handled"));
+		}
+		
+		let mut action: libc::sigaction = unsafe { mem::zeroed() };
+		action.sa_sigaction = handle as extern "C" fn(c_int) as libc::sighandler_t;
+		// restarted, so that the sockets being read from don't see it
+		action.sa_flags = libc::SA_RESTART;
+		if unsafe { libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		
+		thread::spawn(move || {
+			let mut buffer = [0; 64];
+			while let Ok(read) = reader.read(&mut buffer) {
+				if read == 0 { break; }
+				handler();
+			}
+		});
+		Ok(())
+	}
+	
+	#[cfg(test)]
+	mod test {
+		use std::sync::mpsc;
+		use std::time::Duration;
+		
+		use crate::server::signal::on_usr1;
+		
+		#[test]
+		fn test_on_usr1() {
+			let (sender, receiver) = mpsc::channel();
+			on_usr1(move || sender.send(()).unwrap()).unwrap();
+			unsafe { libc::raise(libc::SIGUSR1); }
+			receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+			assert!(on_usr1(|| {}).is_err());
+		}
+	}
+}
@@ -0,0 +1,177 @@
+//! Checks at startup that the servers names are looked up against can be reached, so that a
+//! mistyped `--resolver` or RNS host shows up before the listeners start rather than as SERVFAILs
+//! at query time.
+
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::config::{Config, RnsHost};
+use crate::options::Options;
+use crate::server::{handle_dns, resolver_lookup_within, Response, Upstream};
+use crate::server::protocol::{class, Question, record_type};
+
+/// How long each server is given to respond. It's shorter than `--resolver-timeout`, as starting up
+/// waits on every server in turn.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Looks names up the way the server does, giving each server a different timeout.
+struct Within<'a> {
+	options: &'a Options,
+	timeout: Duration,
+}
+
+impl Upstream for Within<'_> {
+	fn resolvers(&self) -> &[SocketAddr] {
+		return &self.options.resolver;
+	}
+	
+	fn lookup(&self, question: Question, servers: &[SocketAddr]) -> Response {
+		return resolver_lookup_within(question, servers, self.options, self.timeout);
+	}
+	
+	fn verbose(&self) -> bool {
+		return self.options.verbose;
+	}
+}
+
+/// Checks the resolvers and the servers of RNS records, warning about each that can't be reached,
+/// or with `--strict-startup`, failing with all of them.
+pub fn check_upstreams(options: &Options, config: &Config) -> Result<(), String> {
+	let unreachable = unreachable_upstreams(options, config, CHECK_TIMEOUT);
+	if unreachable.is_empty() {
+		if options.verbose { println!("startup checks passed"); }
+		return Ok(());
+	}
+	if options.strict_startup {
+		return Err(format!("startup checks failed:\n  {}", unreachable.join("\n  ")));
+	}
+	for problem in unreachable {
+		eprintln!("warning: {}", problem);
+	}
+	return Ok(());
+}
+
+/// Describes each resolver and RNS server that didn't respond within `timeout`.
+fn unreachable_upstreams(options: &Options, config: &Config, timeout: Duration) -> Vec<String> {
+	let upstream = Within { options, timeout };
+	let mut unreachable = vec![];
+	
+	// the root's NS records, which any resolver can answer without going far
+	let root = Question { qname: vec![], qtype: record_type::NS, qclass: class::IN };
+	for resolver in &options.resolver {
+		match upstream.lookup(root.clone(), &[*resolver]) {
+			Response::Ok(..) | Response::NameError => {}
+			response => unreachable.push(format!("resolver {} didn't answer a lookup of the root ({:?})", resolver, response)),
+		}
+	}
+	
+	let mut addresses = vec![];
+	let mut hosts = vec![];
+	for rns in config.zones.iter().flat_map(|zone| &zone.records.rns) {
+		match &rns.host {
+			RnsHost::SocketAddr(address) => if !addresses.contains(address) { addresses.push(*address) },
+			RnsHost::HostPort(host, _) => if !hosts.contains(&(host, rns.external)) { hosts.push((host, rns.external)) },
+		}
+	}
+	for address in addresses {
+		if let Err(error) = TcpStream::connect_timeout(&address, timeout) {
+			unreachable.push(format!("RNS server {} couldn't be connected to ({})", address, error));
+		}
+	}
+	for (host, external) in hosts {
+		if !resolves(host, external, &upstream, config) {
+			unreachable.push(format!("RNS host {} has no addresses", host));
+		}
+	}
+	
+	return unreachable;
+}
+
+/// Whether an RNS host has an address, looked up the way an RNS record does: in the config first
+/// unless it's external, then through the resolvers.
+fn resolves(host: &str, external: bool, upstream: &Within, config: &Config) -> bool {
+	return [record_type::AAAA, record_type::A].iter().any(|qtype| {
+		let question = Question {
+			qname: host.split(".").map(|label| label.to_string()).collect(),
+			qtype: *qtype,
+			qclass: class::IN,
+		};
+		if !external {
+			if let Response::Ok(answer, _, _) = handle_dns(&question, upstream, config) {
+				if !answer.is_empty() { return true; }
+			}
+		}
+		return match upstream.lookup(question, upstream.resolvers()) {
+			Response::Ok(answer, _, _) => !answer.is_empty(),
+			_ => false,
+		};
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{SocketAddr, TcpListener, UdpSocket};
+	use std::time::Duration;
+	
+	use crate::server::mock_resolver::MockResolver;
+	use crate::server::protocol::{Question, record_type};
+	use crate::server::startup::{check_upstreams, unreachable_upstreams};
+	use crate::server::test::test_options;
+	
+	/// A loopback address that nothing listens on, over UDP or TCP.
+	fn dead_address() -> SocketAddr {
+		loop {
+			let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+			let address = udp.local_addr().unwrap();
+			if TcpListener::bind(address).is_ok() {
+				return address;
+			}
+		}
+	}
+	
+	#[test]
+	fn test_unreachable_upstreams() {
+		let dead = dead_address();
+		let config = crate::config::parse(&format!(r"zones:
+  ns.local.test:
+    A: 127.0.0.1
+  a.test:
+    RNS: {0}
+  b.test:
+    RNS: {0}
+  c.test:
+    RNS: ns.local.test:53
+  d.test:
+    RNS: ns.missing.test:53", dead)).unwrap();
+		let mut options = test_options();
+		options.resolver = vec![dead];
+		assert_eq!(unreachable_upstreams(&options, &config, Duration::from_millis(200)), vec![
+			format!("resolver {} didn't answer a lookup of the root (ServerFailure)", dead),
+			format!("RNS server {} couldn't be connected to (Connection refused (os error 111))", dead),
+			"RNS host ns.missing.test has no addresses".to_string(),
+		]);
+		
+		// warnings don't stop the server from starting, unless it's strict
+		assert_eq!(check_upstreams(&options, &config), Ok(()));
+		options.strict_startup = true;
+		assert!(check_upstreams(&options, &config).unwrap_err().contains("ns.missing.test"));
+	}
+	
+	#[test]
+	fn test_reachable_upstreams() {
+		let resolver = MockResolver::new();
+		resolver.answer(Question { qname: vec![], qtype: record_type::NS, qclass: 1 }, vec![]);
+		let config = crate::config::parse(&format!(r"zones:
+  ns.local.test:
+    A: 127.0.0.1
+  a.test:
+    RNS: {}
+  c.test:
+    RNS: ns.local.test:53", resolver.address)).unwrap();
+		let mut options = test_options();
+		options.resolver = vec![resolver.address];
+		options.strict_startup = true;
+		assert_eq!(unreachable_upstreams(&options, &config, Duration::from_millis(200)), Vec::<String>::new());
+		assert_eq!(check_upstreams(&options, &config), Ok(()));
+	}
+}
@@ -0,0 +1,377 @@
+//! Counts of the queries each zone gets and how they're answered, along with the names asked about
+//! most, so that busy zones and names can be found without logging every query, and histograms of
+//! the sizes of requests and responses and the rcodes answered with. They're printed as a table on
+//! SIGUSR1, served as JSON at `/stats` on the health port, and reset by the API.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::hash_map::RandomState;
+use std::fmt::Write;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// How many parts the counts are split into by name, each behind its own lock, so that workers
+/// counting different names don't wait on each other.
+const SHARDS: usize = 16;
+
+/// How many names each shard counts at once. Past that, the least asked about is replaced by each
+/// new one.
+const TRACKED_NAMES: usize = 64;
+
+/// How many of the names asked about most are reported.
+const TOP_NAMES: usize = 10;
+
+/// The largest request or response counted in each bucket of the size histograms. 1232 is the
+/// buffer size most resolvers advertise, and the last bucket holds anything TCP can carry.
+const SIZE_BUCKETS: [usize; 7] = [64, 128, 256, 512, 1232, 4096, 65535];
+
+/// The counts kept by the server while it runs, which can be sent off to other threads.
+#[derive(Debug, Clone)]
+pub struct Stats(Arc<Shards>);
+
+#[derive(Debug)]
+struct Shards {
+	hasher: RandomState,
+	since: Mutex<SystemTime>,
+	shards: Vec<Mutex<Counts>>,
+	histograms: Histograms,
+}
+
+/// Counts of every request rather than of a name, so there's nothing to shard them by, and they're
+/// kept in atomics instead of behind a lock that every worker would wait on.
+#[derive(Debug, Default)]
+struct Histograms {
+	request_sizes: [AtomicU64; SIZE_BUCKETS.len()],
+	response_sizes: [AtomicU64; SIZE_BUCKETS.len()],
+	/// Responses by the rcode in their header, which holds the low 4 bits of an extended one.
+	rcodes: [AtomicU64; 16],
+}
+
+impl Histograms {
+	fn count_size(buckets: &[AtomicU64], size: usize) {
+		let bucket = SIZE_BUCKETS.iter().position(|max| size <= *max).unwrap_or(SIZE_BUCKETS.len() - 1);
+		buckets[bucket].fetch_add(1, Ordering::Relaxed);
+	}
+	
+	fn reset(&self) {
+		for count in self.request_sizes.iter().chain(&self.response_sizes).chain(&self.rcodes) {
+			count.store(0, Ordering::Relaxed);
+		}
+	}
+}
+
+/// The name of an rcode, as dig shows it.
+fn rcode_name(rcode: usize) -> String {
+	const NAMES: [&str; 11] = ["NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED", "YXDOMAIN", "YXRRSET", "NXRRSET", "NOTAUTH", "NOTZONE"];
+	return NAMES.get(rcode).map(|name| name.to_string()).unwrap_or_else(|| format!("RCODE{}", rcode));
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct ZoneCounts {
+	pub queries: u64,
+	/// Queries answered with records.
+	pub answers: u64,
+	pub nxdomain: u64,
+	/// Queries answered successfully, but without records.
+	pub no_data: u64,
+	/// Lookups elsewhere made to answer the queries, for ANAME, RNS, forwarding, and the like.
+	pub upstream_lookups: u64,
+}
+
+impl ZoneCounts {
+	fn add(&mut self, other: &ZoneCounts) {
+		self.queries += other.queries;
+		self.answers += other.answers;
+		self.nxdomain += other.nxdomain;
+		self.no_data += other.no_data;
+		self.upstream_lookups += other.upstream_lookups;
+	}
+}
+
+/// The counts of the names in one shard.
+#[derive(Debug, Default)]
+struct Counts {
+	total: ZoneCounts,
+	zones: BTreeMap<String, ZoneCounts>,
+	names: TopNames,
+}
+
+/// The counts of every shard added up, for reporting.
+struct Totals {
+	since: SystemTime,
+	total: ZoneCounts,
+	zones: BTreeMap<String, ZoneCounts>,
+	top_names: Vec<(String, u64)>,
+	/// The largest size of each bucket, with how many requests and responses were in it.
+	sizes: Vec<(usize, u64, u64)>,
+	/// The names of the rcodes responses were answered with, with how many were.
+	rcodes: Vec<(String, u64)>,
+}
+
+/// The names asked about most, kept with the Space-Saving algorithm: a name that isn't counted yet
+/// when every slot is taken replaces the one with the lowest count, and starts from that count. The
+/// count of a name that's asked about often enough to stay is then never less than how often it
+/// was, and overestimates it by at most `error`.
+#[derive(Debug, Default)]
+struct TopNames {
+	/// The count and error of each name.
+	counts: HashMap<String, (u64, u64)>,
+	/// Every name by its count, so that the lowest is found without looking through them all.
+	by_count: BTreeSet<(u64, String)>,
+}
+
+impl TopNames {
+	fn add(&mut self, name: String) {
+		if let Some((count, _)) = self.counts.get_mut(&name) {
+			let mut entry = self.by_count.take(&(*count, name)).unwrap();
+			*count += 1;
+			entry.0 = *count;
+			self.by_count.insert(entry);
+			return;
+		}
+		let (count, error) = if self.counts.len() < TRACKED_NAMES {
+			(1, 0)
+		} else {
+			let (count, least) = self.by_count.pop_first().unwrap();
+			self.counts.remove(&least);
+			(count + 1, count)
+		};
+		self.counts.insert(name.clone(), (count, error));
+		self.by_count.insert((count, name));
+	}
+	
+	/// The names with the highest counts, highest first, and by name between those with the same.
+	fn top(&self, n: usize) -> Vec<(&str, u64)> {
+		let mut top: Vec<(&str, u64)> = self.counts.iter().map(|(name, (count, _))| (name.as_str(), *count)).collect();
+		top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+		top.truncate(n);
+		return top;
+	}
+}
+
+impl Default for Stats {
+	fn default() -> Stats {
+		return Stats(Arc::new(Shards {
+			hasher: RandomState::new(),
+			since: Mutex::new(SystemTime::now()),
+			shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+			histograms: Histograms::default(),
+		}));
+	}
+}
+
+impl Stats {
+	fn shards(&self) -> impl Iterator<Item=MutexGuard<'_, Counts>> {
+		return self.0.shards.iter().map(|shard| shard.lock().unwrap_or_else(|error| error.into_inner()));
+	}
+	
+	/// Counts a query for `name`, answered from `zone` unless no zone matched, with the rcode it
+	/// was answered with and whether it had any records.
+	pub fn record(&self, zone: Option<&str>, name: String, rcode: u16, answered: bool, upstream_lookups: u64) {
+		fn add(counts: &mut ZoneCounts, rcode: u16, answered: bool, upstream_lookups: u64) {
+			counts.queries += 1;
+			match rcode {
+				0 if answered => counts.answers += 1,
+				0 => counts.no_data += 1,
+				3 => counts.nxdomain += 1,
+				_ => {}
+			}
+			counts.upstream_lookups += upstream_lookups;
+		}
+		
+		let shard = &self.0.shards[self.0.hasher.hash_one(&name) as usize % SHARDS];
+		let mut counts = shard.lock().unwrap_or_else(|error| error.into_inner());
+		add(&mut counts.total, rcode, answered, upstream_lookups);
+		if let Some(zone) = zone {
+			match counts.zones.get_mut(zone) {
+				Some(zone_counts) => add(zone_counts, rcode, answered, upstream_lookups),
+				None => add(counts.zones.entry(zone.to_string()).or_default(), rcode, answered, upstream_lookups),
+			}
+		}
+		counts.names.add(name);
+	}
+	
+	/// Counts a request of `size` bytes towards the size histogram, whether or not it's answered.
+	pub fn record_request(&self, size: usize) {
+		Histograms::count_size(&self.0.histograms.request_sizes, size);
+	}
+	
+	/// Counts a response of `size` bytes towards the size and rcode histograms. Each message of a
+	/// zone transfer is a response of its own.
+	pub fn record_response(&self, size: usize, rcode: u16) {
+		Histograms::count_size(&self.0.histograms.response_sizes, size);
+		self.0.histograms.rcodes[rcode as usize & 0x0f].fetch_add(1, Ordering::Relaxed);
+	}
+	
+	/// Starts counting again from zero.
+	pub fn reset(&self) {
+		*self.0.since.lock().unwrap_or_else(|error| error.into_inner()) = SystemTime::now();
+		for mut counts in self.shards() {
+			*counts = Counts::default();
+		}
+		self.0.histograms.reset();
+	}
+	
+	fn totals(&self) -> Totals {
+		let mut totals = Totals {
+			since: *self.0.since.lock().unwrap_or_else(|error| error.into_inner()),
+			total: ZoneCounts::default(),
+			zones: BTreeMap::new(),
+			top_names: vec![],
+			sizes: SIZE_BUCKETS.iter().zip(&self.0.histograms.request_sizes).zip(&self.0.histograms.response_sizes)
+				.map(|((max, requests), responses)| (*max, requests.load(Ordering::Relaxed), responses.load(Ordering::Relaxed)))
+				.collect(),
+			rcodes: self.0.histograms.rcodes.iter().enumerate()
+				.map(|(rcode, count)| (rcode_name(rcode), count.load(Ordering::Relaxed)))
+				.filter(|(_, count)| *count > 0)
+				.collect(),
+		};
+		for counts in self.shards() {
+			totals.total.add(&counts.total);
+			for (zone, zone_counts) in &counts.zones {
+				totals.zones.entry(zone.clone()).or_default().add(zone_counts);
+			}
+			// each name is only counted in one shard, so the top names overall are among those of each
+			totals.top_names.extend(counts.names.top(TOP_NAMES).into_iter().map(|(name, count)| (name.to_string(), count)));
+		}
+		totals.top_names.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+		totals.top_names.truncate(TOP_NAMES);
+		return totals;
+	}
+	
+	pub fn zone(&self, zone: &str) -> Option<ZoneCounts> {
+		return self.totals().zones.get(zone).copied();
+	}
+	
+	pub fn total(&self) -> ZoneCounts {
+		return self.totals().total;
+	}
+	
+	/// The names asked about most, along with about how often.
+	pub fn top_names(&self) -> Vec<(String, u64)> {
+		return self.totals().top_names;
+	}
+	
+	/// The largest size of each bucket of the size histograms, with how many requests and responses
+	/// were in it.
+	pub fn sizes(&self) -> Vec<(usize, u64, u64)> {
+		return self.totals().sizes;
+	}
+	
+	/// How many responses were answered with each rcode, by name, leaving out those none were.
+	pub fn rcodes(&self) -> Vec<(String, u64)> {
+		return self.totals().rcodes;
+	}
+	
+	pub fn json(&self) -> Value {
+		let totals = self.totals();
+		return json!({
+			"seconds": totals.since.elapsed().map(|elapsed| elapsed.as_secs()).unwrap_or(0),
+			"total": totals.total,
+			"zones": totals.zones,
+			"top_names": totals.top_names.iter()
+				.map(|(name, queries)| json!({ "name": name, "queries": queries }))
+				.collect::<Vec<Value>>(),
+			"sizes": totals.sizes.iter()
+				.map(|(max, requests, responses)| json!({ "max": max, "requests": requests, "responses": responses }))
+				.collect::<Vec<Value>>(),
+			"rcodes": totals.rcodes.iter().cloned().collect::<BTreeMap<String, u64>>(),
+		});
+	}
+	
+	/// The counts as a table, for the log.
+	pub fn table(&self) -> String {
+		let totals = self.totals();
+		let width = totals.zones.keys().map(String::len)
+			.chain(totals.top_names.iter().map(|(name, _)| name.len()))
+			.chain(totals.rcodes.iter().map(|(rcode, _)| rcode.len()))
+			.chain(["(total)".len(), "top names".len(), "size up to".len()].iter().copied())
+			.max()
+			.unwrap();
+		
+		let mut table = String::new();
+		writeln!(table, "statistics for the last {} seconds:", totals.since.elapsed().map(|elapsed| elapsed.as_secs()).unwrap_or(0)).unwrap();
+		writeln!(table, "{:width$}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}", "zone", "queries", "answers", "nxdomain", "no data", "upstream", width = width).unwrap();
+		for (zone, zone_counts) in totals.zones.iter().chain(std::iter::once((&"(total)".to_string(), &totals.total))) {
+			writeln!(table, "{:width$}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}", zone, zone_counts.queries, zone_counts.answers, zone_counts.nxdomain, zone_counts.no_data, zone_counts.upstream_lookups, width = width).unwrap();
+		}
+		writeln!(table, "{:width$}  {:>10}", "top names", "queries", width = width).unwrap();
+		for (name, queries) in totals.top_names {
+			writeln!(table, "{:width$}  {:>10}", name, queries, width = width).unwrap();
+		}
+		writeln!(table, "{:width$}  {:>10}  {:>10}", "size up to", "requests", "responses", width = width).unwrap();
+		for (max, requests, responses) in totals.sizes {
+			writeln!(table, "{:width$}  {:>10}  {:>10}", max, requests, responses, width = width).unwrap();
+		}
+		writeln!(table, "{:width$}  {:>10}", "rcode", "responses", width = width).unwrap();
+		for (rcode, responses) in totals.rcodes {
+			writeln!(table, "{:width$}  {:>10}", rcode, responses, width = width).unwrap();
+		}
+		return table;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::server::stats::{Stats, TopNames, TRACKED_NAMES, ZoneCounts};
+	
+	#[test]
+	fn test_record() {
+		let stats = Stats::default();
+		stats.record(Some("example.com"), "example.com".to_string(), 0, true, 0);
+		stats.record(Some("example.com"), "www.example.com".to_string(), 0, false, 2);
+		stats.record(Some("example.com"), "www.example.com".to_string(), 3, false, 0);
+		stats.record(None, "example.org".to_string(), 5, false, 0);
+		assert_eq!(stats.zone("example.com"), Some(ZoneCounts { queries: 3, answers: 1, nxdomain: 1, no_data: 1, upstream_lookups: 2 }));
+		assert_eq!(stats.total(), ZoneCounts { queries: 4, answers: 1, nxdomain: 1, no_data: 1, upstream_lookups: 2 });
+		assert_eq!(stats.top_names()[0], ("www.example.com".to_string(), 2));
+		
+		let table = stats.table();
+		let has_row = |row: &[&str]| table.lines().any(|line| line.split_whitespace().collect::<Vec<&str>>() == row);
+		assert!(has_row(&["example.com", "3", "1", "1", "1", "2"]), "{}", table);
+		assert!(has_row(&["(total)", "4", "1", "1", "1", "2"]), "{}", table);
+		assert!(has_row(&["www.example.com", "2"]), "{}", table);
+		
+		stats.record_request(40);
+		stats.record_request(64);
+		stats.record_request(70000);
+		stats.record_response(65, 0);
+		stats.record_response(1232, 3);
+		stats.record_response(1233, 3);
+		assert_eq!(stats.sizes(), vec![(64, 2, 0), (128, 0, 1), (256, 0, 0), (512, 0, 0), (1232, 0, 1), (4096, 0, 1), (65535, 1, 0)]);
+		assert_eq!(stats.rcodes(), vec![("NOERROR".to_string(), 1), ("NXDOMAIN".to_string(), 2)]);
+		let table = stats.table();
+		assert!(table.lines().any(|line| line.split_whitespace().collect::<Vec<&str>>() == ["1232", "0", "1"]), "{}", table);
+		assert!(table.lines().any(|line| line.split_whitespace().collect::<Vec<&str>>() == ["NXDOMAIN", "2"]), "{}", table);
+		assert_eq!(stats.json()["rcodes"]["NXDOMAIN"], 2);
+		
+		stats.reset();
+		assert_eq!(stats.total(), ZoneCounts::default());
+		assert!(stats.rcodes().is_empty());
+		assert_eq!(stats.zone("example.com"), None);
+		assert!(stats.top_names().is_empty());
+	}
+	
+	#[test]
+	fn test_top_names() {
+		let mut names = TopNames::default();
+		// a few busy names among many that are only asked about once, more than can be tracked
+		for index in 0..TRACKED_NAMES * 5 {
+			names.add(format!("{}.example.com", index));
+			if index % 10 == 0 { names.add("busy.example.com".to_string()); }
+			if index % 20 == 0 { names.add("warm.example.com".to_string()); }
+		}
+		assert!(names.counts.len() <= TRACKED_NAMES);
+		assert_eq!(names.by_count.len(), names.counts.len());
+		let top = names.top(2);
+		assert_eq!(top[0], ("busy.example.com", (TRACKED_NAMES * 5 / 10) as u64));
+		assert_eq!(top[1].0, "warm.example.com");
+		// never less than the true count, and over it by no more than its error
+		let (count, error) = names.counts["warm.example.com"];
+		assert!(count >= (TRACKED_NAMES * 5 / 20) as u64 && count - error <= (TRACKED_NAMES * 5 / 20) as u64);
+	}
+}
@@ -0,0 +1,135 @@
+//! Where the time answering a request goes, for `--slow-query-threshold`: parsing it, matching it
+//! against the zones, waiting on lookups elsewhere, and serializing the response.
+//!
+//! As with traces, the timing is kept in a thread local rather than passed down to the lookups that
+//! add to it, and only when the slow query log is on, so that nothing is timed otherwise.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::server::protocol::Message;
+use crate::server::query_log::type_name;
+
+/// The phases of answering a request that end one after the other. Lookups elsewhere happen
+/// during `Answer`, and are told apart with `upstream`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+	Parse,
+	Answer,
+	Serialize,
+}
+
+/// How long each phase of a request took.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Phases {
+	pub total: Duration,
+	pub parse: Duration,
+	/// Answering, apart from waiting on lookups elsewhere.
+	pub matching: Duration,
+	pub upstream: Duration,
+	pub serialize: Duration,
+}
+
+struct Timing {
+	started: Instant,
+	lap: Instant,
+	answer: Duration,
+	phases: Phases,
+}
+
+thread_local! {
+	static TIMING: RefCell<Option<Timing>> = const { RefCell::new(None) };
+}
+
+/// Starts timing a request on this thread if `enabled`, throwing away whatever a request that
+/// panicked or was dropped partway through left behind either way.
+pub fn start(enabled: bool) {
+	TIMING.with(|timing| *timing.borrow_mut() = if enabled {
+		let now = Instant::now();
+		Some(Timing { started: now, lap: now, answer: Duration::default(), phases: Phases::default() })
+	} else {
+		None
+	});
+}
+
+/// Ends a phase, counting the time since the last one ended towards it.
+pub fn lap(phase: Phase) {
+	TIMING.with(|timing| {
+		if let Some(timing) = timing.borrow_mut().as_mut() {
+			let now = Instant::now();
+			let elapsed = now - timing.lap;
+			timing.lap = now;
+			match phase {
+				Phase::Parse => timing.phases.parse += elapsed,
+				Phase::Answer => timing.answer += elapsed,
+				Phase::Serialize => timing.phases.serialize += elapsed,
+			}
+		}
+	});
+}
+
+/// Makes a lookup elsewhere, counting the time it takes as upstream rather than matching.
+pub fn upstream<T, F: FnOnce() -> T>(lookup: F) -> T {
+	let started = TIMING.with(|timing| timing.borrow().as_ref().map(|_| Instant::now()));
+	let result = lookup();
+	if let Some(started) = started {
+		TIMING.with(|timing| {
+			if let Some(timing) = timing.borrow_mut().as_mut() {
+				timing.phases.upstream += started.elapsed();
+			}
+		});
+	}
+	return result;
+}
+
+/// Stops timing, returning how long each phase took if timing was started.
+pub fn finish() -> Option<Phases> {
+	return TIMING.with(|timing| timing.borrow_mut().take()).map(|timing| Phases {
+		total: timing.started.elapsed(),
+		matching: timing.answer.checked_sub(timing.phases.upstream).unwrap_or_default(),
+		..timing.phases
+	});
+}
+
+/// One line of the slow query log, with times in microseconds, as in the query log.
+#[derive(Debug, Serialize)]
+pub struct SlowQuery {
+	pub qname: String,
+	pub qtype: String,
+	pub rcode: u16,
+	/// Size of the serialized response, in bytes.
+	pub size: usize,
+	pub elapsed: u128,
+	pub parse: u128,
+	pub matching: u128,
+	pub upstream: u128,
+	pub serialize: u128,
+}
+
+impl SlowQuery {
+	pub fn new(response: &Message, size: usize, phases: Phases) -> SlowQuery {
+		let question = response.question.first();
+		return SlowQuery {
+			qname: question.map(|question| question.qname.join(".") + ".").unwrap_or_default(),
+			qtype: question.map(|question| type_name(question.qtype)).unwrap_or_default(),
+			rcode: response.header.rcode,
+			size,
+			elapsed: phases.total.as_micros(),
+			parse: phases.parse.as_micros(),
+			matching: phases.matching.as_micros(),
+			upstream: phases.upstream.as_micros(),
+			serialize: phases.serialize.as_micros(),
+		};
+	}
+	
+	/// Formats the entry as either `json` or, for anything else, space separated text.
+	pub fn format(&self, format: &str) -> String {
+		if format == "json" {
+			return serde_json::to_string(self).unwrap();
+		}
+		return format!("slow query: {} {} rcode={} size={} elapsed={}us parse={}us matching={}us upstream={}us serialize={}us",
+			self.qname, self.qtype, self.rcode, self.size, self.elapsed, self.parse, self.matching, self.upstream, self.serialize);
+	}
+}
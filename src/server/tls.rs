@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Loads the certificate chain and private key for DNS over TLS: https://tools.ietf.org/html/rfc7858
+pub fn load_config(cert_path: &str, key_path: &str) -> io::Result<Arc<ServerConfig>> {
+	let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+		.into_iter()
+		.map(Certificate)
+		.collect();
+	if certs.is_empty() {
+		return Err(invalid_data(format!("no certificates found in {}", cert_path)));
+	}
+	
+	let mut key = None;
+	for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))? {
+		match item {
+			Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => {
+				key = Some(PrivateKey(der));
+				break;
+			}
+			_ => {}
+		}
+	}
+	let key = key.ok_or_else(|| invalid_data(format!("no private key found in {}", key_path)))?;
+	
+	let config = ServerConfig::builder()
+		.with_safe_defaults()
+		.with_no_client_auth()
+		.with_single_cert(certs, key)
+		.map_err(invalid_data)?;
+	return Ok(Arc::new(config));
+}
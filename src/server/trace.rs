@@ -0,0 +1,137 @@
+//! Traces of how single queries are answered, for working out why a name did or didn't match a
+//! zone without `--verbose` printing everything about every query. A query is traced when it has
+//! the `TRACE_OPTION` EDNS option, which gets the trace back as a TXT record in the additional
+//! section, or when its name matches a `--trace-name` glob, which prints it.
+//!
+//! A request is answered start to finish on one worker thread, so the trace is collected in a
+//! thread local rather than passed down through everything that adds to it.
+
+use std::cell::RefCell;
+
+use crate::server::protocol::{class, record_type, Resource};
+
+/// The EDNS option asking for a trace, from the range for local and experimental use:
+/// https://tools.ietf.org/html/rfc6891#section-9
+pub const TRACE_OPTION: u16 = 65001;
+
+thread_local! {
+	static TRACE: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Starts collecting a trace on this thread if `enabled`, throwing away whatever a request that
+/// panicked partway through left behind either way.
+pub fn start(enabled: bool) {
+	TRACE.with(|trace| *trace.borrow_mut() = if enabled { Some(vec![]) } else { None });
+}
+
+/// Stops collecting, returning the lines of the trace if one was started.
+pub fn finish() -> Option<Vec<String>> {
+	return TRACE.with(|trace| trace.borrow_mut().take());
+}
+
+/// Whether a trace is being collected, for work that's only done to add to it.
+pub fn is_tracing() -> bool {
+	return TRACE.with(|trace| trace.borrow().is_some());
+}
+
+/// Adds a line to the trace, indented by how many names deep the lookup is. The line is only made
+/// when tracing.
+pub fn note<F: FnOnce() -> String>(depth: usize, line: F) {
+	if !is_tracing() { return; }
+	let line = format!("{}{}", "  ".repeat(depth), line());
+	TRACE.with(|trace| {
+		if let Some(trace) = trace.borrow_mut().as_mut() {
+			trace.push(line);
+		}
+	});
+}
+
+/// Whether a name matches a `--trace-name` glob, in which `*` stands for any run of characters,
+/// dots included. Case and a trailing dot are ignored.
+pub fn matches_glob(glob: &str, qname: &[String]) -> bool {
+	let name = qname.join(".").to_lowercase();
+	let glob = glob.trim_end_matches('.').to_lowercase();
+	let mut parts = glob.split('*');
+	let first = parts.next().unwrap();
+	if !name.starts_with(first) { return false; }
+	let mut rest = &name[first.len()..];
+	let parts: Vec<&str> = parts.collect();
+	let (last, middle) = match parts.split_last() {
+		Some(split) => split,
+		None => return rest.is_empty(),
+	};
+	// each part between stars is matched as early as it can be, leaving the most for the rest
+	for part in middle {
+		match rest.find(part) {
+			Some(index) => rest = &rest[index + part.len()..],
+			None => return false,
+		}
+	}
+	return rest.ends_with(last);
+}
+
+/// The trace as a TXT record for the additional section, with a string for each line. It's in the
+/// CHAOS class, as it's about the server rather than the zones, and has a TTL of 0 so that nothing
+/// caches it. Lines are cut to the 255 bytes a string can hold, and those past what the record can
+/// hold are left out.
+pub fn make_record(qname: &[String], lines: &[String]) -> Resource {
+	let mut rdata = vec![];
+	for line in lines {
+		let line = &line.as_bytes()[..line.len().min(255)];
+		if rdata.len() + 1 + line.len() > u16::MAX as usize { break; }
+		rdata.push(line.len() as u8);
+		rdata.extend_from_slice(line);
+	}
+	return Resource {
+		rname: qname.to_vec(),
+		rtype: record_type::TXT,
+		rclass: class::CH,
+		ttl: 0,
+		rdata,
+	};
+}
+
+#[cfg(test)]
+mod test {
+	use crate::server::trace::{finish, make_record, matches_glob, note, start};
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').map(|label| label.to_string()).collect();
+	}
+	
+	#[test]
+	fn test_matches_glob() {
+		assert!(matches_glob("www.example.com", &name("www.example.com")));
+		assert!(matches_glob("WWW.example.com.", &name("www.Example.com")));
+		assert!(!matches_glob("www.example.com", &name("www.example.co")));
+		assert!(matches_glob("*.example.com", &name("a.b.example.com")));
+		assert!(!matches_glob("*.example.com", &name("example.com")));
+		assert!(matches_glob("api-*.*.com", &name("api-1.example.com")));
+		assert!(!matches_glob("api-*.*.com", &name("web-1.example.com")));
+		assert!(matches_glob("*", &name("anything.at.all")));
+		// a part can't be matched twice over
+		assert!(!matches_glob("*ab*ab", &name("ab")));
+	}
+	
+	#[test]
+	fn test_collecting() {
+		start(false);
+		note(0, || panic!("lines aren't made when not tracing"));
+		assert_eq!(finish(), None);
+		
+		start(true);
+		note(0, || "example.com A".to_string());
+		note(1, || "matched".to_string());
+		assert_eq!(finish(), Some(vec!["example.com A".to_string(), "  matched".to_string()]));
+		assert_eq!(finish(), None);
+	}
+	
+	#[test]
+	fn test_make_record() {
+		let record = make_record(&name("example.com"), &["one".to_string(), "x".repeat(300)]);
+		assert_eq!(record.ttl, 0);
+		assert_eq!(&record.rdata[..4], b"\x03one");
+		assert_eq!(record.rdata[4], 255);
+		assert_eq!(record.rdata.len(), 4 + 1 + 255);
+	}
+}
@@ -0,0 +1,396 @@
+//! Transaction signatures (https://tools.ietf.org/html/rfc8945) with the keys in `tsig-keys`, which
+//! dynamic updates have to be signed with, as do queries of zones with `require-tsig`. Responses to
+//! signed requests are signed with the same key.
+
+use std::io::{Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ring::hmac;
+
+use crate::config::TsigKey;
+use crate::server::protocol::{self, class, Message, record_type, Resource};
+
+/// The rcodes a request that fails verification is answered with.
+mod rcode {
+	pub const FORMERR: u16 = 1;
+	pub const REFUSED: u16 = 5;
+	pub const NOTAUTH: u16 = 9;
+}
+
+/// The error field of a TSIG record: https://tools.ietf.org/html/rfc8945#section-5.3.2
+pub mod error {
+	pub const BADSIG: u16 = 16;
+	pub const BADKEY: u16 = 17;
+	pub const BADTIME: u16 = 18;
+}
+
+/// The only TSIG algorithm supported, and the one every client defaults to these days.
+const HMAC_SHA256: &str = "hmac-sha256";
+
+/// The contents of a TSIG record: https://tools.ietf.org/html/rfc8945#section-4.2
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tsig {
+	pub algorithm: Vec<String>,
+	/// Seconds since the epoch, in 48 bits.
+	pub time_signed: u64,
+	pub fudge: u16,
+	pub mac: Vec<u8>,
+	pub original_id: u16,
+	pub error: u16,
+	pub other: Vec<u8>,
+}
+
+impl Tsig {
+	pub fn parse(rdata: &[u8]) -> Option<Tsig> {
+		let mut cursor = Cursor::new(rdata);
+		let mut algorithm = vec![];
+		loop {
+			let length = cursor.read_u8().ok()?;
+			if length == 0 { break; }
+			let mut label = vec![0; length as usize];
+			cursor.read_exact(&mut label).ok()?;
+			algorithm.push(String::from_utf8(label).ok()?.to_lowercase());
+		}
+		let time_signed = (cursor.read_u16::<BigEndian>().ok()? as u64) << 32 | cursor.read_u32::<BigEndian>().ok()? as u64;
+		let fudge = cursor.read_u16::<BigEndian>().ok()?;
+		let mut mac = vec![0; cursor.read_u16::<BigEndian>().ok()? as usize];
+		cursor.read_exact(&mut mac).ok()?;
+		let original_id = cursor.read_u16::<BigEndian>().ok()?;
+		let error = cursor.read_u16::<BigEndian>().ok()?;
+		let mut other = vec![0; cursor.read_u16::<BigEndian>().ok()? as usize];
+		cursor.read_exact(&mut other).ok()?;
+		if cursor.position() as usize != rdata.len() {
+			return None;
+		}
+		return Some(Tsig { algorithm, time_signed, fudge, mac, original_id, error, other });
+	}
+	
+	pub fn rdata(&self) -> Vec<u8> {
+		let mut rdata = protocol::serialize_name(self.algorithm.iter().map(String::as_str));
+		self.write_time(&mut rdata);
+		rdata.write_u16::<BigEndian>(self.mac.len() as u16).unwrap();
+		rdata.extend_from_slice(&self.mac);
+		rdata.write_u16::<BigEndian>(self.original_id).unwrap();
+		self.write_error_and_other(&mut rdata);
+		return rdata;
+	}
+	
+	fn write_time(&self, buf: &mut Vec<u8>) {
+		buf.write_u16::<BigEndian>((self.time_signed >> 32) as u16).unwrap();
+		buf.write_u32::<BigEndian>(self.time_signed as u32).unwrap();
+		buf.write_u16::<BigEndian>(self.fudge).unwrap();
+	}
+	
+	fn write_error_and_other(&self, buf: &mut Vec<u8>) {
+		buf.write_u16::<BigEndian>(self.error).unwrap();
+		buf.write_u16::<BigEndian>(self.other.len() as u16).unwrap();
+		buf.extend_from_slice(&self.other);
+	}
+	
+	/// The fields of the record that are signed along with the message:
+	/// https://tools.ietf.org/html/rfc8945#section-4.3.3
+	pub fn variables(&self, key_name: &[String]) -> Vec<u8> {
+		let mut variables = protocol::serialize_name(key_name.iter().map(|label| label.to_lowercase()).collect::<Vec<String>>().iter().map(String::as_str));
+		variables.write_u16::<BigEndian>(class::ANY).unwrap();
+		variables.write_u32::<BigEndian>(0).unwrap();
+		variables.extend(protocol::serialize_name(self.algorithm.iter().map(String::as_str)));
+		self.write_time(&mut variables);
+		self.write_error_and_other(&mut variables);
+		return variables;
+	}
+}
+
+/// What the response to a verified request is signed with: the key the request was signed with,
+/// and the request's TSIG, whose MAC is signed along with the response.
+#[derive(Debug, Clone)]
+pub struct Signer {
+	pub key: TsigKey,
+	/// As the request had it, which the response's TSIG record is owned by.
+	key_name: Vec<String>,
+	request: Tsig,
+	/// The error the response gives, which is only ever BADTIME, as the request is otherwise
+	/// either answered or its MAC can't be trusted to sign over.
+	error: u16,
+}
+
+fn mac(key: &TsigKey, data: &[u8]) -> Vec<u8> {
+	return hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key.secret), data).as_ref().to_vec();
+}
+
+fn tsig_record(key_name: &[String], tsig: &Tsig) -> Resource {
+	return Resource {
+		rname: key_name.to_vec(),
+		rtype: record_type::TSIG,
+		rclass: class::ANY,
+		ttl: 0,
+		rdata: tsig.rdata(),
+	};
+}
+
+/// Checks the TSIG record of a request, returning what to sign the response with, or the rcode to
+/// answer with and either the unsigned TSIG record to answer with it or what to sign the answer
+/// with, if any. A request without one is refused.
+///
+/// https://tools.ietf.org/html/rfc8945#section-5.2
+#[allow(clippy::type_complexity)]
+pub fn verify(request: &Message, buf: &[u8], keys: &[TsigKey], now: u64) -> Result<Signer, (u16, Option<Resource>, Option<Box<Signer>>)> {
+	let record = match &request.tsig {
+		Some(record) => record,
+		None => return Err((rcode::REFUSED, None, None)),
+	};
+	let tsig = Tsig::parse(&record.rdata).ok_or((rcode::FORMERR, None, None))?;
+	let error = |error: u16| {
+		let tsig = Tsig { mac: vec![], error, ..tsig.clone() };
+		Err((rcode::NOTAUTH, Some(tsig_record(&record.rname, &tsig)), None))
+	};
+	
+	let key_name = record.rname.iter().map(|label| label.to_lowercase()).collect::<Vec<String>>().join(".");
+	let key = match keys.iter().find(|key| key.name == key_name) {
+		Some(key) if tsig.algorithm == [HMAC_SHA256] => key,
+		_ => return error(error::BADKEY),
+	};
+	
+	// the MAC is of the message as it was before the TSIG record was added, whose owner may be
+	// compressed
+	let start = match protocol::last_record_start(buf) {
+		Some(start) => start,
+		None => return Err((rcode::FORMERR, None, None)),
+	};
+	let mut data = buf[..start].to_vec();
+	let additional_count = u16::from_be_bytes([data[10], data[11]]) - 1;
+	data[0..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+	data[10..12].copy_from_slice(&additional_count.to_be_bytes());
+	data.extend(tsig.variables(&record.rname));
+	// a truncated MAC isn't accepted, so the comparison is of the whole thing
+	if hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, &key.secret), &data, &tsig.mac).is_err() {
+		return error(error::BADSIG);
+	}
+	
+	// the MAC checked out, so the error is signed for the client to trust it, and to tell the
+	// client our time: https://tools.ietf.org/html/rfc8945#section-5.2.3
+	let signer = Signer { key: key.clone(), key_name: record.rname.clone(), request: tsig, error: 0 };
+	if now.max(signer.request.time_signed) - now.min(signer.request.time_signed) > signer.request.fudge as u64 {
+		return Err((rcode::NOTAUTH, None, Some(Box::new(Signer { error: error::BADTIME, ..signer }))));
+	}
+	return Ok(signer);
+}
+
+/// Signs the response to a verified request, leaving room for the TSIG record within `max_size`,
+/// which the response is then serialized to. It's signed as it'll be serialized, so it may be
+/// truncated first: https://tools.ietf.org/html/rfc8945#section-5.3
+///
+/// A BADTIME response keeps the request's time, with ours in the other data instead:
+/// https://tools.ietf.org/html/rfc8945#section-5.3.2
+pub fn sign(response: &mut Message, max_size: u16, signer: &Signer, now: u64) {
+	let (time_signed, other) = match signer.error {
+		error::BADTIME => {
+			let mut other = vec![];
+			other.write_u16::<BigEndian>((now >> 32) as u16).unwrap();
+			other.write_u32::<BigEndian>(now as u32).unwrap();
+			(signer.request.time_signed, other)
+		}
+		_ => (now, vec![]),
+	};
+	// the MAC is the same length whatever it signs
+	let mut signed = Tsig { time_signed, mac: vec![0; 32], error: signer.error, other, ..signer.request.clone() };
+	let record_len = protocol::serialize_name(signer.key_name.iter().map(String::as_str)).len() + 10 + signed.rdata().len();
+	response.tsig = None;
+	
+	let mut data = vec![];
+	data.write_u16::<BigEndian>(signer.request.mac.len() as u16).unwrap();
+	data.extend_from_slice(&signer.request.mac);
+	data.extend(protocol::serialize(response, max_size.saturating_sub(record_len as u16)));
+	data.extend(signed.variables(&signer.key_name));
+	signed.mac = mac(&signer.key, &data);
+	response.tsig = Some(tsig_record(&signer.key_name, &signed));
+}
+
+/// Signs the messages of a zone transfer, the first as `sign` does, and each one after it over the
+/// MAC of the one before it, the message, and the time alone, without the rest of the variables:
+/// https://tools.ietf.org/html/rfc8945#section-5.3.1
+pub fn sign_transfer(messages: &mut [Message], signer: &Signer, now: u64) {
+	let (first, rest) = match messages.split_first_mut() {
+		Some(split) => split,
+		None => return,
+	};
+	sign(first, u16::MAX, signer, now);
+	let mut prior = Tsig::parse(&first.tsig.as_ref().unwrap().rdata).unwrap().mac;
+	for message in rest {
+		let mut signed = Tsig { time_signed: now, error: 0, other: vec![], ..signer.request.clone() };
+		message.tsig = None;
+		let mut data = vec![];
+		data.write_u16::<BigEndian>(prior.len() as u16).unwrap();
+		data.extend_from_slice(&prior);
+		data.extend(protocol::serialize(message, u16::MAX));
+		signed.write_time(&mut data);
+		signed.mac = mac(&signer.key, &data);
+		prior = signed.mac.clone();
+		message.tsig = Some(tsig_record(&signer.key_name, &signed));
+	}
+}
+
+#[cfg(test)]
+pub mod test {
+	use ring::hmac;
+	
+	use crate::config::TsigKey;
+	use crate::server::protocol::{self, class, Message, parse, Question, record_type, Resource, serialize};
+	use crate::server::tsig::{error, sign, sign_transfer, Tsig, verify};
+	
+	const TIME: u64 = 1700000000;
+	
+	fn hex(string: &str) -> Vec<u8> {
+		return (0..string.len()).step_by(2).map(|index| u8::from_str_radix(&string[index..index + 2], 16).unwrap()).collect();
+	}
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').map(str::to_string).collect();
+	}
+	
+	fn keys() -> Vec<TsigKey> {
+		return vec![TsigKey { name: "certbot".to_string(), secret: (0..32).collect() }];
+	}
+	
+	/// Signs a request with the key at `time`, the way a client does.
+	pub fn signed_request(mut message: Message, key_name: &str, secret: &[u8], time: u64) -> Vec<u8> {
+		let mut tsig = Tsig {
+			algorithm: vec!["hmac-sha256".to_string()],
+			time_signed: time,
+			fudge: 300,
+			mac: vec![],
+			original_id: message.header.id,
+			error: 0,
+			other: vec![],
+		};
+		let mut data = protocol::serialize(&message, u16::MAX);
+		data.extend(tsig.variables(&name(key_name)));
+		tsig.mac = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, secret), &data).as_ref().to_vec();
+		message.tsig = Some(Resource { rname: name(key_name), rtype: record_type::TSIG, rclass: class::ANY, ttl: 0, rdata: tsig.rdata() });
+		return protocol::serialize(&message, u16::MAX);
+	}
+	
+	#[test]
+	fn test_verify_and_sign() {
+		// a query for example.com A, signed independently of the code under test with the key above
+		let request = hex("123401000001000000000001076578616d706c6503636f6d00000100010763657274626f740000fa00ff00000000003d0b686d61632d7368613235360000006553f100012c00204e5bb1c400366153611decbdf4accece2c6edcc2ef3a5ee9229c01474e9434f7123400000000");
+		let message = parse(&request).unwrap();
+		let signer = verify(&message, &request, &keys(), TIME + 60).unwrap();
+		assert_eq!(signer.key.name, "certbot");
+		
+		// the response is signed over the request's MAC, and so is only good as a response to it
+		let mut response = Message::default();
+		response.header.id = 0x1234;
+		response.header.qr = true;
+		response.header.aa = true;
+		response.header.rd = true;
+		response.question = message.question.clone();
+		response.answer = vec![Resource { rname: name("example.com"), rtype: record_type::A, rclass: class::IN, ttl: 300, rdata: vec![10, 0, 0, 1] }];
+		sign(&mut response, u16::MAX, &signer, TIME + 60);
+		assert_eq!(serialize(&response, u16::MAX), hex("123485000001000100000001076578616d706c6503636f6d0000010001076578616d706c6503636f6d00000100010000012c00040a0000010763657274626f740000fa00ff00000000003d0b686d61632d7368613235360000006553f13c012c0020bde1a1060fc5f05442a25b5c8f99fd6202a495e9d20c793e0f31f6e3192a622b123400000000"));
+		assert_eq!(signed_request(Message { tsig: None, ..message }, "certbot", &keys()[0].secret, TIME), request);
+	}
+	
+	#[test]
+	fn test_verify_errors() {
+		let message = Message {
+			question: vec![Question { qname: name("example.com"), qtype: record_type::A, qclass: class::IN }],
+			..Message::default()
+		};
+		let error = |buf: &[u8], keys: &[TsigKey], now: u64| match verify(&parse(buf).unwrap(), buf, keys, now) {
+			Ok(_) => panic!("verified"),
+			Err((rcode, record, signer)) => (rcode, record.map(|record| Tsig::parse(&record.rdata).unwrap().error).or(signer.map(|signer| signer.error))),
+		};
+		let request = signed_request(message.clone(), "certbot", &keys()[0].secret, TIME);
+		
+		assert_eq!(error(&request, &[], TIME), (9, Some(error::BADKEY)));
+		assert_eq!(error(&signed_request(message.clone(), "certbot", &[0; 32], TIME), &keys(), TIME), (9, Some(error::BADSIG)));
+		// the fudge is 300 seconds either way
+		assert!(verify(&parse(&request).unwrap(), &request, &keys(), TIME - 300).is_ok());
+		assert_eq!(error(&request, &keys(), TIME + 301), (9, Some(error::BADTIME)));
+		// unsigned
+		assert_eq!(error(&serialize(&message, u16::MAX), &keys(), TIME), (5, None));
+	}
+	
+	#[test]
+	fn test_badtime() {
+		let request = signed_request(Message::default(), "certbot", &keys()[0].secret, TIME);
+		let signer = match verify(&parse(&request).unwrap(), &request, &keys(), TIME + 301) {
+			Err((9, None, Some(signer))) => *signer,
+			result => panic!("{:?}", result),
+		};
+		let mut response = Message::default();
+		response.header.qr = true;
+		response.header.rcode = 9;
+		sign(&mut response, u16::MAX, &signer, TIME + 301);
+		
+		// the error is signed over the request's MAC, with the request's time, and ours after it
+		let tsig = Tsig::parse(&response.tsig.as_ref().unwrap().rdata).unwrap();
+		assert_eq!((tsig.error, tsig.time_signed), (error::BADTIME, TIME));
+		assert_eq!(tsig.other, vec![0, 0, 0x65, 0x53, 0xf2, 0x2d]);
+		let mut data = vec![0, 32];
+		data.extend(&Tsig::parse(&parse(&request).unwrap().tsig.unwrap().rdata).unwrap().mac);
+		data.extend(serialize(&Message { tsig: None, ..response }, u16::MAX));
+		data.extend(tsig.variables(&name("certbot")));
+		assert!(hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, &keys()[0].secret), &data, &tsig.mac).is_ok());
+	}
+	
+	#[test]
+	fn test_verify_compressed_owner() {
+		let message = Message {
+			question: vec![Question { qname: name("certbot"), qtype: record_type::TXT, qclass: class::IN }],
+			..Message::default()
+		};
+		let mut request = signed_request(message, "certbot", &keys()[0].secret, TIME);
+		// the key name is the same as the question's, so it can point back to it
+		let owner = request.len() - 10 - parse(&request).unwrap().tsig.unwrap().rdata.len() - 9;
+		request.splice(owner..owner + 9, vec![0xc0, 12]);
+		let signer = verify(&parse(&request).unwrap(), &request, &keys(), TIME).unwrap();
+		assert_eq!(signer.key_name, name("certbot"));
+	}
+	
+	#[test]
+	fn test_sign_truncated() {
+		let request = signed_request(Message::default(), "certbot", &keys()[0].secret, TIME);
+		let signer = verify(&parse(&request).unwrap(), &request, &keys(), TIME).unwrap();
+		let mut response = Message::default();
+		response.header.qr = true;
+		response.answer = (0..40).map(|i| Resource { rname: name("example.com"), rtype: record_type::TXT, rclass: class::IN, ttl: 300, rdata: vec![i; 20] }).collect();
+		sign(&mut response, 512, &signer, TIME);
+		let buf = serialize(&response, 512);
+		assert!(buf.len() <= 512);
+		
+		// the signature is of the message as truncated
+		let parsed = parse(&buf).unwrap();
+		assert!(parsed.header.tc);
+		assert!(parsed.answer.is_empty());
+		let tsig = Tsig::parse(&parsed.tsig.unwrap().rdata).unwrap();
+		let mut data = vec![0, 32];
+		data.extend(&Tsig::parse(&parse(&request).unwrap().tsig.unwrap().rdata).unwrap().mac);
+		data.extend(serialize(&Message { tsig: None, ..parsed }, u16::MAX));
+		data.extend(tsig.variables(&name("certbot")));
+		assert!(hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, &keys()[0].secret), &data, &tsig.mac).is_ok());
+	}
+	
+	#[test]
+	fn test_sign_transfer() {
+		let request = signed_request(Message::default(), "certbot", &keys()[0].secret, TIME);
+		let signer = verify(&parse(&request).unwrap(), &request, &keys(), TIME).unwrap();
+		let message = |i: u8| Message {
+			answer: vec![Resource { rname: name("example.com"), rtype: record_type::TXT, rclass: class::IN, ttl: 300, rdata: vec![1, i] }],
+			..Message::default()
+		};
+		let mut messages = vec![message(0), message(1)];
+		sign_transfer(&mut messages, &signer, TIME);
+		let mut first = message(0);
+		sign(&mut first, u16::MAX, &signer, TIME);
+		assert_eq!(messages[0], first);
+		
+		// the second is signed over the first's MAC, and the time it was signed
+		let mac = |message: &Message| Tsig::parse(&message.tsig.as_ref().unwrap().rdata).unwrap().mac;
+		let mut data = vec![0, 32];
+		data.extend(mac(&messages[0]));
+		data.extend(serialize(&message(1), u16::MAX));
+		data.extend(&[0, 0, 0x65, 0x53, 0xf1, 0x00, 0x01, 0x2c]);
+		assert!(hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, &keys()[0].secret), &data, &mac(&messages[1])).is_ok());
+	}
+}
@@ -0,0 +1,575 @@
+//! Dynamic updates (https://tools.ietf.org/html/rfc2136), as made by the likes of certbot and
+//! external-dns, which have to be signed with TSIG (https://tools.ietf.org/html/rfc8945) by one of
+//! the keys in `tsig-keys`.
+//!
+//! Records added by updates are kept in memory apart from the config, so they outlive changes to it
+//! but not a restart, and are answered ahead of the configured records. Only records added by
+//! updates can be deleted by them.
+
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{AaaaRecord, ARecord, CnameRecord, Config, Label, MxRecord, NsRecord, PtrRecord, Records, SrvRecord, TxtRecord, Zone, ZoneMatcher};
+use crate::config::matching::plain_name;
+use crate::options::Options;
+use crate::server::{matching_zones, notify, rewrite_xname, Transport, tsig, zone_records};
+use crate::server::protocol::{self, class, Message, opcode, Question, record_type, Resource};
+use crate::server::query_log::QueryLog;
+use crate::server::shared::SharedConfig;
+
+/// https://tools.ietf.org/html/rfc2136#section-2.2
+mod rcode {
+	pub const NOERROR: u16 = 0;
+	pub const FORMERR: u16 = 1;
+	pub const NXDOMAIN: u16 = 3;
+	pub const NOTIMP: u16 = 4;
+	pub const YXDOMAIN: u16 = 6;
+	pub const YXRRSET: u16 = 7;
+	pub const NXRRSET: u16 = 8;
+	pub const NOTAUTH: u16 = 9;
+	pub const NOTZONE: u16 = 10;
+}
+
+/// Record types that can be added by updates, which are those the config has a plain form of.
+const SUPPORTED_TYPES: &[u16] = &[record_type::A, record_type::AAAA, record_type::NS, record_type::CNAME, record_type::PTR, record_type::MX, record_type::TXT, record_type::SRV];
+
+/// Types a name is in use by, for prerequisites.
+const IN_USE_TYPES: &[u16] = &[record_type::A, record_type::AAAA, record_type::NS, record_type::CNAME, record_type::SOA, record_type::PTR, record_type::MX, record_type::TXT, record_type::SRV, record_type::NAPTR, record_type::HINFO, record_type::LOC, record_type::SSHFP, record_type::SVCB, record_type::HTTPS];
+
+/// The records added by updates.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Updates {
+	/// With lowercase names, in the order they were added.
+	records: Vec<Resource>,
+}
+
+impl Updates {
+	pub fn is_empty(&self) -> bool {
+		return self.records.is_empty();
+	}
+	
+	/// Adds the records to the config, so that they're answered before the configured ones. Records
+	/// at the name of a zone matching only that name are merged into it, so that its SOA isn't
+	/// answered twice, while the rest get zones of their own ahead of the configured ones.
+	pub fn layer_over(&self, mut config: Config) -> Config {
+		let mut zones: Vec<Zone> = vec![];
+		for record in &self.records {
+			let matcher: ZoneMatcher = record.rname.iter().map(|label| Label::Basic(label.clone())).collect();
+			let index = match zones.iter().position(|zone| zone.matchers[0] == matcher) {
+				Some(index) => index,
+				None => {
+					zones.push(Zone { matchers: vec![matcher], excludes: vec![], records: Records::default() });
+					zones.len() - 1
+				}
+			};
+			// only records that convert are ever added
+			add_record(&mut zones[index].records, record);
+		}
+		
+		for zone in zones.into_iter().rev() {
+			match config.zones.iter_mut().find(|configured| configured.matchers == zone.matchers) {
+				Some(configured) => prepend_records(&mut configured.records, zone.records),
+				None => config.zones.insert(0, zone),
+			}
+		}
+		config.compile();
+		return config;
+	}
+}
+
+fn prepend_records(records: &mut Records, added: Records) {
+	fn prepend<T>(records: &mut Vec<T>, added: Vec<T>) {
+		records.splice(0..0, added);
+	}
+	prepend(&mut records.a, added.a);
+	prepend(&mut records.aaaa, added.aaaa);
+	prepend(&mut records.ns, added.ns);
+	prepend(&mut records.cname, added.cname);
+	prepend(&mut records.ptr, added.ptr);
+	prepend(&mut records.mx, added.mx);
+	prepend(&mut records.txt, added.txt);
+	prepend(&mut records.srv, added.srv);
+	// addresses added by an update are answered, rather than hidden by `A: none`
+	records.no_a &= records.a.is_empty();
+	records.no_aaaa &= records.aaaa.is_empty();
+}
+
+/// Converts a record to its form in the config, returning `None` if its type isn't supported or its
+/// rdata is malformed.
+fn add_record(records: &mut Records, record: &Resource) -> Option<()> {
+	let ttl = Duration::from_secs(record.ttl as u64);
+	let rdata = &record.rdata[..];
+	let u16_at = |offset: usize| Some(u16::from_be_bytes(<[u8; 2]>::try_from(rdata.get(offset..offset + 2)?).ok()?));
+	match record.rtype {
+		record_type::A => records.a.push(ARecord { ttl, ip4addr: Ipv4Addr::from(<[u8; 4]>::try_from(rdata).ok()?), weight: None, check: None, metadata: None }),
+		record_type::AAAA => records.aaaa.push(AaaaRecord { ttl, ip6addr: Ipv6Addr::from(<[u8; 16]>::try_from(rdata).ok()?), weight: None, check: None, metadata: None }),
+		record_type::NS => records.ns.push(NsRecord { ttl, name: read_name(rdata)?, metadata: None }),
+		record_type::CNAME => records.cname.push(CnameRecord { ttl, name: read_name(rdata)?, metadata: None }),
+		record_type::PTR => records.ptr.push(PtrRecord { ttl, name: read_name(rdata)?, metadata: None }),
+		record_type::MX => records.mx.push(MxRecord { ttl, priority: u16_at(0)?, host: read_name(&rdata[2..])?, metadata: None }),
+		record_type::TXT => records.txt.push(TxtRecord { ttl, data: read_txt(rdata)?, metadata: None }),
+		record_type::SRV => records.srv.push(SrvRecord {
+			ttl,
+			priority: u16_at(0)?,
+			weight: u16_at(2)?,
+			port: u16_at(4)?,
+			target: read_name(&rdata[6..])?,
+			metadata: None,
+		}),
+		_ => return None,
+	}
+	return Some(());
+}
+
+/// Reads an uncompressed name taking up all of the rdata, as the config has it: absolute, with a
+/// trailing dot.
+fn read_name(rdata: &[u8]) -> Option<String> {
+	let mut labels = vec![];
+	let mut rest = rdata;
+	loop {
+		let (&length, after) = rest.split_first()?;
+		if length == 0 {
+			rest = after;
+			break;
+		}
+		let label = std::str::from_utf8(after.get(..length as usize)?).ok()?;
+		if !label.is_ascii() || label.contains('.') {
+			return None;
+		}
+		labels.push(label.to_lowercase());
+		rest = &after[length as usize..];
+	}
+	if !rest.is_empty() {
+		return None;
+	}
+	return Some(labels.join(".") + ".");
+}
+
+/// Joins the strings of a TXT record back into one, which is how the config has it.
+fn read_txt(rdata: &[u8]) -> Option<String> {
+	let mut data = vec![];
+	let mut rest = rdata;
+	while let Some((&length, after)) = rest.split_first() {
+		data.extend_from_slice(after.get(..length as usize)?);
+		rest = &after[length as usize..];
+	}
+	return String::from_utf8(data).ok();
+}
+
+/// Applies the request if it's an update, returning the raw response, or `None` if it's some other
+/// request that should go to `handle_request`. The config's serial is bumped by every update that
+/// changes a record.
+pub fn handle_update(request: &Message, buf: &[u8], options: &Options, config: &SharedConfig, transport: Transport, src: SocketAddr) -> Option<Vec<u8>> {
+	let instant = Instant::now();
+	if request.header.qr || request.header.opcode != opcode::UPDATE {
+		return None;
+	}
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+	let (response, changed) = update(request, buf, config, now);
+	if options.verbose { println!("update from {}: {:?}", src, response); }
+	
+	if let Some(config) = changed {
+		notify::notify(options, &config, &request.question[0].qname);
+	}
+	
+	if options.query_log {
+		println!("{}", QueryLog::new(src, transport, &response, instant.elapsed()).format(&options.log_format));
+	}
+	// responses are small enough for UDP, and truncating them would lose the signature
+	return Some(protocol::serialize(&response, u16::MAX));
+}
+
+/// Verifies and applies an update, returning the response along with the changed config, if
+/// anything changed.
+fn update(request: &Message, buf: &[u8], config: &SharedConfig, now: u64) -> (Message, Option<Arc<Config>>) {
+	let mut response = Message { header: request.header.clone(), ..Default::default() };
+	response.header.qr = true;
+	response.header.aa = false;
+	response.header.tc = false;
+	response.header.ra = false;
+	response.header.z = 0;
+	response.question = request.question.clone();
+	
+	let signer = match tsig::verify(request, buf, &config.get().tsig_keys, now) {
+		Ok(signer) => signer,
+		Err((rcode, tsig, signer)) => {
+			response.header.rcode = rcode;
+			response.tsig = tsig;
+			if let Some(signer) = &signer {
+				tsig::sign(&mut response, u16::MAX, signer, now);
+			}
+			return (response, None);
+		}
+	};
+	// made one at a time, on what the prerequisites checked
+	let (rcode, changed) = config.update(|configured, served, updates| apply(request, configured, served, updates));
+	response.header.rcode = rcode;
+	tsig::sign(&mut response, u16::MAX, &signer, now);
+	return (response, changed);
+}
+
+/// Checks the zone, prerequisites, and updates of a verified request, then makes the updates if
+/// they all pass, returning the rcode. Prerequisites are checked against the config with the
+/// records already added by updates, `layered`.
+///
+/// https://tools.ietf.org/html/rfc2136#section-3
+fn apply(request: &Message, config: &Config, layered: &Config, updates: &mut Updates) -> u16 {
+	let zone = match &request.question[..] {
+		[zone] if zone.qtype == record_type::SOA && zone.qclass == class::IN => zone.qname.iter().map(|label| label.to_lowercase()).collect::<Vec<String>>(),
+		_ => return rcode::FORMERR,
+	};
+	// only zones that are answered from the config can be updated, not wildcards, delegations, or
+	// forwarded zones
+	let authoritative = config.zones.iter().any(|configured| {
+		configured.matchers.iter().any(|matcher| plain_name(matcher).as_ref() == Some(&zone))
+			&& !configured.records.is_delegation()
+			&& configured.records.forward.is_none()
+	});
+	if !authoritative {
+		return rcode::NOTAUTH;
+	}
+	let in_zone = |name: &[String]| name.len() >= zone.len()
+		&& name[name.len() - zone.len()..].iter().zip(&zone).all(|(label, zone_label)| label.eq_ignore_ascii_case(zone_label));
+	
+	// https://tools.ietf.org/html/rfc2136#section-3.2
+	let mut rrsets: Vec<(Vec<String>, u16, Vec<Vec<u8>>)> = vec![];
+	for prerequisite in &request.answer {
+		if prerequisite.ttl != 0 {
+			return rcode::FORMERR;
+		}
+		if !in_zone(&prerequisite.rname) {
+			return rcode::NOTZONE;
+		}
+		let in_use = || IN_USE_TYPES.iter().any(|rtype| !rrset(layered, &prerequisite.rname, *rtype).is_empty());
+		match (prerequisite.rclass, prerequisite.rtype) {
+			(class::ANY, _) | (class::NONE, _) if !prerequisite.rdata.is_empty() => return rcode::FORMERR,
+			(class::ANY, record_type::ANY) => if !in_use() { return rcode::NXDOMAIN; },
+			(class::ANY, rtype) => if rrset(layered, &prerequisite.rname, rtype).is_empty() { return rcode::NXRRSET; },
+			(class::NONE, record_type::ANY) => if in_use() { return rcode::YXDOMAIN; },
+			(class::NONE, rtype) => if !rrset(layered, &prerequisite.rname, rtype).is_empty() { return rcode::YXRRSET; },
+			(class::IN, rtype) => {
+				let name: Vec<String> = prerequisite.rname.iter().map(|label| label.to_lowercase()).collect();
+				match rrsets.iter_mut().find(|(rrset_name, rrset_type, _)| *rrset_name == name && *rrset_type == rtype) {
+					Some((_, _, rdatas)) => rdatas.push(prerequisite.rdata.clone()),
+					None => rrsets.push((name, rtype, vec![prerequisite.rdata.clone()])),
+				}
+			}
+			_ => return rcode::FORMERR,
+		}
+	}
+	for (name, rtype, mut expected) in rrsets {
+		let mut actual = rrset(layered, &name, rtype);
+		expected.sort();
+		expected.dedup();
+		actual.sort();
+		actual.dedup();
+		if actual != expected {
+			return rcode::NXRRSET;
+		}
+	}
+	
+	// https://tools.ietf.org/html/rfc2136#section-3.4.1
+	for update in &request.authority {
+		if !in_zone(&update.rname) {
+			return rcode::NOTZONE;
+		}
+		let valid = match update.rclass {
+			class::IN => update.rtype < 128 || update.rtype >= 256,
+			class::ANY => update.ttl == 0 && update.rdata.is_empty() && (update.rtype < 128 || update.rtype == record_type::ANY),
+			class::NONE => update.ttl == 0 && update.rtype < 128,
+			_ => false,
+		};
+		if !valid {
+			return rcode::FORMERR;
+		}
+		if update.rclass == class::IN && update.rtype != record_type::SOA {
+			if !SUPPORTED_TYPES.contains(&update.rtype) {
+				return rcode::NOTIMP;
+			}
+			if add_record(&mut Records::default(), update).is_none() {
+				return rcode::FORMERR;
+			}
+		}
+	}
+	
+	// https://tools.ietf.org/html/rfc2136#section-3.4.2
+	let is_rrset = |record: &Resource, update: &Resource| record.rname.len() == update.rname.len()
+		&& record.rname.iter().zip(&update.rname).all(|(label, update_label)| label.eq_ignore_ascii_case(update_label))
+		&& (update.rtype == record_type::ANY || record.rtype == update.rtype);
+	for update in &request.authority {
+		match update.rclass {
+			// the zone's SOA is the one in the config
+			class::IN if update.rtype == record_type::SOA => {}
+			class::IN => {
+				let record = Resource {
+					rname: update.rname.iter().map(|label| label.to_lowercase()).collect(),
+					rclass: class::IN,
+					..update.clone()
+				};
+				// adding a record that's already there only changes its TTL
+				match updates.records.iter_mut().find(|existing| is_rrset(existing, &record) && existing.rdata == record.rdata) {
+					Some(existing) => existing.ttl = record.ttl,
+					None => updates.records.push(record),
+				}
+			}
+			class::ANY => updates.records.retain(|record| !is_rrset(record, update)),
+			_ => updates.records.retain(|record| !(is_rrset(record, update) && record.rdata == update.rdata)),
+		}
+	}
+	return rcode::NOERROR;
+}
+
+/// The rdata of the records of a type at a name, configured or added by updates.
+fn rrset(config: &Config, name: &[String], rtype: u16) -> Vec<Vec<u8>> {
+	let question = Question {
+		qname: name.iter().map(|label| label.to_lowercase()).collect(),
+		qtype: rtype,
+		qclass: class::IN,
+	};
+	let mut rdatas = vec![];
+	for (zone_index, zone) in matching_zones(config, name) {
+		if rtype == record_type::CNAME {
+			rdatas.extend(zone.records.cname.iter().map(|cname| protocol::serialize_name(rewrite_xname(&cname.name, &question.qname).iter().map(String::as_str))));
+		} else {
+			rdatas.extend(zone_records(zone, zone_index, &question, rtype, config).into_iter().map(|record| record.rdata));
+		}
+	}
+	return rdatas;
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::{SystemTime, UNIX_EPOCH};
+	
+	use crate::config::{self, Config};
+	use crate::server::Transport;
+	use crate::server::test::handle_request;
+	use crate::server::protocol::{self, class, Message, opcode, Question, record_type, Resource};
+	use crate::server::shared::SharedConfig;
+	use crate::server::test::test_options;
+	use crate::server::tsig::Tsig;
+	use crate::server::tsig::test::signed_request;
+	use crate::server::update::{handle_update, update};
+	
+	const TIME: u64 = 1700000000;
+	
+	fn hex(string: &str) -> Vec<u8> {
+		return (0..string.len()).step_by(2).map(|index| u8::from_str_radix(&string[index..index + 2], 16).unwrap()).collect();
+	}
+	
+	fn name(name: &str) -> Vec<String> {
+		return name.split('.').map(str::to_string).collect();
+	}
+	
+	fn test_config() -> Config {
+		return config::parse("
+tsig-keys:
+  certbot.:
+    algorithm: hmac-sha256
+    secret: AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=
+zones:
+  example.com:
+    A: 10.0.0.1
+  '*.example.org':
+    A: 10.0.0.2
+").unwrap();
+	}
+	
+	/// Makes an update of example.com, signed at `TIME` with the secret.
+	fn request(prerequisites: Vec<Resource>, updates: Vec<Resource>, key_name: &str, secret: &[u8]) -> Vec<u8> {
+		return request_at(TIME, prerequisites, updates, key_name, secret);
+	}
+	
+	fn request_at(time: u64, prerequisites: Vec<Resource>, updates: Vec<Resource>, key_name: &str, secret: &[u8]) -> Vec<u8> {
+		let mut message = Message::default();
+		message.header.id = 42;
+		message.header.opcode = opcode::UPDATE;
+		message.question = vec![Question { qname: name("example.com"), qtype: record_type::SOA, qclass: class::IN }];
+		message.answer = prerequisites;
+		message.authority = updates;
+		return signed_request(message, key_name, secret, time);
+	}
+	
+	fn record(rname: &str, rtype: u16, rclass: u16, ttl: u32, rdata: Vec<u8>) -> Resource {
+		return Resource { rname: name(rname), rtype, rclass, ttl, rdata };
+	}
+	
+	fn send(buf: &[u8], config: &SharedConfig, now: u64) -> Message {
+		return update(&protocol::parse(buf).unwrap(), buf, config, now).0;
+	}
+	
+	fn rcode(buf: &[u8], config: &SharedConfig) -> u16 {
+		return send(buf, config, TIME).header.rcode;
+	}
+	
+	#[test]
+	fn test_query() {
+		let config = SharedConfig::new(test_config());
+		let src = "127.0.0.1:5353".parse().unwrap();
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let txt = record("_acme-challenge.example.com", record_type::TXT, class::IN, 60, protocol::serialize_txt("token"));
+		let update = |key_name: &str| {
+			let buf = request_at(now, vec![], vec![txt.clone()], key_name, &test_config().tsig_keys[0].secret);
+			protocol::parse(&handle_update(&protocol::parse(&buf).unwrap(), &buf, &test_options(), &config, Transport::Udp, src).unwrap()).unwrap()
+		};
+		let serial = config.get().serial;
+		assert_eq!(update("other").header.rcode, 9);
+		assert_eq!(config.get().serial, serial);
+		assert_eq!(update("certbot").header.rcode, 0);
+		assert_eq!(config.get().serial, serial.wrapping_add(1));
+		
+		let query = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+			qname: name("_acme-challenge.example.com"),
+			qtype: record_type::TXT,
+			qclass: class::IN,
+		}]), 512);
+		// queries are left to `handle_request`, which answers with the added record
+		assert_eq!(handle_update(&protocol::parse(&query).unwrap(), &query, &test_options(), &config, Transport::Udp, src), None);
+		let response = protocol::parse(&handle_request(query, &test_options(), &config.served(), Transport::Udp, src).unwrap()).unwrap();
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.answer.len(), 1);
+		assert_eq!(response.answer[0].rdata, protocol::serialize_txt("token"));
+	}
+	
+	#[test]
+	fn test_signature() {
+		// signed independently of the code under test, with the secret above
+		let request = hex("123428000001000000010001076578616d706c6503636f6d00000600010f5f61636d652d6368616c6c656e6765076578616d706c6503636f6d00001000010000003c000605746f6b656e0763657274626f740000fa00ff00000000003d0b686d61632d7368613235360000006553f100012c0020c37dcce87cf2a19c15d9fa29d932c0eb0648b99581cb085a57c9b4af977d713f123400000000");
+		let response = send(&request, &SharedConfig::new(test_config()), TIME + 60);
+		assert_eq!(protocol::serialize(&response, u16::MAX), hex("1234a8000001000000000001076578616d706c6503636f6d00000600010763657274626f740000fa00ff00000000003d0b686d61632d7368613235360000006553f13c012c002008a1a385b8b4c2088676f952ca25a9eccf2d4a20563f5fb77443aea2105c4a0b123400000000"));
+	}
+	
+	#[test]
+	fn test_add_and_delete() {
+		let config = test_config();
+		let shared = SharedConfig::new(config.clone());
+		let txt = || record("_acme-challenge.example.com", record_type::TXT, class::IN, 60, protocol::serialize_txt("token"));
+		let response = send(&request(vec![], vec![txt()], "certbot", &hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")), &shared, TIME);
+		assert_eq!(response.header.rcode, 0);
+		assert_eq!(response.tsig.unwrap().rtype, record_type::TSIG);
+		
+		let layered = shared.served();
+		assert_eq!(layered.zones.len(), config.zones.len() + 1);
+		assert_eq!(layered.zones[0].records.txt[0].data, "token");
+		// the config itself is left as it was, apart from its serial
+		assert_eq!(shared.get().zones, config.zones);
+		// and the records are layered over the config again when it changes
+		shared.change(|_| Ok::<(), ()>(())).unwrap();
+		assert_eq!(shared.served().zones[0].records.txt[0].data, "token");
+		
+		// a record at the name of a zone is merged into it
+		let a = || record("example.com", record_type::A, class::IN, 60, vec![10, 0, 0, 3]);
+		assert_eq!(rcode(&request(vec![], vec![a()], "certbot", &config.tsig_keys[0].secret), &shared), 0);
+		let layered = shared.served();
+		assert_eq!(layered.zones.len(), config.zones.len() + 1);
+		assert_eq!(layered.zones[1].records.a.iter().map(|a| a.ip4addr.octets()).collect::<Vec<[u8; 4]>>(), vec![[10, 0, 0, 3], [10, 0, 0, 1]]);
+		
+		// the configured record can't be deleted, only the added one
+		let delete_a = record("example.com", record_type::A, class::ANY, 0, vec![]);
+		assert_eq!(rcode(&request(vec![], vec![delete_a], "certbot", &config.tsig_keys[0].secret), &shared), 0);
+		let delete_txt = Resource { rclass: class::NONE, ttl: 0, ..txt() };
+		assert_eq!(rcode(&request(vec![], vec![delete_txt], "certbot", &config.tsig_keys[0].secret), &shared), 0);
+		assert_eq!(shared.served(), shared.get());
+		assert_eq!(shared.get().zones, config.zones);
+	}
+	
+	#[test]
+	fn test_add_to_none() {
+		let mut config = test_config();
+		config.zones[0].records.no_aaaa = true;
+		let shared = SharedConfig::new(config);
+		let aaaa = record("example.com", record_type::AAAA, class::IN, 60, vec![0; 16]);
+		assert_eq!(rcode(&request(vec![], vec![aaaa], "certbot", &test_config().tsig_keys[0].secret), &shared), 0);
+		// the added address is answered rather than hidden by `AAAA: none`, which the config keeps
+		assert_eq!(shared.served().zones[0].records.aaaa.len(), 1);
+		assert!(!shared.served().zones[0].records.no_aaaa);
+		assert!(shared.get().zones[0].records.no_aaaa);
+	}
+	
+	#[test]
+	fn test_bad_signature() {
+		let config = SharedConfig::new(test_config());
+		let txt = record("example.com", record_type::TXT, class::IN, 60, protocol::serialize_txt("token"));
+		let secret = test_config().tsig_keys[0].secret.clone();
+		
+		let response = send(&request(vec![], vec![txt.clone()], "other", &secret), &config, TIME);
+		assert_eq!(response.header.rcode, 9);
+		assert_eq!(Tsig::parse(&response.tsig.unwrap().rdata).unwrap().error, 17);
+		
+		let response = send(&request(vec![], vec![txt.clone()], "certbot", &[0; 32]), &config, TIME);
+		assert_eq!(response.header.rcode, 9);
+		assert_eq!(Tsig::parse(&response.tsig.unwrap().rdata).unwrap().error, 16);
+		
+		// signed, with our time for the client to see how far off it is
+		let response = send(&request(vec![], vec![txt.clone()], "certbot", &secret), &config, TIME + 301);
+		assert_eq!(response.header.rcode, 9);
+		let tsig = Tsig::parse(&response.tsig.unwrap().rdata).unwrap();
+		assert_eq!((tsig.error, tsig.mac.len(), tsig.other.len()), (18, 32, 6));
+		
+		// unsigned
+		let mut message = Message::default();
+		message.header.opcode = opcode::UPDATE;
+		message.question = vec![Question { qname: name("example.com"), qtype: record_type::SOA, qclass: class::IN }];
+		message.authority = vec![txt];
+		let buf = protocol::serialize(&message, u16::MAX);
+		let response = send(&buf, &config, TIME);
+		assert_eq!(response.header.rcode, 5);
+		assert!(response.tsig.is_none());
+		
+		assert_eq!(config.served().zones, test_config().zones);
+	}
+	
+	#[test]
+	fn test_zones() {
+		let config = SharedConfig::new(test_config());
+		let secret = test_config().tsig_keys[0].secret.clone();
+		let signed = |zone: &str, rname: &str| {
+			let mut buf = request(vec![], vec![record(rname, record_type::A, class::IN, 60, vec![10, 0, 0, 3])], "certbot", &secret);
+			// the zone is the first name in the message, and the same length in every case here
+			let zone = protocol::serialize_name(zone.split('.'));
+			buf[12..12 + zone.len()].copy_from_slice(&zone);
+			buf
+		};
+		assert_eq!(rcode(&request(vec![], vec![record("www.example.com", record_type::A, class::IN, 60, vec![10, 0, 0, 3])], "certbot", &secret), &config), 0);
+		// changing the zone breaks the signature, so it's checked before anything else
+		assert_eq!(rcode(&signed("example.net", "www.example.com"), &config), 9);
+		assert_eq!(rcode(&request(vec![], vec![record("www.example.net", record_type::A, class::IN, 60, vec![10, 0, 0, 3])], "certbot", &secret), &config), 10);
+		assert_eq!(rcode(&request(vec![], vec![record("www.example.com", record_type::NAPTR, class::IN, 60, protocol::serialize_naptr(10, 20, "u", "E2U+sip", "", "example.com"))], "certbot", &secret), &config), 4);
+		assert_eq!(rcode(&request(vec![], vec![record("www.example.com", record_type::A, class::IN, 60, vec![10, 0, 0])], "certbot", &secret), &config), 1);
+		assert_eq!(rcode(&request(vec![], vec![record("www.example.com", record_type::A, class::ANY, 60, vec![])], "certbot", &secret), &config), 1);
+		assert_eq!(config.served().zones.len(), test_config().zones.len() + 1);
+	}
+	
+	#[test]
+	fn test_not_authoritative() {
+		let secret = test_config().tsig_keys[0].secret.clone();
+		let mut config = test_config();
+		config.zones.remove(0);
+		let buf = request(vec![], vec![record("www.example.com", record_type::A, class::IN, 60, vec![10, 0, 0, 3])], "certbot", &secret);
+		assert_eq!(send(&buf, &SharedConfig::new(config), TIME).header.rcode, 9);
+	}
+	
+	#[test]
+	fn test_prerequisites() {
+		let config = SharedConfig::new(test_config());
+		let secret = test_config().tsig_keys[0].secret.clone();
+		let a = record("www.example.com", record_type::A, class::IN, 60, vec![10, 0, 0, 3]);
+		let prerequisite = |rname: &str, rtype: u16, rclass: u16, rdata: Vec<u8>| record(rname, rtype, rclass, 0, rdata);
+		let rcode = |prerequisites: Vec<Resource>| rcode(&request(prerequisites, vec![a.clone()], "certbot", &secret), &config);
+		
+		// configured records count too
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::ANY, class::ANY, vec![])]), 0);
+		assert_eq!(rcode(vec![prerequisite("www.example.com", record_type::A, class::ANY, vec![])]), 0);
+		assert_eq!(rcode(vec![prerequisite("mail.example.com", record_type::ANY, class::ANY, vec![])]), 3);
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::TXT, class::ANY, vec![])]), 8);
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::ANY, class::NONE, vec![])]), 6);
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::A, class::NONE, vec![])]), 7);
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::TXT, class::NONE, vec![])]), 0);
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::A, class::IN, vec![10, 0, 0, 1])]), 0);
+		assert_eq!(rcode(vec![prerequisite("example.com", record_type::A, class::IN, vec![10, 0, 0, 9])]), 8);
+		assert_eq!(rcode(vec![Resource { ttl: 60, ..prerequisite("example.com", record_type::A, class::ANY, vec![]) }]), 1);
+		assert_eq!(rcode(vec![prerequisite("example.net", record_type::A, class::ANY, vec![])]), 10);
+	}
+}
@@ -0,0 +1,90 @@
+//! Where the names that the config points outside of itself are looked up: the targets of CNAME
+//! and ANAME records, forwarded zones, RNS, and the servers that RNS delegates to.
+
+use std::cell::Cell;
+use std::net::SocketAddr;
+
+use crate::options::Options;
+use crate::server::{resolver_lookup, Response};
+use crate::server::protocol::Question;
+
+/// Looks questions up against other DNS servers. The server does so with `Options`, while an
+/// embedder or a test can answer them however it likes.
+pub trait Upstream {
+	/// The resolvers asked about names that no zone has, such as the targets of CNAME and ANAME
+	/// records outside the config.
+	fn resolvers(&self) -> &[SocketAddr];
+	
+	/// Asks `servers` a question, failing over from one to the next if it doesn't respond.
+	fn lookup(&self, question: Question, servers: &[SocketAddr]) -> Response;
+	
+	/// Whether to print what happens while resolving.
+	fn verbose(&self) -> bool {
+		return false;
+	}
+}
+
+/// Asks nothing elsewhere, for a query that doesn't want recursion, so that whatever would be looked
+/// up fails as if the servers were down.
+pub struct NoRecursion<'a>(pub &'a dyn Upstream);
+
+impl Upstream for NoRecursion<'_> {
+	fn resolvers(&self) -> &[SocketAddr] {
+		return self.0.resolvers();
+	}
+	
+	fn lookup(&self, question: Question, servers: &[SocketAddr]) -> Response {
+		if self.verbose() { println!("not asking {:?} about {:?}, as recursion wasn't desired", servers, question.qname); }
+		return Response::ServerFailure;
+	}
+	
+	fn verbose(&self) -> bool {
+		return self.0.verbose();
+	}
+}
+
+/// Counts the lookups made through another upstream, for the statistics.
+pub struct Counted<'a> {
+	upstream: &'a dyn Upstream,
+	lookups: Cell<u64>,
+}
+
+impl<'a> Counted<'a> {
+	pub fn new(upstream: &'a dyn Upstream) -> Counted<'a> {
+		return Counted { upstream, lookups: Cell::new(0) };
+	}
+	
+	/// The lookups made since the last time this was called.
+	pub fn take(&self) -> u64 {
+		return self.lookups.replace(0);
+	}
+}
+
+impl Upstream for Counted<'_> {
+	fn resolvers(&self) -> &[SocketAddr] {
+		return self.upstream.resolvers();
+	}
+	
+	fn lookup(&self, question: Question, servers: &[SocketAddr]) -> Response {
+		self.lookups.set(self.lookups.get() + 1);
+		return self.upstream.lookup(question, servers);
+	}
+	
+	fn verbose(&self) -> bool {
+		return self.upstream.verbose();
+	}
+}
+
+impl Upstream for Options {
+	fn resolvers(&self) -> &[SocketAddr] {
+		return &self.resolver;
+	}
+	
+	fn lookup(&self, question: Question, servers: &[SocketAddr]) -> Response {
+		return resolver_lookup(question, servers, self);
+	}
+	
+	fn verbose(&self) -> bool {
+		return self.verbose;
+	}
+}
@@ -0,0 +1,100 @@
+//! The config as each client sees it, without the zones in views of networks the client isn't in.
+//! Every client in the same networks sees the same zones, so the config for each set of networks is
+//! made and indexed once, and kept alongside the config it's made from until that changes.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::config::{Config, Zone, ZoneMatcher};
+
+/// The configs seen by the clients of one config, which start out empty with each change.
+#[derive(Debug, Clone)]
+pub struct ClientViews {
+	/// Every view a zone is in, which is empty when there's nothing to leave out for anyone.
+	views: Arc<Vec<String>>,
+	/// The config seen by the clients in each set of `views`.
+	seen: Arc<RwLock<HashMap<Vec<bool>, Arc<Config>>>>,
+}
+
+impl ClientViews {
+	pub fn new(config: &Config) -> ClientViews {
+		let mut views: Vec<String> = config.zones.iter().filter_map(|zone| zone.records.view.clone()).collect();
+		views.sort();
+		views.dedup();
+		return ClientViews { views: Arc::new(views), seen: Arc::default() };
+	}
+	
+	/// The config as a client sees it, given the config these views were made for.
+	pub fn get(&self, config: &Arc<Config>, client: IpAddr) -> Arc<Config> {
+		if self.views.is_empty() {
+			return config.clone();
+		}
+		let in_views: Vec<bool> = self.views.iter()
+			.map(|view| config.networks.get(view).map(|cidrs| cidrs.iter().any(|cidr| cidr.contains(client))).unwrap_or(false))
+			.collect();
+		if let Some(seen) = self.seen.read().unwrap_or_else(|error| error.into_inner()).get(&in_views) {
+			return seen.clone();
+		}
+		let in_view = |view: &str| self.views.iter().position(|name| name == view).map(|index| in_views[index]).unwrap_or(false);
+		let seen = Arc::new(view(config, in_view));
+		self.seen.write().unwrap_or_else(|error| error.into_inner()).insert(in_views, seen.clone());
+		return seen;
+	}
+}
+
+/// The config without the zones in views that `in_view` says no to. Zones in a view that it says
+/// yes to replace those with the same name in none, so that a client gets a single answer.
+fn view(config: &Config, in_view: impl Fn(&str) -> bool) -> Config {
+	let is_seen = |zone: &Zone| zone.records.view.as_deref().map(&in_view).unwrap_or(true);
+	let viewed: Vec<&ZoneMatcher> = config.zones.iter()
+		.filter(|zone| zone.records.view.is_some() && is_seen(zone))
+		.flat_map(|zone| &zone.matchers)
+		.collect();
+	let zones = config.zones.iter()
+		.filter(|zone| is_seen(zone) && (zone.records.view.is_some() || !zone.matchers.iter().all(|matcher| viewed.contains(&matcher))))
+		.cloned()
+		.collect();
+	let mut config = Config { zones, ..config.clone() };
+	config.compile();
+	return config;
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::Ipv4Addr;
+	use std::sync::Arc;
+	
+	use crate::config;
+	use crate::config::ZoneIndex;
+	use crate::server::views::ClientViews;
+	
+	#[test]
+	fn test_client_views() {
+		let config = Arc::new(config::parse(r"networks:
+  internal: [10.0.0.0/8]
+  lab: [10.1.0.0/16]
+zones:
+  example.com @internal:
+    A: 10.0.0.5
+  example.com:
+    A: 203.0.113.5
+  lab.example.com @lab:
+    A: 10.1.0.6").unwrap());
+		let views = ClientViews::new(&config);
+		let view = |client: &str| views.get(&config, client.parse().unwrap());
+		
+		let internal = view("10.2.0.1");
+		assert_eq!(internal.zones.len(), 1);
+		assert_eq!(internal.zones[0].records.a[0].ip4addr, "10.0.0.5".parse::<Ipv4Addr>().unwrap());
+		assert_eq!(internal.index, ZoneIndex::new(&internal.zones));
+		assert_eq!(view("10.1.0.1").zones.len(), 2);
+		assert_eq!(view("198.51.100.1").zones.len(), 1);
+		// clients in the same networks share the config they see
+		assert!(Arc::ptr_eq(&internal, &view("10.3.0.1")));
+		
+		// which is the config itself when no zone is in a view
+		let config = Arc::new(config::parse("zones:\n  example.com:\n    A: 10.0.0.1").unwrap());
+		assert!(Arc::ptr_eq(&ClientViews::new(&config).get(&config, "10.0.0.1".parse().unwrap()), &config));
+	}
+}
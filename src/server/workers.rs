@@ -0,0 +1,176 @@
+//! The pool requests are answered on. A job that panics is logged and counted rather than taking
+//! its worker down with it, and a pool that stops finishing jobs altogether aborts the process, so
+//! that whatever supervises it starts a fresh one rather than it taking requests it never answers.
+
+use std::any::Any;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use threadpool::ThreadPool;
+
+use crate::server::protocol::{self, Message};
+use crate::server::query_log::QueryLog;
+use crate::server::Transport;
+
+/// How often the watchdog looks at whether the pool is still finishing jobs.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct Workers {
+	pool: ThreadPool,
+	/// Number of jobs that panicked.
+	panics: Arc<AtomicUsize>,
+	/// Number of jobs finished, whether or not they panicked.
+	completed: Arc<AtomicUsize>,
+}
+
+impl Workers {
+	pub fn new(threads: usize) -> Workers {
+		return Workers {
+			pool: ThreadPool::with_name("worker".to_string(), threads),
+			panics: Arc::new(AtomicUsize::new(0)),
+			completed: Arc::new(AtomicUsize::new(0)),
+		};
+	}
+	
+	/// Runs `job` on the pool. If it panics, the panic is logged along with what `describe` says the
+	/// job was doing, which is only worked out then.
+	pub fn execute(&self, describe: impl FnOnce() -> String + Send + 'static, job: impl FnOnce() + Send + 'static) {
+		let panics = self.panics.clone();
+		let completed = self.completed.clone();
+		self.pool.execute(move || {
+			// whatever the job shares with other threads is behind locks, which are poisoned if it
+			// panics while holding them, so nothing is left half changed without anyone knowing
+			if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+				let count = panics.fetch_add(1, Ordering::SeqCst) + 1;
+				eprintln!("worker panicked handling {} ({} so far): {}", describe(), count, panic_message(&*payload));
+			}
+			completed.fetch_add(1, Ordering::SeqCst);
+		});
+	}
+	
+	#[cfg(test)]
+	pub fn panics(&self) -> usize {
+		return self.panics.load(Ordering::SeqCst);
+	}
+	
+	/// Waits until every job given to the pool is done.
+	pub fn join(&self) {
+		self.pool.join();
+	}
+	
+	/// Aborts the process once jobs have been waiting for `timeout` without any finishing, e.g. as
+	/// every worker is stuck. Returns when `is_shutdown` says the server was shut down.
+	pub fn watch(&self, timeout: Duration, is_shutdown: impl Fn() -> bool) {
+		let mut progress = Progress::new(self.completed.load(Ordering::SeqCst), Instant::now());
+		loop {
+			thread::sleep(WATCH_INTERVAL.min(timeout));
+			if is_shutdown() {
+				return;
+			}
+			let now = Instant::now();
+			if progress.is_stalled(self.completed.load(Ordering::SeqCst), self.pool.queued_count(), now, timeout) {
+				eprintln!("aborting, as no worker has finished a job in {:?} with {} waiting", now - progress.since, self.pool.queued_count());
+				process::abort();
+			}
+		}
+	}
+}
+
+/// The last time the pool was seen finishing a job, or with none waiting.
+struct Progress {
+	completed: usize,
+	since: Instant,
+}
+
+impl Progress {
+	fn new(completed: usize, now: Instant) -> Progress {
+		return Progress { completed, since: now };
+	}
+	
+	fn is_stalled(&mut self, completed: usize, queued: usize, now: Instant, timeout: Duration) -> bool {
+		if completed != self.completed || queued == 0 {
+			self.completed = completed;
+			self.since = now;
+			return false;
+		}
+		return now - self.since >= timeout;
+	}
+}
+
+/// What a panic was given, which is almost always a message.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+	if let Some(message) = payload.downcast_ref::<&str>() {
+		return message.to_string();
+	}
+	if let Some(message) = payload.downcast_ref::<String>() {
+		return message.clone();
+	}
+	return "a value that isn't a message".to_string();
+}
+
+/// Describes a raw request for the log, with its question if it has one.
+pub(super) fn describe_request(buf: &[u8], transport: Transport, src: SocketAddr) -> String {
+	let message = protocol::parse(buf).ok().filter(|message| !message.question.is_empty());
+	let entry = QueryLog::new(src, transport, message.as_ref().unwrap_or(&Message::default()), Duration::default());
+	return match message {
+		Some(_) => format!("a {} request from {} for {} {}", entry.protocol, src, entry.qname, entry.qtype),
+		None => format!("a malformed {} request of {} bytes from {}", entry.protocol, buf.len(), src),
+	};
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::mpsc;
+	use std::time::{Duration, Instant};
+	
+	use crate::server::protocol::{self, Question, record_type};
+	use crate::server::Transport;
+	use crate::server::workers::{describe_request, Progress, Workers};
+	
+	#[test]
+	fn test_panics() {
+		let workers = Workers::new(1);
+		for _ in 0..3 {
+			workers.execute(|| "a test".to_string(), || panic!("test panic"));
+		}
+		// the only worker is still around to run this
+		let (sender, receiver) = mpsc::channel();
+		workers.execute(|| "a test".to_string(), move || sender.send(()).unwrap());
+		receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!(workers.panics(), 3);
+	}
+	
+	#[test]
+	fn test_progress() {
+		let timeout = Duration::from_secs(10);
+		let start = Instant::now();
+		let mut progress = Progress::new(0, start);
+		assert!(!progress.is_stalled(0, 5, start + Duration::from_secs(9), timeout));
+		assert!(progress.is_stalled(0, 5, start + Duration::from_secs(10), timeout));
+		// finishing a job starts the wait over
+		assert!(!progress.is_stalled(1, 5, start + Duration::from_secs(11), timeout));
+		assert!(!progress.is_stalled(1, 5, start + Duration::from_secs(20), timeout));
+		assert!(progress.is_stalled(1, 5, start + Duration::from_secs(21), timeout));
+		// as does nothing waiting, as an idle pool finishes nothing either
+		assert!(!progress.is_stalled(1, 0, start + Duration::from_secs(40), timeout));
+		assert!(!progress.is_stalled(1, 1, start + Duration::from_secs(45), timeout));
+	}
+	
+	#[test]
+	fn test_describe_request() {
+		let src = "127.0.0.1:5353".parse().unwrap();
+		let request = protocol::serialize(&protocol::make_message_from_question(vec![Question {
+			qname: vec!["example".to_string(), "com".to_string()],
+			qtype: record_type::AAAA,
+			qclass: 1,
+		}]), 512);
+		assert_eq!(describe_request(&request, Transport::Udp, src), "a udp request from 127.0.0.1:5353 for example.com. AAAA");
+		assert_eq!(describe_request(&request[..5], Transport::Tcp, src), "a malformed tcp request of 5 bytes from 127.0.0.1:5353");
+	}
+}